@@ -0,0 +1,128 @@
+use chrono::Utc;
+use dragonfly_common::models::{HardwareCheckResult, HardwareDiagnosticsReport};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+use tracing::info;
+
+// Kernel argument the server adds when it boots the agent for hardware diagnostics
+const DIAGNOSE_CMDLINE_FLAG: &str = "dragonfly.diagnose=1";
+
+// How much of each disk to read back when checking it's readable
+const DISK_READ_MIB: u32 = 256;
+
+// Kernel log lines that point at failing hardware
+const HARDWARE_ERROR_MARKERS: &[&str] = &["Hardware Error", "Machine check", "I/O error", "EDAC", "critical medium error"];
+
+/// Whether this boot was requested as a hardware diagnostics run.
+pub fn requested() -> bool {
+    fs::read_to_string("/proc/cmdline")
+        .map(|cmdline| cmdline.split_whitespace().any(|arg| arg == DIAGNOSE_CMDLINE_FLAG))
+        .unwrap_or(false)
+}
+
+fn check(name: &str, passed: bool, detail: impl Into<String>) -> HardwareCheckResult {
+    HardwareCheckResult { name: name.to_string(), passed, detail: detail.into() }
+}
+
+// Corrected and uncorrected error counts from every EDAC memory controller
+fn check_memory_errors() -> HardwareCheckResult {
+    let Ok(controllers) = fs::read_dir("/sys/devices/system/edac/mc") else {
+        return check("memory_errors", true, "EDAC not available; memory error counters not checked");
+    };
+
+    let read_count = |dir: &Path, file: &str| -> u64 {
+        fs::read_to_string(dir.join(file)).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(0)
+    };
+    let (mut corrected, mut uncorrected) = (0, 0);
+    for entry in controllers.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with("mc") {
+            corrected += read_count(&path, "ce_count");
+            uncorrected += read_count(&path, "ue_count");
+        }
+    }
+    check(
+        "memory_errors",
+        uncorrected == 0,
+        format!("{} corrected, {} uncorrected memory errors", corrected, uncorrected),
+    )
+}
+
+fn check_kernel_log() -> HardwareCheckResult {
+    let output = match Command::new("dmesg").output() {
+        Ok(output) if output.status.success() => output,
+        _ => return check("kernel_log", true, "dmesg unavailable; kernel log not checked"),
+    };
+    let log = String::from_utf8_lossy(&output.stdout);
+    let errors: Vec<&str> = log.lines()
+        .filter(|line| HARDWARE_ERROR_MARKERS.iter().any(|marker| line.contains(marker)))
+        .collect();
+    if errors.is_empty() {
+        check("kernel_log", true, "No hardware errors in kernel log")
+    } else {
+        // The last few lines are usually the most telling
+        let tail = errors[errors.len().saturating_sub(5)..].join("\n");
+        check("kernel_log", false, format!("{} hardware error lines, last:\n{}", errors.len(), tail))
+    }
+}
+
+fn block_devices() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("/sys/block") else {
+        return Vec::new();
+    };
+    let mut devices: Vec<String> = entries.flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| !["loop", "ram", "sr", "zram", "fd"].iter().any(|p| name.starts_with(p)))
+        .collect();
+    devices.sort();
+    devices
+}
+
+// Read the start of the disk back; a failing disk usually can't
+fn check_disk_read(device: &str) -> HardwareCheckResult {
+    let name = format!("disk_read:{}", device);
+    let started = Instant::now();
+    let result = Command::new("dd")
+        .args([
+            format!("if=/dev/{}", device),
+            "of=/dev/null".to_string(),
+            "bs=1M".to_string(),
+            format!("count={}", DISK_READ_MIB),
+            "iflag=direct".to_string(),
+        ])
+        .output();
+    match result {
+        Ok(output) if output.status.success() => {
+            let secs = started.elapsed().as_secs_f64().max(0.001);
+            check(&name, true, format!("Read {} MiB at {:.0} MiB/s", DISK_READ_MIB, DISK_READ_MIB as f64 / secs))
+        }
+        Ok(output) => check(&name, false, String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        Err(e) => check(&name, false, format!("Failed to run dd: {}", e)),
+    }
+}
+
+// SMART overall health, when smartmontools is in the image
+fn check_disk_smart(device: &str) -> Option<HardwareCheckResult> {
+    let output = Command::new("smartctl").args(["-H", &format!("/dev/{}", device)]).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let verdict = stdout.lines().find(|l| l.contains("overall-health") || l.contains("Health Status"))?;
+    let passed = verdict.contains("PASSED") || verdict.contains("OK");
+    Some(check(&format!("smart:{}", device), passed, verdict.trim().to_string()))
+}
+
+/// Run every check. Individual failures are reported, never fatal.
+pub fn run() -> HardwareDiagnosticsReport {
+    let started_at = Utc::now();
+    let mut checks = vec![check_memory_errors(), check_kernel_log()];
+    for device in block_devices() {
+        info!("Checking disk {}", device);
+        checks.push(check_disk_read(&device));
+        checks.extend(check_disk_smart(&device));
+    }
+    for result in &checks {
+        info!("{}: {} ({})", result.name, if result.passed { "passed" } else { "FAILED" }, result.detail);
+    }
+    HardwareDiagnosticsReport { checks, started_at, finished_at: Utc::now() }
+}
@@ -1,6 +1,6 @@
 use reqwest::Client;
 use anyhow::{Result, Context};
-use dragonfly_common::models::{MachineStatus, DiskInfo, Machine, RegisterRequest, RegisterResponse, StatusUpdateRequest, OsInstalledUpdateRequest};
+use dragonfly_common::models::{AgentEnvironment, ApiVersionInfo, MachineStatus, DiskInfo, HardwareFingerprint, Machine, RegisterRequest, RegisterResponse, StatusUpdateRequest, OsInstalledUpdateRequest, API_VERSIONS};
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -13,6 +13,16 @@ use tracing::{info, error, warn};
 use sysinfo::*;
 use serde_json;
 
+mod diagnose;
+mod patches;
+mod redetect;
+mod reports;
+mod signing;
+mod terminal;
+
+// How often a daemon-mode agent reports pending updates and the running kernel
+const PATCH_REPORT_INTERVAL_SECS: u64 = 6 * 60 * 60;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -24,6 +34,11 @@ struct Args {
     #[arg(long, requires = "setup")]
     kexec: bool,
 
+    /// Stay connected to the server after registering (rescue/daemon mode), so admins can
+    /// open a terminal on this machine from the UI
+    #[arg(long, conflicts_with = "setup")]
+    daemon: bool,
+
     /// Server URL (default: http://localhost:3000)
     #[arg(long)]
     server: Option<String>,
@@ -31,6 +46,11 @@ struct Args {
     /// Tinkerbell IPXE URL (default: http://10.7.1.30:8080/hookos.ipxe)
     #[arg(long, default_value = "http://10.7.1.30:8080/hookos.ipxe")]
     ipxe_url: String,
+
+    /// Token to sign payloads with, when the server requires signed agents
+    /// (default: $DRAGONFLY_ENROLLMENT_TOKEN or /etc/dragonfly/enrollment-token)
+    #[arg(long)]
+    enrollment_token: Option<String>,
 }
 
 // Enhanced OS detection with support for more distributions
@@ -333,6 +353,15 @@ fn detect_disks() -> Vec<DiskInfo> {
         }
     }
     
+    // Empty optical drives and card readers show up with no size; they aren't disks to
+    // install to, and the server rejects zero-size disks
+    disks.retain(|disk| {
+        if disk.size_bytes == 0 {
+            tracing::debug!("Skipping {}: no media", disk.device);
+        }
+        disk.size_bytes > 0
+    });
+
     tracing::info!("Detected {} disks", disks.len());
     for disk in &disks {
         tracing::info!("  Disk: {} ({} bytes){}", 
@@ -344,6 +373,58 @@ fn detect_disks() -> Vec<DiskInfo> {
     disks
 }
 
+// Read a DMI identifier, ignoring the filler values many vendors ship with
+fn read_dmi_id(field: &str) -> Option<String> {
+    let value = fs::read_to_string(Path::new("/sys/class/dmi/id").join(field)).ok()?;
+    let value = value.trim().to_string();
+    if HardwareFingerprint::is_placeholder(&value) {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// Boot media other than the network (a USB stick, say) put dragonfly.boot=<method> on the
+// kernel command line; network boots are recognised by the server itself
+fn detect_boot_method() -> Option<String> {
+    fs::read_to_string("/proc/cmdline").ok()?
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("dragonfly.boot="))
+        .map(str::to_string)
+        .filter(|method| !method.is_empty())
+}
+
+// Collect identifiers that stay the same when a NIC is replaced
+fn detect_hardware_fingerprint() -> HardwareFingerprint {
+    let mut disk_serials = Vec::new();
+    
+    if let Ok(output) = Command::new("lsblk")
+        .args(["-d", "-n", "-o", "NAME,SERIAL"])
+        .output()
+    {
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            for line in stdout.lines() {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() >= 2 && !parts[0].starts_with("loop") && !parts[0].starts_with("ram") {
+                    disk_serials.push(parts[1].to_string());
+                }
+            }
+        }
+    }
+    disk_serials.sort();
+    
+    let fingerprint = HardwareFingerprint {
+        system_uuid: read_dmi_id("product_uuid"),
+        system_serial: read_dmi_id("product_serial"),
+        disk_serials,
+        system_vendor: read_dmi_id("sys_vendor"),
+        system_product: read_dmi_id("product_name"),
+    };
+    tracing::info!("Hardware fingerprint: {:?}", fingerprint);
+    fingerprint
+}
+
 // Detect nameservers from resolv.conf
 fn detect_nameservers() -> Vec<String> {
     let mut nameservers = Vec::new();
@@ -374,6 +455,37 @@ fn detect_nameservers() -> Vec<String> {
     nameservers
 }
 
+// Pick the newest API prefix both sides speak. Servers that predate /api/version
+// only serve the unversioned /api routes.
+async fn negotiate_api_base(client: &Client, api_url: &str) -> String {
+    let legacy = format!("{}/api", api_url);
+    let info = match client.get(format!("{}/api/version", api_url)).send().await {
+        Ok(resp) if resp.status().is_success() => resp.json::<ApiVersionInfo>().await.ok(),
+        Ok(resp) => {
+            info!("Server has no version endpoint ({}), using unversioned API", resp.status());
+            None
+        }
+        Err(e) => {
+            warn!("Failed to query server API version, using unversioned API: {}", e);
+            None
+        }
+    };
+    let Some(info) = info else {
+        return legacy;
+    };
+    match API_VERSIONS.iter().find(|v| info.api_versions.iter().any(|s| s == *v)) {
+        Some(version) => {
+            info!("Server {} supports API {}, using it", info.server_version, version);
+            format!("{}/api/{}", api_url, version)
+        }
+        None => {
+            warn!("Server {} offers API versions {:?}, none of which this agent speaks; using unversioned API",
+                  info.server_version, info.api_versions);
+            legacy
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
@@ -419,6 +531,8 @@ async fn main() -> Result<()> {
         }
     };
     
+    let api_base = negotiate_api_base(&client, &api_url).await;
+
     // Get system information (rest of it)
     let mut sys = System::new_all();
     sys.refresh_all();
@@ -446,6 +560,11 @@ async fn main() -> Result<()> {
     // Detect disks and nameservers
     let disks = detect_disks();
     let nameservers = detect_nameservers();
+    let hardware_fingerprint = detect_hardware_fingerprint();
+    let agent_environment = AgentEnvironment {
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        boot_method: detect_boot_method(),
+    };
     
     // Detect OS - even in setup mode we want to check for existing OS
     let (os_name, os_version) = detect_os()?;
@@ -477,7 +596,7 @@ async fn main() -> Result<()> {
     
     // Check if this machine already exists in the database
     tracing::info!("Checking if machine with MAC {} already exists...", mac_address);
-    let existing_machines_response = client.get(format!("{}/api/machines", api_url))
+    let existing_machines_response = client.get(format!("{}/machines", api_base))
         .send()
         .await
         .context("Failed to fetch existing machines")?;
@@ -487,21 +606,49 @@ async fn main() -> Result<()> {
         anyhow::bail!("Failed to fetch existing machines: {}", error_text);
     }
     
+    let signer = signing::Signer::new(
+        signing::find_token(args.enrollment_token.clone()),
+        existing_machines_response.headers().get(reqwest::header::DATE),
+    );
+    
     let existing_machines: Vec<Machine> = existing_machines_response.json().await
         .context("Failed to parse existing machines response")?;
     
     // Find if this machine already exists by MAC address
     let existing_machine_option = existing_machines.iter().find(|m| m.mac_address == mac_address).cloned();
     
+    // A diagnostics boot only reports on the hardware; registration and status are left alone
+    if diagnose::requested() {
+        let Some(machine) = &existing_machine_option else {
+            anyhow::bail!("Booted for hardware diagnostics, but no machine with MAC {} is registered", mac_address);
+        };
+        info!("Running hardware diagnostics for machine {}", machine.id);
+        let report = diagnose::run();
+        let report_path = format!("/machines/{}/diagnose/report", machine.id);
+        match signer.request(&client, reqwest::Method::POST, &api_base, &report_path, &report)?.send().await {
+            Ok(resp) if resp.status().is_success() => info!("Hardware diagnostics report submitted"),
+            Ok(resp) => error!("Server rejected hardware diagnostics report: {}", resp.status()),
+            Err(e) => error!("Failed to submit hardware diagnostics report: {}", e),
+        }
+        // The kernel log behind any failed checks, for a closer look
+        if let Err(e) = reports::upload_dmesg(&client, &signer, &api_base, &machine.id).await {
+            warn!("Failed to upload kernel log: {}", e);
+        }
+        // The diagnostics boot was one-shot, so the next boot is back to normal
+        info!("Rebooting after hardware diagnostics");
+        Command::new("reboot").status().context("Failed to reboot")?;
+        return Ok(());
+    }
+    
     // Process registration/update as before
-    let _machine_id = match existing_machine_option {
+    let machine_id = match existing_machine_option {
         Some(mut machine) => { // Make machine mutable
             // Machine exists, update its status, OS, and hardware info
             tracing::info!("Machine already exists with ID: {}, fetching current state...", machine.id);
 
             // Fetch the full machine data first to ensure we have the latest base
             // This is less efficient but safer than assuming the list endpoint has absolutely latest data
-            let fetch_url = format!("{}/api/machines/{}", api_url, machine.id);
+            let fetch_url = format!("{}/machines/{}", api_base, machine.id);
             match client.get(&fetch_url).send().await {
                 Ok(resp) => {
                     if resp.status().is_success() {
@@ -545,13 +692,12 @@ async fn main() -> Result<()> {
             
             // Send the full updated machine object back to the server
             tracing::info!("Updating existing machine {} with full payload...", machine.id);
-            let update_url = format!("{}/api/machines/{}", api_url, machine.id);
+            let update_path = format!("/machines/{}", machine.id);
             
             // Log the request details before sending
-            info!("Attempting to PUT full machine update to URL: {} with payload: {:?}", update_url, machine);
+            info!("Attempting to PUT full machine update to URL: {}{} with payload: {:?}", api_base, update_path, machine);
 
-            let update_response = client.put(&update_url)
-                .json(&machine) // Send the whole updated machine struct
+            let update_response = signer.request(&client, reqwest::Method::PUT, &api_base, &update_path, &machine)? // Send the whole updated machine struct
                 .send()
                 .await
                 .context("Failed to send machine update request")?;
@@ -581,6 +727,24 @@ async fn main() -> Result<()> {
                 info!("Successfully updated machine {} on server", machine.id);
             }
             
+            // Keep the server's fingerprint current so a future NIC swap is recognised
+            if !hardware_fingerprint.is_empty() {
+                let fingerprint_path = format!("/machines/{}/fingerprint", machine.id);
+                match signer.request(&client, reqwest::Method::PUT, &api_base, &fingerprint_path, &hardware_fingerprint)?.send().await {
+                    Ok(resp) if resp.status().is_success() => info!("Reported hardware fingerprint for machine {}", machine.id),
+                    Ok(resp) => warn!("Failed to report hardware fingerprint for machine {}: Status {}", machine.id, resp.status()),
+                    Err(e) => warn!("Network error reporting hardware fingerprint for machine {}: {}", machine.id, e),
+                }
+            }
+
+            // Record which agent build (and boot method) this machine is running
+            let environment_path = format!("/machines/{}/agent-environment", machine.id);
+            match signer.request(&client, reqwest::Method::PUT, &api_base, &environment_path, &agent_environment)?.send().await {
+                Ok(resp) if resp.status().is_success() => info!("Reported agent version {} for machine {}", agent_environment.agent_version, machine.id),
+                Ok(resp) => warn!("Failed to report agent environment for machine {}: Status {}", machine.id, resp.status()),
+                Err(e) => warn!("Network error reporting agent environment for machine {}: {}", machine.id, e),
+            }
+            
             // We don't need to update status/os_installed separately anymore
             /*
             // Update machine status with the OS information
@@ -611,15 +775,22 @@ async fn main() -> Result<()> {
                 cpu_model: cpu_model.clone(), 
                 cpu_cores,
                 total_ram_bytes: Some(total_ram_bytes),
+                hardware_fingerprint: Some(hardware_fingerprint),
+                agent_environment: Some(agent_environment),
             };
             
             // Register the machine
-            let response = client.post(format!("{}/api/machines", api_url))
-                .json(&register_request)
+            let response = signer.request(&client, reqwest::Method::POST, &api_base, "/machines", &register_request)?
                 .send()
                 .await
                 .context("Failed to send registration request")?;
             
+            if response.status() == reqwest::StatusCode::CONFLICT {
+                // Held for an admin: a known machine with a new NIC, or a MAC not on the allowlist
+                let error_text = response.text().await?;
+                anyhow::bail!("Registration is awaiting admin approval: {}", error_text);
+            }
+            
             if !response.status().is_success() {
                 let error_text = response.text().await?;
                 anyhow::bail!("Failed to register machine: {}", error_text);
@@ -639,8 +810,8 @@ async fn main() -> Result<()> {
                 message: None,
             };
             
-            let status_response = client.put(format!("{}/api/machines/{}/status", api_url, register_response.machine_id))
-                .json(&status_update)
+            let status_path = format!("/machines/{}/status", register_response.machine_id);
+            let status_response = signer.request(&client, reqwest::Method::PUT, &api_base, &status_path, &status_update)?
                 .send()
                 .await
                 .context("Failed to send status update")?;
@@ -659,20 +830,17 @@ async fn main() -> Result<()> {
                     os_installed: os_name.to_string(),
                 };
                 
-                // Construct the URL
-                let url = format!(
-                    "{}/api/machines/{}/os-installed",
-                    api_url,
+                // Construct the path under the API base
+                let path = format!(
+                    "/machines/{}/os-installed",
                     register_response.machine_id
                 );
 
                 // Log the request details before sending
-                info!("Attempting to PUT OS installed update to URL: {} with payload: {:?}", url, os_installed_update);
+                info!("Attempting to PUT OS installed update to URL: {}{} with payload: {:?}", api_base, path, os_installed_update);
 
                 // Send the request and handle potential network/send errors
-                let response_result = client
-                    .put(&url)
-                    .json(&os_installed_update)
+                let response_result = signer.request(&client, reqwest::Method::PUT, &api_base, &path, &os_installed_update)?
                     .send()
                     .await;
 
@@ -714,6 +882,36 @@ async fn main() -> Result<()> {
         }
     };
     
+    if args.daemon {
+        info!("Running in daemon mode, keeping the agent channel to the server open");
+        let signer = std::sync::Arc::new(signer);
+        let (redetect_tx, mut redetect_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (redetect_client, redetect_base, redetect_signer) = (client.clone(), api_base.clone(), signer.clone());
+        tokio::spawn(async move {
+            while redetect_rx.recv().await.is_some() {
+                if let Err(e) = redetect::report(&redetect_client, &redetect_base, &redetect_signer, machine_id).await {
+                    error!("Failed to report redetected OS and hardware: {:#}", e);
+                }
+            }
+        });
+        // Report the patch baseline now and then every few hours
+        let patch_interval = env::var("DRAGONFLY_PATCH_REPORT_INTERVAL_SECS").ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(PATCH_REPORT_INTERVAL_SECS)
+            .max(60);
+        let (patch_base, channel_signer) = (api_base.clone(), signer.clone());
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = patches::report(&client, &patch_base, &signer, machine_id).await {
+                    warn!("Failed to report patch baseline: {:#}", e);
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(patch_interval)).await;
+            }
+        });
+        terminal::run(&api_base, machine_id, channel_signer, redetect_tx).await;
+        return Ok(());
+    }
+
     // If in setup mode, handle boot decision
     if args.setup {
         if has_bootable_os {
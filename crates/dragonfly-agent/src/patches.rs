@@ -0,0 +1,124 @@
+// Patch baseline of the installed OS: kernel version and pending (security) updates, sent
+// to the server periodically while the agent runs as a daemon.
+//
+// Counts come from the package manager's cached metadata; the agent doesn't refresh it,
+// so they're as current as the OS's own apt/dnf timers keep them.
+
+use anyhow::{Context, Result};
+use dragonfly_common::models::PatchReport;
+use reqwest::{Client, Method};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::signing::Signer;
+
+fn kernel_version() -> String {
+    if let Ok(release) = fs::read_to_string("/proc/sys/kernel/osrelease") {
+        return release.trim().to_string();
+    }
+    Command::new("uname").arg("-r").output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+fn has_command(name: &str) -> bool {
+    Command::new("sh").args(["-c", &format!("command -v {} >/dev/null 2>&1", name)])
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+// Upgrades apt would make, from a simulated dist-upgrade. Security updates are the ones
+// coming from a -security suite, e.g. "Inst openssl [3.0.2-0ubuntu1.9] (3.0.2-0ubuntu1.10 Ubuntu:22.04/jammy-security [amd64])"
+fn apt_counts() -> Option<(u32, u32)> {
+    let output = Command::new("apt-get")
+        .args(["-s", "-q", "-o", "Debug::NoLocking=1", "dist-upgrade"])
+        .env("LC_ALL", "C")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let upgrades: Vec<&str> = stdout.lines().filter(|line| line.starts_with("Inst ")).collect();
+    let security = upgrades.iter().filter(|line| line.contains("-security")).count();
+    Some((upgrades.len() as u32, security as u32))
+}
+
+// Package lines from dnf list-style output ("name.arch  version  repo"), up to any
+// "Obsoleting Packages" section
+fn dnf_package_lines(stdout: &str) -> usize {
+    stdout.lines()
+        .take_while(|line| !line.starts_with("Obsoleting"))
+        .filter(|line| line.split_whitespace().count() == 3)
+        .count()
+}
+
+fn dnf_counts() -> Option<(u32, u32)> {
+    // check-update exits 100 when there are updates, 0 when there aren't
+    let output = Command::new("dnf").args(["-q", "--cacheonly", "check-update"]).output().ok()?;
+    let pending = match output.status.code() {
+        Some(0) => 0,
+        Some(100) => dnf_package_lines(&String::from_utf8_lossy(&output.stdout)),
+        _ => return None,
+    };
+    // One line per advisory and package: "RHSA-2024:1234 Important/Sec. openssl-3.0.7-25.el9.x86_64"
+    let output = Command::new("dnf").args(["-q", "--cacheonly", "updateinfo", "list", "--security"]).output().ok()?;
+    let mut packages: Vec<&str> = Vec::new();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in stdout.lines() {
+        if let [_, _, package] = line.split_whitespace().collect::<Vec<_>>()[..] {
+            if !packages.contains(&package) {
+                packages.push(package);
+            }
+        }
+    }
+    Some((pending as u32, packages.len() as u32))
+}
+
+fn reboot_required() -> bool {
+    // Debian and Ubuntu flag it with a file; RHEL and Fedora have needs-restarting, which
+    // exits 1 when a reboot is needed
+    if Path::new("/var/run/reboot-required").exists() {
+        return true;
+    }
+    has_command("needs-restarting")
+        && Command::new("needs-restarting").arg("-r").output()
+            .map(|output| output.status.code() == Some(1))
+            .unwrap_or(false)
+}
+
+pub fn collect() -> PatchReport {
+    let (package_manager, counts) = if has_command("apt-get") {
+        (Some("apt"), apt_counts())
+    } else if has_command("dnf") {
+        (Some("dnf"), dnf_counts())
+    } else {
+        (None, None)
+    };
+    PatchReport {
+        kernel_version: kernel_version(),
+        package_manager: package_manager.map(str::to_string),
+        pending_updates: counts.map(|(pending, _)| pending),
+        pending_security_updates: counts.map(|(_, security)| security),
+        reboot_required: reboot_required(),
+    }
+}
+
+pub async fn report(client: &Client, api_base: &str, signer: &Signer, machine_id: Uuid) -> Result<()> {
+    let report = tokio::task::spawn_blocking(collect).await.context("Patch collection panicked")?;
+    let path = format!("/machines/{}/patches", machine_id);
+    let response = signer.request(client, Method::PUT, api_base, &path, &report)?
+        .send()
+        .await
+        .context("Failed to send patch report")?;
+    if !response.status().is_success() {
+        anyhow::bail!("Server rejected patch report: Status {}", response.status());
+    }
+    info!("Reported kernel {} with {:?} pending update(s), {:?} security",
+          report.kernel_version, report.pending_updates, report.pending_security_updates);
+    Ok(())
+}
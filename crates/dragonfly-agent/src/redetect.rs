@@ -0,0 +1,66 @@
+// Detection on request from the server (ChannelMessage::Redetect), so the machine record
+// catches up after the OS or hardware was changed outside Dragonfly. Only what the agent
+// detects is touched; status, OS assignment and the rest are left as the server has them.
+
+use anyhow::{Context, Result};
+use dragonfly_common::models::Machine;
+use reqwest::{Client, Method};
+use sysinfo::System;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::signing::Signer;
+
+pub async fn report(client: &Client, api_base: &str, signer: &Signer, machine_id: Uuid) -> Result<()> {
+    let (os_name, os_version) = crate::detect_os()?;
+    info!("Redetected OS: {} {}", os_name, os_version);
+    let os_installed = (os_name != "Alpine" && os_name != "Unknown")
+        .then(|| format!("{} {}", os_name, os_version));
+
+    let mut sys = System::new_all();
+    sys.refresh_cpu();
+    sys.refresh_memory();
+
+    let response = client.get(format!("{}/machines/{}", api_base, machine_id))
+        .send()
+        .await
+        .context("Failed to fetch machine record")?;
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to fetch machine record: Status {}", response.status());
+    }
+    // The API returns {"machine": ..., "workflow_info": ...}
+    let data: serde_json::Value = response.json().await.context("Failed to parse machine record")?;
+    let mut machine: Machine = serde_json::from_value(data.get("machine").cloned().context("Machine record is missing 'machine'")?)
+        .context("Failed to parse machine record")?;
+
+    machine.os_installed = os_installed;
+    machine.cpu_model = sys.cpus().first().map(|cpu| cpu.brand().to_string());
+    machine.cpu_cores = sys.physical_core_count().map(|c| c as u32).or(Some(sys.cpus().len() as u32));
+    machine.total_ram_bytes = Some(sys.total_memory());
+    machine.disks = crate::detect_disks();
+    machine.nameservers = crate::detect_nameservers();
+
+    let path = format!("/machines/{}", machine_id);
+    let response = signer.request(client, Method::PUT, api_base, &path, &machine)?
+        .send()
+        .await
+        .context("Failed to send machine update")?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        anyhow::bail!("Server rejected machine update: Status {}, Response: {}", status, text);
+    }
+
+    let fingerprint = crate::detect_hardware_fingerprint();
+    if !fingerprint.is_empty() {
+        let path = format!("/machines/{}/fingerprint", machine_id);
+        match signer.request(client, Method::PUT, api_base, &path, &fingerprint)?.send().await {
+            Ok(resp) if resp.status().is_success() => {},
+            Ok(resp) => warn!("Failed to report hardware fingerprint: Status {}", resp.status()),
+            Err(e) => warn!("Network error reporting hardware fingerprint: {}", e),
+        }
+    }
+
+    info!("Reported redetected OS and hardware for machine {}", machine_id);
+    Ok(())
+}
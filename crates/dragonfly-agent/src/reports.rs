@@ -0,0 +1,77 @@
+// Large files about this machine (dmesg, installer logs, sosreports) go to the server's
+// report endpoint in Content-Range pieces, so nothing has to fit in memory or in a JSON payload.
+// The server streams the pieces to disk, so their signatures cover the method and path only.
+
+use anyhow::{bail, Context, Result};
+use dragonfly_common::signing::SIGNATURE_HEADER;
+use reqwest::{Client, RequestBuilder};
+use std::io::Read;
+use std::path::Path;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::signing::Signer;
+
+// Size of each piece sent
+const PIECE_BYTES: usize = 8 * 1024 * 1024;
+
+// A PUT of one piece, signed if there's a token
+fn put(client: &Client, signer: &Signer, api_base: &str, report_path: &str) -> RequestBuilder {
+    let request = client.put(format!("{}{}", api_base, report_path));
+    match signer.signature("PUT", report_path, b"") {
+        Some(signature) => request.header(SIGNATURE_HEADER, signature),
+        None => request,
+    }
+}
+
+/// Upload a file as a report for this machine under `name`.
+pub async fn upload_file(client: &Client, signer: &Signer, api_base: &str, machine_id: &Uuid, name: &str, path: &Path) -> Result<()> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let total = file.metadata()?.len();
+    let report_path = format!("/machines/{}/reports/{}", machine_id, name);
+
+    // An empty file still gets stored, as one empty request
+    if total == 0 {
+        let response = put(client, signer, api_base, &report_path).body(Vec::new()).send().await?;
+        if !response.status().is_success() {
+            bail!("Server rejected report {}: {}", name, response.status());
+        }
+        return Ok(());
+    }
+
+    let mut offset = 0u64;
+    let mut piece = vec![0u8; PIECE_BYTES];
+    while offset < total {
+        let len = file.read(&mut piece)?;
+        if len == 0 {
+            bail!("{} shrank while it was being uploaded", path.display());
+        }
+        let end = offset + len as u64 - 1;
+        let response = put(client, signer, api_base, &report_path)
+            .header(reqwest::header::CONTENT_RANGE, format!("bytes {}-{}/{}", offset, end, total))
+            .body(piece[..len].to_vec())
+            .send().await
+            .with_context(|| format!("Failed to upload report {}", name))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let message = response.text().await.unwrap_or_default();
+            bail!("Server rejected report {} at byte {}: {} {}", name, offset, status, message);
+        }
+        offset = end + 1;
+    }
+    info!("Uploaded report {} ({} bytes)", name, total);
+    Ok(())
+}
+
+/// Upload the kernel log as "dmesg.log".
+pub async fn upload_dmesg(client: &Client, signer: &Signer, api_base: &str, machine_id: &Uuid) -> Result<()> {
+    let output = std::process::Command::new("dmesg").output().context("Failed to run dmesg")?;
+    if !output.status.success() {
+        bail!("dmesg failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+    let path = std::env::temp_dir().join("dragonfly-dmesg.log");
+    std::fs::write(&path, &output.stdout)?;
+    let result = upload_file(client, signer, api_base, machine_id, "dmesg.log", &path).await;
+    let _ = std::fs::remove_file(&path);
+    result
+}
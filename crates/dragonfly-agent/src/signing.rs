@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use dragonfly_common::signing::{self, SIGNATURE_HEADER};
+use reqwest::{Client, Method, RequestBuilder};
+use serde::Serialize;
+use std::env;
+use std::fs;
+use tracing::{info, warn};
+
+// Baked into images for machines that run the agent from an installed OS
+const TOKEN_FILE: &str = "/etc/dragonfly/enrollment-token";
+
+/// Signs registration and update payloads with the enrollment token, when there is one.
+pub struct Signer {
+    token: Option<String>,
+    // Seconds to add to the local clock to get the server's; PXE-booted machines often
+    // haven't synced their clock yet
+    clock_offset: i64,
+}
+
+/// The enrollment token from --enrollment-token, $DRAGONFLY_ENROLLMENT_TOKEN or the token file.
+pub fn find_token(flag: Option<String>) -> Option<String> {
+    flag.or_else(|| env::var("DRAGONFLY_ENROLLMENT_TOKEN").ok())
+        .or_else(|| fs::read_to_string(TOKEN_FILE).ok())
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+impl Signer {
+    /// `server_date` is the Date header of any response from the server.
+    pub fn new(token: Option<String>, server_date: Option<&reqwest::header::HeaderValue>) -> Self {
+        let clock_offset = server_date
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.timestamp() - chrono::Utc::now().timestamp())
+            .unwrap_or(0);
+        if token.is_some() {
+            info!("Signing payloads with the enrollment token");
+            if clock_offset.abs() > signing::MAX_CLOCK_SKEW_SECS {
+                warn!("Local clock is {}s off the server's; signing with the server's time", clock_offset);
+            }
+        }
+        Self { token, clock_offset }
+    }
+
+    /// A JSON request to `path` under the API base, signed if there's a token.
+    pub fn request(&self, client: &Client, method: Method, api_base: &str, path: &str, body: &impl Serialize) -> Result<RequestBuilder> {
+        let bytes = serde_json::to_vec(body).context("Failed to serialize request body")?;
+        let mut request = client.request(method.clone(), format!("{}{}", api_base, path))
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(signature) = self.signature(method.as_str(), path, &bytes) {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        Ok(request.body(bytes))
+    }
+
+    /// The signature header value for a request to `path` under the API base, if there's a token.
+    pub fn signature(&self, method: &str, path: &str, body: &[u8]) -> Option<String> {
+        self.token.as_ref().map(|token| {
+            let timestamp = chrono::Utc::now().timestamp() + self.clock_offset;
+            signing::sign(token, timestamp, method, path, body)
+        })
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use dragonfly_common::channel::{decode_terminal_data, encode_terminal_data, ChannelMessage};
+use dragonfly_common::signing::SIGNATURE_HEADER;
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::signing::Signer;
+
+// How long to wait before reconnecting a dropped channel
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+struct Session {
+    master: File,
+    pid: i32,
+}
+
+impl Session {
+    fn resize(&self, cols: u16, rows: u16) {
+        let size = winsize(cols, rows);
+        unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ as _, &size) };
+    }
+
+    // The shell leads its own session, so this reaches everything started from it
+    fn hang_up(&self) {
+        unsafe { libc::kill(-self.pid, libc::SIGHUP) };
+    }
+}
+
+type Sessions = Arc<Mutex<HashMap<Uuid, Session>>>;
+
+fn lock(sessions: &Sessions) -> MutexGuard<'_, HashMap<Uuid, Session>> {
+    sessions.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn winsize(cols: u16, rows: u16) -> libc::winsize {
+    libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 }
+}
+
+/// Start a login shell on a new PTY, returning the master side and the shell process.
+fn spawn_shell(cols: u16, rows: u16) -> Result<(File, Child)> {
+    let (mut master, mut slave) = (-1, -1);
+    let size = winsize(cols, rows);
+    if unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to open a PTY");
+    }
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    // Shells opened later mustn't inherit this one's master
+    unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+
+    let shell = ["/bin/bash", "/bin/sh"].into_iter().find(|s| Path::new(s).exists()).unwrap_or("/bin/sh");
+    let mut command = Command::new(shell);
+    command
+        .arg("-l")
+        .env("TERM", "xterm-256color")
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    // A session of its own with the PTY as controlling terminal, so Ctrl-C and job control work
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn().with_context(|| format!("Failed to start {}", shell))?;
+    Ok((File::from(master), child))
+}
+
+fn send_exit(tx: &mpsc::UnboundedSender<Message>, session_id: Uuid, code: Option<i32>, error: Option<String>) {
+    if let Ok(json) = serde_json::to_string(&ChannelMessage::TerminalExit { session_id, code, error }) {
+        let _ = tx.send(Message::Text(json.into()));
+    }
+}
+
+fn open_session(session_id: Uuid, cols: u16, rows: u16, sessions: &Sessions, tx: &mpsc::UnboundedSender<Message>) -> Result<()> {
+    let (master, mut child) = spawn_shell(cols, rows)?;
+    let mut reader = master.try_clone().context("Failed to clone PTY")?;
+    lock(sessions).insert(session_id, Session { master, pid: child.id() as i32 });
+    info!("Opened terminal session {}", session_id);
+
+    // PTY reads block, so each session gets a thread that forwards output until the shell exits
+    let sessions = sessions.clone();
+    let tx = tx.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(Message::Binary(encode_terminal_data(&session_id, &buf[..n]).into())).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // EIO once the shell has exited and the slave side is closed
+                Err(_) => break,
+            }
+        }
+        let code = child.wait().ok().and_then(|status| status.code());
+        lock(&sessions).remove(&session_id);
+        info!("Terminal session {} ended", session_id);
+        send_exit(&tx, session_id, code, None);
+    });
+    Ok(())
+}
+
+fn handle_control(message: ChannelMessage, sessions: &Sessions, tx: &mpsc::UnboundedSender<Message>, redetect: &mpsc::UnboundedSender<()>) {
+    match message {
+        ChannelMessage::TerminalOpen { session_id, cols, rows } => {
+            if let Err(e) = open_session(session_id, cols, rows, sessions, tx) {
+                error!("Failed to open terminal session {}: {:#}", session_id, e);
+                send_exit(tx, session_id, None, Some(format!("{:#}", e)));
+            }
+        }
+        ChannelMessage::TerminalResize { session_id, cols, rows } => {
+            if let Some(session) = lock(sessions).get(&session_id) {
+                session.resize(cols, rows);
+            }
+        }
+        ChannelMessage::TerminalClose { session_id } => {
+            if let Some(session) = lock(sessions).remove(&session_id) {
+                session.hang_up();
+            }
+        }
+        ChannelMessage::Redetect => {
+            info!("Server asked for OS and hardware redetection");
+            let _ = redetect.send(());
+        }
+        ChannelMessage::Shutdown => {
+            info!("Server asked for a managed shutdown, powering off");
+            if let Err(e) = Command::new("shutdown").args(["-h", "now"]).status() {
+                error!("Failed to shut down: {}", e);
+            }
+        }
+        ChannelMessage::TerminalExit { .. } => warn!("Ignoring unexpected {:?} from server", message),
+    }
+}
+
+/// The channel's path under the API base, which is what gets signed.
+fn channel_path(machine_id: &Uuid) -> String {
+    format!("/agent/channel/{}", machine_id)
+}
+
+/// The channel URL for an API base such as http://10.0.0.1:3000/api/v1.
+fn channel_url(api_base: &str, machine_id: &Uuid) -> String {
+    let base = if let Some(rest) = api_base.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = api_base.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        api_base.to_string()
+    };
+    format!("{}{}", base, channel_path(machine_id))
+}
+
+// One connection's worth of the channel; returns when the server goes away
+async fn serve(url: &str, path: &str, signer: &Signer, redetect: &mpsc::UnboundedSender<()>) -> Result<()> {
+    // The server only hands the channel to agents holding the enrollment token
+    let mut request = url.into_client_request().context("Invalid agent channel URL")?;
+    if let Some(signature) = signer.signature("GET", path, b"") {
+        let value = HeaderValue::from_str(&signature).context("Invalid agent channel signature")?;
+        request.headers_mut().insert(SIGNATURE_HEADER, value);
+    }
+    let (socket, _) = tokio_tungstenite::connect_async(request).await.context("Failed to connect agent channel")?;
+    info!("Agent channel connected to {}", url);
+
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut outgoing) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let sessions: Sessions = Arc::default();
+    let result = loop {
+        let message = match stream.next().await {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => break Err(anyhow::Error::new(e).context("Agent channel failed")),
+            None => break Ok(()),
+        };
+        match message {
+            Message::Text(text) => match serde_json::from_str::<ChannelMessage>(text.as_str()) {
+                Ok(control) => handle_control(control, &sessions, &tx, redetect),
+                Err(e) => warn!("Malformed message on agent channel: {}", e),
+            },
+            Message::Binary(frame) => {
+                if let Some((session_id, data)) = decode_terminal_data(&frame) {
+                    if let Some(session) = lock(&sessions).get_mut(&session_id) {
+                        if let Err(e) = session.master.write_all(data) {
+                            warn!("Failed to write to terminal session {}: {}", session_id, e);
+                        }
+                    }
+                }
+            }
+            Message::Close(_) => break Ok(()),
+            _ => {}
+        }
+    };
+    writer.abort();
+
+    // The server forgets sessions when the channel drops, so nobody can reach these shells
+    for (_, session) in lock(&sessions).drain() {
+        session.hang_up();
+    }
+    result
+}
+
+/// Keep the channel to the server open for good, reconnecting whenever it drops.
+/// Redetection requests are passed on to `redetect`.
+pub async fn run(api_base: &str, machine_id: Uuid, signer: Arc<Signer>, redetect: mpsc::UnboundedSender<()>) {
+    let url = channel_url(api_base, &machine_id);
+    let path = channel_path(&machine_id);
+    if signer.signature("GET", &path, b"").is_none() {
+        warn!("No enrollment token; the server will refuse the agent channel");
+    }
+    loop {
+        match serve(&url, &path, &signer, &redetect).await {
+            Ok(()) => info!("Agent channel closed by server"),
+            Err(e) => warn!("Agent channel unavailable: {:#}", e),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
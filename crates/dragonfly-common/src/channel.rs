@@ -0,0 +1,63 @@
+// Messages on the websocket channel agents keep open to the server in daemon mode.
+//
+// Control messages are JSON text frames. Terminal bytes are binary frames: the 16-byte
+// session ID followed by the raw data, so keystrokes and output aren't re-encoded.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelMessage {
+    // Server -> agent: start a shell on a new PTY
+    TerminalOpen { session_id: Uuid, cols: u16, rows: u16 },
+    // Server -> agent: the browser window changed size
+    TerminalResize { session_id: Uuid, cols: u16, rows: u16 },
+    // Server -> agent: the browser went away, kill the shell
+    TerminalClose { session_id: Uuid },
+    // Agent -> server: the shell exited (or could not be started)
+    TerminalExit { session_id: Uuid, code: Option<i32>, error: Option<String> },
+    // Server -> agent: detect the OS and hardware again and update the machine record
+    Redetect,
+    // Server -> agent: shut the machine down cleanly (a managed power-off to save energy)
+    Shutdown,
+}
+
+/// Frame terminal bytes for a session.
+pub fn encode_terminal_data(session_id: &Uuid, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(16 + data.len());
+    frame.extend_from_slice(session_id.as_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Split a binary frame into its session ID and data.
+pub fn decode_terminal_data(frame: &[u8]) -> Option<(Uuid, &[u8])> {
+    if frame.len() < 16 {
+        return None;
+    }
+    let session_id = Uuid::from_slice(&frame[..16]).ok()?;
+    Some((session_id, &frame[16..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminal_data_round_trip() {
+        let session_id = Uuid::new_v4();
+        let frame = encode_terminal_data(&session_id, b"ls -la\r");
+        assert_eq!(decode_terminal_data(&frame), Some((session_id, &b"ls -la\r"[..])));
+        assert_eq!(decode_terminal_data(&frame[..10]), None);
+    }
+
+    #[test]
+    fn test_control_message_format() {
+        let session_id = Uuid::nil();
+        let json = serde_json::to_string(&ChannelMessage::TerminalResize { session_id, cols: 120, rows: 40 }).unwrap();
+        assert_eq!(json, format!(r#"{{"type":"terminal_resize","session_id":"{}","cols":120,"rows":40}}"#, session_id));
+        assert_eq!(serde_json::to_string(&ChannelMessage::Redetect).unwrap(), r#"{"type":"redetect"}"#);
+        assert_eq!(serde_json::to_string(&ChannelMessage::Shutdown).unwrap(), r#"{"type":"shutdown"}"#);
+    }
+}
@@ -1,19 +1,164 @@
 use thiserror::Error;
 
+use crate::validation::ValidationErrors;
+
+/// Underlying cause carried by errors that wrap another library's error.
+pub type Source = Box<dyn std::error::Error + Send + Sync + 'static>;
+
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Not found")]
-    NotFound,
-    
+    #[error("{resource} not found")]
+    NotFound { resource: String },
+
+    #[error("Invalid request: {0}")]
+    Validation(#[from] ValidationErrors),
+
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
-    
-    #[error("Database error: {0}")]
-    Database(String),
-    
-    #[error("Internal error: {0}")]
-    Internal(String),
-    
+
+    #[error("Database error: {message}")]
+    Database {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+
+    #[error("Kubernetes error: {message}")]
+    K8s {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A service Dragonfly depends on (artifact mirror, Proxmox, ...) failed or misbehaved
+    #[error("{service}: {message}")]
+    Upstream {
+        service: String,
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
     #[error("Authentication error: {0}")]
     Auth(String),
-} 
\ No newline at end of file
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl Error {
+    pub fn not_found(resource: impl Into<String>) -> Self {
+        Error::NotFound { resource: resource.into() }
+    }
+
+    pub fn io(context: impl Into<String>, source: std::io::Error) -> Self {
+        Error::Io { context: context.into(), source }
+    }
+
+    pub fn database(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        Error::Database { message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn k8s(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        Error::K8s { message: message.into(), source: Some(source.into()) }
+    }
+
+    /// An upstream failure with no underlying error, e.g. an unexpected HTTP status.
+    pub fn upstream(service: impl Into<String>, message: impl Into<String>) -> Self {
+        Error::Upstream { service: service.into(), message: message.into(), source: None }
+    }
+
+    pub fn upstream_with(service: impl Into<String>, message: impl Into<String>, source: impl Into<Source>) -> Self {
+        Error::Upstream { service: service.into(), message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound { .. })
+    }
+
+    /// HTTP status code this error should be reported with.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Error::NotFound { .. } => 404,
+            Error::Validation(_) => 422,
+            Error::InvalidRequest(_) => 400,
+            Error::Auth(_) => 401,
+            Error::Upstream { .. } => 502,
+            Error::K8s { .. } => 503,
+            Error::Database { .. } | Error::Io { .. } | Error::Config(_) | Error::Internal(_) => 500,
+        }
+    }
+
+    /// Short, stable summary of the error class, used as the `error` field of API responses.
+    pub fn title(&self) -> &'static str {
+        match self {
+            Error::NotFound { .. } => "Not Found",
+            Error::Validation(_) => "Validation Failed",
+            Error::InvalidRequest(_) => "Invalid Request",
+            Error::Auth(_) => "Unauthorized",
+            Error::Upstream { .. } => "Upstream Error",
+            Error::K8s { .. } => "Kubernetes Unavailable",
+            Error::Database { .. } => "Database Error",
+            Error::Io { .. } => "I/O Error",
+            Error::Config(_) => "Configuration Error",
+            Error::Internal(_) => "Internal Error",
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::io("I/O operation failed", source)
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let status = axum::http::StatusCode::from_u16(self.status_code())
+            .unwrap_or(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let mut body = serde_json::json!({
+            "error": self.title(),
+            "message": self.to_string(),
+        });
+        if let Error::Validation(errors) = &self {
+            body["fields"] = serde_json::json!(errors.errors);
+        }
+        (status, axum::Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(Error::not_found("Template debian-12").status_code(), 404);
+        assert_eq!(Error::from(ValidationErrors::new()).status_code(), 422);
+        assert_eq!(Error::upstream("Artifact mirror", "HTTP 503").status_code(), 502);
+        assert_eq!(Error::Config("DRAGONFLY_BASE_URL is not set".to_string()).status_code(), 500);
+    }
+
+    #[test]
+    fn test_sources_are_kept() {
+        let io = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        let err = Error::io("Failed to write etc/hosts", io);
+        assert_eq!(err.to_string(), "Failed to write etc/hosts: denied");
+        assert!(err.source().is_some());
+
+        assert!(Error::upstream("Mirror", "HTTP 404").source().is_none());
+        assert_eq!(Error::not_found("Template debian-12").to_string(), "Template debian-12 not found");
+    }
+}
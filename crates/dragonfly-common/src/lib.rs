@@ -1,8 +1,15 @@
+pub mod channel;
 pub mod error;
 pub mod models;
 pub mod mac_to_words;
+pub mod validation;
+pub mod signing;
+pub mod os;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 pub use error::Error;
 pub use models::*;
+pub use os::OsIdentifier;
 
 pub type Result<T> = std::result::Result<T, Error>; 
\ No newline at end of file
@@ -39,6 +39,13 @@ pub struct Machine {
     pub proxmox_cluster: Option<String>,
     // New flag for Proxmox hosts
     pub is_proxmox_host: bool, // Defaults to false if not specified in JSON
+    // False while the Tinkerbell hardware record is missing or stale and a retry is queued
+    #[serde(default = "default_tinkerbell_synced")]
+    pub tinkerbell_synced: bool,
+}
+
+fn default_tinkerbell_synced() -> bool {
+    true
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -47,7 +54,9 @@ pub enum MachineStatus {
     AwaitingAssignment,    // Blank machine ready for OS assignment
     InstallingOS,          // Installing an OS via tinkerbell
     Ready,                 // Part of the cluster, serving K8s workloads
+    BootedLive,            // Running a live image from the network, nothing installed (ephemeral mode)
     Offline,               // Machine is offline (can be WoL'd)
+    PoweredOff,            // Shut down by Dragonfly to save power (managed, not a fault); woken on schedule or demand
     Error(String),         // Error state with message
 }
 
@@ -58,7 +67,9 @@ impl fmt::Display for MachineStatus {
             MachineStatus::AwaitingAssignment => write!(f, "Awaiting OS Assignment"),
             MachineStatus::InstallingOS => write!(f, "InstallingOS"),
             MachineStatus::Ready => write!(f, "Ready"),
+            MachineStatus::BootedLive => write!(f, "Booted Live"),
             MachineStatus::Offline => write!(f, "Offline"),
+            MachineStatus::PoweredOff => write!(f, "Powered Off"),
             MachineStatus::Error(msg) => write!(f, "Error: {}", msg),
         }
     }
@@ -103,6 +114,91 @@ pub struct RegisterRequest {
     pub proxmox_vmid: Option<u32>,
     pub proxmox_node: Option<String>,
     pub proxmox_cluster: Option<String>,
+    // Reported by the agent so a replaced NIC doesn't look like a new machine
+    #[serde(default)]
+    pub hardware_fingerprint: Option<HardwareFingerprint>,
+    // Agent build and how it was booted, so outdated boot environments can be found
+    #[serde(default)]
+    pub agent_environment: Option<AgentEnvironment>,
+}
+
+// Reported by the agent at registration
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AgentEnvironment {
+    pub agent_version: String,
+    // From dragonfly.boot= on the kernel command line ("pxe", "http_boot", "usb"); boot media
+    // other than the network set it, otherwise the server knows from the boot request
+    #[serde(default)]
+    pub boot_method: Option<String>,
+}
+
+// Identifiers that survive a NIC swap: DMI system UUID/serial and disk serials
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct HardwareFingerprint {
+    pub system_uuid: Option<String>,
+    pub system_serial: Option<String>,
+    #[serde(default)]
+    pub disk_serials: Vec<String>,
+    // Not identifying, but reported alongside so OS policies can match on hardware model
+    #[serde(default)]
+    pub system_vendor: Option<String>,
+    #[serde(default)]
+    pub system_product: Option<String>,
+}
+
+impl HardwareFingerprint {
+    // Vendors ship boards with filler values in DMI; these identify nothing
+    pub fn is_placeholder(value: &str) -> bool {
+        let v = value.trim().to_lowercase();
+        v.is_empty()
+            || v.chars().all(|c| c == '0' || c == '-' || c == 'f')
+            || v == "03000200-0400-0500-0006-000700080009"
+            || v.contains("to be filled")
+            || v.contains("default string")
+            || v == "system serial number"
+            || v == "not specified"
+            || v == "none"
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.system_uuid.is_none() && self.system_serial.is_none() && self.disk_serials.is_empty()
+    }
+
+    /// True if both fingerprints share the system UUID or serial, or have the same set of disks.
+    pub fn matches(&self, other: &HardwareFingerprint) -> bool {
+        let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => !Self::is_placeholder(a) && a.eq_ignore_ascii_case(b),
+            _ => false,
+        };
+        if same(&self.system_uuid, &other.system_uuid) || same(&self.system_serial, &other.system_serial) {
+            return true;
+        }
+
+        let mut mine: Vec<&String> = self.disk_serials.iter().collect();
+        let mut theirs: Vec<&String> = other.disk_serials.iter().collect();
+        mine.sort();
+        theirs.sort();
+        !mine.is_empty() && mine == theirs
+    }
+}
+
+// A candidate returned when resolving a user-supplied machine reference
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MachineMatch {
+    pub id: Uuid,
+    pub hostname: Option<String>,
+    pub memorable_name: Option<String>,
+    pub mac_address: String,
+    pub ip_address: String,
+    pub status: String,
+    pub matched_on: String,  // Which field matched: id, mac_address, hostname or memorable_name
+    pub exact: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MachineResolveResponse {
+    pub query: String,
+    pub matches: Vec<MachineMatch>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -195,4 +291,191 @@ pub struct InstallationProgressUpdateRequest {
 pub struct InstallationProgressUpdateResponse {
     pub success: bool,
     pub message: String,
-} 
\ No newline at end of file
+} 
+// One check run by the agent when booted for hardware diagnostics
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HardwareCheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+// Everything the agent found during a hardware diagnostics boot
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HardwareDiagnosticsReport {
+    pub checks: Vec<HardwareCheckResult>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+impl HardwareDiagnosticsReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+}
+
+// Result of the post-install verification action: the expected disk layout exists, the
+// installed filesystems mount and /etc/fstab is sane
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstallVerificationReport {
+    // Layout name from the partitioning profile ("lvm", "zfs"); None for the image's own layout
+    #[serde(default)]
+    pub layout: Option<String>,
+    pub checks: Vec<HardwareCheckResult>,
+}
+
+impl InstallVerificationReport {
+    pub fn passed(&self) -> bool {
+        !self.checks.is_empty() && self.checks.iter().all(|c| c.passed)
+    }
+}
+
+// Patch level of an installed OS, reported periodically by the agent running as a daemon
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PatchReport {
+    pub kernel_version: String,
+    // Package manager the counts came from ("apt", "dnf"); None when there isn't a supported one
+    #[serde(default)]
+    pub package_manager: Option<String>,
+    #[serde(default)]
+    pub pending_updates: Option<u32>,
+    #[serde(default)]
+    pub pending_security_updates: Option<u32>,
+    #[serde(default)]
+    pub reboot_required: bool,
+}
+
+// Install stages whose timing the installer environment reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStage {
+    ImageDownload,
+    ImageWrite,
+    BootloaderInstall,
+}
+
+impl InstallStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallStage::ImageDownload => "image_download",
+            InstallStage::ImageWrite => "image_write",
+            InstallStage::BootloaderInstall => "bootloader_install",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "image_download" => Some(InstallStage::ImageDownload),
+            "image_write" => Some(InstallStage::ImageWrite),
+            "bootloader_install" => Some(InstallStage::BootloaderInstall),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageBoundary {
+    Start,
+    End,
+}
+
+// A stage starting or ending during an install
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallStageEvent {
+    pub stage: InstallStage,
+    pub event: StageBoundary,
+    // When it happened; reporters without a reliable clock leave it to the server
+    #[serde(default)]
+    pub at: Option<DateTime<Utc>>,
+    // The workflow action this stage's duration stands in for in timing estimates
+    #[serde(default)]
+    pub action: Option<String>,
+}
+
+// Body of POST /api/install-telemetry/{mac}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallTelemetryReport {
+    pub events: Vec<InstallStageEvent>,
+}
+
+// API versions this build of the common models speaks, newest first
+pub const API_VERSIONS: &[&str] = &["v1"];
+
+// Returned by GET /api/version so agents can pick a route prefix the server supports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiVersionInfo {
+    pub server_version: String,
+    pub schema_version: u32,
+    pub api_versions: Vec<String>,
+    pub current_api_version: String,
+}
+
+// Returned by GET /api/capabilities; clients check features here rather than parsing versions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    // CPU architectures the server has boot artifacts for (e.g. "x86_64", "aarch64")
+    pub architectures: Vec<String>,
+    // Signed shim/kernel artifacts for Secure Boot machines
+    pub secure_boot_artifacts: bool,
+    // Boot files and DHCP guidance for UEFI HTTP Boot at /api/http-boot
+    #[serde(default)]
+    pub http_boot: bool,
+    // Artifact downloads honour Range headers
+    pub ranged_downloads: bool,
+    // Several ranges in one request come back as multipart/byteranges
+    pub multi_range_downloads: bool,
+    // Bidirectional websocket channel between agent and server
+    pub websocket_channel: bool,
+    // Server-sent event stream at /events
+    pub event_stream: bool,
+    // Scopes of limited-access tokens the server issues
+    pub token_auth_scopes: Vec<String>,
+    // Installers can report stage timestamps at /install-telemetry/{mac}
+    #[serde(default)]
+    pub install_telemetry: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(uuid: Option<&str>, serial: Option<&str>, disks: &[&str]) -> HardwareFingerprint {
+        HardwareFingerprint {
+            system_uuid: uuid.map(String::from),
+            system_serial: serial.map(String::from),
+            disk_serials: disks.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_placeholder() {
+        for value in ["", "  ", "00000000-0000-0000-0000-000000000000", "FFFFFFFF-FFFF-FFFF-FFFF-FFFFFFFFFFFF",
+            "03000200-0400-0500-0006-000700080009", "To Be Filled By O.E.M.", "Default string",
+            "System Serial Number", "Not Specified", "None"] {
+            assert!(HardwareFingerprint::is_placeholder(value), "{:?} should be a placeholder", value);
+        }
+        for value in ["4c4c4544-0042-3510-8052-b4c04f4e3332", "CZ2831ABCD"] {
+            assert!(!HardwareFingerprint::is_placeholder(value), "{:?} should not be a placeholder", value);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let known = fingerprint(Some("4c4c4544-0042-3510-8052-b4c04f4e3332"), Some("CZ2831ABCD"), &["S1", "S2"]);
+
+        // UUID and serial compare case-insensitively, and either is enough on its own
+        assert!(known.matches(&fingerprint(Some("4C4C4544-0042-3510-8052-B4C04F4E3332"), None, &[])));
+        assert!(known.matches(&fingerprint(None, Some("cz2831abcd"), &[])));
+
+        // The same set of disks matches regardless of order, but a subset doesn't
+        assert!(known.matches(&fingerprint(None, None, &["S2", "S1"])));
+        assert!(!known.matches(&fingerprint(None, None, &["S1"])));
+
+        // Shared placeholder values identify nothing
+        let filler = fingerprint(Some("03000200-0400-0500-0006-000700080009"), Some("To Be Filled By O.E.M."), &[]);
+        assert!(!filler.matches(&filler.clone()));
+        assert!(!HardwareFingerprint::default().matches(&HardwareFingerprint::default()));
+    }
+}
@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// OS choices for custom images start with this, e.g. "custom-ubuntu-golden".
+pub const CUSTOM_OS_PREFIX: &str = "custom-";
+
+/// An operating system Dragonfly ships a Tinkerbell template for. The string form
+/// ("ubuntu-2204") is the OS choice stored on machines and the template's name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum OsIdentifier {
+    #[default]
+    Ubuntu2204,
+    Ubuntu2404,
+    Debian12,
+    Proxmox,
+    Talos,
+}
+
+impl OsIdentifier {
+    /// Every built-in OS, in the order menus list them.
+    pub const ALL: [OsIdentifier; 5] = [
+        OsIdentifier::Ubuntu2204,
+        OsIdentifier::Ubuntu2404,
+        OsIdentifier::Debian12,
+        OsIdentifier::Proxmox,
+        OsIdentifier::Talos,
+    ];
+
+    /// The OS choice and template name, e.g. "ubuntu-2204".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OsIdentifier::Ubuntu2204 => "ubuntu-2204",
+            OsIdentifier::Ubuntu2404 => "ubuntu-2404",
+            OsIdentifier::Debian12 => "debian-12",
+            OsIdentifier::Proxmox => "proxmox",
+            OsIdentifier::Talos => "talos",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            OsIdentifier::Ubuntu2204 => "Ubuntu 22.04",
+            OsIdentifier::Ubuntu2404 => "Ubuntu 24.04",
+            OsIdentifier::Debian12 => "Debian 12",
+            OsIdentifier::Proxmox => "Proxmox VE",
+            OsIdentifier::Talos => "Talos",
+        }
+    }
+
+    /// Lowercase distribution name, e.g. "ubuntu".
+    pub fn distribution(&self) -> &'static str {
+        match self {
+            OsIdentifier::Ubuntu2204 | OsIdentifier::Ubuntu2404 => "ubuntu",
+            OsIdentifier::Debian12 => "debian",
+            OsIdentifier::Proxmox => "proxmox",
+            OsIdentifier::Talos => "talos",
+        }
+    }
+
+    /// Release version, for distributions whose templates pin one.
+    pub fn version(&self) -> Option<&'static str> {
+        match self {
+            OsIdentifier::Ubuntu2204 => Some("22.04"),
+            OsIdentifier::Ubuntu2404 => Some("24.04"),
+            OsIdentifier::Debian12 => Some("12"),
+            OsIdentifier::Proxmox | OsIdentifier::Talos => None,
+        }
+    }
+
+    pub fn codename(&self) -> Option<&'static str> {
+        match self {
+            OsIdentifier::Ubuntu2204 => Some("jammy"),
+            OsIdentifier::Ubuntu2404 => Some("noble"),
+            OsIdentifier::Debian12 => Some("bookworm"),
+            OsIdentifier::Proxmox | OsIdentifier::Talos => None,
+        }
+    }
+
+    /// Whether the install is configured with cloud-init (user data, kernel args, partitioning).
+    pub fn uses_cloud_init(&self) -> bool {
+        matches!(self, OsIdentifier::Ubuntu2204 | OsIdentifier::Ubuntu2404 | OsIdentifier::Debian12)
+    }
+
+    /// Match loosely written names too: "Ubuntu 22.04", "ubuntu_2204", "debian-bookworm".
+    pub fn from_alias(name: &str) -> Option<Self> {
+        let wanted = normalize(name);
+        Self::ALL.into_iter().find(|os| {
+            normalize(os.as_str()) == wanted
+                || normalize(&format!("{} {}", os.distribution(), os.version().unwrap_or_default())) == wanted
+                || os.codename().is_some_and(|codename| normalize(&format!("{} {}", os.distribution(), codename)) == wanted)
+                || normalize(os.display_name()) == wanted
+        })
+    }
+}
+
+// Lowercase letters and digits only
+fn normalize(name: &str) -> String {
+    name.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+impl fmt::Display for OsIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OsIdentifier {
+    type Err = UnknownOs;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter()
+            .find(|os| os.as_str() == s)
+            .ok_or_else(|| UnknownOs::new(s))
+    }
+}
+
+impl TryFrom<String> for OsIdentifier {
+    type Error = UnknownOs;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<OsIdentifier> for String {
+    fn from(os: OsIdentifier) -> Self {
+        os.as_str().to_string()
+    }
+}
+
+/// An OS choice that isn't a built-in OS, with the one it was probably meant to be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownOs {
+    pub name: String,
+    pub suggestion: Option<OsIdentifier>,
+}
+
+impl UnknownOs {
+    fn new(name: &str) -> Self {
+        let suggestion = OsIdentifier::from_alias(name).or_else(|| {
+            OsIdentifier::ALL.into_iter()
+                .map(|os| (edit_distance(&name.to_ascii_lowercase(), os.as_str()), os))
+                .filter(|(distance, _)| *distance <= 2)
+                .min_by_key(|(distance, _)| *distance)
+                .map(|(_, os)| os)
+        });
+        Self { name: name.to_string(), suggestion }
+    }
+}
+
+impl fmt::Display for UnknownOs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not a known operating system", self.name)?;
+        match self.suggestion {
+            Some(os) => write!(f, "; did you mean '{}'?", os),
+            None => {
+                let known: Vec<&str> = OsIdentifier::ALL.iter().map(OsIdentifier::as_str).collect();
+                write!(f, " (expected one of {} or a custom image)", known.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnknownOs {}
+
+// Levenshtein distance, for typo suggestions between short names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Check an OS choice: a built-in OS or a custom image. Whether the custom image exists is
+/// up to the caller.
+pub fn check_os_choice(choice: &str) -> Result<(), UnknownOs> {
+    if choice.strip_prefix(CUSTOM_OS_PREFIX).is_some_and(|name| !name.is_empty()) {
+        return Ok(());
+    }
+    choice.parse::<OsIdentifier>().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for os in OsIdentifier::ALL {
+            assert_eq!(os.as_str().parse::<OsIdentifier>(), Ok(os));
+            assert_eq!(serde_json::to_string(&os).unwrap(), format!("\"{}\"", os));
+            assert_eq!(serde_json::from_str::<OsIdentifier>(&format!("\"{}\"", os)).unwrap(), os);
+        }
+        assert!(serde_json::from_str::<OsIdentifier>("\"ubuntu\"").is_err());
+    }
+
+    #[test]
+    fn test_aliases_and_suggestions() {
+        assert_eq!(OsIdentifier::from_alias("Ubuntu 22.04"), Some(OsIdentifier::Ubuntu2204));
+        assert_eq!(OsIdentifier::from_alias("debian-bookworm"), Some(OsIdentifier::Debian12));
+        assert_eq!(OsIdentifier::from_alias("Proxmox VE"), Some(OsIdentifier::Proxmox));
+        assert_eq!(OsIdentifier::from_alias("fedora-40"), None);
+
+        let err = "ubuntu-22.04".parse::<OsIdentifier>().unwrap_err();
+        assert_eq!(err.suggestion, Some(OsIdentifier::Ubuntu2204));
+        assert_eq!(err.to_string(), "'ubuntu-22.04' is not a known operating system; did you mean 'ubuntu-2204'?");
+        assert_eq!("debain-12".parse::<OsIdentifier>().unwrap_err().suggestion, Some(OsIdentifier::Debian12));
+        assert_eq!("windows".parse::<OsIdentifier>().unwrap_err().suggestion, None);
+    }
+
+    #[test]
+    fn test_check_os_choice() {
+        assert!(check_os_choice("talos").is_ok());
+        assert!(check_os_choice("custom-ubuntu-golden").is_ok());
+        assert!(check_os_choice("custom-").is_err());
+        assert!(check_os_choice("Ubuntu-2404").is_err());
+    }
+}
@@ -0,0 +1,37 @@
+// Fixtures for tests here and in the crates that depend on this one (with the "testing" feature).
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::models::{Machine, MachineStatus};
+
+// A Ready machine with nothing but its NIC set. Tests override the fields they care about with
+// struct update syntax, so a new Machine field only has to be added here.
+pub fn machine() -> Machine {
+    Machine {
+        id: Uuid::new_v4(),
+        mac_address: "52:54:00:12:34:56".to_string(),
+        ip_address: "10.0.0.5".to_string(),
+        hostname: None,
+        os_choice: None,
+        os_installed: None,
+        status: MachineStatus::Ready,
+        disks: Vec::new(),
+        nameservers: Vec::new(),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        memorable_name: None,
+        bmc_credentials: None,
+        installation_progress: 0,
+        installation_step: None,
+        last_deployment_duration: None,
+        cpu_model: None,
+        cpu_cores: None,
+        total_ram_bytes: None,
+        proxmox_vmid: None,
+        proxmox_node: None,
+        proxmox_cluster: None,
+        is_proxmox_host: false,
+        tinkerbell_synced: true,
+    }
+}
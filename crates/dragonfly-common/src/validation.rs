@@ -0,0 +1,325 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::models::{
+    BmcCredentialsUpdateRequest, DiskInfo, HostnameUpdateRequest, InstallationProgressUpdateRequest,
+    Machine, OsAssignmentRequest, OsInstalledUpdateRequest, RegisterRequest,
+};
+use crate::os::check_os_choice;
+
+/// A single invalid field in a request payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// All field errors found while validating a payload.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Ok if no errors were collected, otherwise the collected errors.
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.errors.iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Implemented by request payloads that can be checked before they reach the database.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// `aa:bb:cc:dd:ee:ff` or `aa-bb-cc-dd-ee-ff`, case-insensitive.
+pub fn is_valid_mac(mac: &str) -> bool {
+    let parts: Vec<&str> = mac.split([':', '-']).collect();
+    parts.len() == 6 && parts.iter().all(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// IPv4 or IPv6 literal.
+pub fn is_valid_ip(ip: &str) -> bool {
+    ip.parse::<IpAddr>().is_ok()
+}
+
+/// RFC 1123 hostname: dot-separated labels of 1-63 alphanumerics/hyphens,
+/// not starting or ending with a hyphen, at most 253 characters overall.
+pub fn is_valid_hostname(hostname: &str) -> bool {
+    if hostname.is_empty() || hostname.len() > 253 {
+        return false;
+    }
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+    hostname.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// BMC address: an IP, a hostname, either with an optional port, or an http(s) URL
+/// whose host is one of those.
+pub fn is_valid_bmc_address(address: &str) -> bool {
+    let host_port = match address.split_once("://") {
+        Some((scheme, rest)) if scheme == "http" || scheme == "https" => {
+            rest.split('/').next().unwrap_or("")
+        },
+        Some(_) => return false,
+        None => address,
+    };
+
+    // Bracketed IPv6 with optional port, e.g. [fe80::1]:623
+    if let Some(rest) = host_port.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((ip, port)) => is_valid_ip(ip) && (port.is_empty() || is_valid_port_suffix(port)),
+            None => false,
+        };
+    }
+
+    // Bare IPv6 without port
+    if is_valid_ip(host_port) {
+        return true;
+    }
+
+    match host_port.rsplit_once(':') {
+        Some((host, port)) => (is_valid_ip(host) || is_valid_hostname(host)) && is_valid_port_suffix(&format!(":{}", port)),
+        None => is_valid_hostname(host_port),
+    }
+}
+
+fn is_valid_port_suffix(suffix: &str) -> bool {
+    suffix.strip_prefix(':')
+        .and_then(|p| p.parse::<u16>().ok())
+        .is_some_and(|p| p != 0)
+}
+
+fn validate_disks(disks: &[DiskInfo], errors: &mut ValidationErrors) {
+    for (i, disk) in disks.iter().enumerate() {
+        if disk.device.trim().is_empty() {
+            errors.add(format!("disks[{}].device", i), "must not be empty");
+        }
+        if disk.size_bytes == 0 {
+            errors.add(format!("disks[{}].size_bytes", i), "must be greater than zero");
+        }
+    }
+}
+
+fn validate_nameservers(nameservers: &[String], errors: &mut ValidationErrors) {
+    for (i, ns) in nameservers.iter().enumerate() {
+        if !is_valid_ip(ns) {
+            errors.add(format!("nameservers[{}]", i), format!("'{}' is not a valid IP address", ns));
+        }
+    }
+}
+
+impl Validate for RegisterRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !is_valid_mac(&self.mac_address) {
+            errors.add("mac_address", format!("'{}' is not a valid MAC address", self.mac_address));
+        }
+        if !is_valid_ip(&self.ip_address) {
+            errors.add("ip_address", format!("'{}' is not a valid IPv4 or IPv6 address", self.ip_address));
+        }
+        if let Some(hostname) = &self.hostname {
+            if !is_valid_hostname(hostname) {
+                errors.add("hostname", format!("'{}' is not a valid RFC 1123 hostname", hostname));
+            }
+        }
+        validate_disks(&self.disks, &mut errors);
+        validate_nameservers(&self.nameservers, &mut errors);
+        if self.cpu_cores == Some(0) {
+            errors.add("cpu_cores", "must be greater than zero");
+        }
+        if self.total_ram_bytes == Some(0) {
+            errors.add("total_ram_bytes", "must be greater than zero");
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for Machine {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !is_valid_mac(&self.mac_address) {
+            errors.add("mac_address", format!("'{}' is not a valid MAC address", self.mac_address));
+        }
+        if !is_valid_ip(&self.ip_address) {
+            errors.add("ip_address", format!("'{}' is not a valid IPv4 or IPv6 address", self.ip_address));
+        }
+        if let Some(hostname) = &self.hostname {
+            if !is_valid_hostname(hostname) {
+                errors.add("hostname", format!("'{}' is not a valid RFC 1123 hostname", hostname));
+            }
+        }
+        validate_disks(&self.disks, &mut errors);
+        validate_nameservers(&self.nameservers, &mut errors);
+        if let Some(bmc) = &self.bmc_credentials {
+            if !is_valid_bmc_address(&bmc.address) {
+                errors.add("bmc_credentials.address", format!("'{}' is not a valid BMC address", bmc.address));
+            }
+        }
+        if self.installation_progress > 100 {
+            errors.add("installation_progress", "must be between 0 and 100");
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for HostnameUpdateRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !is_valid_hostname(&self.hostname) {
+            errors.add("hostname", format!("'{}' is not a valid RFC 1123 hostname", self.hostname));
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for OsInstalledUpdateRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.os_installed.trim().is_empty() {
+            errors.add("os_installed", "must not be empty");
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for OsAssignmentRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if let Err(e) = check_os_choice(&self.os_choice) {
+            errors.add("os_choice", e.to_string());
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for BmcCredentialsUpdateRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !is_valid_bmc_address(&self.bmc_address) {
+            errors.add("bmc_address", format!("'{}' is not a valid BMC address", self.bmc_address));
+        }
+        if self.bmc_username.trim().is_empty() {
+            errors.add("bmc_username", "must not be empty");
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for InstallationProgressUpdateRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.progress > 100 {
+            errors.add("progress", "must be between 0 and 100");
+        }
+        errors.into_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac_validation() {
+        assert!(is_valid_mac("00:1a:2B:3c:4d:5e"));
+        assert!(is_valid_mac("00-1a-2b-3c-4d-5e"));
+        assert!(!is_valid_mac("00:1a:2b:3c:4d"));
+        assert!(!is_valid_mac("00:1a:2b:3c:4d:zz"));
+        assert!(!is_valid_mac(""));
+    }
+
+    #[test]
+    fn test_ip_validation() {
+        assert!(is_valid_ip("192.168.1.10"));
+        assert!(is_valid_ip("fe80::1"));
+        assert!(!is_valid_ip("256.1.1.1"));
+        assert!(!is_valid_ip("not-an-ip"));
+    }
+
+    #[test]
+    fn test_hostname_validation() {
+        assert!(is_valid_hostname("node-01"));
+        assert!(is_valid_hostname("node-01.example.com"));
+        assert!(!is_valid_hostname("-node"));
+        assert!(!is_valid_hostname("node_01"));
+        assert!(!is_valid_hostname("a..b"));
+        assert!(!is_valid_hostname(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_bmc_address_validation() {
+        assert!(is_valid_bmc_address("10.0.0.5"));
+        assert!(is_valid_bmc_address("10.0.0.5:623"));
+        assert!(is_valid_bmc_address("bmc01.example.com"));
+        assert!(is_valid_bmc_address("https://bmc01.example.com/redfish/v1"));
+        assert!(is_valid_bmc_address("[fe80::1]:443"));
+        assert!(is_valid_bmc_address("fe80::1"));
+        assert!(!is_valid_bmc_address("ftp://bmc01"));
+        assert!(!is_valid_bmc_address("10.0.0.5:99999"));
+        assert!(!is_valid_bmc_address(""));
+    }
+
+    #[test]
+    fn test_register_request_collects_all_errors() {
+        let req = RegisterRequest {
+            mac_address: "bogus".to_string(),
+            ip_address: "also-bogus".to_string(),
+            hostname: Some("bad_host".to_string()),
+            disks: vec![DiskInfo {
+                device: "".to_string(),
+                size_bytes: 0,
+                model: None,
+                calculated_size: None,
+            }],
+            nameservers: vec![],
+            cpu_model: None,
+            cpu_cores: None,
+            total_ram_bytes: None,
+            proxmox_vmid: None,
+            proxmox_node: None,
+            proxmox_cluster: None,
+            hardware_fingerprint: None,
+            agent_environment: None,
+        };
+        let errors = req.validate().unwrap_err();
+        let fields: Vec<&str> = errors.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["mac_address", "ip_address", "hostname", "disks[0].device", "disks[0].size_bytes"]);
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+// Length of generated tokens; alphanumeric so they survive being pasted into URLs
+const TOKEN_LENGTH: usize = 32;
+
+/// What a limited-access token lets its holder see. These never grant admin rights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenScope {
+    /// The public install status page of a single machine
+    MachineStatus,
+    /// The fleet-wide wallboard, for displays that can't hold an admin session
+    Wallboard,
+    /// Registering new custom image versions from a CI pipeline
+    ImageWebhook,
+    /// Uploading files into the artifact store from a build host
+    ArtifactUpload,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 4] = [TokenScope::MachineStatus, TokenScope::Wallboard, TokenScope::ImageWebhook, TokenScope::ArtifactUpload];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::MachineStatus => "machine_status",
+            TokenScope::Wallboard => "wallboard",
+            TokenScope::ImageWebhook => "image_webhook",
+            TokenScope::ArtifactUpload => "artifact_upload",
+        }
+    }
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+// Only the hash is stored, so a leaked database doesn't leak working links
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Create a token for the given scope (optionally bound to one machine) and return it.
+/// The plaintext is only available here.
+pub async fn issue(scope: TokenScope, machine_id: Option<&Uuid>) -> Result<String> {
+    let token = generate_token();
+    crate::db::save_access_token(&hash_token(&token), scope.as_str(), machine_id).await?;
+    Ok(token)
+}
+
+/// Check a presented token against the scope and machine it must be valid for.
+pub async fn verify(token: &str, scope: TokenScope, machine_id: Option<&Uuid>) -> Result<bool> {
+    if token.is_empty() {
+        return Ok(false);
+    }
+    crate::db::access_token_exists(&hash_token(token), scope.as_str(), machine_id).await
+}
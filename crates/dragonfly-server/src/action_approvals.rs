@@ -0,0 +1,529 @@
+// Two-person rule for destructive actions.
+//
+// When enabled, deleting machines (one at a time or in bulk) and reinstalling Ready machines
+// don't happen straight away. The request is stored as a pending approval and every configured
+// approver is emailed a signed link to a confirmation page. The action runs once a second admin
+// approves it, through that page or the API. Either way the decision is made signed in, and it's
+// the admin account that counts: whoever requested an action can't approve it, whichever
+// approver's link they open. Unanswered requests expire after expiry_minutes.
+//
+// Each approval keeps who asked, who decided, how, and what the action did when it ran, which
+// makes the table the audit log for these actions (`GET /api/action-approvals`).
+//
+// Mail is sent as plain SMTP without authentication to smtp_relay, meant to be a local MTA that
+// handles delivery.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+const KEY_PURPOSE: &str = "dragonfly-action-approvals-v1";
+const DEFAULT_EXPIRY_MINUTES: u32 = 60;
+const MAX_EXPIRY_MINUTES: u32 = 7 * 24 * 60;
+const SMTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+// How many recent requests are searched for one already pending on a machine's deletion
+const PENDING_SCAN_LIMIT: i64 = 1000;
+
+fn default_expiry_minutes() -> u32 {
+    DEFAULT_EXPIRY_MINUTES
+}
+
+/// Whether destructive actions need a second person, and who to ask.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Email addresses sent a confirmation link for every request
+    #[serde(default)]
+    pub approvers: Vec<String>,
+    #[serde(default = "default_expiry_minutes")]
+    pub expiry_minutes: u32,
+    /// SMTP relay as host or host:port (port 25 when left out)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp_relay: Option<String>,
+    /// Sender address for approval mail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mail_from: Option<String>,
+}
+
+impl Default for ApprovalSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            approvers: Vec::new(),
+            expiry_minutes: DEFAULT_EXPIRY_MINUTES,
+            smtp_relay: None,
+            mail_from: None,
+        }
+    }
+}
+
+// One address, nothing that could end up as an extra mail header or SMTP command
+fn valid_address(address: &str) -> bool {
+    let Some((local, domain)) = address.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && domain.contains('.')
+        && !domain.contains('@')
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || "<>,;\"".contains(c))
+}
+
+impl ApprovalSettings {
+    /// Trim fields, drop blanks and check the mail settings hang together.
+    pub fn normalize(mut self) -> Result<Self> {
+        let blank_to_none = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        self.smtp_relay = blank_to_none(self.smtp_relay);
+        self.mail_from = blank_to_none(self.mail_from);
+        self.approvers = self.approvers.iter()
+            .map(|a| a.trim().to_lowercase())
+            .filter(|a| !a.is_empty())
+            .collect();
+        self.approvers.sort();
+        self.approvers.dedup();
+
+        if self.expiry_minutes == 0 || self.expiry_minutes > MAX_EXPIRY_MINUTES {
+            bail!("expiry_minutes must be between 1 and {}", MAX_EXPIRY_MINUTES);
+        }
+        for address in self.approvers.iter().chain(self.mail_from.iter()) {
+            if !valid_address(address) {
+                bail!("'{}' is not a valid email address", address);
+            }
+        }
+        if let Some(relay) = &self.smtp_relay {
+            if relay.chars().any(|c| c.is_whitespace() || c == '/') {
+                bail!("smtp_relay must be host or host:port");
+            }
+        }
+        if !self.approvers.is_empty() && (self.smtp_relay.is_none() || self.mail_from.is_none()) {
+            bail!("Emailing approvers needs smtp_relay and mail_from");
+        }
+        Ok(self)
+    }
+}
+
+/// A destructive action waiting on, or carried out after, a second person's approval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DestructiveAction {
+    DeleteMachines { machine_ids: Vec<Uuid> },
+    ReinstallMachines { machine_ids: Vec<Uuid> },
+    /// Install a campaign's machines as a canary rollout
+    StartRollout { campaign_id: Uuid, strategy: crate::campaign::RolloutStrategy },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalState {
+    Pending,
+    Approved,
+    Rejected,
+    Expired,
+}
+
+/// How an approval was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecidedVia {
+    /// From the UI or API
+    Ui,
+    /// From the page an approver's emailed link opens
+    Email,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionApproval {
+    pub id: Uuid,
+    pub action: DestructiveAction,
+    /// What the action does, in words, e.g. "Delete machine web-1"
+    pub summary: String,
+    pub state: ApprovalState,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_via: Option<DecidedVia>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_at: Option<DateTime<Utc>>,
+    /// What happened when the approved action ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<serde_json::Value>,
+}
+
+impl ActionApproval {
+    /// The state as of `now`: pending requests past their expiry are expired, stored or not.
+    pub fn state_at(&self, now: DateTime<Utc>) -> ApprovalState {
+        match self.state {
+            ApprovalState::Pending if now >= self.expires_at => ApprovalState::Expired,
+            state => state,
+        }
+    }
+
+    /// Whether this is a request to delete machine `id` that's still waiting on a decision.
+    pub fn awaits_deletion_of(&self, id: &Uuid, now: DateTime<Utc>) -> bool {
+        self.state_at(now) == ApprovalState::Pending
+            && matches!(&self.action, DestructiveAction::DeleteMachines { machine_ids } if machine_ids.contains(id))
+    }
+
+    /// Whether the signed-in admin `username` may decide this request: anyone but its requester.
+    pub fn decidable_by(&self, username: &str) -> bool {
+        !username.eq_ignore_ascii_case(&self.requested_by)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum DecisionError {
+    #[error("No such approval request")]
+    NotFound,
+    #[error("This request has already been decided")]
+    AlreadyDecided,
+    #[error("This request has expired")]
+    Expired,
+    #[error("A request has to be approved by someone other than whoever made it")]
+    SelfApproval,
+    #[error("This approval link is invalid")]
+    BadLink,
+}
+
+pub async fn settings() -> Result<ApprovalSettings> {
+    crate::db::get_action_approval_settings().await
+}
+
+/// Whether destructive actions currently need a second person.
+pub async fn required() -> Result<bool> {
+    Ok(settings().await?.enabled)
+}
+
+fn signing_key() -> [u8; 32] {
+    crate::encryption::derive_key(KEY_PURPOSE)
+}
+
+fn mac(key: &[u8], id: &Uuid, approver: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(id.as_bytes());
+    mac.update(approver.as_bytes());
+    mac
+}
+
+/// Signature binding an emailed link to one request and one approver.
+pub fn sign_link(key: &[u8], id: &Uuid, approver: &str) -> String {
+    URL_SAFE_NO_PAD.encode(mac(key, id, approver).finalize().into_bytes())
+}
+
+pub fn verify_link(key: &[u8], id: &Uuid, approver: &str, signature: &str) -> bool {
+    URL_SAFE_NO_PAD.decode(signature)
+        .is_ok_and(|signature| mac(key, id, approver).verify_slice(&signature).is_ok())
+}
+
+/// Check an emailed link, returning the approver it was sent to. The approver must still be
+/// configured, so removing someone from the list revokes their outstanding links.
+pub async fn link_approver(id: &Uuid, approver: &str, signature: &str) -> Result<Result<String, DecisionError>> {
+    let approver = approver.trim().to_lowercase();
+    if !verify_link(&signing_key(), id, &approver, signature) {
+        return Ok(Err(DecisionError::BadLink));
+    }
+    if !settings().await?.approvers.contains(&approver) {
+        return Ok(Err(DecisionError::BadLink));
+    }
+    Ok(Ok(approver))
+}
+
+// Link to an approval's confirmation page, absolute when DRAGONFLY_BASE_URL is set
+fn confirm_link(id: &Uuid, approver: &str) -> String {
+    let path = crate::base_path::url(&format!(
+        "/approve/{}?approver={}&sig={}",
+        id,
+        urlencoding::encode(approver),
+        sign_link(&signing_key(), id, approver)
+    ));
+    match crate::config::base_url() {
+        Some(base) => format!("{}{}", base.trim_end_matches('/'), path),
+        None => path,
+    }
+}
+
+/// File an action for approval and email the approvers.
+pub async fn request(action: DestructiveAction, summary: String, requested_by: &str) -> Result<ActionApproval> {
+    let settings = settings().await?;
+    let now = Utc::now();
+    let approval = ActionApproval {
+        id: Uuid::new_v4(),
+        action,
+        summary,
+        state: ApprovalState::Pending,
+        requested_by: requested_by.to_string(),
+        requested_at: now,
+        expires_at: now + Duration::minutes(settings.expiry_minutes as i64),
+        decided_by: None,
+        decided_via: None,
+        decided_at: None,
+        outcome: None,
+    };
+    crate::db::save_action_approval(&approval).await?;
+    info!("Audit: {} requested \"{}\", awaiting approval {}", requested_by, approval.summary, approval.id);
+
+    // The request stands even if the relay is down; another admin can still approve it
+    let pending = approval.clone();
+    tokio::spawn(async move {
+        for approver in &settings.approvers {
+            if let Err(e) = mail_approver(&settings, &pending, approver).await {
+                error!("Failed to email approval {} to {}: {:#}", pending.id, approver, e);
+            }
+        }
+    });
+    Ok(approval)
+}
+
+/// Delete a machine on the server's own initiative, such as pruning a VM that's gone from
+/// Proxmox. With the two-person rule on, the deletion is filed for approval instead, once per
+/// machine, so a periodic sync doesn't file it again each run. Returns whether it was deleted.
+pub async fn delete_machine(id: &Uuid, summary: String, requested_by: &str) -> Result<bool> {
+    if !required().await? {
+        return crate::db::delete_machine(id).await;
+    }
+    let now = Utc::now();
+    let approvals = crate::db::list_action_approvals(PENDING_SCAN_LIMIT).await?;
+    if !approvals.iter().any(|approval| approval.awaits_deletion_of(id, now)) {
+        request(DestructiveAction::DeleteMachines { machine_ids: vec![*id] }, summary, requested_by).await?;
+    }
+    Ok(false)
+}
+
+/// Approve or reject a pending request. The outer error is a database failure.
+pub async fn decide(id: &Uuid, approve: bool, decided_by: &str, via: DecidedVia) -> Result<Result<ActionApproval, DecisionError>> {
+    let Some(mut approval) = crate::db::get_action_approval(id).await? else {
+        return Ok(Err(DecisionError::NotFound));
+    };
+    let now = Utc::now();
+    match approval.state_at(now) {
+        ApprovalState::Pending => {}
+        ApprovalState::Expired => {
+            if approval.state == ApprovalState::Pending {
+                approval.state = ApprovalState::Expired;
+                crate::db::decide_action_approval(&approval).await?;
+                info!("Audit: approval {} (\"{}\") expired unanswered", approval.id, approval.summary);
+            }
+            return Ok(Err(DecisionError::Expired));
+        }
+        _ => return Ok(Err(DecisionError::AlreadyDecided)),
+    }
+    if !approval.decidable_by(decided_by) {
+        warn!("Audit: {} tried to decide their own request {}", decided_by, approval.id);
+        return Ok(Err(DecisionError::SelfApproval));
+    }
+
+    approval.state = if approve { ApprovalState::Approved } else { ApprovalState::Rejected };
+    approval.decided_by = Some(decided_by.to_string());
+    approval.decided_via = Some(via);
+    approval.decided_at = Some(now);
+    // Only one decision wins when two approvers answer at once
+    if !crate::db::decide_action_approval(&approval).await? {
+        return Ok(Err(DecisionError::AlreadyDecided));
+    }
+    info!(
+        "Audit: {} {} \"{}\" (approval {}, requested by {})",
+        decided_by,
+        if approve { "approved" } else { "rejected" },
+        approval.summary,
+        approval.id,
+        approval.requested_by
+    );
+    Ok(Ok(approval))
+}
+
+/// Record what an approved action did.
+pub async fn record_outcome(approval: &mut ActionApproval, outcome: serde_json::Value) -> Result<()> {
+    approval.outcome = Some(outcome);
+    crate::db::update_action_approval(approval).await?;
+    info!("Audit: ran \"{}\" (approval {})", approval.summary, approval.id);
+    Ok(())
+}
+
+async fn mail_approver(settings: &ApprovalSettings, approval: &ActionApproval, approver: &str) -> Result<()> {
+    let relay = settings.smtp_relay.as_deref().ok_or_else(|| anyhow!("No SMTP relay configured"))?;
+    let from = settings.mail_from.as_deref().ok_or_else(|| anyhow!("No sender address configured"))?;
+    let link = confirm_link(&approval.id, approver);
+    if crate::config::base_url().is_none() {
+        warn!("DRAGONFLY_BASE_URL isn't set, so approval links in mail are relative");
+    }
+    let body = format!(
+        "{} asked Dragonfly to:\n\n    {}\n\nThis needs a second admin's approval. Sign in and approve or reject it here:\n\n{}\n\nThe request expires at {}.\n",
+        approval.requested_by,
+        approval.summary,
+        link,
+        approval.expires_at.format("%Y-%m-%d %H:%M UTC"),
+    );
+    let subject = format!("Approval needed: {}", approval.summary);
+    send_mail(relay, from, approver, &subject, &body).await
+}
+
+// Build the DATA section of a message: headers, a CRLF body with leading dots doubled, and the
+// terminating dot
+fn message(from: &str, to: &str, subject: &str, body: &str, date: DateTime<Utc>) -> String {
+    let subject: String = subject.chars().filter(|c| !c.is_control()).collect();
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@dragonfly>\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        from, to, subject, date.to_rfc2822(), Uuid::new_v4()
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+    message
+}
+
+// Read one (possibly multi-line) reply and check its code
+async fn expect_reply<R: AsyncBufReadExt + Unpin>(reader: &mut R, expected: u16) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("SMTP relay closed the connection");
+        }
+        // "250-..." continues the reply; the last line has a space after the code
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if line.get(..3).and_then(|code| code.parse::<u16>().ok()) != Some(expected) {
+            bail!("SMTP relay replied '{}'", line.trim_end());
+        }
+        return Ok(());
+    }
+}
+
+async fn send_mail(relay: &str, from: &str, to: &str, subject: &str, body: &str) -> Result<()> {
+    let address = if relay.contains(':') { relay.to_string() } else { format!("{}:25", relay) };
+    tokio::time::timeout(SMTP_TIMEOUT, async {
+        let stream = TcpStream::connect(&address).await
+            .with_context(|| format!("Failed to connect to SMTP relay {}", address))?;
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        expect_reply(&mut reader, 220).await?;
+        let commands = [
+            ("EHLO dragonfly".to_string(), 250),
+            (format!("MAIL FROM:<{}>", from), 250),
+            (format!("RCPT TO:<{}>", to), 250),
+            ("DATA".to_string(), 354),
+        ];
+        for (command, code) in commands {
+            write.write_all(format!("{}\r\n", command).as_bytes()).await?;
+            expect_reply(&mut reader, code).await?;
+        }
+        write.write_all(message(from, to, subject, body, Utc::now()).as_bytes()).await?;
+        expect_reply(&mut reader, 250).await?;
+        write.write_all(b"QUIT\r\n").await?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| anyhow!("Timed out talking to SMTP relay {}", address))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let settings = ApprovalSettings {
+            enabled: true,
+            approvers: vec![" Ops@Example.com ".to_string(), "".to_string()],
+            smtp_relay: Some("localhost".to_string()),
+            mail_from: Some("dragonfly@example.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(settings.clone().normalize().unwrap().approvers, vec!["ops@example.com"]);
+
+        let injected = ApprovalSettings { approvers: vec!["ops@example.com>\r\nRCPT TO:<x@y.z".to_string()], ..settings.clone() };
+        assert!(injected.normalize().is_err());
+        let no_relay = ApprovalSettings { smtp_relay: None, ..settings.clone() };
+        assert!(no_relay.normalize().is_err());
+        let forever = ApprovalSettings { expiry_minutes: 0, ..settings };
+        assert!(forever.normalize().is_err());
+    }
+
+    #[test]
+    fn test_link_signature() {
+        let key = [7u8; 32];
+        let id = Uuid::new_v4();
+        let signature = sign_link(&key, &id, "ops@example.com");
+        assert!(verify_link(&key, &id, "ops@example.com", &signature));
+        assert!(!verify_link(&key, &id, "intruder@example.com", &signature));
+        assert!(!verify_link(&key, &Uuid::new_v4(), "ops@example.com", &signature));
+        assert!(!verify_link(&[8u8; 32], &id, "ops@example.com", &signature));
+    }
+
+    #[test]
+    fn test_message_dot_stuffing() {
+        let message = message("a@example.com", "b@example.com", "Approval\r\nBcc: x", "one\n.two\n", Utc::now());
+        assert!(message.contains("Subject: ApprovalBcc: x\r\n"));
+        assert!(message.ends_with("one\r\n..two\r\n.\r\n"));
+    }
+
+    #[test]
+    fn test_expired_state() {
+        let now = Utc::now();
+        let approval = ActionApproval {
+            id: Uuid::new_v4(),
+            action: DestructiveAction::DeleteMachines { machine_ids: vec![] },
+            summary: "Delete machine web-1".to_string(),
+            state: ApprovalState::Pending,
+            requested_by: "admin".to_string(),
+            requested_at: now - Duration::hours(2),
+            expires_at: now - Duration::hours(1),
+            decided_by: None,
+            decided_via: None,
+            decided_at: None,
+            outcome: None,
+        };
+        assert_eq!(approval.state_at(now), ApprovalState::Expired);
+        assert_eq!(approval.state_at(now - Duration::hours(2)), ApprovalState::Pending);
+
+        // Requester and approver are compared as admin accounts
+        assert!(!approval.decidable_by("Admin"));
+        assert!(approval.decidable_by("ops"));
+    }
+
+    #[test]
+    fn test_awaits_deletion_of() {
+        let now = Utc::now();
+        let machine = Uuid::new_v4();
+        let mut approval = ActionApproval {
+            id: Uuid::new_v4(),
+            action: DestructiveAction::DeleteMachines { machine_ids: vec![machine] },
+            summary: "Delete machine web-1".to_string(),
+            state: ApprovalState::Pending,
+            requested_by: "proxmox-sync".to_string(),
+            requested_at: now,
+            expires_at: now + Duration::hours(1),
+            decided_by: None,
+            decided_via: None,
+            decided_at: None,
+            outcome: None,
+        };
+        assert!(approval.awaits_deletion_of(&machine, now));
+        assert!(!approval.awaits_deletion_of(&Uuid::new_v4(), now));
+
+        // Once decided or expired, the deletion can be filed again
+        assert!(!approval.awaits_deletion_of(&machine, now + Duration::hours(2)));
+        approval.state = ApprovalState::Rejected;
+        assert!(!approval.awaits_deletion_of(&machine, now));
+
+        approval.state = ApprovalState::Pending;
+        approval.action = DestructiveAction::ReinstallMachines { machine_ids: vec![machine] };
+        assert!(!approval.awaits_deletion_of(&machine, now));
+    }
+}
@@ -0,0 +1,274 @@
+// Adopting machines that already run an OS.
+//
+// A machine detected with an existing OS can be brought under management without being
+// reimaged: the agent is installed on that OS as a service (over SSH from the server, or by
+// running an installer script generated for the machine), the machine is marked Ready and
+// the OS it runs is recorded as authoritative. A script adoption only takes effect once the
+// agent it installs reports in. Adopted machines' agents keep reporting "existing OS" because
+// that's what they detect; those reports keep the machine Ready.
+//
+// Installers fetch the agent from this server's artifact directory (AGENT_BINARY_ARTIFACT,
+// served under /ipxe/), or from DRAGONFLY_AGENT_BINARY_URL when that's set along with
+// DRAGONFLY_AGENT_BINARY_SHA256, and refuse a binary whose SHA-256 doesn't match.
+//
+// SSH bootstrap goes through the SSH transport (see ssh.rs) with the machine's stored
+// credentials, falling back to the server's own keys or the key in DRAGONFLY_ADOPT_SSH_KEY.
+// Host keys are accepted on first use.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The static musl agent build installers fetch, relative to the artifact directory.
+pub const AGENT_BINARY_ARTIFACT: &str = "dragonfly-agent/dragonfly-agent-musl";
+// Keep this much of the SSH session's output for the response
+const MAX_OUTPUT_LEN: usize = 4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdoptionMethod {
+    /// The server ran the installer over SSH
+    Ssh,
+    /// An admin downloads the installer script and runs it on the machine
+    Script,
+}
+
+impl AdoptionMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdoptionMethod::Ssh => "ssh",
+            AdoptionMethod::Script => "script",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ssh" => Some(AdoptionMethod::Ssh),
+            "script" => Some(AdoptionMethod::Script),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Adoption {
+    pub machine_id: Uuid,
+    pub method: AdoptionMethod,
+    /// The OS the machine was adopted with, kept as its installed OS
+    pub os_installed: String,
+    pub adopted_at: DateTime<Utc>,
+    /// When the adopted machine's agent first reported in
+    pub agent_connected_at: Option<DateTime<Utc>>,
+}
+
+/// Where to reach a machine for SSH bootstrap; the host defaults to its recorded IP.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SshTarget {
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+/// Machines in these states can be adopted.
+pub fn adoptable(status: &MachineStatus) -> bool {
+    matches!(status, MachineStatus::ExistingOS)
+}
+
+/// The status to store when an adopted machine's agent reports `reported`.
+pub fn effective_status(reported: MachineStatus) -> MachineStatus {
+    match reported {
+        MachineStatus::ExistingOS => MachineStatus::Ready,
+        other => other,
+    }
+}
+
+/// Where an installer fetches the agent from, and the SHA-256 it must have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentBinary {
+    pub url: String,
+    pub sha256: String,
+}
+
+fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The agent binary for installers pointed at `base_url`.
+pub async fn agent_binary(base_url: &str) -> Result<AgentBinary> {
+    let configured = crate::config::var("DRAGONFLY_AGENT_BINARY_URL")
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty());
+    if let Some(url) = configured {
+        let sha256 = crate::config::var("DRAGONFLY_AGENT_BINARY_SHA256")
+            .map(|hash| hash.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        if !is_sha256(&sha256) {
+            bail!("DRAGONFLY_AGENT_BINARY_URL needs DRAGONFLY_AGENT_BINARY_SHA256 set to the binary's SHA-256");
+        }
+        return Ok(AgentBinary { url, sha256 });
+    }
+    let path = crate::api::artifact_base_dir().join(AGENT_BINARY_ARTIFACT);
+    if !path.exists() {
+        bail!("No agent binary at {}; put the static agent build there to adopt machines", path.display());
+    }
+    Ok(AgentBinary {
+        url: format!("{}/ipxe/{}", base_url, AGENT_BINARY_ARTIFACT),
+        sha256: crate::custom_images::hash_file(&path).await?,
+    })
+}
+
+// Single-quote a value for sh
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// A POSIX sh script that installs the agent as a systemd or OpenRC service pointed at this
+/// server, with the enrollment token when agent signing has one.
+pub fn installer_script(base_url: &str, machine_id: &Uuid, binary: &AgentBinary, enrollment_token: Option<&str>) -> String {
+    let token = match enrollment_token {
+        Some(token) => format!(
+            "umask 077\nprintf '%s' {} > /etc/dragonfly/enrollment-token\numask 022\n",
+            shell_quote(token)
+        ),
+        None => String::new(),
+    };
+    format!(
+        r#"#!/bin/sh
+# Installs the Dragonfly agent for machine {machine_id}, adopting the OS it runs.
+# Run as root on the machine itself.
+set -eu
+
+SERVER={server}
+BINARY_URL={binary_url}
+BINARY_SHA256={binary_sha256}
+
+mkdir -p /usr/local/bin /etc/dragonfly
+if command -v curl >/dev/null 2>&1; then
+    curl -fsSL "$BINARY_URL" -o /usr/local/bin/dragonfly-agent.new
+else
+    wget -qO /usr/local/bin/dragonfly-agent.new "$BINARY_URL"
+fi
+if ! echo "$BINARY_SHA256  /usr/local/bin/dragonfly-agent.new" | sha256sum -c - >/dev/null 2>&1; then
+    rm -f /usr/local/bin/dragonfly-agent.new
+    echo "The agent downloaded from $BINARY_URL doesn't have SHA-256 $BINARY_SHA256" >&2
+    exit 1
+fi
+chmod 755 /usr/local/bin/dragonfly-agent.new
+mv /usr/local/bin/dragonfly-agent.new /usr/local/bin/dragonfly-agent
+{token}
+if command -v systemctl >/dev/null 2>&1; then
+    cat > /etc/systemd/system/dragonfly-agent.service <<EOF
+[Unit]
+Description=Dragonfly agent
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+ExecStart=/usr/local/bin/dragonfly-agent --server $SERVER --daemon
+Restart=always
+RestartSec=10
+
+[Install]
+WantedBy=multi-user.target
+EOF
+    systemctl daemon-reload
+    systemctl enable dragonfly-agent
+    systemctl restart dragonfly-agent
+elif command -v rc-update >/dev/null 2>&1; then
+    cat > /etc/init.d/dragonfly-agent <<EOF
+#!/sbin/openrc-run
+description="Dragonfly agent"
+command=/usr/local/bin/dragonfly-agent
+command_args="--server $SERVER --daemon"
+command_background=true
+pidfile=/run/dragonfly-agent.pid
+depend() {{
+    need net
+}}
+EOF
+    chmod 755 /etc/init.d/dragonfly-agent
+    rc-update add dragonfly-agent default
+    rc-service dragonfly-agent restart
+else
+    echo "No systemd or OpenRC found; run '/usr/local/bin/dragonfly-agent --server $SERVER --daemon' at boot" >&2
+    exit 1
+fi
+
+echo "Dragonfly agent installed and connecting to $SERVER"
+"#,
+        machine_id = machine_id,
+        server = shell_quote(base_url),
+        binary_url = shell_quote(&binary.url),
+        binary_sha256 = shell_quote(&binary.sha256),
+        token = token,
+    )
+}
+
+/// Run the installer on a machine over SSH, returning the end of the session's output. The
+/// machine's stored SSH credentials are used unless the request names a user.
+pub async fn bootstrap_over_ssh(machine: &Machine, target: &SshTarget, script: &str) -> Result<String> {
+    let host = target.host.as_deref().unwrap_or(&machine.ip_address);
+    let stored = match target.user {
+        Some(_) => None,
+        None => crate::ssh::credentials_for(&machine.id).await?,
+    };
+    let connection = match &stored {
+        Some((credentials, _)) => crate::ssh::Connection {
+            port: target.port.unwrap_or(credentials.port),
+            ..crate::ssh::Connection::new(host, credentials)
+        },
+        None => crate::ssh::Connection {
+            host,
+            username: target.user.as_deref().unwrap_or("root"),
+            port: target.port.unwrap_or(22),
+            private_key: None,
+        },
+    };
+    let output = crate::ssh::execute(&connection, script, crate::ssh::DEFAULT_TIMEOUT).await?;
+    let skip = output.chars().count().saturating_sub(MAX_OUTPUT_LEN);
+    Ok(output.chars().skip(skip).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installer_script() {
+        let id = Uuid::new_v4();
+        let binary = AgentBinary {
+            url: format!("http://10.0.0.1:3000/ipxe/{}", AGENT_BINARY_ARTIFACT),
+            sha256: "ab".repeat(32),
+        };
+        let script = installer_script("http://10.0.0.1:3000", &id, &binary, Some("it's-secret"));
+        assert!(script.starts_with("#!/bin/sh"));
+        assert!(script.contains(&id.to_string()));
+        assert!(script.contains("SERVER='http://10.0.0.1:3000'"));
+        assert!(script.contains("BINARY_URL='http://10.0.0.1:3000/ipxe/dragonfly-agent/dragonfly-agent-musl'"));
+        assert!(script.contains(&format!("BINARY_SHA256='{}'", "ab".repeat(32))));
+        assert!(script.contains("sha256sum -c -"));
+        assert!(script.contains(r"printf '%s' 'it'\''s-secret' > /etc/dragonfly/enrollment-token"));
+        assert!(script.contains("--daemon"));
+
+        let script = installer_script("http://10.0.0.1:3000", &id, &binary, None);
+        assert!(!script.contains("enrollment-token"));
+
+        assert!(is_sha256(&"0f".repeat(32)));
+        assert!(!is_sha256("0f"));
+        assert!(!is_sha256(&"zz".repeat(32)));
+    }
+
+    #[test]
+    fn test_adopted_status() {
+        assert!(adoptable(&MachineStatus::ExistingOS));
+        assert!(!adoptable(&MachineStatus::AwaitingAssignment));
+        assert_eq!(effective_status(MachineStatus::ExistingOS), MachineStatus::Ready);
+        assert_eq!(effective_status(MachineStatus::Offline), MachineStatus::Offline);
+        assert_eq!(AdoptionMethod::parse(AdoptionMethod::Ssh.as_str()), Some(AdoptionMethod::Ssh));
+    }
+}
@@ -0,0 +1,357 @@
+// Signed agent payloads.
+//
+// An admin generates an enrollment token and hands it to agents out of band (baked into
+// an image, or via --enrollment-token / DRAGONFLY_ENROLLMENT_TOKEN). Agents then sign
+// their registration and update payloads with it (see dragonfly_common::signing), and
+// this middleware checks the signatures on every route in `is_agent_write`. With signing
+// `optional`, unsigned payloads are still accepted but a bad signature is rejected; with
+// `required`, unsigned agent payloads are rejected too, including the ones installers
+// send during a workflow. Admin sessions never need to sign.
+//
+// The token is shared by every agent, so this stops hosts that don't have it from
+// spoofing a machine's MAC; it doesn't tell one token holder from another.
+//
+// The agent channel (admin terminals, redetect, shutdown) is stricter: its upgrade
+// request must be signed whatever the mode, and without a token no agent can open one.
+
+use anyhow::Result;
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use dragonfly_common::signing::{self, SignatureError, SIGNATURE_HEADER};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthSession;
+
+const TOKEN_LENGTH: usize = 48;
+
+// Agent payloads are a few KB; anything this big isn't one
+const MAX_SIGNED_BODY: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningMode {
+    /// Signatures are ignored
+    #[default]
+    Off,
+    /// Signed payloads are verified; unsigned ones are still accepted
+    Optional,
+    /// Unsigned agent payloads are rejected
+    Required,
+}
+
+impl SigningMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SigningMode::Off => "off",
+            SigningMode::Optional => "optional",
+            SigningMode::Required => "required",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(SigningMode::Off),
+            "optional" => Some(SigningMode::Optional),
+            "required" => Some(SigningMode::Required),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SigningSettings {
+    pub mode: SigningMode,
+    pub token: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// What the admin API shows; the token itself is only returned when generated.
+#[derive(Debug, Clone, Serialize)]
+pub struct SigningStatus {
+    pub mode: SigningMode,
+    pub token_configured: bool,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<&SigningSettings> for SigningStatus {
+    fn from(settings: &SigningSettings) -> Self {
+        Self { mode: settings.mode, token_configured: settings.token.is_some(), updated_at: settings.updated_at }
+    }
+}
+
+/// Left on requests whose signature checked out, whatever the mode, for handlers that only
+/// take writes from agents holding the enrollment token (or from admins).
+#[derive(Debug, Clone, Copy)]
+pub struct SignedAgent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Accept,
+    Signed,
+    Unsigned,
+    Reject(SignatureError),
+}
+
+/// Whether a request is an agent write, by method and path under /api. Every route agents
+/// or installers post their results to belongs here.
+pub fn is_agent_write(method: &Method, path: &str) -> bool {
+    let path = crate::recorder::api_relative(path);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["machines"] => method == Method::POST,
+        ["machines", id, rest @ ..] if Uuid::parse_str(id).is_ok() => match rest {
+            [] | ["status"] | ["os-installed"] | ["fingerprint"] | ["patches"] | ["agent-environment"] | ["reports", _] => method == Method::PUT,
+            ["diagnose", "report"] => method == Method::POST,
+            _ => false,
+        },
+        ["installation", "progress"] => method == Method::PUT,
+        ["install-verification", _mac] => method == Method::POST,
+        ["install-telemetry", _mac, "disks"] => method == Method::POST,
+        ["install-telemetry", _mac] => method == Method::POST,
+        _ => false,
+    }
+}
+
+/// Report uploads are streamed to disk rather than buffered here, so agents sign them as if the
+/// body were empty: the signature covers the method and path only.
+fn is_streamed_upload(method: &Method, path: &str) -> bool {
+    let path = crate::recorder::api_relative(path);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    method == Method::PUT && matches!(segments.as_slice(), ["machines", _, "reports", _])
+}
+
+/// Whether to let an agent payload through, given its signature header (if any).
+pub fn decide(settings: &SigningSettings, header: Option<&str>, method: &str, path: &str, body: &[u8], now: i64) -> Decision {
+    let Some(token) = &settings.token else {
+        return Decision::Accept;
+    };
+    match header {
+        Some(header) => match signing::verify(token, header, method, path, body, now) {
+            Ok(()) => Decision::Signed,
+            // With signing off a bad signature is ignored, it just doesn't count as signed
+            Err(_) if settings.mode == SigningMode::Off => Decision::Accept,
+            Err(e) => Decision::Reject(e),
+        },
+        None if settings.mode == SigningMode::Required => Decision::Unsigned,
+        None => Decision::Accept,
+    }
+}
+
+/// Whether an agent may open its channel. Unlike payloads this ignores the mode: an unsigned
+/// upgrade is never accepted, and with no token configured nothing is.
+pub fn decide_channel(settings: &SigningSettings, header: Option<&str>, path: &str, now: i64) -> Decision {
+    match (&settings.token, header) {
+        (Some(token), Some(header)) => match signing::verify(token, header, "GET", path, b"", now) {
+            Ok(()) => Decision::Signed,
+            Err(e) => Decision::Reject(e),
+        },
+        _ => Decision::Unsigned,
+    }
+}
+
+pub async fn settings() -> Result<SigningSettings> {
+    crate::db::get_agent_signing().await
+}
+
+pub async fn set_mode(mode: SigningMode) -> Result<SigningSettings> {
+    let mut settings = settings().await?;
+    if mode != SigningMode::Off && settings.token.is_none() {
+        anyhow::bail!("Generate an enrollment token before turning signing on");
+    }
+    settings.mode = mode;
+    crate::db::save_agent_signing(settings.mode, settings.token.as_deref()).await?;
+    settings.updated_at = Some(Utc::now());
+    Ok(settings)
+}
+
+/// Replace the enrollment token; agents holding the old one must be given the new one.
+pub async fn rotate_token() -> Result<String> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+    let mode = settings().await?.mode;
+    crate::db::save_agent_signing(mode, Some(&token)).await?;
+    Ok(token)
+}
+
+fn rejection(error: &str, message: String) -> Response {
+    (StatusCode::UNAUTHORIZED, Json(json!({ "error": error, "message": message }))).into_response()
+}
+
+/// Check the signature on a machine's agent channel upgrade, returning the response to refuse it with.
+pub async fn authorize_channel(headers: &HeaderMap, machine_id: &Uuid) -> Result<(), Response> {
+    let settings = match settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load agent signing settings: {}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to load agent signing settings").into_response());
+        }
+    };
+    if settings.token.is_none() {
+        warn!("Refused agent channel for machine {}: no enrollment token configured", machine_id);
+        return Err((StatusCode::FORBIDDEN, Json(json!({
+            "error": "Forbidden",
+            "message": "Generate an enrollment token before agents can open a channel",
+        }))).into_response());
+    }
+
+    let header = headers.get(SIGNATURE_HEADER).and_then(|v| v.to_str().ok());
+    let path = format!("/agent/channel/{}", machine_id);
+    match decide_channel(&settings, header, &path, Utc::now().timestamp()) {
+        Decision::Accept | Decision::Signed => Ok(()),
+        Decision::Unsigned => {
+            warn!("Rejected unsigned agent channel for machine {}", machine_id);
+            Err(rejection("Signature Required", "Agent channels must be signed with the enrollment token".to_string()))
+        }
+        Decision::Reject(e) => {
+            warn!("Rejected agent channel for machine {}: {}", machine_id, e);
+            Err(rejection("Invalid Signature", format!("Agent channel signature rejected: {}", e)))
+        }
+    }
+}
+
+/// Middleware that checks signatures on agent registration and update payloads.
+pub async fn verify_agent_signatures(auth_session: AuthSession, request: Request, next: Next) -> Response {
+    // Routes are also served under the base path, which mustn't sidestep the check
+    let path = request.extensions()
+        .get::<axum::extract::OriginalUri>()
+        .map(|uri| crate::base_path::strip(uri.path()).to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    if auth_session.user.is_some() || !is_agent_write(request.method(), &path) {
+        return next.run(request).await;
+    }
+    let settings = match settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load agent signing settings: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load agent signing settings").into_response();
+        }
+    };
+
+    let method = request.method().to_string();
+    let header = request.headers().get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    // Nothing to check, and nothing to mark as signed
+    if settings.token.is_none() || (header.is_none() && settings.mode != SigningMode::Required) {
+        return next.run(request).await;
+    }
+    let relative = crate::recorder::api_relative(&path);
+    let now = Utc::now().timestamp();
+    let (mut parts, body) = request.into_parts();
+    let (decision, body) = if is_streamed_upload(&parts.method, &path) {
+        (decide(&settings, header.as_deref(), &method, relative, b"", now), body)
+    } else {
+        let bytes = match to_bytes(body, MAX_SIGNED_BODY).await {
+            Ok(bytes) => bytes,
+            Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response(),
+        };
+        (decide(&settings, header.as_deref(), &method, relative, &bytes, now), Body::from(bytes))
+    };
+
+    match decision {
+        Decision::Accept => next.run(Request::from_parts(parts, body)).await,
+        Decision::Signed => {
+            debug!("Accepted signed agent payload for {} {}", method, relative);
+            parts.extensions.insert(SignedAgent);
+            next.run(Request::from_parts(parts, body)).await
+        }
+        Decision::Unsigned => {
+            warn!("Rejected unsigned agent payload for {} {}", method, relative);
+            rejection("Signature Required", "Agent payloads must be signed with the enrollment token".to_string())
+        }
+        Decision::Reject(e) => {
+            warn!("Rejected agent payload for {} {}: {}", method, relative, e);
+            rejection("Invalid Signature", format!("Agent payload signature rejected: {}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decide() {
+        let body = br#"{"status":"Ready"}"#;
+        let path = "/machines/6b1d0c1e-0000-4000-8000-000000000000/status";
+        let signed = signing::sign("secret", 1000, "PUT", path, body);
+
+        let mut settings = SigningSettings { mode: SigningMode::Optional, token: Some("secret".to_string()), updated_at: None };
+        assert_eq!(decide(&settings, Some(&signed), "PUT", path, body, 1000), Decision::Signed);
+        assert_eq!(decide(&settings, None, "PUT", path, body, 1000), Decision::Accept);
+        assert_eq!(decide(&settings, Some(&signed), "PUT", "/machines", body, 1000), Decision::Reject(SignatureError::Mismatch));
+
+        settings.mode = SigningMode::Required;
+        assert_eq!(decide(&settings, None, "PUT", path, body, 1000), Decision::Unsigned);
+        assert_eq!(decide(&settings, Some(&signed), "PUT", path, body, 5000), Decision::Reject(SignatureError::Expired));
+
+        // Off, or no token yet, lets everything through; a good signature still counts
+        settings.mode = SigningMode::Off;
+        assert_eq!(decide(&settings, Some("garbage"), "PUT", path, body, 1000), Decision::Accept);
+        assert_eq!(decide(&settings, Some(&signed), "PUT", path, body, 1000), Decision::Signed);
+        let tokenless = SigningSettings { mode: SigningMode::Required, token: None, updated_at: None };
+        assert_eq!(decide(&tokenless, None, "PUT", path, body, 1000), Decision::Accept);
+    }
+
+    #[test]
+    fn test_agent_writes_require_signatures() {
+        let id = "6b1d0c1e-0000-4000-8000-000000000000";
+        let settings = SigningSettings { mode: SigningMode::Required, token: Some("secret".to_string()), updated_at: None };
+        for (method, path) in [
+            (Method::POST, "/api/machines".to_string()),
+            (Method::PUT, format!("/api/v1/machines/{}", id)),
+            (Method::PUT, format!("/api/machines/{}/status", id)),
+            (Method::PUT, format!("/api/machines/{}/os-installed", id)),
+            (Method::PUT, format!("/api/machines/{}/fingerprint", id)),
+            (Method::POST, format!("/api/machines/{}/diagnose/report", id)),
+            (Method::PUT, "/api/installation/progress".to_string()),
+            (Method::PUT, format!("/api/machines/{}/reports/dmesg.log", id)),
+            (Method::PUT, format!("/api/machines/{}/agent-environment", id)),
+            (Method::PUT, format!("/api/machines/{}/patches", id)),
+            (Method::POST, "/api/install-verification/52:54:00:12:34:56".to_string()),
+            (Method::POST, "/api/install-telemetry/52:54:00:12:34:56/disks".to_string()),
+            (Method::POST, "/api/install-telemetry/52:54:00:12:34:56".to_string()),
+        ] {
+            assert!(is_agent_write(&method, &path), "{} {} should be signed", method, path);
+            let relative = crate::recorder::api_relative(&path);
+            assert_eq!(decide(&settings, None, method.as_str(), relative, b"{}", 1000), Decision::Unsigned);
+        }
+        // Reads, admin actions and anything that isn't a machine ID
+        assert!(!is_agent_write(&Method::GET, "/api/machines"));
+        assert!(!is_agent_write(&Method::DELETE, &format!("/api/machines/{}", id)));
+        assert!(!is_agent_write(&Method::POST, &format!("/api/machines/{}/diagnose", id)));
+        assert!(!is_agent_write(&Method::PUT, "/api/machines/not-an-id/status"));
+
+        // Only report uploads skip the body
+        assert!(is_streamed_upload(&Method::PUT, &format!("/api/machines/{}/reports/dmesg.log", id)));
+        assert!(!is_streamed_upload(&Method::PUT, &format!("/api/machines/{}/status", id)));
+    }
+
+    #[test]
+    fn test_decide_channel() {
+        let path = "/agent/channel/6b1d0c1e-0000-4000-8000-000000000000";
+        let signed = signing::sign("secret", 1000, "GET", path, b"");
+
+        // The mode doesn't matter: channels are always signed
+        let settings = SigningSettings { mode: SigningMode::Off, token: Some("secret".to_string()), updated_at: None };
+        assert_eq!(decide_channel(&settings, Some(&signed), path, 1000), Decision::Signed);
+        assert_eq!(decide_channel(&settings, None, path, 1000), Decision::Unsigned);
+        let other = "/agent/channel/6b1d0c1e-0000-4000-8000-000000000001";
+        assert_eq!(decide_channel(&settings, Some(&signed), other, 1000), Decision::Reject(SignatureError::Mismatch));
+
+        let tokenless = SigningSettings { mode: SigningMode::Off, token: None, updated_at: None };
+        assert_eq!(decide_channel(&tokenless, Some(&signed), path, 1000), Decision::Unsigned);
+    }
+}
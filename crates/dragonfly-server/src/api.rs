@@ -3,7 +3,7 @@ use axum::{
     Router,
     extract::{
         State, Path, Json, Form, FromRequest,
-        ConnectInfo,
+        ConnectInfo, ws::WebSocketUpgrade,
     },
     http::{StatusCode, header::HeaderValue, HeaderMap},
     response::{IntoResponse, Html, Response, sse::{Event, Sse, KeepAlive}},
@@ -11,7 +11,7 @@ use axum::{
 use std::convert::Infallible;
 use serde_json::json;
 use uuid::Uuid;
-use dragonfly_common::models::{MachineStatus, HostnameUpdateRequest, HostnameUpdateResponse, OsInstalledUpdateRequest, OsInstalledUpdateResponse, BmcType, BmcCredentials, StatusUpdateRequest, BmcCredentialsUpdateRequest, InstallationProgressUpdateRequest, RegisterRequest, Machine};
+use dragonfly_common::models::{MachineStatus, HostnameUpdateRequest, HostnameUpdateResponse, OsInstalledUpdateRequest, OsInstalledUpdateResponse, BmcType, BmcCredentials, StatusUpdateRequest, BmcCredentialsUpdateRequest, InstallationProgressUpdateRequest, RegisterRequest, Machine, HardwareFingerprint, MachineResolveResponse};
 use crate::db::{self, RegisterResponse, ErrorResponse, OsAssignmentRequest, get_machine_tags, update_machine_tags as db_update_machine_tags};
 use crate::AppState;
 use crate::auth::AuthSession;
@@ -19,7 +19,6 @@ use std::collections::HashMap;
 use tracing::{info, error, warn, debug};
 use std::env;
 use std::time::Duration;
-use tokio_stream::Stream;
 use futures::stream;
 use crate::{
     INSTALL_STATE_REF, 
@@ -43,36 +42,173 @@ use axum::body::{Body, Bytes};
 use http_body::Frame;
 use http_body_util::{StreamBody, Empty};
 use dragonfly_common::Error;
-use tokio::io::{AsyncSeekExt, AsyncReadExt, AsyncWriteExt};
+use dragonfly_common::OsIdentifier;
+use dragonfly_common::validation::{Validate, ValidationErrors};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use futures::StreamExt; // For .next() on stream
 use crate::ui; // Import the ui module
 use std::net::SocketAddr;
 use axum::middleware::Next; // Add this import back
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use axum::extract::DefaultBodyLimit;
 use serde::Deserialize;
+use crate::range_reader::{self, RangeLayout};
 
 pub fn api_router() -> Router<crate::AppState> {
     // Core API routes
     Router::new()
         .route("/machines", get(get_all_machines).post(register_machine))
         .route("/machines/install-status", get(get_install_status))
+        .route("/install/status", get(get_install_progress))
+        .route("/cluster/nodes", get(get_cluster_nodes))
+        .route("/admin/flight/external-cluster", post(api_configure_external_cluster))
+        .route("/admin/components", get(api_get_component_values))
+        .route("/admin/components/{component}", put(api_update_component_values))
+        .route("/admin/redeploy", post(api_redeploy_components))
+        .route("/admin/stack", get(api_get_stack_conformance))
+        .route("/admin/stack/upgrade", post(api_upgrade_stack))
+        .route("/admin/leader", get(api_get_leader))
+        .route("/templates/validate", post(api_validate_template))
+        .route("/admin/os-policies", get(api_list_os_policies).post(api_create_os_policy))
+        .route("/admin/os-policies/evaluate", post(api_evaluate_os_policies))
+        .route("/admin/os-policies/{id}", put(api_update_os_policy).delete(api_delete_os_policy))
+        .route("/fleet/plan", post(api_fleet_plan))
+        .route("/fleet/apply", post(api_fleet_apply))
+        .route("/fleet/power/hibernate", post(api_fleet_hibernate))
+        .route("/fleet/power/wake", post(api_fleet_wake))
+        .route("/fleet/power/managed", get(api_list_managed_power_offs))
+        .route("/admin/boot-menu/selections", get(api_list_boot_selections))
+        .route("/admin/images", get(api_list_custom_images).post(api_register_custom_image))
+        .route("/admin/images/webhook-token", post(api_create_image_webhook_token))
+        .route("/admin/images/{name}", delete(api_delete_custom_image))
+        .route("/admin/images/{name}/prefetch", post(api_prefetch_custom_image))
+        .route("/images/webhook", post(api_custom_image_webhook))
+        .route("/images/usage", get(api_image_usage))
+        .route("/reports/usage", get(api_usage_report))
+        .route("/reports/sla", get(api_sla_report))
+        .route("/reports/warranty", get(api_warranty_report))
+        .route("/os-catalog", get(api_get_os_catalog))
+        .route("/peers/config", get(api_peer_config))
+        .route("/peers/announce", post(api_peer_announce))
+        .route("/peers/chunks/{hash}", get(api_peer_lookup))
+        .route("/admin/peers", get(api_list_peers))
+        .route("/admin/bandwidth", get(api_get_bandwidth))
+        .route("/admin/agent-signing", get(api_get_agent_signing).put(api_put_agent_signing))
+        .route("/admin/agent-signing/token", post(api_rotate_enrollment_token))
+        .route("/admin/db/stats", get(api_get_db_stats))
+        .route("/admin/diagnostics/artifacts/{name}", put(api_upload_diagnostic_artifact).layer(DefaultBodyLimit::disable()))
+        .route("/admin/artifacts/upload-token", post(api_create_artifact_upload_token))
+        .route("/artifacts/{*path}", get(api_get_artifact).put(api_upload_artifact).delete(api_abort_artifact_upload).layer(DefaultBodyLimit::disable()))
+        .route("/replication/manifest", get(api_get_replication_manifest))
+        .route("/replication/sites/{site}/status", post(api_post_replication_status))
+        .route("/admin/replication", get(api_get_replication))
+        .route("/admin/replication/settings", put(api_put_replication_settings))
+        .route("/admin/replication/key", post(api_rotate_replication_key))
+        .route("/admin/replication/sync", post(api_run_replication))
+        .route("/machines/resolve", get(api_resolve_machine))
         .route("/machines/{id}/os", get(get_machine_os).post(assign_os))
         .route("/machines/{id}/reimage", post(reimage_machine)) // Add new reimage endpoint
         .route("/machines/{id}/hostname", get(get_hostname_form).put(update_hostname))
         .route("/machines/{id}/status", put(update_status))
         .route("/machines/{id}/status-and-progress", get(get_machine_status_and_progress_partial))
         .route("/machines/{id}/os-installed", put(update_os_installed))
+        .route("/machines/{id}/fingerprint", put(update_hardware_fingerprint))
+        .route("/machines/{id}/patches", get(api_get_machine_patches).put(update_machine_patches))
+        .route("/machines/{id}/agent-environment", put(update_agent_environment))
+        .route("/machines/{id}/adopt", post(api_adopt_machine))
+        .route("/machines/{id}/adopt/install.sh", get(api_adoption_script))
+        .route("/machines/{id}/adoption", get(api_get_adoption))
+        .route("/machines/{id}/ssh-credentials", get(api_get_machine_ssh_credentials).put(api_put_machine_ssh_credentials).delete(api_delete_machine_ssh_credentials))
+        .route("/machines/{id}/ssh", post(api_machine_ssh_action))
+        .route("/machines/{id}/boot-environment", get(api_get_boot_environment))
+        .route("/machines/boot-environments", get(api_list_boot_environments))
+        .route("/machines/{id}/redetect", get(api_get_redetect).post(api_request_redetect))
+        .route("/machines/{id}/warranty", get(api_get_machine_warranty))
+        .route("/machines/{id}/sensors", get(api_get_machine_sensors))
+        .route("/machines/{id}/status-link", post(api_create_status_link))
+        .route("/machines/{id}/share-links", post(api_create_share_link).delete(api_revoke_share_links))
+        .route("/machines/{id}/terminal", get(machine_terminal_ws))
+        .route("/machines/{id}/terminal-sessions", get(api_list_terminal_sessions))
         .route("/machines/{id}/bmc", post(update_bmc))
         // Add route for BMC power actions
         .route("/machines/{id}/bmc/power-action", post(crate::handlers::machines::bmc_power_action_handler))
         .route("/machines/{id}/workflow-progress", get(get_workflow_progress))
+        .route("/machines/{id}/diagnostics", get(api_list_diagnostics))
+        .route("/machines/{id}/diagnostics/{bundle_id}", get(api_get_diagnostics_bundle))
+        .route("/machines/{id}/reports", get(api_list_machine_reports))
+        .route("/machines/{id}/reports/{report}", get(api_get_machine_report).put(api_upload_machine_report).delete(api_delete_machine_report).layer(DefaultBodyLimit::disable()))
+        .route("/machines/{id}/diagnose", get(api_get_diagnose).post(api_request_diagnose).delete(api_cancel_diagnose))
+        .route("/machines/{id}/diagnose/report", post(api_diagnose_report))
+        .route("/machines/{id}/install-telemetry", get(api_get_install_telemetry))
+        .route("/machines/{id}/history", get(api_get_machine_history))
+        .route("/machines/{id}/boot-loop", get(api_get_boot_loop).delete(api_clear_boot_loop))
+        .route("/install-telemetry/{mac}", post(api_install_telemetry))
+        .route("/install-telemetry/{mac}/disks", post(api_disk_imaging_progress))
+        .route("/install/multi-disk.sh", get(api_multi_disk_script))
+        .route("/install/verify.sh", get(api_verify_script))
+        .route("/install-verification/{mac}", post(api_install_verification))
+        .route("/machines/{id}/install-verification", get(api_get_install_verification))
+        .route("/machines/{id}/partitioning", get(api_get_partitioning).put(api_set_partitioning))
+        .route("/machines/{id}/disk-progress", get(api_get_disk_progress))
+        .route("/machines/{id}/desired-state", get(api_get_desired_state).put(api_put_desired_state).delete(api_delete_desired_state))
+        .route("/machines/{id}/drift", get(api_get_drift))
+        .route("/machines/{id}/definition", get(api_export_definition).put(api_import_definition))
+        .route("/machines/{id}/clone-profile", post(api_clone_profile))
+        .route("/profiles", get(api_list_profiles))
+        .route("/profiles/{id}", get(api_get_profile).delete(api_delete_profile))
+        .route("/profiles/{id}/apply", post(api_apply_profile))
+        .route("/machines/{id}/vlan", get(api_get_machine_vlan).put(api_put_machine_vlan))
+        .route("/machines/{id}/boot-interfaces", get(api_get_boot_interfaces).put(api_put_boot_interfaces).delete(api_delete_boot_interfaces))
+        .route("/machines/{id}/system-config", get(api_get_machine_system_config))
+        .route("/machines/{id}/maintenance", get(api_get_machine_maintenance).put(api_put_machine_maintenance))
+        .route("/machines/{id}/ephemeral", get(api_get_ephemeral).put(api_put_ephemeral))
+        .route("/machines/{id}/secure-boot", get(api_get_secure_boot).put(api_put_secure_boot))
         .route("/machines/{id}/tags", get(api_get_machine_tags).put(api_update_machine_tags))
+        .route("/machines/{id}/enrichment", get(api_get_machine_enrichment))
+        .route("/machines/bulk/tags", post(api_bulk_tags))
+        .route("/machines/bulk/status", post(api_bulk_status))
+        .route("/machines/bulk/reinstall", post(api_bulk_reinstall))
+        .route("/machines/bulk/delete", post(api_bulk_delete))
+        .route("/action-approvals", get(api_list_action_approvals))
+        .route("/action-approvals/{id}", get(api_get_action_approval))
+        .route("/action-approvals/{id}/approve", post(api_approve_action))
+        .route("/action-approvals/{id}/reject", post(api_reject_action))
+        .route("/campaigns", get(api_list_campaigns).post(api_create_campaign))
+        .route("/campaigns/{id}", get(api_get_campaign))
+        .route("/campaigns/{id}/machines", post(api_add_campaign_machines))
+        .route("/campaigns/{id}/finish", post(api_finish_campaign))
+        .route("/campaigns/{id}/rollout", post(api_start_rollout))
         .route("/machines/{id}/tags/{tag}", delete(api_delete_machine_tag))
         .route("/machines/{id}", get(get_machine).put(update_machine).delete(delete_machine))
         .route("/installation/progress", put(update_installation_progress))
         .route("/events", get(machine_events))
         .route("/heartbeat", get(heartbeat))
+        .route("/version", get(api_version))
+        .route("/status", get(api_status))
+        .route("/capabilities", get(api_capabilities))
+        .route("/agent/channel/{id}", get(agent_channel_ws))
+        .route("/http-boot", get(api_http_boot_guidance))
+        .route("/settings", get(api_get_settings).put(api_update_settings))
+        .route("/setup", get(api_get_setup).post(api_complete_setup))
+        .route("/settings/retention/run", post(api_run_retention))
+        .route("/settings/notifications", get(api_get_notification_settings).put(api_put_notification_settings))
+        .route("/settings/power", get(api_get_power_settings).put(api_put_power_settings))
+        .route("/settings/action-approvals", get(api_get_action_approval_settings).put(api_put_action_approval_settings))
+        .route("/settings/public-api", get(api_get_public_api_settings).put(api_put_public_api_settings))
+        .route("/settings/onboarding", get(api_get_onboarding_settings).put(api_put_onboarding_settings))
+        .route("/wallboard-tokens", post(api_create_wallboard_token))
+        .route("/admin/logs", get(api_get_logs))
+        .route("/admin/logs/stream", get(api_stream_logs))
+        .route("/admin/tinkerbell/outbox", get(api_get_tinkerbell_outbox))
+        .route("/admin/tinkerbell/outbox/{id}", delete(api_delete_tinkerbell_op))
+        .route("/admin/templates/render", get(api_render_template))
+        .route("/merges", get(api_list_pending_merges))
+        .route("/merges/{id}/approve", post(api_approve_merge))
+        .route("/merges/{id}/reject", post(api_reject_merge))
+        .route("/approvals", get(api_list_approvals))
+        .route("/approvals/{id}", delete(api_delete_approval))
+        .route("/approvals/{id}/approve", post(api_approve_registration))
+        .route("/approvals/{id}/reject", post(api_reject_registration))
         // --- Proxmox Routes ---
         .route("/proxmox/connect", post(crate::handlers::proxmox::connect_proxmox_handler))
         .route("/proxmox/discover", get(crate::handlers::proxmox::discover_proxmox_handler))
@@ -82,6 +218,17 @@ pub fn api_router() -> Router<crate::AppState> {
         .route("/tags", get(api_get_tags).post(api_create_tag))
         .route("/tags/{tag_name}", delete(api_delete_tag))
         .route("/tags/{tag_name}/machines", get(api_get_machines_by_tag))
+        .route("/tags/{tag_name}/maintenance", get(api_get_tag_maintenance).put(api_put_tag_maintenance))
+        .route("/tags/{tag_name}/vlan", get(api_get_tag_vlan).put(api_put_tag_vlan))
+        .route("/tags/{tag_name}/system-config", get(api_get_tag_system_config).put(api_put_tag_system_config))
+        .route("/tags/{tag_name}/profile", get(api_get_tag_profile).put(api_put_tag_profile).delete(api_delete_tag_profile))
+        .route("/tags/{tag_name}/ssh-credentials", get(api_get_tag_ssh_credentials).put(api_put_tag_ssh_credentials).delete(api_delete_tag_ssh_credentials))
+        // Reject agent payloads without a valid enrollment-token signature (when enabled)
+        .layer(axum::middleware::from_fn(crate::agent_signing::verify_agent_signatures))
+        // Opt-in capture of agent payloads for `dragonfly replay`
+        .layer(axum::middleware::from_fn(crate::recorder::record_agent_payloads))
+        // Every JSON error leaves as application/problem+json with a correlation ID
+        .layer(axum::middleware::from_fn(crate::problem::problem_responses))
         .layer(DefaultBodyLimit::max(1024 * 1024 * 50)) // 50 MB
 }
 
@@ -122,41 +269,41 @@ pub async fn generate_agent_apkovl(
     
     // 1. Create a temporary directory
     let temp_dir = tempdir()
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to create temp directory for apkovl: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to create temp directory for apkovl", e))?;
     let temp_path = temp_dir.path();
     info!("Building apkovl structure in: {:?}", temp_path);
     
     // 2. Create directory structure
     fs::create_dir_all(temp_path.join("etc/local.d")).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to create dir etc/local.d: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to create dir etc/local.d", e))?;
     fs::create_dir_all(temp_path.join("etc/apk/protected_paths.d")).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to create dir etc/apk/protected_paths.d: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to create dir etc/apk/protected_paths.d", e))?;
     fs::create_dir_all(temp_path.join("etc/runlevels/default")).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to create dir etc/runlevels/default: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to create dir etc/runlevels/default", e))?;
     fs::create_dir_all(temp_path.join("usr/local/bin")).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to create dir usr/local/bin: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to create dir usr/local/bin", e))?;
     
     // 3. Write static files
     fs::write(temp_path.join("etc/hosts"), HOSTS_CONTENT).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write etc/hosts: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write etc/hosts", e))?;
     fs::write(temp_path.join("etc/hostname"), HOSTNAME_CONTENT).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write etc/hostname: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write etc/hostname", e))?;
     fs::write(temp_path.join("etc/apk/arch"), APK_ARCH_CONTENT).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write etc/apk/arch: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write etc/apk/arch", e))?;
     fs::write(temp_path.join("etc/apk/protected_paths.d/lbu.list"), LBU_LIST_CONTENT).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write lbu.list: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write lbu.list", e))?;
     fs::write(temp_path.join("etc/apk/repositories"), REPOSITORIES_CONTENT).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write repositories: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write repositories", e))?;
     fs::write(temp_path.join("etc/apk/world"), WORLD_CONTENT).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write world: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write world", e))?;
     
     // Create empty mtab needed by Alpine init
     fs::write(temp_path.join("etc/mtab"), "").await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write etc/mtab: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write etc/mtab", e))?;
     
     // Create empty .default_boot_services
     fs::write(temp_path.join("etc/.default_boot_services"), "").await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write .default_boot_services: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write .default_boot_services", e))?;
     
     // 4. Write dynamic dragonfly-agent.start script
     let start_script_path = temp_path.join("etc/local.d/dragonfly-agent.start");
@@ -172,7 +319,7 @@ pub async fn generate_agent_apkovl(
     
     // Write the file
     fs::write(&start_script_path, script_content).await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to write start script: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to write start script", e))?;
     
     // Make it executable
     set_executable_permission(&start_script_path).await?;
@@ -181,9 +328,7 @@ pub async fn generate_agent_apkovl(
     let link_target = "/etc/init.d/local";
     let link_path = temp_path.join("etc/runlevels/default/local");
     unix_symlink(link_target, &link_path)
-        .map_err(|e| dragonfly_common::Error::Internal(
-            format!("Failed to create symlink {:?} -> {}: {}", link_path, link_target, e)
-        ))?;
+        .map_err(|e| dragonfly_common::Error::io(format!("Failed to create symlink {:?} -> {}", link_path, link_target), e))?;
     
     // 6. Download the agent binary
     let agent_binary_path = temp_path.join("usr/local/bin/dragonfly-agent");
@@ -192,23 +337,34 @@ pub async fn generate_agent_apkovl(
     // Make it executable
     set_executable_permission(&agent_binary_path).await?;
     
-    // 7. Create the tar.gz archive
-    info!("Creating tarball: {:?}", target_apkovl_path);
+    // 7. Create the tar.gz archive next to the target, then rename it into place
+    if let Some(parent) = target_apkovl_path.parent() {
+        fs::create_dir_all(parent).await
+            .map_err(|e| dragonfly_common::Error::io(format!("Failed to create dir {:?}", parent), e))?;
+    }
+    let temp_tarball = temp_artifact_path(target_apkovl_path);
+    info!("Creating tarball: {:?}", temp_tarball);
     let output = Command::new("tar")
         .arg("-czf")
-        .arg(target_apkovl_path)
+        .arg(&temp_tarball)
         .arg("-C")
         .arg(temp_path)
         .arg(".")
         .output()
         .await
-        .map_err(|e| dragonfly_common::Error::Internal(format!("Failed to execute tar command: {}", e)))?;
+        .map_err(|e| dragonfly_common::Error::io("Failed to execute tar command", e))?;
     
     if !output.status.success() {
+        let _ = fs::remove_file(&temp_tarball).await;
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(dragonfly_common::Error::Internal(format!("Tar command failed: {}", stderr)));
     }
     
+    if let Err(e) = fs::rename(&temp_tarball, target_apkovl_path).await {
+        let _ = fs::remove_file(&temp_tarball).await;
+        return Err(dragonfly_common::Error::io("Failed to move apkovl into place", e));
+    }
+    
     info!("Successfully generated apkovl: {:?}", target_apkovl_path);
     Ok(())
 }
@@ -218,17 +374,13 @@ async fn set_executable_permission(path: &StdPath) -> Result<(), dragonfly_commo
     use std::os::unix::fs::PermissionsExt;
     
     let metadata = fs::metadata(path).await
-        .map_err(|e| dragonfly_common::Error::Internal(
-            format!("Failed to get metadata for {:?}: {}", path, e)
-        ))?;
+        .map_err(|e| dragonfly_common::Error::io(format!("Failed to get metadata for {:?}", path), e))?;
     
     let mut perms = metadata.permissions();
     perms.set_mode(0o755); // rwxr-xr-x
     
     fs::set_permissions(path, perms).await
-        .map_err(|e| dragonfly_common::Error::Internal(
-            format!("Failed to set executable permission on {:?}: {}", path, e)
-        ))
+        .map_err(|e| dragonfly_common::Error::io(format!("Failed to set executable permission on {:?}", path), e))
 }
 
 // Helper function to download a file from a URL
@@ -242,53 +394,201 @@ async fn download_file(url: &str, target_path: &StdPath) -> Result<(), dragonfly
     let response = client.get(url)
         .send()
         .await
-        .map_err(|e| dragonfly_common::Error::Internal(
-            format!("Failed to download file from {}: {}", url, e)
-        ))?;
+        .map_err(|e| dragonfly_common::Error::upstream_with(url, "Download failed", e))?;
     
     // Check if the request was successful
     if !response.status().is_success() {
-        return Err(dragonfly_common::Error::Internal(
-            format!("Failed to download file from {}: HTTP status {}", url, response.status())
-        ));
+        return Err(dragonfly_common::Error::upstream(url, format!("Download failed with HTTP status {}", response.status())));
     }
     
     // Get the file content as bytes
     let bytes = response.bytes().await
-        .map_err(|e| dragonfly_common::Error::Internal(
-            format!("Failed to read response body from {}: {}", url, e)
-        ))?;
+        .map_err(|e| dragonfly_common::Error::upstream_with(url, "Failed to read response body", e))?;
     
     // Create the file and write the content
     fs::write(target_path, bytes).await
-        .map_err(|e| dragonfly_common::Error::Internal(
-            format!("Failed to write downloaded file to {:?}: {}", target_path, e)
-        ))?;
+        .map_err(|e| dragonfly_common::Error::io(format!("Failed to write downloaded file to {:?}", target_path), e))?;
     
     info!("Successfully downloaded {} to {:?}", url, target_path);
     Ok(())
 }
 
+// Per-path locks so only one request generates or downloads a given artifact at a time
+static ARTIFACT_LOCKS: once_cell::sync::Lazy<std::sync::Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>> =
+    once_cell::sync::Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+// Get (or create) the generation lock for an artifact path
+fn artifact_lock(path: &StdPath) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = ARTIFACT_LOCKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    locks.entry(path.to_path_buf())
+        .or_insert_with(|| Arc::new(tokio::sync::Mutex::new(())))
+        .clone()
+}
+
+// Temporary sibling path an artifact is written to before being renamed into place.
+// Same directory as the target so the final rename is atomic.
+fn temp_artifact_path(path: &StdPath) -> PathBuf {
+    let file_name = path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "artifact".to_string());
+    path.with_file_name(format!(".{}.tmp-{}", file_name, Uuid::new_v4()))
+}
+
+// Write an artifact via temp file + fsync + rename so readers never see a partial file
+async fn write_artifact_atomic(path: &StdPath, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let temp_path = temp_artifact_path(path);
+    let result = async {
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&temp_path, path).await
+    }.await;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+// 422 response listing every invalid field in the payload
+fn validation_error_response(errors: ValidationErrors) -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({
+        "error": "Validation Failed",
+        "message": errors.to_string(),
+        "fields": errors.errors,
+    }))).into_response()
+}
+
 #[axum::debug_handler]
 async fn register_machine(
     State(state): State<AppState>,
     // Ensure the payload type is correct, matching the updated common struct
     Json(payload): Json<RegisterRequest>,
 ) -> Response {
+    if let Err(errors) = payload.validate() {
+        warn!("Rejecting registration for MAC {}: {}", payload.mac_address, errors);
+        return validation_error_response(errors);
+    }
+
+    // In allowlist mode, MACs nobody pre-registered wait for an admin
+    match crate::onboarding::check_registration(&payload).await {
+        Ok(crate::onboarding::Admission::Allowed) => {},
+        Ok(crate::onboarding::Admission::Pending(approval)) => {
+            if approval.attempts == 1 {
+                let _ = state.event_manager.send(format!("approval_pending:{}", approval.mac_address));
+            }
+            return (StatusCode::CONFLICT, Json(json!({
+                "error": "Pending Approval",
+                "message": format!(
+                    "MAC {} is not on the onboarding allowlist; registration is waiting for an admin to approve or reject {}",
+                    approval.mac_address, approval.id
+                ),
+                "approval_id": approval.id,
+            }))).into_response();
+        },
+        Ok(crate::onboarding::Admission::Rejected(approval)) => {
+            warn!("Turning away registration from rejected MAC {}", approval.mac_address);
+            return (StatusCode::FORBIDDEN, Json(ErrorResponse {
+                error: "Registration Rejected".to_string(),
+                message: format!("An admin rejected MAC {} at onboarding", approval.mac_address),
+            })).into_response();
+        },
+        Err(e) => {
+            // Failing open would let exactly the machines the allowlist is for through
+            error!("Onboarding check failed for MAC {}: {}", payload.mac_address, e);
+            return (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorResponse {
+                error: "Registration Failed".to_string(),
+                message: format!("Could not check the onboarding allowlist: {}", e),
+            })).into_response();
+        }
+    }
+
+    // A new MAC with known hardware is most likely a replaced NIC; hold it for an admin
+    match crate::dedupe::check_registration(&payload).await {
+        Ok(Some(merge)) => {
+            let _ = state.event_manager.send(format!("merge_pending:{}:{}", merge.existing_machine_id, merge.id));
+            return (StatusCode::CONFLICT, Json(json!({
+                "error": "Possible Duplicate",
+                "message": format!(
+                    "Hardware matches existing machine {}; registration is waiting for an admin to approve or reject merge {}",
+                    merge.existing_machine_id, merge.id
+                ),
+                "merge_id": merge.id,
+                "existing_machine_id": merge.existing_machine_id,
+            }))).into_response();
+        },
+        Ok(None) => {},
+        Err(e) => {
+            // Don't block registration because the duplicate check failed
+            warn!("Hardware fingerprint check failed for MAC {} (continuing anyway): {}", payload.mac_address, e);
+        }
+    }
+
     // Pass the full payload (including new hardware fields) to the db function
     info!("Registering machine with MAC: {}, CPU: {:?}, Cores: {:?}, RAM: {:?}", 
           payload.mac_address, payload.cpu_model, payload.cpu_cores, payload.total_ram_bytes);
     
+    // A new machine can be rolled back if Tinkerbell rejects it; an existing one can't
+    let existing = db::get_machine_by_mac(&payload.mac_address).await;
+    let is_new = matches!(existing, Ok(None));
+    let existing = existing.ok().flatten();
+
+    // Site hooks may turn the machine away or add tags, a hostname, an OS or metadata
+    let existing_id = existing.as_ref().map(|machine| machine.id);
+    let enrichment = match crate::enrichment::run(&payload, existing_id).await {
+        (crate::enrichment::Outcome::Accept, enrichment) => enrichment,
+        (crate::enrichment::Outcome::Reject { hook, reason }, _) => {
+            warn!("Rejecting registration for MAC {}: enrichment hook '{}' said {}", payload.mac_address, hook, reason);
+            return (StatusCode::FORBIDDEN, Json(ErrorResponse {
+                error: "Registration Rejected".to_string(),
+                message: reason,
+            })).into_response();
+        }
+    };
+
     match db::register_machine(&payload).await {
         Ok(machine_id) => {
-            // Get the new machine to register with Tinkerbell
-            if let Ok(Some(machine)) = db::get_machine_by_id(&machine_id).await {
-                // Register with Tinkerbell (don't fail if this fails)
-                if let Err(e) = crate::tinkerbell::register_machine(&machine).await {
-                    warn!("Failed to register machine with Tinkerbell (continuing anyway): {}", e);
+            // Before the Tinkerbell sync, so the hardware record carries the hook's hostname
+            if let Err(e) = crate::enrichment::apply(&machine_id, &enrichment).await {
+                error!("Failed to apply enrichment to machine {}: {}", machine_id, e);
+            }
+            if let Some(environment) = &payload.agent_environment {
+                if let Err(e) = crate::boot_environment::record_agent(&machine_id, &payload.mac_address, environment).await {
+                    warn!("Failed to record agent environment for machine {}: {}", machine_id, e);
                 }
             }
+
+            // Register with Tinkerbell; failures are retried in the background unless rolled back
+            if let Err(e) = crate::tinkerbell_sync::sync_after_registration(&machine_id, is_new, &state.event_manager).await {
+                error!("Failed to register machine {} with Tinkerbell: {}", machine_id, e);
+                return (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorResponse {
+                    error: "Registration Failed".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+            
+            // An OS picked from the boot menu before registering is applied now
+            match db::take_boot_selection(&payload.mac_address, &machine_id).await {
+                Ok(Some(choice)) => {
+                    if let Some(crate::boot_menu::MenuChoice::Os(os_choice)) = crate::boot_menu::MenuChoice::parse(&choice) {
+                        info!("Applying boot menu OS choice '{}' to machine {}", os_choice, machine_id);
+                        if let Err(e) = db::assign_os(&machine_id, os_choice.as_str()).await {
+                            error!("Failed to apply boot menu OS choice to machine {}: {}", machine_id, e);
+                        }
+                    }
+                },
+                Ok(None) => {},
+                Err(e) => warn!("Failed to look up boot menu selection for MAC {}: {}", payload.mac_address, e),
+            }
             
+            // Registering again and again without progressing means the machine is PXE looping
+            if crate::boot_loop::record_registration(&machine_id, existing.as_ref()).await.is_some() {
+                let _ = state.event_manager.send(format!("boot_loop:{}", machine_id));
+            }
+
             // Emit machine discovered event
             let _ = state.event_manager.send(format!("machine_discovered:{}", machine_id));
             
@@ -311,6 +611,7 @@ async fn register_machine(
 
 #[axum::debug_handler]
 async fn get_all_machines(
+    State(state): State<AppState>,
     auth_session: AuthSession,
     req: axum::http::Request<axum::body::Body>
 ) -> Response {
@@ -322,7 +623,7 @@ async fn get_all_machines(
     // Check if user is authenticated as admin
     let is_admin = auth_session.user.is_some();
 
-    match db::get_all_machines().await {
+    match state.repos.machines.list().await {
         Ok(machines) => {
             // Get workflow info for machines that are installing OS
             let mut workflow_infos = HashMap::new();
@@ -490,9 +791,10 @@ async fn get_all_machines(
 
 #[axum::debug_handler]
 async fn get_machine(
+    State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Response {
-    match db::get_machine_by_id(&id).await {
+    match state.repos.machines.get(&id).await {
         Ok(Some(machine)) => { // machine now includes hardware fields from db query
             // Fetch workflow info if the machine is installing OS
             let workflow_info = if machine.status == MachineStatus::InstallingOS {
@@ -533,11 +835,19 @@ async fn get_machine(
     }
 }
 
+// Query parameters accepted by the OS assignment endpoint
+#[derive(Debug, Deserialize, Default)]
+struct AssignOsQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
 // Combined OS assignment handler
 #[axum::debug_handler]
 async fn assign_os(
     auth_session: AuthSession,
     Path(id): Path<Uuid>,
+    axum::extract::Query(query): axum::extract::Query<AssignOsQuery>,
     req: axum::http::Request<axum::body::Body>,
 ) -> Response {
     // Check if user is authenticated as admin
@@ -579,7 +889,19 @@ async fn assign_os(
         None
     };
     
+    // Catch typos before they become a workflow for a template that doesn't exist
+    if let Some(os_choice) = &os_choice {
+        if let Err(e) = (OsAssignmentRequest { os_choice: os_choice.clone() }).validate() {
+            let error_response = ErrorResponse {
+                error: "Invalid OS Choice".to_string(),
+                message: e.to_string(),
+            };
+            return (StatusCode::BAD_REQUEST, Json(error_response)).into_response();
+        }
+    }
+
     match os_choice {
+        Some(os_choice) if query.dry_run => preview_os_assignment(id, os_choice).await,
         Some(os_choice) => assign_os_internal(id, os_choice).await,
         None => {
             let error_response = ErrorResponse {
@@ -591,6 +913,42 @@ async fn assign_os(
     }
 }
 
+// Dry-run: validate the assignment and return the rendered workflow without changing anything
+async fn preview_os_assignment(id: Uuid, os_choice: String) -> Response {
+    info!("Previewing OS {} assignment for machine {} (dry run)", os_choice, id);
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            let error_response = ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            };
+            return (StatusCode::NOT_FOUND, Json(error_response)).into_response();
+        },
+        Err(e) => {
+            error!("Failed to load machine {} for dry run: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+        }
+    };
+
+    match crate::tinkerbell::preview_workflow(&machine, &os_choice).await {
+        Ok(preview) => (StatusCode::OK, Json(preview)).into_response(),
+        Err(e) => {
+            error!("Failed to preview OS assignment for machine {}: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Preview Failed".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
 // Shared implementation
 async fn assign_os_internal(id: Uuid, os_choice: String) -> Response {
     info!("Assigning OS {} to machine {}", os_choice, id);
@@ -692,27 +1050,11 @@ async fn update_status(
         Ok(true) => {
             // Get the updated machine to update Tinkerbell
             if let Ok(Some(machine)) = db::get_machine_by_id(&id).await {
-                // Update the machine in Tinkerbell (don't fail if this fails)
-                if let Err(e) = crate::tinkerbell::register_machine(&machine).await {
-                    warn!("Failed to update machine in Tinkerbell (continuing anyway): {}", e);
-                }
-                
-                // If the status is AwaitingAssignment, check if we should apply a default OS
-                if status == MachineStatus::AwaitingAssignment {
-                    // Check if a default OS is configured
-                    if let Ok(settings) = db::get_app_settings().await {
-                        if let Some(default_os) = settings.default_os {
-                            info!("Applying default OS '{}' to newly registered machine {}", default_os, id);
-                            // Assign the OS without triggering installation
-                            if let Ok(true) = db::assign_os(&id, &default_os).await {
-                                info!("Default OS choice '{}' applied to machine {}", default_os, id);
-                            }
-                        }
-                    }
-                }
+                // Update the machine in Tinkerbell (retried later if this fails)
+                crate::tinkerbell_sync::sync_or_queue(&*state.tinkerbell, &machine).await;
             }
             
-            // Emit machine updated event
+            // Emit machine updated event; OS policies are applied from it if now AwaitingAssignment
             let _ = state.event_manager.send(format!("machine_updated:{}", id));
             
             // Return HTML success message
@@ -761,16 +1103,18 @@ async fn update_hostname(
         }))).into_response();
     }
 
+    if let Err(errors) = payload.validate() {
+        return validation_error_response(errors);
+    }
+
     info!("Updating hostname for machine {} to {}", id, payload.hostname);
-    
+
     match db::update_hostname(&id, &payload.hostname).await {
         Ok(true) => {
             // Get the updated machine to update Tinkerbell
             if let Ok(Some(machine)) = db::get_machine_by_id(&id).await {
-                // Update the machine in Tinkerbell (don't fail if this fails)
-                if let Err(e) = crate::tinkerbell::register_machine(&machine).await {
-                    warn!("Failed to update machine in Tinkerbell (continuing anyway): {}", e);
-                }
+                // Update the machine in Tinkerbell (retried later if this fails)
+                crate::tinkerbell_sync::sync_or_queue(&*state.tinkerbell, &machine).await;
             }
             
             // Emit machine updated event
@@ -806,8 +1150,12 @@ async fn update_os_installed(
     Path(id): Path<Uuid>,
     Json(payload): Json<OsInstalledUpdateRequest>,
 ) -> Response {
+    if let Err(errors) = payload.validate() {
+        return validation_error_response(errors);
+    }
+
     info!("Updating OS installed for machine {} to {}", id, payload.os_installed);
-    
+
     match db::update_os_installed(&id, &payload.os_installed).await {
         Ok(true) => {
             // Emit machine updated event
@@ -839,182 +1187,245 @@ async fn update_os_installed(
     }
 }
 
+// Agents report the fingerprint of machines that were registered before fingerprinting existed.
+// The fingerprint decides which machine a new registration merges into, so once one is stored
+// only a signing agent or an admin may replace it.
 #[axum::debug_handler]
-async fn update_bmc(
-    State(state): State<AppState>,
+async fn update_hardware_fingerprint(
     auth_session: AuthSession,
+    signed: Option<axum::Extension<crate::agent_signing::SignedAgent>>,
     Path(id): Path<Uuid>,
-    Form(payload): Form<BmcCredentialsUpdateRequest>,
+    Json(payload): Json<HardwareFingerprint>,
 ) -> Response {
-    // Check if user is authenticated as admin
-    if auth_session.user.is_none() {
-        return (StatusCode::UNAUTHORIZED, Json(json!({
-            "error": "Unauthorized",
-            "message": "Admin authentication required for this operation"
-        }))).into_response();
+    if payload.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: "Hardware fingerprint is empty".to_string(),
+        })).into_response();
     }
 
-    info!("Updating BMC credentials for machine {}", id);
-    
-    // Create BMC credentials from the form data
-    let bmc_type = match payload.bmc_type.as_str() {
-        "IPMI" => BmcType::IPMI,
-        "Redfish" => BmcType::Redfish,
-        _ => BmcType::Other(payload.bmc_type.clone()), // Clone string
-    };
-    
-    let credentials = BmcCredentials {
-        address: payload.bmc_address,
-        username: payload.bmc_username,
-        password: Some(payload.bmc_password), // Assume password is provided
-        bmc_type,
-    };
-    
-    match db::update_bmc_credentials(&id, &credentials).await {
-        Ok(true) => {
-            // Emit machine updated event
-            let _ = state.event_manager.send(format!("machine_updated:{}", id));
-            
-            (StatusCode::OK, Html(format!(r#"
-                <div class="p-4 mb-4 text-sm text-green-700 bg-green-100 rounded-lg" role="alert">
-                    <span class="font-medium">Success!</span> BMC credentials updated.
-                </div>
-                <script>
-                    setTimeout(function() {{
-                        window.location.reload();
-                    }}, 1500);
-                </script>
-            "#))).into_response()
-        },
+    if auth_session.user.is_none() && signed.is_none() {
+        match db::get_hardware_fingerprint(&id).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                return (StatusCode::FORBIDDEN, Json(ErrorResponse {
+                    error: "Forbidden".to_string(),
+                    message: "A stored hardware fingerprint can only be replaced by a signed agent or an admin".to_string(),
+                })).into_response();
+            }
+            Err(e) => {
+                error!("Failed to look up hardware fingerprint for machine {}: {}", id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                    error: "Database Error".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+        }
+    }
+
+    match db::update_hardware_fingerprint(&id, &payload).await {
+        Ok(true) => (StatusCode::OK, Json(json!({ "success": true }))).into_response(),
         Ok(false) => {
-            let error_message = format!("Machine with ID {} not found", id);
-            (StatusCode::NOT_FOUND, Html(format!(r#"
-                <div class="p-4 mb-4 text-sm text-red-700 bg-red-100 rounded-lg" role="alert">
-                    <span class="font-medium">Error!</span> {}.
-                </div>
-            "#, error_message))).into_response()
+            let error_response = ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response)).into_response()
         },
         Err(e) => {
-            error!("Failed to update BMC credentials for machine {}: {}", id, e);
-            let error_message = format!("Database error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Html(format!(r#"
-                <div class="p-4 mb-4 text-sm text-red-700 bg-red-100 rounded-lg" role="alert">
-                    <span class="font-medium">Error!</span> {}.
-                </div>
-            "#, error_message))).into_response()
+            error!("Failed to update hardware fingerprint for machine {}: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
         }
     }
 }
 
-// Handler to get the hostname edit form
-#[axum::debug_handler]
-async fn get_hostname_form(
+// Agents running as daemons report the kernel and pending updates of the installed OS
+async fn update_machine_patches(
+    State(state): State<AppState>,
     Path(id): Path<Uuid>,
-) -> impl IntoResponse {
+    Json(payload): Json<dragonfly_common::models::PatchReport>,
+) -> Response {
     match db::get_machine_by_id(&id).await {
-        Ok(Some(machine)) => {
-            let current_hostname = machine.hostname.unwrap_or_default();
-            // Use raw string literals to avoid escaping issues
-            let html = format!(
-                r###"
-                <div class="sm:flex sm:items-start">
-                    <div class="mt-3 text-center sm:mt-0 sm:text-left w-full">
-                        <h3 class="text-base font-semibold leading-6 text-gray-900">
-                            Update Machine Hostname
-                        </h3>
-                        <div class="mt-2">
-                            <form hx-post="/machines/{}/hostname" hx-target="#hostname-modal">
-                                <label for="hostname" class="block text-sm font-medium text-gray-700">Hostname</label>
-                                <input type="text" name="hostname" id="hostname" value="{}" class="mt-1 block w-full rounded-md border-gray-300 shadow-sm focus:border-indigo-500 focus:ring-indigo-500 sm:text-sm" placeholder="Enter hostname">
-                                <div class="mt-5 sm:mt-4 sm:flex sm:flex-row-reverse">
-                                    <button type="submit" class="inline-flex w-full justify-center rounded-md bg-indigo-600 px-3 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-500 sm:ml-3 sm:w-auto">
-                                        Update
-                                    </button>
-                                    <button type="button" class="mt-3 inline-flex w-full justify-center rounded-md bg-white px-3 py-2 text-sm font-semibold text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 hover:bg-gray-50 sm:mt-0 sm:w-auto" onclick="document.getElementById('hostname-modal').classList.add('hidden')">
-                                        Cancel
-                                    </button>
-                                </div>
-                            </form>
-                        </div>
-                    </div>
-                </div>
-                "###,
-                id, current_hostname
-            );
-            
-            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/html")], html)
-        },
+        Ok(Some(_)) => {},
         Ok(None) => {
-            let error_html = format!(
-                r###"<div class="p-4 text-red-500">Machine with ID {} not found</div>"###,
-                id
-            );
-            (StatusCode::NOT_FOUND, [(axum::http::header::CONTENT_TYPE, "text/html")], error_html)
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
         },
         Err(e) => {
-            let error_html = format!(
-                r###"<div class="p-4 text-red-500">Error: {}</div>"###,
-                e
-            );
-            (StatusCode::INTERNAL_SERVER_ERROR, [(axum::http::header::CONTENT_TYPE, "text/html")], error_html)
+            error!("Failed to load machine {} for patch report: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
         }
     }
-}
 
-// Handler for initial iPXE script generation (DHCP points here)
-// Determines whether to chain to HookOS or the Dragonfly Agent
-pub async fn ipxe_script(Path(mac): Path<String>) -> Response {
-    if !mac.contains(':') || mac.split(':').count() != 6 {
-        warn!("Received invalid MAC format in iPXE request: {}", mac);
-        return (StatusCode::BAD_REQUEST, "Invalid MAC Address Format").into_response();
+    match db::save_patch_report(&id, &payload).await {
+        Ok(()) => {
+            let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            (StatusCode::OK, Json(json!({ "success": true }))).into_response()
+        },
+        Err(e) => {
+            error!("Failed to save patch report for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
     }
+}
 
-    info!("Generating initial iPXE script for MAC: {}", mac);
+#[derive(Deserialize)]
+struct AdoptRequest {
+    // "ssh" to install the agent from here, "script" to hand the installer to an admin
+    method: String,
+    #[serde(default)]
+    ssh: crate::adoption::SshTarget,
+}
 
-    // Read required base URL from environment variable
-    let base_url = match env::var("DRAGONFLY_BASE_URL") {
-        Ok(url) => url,
-        Err(_) => {
-            error!("CRITICAL: DRAGONFLY_BASE_URL environment variable is not set. iPXE booting requires this configuration.");
-            let error_response = ErrorResponse {
+// The installer for a machine, or the response explaining why it can't be built
+async fn adoption_installer(id: &Uuid) -> Result<String, Response> {
+    let base_url = match crate::config::base_url() {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
                 error: "Configuration Error".to_string(),
                 message: "Server is missing required DRAGONFLY_BASE_URL configuration.".to_string(),
-            };
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+            })).into_response());
+        }
+    };
+    let token = match crate::agent_signing::settings().await {
+        Ok(settings) => settings.token,
+        Err(e) => {
+            error!("Failed to load agent signing settings: {}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response());
+        }
+    };
+    let binary = match crate::adoption::agent_binary(&base_url).await {
+        Ok(binary) => binary,
+        Err(e) => {
+            error!("No agent binary for machine {}'s installer: {:#}", id, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Configuration Error".to_string(),
+                message: format!("{:#}", e),
+            })).into_response());
         }
     };
+    Ok(crate::adoption::installer_script(&base_url, id, &binary, token.as_deref()))
+}
 
-    match db::get_machine_by_mac(&mac).await {
-        Ok(Some(_)) => {
-            // Known machine: Chain to Dragonfly's OS installation hook script (hookos.ipxe)
-            info!("Known MAC {}, chaining to HookOS script", mac);
-            let script = format!("#!ipxe\nchain {}/ipxe/hookos.ipxe", base_url);
-            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response()
-        },
+// Bring a machine running its own OS under management without reimaging it
+async fn api_adopt_machine(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<AdoptRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let Some(method) = crate::adoption::AdoptionMethod::parse(&payload.method) else {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: format!("Unknown adoption method '{}'; use ssh or script", payload.method),
+        })).into_response();
+    };
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
         Ok(None) => {
-            // Unknown machine: Chain to the Dragonfly agent script
-            info!("Unknown MAC {}, chaining to Dragonfly Agent iPXE script", mac);
-            let script = format!("#!ipxe\nchain {}/ipxe/dragonfly-agent.ipxe", base_url);
-            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response()
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
         },
         Err(e) => {
-            error!("Database error while looking up MAC {}: {}", mac, e);
-            let error_response = ErrorResponse {
+            error!("Failed to load machine {} for adoption: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
                 error: "Database Error".to_string(),
                 message: e.to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+            })).into_response();
+        }
+    };
+    if !crate::adoption::adoptable(&machine.status) {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Not Adoptable".to_string(),
+            message: format!("Machine {} is {}; only machines detected with an existing OS can be adopted", id, machine.status),
+        })).into_response();
+    }
+    let os_installed = match machine.os_installed.as_deref().map(str::trim) {
+        Some(os) if !os.is_empty() => os.to_string(),
+        _ => {
+            return (StatusCode::CONFLICT, Json(ErrorResponse {
+                error: "Unknown OS".to_string(),
+                message: format!("Machine {} hasn't reported which OS it runs yet", id),
+            })).into_response();
+        }
+    };
+
+    let script = match adoption_installer(&id).await {
+        Ok(script) => script,
+        Err(response) => return response,
+    };
+    let output = match method {
+        crate::adoption::AdoptionMethod::Ssh => {
+            info!("Installing the agent on machine {} over SSH", id);
+            match crate::adoption::bootstrap_over_ssh(&machine, &payload.ssh, &script).await {
+                Ok(output) => Some(output),
+                Err(e) => {
+                    warn!("Failed to adopt machine {} over SSH: {:#}", id, e);
+                    return (StatusCode::BAD_GATEWAY, Json(ErrorResponse {
+                        error: "SSH Bootstrap Failed".to_string(),
+                        message: format!("{:#}", e),
+                    })).into_response();
+                }
+            }
+        },
+        crate::adoption::AdoptionMethod::Script => None,
+    };
+
+    match db::adopt_machine(&id, method, &os_installed).await {
+        Ok(adoption) if method == crate::adoption::AdoptionMethod::Script => {
+            info!("Handed out the installer for machine {}; it's adopted once its agent reports in", id);
+            Json(json!({
+                "adoption": adoption,
+                "script_url": crate::base_path::url(&format!("/api/machines/{}/adopt/install.sh", id)),
+                "output": output,
+            })).into_response()
+        },
+        Ok(adoption) => {
+            info!("Adopted machine {} running {} ({})", id, os_installed, method.as_str());
+            let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            Json(json!({
+                "adoption": adoption,
+                "script_url": crate::base_path::url(&format!("/api/machines/{}/adopt/install.sh", id)),
+                "output": output,
+            })).into_response()
+        },
+        Err(e) => {
+            error!("Failed to record adoption of machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
         }
     }
 }
 
-#[axum::debug_handler]
-async fn delete_machine(
-    State(state): State<AppState>,
-    auth_session: AuthSession,
-    Path(id): Path<Uuid>,
-) -> Response {
-    // Check if user is authenticated as admin
+// Download the agent installer generated for a machine
+async fn api_adoption_script(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
     if auth_session.user.is_none() {
         return (StatusCode::UNAUTHORIZED, Json(json!({
             "error": "Unauthorized",
@@ -1022,1752 +1433,8713 @@ async fn delete_machine(
         }))).into_response();
     }
 
-    info!("Request to delete machine: {}", id);
-
-    // Get the machine to find its MAC address
     match db::get_machine_by_id(&id).await {
-        Ok(Some(machine)) => {
-            // Delete from Tinkerbell
-            let mac_address = machine.mac_address.replace(":", "-").to_lowercase();
-            
-            let tinkerbell_result = match crate::tinkerbell::delete_hardware(&mac_address).await {
-                Ok(_) => {
-                    info!("Successfully deleted machine from Tinkerbell: {}", mac_address);
-                    true
-                },
-                Err(e) => {
-                    warn!("Failed to delete machine from Tinkerbell: {}", e);
-                    false
-                }
-            };
-
-            // Delete from database
-            match db::delete_machine(&id).await {
-                Ok(true) => {
-                    let message = if tinkerbell_result {
-                        "Machine successfully deleted from Dragonfly and Tinkerbell."
-                    } else {
-                        "Machine deleted from Dragonfly but there was an issue removing it from Tinkerbell."
-                    };
-                    
-                    // Emit machine deleted event
-                    let _ = state.event_manager.send(format!("machine_deleted:{}", id));
-                    
-                    (StatusCode::OK, Json(json!({ "success": true, "message": message }))).into_response()
-                },
-                Ok(false) => {
-                    (StatusCode::NOT_FOUND, Json(json!({ "error": "Machine not found in database" }))).into_response()
-                },
-                Err(e) => {
-                    error!("Failed to delete machine from database: {}", e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("Database error: {}", e) }))).into_response()
-                }
-            }
-        },
+        Ok(Some(_)) => {},
         Ok(None) => {
-            (StatusCode::NOT_FOUND, Json(json!({ "error": "Machine not found" }))).into_response()
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
         },
         Err(e) => {
-            error!("Error fetching machine for deletion: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("Database error: {}", e) }))).into_response()
+            error!("Failed to load machine {} for its installer: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
         }
     }
+    match adoption_installer(&id).await {
+        Ok(script) => (
+            StatusCode::OK,
+            [
+                (axum::http::header::CONTENT_TYPE, "text/x-shellscript".to_string()),
+                (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"dragonfly-adopt-{}.sh\"", id)),
+            ],
+            script,
+        ).into_response(),
+        Err(response) => response,
+    }
 }
 
-// Add this function to handle machine updates
-#[axum::debug_handler]
-async fn update_machine(
-    State(state): State<AppState>,
-    // Use AuthSession directly, not Option<AuthSession>
-    auth_session: AuthSession,
-    // Add ConnectInfo to get client IP
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Path(id): Path<Uuid>,
-    Json(mut machine_payload): Json<Machine>,
-) -> Response {
-    let client_ip = addr.ip().to_string();
-    info!("Update request for machine {} from IP: {}", id, client_ip);
+async fn api_get_adoption(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
 
-    // Authorization Logic
-    // Check if an admin user is logged in
-    let is_admin = auth_session.user.is_some();
+    match db::get_adoption(&id).await {
+        Ok(Some(adoption)) => Json(adoption).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} hasn't been adopted", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load adoption for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
 
-    let authorized = if is_admin {
-        // Admin is always authorized
-        info!("Admin user authorized update for machine {}", id);
-        true
-    } else {
-        // Not an admin, check if it's the agent based on IP
-        info!("Request is not from an admin, checking IP for agent authorization...");
-        match db::get_machine_by_id(&id).await {
-            Ok(Some(stored_machine)) => {
-                if stored_machine.ip_address == client_ip {
-                    info!("Agent IP {} matches stored IP for machine {}. Authorizing update.", client_ip, id);
-                    true // IP matches, allow update
-                } else {
-                    warn!("Agent IP {} does NOT match stored IP {} for machine {}. Denying update.",
-                          client_ip, stored_machine.ip_address, id);
-                    false // IP mismatch
-                }
-            },
-            Ok(None) => {
-                warn!("Machine {} not found during IP authorization check.", id);
-                false // Machine not found
-                },
-                Err(e) => {
-                error!("Database error during IP authorization check for machine {}: {}", id, e);
-                false // Database error
-            }
+fn ssh_db_error(what: &str, e: anyhow::Error) -> Response {
+    error!("Failed to {}: {}", what, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+        error: "Database Error".to_string(),
+        message: e.to_string(),
+    })).into_response()
+}
+
+// The credentials a machine is managed with over SSH (its own or a tag's); never the key
+async fn api_get_machine_ssh_credentials(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    match crate::ssh::credentials_for(&id).await {
+        Ok(Some((_, summary))) => Json(summary).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No SSH credentials apply to machine {}", id),
+        })).into_response(),
+        Err(e) => ssh_db_error(&format!("load SSH credentials for machine {}", id), e),
+    }
+}
+
+async fn put_ssh_credentials(scope: crate::ssh::CredentialScope, payload: crate::ssh::CredentialsInput) -> Response {
+    let credentials = match payload.validate() {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Credentials".to_string(),
+                message: e.to_string(),
+            })).into_response();
         }
     };
+    match db::save_ssh_credentials(&scope, &credentials).await {
+        Ok(()) => {
+            info!("SSH credentials for {:?} saved", scope);
+            Json(json!({ "success": true, "username": credentials.username, "port": credentials.port })).into_response()
+        },
+        Err(e) => ssh_db_error(&format!("save SSH credentials for {:?}", scope), e),
+    }
+}
 
-    if !authorized {
-        // Use 403 Forbidden for authorization failures
-        // (axum-login middleware handles 401 for missing authentication if configured)
-        return (StatusCode::FORBIDDEN, Json(json!({
-            "error": "Forbidden",
-            "message": "You are not authorized to update this machine."
+async fn delete_ssh_credentials(scope: crate::ssh::CredentialScope) -> Response {
+    match db::delete_ssh_credentials(&scope).await {
+        Ok(true) => {
+            info!("SSH credentials for {:?} removed", scope);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: "No SSH credentials are stored there".to_string(),
+        })).into_response(),
+        Err(e) => ssh_db_error(&format!("delete SSH credentials for {:?}", scope), e),
+    }
+}
+
+async fn api_put_machine_ssh_credentials(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<crate::ssh::CredentialsInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
         }))).into_response();
     }
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(_)) => put_ssh_credentials(crate::ssh::CredentialScope::Machine(id), payload).await,
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine with ID {} not found", id),
+        })).into_response(),
+        Err(e) => ssh_db_error(&format!("look up machine {}", id), e),
+    }
+}
 
-    // --- Proceed with Update (if authorized) ---
-    
-    // Ensure the ID from the path matches the payload ID
-    if machine_payload.id != id {
-        return (StatusCode::BAD_REQUEST, Json(json!({
-            "error": "ID Mismatch",
-            "message": "The machine ID in the URL path does not match the ID in the request body."
+async fn api_delete_machine_ssh_credentials(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
         }))).into_response();
     }
+    delete_ssh_credentials(crate::ssh::CredentialScope::Machine(id)).await
+}
 
-    info!("Updating machine {} with full payload (Authorized by admin: {})", id, is_admin);
-    
-    // Set the updated_at timestamp before saving
-    machine_payload.updated_at = Utc::now();
+async fn api_get_tag_ssh_credentials(auth_session: AuthSession, Path(tag_name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    match db::get_ssh_credentials(&crate::ssh::CredentialScope::Tag(tag_name.clone())).await {
+        Ok(Some((credentials, updated_at))) => Json(crate::ssh::CredentialSummary {
+            username: credentials.username,
+            port: credentials.port,
+            updated_at,
+            source_tag: Some(tag_name),
+        }).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No SSH credentials are stored for tag {}", tag_name),
+        })).into_response(),
+        Err(e) => ssh_db_error(&format!("load SSH credentials for tag {}", tag_name), e),
+    }
+}
 
-    // Call the updated db::update_machine function
-    match db::update_machine(&machine_payload).await {
-                Ok(true) => {
-            // Emit machine updated event
-            let _ = state.event_manager.send(format!("machine_updated:{}", id));
-            
-            // Return the updated machine object
-            (StatusCode::OK, Json(machine_payload)).into_response()
-                },
-                Ok(false) => {
-            // This case should ideally not happen if the ID check above passed
-            // but handle it just in case (e.g., race condition with deletion)
-            (StatusCode::NOT_FOUND, Json(json!({
-                "error": "Not Found",
-                "message": format!("Machine with ID {} not found during update attempt.", id)
-            }))).into_response()
-                },
-                Err(e) => {
-            error!("Failed to update machine {}: {}", id, e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
-                "error": "Database Error",
-                "message": e.to_string()
-            }))).into_response()
-        }
+async fn api_put_tag_ssh_credentials(
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+    Json(payload): Json<crate::ssh::CredentialsInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
+    put_ssh_credentials(crate::ssh::CredentialScope::Tag(tag_name), payload).await
 }
 
-// Handler to get the OS assignment form
-async fn get_machine_os(Path(id): Path<Uuid>) -> Response {
-    Html(format!(r#"
-        <div class="sm:flex sm:items-start">
-            <div class="mt-3 text-center sm:mt-0 sm:text-left w-full">
-                <h3 class="text-lg leading-6 font-medium text-gray-900">
-                    Assign Operating System
-                </h3>
-                <div class="mt-2">
-                    <form hx-post="/api/machines/{}/os" hx-swap="none" @submit="osModal = false">
-                        <div class="mt-4">
-                            <label for="os_choice" class="block text-sm font-medium text-gray-700">Operating System</label>
-                            <select
-                                id="os_choice"
-                                name="os_choice"
-                                class="mt-1 block w-full pl-3 pr-10 py-2 text-base border-gray-300 focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm rounded-md"
-                            >
-                                <option value="ubuntu-2204">Ubuntu 22.04</option>
-                                <option value="ubuntu-2404">Ubuntu 24.04</option>
-                                <option value="debian-12">Debian 12</option>
-                                <option value="proxmox">Proxmox VE</option>
-                                <option value="talos">Talos</option>
-                            </select>
-                        </div>
-                        <div class="mt-5 sm:mt-4 sm:flex sm:flex-row-reverse">
-                            <button
-                                type="submit"
-                                class="inline-flex w-full justify-center rounded-md bg-indigo-600 px-3 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-500 sm:ml-3 sm:w-auto"
-                            >
-                                Assign
-                            </button>
-                            <button
-                                type="button"
-                                class="mt-3 inline-flex w-full justify-center rounded-md bg-white px-3 py-2 text-sm font-semibold text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 hover:bg-gray-50 sm:mt-0 sm:w-auto"
-                                @click="osModal = false"
-                            >
-                                Cancel
-                            </button>
-                        </div>
-                    </form>
-                </div>
-            </div>
-        </div>
-    "#, id)).into_response()
+async fn api_delete_tag_ssh_credentials(auth_session: AuthSession, Path(tag_name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    delete_ssh_credentials(crate::ssh::CredentialScope::Tag(tag_name)).await
 }
 
-// Handler to get the status update form 
-pub async fn get_machine_status(Path(id): Path<Uuid>) -> impl IntoResponse {
-    let html = format!(r#"
-        <div class="sm:flex sm:items-start">
-            <div class="mt-3 text-center sm:mt-0 sm:text-left w-full">
-                <h3 class="text-lg leading-6 font-medium text-gray-900">
-                    Update Machine Status
-                </h3>
-                <div class="mt-2">
-                    <form hx-post="/machines/{}/status" hx-swap="none" @submit="statusModal = false">
-                        <div class="mb-4">
-                            <label for="status" class="block text-sm font-medium text-gray-700">Status</label>
-                            <select name="status" id="status" class="mt-1 block w-full pl-3 pr-10 py-2 text-base border-gray-300 focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm rounded-md">
-                                <option value="Ready">Ready</option>
-                                <option value="AwaitingAssignment">Awaiting OS Assignment</option>
-                                <option value="InstallingOS">Installing OS</option>
-                                <option value="Error">Error</option>
-                            </select>
-                        </div>
-                        <div class="mt-5 sm:mt-6">
-                            <button type="submit" class="inline-flex justify-center w-full rounded-md border border-transparent shadow-sm px-4 py-2 bg-indigo-600 text-base font-medium text-white hover:bg-indigo-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-indigo-500 sm:text-sm">
-                                Update Status
-                            </button>
-                        </div>
-                    </form>
-                </div>
-            </div>
-        </div>
-    "#, id);
-
-    Html(html)
+#[derive(Deserialize)]
+struct SshActionRequest {
+    // reboot-pxe, reboot-firmware, reboot, power-off or logs
+    action: String,
 }
 
-// Rename from sse_events to machine_events to match the function name used in the working implementation
-async fn machine_events(
-    State(state): State<AppState>,
-) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
-    let rx = state.event_manager.subscribe(); // Remove mut
-    
-    let stream = stream::unfold(rx, |mut rx| async move {
-        match rx.recv().await {
-            Ok(event_string) => {
-                // FIX: Correct parsing and variable naming
-                let parts: Vec<&str> = event_string.splitn(2, ':').collect();
-                let (event_type, event_payload_str) = if parts.len() == 2 { // Renamed event_id_str to event_payload_str for clarity
-                    (parts[0], Some(parts[1]))
-                } else {
-                    (event_string.as_str(), None)
-                };
-
-                // Special handling for ip_download_progress to send raw JSON payload
-                if event_type == "ip_download_progress" {
-                    if let Some(payload_str) = event_payload_str {
-                        // Directly use the JSON string as data for this specific event type
-                let sse_event = Event::default()
-                    .event(event_type)
-                            .data(payload_str); // Use the payload string directly
-                        Some((Ok(sse_event), rx))
-                    } else {
-                         warn!("Received ip_download_progress event without payload: {}", event_string);
-                         // Optionally send a comment or skip
-                         let comment_event = Event::default().comment("Warning: ip_download_progress event received without payload.");
-                         Some((Ok(comment_event), rx))
-                    }
-                } else {
-                    // Existing logic for other events (like machine_updated, machine_discovered, etc.)
-                    let data_payload = if let Some(id_str) = event_payload_str { // Use the renamed variable
-                        json!({ "type": event_type, "id": id_str })
-                    } else {
-                        // Ensure there's always a payload, even without ID
-                        json!({ "type": event_type })
-                    };
-
-                    // Serialize JSON to string for SSE data field
-                    match serde_json::to_string(&data_payload) {
-                        Ok(json_string) => {
-                            let sse_event = Event::default()
-                                .event(event_type)
-                                .data(json_string);
-                Some((Ok(sse_event), rx))
-                        },
-                        Err(e) => {
-                            error!("Failed to serialize SSE event data to JSON: {}", e);
-                            let comment_event = Event::default().comment("Internal error: failed to serialize event.");
-                            Some((Ok(comment_event), rx))
-                        }
-                    }
-                }
-            },
-            Err(_) => None,
+// Run a management command on a machine over SSH
+async fn api_machine_ssh_action(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<SshActionRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    let Some(action) = crate::ssh::Action::parse(&payload.action) else {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: format!("Unknown action '{}'; use reboot-pxe, reboot-firmware, reboot, power-off or logs", payload.action),
+        })).into_response();
+    };
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        },
+        Err(e) => return ssh_db_error(&format!("look up machine {}", id), e),
+    };
+    // Collecting logs is harmless; anything else could undo work being done by hand
+    if action != crate::ssh::Action::CollectLogs {
+        match crate::maintenance::status(&id).await {
+            Ok(status) if status.maintenance => return maintenance_blocked(&id, &status),
+            Ok(_) => {},
+            Err(e) => return ssh_db_error(&format!("check maintenance for machine {}", id), e),
         }
-    });
+    }
 
-    Sse::new(stream).keep_alive(
-        KeepAlive::new()
-            .interval(Duration::from_secs(1))
-            .text("ping"),
-    )
+    match crate::ssh::run_action(&machine, action).await {
+        Ok(output) => Json(json!({
+            "success": true,
+            "action": action.as_str(),
+            "output": output,
+        })).into_response(),
+        Err(e) => {
+            warn!("SSH action '{}' failed on machine {}: {:#}", action.as_str(), id, e);
+            (StatusCode::BAD_GATEWAY, Json(ErrorResponse {
+                error: "SSH Command Failed".to_string(),
+                message: format!("{:#}", e),
+            })).into_response()
+        }
+    }
 }
 
-async fn generate_ipxe_script(script_name: &str) -> Result<String, dragonfly_common::Error> {
-    info!("Generating IPXE script: {}", script_name);
- 
-    match script_name {
-        "hookos.ipxe" => {
-            // Get Dragonfly base URL (required)
-            let base_url_str = env::var("DRAGONFLY_BASE_URL")
-                .map_err(|_| {
-                    error!("CRITICAL: DRAGONFLY_BASE_URL environment variable is not set. HookOS iPXE script requires this.");
-                    Error::Internal("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string())
-                })?;
-
-            // --- Derive Tinkerbell defaults from DRAGONFLY_BASE_URL ---
-            let default_tinkerbell_host = Url::parse(&base_url_str)
-                .ok()
-                .and_then(|url| url.host_str().map(String::from))
-                .unwrap_or_else(|| {
-                    warn!("Could not parse DRAGONFLY_BASE_URL host, using fallback '127.0.0.1' for Tinkerbell defaults.");
-                    "127.0.0.1".to_string()
-                });
-            
-            const DEFAULT_GRPC_PORT: u16 = 42113;
-            let default_grpc_authority = format!("{}:{}", default_tinkerbell_host, DEFAULT_GRPC_PORT);
-            let default_syslog_host = default_tinkerbell_host.clone(); // Default syslog host is just the host part
-            // -----------------------------------------------------------
-
-            // Get Tinkerbell config, using derived values as defaults
-            let grpc_authority = env::var("TINKERBELL_GRPC_AUTHORITY")
-                .unwrap_or_else(|_| {
-                    info!("TINKERBELL_GRPC_AUTHORITY not set, deriving default: {}", default_grpc_authority);
-                    default_grpc_authority
-                });
-            let syslog_host = env::var("TINKERBELL_SYSLOG_HOST")
-                .unwrap_or_else(|_| {
-                     info!("TINKERBELL_SYSLOG_HOST not set, deriving default: {}", default_syslog_host);
-                     default_syslog_host
-                 });
-            let tinkerbell_tls = env::var("TINKERBELL_TLS")
-                .map(|s| s.parse().unwrap_or(false))
-                .unwrap_or(false);
-
-            // Format the HookOS iPXE script using Dragonfly URL for artifacts and Tinkerbell details for params
-            Ok(format!(r#"#!ipxe
-
-echo Loading HookOS via Dragonfly...
-
-set arch ${{buildarch}}
-# Dragonfly + Tinkerbell only supports 64 bit archectures.
-# The build architecture does not necessarily represent the architecture of the machine on which iPXE is running.
-# https://ipxe.org/cfg/buildarch
+// Agents report their version (and a boot method the server can't see) on every start
+async fn update_agent_environment(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<dragonfly_common::models::AgentEnvironment>,
+) -> Response {
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        },
+        Err(e) => {
+            error!("Failed to load machine {} for agent environment: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    if payload.agent_version.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: "Agent version is empty".to_string(),
+        })).into_response();
+    }
 
-iseq ${{arch}} i386 && set arch x86_64 ||
-iseq ${{arch}} arm32 && set arch aarch64 ||
-iseq ${{arch}} arm64 && set arch aarch64 ||
-set base-url {}
-set retries:int32 0
-set retry_delay:int32 0
+    match crate::boot_environment::record_agent(&id, &machine.mac_address, &payload).await {
+        Ok(()) => {
+            let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            (StatusCode::OK, Json(json!({ "success": true }))).into_response()
+        },
+        Err(e) => {
+            error!("Failed to save agent environment for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
 
-set worker_id ${{mac}}
-set grpc_authority {}
-set syslog_host {}
-set tinkerbell_tls {}
+async fn api_get_boot_environment(Path(id): Path<Uuid>) -> Response {
+    match db::get_boot_environment(&id).await {
+        Ok(Some(environment)) => Json(json!({
+            "agent_outdated": environment.agent_outdated(),
+            "hookos_outdated": environment.hookos_outdated(),
+            "current_agent_version": crate::boot_environment::current_agent_version(),
+            "current_hookos_version": crate::stack::RECOMMENDED_HOOK_RELEASE,
+            "environment": environment,
+        })).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine with ID {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load boot environment for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
 
-echo worker_id=${{mac}}
-echo grpc_authority={}
-echo syslog_host={}
-echo tinkerbell_tls={}
+#[derive(Deserialize)]
+struct BootEnvironmentQuery {
+    // outdated, outdated_agent, outdated_hookos or unknown
+    filter: Option<String>,
+}
 
-set idx:int32 0
-:retry_kernel
-kernel ${{base-url}}/ipxe/hookos/vmlinuz-${{arch}} \
-syslog_host=${{syslog_host}} grpc_authority=${{grpc_authority}} tinkerbell_tls=${{tinkerbell_tls}} worker_id=${{worker_id}} hw_addr=${{mac}} \
-console=tty1 console=tty2 console=ttyAMA0,115200 console=ttyAMA1,115200 console=ttyS0,115200 console=ttyS1,115200 tink_worker_image=quay.io/tinkerbell/tink-worker:v0.12.1 \
-intel_iommu=on iommu=pt initrd=initramfs-${{arch}} && goto download_initrd || iseq ${{idx}} ${{retries}} && goto kernel-error || inc idx && echo retry in ${{retry_delay}} seconds ; sleep ${{retry_delay}} ; goto retry_kernel
+// Boot environments across the fleet; ?filter=outdated lists the machines due a refresh
+async fn api_list_boot_environments(
+    axum::extract::Query(query): axum::extract::Query<BootEnvironmentQuery>,
+) -> Response {
+    let filter = match query.filter.as_deref().map(|f| (f, crate::boot_environment::EnvironmentFilter::parse(f))) {
+        None => None,
+        Some((_, Some(filter))) => Some(filter),
+        Some((value, None)) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Bad Request".to_string(),
+                message: format!("Unknown filter '{}'; use outdated, outdated_agent, outdated_hookos or unknown", value),
+            })).into_response();
+        }
+    };
+    let environments = match db::list_boot_environments().await {
+        Ok(environments) => environments,
+        Err(e) => {
+            error!("Failed to load boot environments: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    let machines: Vec<_> = environments.iter()
+        .filter(|(_, environment)| match filter {
+            Some(filter) => filter.matches(Some(environment)),
+            None => true,
+        })
+        .map(|(id, environment)| json!({
+            "machine_id": id,
+            "agent_outdated": environment.agent_outdated(),
+            "hookos_outdated": environment.hookos_outdated(),
+            "environment": environment,
+        }))
+        .collect();
+    Json(json!({
+        "current_agent_version": crate::boot_environment::current_agent_version(),
+        "current_hookos_version": crate::stack::RECOMMENDED_HOOK_RELEASE,
+        "count": machines.len(),
+        "machines": machines,
+    })).into_response()
+}
 
-:download_initrd
-set idx:int32 0
-:retry_initrd
-initrd ${{base-url}}/ipxe/hookos/initramfs-${{arch}} && goto boot || iseq ${{idx}} ${{retries}} && goto initrd-error || inc idx && echo retry in ${{retry_delay}} seconds ; sleep ${{retry_delay}} ; goto retry_initrd
+async fn api_get_machine_patches(Path(id): Path<Uuid>) -> Response {
+    match db::get_patch_status(&id).await {
+        Ok(Some(status)) => Json(status).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No patch report for machine {}", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load patch report for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
 
-:boot
-set idx:int32 0
-:retry_boot
-boot || iseq ${{idx}} ${{retries}} && goto boot-error || inc idx && echo retry in ${{retry_delay}} seconds ; sleep ${{retry_delay}} ; goto retry_boot
+#[axum::debug_handler]
+async fn update_bmc(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Form(payload): Form<BmcCredentialsUpdateRequest>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
 
-:kernel-error
-echo Failed to load kernel
-imgfree
-exit
+    // The form is submitted via htmx, so report invalid fields as an HTML fragment
+    if let Err(errors) = payload.validate() {
+        let items: String = errors.errors.iter()
+            .map(|e| format!("<li>{}: {}</li>", minijinja::HtmlEscape(&e.field), minijinja::HtmlEscape(&e.message)))
+            .collect();
+        return (StatusCode::UNPROCESSABLE_ENTITY, Html(format!(r#"
+            <div class="p-4 mb-4 text-sm text-red-700 bg-red-100 rounded-lg" role="alert">
+                <span class="font-medium">Invalid BMC settings:</span>
+                <ul class="mt-1 list-disc list-inside">{}</ul>
+            </div>
+        "#, items))).into_response();
+    }
 
-:initrd-error
-echo Failed to load initrd
-imgfree
-exit
+    info!("Updating BMC credentials for machine {}", id);
 
-:boot-error
-echo Failed to boot
-imgfree
-exit
-"#, 
-            base_url_str, // Use Dragonfly base URL for artifacts
-            grpc_authority, // Use determined gRPC authority (env var or derived default)
-            syslog_host,    // Use determined syslog host (env var or derived default)
-            tinkerbell_tls, // Use determined TLS setting
-            grpc_authority, // for echo
-            syslog_host,    // for echo
-            tinkerbell_tls  // for echo
-            ))
-        },
-        "dragonfly-agent.ipxe" => {
-            // Get Dragonfly base URL for agent artifacts
-            let base_url = env::var("DRAGONFLY_BASE_URL")
-                .map_err(|_| {
-                    error!("CRITICAL: DRAGONFLY_BASE_URL environment variable is not set. Agent iPXE script requires this.");
-                    Error::Internal("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string())
-                })?;
-                
-            // Format the Dragonfly Agent iPXE script
-            Ok(format!(r#"#!ipxe
-kernel {}/ipxe/dragonfly-agent/vmlinuz \
-  ip=dhcp \
-  alpine_repo=http://dl-cdn.alpinelinux.org/alpine/v3.21/main \
-  modules=loop,squashfs,sd-mod,usb-storage \
-  initrd=initramfs-lts \
-  modloop={}/ipxe/dragonfly-agent/modloop \
-  apkovl={}/ipxe/dragonfly-agent/localhost.apkovl.tar.gz \
-  rw
-initrd {}/ipxe/dragonfly-agent/initramfs-lts
-boot
-"#, 
-            base_url, // for kernel path
-            base_url, // for modloop path
-            base_url, // for apkovl path
-            base_url  // for initrd path
-            ))
+    // Create BMC credentials from the form data
+    let bmc_type = match payload.bmc_type.as_str() {
+        "IPMI" => BmcType::IPMI,
+        "Redfish" => BmcType::Redfish,
+        _ => BmcType::Other(payload.bmc_type.clone()), // Clone string
+    };
+    
+    let credentials = BmcCredentials {
+        address: payload.bmc_address,
+        username: payload.bmc_username,
+        password: Some(payload.bmc_password), // Assume password is provided
+        bmc_type,
+    };
+    
+    match db::update_bmc_credentials(&id, &credentials).await {
+        Ok(true) => {
+            // Emit machine updated event
+            let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            
+            (StatusCode::OK, Html(format!(r#"
+                <div class="p-4 mb-4 text-sm text-green-700 bg-green-100 rounded-lg" role="alert">
+                    <span class="font-medium">Success!</span> BMC credentials updated.
+                </div>
+                <script>
+                    setTimeout(function() {{
+                        window.location.reload();
+                    }}, 1500);
+                </script>
+            "#))).into_response()
         },
-        _ => {
-            warn!("Cannot generate unknown IPXE script: {}", script_name); // Log the specific script name
-            Err(Error::NotFound) // Use the unit variant correctly
+        Ok(false) => {
+            let error_message = format!("Machine with ID {} not found", id);
+            (StatusCode::NOT_FOUND, Html(format!(r#"
+                <div class="p-4 mb-4 text-sm text-red-700 bg-red-100 rounded-lg" role="alert">
+                    <span class="font-medium">Error!</span> {}.
+                </div>
+            "#, error_message))).into_response()
         },
+        Err(e) => {
+            error!("Failed to update BMC credentials for machine {}: {}", id, e);
+            let error_message = format!("Database error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Html(format!(r#"
+                <div class="p-4 mb-4 text-sm text-red-700 bg-red-100 rounded-lg" role="alert">
+                    <span class="font-medium">Error!</span> {}.
+                </div>
+            "#, error_message))).into_response()
+        }
     }
 }
 
-fn create_streaming_response(
-    stream: ReceiverStream<Result<Bytes, Error>>,
-    content_type: &str,
-    content_length: Option<u64>,
-    content_range: Option<String>
-) -> Response {
-    // Map the stream from Result<Bytes> to Result<Frame<Bytes>, BoxError>
-    let mapped_stream = stream.map(|result| {
-        match result {
-            Ok(bytes) => {
-                // Removed check for empty EOF marker
-                // Simply map non-empty bytes to a data frame
-                Ok(Frame::data(bytes))
-            },
-            Err(e) => Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>),
+// Handler to get the hostname edit form
+#[axum::debug_handler]
+async fn get_hostname_form(
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => {
+            let current_hostname = machine.hostname.unwrap_or_default();
+            // Use raw string literals to avoid escaping issues
+            let html = format!(
+                r###"
+                <div class="sm:flex sm:items-start">
+                    <div class="mt-3 text-center sm:mt-0 sm:text-left w-full">
+                        <h3 class="text-base font-semibold leading-6 text-gray-900">
+                            Update Machine Hostname
+                        </h3>
+                        <div class="mt-2">
+                            <form hx-post="{}/machines/{}/hostname" hx-target="#hostname-modal">
+                                <label for="hostname" class="block text-sm font-medium text-gray-700">Hostname</label>
+                                <input type="text" name="hostname" id="hostname" value="{}" class="mt-1 block w-full rounded-md border-gray-300 shadow-sm focus:border-indigo-500 focus:ring-indigo-500 sm:text-sm" placeholder="Enter hostname">
+                                <div class="mt-5 sm:mt-4 sm:flex sm:flex-row-reverse">
+                                    <button type="submit" class="inline-flex w-full justify-center rounded-md bg-indigo-600 px-3 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-500 sm:ml-3 sm:w-auto">
+                                        Update
+                                    </button>
+                                    <button type="button" class="mt-3 inline-flex w-full justify-center rounded-md bg-white px-3 py-2 text-sm font-semibold text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 hover:bg-gray-50 sm:mt-0 sm:w-auto" onclick="document.getElementById('hostname-modal').classList.add('hidden')">
+                                        Cancel
+                                    </button>
+                                </div>
+                            </form>
+                        </div>
+                    </div>
+                </div>
+                "###,
+                crate::base_path::get(), id, current_hostname
+            );
+            
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/html")], html)
+        },
+        Ok(None) => {
+            let error_html = format!(
+                r###"<div class="p-4 text-red-500">Machine with ID {} not found</div>"###,
+                id
+            );
+            (StatusCode::NOT_FOUND, [(axum::http::header::CONTENT_TYPE, "text/html")], error_html)
+        },
+        Err(e) => {
+            let error_html = format!(
+                r###"<div class="p-4 text-red-500">Error: {}</div>"###,
+                e
+            );
+            (StatusCode::INTERNAL_SERVER_ERROR, [(axum::http::header::CONTENT_TYPE, "text/html")], error_html)
         }
-    });
-    
-    // Create a stream body with explicit end signal
-    let body = StreamBody::new(mapped_stream);
-    
-    // Determine status code based on whether it's a partial response
-    let status_code = if content_range.is_some() {
-        StatusCode::PARTIAL_CONTENT
-    } else {
-        StatusCode::OK
-    };
-    
-    // Start building the response
-    let mut builder = Response::builder()
-        .status(status_code)
-        .header(axum::http::header::CONTENT_TYPE, content_type)
-        // Always accept ranges
-        .header(axum::http::header::ACCEPT_RANGES, "bytes")
-        // Always set no compression
-        .header(axum::http::header::CONTENT_ENCODING, "identity");
+    }
+}
 
-    if let Some(length) = content_length {
-        // If Content-Length is known, set it and DO NOT use chunked encoding.
-        // This applies to both 200 OK and 206 Partial Content.
-        builder = builder.header(axum::http::header::CONTENT_LENGTH, length.to_string());
-    } else {
-        // Only use chunked encoding if length is truly unknown (should typically only be for 200 OK).
-        // It's an error to have a 206 response without Content-Length.
-        if status_code == StatusCode::OK { 
-            builder = builder.header(axum::http::header::TRANSFER_ENCODING, "chunked");
-        } else {
-            // This case (206 without Content-Length) ideally shouldn't happen with our logic.
-            // Log a warning if it does.
-            warn!("Attempting to create 206 response without Content-Length!");
-        }
+#[derive(Deserialize)]
+pub struct IpxeScriptQuery {
+    // Set once a boot has moved onto the machine's preferred NIC, so a fallback NIC isn't bounced again
+    #[serde(default)]
+    switched: bool,
+}
+
+// Handler for initial iPXE script generation (DHCP points here)
+// Determines whether to chain to HookOS or the Dragonfly Agent
+pub async fn ipxe_script(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(mac): Path<String>,
+    axum::extract::Query(query): axum::extract::Query<IpxeScriptQuery>,
+) -> Response {
+    if !mac.contains(':') || mac.split(':').count() != 6 {
+        warn!("Received invalid MAC format in iPXE request: {}", mac);
+        return (StatusCode::BAD_REQUEST, "Invalid MAC Address Format").into_response();
     }
-    
-    // Include Content-Range if it's a partial response
-    if let Some(range_header_value) = content_range {
-        builder = builder.header(axum::http::header::CONTENT_RANGE, range_header_value);
+
+    info!("Generating initial iPXE script for MAC: {}", mac);
+
+    // Remember whether this machine came in through HTTP Boot, for boot rules
+    let boot_method = crate::http_boot::boot_method_for(request_ip(&headers, addr));
+    if let Err(e) = db::record_boot_method(&mac, boot_method).await {
+        warn!("Failed to record boot method for MAC {}: {}", mac, e);
     }
-    
-    // Build the final response
-    builder.body(Body::new(body))
-        .unwrap_or_else(|_| {
-            Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::new(Empty::new()))
-                .unwrap()
-        })
-}
 
+    // Read required base URL from environment variable
+    let base_url = match crate::config::base_url() {
+        Some(url) => url,
+        None => {
+            error!("CRITICAL: server.base_url (DRAGONFLY_BASE_URL) is not set. iPXE booting requires this configuration.");
+            let error_response = ErrorResponse {
+                error: "Configuration Error".to_string(),
+                message: "Server is missing required DRAGONFLY_BASE_URL configuration.".to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+        }
+    };
 
-async fn read_file_as_stream(
-    path: &StdPath,
-    range_header: Option<&HeaderValue>, // Add parameter for Range header
-    state: Option<&AppState>, // Add optional state for event emission
-    machine_id: Option<Uuid> // Add optional machine ID for tracking
-) -> Result<(ReceiverStream<Result<Bytes, Error>>, Option<u64>, Option<String>), Error> { // Return size and Content-Range
-    info!("[STREAM_READ] Beginning read_file_as_stream for path: {}, range: {:?}, machine_id: {:?}", 
-          path.display(), range_header.map(|h| h.to_str().unwrap_or("invalid")), machine_id);
+    // Machines rejected at onboarding are sent back to their next boot device
+    if crate::onboarding::boot_refused(&mac).await {
+        info!("MAC {} was rejected at onboarding, not booting it", mac);
+        let script = "#!ipxe\necho This machine was not approved to provision from Dragonfly\nexit\n";
+        return (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response();
+    }
 
-    let mut file = fs::File::open(path).await.map_err(|e| Error::Internal(format!("Failed to open file {}: {}", path.display(), e)))?; // Added mut back
-    let (tx, rx) = mpsc::channel::<Result<Bytes, Error>>(32);
-    let path_buf = path.to_path_buf();
-    
-    // Get total file size
-    let metadata = fs::metadata(path).await.map_err(|e| Error::Internal(format!("Failed to get metadata {}: {}", path.display(), e)))?;
-    let total_size = metadata.len();
-    
-    // Get file name for progress tracking
-    let file_name = path.file_name()
-                        .and_then(|name| name.to_str())
-                        .map(String::from);
-    
-    let (start, _end, response_length, content_range_header) = // Marked end as unused
-        if let Some(range_val) = range_header {
-            if let Ok(range_str) = range_val.to_str() {
-                if let Some((start, end)) = parse_range_header(range_str, total_size, file_name.as_deref(), state).await {
-                    let length = end - start + 1;
-                    let content_range = format!("bytes {}-{}/{}", start, end, total_size);
-                    // info!("Serving range request: {} for file {}", content_range, path.display()); // Commented out log
-                    (start, end, length, Some(content_range))
-                } else {
-                    warn!("Invalid Range header format: {}", range_str);
-                    // Invalid range, serve the whole file
-                    (0, total_size.saturating_sub(1), total_size, None)
+    // A machine's preferred boot NICs are known to it too, even if it registered with another
+    match crate::boot_interface::machine_for_mac(&mac).await {
+        Ok(Some(machine)) => {
+            // Multi-homed machines move onto their preferred NIC before anything else
+            if !query.switched {
+                match crate::boot_interface::for_machine(&machine.id).await {
+                    Ok(Some(interfaces)) if !interfaces.boot_mac.eq_ignore_ascii_case(&mac) => {
+                        info!("Known MAC {}, switching to boot interface {}", mac, interfaces.boot_mac);
+                        let script = interfaces.switch_script(&base_url, &mac);
+                        return (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response();
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to check boot interfaces for MAC {}: {}", mac, e),
                 }
-            } else {
-                warn!("Invalid Range header value (not UTF-8)");
-                // Invalid range, serve the whole file
-                (0, total_size.saturating_sub(1), total_size, None)
             }
-        } else {
-            // No range header, serve the whole file
-            (0, total_size.saturating_sub(1), total_size, None)
-        };
-
-    let response_content_length = Some(response_length);
-    let content_range_header_clone = content_range_header.clone(); // Clone for the task
-    // Clone state and machine_id needed for the background task *before* spawning
-    // Ensures owned values are moved into the async block, avoiding lifetime issues.
-    let task_state_owned = state.cloned(); // Creates Option<AppState>
-    let task_machine_id_copied = machine_id; // Copies Option<Uuid>
+            // Machines on trunked ports move onto their provisioning VLAN before anything else
+            let vlan = match crate::vlan::for_machine(&machine).await {
+                Ok(vlan) => vlan,
+                Err(e) => {
+                    error!("Failed to look up VLAN for MAC {}, booting untagged: {}", mac, e);
+                    None
+                }
+            };
+            let with_vlan = |script: String| match &vlan {
+                Some(vlan) => crate::vlan::wrap_script(&script, vlan.vlan_id),
+                None => script,
+            };
 
-    tokio::spawn(async move {
-        // Handle Range requests differently: read the whole range at once
-        if content_range_header_clone.is_some() { // Use the clone
-            if start > 0 {
-                if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
-                    error!("Failed to seek file {}: {}", path_buf.display(), e);
-                    let _ = tx.send(Err(Error::Internal(format!("File seek error: {}", e)))).await;
-                    return;
+            // A pending diagnostics boot is served once, then the machine boots normally again
+            match db::take_pending_diagnostic_boot(&machine.id).await {
+                Ok(Some(boot)) => {
+                    if let Some(target) = crate::diagnostic_boot::DiagnosticTarget::parse(&boot.target) {
+                        info!("Known MAC {}, serving '{}' diagnostics boot", mac, boot.target);
+                        let script = with_vlan(target.render_script(&base_url));
+                        return (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response();
+                    }
+                    warn!("Ignoring diagnostics boot with unknown target '{}' for MAC {}", boot.target, mac);
                 }
+                Ok(None) => {}
+                Err(e) => error!("Failed to check diagnostics boots for MAC {}: {}", mac, e),
             }
-            
-            // Allocate buffer for the exact range size
-            let mut buffer = Vec::with_capacity(response_length as usize); // Use with_capacity
-            
-            // Create a reader limited to the exact range size
-            let mut limited_reader = file.take(response_length);
-            
-            // Read the exact range using the limited reader
-            match limited_reader.read_to_end(&mut buffer).await {
-                Ok(_) => {
-                    // Track progress for range requests too
-                    // For range requests, we use the start offset as an indicator of download progress
-                    if let (Some(state_ref), Some(machine_id_captured)) = (&task_state_owned, task_machine_id_copied) {
-                        if total_size > 0 {
-                            // Use start position + current range size as effective progress indicator
-                            let bytes_read = buffer.len() as u64;
-                            let effective_progress = start + bytes_read;
-                            
-                            info!("[RANGE_READ] Range request: start={}, bytes_read={}, total_size={}, effective_progress={}",
-                                  start, bytes_read, total_size, effective_progress);
-                                  
-                            // Clone state for progress tracking
-                            let owned_state = state_ref.clone();
-                            
-                            // Spawn progress tracking in a separate task
-                            tokio::spawn(async move {
-                                track_download_progress(Some(machine_id_captured), effective_progress, total_size, owned_state).await;
-                            });
-                        }
-                    }
-                
-                    // Send the complete range as a single chunk
-                    if tx.send(Ok(Bytes::from(buffer))).await.is_err() {
-                        warn!("Client stream receiver dropped for file {} while sending range", path_buf.display());
+            // A non-default boot profile in the desired state replaces the usual flow
+            match db::get_desired_state(&machine.id).await {
+                Ok(Some(desired)) => {
+                    if let Some(script) = desired.boot_profile.render_script(&base_url) {
+                        info!("Known MAC {}, serving '{}' boot profile", mac, desired.boot_profile.as_str());
+                        let script = with_vlan(script);
+                        return (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response();
                     }
-                    // Task finishes, tx is dropped, stream closes.
-                },
-                Err(e) => {
-                    error!("Failed to read exact range for file {}: {}", path_buf.display(), e);
-                    let _ = tx.send(Err(Error::Internal(format!("File read_exact error: {}", e)))).await;
                 }
+                Ok(None) => {}
+                Err(e) => error!("Failed to check desired boot profile for MAC {}: {}", mac, e),
             }
-        } else {
-            // Original streaming logic for full file requests
-            let mut buffer = vec![0; 65536]; // 64KB buffer
-            let mut remaining = response_length; // For full file, response_length == total_size
-            let mut total_bytes_sent: u64 = 0;
-
-            while remaining > 0 {
-                let read_size = std::cmp::min(remaining as usize, buffer.len());
-                match file.read(&mut buffer[..read_size]).await {
-                    Ok(0) => {
-                        //info!("Reached EOF while serving file {} (remaining: {} bytes)", path_buf.display(), remaining);
-                        break; // EOF reached
-                    },
-                    Ok(n) => { // Handles n > 0
-                        let chunk = Bytes::copy_from_slice(&buffer[0..n]);
-                        remaining -= n as u64;
-                        total_bytes_sent += n as u64; // Add this line to update total bytes sent!
+            // Ephemeral machines boot their live image every time instead of installing
+            match crate::ephemeral::boot_script(&machine, &base_url).await {
+                Ok(Some(script)) => {
+                    info!("Known MAC {}, serving ephemeral live boot", mac);
+                    let script = with_vlan(script);
+                    return (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response();
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to check ephemeral mode for MAC {}: {}", mac, e),
+            }
+            // Known machine: Chain to Dragonfly's OS installation hook script (hookos.ipxe)
+            info!("Known MAC {}, chaining to HookOS script", mac);
+            if let Err(e) = db::record_hookos_boot(&machine.id, crate::stack::served_hook_version().await.as_deref()).await {
+                warn!("Failed to record HookOS version for machine {}: {}", machine.id, e);
+            }
+            // Tinkerbell knows the machine by its registered MAC, whichever NIC it booted from
+            let worker_id = if machine.mac_address.eq_ignore_ascii_case(&mac) {
+                String::new()
+            } else {
+                format!("set dragonfly_worker_id {}\n", machine.mac_address)
+            };
+            let script = with_vlan(format!("#!ipxe\n{}chain {}/ipxe/hookos.ipxe", worker_id, base_url));
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response()
+        },
+        Ok(None) if crate::boot_menu::enabled() => {
+            // Unknown machine with the menu enabled: let whoever is at the console choose
+            info!("Unknown MAC {}, serving interactive boot menu", mac);
+            let script = crate::boot_menu::render_menu(&base_url, &mac, crate::boot_menu::memtest_available());
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response()
+        },
+        Ok(None) => {
+            // Unknown machine: Chain to the Dragonfly agent script
+            info!("Unknown MAC {}, chaining to Dragonfly Agent iPXE script", mac);
+            let script = format!("#!ipxe\nchain {}/ipxe/dragonfly-agent.ipxe", base_url);
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response()
+        },
+        Err(e) => {
+            error!("Database error while looking up MAC {}: {}", mac, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
 
-                        // ADDED LOG: Log bytes read and total sent
-                        debug!(path = %path_buf.display(), bytes_read = n, total_bytes_sent = total_bytes_sent, total_size = total_size, "[STREAM_READ_LOOP] Read chunk");
+fn grub_config_response(config: Option<String>) -> Response {
+    match config {
+        Some(config) => (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], config).into_response(),
+        None => Error::Config("DRAGONFLY_BASE_URL is not a valid URL".to_string()).into_response(),
+    }
+}
 
-                        // Use the owned/copied state and machine_id captured by the 'move' closure
-                        // Match against the Option<&AppState> and Option<Uuid> directly
-                        if let (Some(state_ref), Some(machine_id_captured)) = (&task_state_owned, task_machine_id_copied) {
-                            if total_size > 0 { // Avoid division by zero
-                                debug!("[PROGRESS_DEBUG][CACHE_READ] Calling track_download_progress (machine_id: {}, sent: {}, total: {})", machine_id_captured, total_bytes_sent, total_size);
-                                // Clone the AppState here to get an owned value for the inner task.
-                                let owned_state = state_ref.clone(); // <-- Add this line
-                                // Spawn progress tracking in a separate task to avoid blocking the stream
-                                tokio::spawn(async move {
-                                    // Pass the already owned AppState.
-                                    track_download_progress(Some(machine_id_captured), total_bytes_sent, total_size, owned_state).await; // <-- Use owned_state here
-                                });
-                            } // else: Skipping progress track because total_size is 0 (logged elsewhere if needed)
-                        } // else: Skipping progress track because machine_id or state is missing
+// Config signed GRUB fetches first on Secure Boot machines; hands over to the per-MAC config
+pub async fn grub_bootstrap_config() -> Response {
+    let base_url = match crate::config::base_url() {
+        Some(url) => url,
+        None => {
+            error!("CRITICAL: server.base_url (DRAGONFLY_BASE_URL) is not set. Secure Boot requires this configuration.");
+            return Error::Config("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string()).into_response();
+        }
+    };
+    grub_config_response(crate::secure_boot::bootstrap_config(&base_url))
+}
 
-                        if tx.send(Ok(chunk)).await.is_err() {
-                            warn!("Client stream receiver dropped for file {}", path_buf.display());
-                            break; // Exit loop if receiver is gone
-                        }
-                    },
-                    Err(e) => {
-                        let err = Error::Internal(format!("File read error for {}: {}", path_buf.display(), e));
-                        if tx.send(Err(err)).await.is_err() {
-                            warn!("Client stream receiver dropped while sending error for {}", path_buf.display());
-                        }
-                        break; // Exit loop on read error
-                    }
-                }
-            }
+// Secure Boot counterpart of ipxe_script: HookOS for known machines, the agent otherwise
+pub async fn grub_config(Path(mac): Path<String>) -> Response {
+    let mac = mac.to_lowercase();
+    if !mac.contains(':') || mac.split(':').count() != 6 {
+        warn!("Received invalid MAC format in GRUB config request: {}", mac);
+        return (StatusCode::BAD_REQUEST, "Invalid MAC Address Format").into_response();
+    }
+
+    let base_url = match crate::config::base_url() {
+        Some(url) => url,
+        None => {
+            error!("CRITICAL: server.base_url (DRAGONFLY_BASE_URL) is not set. Secure Boot requires this configuration.");
+            return Error::Config("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string()).into_response();
         }
-        
-        // Task finishes, tx is dropped, stream closes.
-        debug!("Finished streaming task for: {}", path_buf.display());
-    });
-    
-    // Return the stream, the length of the *content being sent*, and the *original* Content-Range header string
-    Ok((tokio_stream::wrappers::ReceiverStream::new(rx), response_content_length, content_range_header))
+    };
+
+    let target = match db::get_machine_by_mac(&mac).await {
+        Ok(Some(_)) => {
+            info!("Known MAC {}, serving HookOS GRUB config", mac);
+            crate::secure_boot::GrubTarget::HookOs(tinkerbell_boot_params(&base_url))
+        },
+        Ok(None) => {
+            info!("Unknown MAC {}, serving Dragonfly Agent GRUB config", mac);
+            crate::secure_boot::GrubTarget::Agent
+        },
+        Err(e) => {
+            error!("Database error while looking up MAC {}: {}", mac, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    grub_config_response(crate::secure_boot::render_config(&base_url, &mac, &target))
 }
 
-// Serve iPXE artifacts (scripts and binaries)
-// Function to serve an iPXE artifact file from a configured directory
-pub async fn serve_ipxe_artifact(
+// Stable per-architecture URL UEFI HTTP Boot firmware is pointed at
+pub async fn http_boot_file(
     headers: HeaderMap,
-    Path(requested_path): Path<String>,
-    State(state): State<AppState>, // Add AppState to access event manager and client_ip
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(arch): Path<String>,
+    State(state): State<AppState>,
 ) -> Response {
-    // Define constants for directories and URLs
-    const DEFAULT_ARTIFACT_DIR: &str = "/var/lib/dragonfly/ipxe-artifacts";
-    const ARTIFACT_DIR_ENV_VAR: &str = "DRAGONFLY_IPXE_ARTIFACT_DIR";
-    const ALLOWED_IPXE_SCRIPTS: &[&str] = &["hookos", "dragonfly-agent"]; // Define allowlist
-    const AGENT_APKOVL_PATH: &str = "/var/lib/dragonfly/ipxe-artifacts/dragonfly-agent/localhost.apkovl.tar.gz";
-    const AGENT_BINARY_URL: &str = "https://github.com/Zorlin/dragonfly/raw/refs/heads/main/dragonfly-agent-musl"; // TODO: Make configurable
-    
-    // --- Get Machine ID from Client IP --- 
-    let client_ip = state.client_ip.lock().await.clone();
-    let machine_id = if let Some(ip) = &client_ip {
-        // ADDED LOG: Log the IP being looked up
-        info!("[PROGRESS_DEBUG] Looking up machine by IP: {}", ip);
-        match db::get_machine_by_ip(ip).await {
-            Ok(Some(machine)) => {
-                // ADDED LOG: Log successful lookup
-                info!("[PROGRESS_DEBUG] Found machine ID {} for IP {}", machine.id, ip);
-                Some(machine.id)
-            },
-            Ok(None) => {
-                // Changed to INFO for visibility
-                info!("[PROGRESS_DEBUG] No machine found for IP {} requesting artifact {}", ip, requested_path);
-                None
-            },
-            Err(e) => {
-                // Changed to INFO for visibility
-                info!("[PROGRESS_DEBUG] DB error looking up machine by IP {}: {}", ip, e);
-                None
-            }
+    let Some(arch) = crate::http_boot::BootArch::parse(&arch) else {
+        return (StatusCode::NOT_FOUND, "No HTTP Boot file for this architecture").into_response();
+    };
+    let client = request_ip(&headers, addr);
+    info!("Serving {} HTTP Boot file to {}", arch.as_str(), client);
+    crate::http_boot::note_boot_file_fetch(client);
+    serve_ipxe_artifact(headers, ConnectInfo(addr), Path(arch.artifact_path().to_string()), State(state)).await
+}
+
+// Record a boot menu selection and hand back the script that carries it out
+pub async fn boot_menu_selection(
+    State(state): State<AppState>,
+    Path((mac, choice)): Path<(String, String)>,
+) -> Response {
+    if !mac.contains(':') || mac.split(':').count() != 6 {
+        warn!("Received invalid MAC format in boot menu selection: {}", mac);
+        return (StatusCode::BAD_REQUEST, "Invalid MAC Address Format").into_response();
+    }
+    let Some(choice) = crate::boot_menu::MenuChoice::parse(&choice) else {
+        warn!("Unknown boot menu choice '{}' from MAC {}", choice, mac);
+        return (StatusCode::BAD_REQUEST, "Unknown boot menu choice").into_response();
+    };
+
+    let base_url = match crate::config::base_url() {
+        Some(url) => url,
+        None => {
+            error!("CRITICAL: server.base_url (DRAGONFLY_BASE_URL) is not set. iPXE booting requires this configuration.");
+            let error_response = ErrorResponse {
+                error: "Configuration Error".to_string(),
+                message: "Server is missing required DRAGONFLY_BASE_URL configuration.".to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
         }
-    } else {
-        // Changed to INFO for visibility
-        info!("[PROGRESS_DEBUG] Client IP not found in state for artifact request {}", requested_path);
-        None
     };
-    // ----------------------------------
 
-    // Get the base directory from env var or use default
-    let base_dir = env::var(ARTIFACT_DIR_ENV_VAR)
-        .unwrap_or_else(|_| {
-            debug!("{} not set, using default: {}", ARTIFACT_DIR_ENV_VAR, DEFAULT_ARTIFACT_DIR);
-            DEFAULT_ARTIFACT_DIR.to_string()
-        });
-    let base_path = PathBuf::from(base_dir);
-    
-    // Path sanitization - Allow '/' but prevent '..'
-    if requested_path.contains("..") || requested_path.contains('\\') {
-        warn!("Attempted iPXE artifact path traversal using '..' or '\': {}", requested_path);
-        return (StatusCode::BAD_REQUEST, "Invalid artifact path").into_response();
+    info!("Boot menu selection from MAC {}: {}", mac, choice.as_str());
+    // Booting matters more than the record; carry on if it can't be saved
+    if let Err(e) = db::save_boot_selection(&mac, &choice.as_str()).await {
+        error!("Failed to record boot menu selection for MAC {}: {}", mac, e);
     }
-    
-    let artifact_path = base_path.join(&requested_path);
+    let _ = state.event_manager.send(format!("boot_menu_selection:{}:{}", mac.to_lowercase(), choice.as_str()));
 
-    // --- Serve from Cache First ---
-    if artifact_path.exists() {
-        info!("[SERVE_ARTIFACT] Cached artifact exists at {}, will use read_file_as_stream", artifact_path.display());
-        // Determine content type AND if it's an IPXE script
-        let (content_type, is_ipxe) = if requested_path.ends_with(".ipxe") {
-            ("text/plain", true)
-        } else if requested_path.ends_with(".tar.gz") {
-            ("application/gzip", false) // Ensure this returns a tuple
-        } else {
-            ("application/octet-stream", false) // Ensure this returns a tuple
-        };
+    let script = crate::boot_menu::render_choice(&base_url, &choice);
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], script).into_response()
+}
 
-        // Allowlist check for IPXE scripts from cache
-        if is_ipxe { // Check the boolean flag
-            let stem = StdPath::new(&requested_path).file_stem().and_then(|s| s.to_str());
-            if let Some(stem_str) = stem {
-                if !ALLOWED_IPXE_SCRIPTS.contains(&stem_str) {
-                    warn!("Attempt to serve non-allowlisted IPXE script stem from cache: {}", stem_str);
-                    return (StatusCode::NOT_FOUND, "iPXE Script Not Found").into_response();
-                }
-            } else {
-                 warn!("Could not extract stem from IPXE script path: {}", requested_path);
-                 return (StatusCode::BAD_REQUEST, "Invalid IPXE Script Path").into_response();
+#[axum::debug_handler]
+async fn delete_machine(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    // With the two-person rule on, deleting waits for someone else to approve it
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => {
+            let action = crate::action_approvals::DestructiveAction::DeleteMachines { machine_ids: vec![id] };
+            if let Some(response) = held_for_approval(&auth_session, action, action_summary("Delete", &[&machine])).await {
+                return response;
             }
         }
-        
-        // Serve allowed script or binary artifact from cache using streaming
-        // Pass the potentially found machine_id for progress tracking
-        match read_file_as_stream(&artifact_path, headers.get(axum::http::header::RANGE), Some(&state), machine_id).await {
-            Ok((stream, file_size, content_range)) => {
-                info!("Streaming cached artifact from disk: {}", requested_path);
-                return create_streaming_response(stream, content_type, file_size, content_range); // Pass content_range
-            },
-            Err(e) => {
-                error!("Failed to stream cached iPXE artifact: {}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, "Error reading iPXE artifact").into_response();
-            }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Failed to look up machine {} before deleting it: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
         }
-    } else {
-        // --- File Not Found: Generate or Download --- 
-        info!("[SERVE_ARTIFACT] Artifact {} not found locally, will need to generate or download", requested_path);
-        
-        // FIRST check if it is the specific apkovl path that needs generation
-        // Compare against the RELATIVE path expected from the URL
-        if requested_path == "dragonfly-agent/localhost.apkovl.tar.gz" {
-            // --- Special Case: Generate apkovl on demand ---
-            // Use the full absolute path for generation logic
-            let generation_target_path = PathBuf::from(AGENT_APKOVL_PATH);
-            info!("Generating {} on demand...", generation_target_path.display());
+    }
 
-            let base_url = match env::var("DRAGONFLY_BASE_URL") {
-                Ok(url) => url,
-                Err(_) => {
-                    error!("Cannot generate apkovl: DRAGONFLY_BASE_URL environment variable is not set.");
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Server configuration error for apkovl generation").into_response();
+    remove_machine(&state, &id).await
+}
+
+// Delete a machine from Dragonfly and Tinkerbell
+async fn remove_machine(state: &AppState, id: &Uuid) -> Response {
+    let id = *id;
+    info!("Request to delete machine: {}", id);
+
+    // Get the machine to find its MAC address
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => {
+            // Delete from Tinkerbell
+            let mac_address = machine.mac_address.replace(":", "-").to_lowercase();
+            
+            // Queued if Kubernetes is unreachable, and removed once it's back
+            let tinkerbell_result = match crate::tinkerbell_outbox::delete_hardware(&*state.tinkerbell, &id, &mac_address).await {
+                Ok(outcome) => {
+                    if outcome == crate::tinkerbell_outbox::Outcome::Done {
+                        info!("Successfully deleted machine from Tinkerbell: {}", mac_address);
+                    }
+                    Some(outcome)
+                },
+                Err(e) => {
+                    warn!("Failed to delete machine from Tinkerbell: {}", e);
+                    None
                 }
             };
 
-            match generate_agent_apkovl(&generation_target_path, &base_url, AGENT_BINARY_URL).await {
-                Ok(()) => {
-                    info!("Successfully generated {}, now serving...", generation_target_path.display());
-                    // Serve the newly generated file (no range needed here as it was just created)
-                    match read_file_as_stream(&generation_target_path, None, None, None).await { 
-                        Ok((stream, file_size, _)) => {
-                            return create_streaming_response(stream, "application/gzip", file_size, None); 
-                        },
-                        Err(e) => {
-                            error!("Failed to stream newly generated apkovl {}: {}", generation_target_path.display(), e);
-                            return (StatusCode::INTERNAL_SERVER_ERROR, "Error reading newly generated apkovl").into_response();
-                        }
-                    }
+            // Delete from database
+            match db::delete_machine(&id).await {
+                Ok(true) => {
+                    let message = match tinkerbell_result {
+                        Some(crate::tinkerbell_outbox::Outcome::Done) => "Machine successfully deleted from Dragonfly and Tinkerbell.",
+                        Some(crate::tinkerbell_outbox::Outcome::Queued) => "Machine deleted from Dragonfly. Kubernetes is unreachable, so it will be removed from Tinkerbell once the cluster is back.",
+                        None => "Machine deleted from Dragonfly but there was an issue removing it from Tinkerbell.",
+                    };
+                    
+                    // Emit machine deleted event
+                    let _ = state.event_manager.send(format!("machine_deleted:{}", id));
+                    
+                    (StatusCode::OK, Json(json!({ "success": true, "message": message }))).into_response()
+                },
+                Ok(false) => {
+                    (StatusCode::NOT_FOUND, Json(json!({ "error": "Machine not found in database" }))).into_response()
                 },
                 Err(e) => {
-                    error!("Failed to generate {}: {}", generation_target_path.display(), e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate {}: {}", generation_target_path.display(), e)).into_response();
+                    error!("Failed to delete machine from database: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("Database error: {}", e) }))).into_response()
                 }
             }
-        } 
-        // NEXT check if it's a generic .ipxe script that needs generation
-        else if requested_path.ends_with(".ipxe") {
-            // --- Generate iPXE scripts on the fly ---
-            // Use the relative path for script generation lookup
-            match generate_ipxe_script(&requested_path).await {
-                Ok(script) => {
-                    info!("Generated {} script dynamically.", requested_path);
-                    // Cache in background using the full artifact_path
-                    let path_clone = artifact_path.clone(); 
-                    let script_clone = script.clone();
-                    let requested_path_clone = requested_path.clone(); // Clone for the task
-                    tokio::spawn(async move {
-                        // Ensure parent directory exists before writing
-                        if let Some(parent) = path_clone.parent() {
-                             if let Err(e) = fs::create_dir_all(parent).await {
-                                 warn!("Failed to create directory for caching {}: {}", requested_path_clone, e);
-                                 return; 
-                             }
-                         }
-                        if let Err(e) = fs::write(&path_clone, &script_clone).await {
-                             warn!("Failed to cache generated {} script: {}", requested_path_clone, e);
-                        }
-                    });
-                    
-                    // For iPXE scripts, let's build our own response
-                    let content_length = script.len() as u64;
-                    
-                    // Create a response that's optimized for iPXE
-                    return Response::builder()
-                        .status(StatusCode::OK)
-                        .header(axum::http::header::CONTENT_TYPE, "text/plain")
-                        .header(axum::http::header::CONTENT_LENGTH, content_length.to_string())
-                        .header(axum::http::header::CONTENT_ENCODING, "identity") // No compression
-                        .body(Body::from(script))
-                        .unwrap_or_else(|_| {
-                            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response").into_response()
-                        });
-                },
-                Err(Error::NotFound { .. }) => {
-                    warn!("IPXE script {} not found or could not be generated.", requested_path);
-                    // Fall through to final 404
-                },
-                Err(e) => {
-                    // Other error during generation (e.g., missing env var)
-                    error!("Failed to generate {} script: {}", requested_path, e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate script: {}", e)).into_response();
-                }
-            }
-            // If we fall through here, it means generate_ipxe_script returned NotFound
+        },
+        Ok(None) => {
+            (StatusCode::NOT_FOUND, Json(json!({ "error": "Machine not found" }))).into_response()
+        },
+        Err(e) => {
+            error!("Error fetching machine for deletion: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("Database error: {}", e) }))).into_response()
         }
-        // FINALLY, assume it's a binary artifact to download/stream
-        else {
-            // --- Download/Stream Other Binary Artifacts ---
-            let remote_url = match requested_path.as_str() {
-                // Alpine Linux netboot artifacts for Dragonfly Agent
-                "dragonfly-agent/vmlinuz" => "https://dl-cdn.alpinelinux.org/alpine/latest-stable/releases/x86_64/netboot/vmlinuz-lts",
-                "dragonfly-agent/initramfs-lts" => "https://dl-cdn.alpinelinux.org/alpine/latest-stable/releases/x86_64/netboot/initramfs-lts",
-                "dragonfly-agent/modloop" => "https://dl-cdn.alpinelinux.org/alpine/latest-stable/releases/x86_64/netboot/modloop-lts",
-                // Ubuntu 22.04
-                "ubuntu/jammy-server-cloudimg-amd64.img" => "https://cloud-images.ubuntu.com/jammy/current/jammy-server-cloudimg-amd64.img",
-                // Ubuntu 24.04
-                "ubuntu/noble-server-cloudimg-amd64.img" => "https://cloud-images.ubuntu.com/noble/current/noble-server-cloudimg-amd64.img",
-                _ => {
-                    // If it wasn't an .ipxe script and not a known binary, it's unknown.
-                    warn!("Unknown artifact requested: {}", requested_path);
-                    return (StatusCode::NOT_FOUND, "Unknown iPXE artifact").into_response();
+    }
+}
+
+// Add this function to handle machine updates
+#[axum::debug_handler]
+async fn update_machine(
+    State(state): State<AppState>,
+    // Use AuthSession directly, not Option<AuthSession>
+    auth_session: AuthSession,
+    // Add ConnectInfo to get client IP
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<Uuid>,
+    Json(mut machine_payload): Json<Machine>,
+) -> Response {
+    let client_ip = addr.ip().to_string();
+    info!("Update request for machine {} from IP: {}", id, client_ip);
+
+    // Authorization Logic
+    // Check if an admin user is logged in
+    let is_admin = auth_session.user.is_some();
+
+    let authorized = if is_admin {
+        // Admin is always authorized
+        info!("Admin user authorized update for machine {}", id);
+        true
+    } else {
+        // Not an admin, check if it's the agent based on IP
+        info!("Request is not from an admin, checking IP for agent authorization...");
+        match db::get_machine_by_id(&id).await {
+            Ok(Some(stored_machine)) => {
+                if stored_machine.ip_address == client_ip {
+                    info!("Agent IP {} matches stored IP for machine {}. Authorizing update.", client_ip, id);
+                    true // IP matches, allow update
+                } else {
+                    warn!("Agent IP {} does NOT match stored IP {} for machine {}. Denying update.",
+                          client_ip, stored_machine.ip_address, id);
+                    false // IP mismatch
                 }
-            };
-            
-            // Use the efficient streaming download with caching for known artifacts
-            // Use artifact_path (full path) for caching
-            match stream_download_with_caching(
-                remote_url, 
-                &artifact_path, 
-                headers.get(axum::http::header::RANGE),
-                machine_id, // Pass the machine_id found via IP lookup
-                Some(&state)
-            ).await {
-                Ok((stream, content_length, content_range)) => {
-                    info!("Streaming artifact {} from remote source", requested_path);
-                    return create_streaming_response(stream, "application/octet-stream", content_length, content_range);
+            },
+            Ok(None) => {
+                warn!("Machine {} not found during IP authorization check.", id);
+                false // Machine not found
                 },
                 Err(e) => {
-                    error!("Failed to stream artifact {}: {}", requested_path, e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, format!("Error streaming artifact: {}", e)).into_response();
-                }
+                error!("Database error during IP authorization check for machine {}: {}", id, e);
+                false // Database error
             }
         }
+    };
 
-        // If code reaches here, it means an IPXE script was requested but generate_ipxe_script 
-        // returned NotFound, so return 404.
-        (StatusCode::NOT_FOUND, "Unknown or Ungeneratable IPXE Script").into_response()
+    if !authorized {
+        // Use 403 Forbidden for authorization failures
+        // (axum-login middleware handles 401 for missing authentication if configured)
+        return (StatusCode::FORBIDDEN, Json(json!({
+            "error": "Forbidden",
+            "message": "You are not authorized to update this machine."
+        }))).into_response();
     }
-}
-
-// Add this function after parse_range_header
-// Helper function to track and report image download progress
-async fn track_download_progress(
-    machine_id: Option<Uuid>, 
-    bytes_downloaded: u64, 
-    total_size: u64,
-    state: AppState // Changed from Option<&AppState> to AppState
-) {
-    info!(
-        machine_id = ?machine_id, 
-        bytes_downloaded = bytes_downloaded, 
-        total_size = total_size, 
-        "[PROGRESS_TRACK] CALLED track_download_progress with values: bytes_downloaded={}, total_size={}, machine_id={:?}",
-        bytes_downloaded, total_size, machine_id
-    );
 
-    debug!(
-        machine_id = ?machine_id, 
-        bytes_downloaded = bytes_downloaded, 
-        total_size = total_size, 
-        "[PROGRESS_DEBUG] Entering track_download_progress"
-    );
+    // --- Proceed with Update (if authorized) ---
+    
+    // Ensure the ID from the path matches the payload ID
+    if machine_payload.id != id {
+        return (StatusCode::BAD_REQUEST, Json(json!({
+            "error": "ID Mismatch",
+            "message": "The machine ID in the URL path does not match the ID in the request body."
+        }))).into_response();
+    }
 
-    if total_size == 0 {
-        // Changed to INFO
-        info!("[PROGRESS_DEBUG] Exiting track_download_progress early: total_size is 0");
-        return; // Skip progress for zero-sized files
+    if let Err(errors) = machine_payload.validate() {
+        return validation_error_response(errors);
     }
-    
-    let progress_float = (bytes_downloaded as f64 / total_size as f64) * 100.0;
-    let task_name = "stream image"; // TODO: Can we get the actual filename here?
-    
-    // If we have a machine ID, send task-specific event
-    if let Some(id) = machine_id {
-        debug!(machine_id = %id, progress = progress_float, task_name = task_name, "Updating DB progress");
-        // Update the machine's task progress in DB
-        if let Err(e) = db::update_installation_progress(
-            &id,
-            progress_float.min(100.0) as u8, // Convert to u8 for DB, clamped at 100
-            Some(task_name)
-        ).await {
-            warn!(machine_id = %id, error = %e, "Failed to update download progress in DB");
-        }
-        
-        // For real-time UI updates, emit a more detailed event with floating point precision
-        let task_progress_event = format!(
-            "task_progress:{}:{}:{:.3}:{}:{}",
-            id,                   // Machine ID
-            task_name,            // Task name 
-            progress_float,       // Floating point percentage (with 3 decimal precision)
-            bytes_downloaded,     // Current bytes
-            total_size            // Total bytes
-        );
-        
-        debug!(machine_id = %id, event = %task_progress_event, "Attempting to send task_progress event");
-        // Emit the detailed task progress event
-        if let Err(e) = state.event_manager.send(task_progress_event.clone()) { // Clone for logging
-            warn!(machine_id = %id, error = %e, "Failed to emit task_progress event: {}", task_progress_event);
+
+    // An adopted machine's agent still detects an "existing OS"; that OS is the one it was
+    // adopted with, so the machine stays managed
+    if !is_admin {
+        match db::get_adoption(&id).await {
+            Ok(Some(_)) => {
+                machine_payload.status = crate::adoption::effective_status(machine_payload.status);
+                match db::mark_adoption_connected(&id).await {
+                    Ok(true) => info!("Agent on adopted machine {} reported in", id),
+                    Ok(false) => {},
+                    Err(e) => warn!("Failed to record agent connection for adopted machine {}: {}", id, e),
+                }
+            },
+            Ok(None) => {},
+            Err(e) => warn!("Failed to check adoption for machine {}: {}", id, e),
         }
-        
-        // Also emit standard machine updated event for compatibility
-        // debug!(machine_id = %id, "Sending generic machine_updated event");
-        // let _ = state.event_manager.send(format!("machine_updated:{}", id));
     }
+
+    info!("Updating machine {} with full payload (Authorized by admin: {})", id, is_admin);
     
-    // Also send IP-based progress event for any HTTP requests
-    let client_ip_guard = state.client_ip.lock().await;
-    if let Some(client_ip) = client_ip_guard.as_ref() {
-        // Find machine by IP if possible (for cases where we don't have machine_id)
-        let ip_machine_id = if machine_id.is_none() {
-            match db::get_machine_by_ip(client_ip).await {
-                Ok(Some(machine)) => Some(machine.id),
-                _ => None,
+    // Set the updated_at timestamp before saving
+    machine_payload.updated_at = Utc::now();
+
+    // Call the updated db::update_machine function
+    match db::update_machine(&machine_payload).await {
+                Ok(true) => {
+            // The agent reporting in answers any pending redetection request
+            if !is_admin {
+                match db::clear_redetect_requested(&id).await {
+                    Ok(true) => info!("Machine {} reported its detected OS and hardware", id),
+                    Ok(false) => {},
+                    Err(e) => warn!("Failed to clear redetection request for machine {}: {}", id, e),
+                }
             }
-        } else {
-            machine_id
-        };
-        
-        // Emit IP-based progress event
-        let ip_progress_event_payload = serde_json::json!({ 
-            "ip": client_ip,
-            "progress": progress_float, // Send float
-            "bytes_downloaded": bytes_downloaded,
-            "total_size": total_size,
-            "file_name": task_name, // Still uses hardcoded "Stream image"
-            "machine_id": ip_machine_id
-        });
 
-        // Construct the event string
-        let ip_progress_event_string = format!("ip_download_progress:{}", ip_progress_event_payload.to_string());
+            // Emit machine updated event
+            let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            
+            // Return the updated machine object
+            (StatusCode::OK, Json(machine_payload)).into_response()
+                },
+                Ok(false) => {
+            // This case should ideally not happen if the ID check above passed
+            // but handle it just in case (e.g., race condition with deletion)
+            (StatusCode::NOT_FOUND, Json(json!({
+                "error": "Not Found",
+                "message": format!("Machine with ID {} not found during update attempt.", id)
+            }))).into_response()
+                },
+                Err(e) => {
+            error!("Failed to update machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+                "error": "Database Error",
+                "message": e.to_string()
+            }))).into_response()
+        }
+    }
+}
+
+// Handler to get the OS assignment form
+async fn get_machine_os(Path(id): Path<Uuid>) -> Response {
+    Html(format!(r#"
+        <div class="sm:flex sm:items-start">
+            <div class="mt-3 text-center sm:mt-0 sm:text-left w-full">
+                <h3 class="text-lg leading-6 font-medium text-gray-900">
+                    Assign Operating System
+                </h3>
+                <div class="mt-2">
+                    <form hx-post="{}/api/machines/{}/os" hx-swap="none" @submit="osModal = false">
+                        <div class="mt-4">
+                            <label for="os_choice" class="block text-sm font-medium text-gray-700">Operating System</label>
+                            <select
+                                id="os_choice"
+                                name="os_choice"
+                                class="mt-1 block w-full pl-3 pr-10 py-2 text-base border-gray-300 focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm rounded-md"
+                            >
+                                <option value="ubuntu-2204">Ubuntu 22.04</option>
+                                <option value="ubuntu-2404">Ubuntu 24.04</option>
+                                <option value="debian-12">Debian 12</option>
+                                <option value="proxmox">Proxmox VE</option>
+                                <option value="talos">Talos</option>
+                            </select>
+                        </div>
+                        <div class="mt-5 sm:mt-4 sm:flex sm:flex-row-reverse">
+                            <button
+                                type="submit"
+                                class="inline-flex w-full justify-center rounded-md bg-indigo-600 px-3 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-500 sm:ml-3 sm:w-auto"
+                            >
+                                Assign
+                            </button>
+                            <button
+                                type="button"
+                                class="mt-3 inline-flex w-full justify-center rounded-md bg-white px-3 py-2 text-sm font-semibold text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 hover:bg-gray-50 sm:mt-0 sm:w-auto"
+                                @click="osModal = false"
+                            >
+                                Cancel
+                            </button>
+                        </div>
+                    </form>
+                </div>
+            </div>
+        </div>
+    "#, crate::base_path::get(), id)).into_response()
+}
+
+// Handler to get the status update form 
+pub async fn get_machine_status(Path(id): Path<Uuid>) -> impl IntoResponse {
+    let html = format!(r#"
+        <div class="sm:flex sm:items-start">
+            <div class="mt-3 text-center sm:mt-0 sm:text-left w-full">
+                <h3 class="text-lg leading-6 font-medium text-gray-900">
+                    Update Machine Status
+                </h3>
+                <div class="mt-2">
+                    <form hx-post="{}/machines/{}/status" hx-swap="none" @submit="statusModal = false">
+                        <div class="mb-4">
+                            <label for="status" class="block text-sm font-medium text-gray-700">Status</label>
+                            <select name="status" id="status" class="mt-1 block w-full pl-3 pr-10 py-2 text-base border-gray-300 focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm rounded-md">
+                                <option value="Ready">Ready</option>
+                                <option value="AwaitingAssignment">Awaiting OS Assignment</option>
+                                <option value="InstallingOS">Installing OS</option>
+                                <option value="Error">Error</option>
+                            </select>
+                        </div>
+                        <div class="mt-5 sm:mt-6">
+                            <button type="submit" class="inline-flex justify-center w-full rounded-md border border-transparent shadow-sm px-4 py-2 bg-indigo-600 text-base font-medium text-white hover:bg-indigo-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-indigo-500 sm:text-sm">
+                                Update Status
+                            </button>
+                        </div>
+                    </form>
+                </div>
+            </div>
+        </div>
+    "#, crate::base_path::get(), id);
+
+    Html(html)
+}
+
+#[derive(Deserialize)]
+struct LogStreamQuery {
+    level: Option<String>,
+    machine: Option<Uuid>,
+}
+
+// How often workflow state is re-read when streaming a machine's logs
+const WORKFLOW_LOG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Summary of a workflow that only changes when something worth printing happens
+fn workflow_signature(info: &crate::tinkerbell::WorkflowInfo) -> String {
+    let tasks: Vec<String> = info.tasks.iter().map(|t| format!("{}={}", t.name, t.status)).collect();
+    format!("{}|{:?}|{}|{}", info.state, info.current_action, info.progress, tasks.join(","))
+}
+
+// Log lines that mention any of the machine's identifiers belong to it; no keys means no filter
+fn record_matches_machine(record: &crate::logs::LogRecord, machine_keys: &[String]) -> bool {
+    machine_keys.is_empty() || machine_keys.iter().any(|k| record.message.contains(k.as_str()))
+}
+
+#[derive(Deserialize)]
+struct LogBufferQuery {
+    level: Option<String>,
+    since: Option<DateTime<Utc>>,
+    after_seq: Option<u64>,
+}
+
+// Recent log records from the in-memory buffer, oldest first
+async fn api_get_logs(
+    auth_session: AuthSession,
+    axum::extract::Query(query): axum::extract::Query<LogBufferQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let min_level = match query.level.as_deref() {
+        None => tracing::Level::INFO,
+        Some(level) => match crate::logs::parse_level(level) {
+            Some(level) => level,
+            None => {
+                return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                    error: "Bad Request".to_string(),
+                    message: format!("Unknown log level '{}'", level),
+                })).into_response();
+            }
+        },
+    };
+
+    Json(crate::logs::recent(&min_level, query.since, query.after_seq)).into_response()
+}
+
+// Stream server log records (and optionally one machine's workflow state) as SSE.
+// Sends a `ready` event once any backlog has been delivered so clients not
+// following the stream know when to stop.
+async fn api_stream_logs(
+    auth_session: AuthSession,
+    axum::extract::Query(query): axum::extract::Query<LogStreamQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let min_level = match query.level.as_deref() {
+        None => tracing::Level::INFO,
+        Some(level) => match crate::logs::parse_level(level) {
+            Some(level) => level,
+            None => {
+                return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                    error: "Bad Request".to_string(),
+                    message: format!("Unknown log level '{}'", level),
+                })).into_response();
+            }
+        },
+    };
+
+    let machine = match query.machine {
+        Some(id) => match db::get_machine_by_id(&id).await {
+            Ok(Some(machine)) => Some(machine),
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                    error: "Not Found".to_string(),
+                    message: format!("Machine with ID {} not found", id),
+                })).into_response();
+            },
+            Err(e) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                    error: "Database Error".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+        },
+        None => None,
+    };
+
+    // Log lines that mention any of these belong to the machine
+    let machine_keys: Vec<String> = machine.iter()
+        .flat_map(|m| vec![Some(m.id.to_string()), Some(m.mac_address.clone()), m.hostname.clone(), m.memorable_name.clone()])
+        .flatten()
+        .collect();
+
+    let mut log_rx = crate::logs::subscribe();
+    let (tx, rx) = mpsc::channel::<Event>(256);
+
+    tokio::spawn(async move {
+        // Replay what's already buffered. We subscribed first, so anything logged in
+        // between shows up in both; the sequence number lets us skip those duplicates.
+        let mut replayed_up_to = 0;
+        for record in crate::logs::recent(&min_level, None, None) {
+            replayed_up_to = record.seq;
+            if !record_matches_machine(&record, &machine_keys) {
+                continue;
+            }
+            let Ok(data) = serde_json::to_string(&record) else { continue };
+            if tx.send(Event::default().event("log").data(data)).await.is_err() {
+                return;
+            }
+        }
+
+        // The machine's current workflow state counts as backlog too
+        let mut last_workflow: Option<String> = None;
+        if let Some(machine) = machine.as_ref() {
+            if let Ok(Some(info)) = crate::tinkerbell::get_workflow_info(machine).await {
+                last_workflow = Some(workflow_signature(&info));
+                if let Ok(data) = serde_json::to_string(&info) {
+                    if tx.send(Event::default().event("workflow").data(data)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        if tx.send(Event::default().event("ready").data("{}")).await.is_err() {
+            return;
+        }
+
+        let mut poll = tokio::time::interval(WORKFLOW_LOG_POLL_INTERVAL);
+
+        loop {
+            let event = tokio::select! {
+                record = log_rx.recv() => match record {
+                    Ok(record) => {
+                        if record.seq <= replayed_up_to || !crate::logs::level_at_least(&record, &min_level) {
+                            continue;
+                        }
+                        if !record_matches_machine(&record, &machine_keys) {
+                            continue;
+                        }
+                        match serde_json::to_string(&record) {
+                            Ok(data) => Event::default().event("log").data(data),
+                            Err(_) => continue,
+                        }
+                    },
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        Event::default().event("lagged").data(json!({ "skipped": skipped }).to_string())
+                    },
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = poll.tick(), if machine.is_some() => {
+                    let machine = machine.as_ref().expect("guarded by select condition");
+                    match crate::tinkerbell::get_workflow_info(machine).await {
+                        Ok(Some(info)) => {
+                            let signature = workflow_signature(&info);
+                            if last_workflow.as_deref() == Some(signature.as_str()) {
+                                continue;
+                            }
+                            last_workflow = Some(signature);
+                            match serde_json::to_string(&info) {
+                                Ok(data) => Event::default().event("workflow").data(data),
+                                Err(_) => continue,
+                            }
+                        },
+                        Ok(None) => continue,
+                        Err(e) => {
+                            debug!("Failed to poll workflow for log stream: {}", e);
+                            continue;
+                        }
+                    }
+                },
+            };
+
+            // Client went away
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let stream = ReceiverStream::new(rx).map(Ok::<_, Infallible>);
+    Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(crate::config::get().http.sse_keepalive()))
+        .into_response()
+}
+
+// Rename from sse_events to machine_events to match the function name used in the working implementation
+// An event as sent to browsers; the ID lets a reconnecting EventSource resume via Last-Event-ID
+fn sse_event(event: &crate::event_manager::RecordedEvent) -> Event {
+    let (event_type, event_payload_str) = match event.message.split_once(':') {
+        Some((event_type, payload)) => (event_type, Some(payload)),
+        None => (event.message.as_str(), None),
+    };
+    let sse_event = Event::default().id(event.id.to_string());
+
+    // Special handling for ip_download_progress to send raw JSON payload
+    if event_type == "ip_download_progress" {
+        return match event_payload_str {
+            Some(payload_str) => sse_event.event(event_type).data(payload_str),
+            None => {
+                warn!("Received ip_download_progress event without payload: {}", event.message);
+                sse_event.comment("Warning: ip_download_progress event received without payload.")
+            }
+        };
+    }
+
+    // Other events (machine_updated, machine_discovered, etc.) carry the type, ID and send time
+    let data_payload = match event_payload_str {
+        Some(id_str) => json!({ "type": event_type, "id": id_str, "timestamp": event.timestamp }),
+        // Ensure there's always a payload, even without ID
+        None => json!({ "type": event_type, "timestamp": event.timestamp }),
+    };
+    match serde_json::to_string(&data_payload) {
+        Ok(json_string) => sse_event.event(event_type).data(json_string),
+        Err(e) => {
+            error!("Failed to serialize SSE event data to JSON: {}", e);
+            sse_event.comment("Internal error: failed to serialize event.")
+        }
+    }
+}
+
+// Pages that reconnect by opening a new EventSource can't set Last-Event-ID, so it's accepted here too
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    last_event_id: Option<u64>,
+    // Comma-separated tags; only events about machines carrying one of them are sent
+    #[serde(alias = "group")]
+    tag: Option<String>,
+}
+
+async fn machine_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<EventsQuery>,
+) -> Response {
+    let http = &crate::config::get().http;
+    // Subscribe before reading the replay buffer so nothing falls between the two
+    let Some(rx) = state.event_manager.subscribe_stream(http.max_sse_subscribers) else {
+        warn!("Refusing event stream: {} streams already open", state.event_manager.metrics().streams);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, "30")],
+            Json(ErrorResponse {
+                error: "Too Many Event Streams".to_string(),
+                message: "The server has reached its limit of open event streams; try again shortly".to_string(),
+            }),
+        ).into_response();
+    };
+
+    // A reconnecting EventSource sends the ID of the last event it saw
+    let last_event_id = headers.get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .or(query.last_event_id);
+    let mut scope = query.tag.as_deref()
+        .map(crate::event_scope::parse_tags)
+        .and_then(crate::event_scope::EventScope::new);
+    if let Some(scope) = &scope {
+        debug!("SSE client subscribed to events for tags {:?}", scope.tags());
+    }
+
+    let (replay, replayed_up_to) = match last_event_id {
+        Some(last_id) => match state.event_manager.events_after(last_id) {
+            Some(missed) => {
+                let up_to = missed.last().map_or(last_id, |e| e.id);
+                let mut events = Vec::with_capacity(missed.len());
+                for event in &missed {
+                    if let Some(scope) = scope.as_mut() {
+                        if !scope.allows(&event.message).await {
+                            continue;
+                        }
+                    }
+                    events.push(sse_event(event));
+                }
+                if !events.is_empty() {
+                    debug!("Replaying {} missed events to reconnecting SSE client", events.len());
+                }
+                (events, up_to)
+            },
+            None => {
+                // Too far behind to replay; the page reloads its state instead
+                info!("SSE client reconnected after event {}, which is no longer buffered; asking it to resync", last_id);
+                (vec![Event::default().event("resync").data("{\"type\":\"resync\"}")], 0)
+            },
+        },
+        None => (Vec::new(), 0),
+    };
+
+    let replay_stream = stream::iter(replay.into_iter().map(Ok));
+    let live_stream = stream::unfold((rx, scope), move |(mut rx, mut scope)| async move {
+        loop {
+            match rx.recv_event().await {
+                // Fault injection drops the connection, as a flaky proxy would
+                Some(_) if crate::chaos::inject(crate::chaos::Fault::SseEvent) => {
+                    warn!("Injected SSE connection drop");
+                    return None;
+                },
+                // Already sent from the replay buffer
+                Some(event) if event.id <= replayed_up_to => continue,
+                Some(event) => {
+                    if let Some(scope) = scope.as_mut() {
+                        if !scope.allows(&event.message).await {
+                            continue;
+                        }
+                    }
+                    return Some((Ok(sse_event(&event)), (rx, scope)));
+                },
+                // Cut off for lagging; the client reconnects and replays what it missed
+                None => return None,
+            }
+        }
+    });
+
+    Sse::new(replay_stream.chain(live_stream))
+        .keep_alive(KeepAlive::new().interval(http.sse_keepalive()).text("ping"))
+        .into_response()
+}
+
+/// Where HookOS reaches Tinkerbell: TINKERBELL_* if set, otherwise derived from DRAGONFLY_BASE_URL.
+pub struct TinkerbellBootParams {
+    pub grpc_authority: String,
+    pub syslog_host: String,
+    pub tls: bool,
+}
+
+pub fn tinkerbell_boot_params(base_url_str: &str) -> TinkerbellBootParams {
+    // --- Derive Tinkerbell defaults from DRAGONFLY_BASE_URL ---
+    let default_tinkerbell_host = Url::parse(base_url_str)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| {
+            warn!("Could not parse DRAGONFLY_BASE_URL host, using fallback '127.0.0.1' for Tinkerbell defaults.");
+            "127.0.0.1".to_string()
+        });
+    
+    const DEFAULT_GRPC_PORT: u16 = 42113;
+    let default_grpc_authority = format!("{}:{}", default_tinkerbell_host, DEFAULT_GRPC_PORT);
+    let default_syslog_host = default_tinkerbell_host.clone(); // Default syslog host is just the host part
+    // -----------------------------------------------------------
+
+    // Get Tinkerbell config, using derived values as defaults
+    let tinkerbell = &crate::config::get().tinkerbell;
+    let grpc_authority = tinkerbell.grpc_authority.clone()
+        .unwrap_or_else(|| {
+            info!("tinkerbell.grpc_authority not set, deriving default: {}", default_grpc_authority);
+            default_grpc_authority
+        });
+    let syslog_host = tinkerbell.syslog_host.clone()
+        .unwrap_or_else(|| {
+             info!("tinkerbell.syslog_host not set, deriving default: {}", default_syslog_host);
+             default_syslog_host
+         });
+    let tinkerbell_tls = tinkerbell.tls.unwrap_or(false);
+
+    TinkerbellBootParams { grpc_authority, syslog_host, tls: tinkerbell_tls }
+}
+
+async fn generate_ipxe_script(script_name: &str) -> Result<String, dragonfly_common::Error> {
+    info!("Generating IPXE script: {}", script_name);
+ 
+    match script_name {
+        "hookos.ipxe" => {
+            // Get Dragonfly base URL (required)
+            let base_url_str = crate::config::base_url()
+                .ok_or_else(|| {
+                    error!("CRITICAL: server.base_url (DRAGONFLY_BASE_URL) is not set. HookOS iPXE script requires this.");
+                    Error::Config("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string())
+                })?;
+
+            let TinkerbellBootParams { grpc_authority, syslog_host, tls: tinkerbell_tls } = tinkerbell_boot_params(&base_url_str);
+
+            // Format the HookOS iPXE script using Dragonfly URL for artifacts and Tinkerbell details for params
+            Ok(format!(r#"#!ipxe
+
+echo Loading HookOS via Dragonfly...
+
+set arch ${{buildarch}}
+# Dragonfly + Tinkerbell only supports 64 bit archectures.
+# The build architecture does not necessarily represent the architecture of the machine on which iPXE is running.
+# https://ipxe.org/cfg/buildarch
+
+iseq ${{arch}} i386 && set arch x86_64 ||
+iseq ${{arch}} arm32 && set arch aarch64 ||
+iseq ${{arch}} arm64 && set arch aarch64 ||
+set base-url {}
+set retries:int32 0
+set retry_delay:int32 0
+
+set worker_id ${{mac}}
+# Set by the /{{mac}} script when the machine boots from a NIC other than the one it registered with
+isset ${{dragonfly_worker_id}} && set worker_id ${{dragonfly_worker_id}} ||
+set grpc_authority {}
+set syslog_host {}
+set tinkerbell_tls {}
+
+echo worker_id=${{worker_id}}
+echo grpc_authority={}
+echo syslog_host={}
+echo tinkerbell_tls={}
+
+# Set by the /{{mac}} script for machines provisioning on a VLAN
+isset ${{dragonfly_vlan}} && set vlan_arg vlan_id=${{dragonfly_vlan}} ||
+
+set idx:int32 0
+:retry_kernel
+kernel ${{base-url}}/ipxe/hookos/vmlinuz-${{arch}} \
+syslog_host=${{syslog_host}} grpc_authority=${{grpc_authority}} tinkerbell_tls=${{tinkerbell_tls}} worker_id=${{worker_id}} hw_addr=${{mac}} ${{vlan_arg}} \
+console=tty1 console=tty2 console=ttyAMA0,115200 console=ttyAMA1,115200 console=ttyS0,115200 console=ttyS1,115200 tink_worker_image=quay.io/tinkerbell/tink-worker:v0.12.1 \
+intel_iommu=on iommu=pt initrd=initramfs-${{arch}} && goto download_initrd || iseq ${{idx}} ${{retries}} && goto kernel-error || inc idx && echo retry in ${{retry_delay}} seconds ; sleep ${{retry_delay}} ; goto retry_kernel
+
+:download_initrd
+set idx:int32 0
+:retry_initrd
+initrd ${{base-url}}/ipxe/hookos/initramfs-${{arch}} && goto boot || iseq ${{idx}} ${{retries}} && goto initrd-error || inc idx && echo retry in ${{retry_delay}} seconds ; sleep ${{retry_delay}} ; goto retry_initrd
+
+:boot
+set idx:int32 0
+:retry_boot
+boot || iseq ${{idx}} ${{retries}} && goto boot-error || inc idx && echo retry in ${{retry_delay}} seconds ; sleep ${{retry_delay}} ; goto retry_boot
+
+:kernel-error
+echo Failed to load kernel
+imgfree
+exit
+
+:initrd-error
+echo Failed to load initrd
+imgfree
+exit
+
+:boot-error
+echo Failed to boot
+imgfree
+exit
+"#, 
+            base_url_str, // Use Dragonfly base URL for artifacts
+            grpc_authority, // Use determined gRPC authority (env var or derived default)
+            syslog_host,    // Use determined syslog host (env var or derived default)
+            tinkerbell_tls, // Use determined TLS setting
+            grpc_authority, // for echo
+            syslog_host,    // for echo
+            tinkerbell_tls  // for echo
+            ))
+        },
+        "dragonfly-agent.ipxe" => {
+            // Get Dragonfly base URL for agent artifacts
+            let base_url = crate::config::base_url()
+                .ok_or_else(|| {
+                    error!("CRITICAL: server.base_url (DRAGONFLY_BASE_URL) is not set. Agent iPXE script requires this.");
+                    Error::Config("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string())
+                })?;
+                
+            // Format the Dragonfly Agent iPXE script
+            Ok(format!(r#"#!ipxe
+kernel {}/ipxe/dragonfly-agent/vmlinuz \
+  ip=dhcp \
+  alpine_repo=http://dl-cdn.alpinelinux.org/alpine/v3.21/main \
+  modules=loop,squashfs,sd-mod,usb-storage \
+  initrd=initramfs-lts \
+  modloop={}/ipxe/dragonfly-agent/modloop \
+  apkovl={}/ipxe/dragonfly-agent/localhost.apkovl.tar.gz \
+  rw
+initrd {}/ipxe/dragonfly-agent/initramfs-lts
+boot
+"#, 
+            base_url, // for kernel path
+            base_url, // for modloop path
+            base_url, // for apkovl path
+            base_url  // for initrd path
+            ))
+        },
+        _ => {
+            warn!("Cannot generate unknown IPXE script: {}", script_name); // Log the specific script name
+            Err(Error::not_found(format!("iPXE script {}", script_name)))
+        },
+    }
+}
+
+fn create_streaming_response(
+    label: &str, // Reported if shutdown has to cut the stream off
+    stream: ReceiverStream<Result<Bytes, Error>>,
+    content_type: &str,
+    content_length: Option<u64>,
+    range_layout: Option<RangeLayout>,
+    client: Option<String>, // Used for per-client bandwidth limits
+) -> Response {
+    // Hold each chunk back as long as the bandwidth limits require
+    let throttled = stream.then(move |result| {
+        let client = client.clone();
+        async move {
+            if let Ok(bytes) = &result {
+                crate::bandwidth::throttle(client.as_deref(), bytes.len()).await;
+            }
+            result
+        }
+    });
+
+    // Shutdown waits for the body to finish (or be dropped) before exiting
+    let work = crate::drain::track(crate::drain::WorkKind::ArtifactStream, label);
+
+    // Map the stream from Result<Bytes> to Result<Frame<Bytes>, BoxError>
+    let mapped_stream = throttled.map(move |result| {
+        let _ = &work;
+        match result {
+            Ok(bytes) => {
+                // Removed check for empty EOF marker
+                // Simply map non-empty bytes to a data frame
+                Ok(Frame::data(bytes))
+            },
+            Err(e) => Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>),
+        }
+    });
+    
+    // Create a stream body with explicit end signal
+    let body = StreamBody::new(mapped_stream);
+    
+    // Determine status code based on whether it's a partial response
+    let status_code = if range_layout.is_some() {
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+    
+    // Start building the response
+    // Multiple ranges are sent as multipart/byteranges, each part carrying its own Content-Range
+    let content_type = match &range_layout {
+        Some(RangeLayout::Multipart { boundary }) => format!("multipart/byteranges; boundary={}", boundary),
+        _ => content_type.to_string(),
+    };
+
+    let mut builder = Response::builder()
+        .status(status_code)
+        .header(axum::http::header::CONTENT_TYPE, content_type)
+        // Always accept ranges
+        .header(axum::http::header::ACCEPT_RANGES, "bytes")
+        // Always set no compression
+        .header(axum::http::header::CONTENT_ENCODING, "identity");
+
+    if let Some(length) = content_length {
+        // If Content-Length is known, set it and DO NOT use chunked encoding.
+        // This applies to both 200 OK and 206 Partial Content.
+        builder = builder.header(axum::http::header::CONTENT_LENGTH, length.to_string());
+    } else {
+        // Only use chunked encoding if length is truly unknown (should typically only be for 200 OK).
+        // It's an error to have a 206 response without Content-Length.
+        if status_code == StatusCode::OK { 
+            builder = builder.header(axum::http::header::TRANSFER_ENCODING, "chunked");
+        } else {
+            // This case (206 without Content-Length) ideally shouldn't happen with our logic.
+            // Log a warning if it does.
+            warn!("Attempting to create 206 response without Content-Length!");
+        }
+    }
+    
+    // Include Content-Range if it's a single-range partial response
+    if let Some(RangeLayout::Single(range_header_value)) = range_layout {
+        builder = builder.header(axum::http::header::CONTENT_RANGE, range_header_value);
+    }
+    
+    // Build the final response
+    builder.body(Body::new(body))
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::new(Empty::new()))
+                .unwrap()
+        })
+}
+
+
+async fn read_file_as_stream(
+    path: &StdPath,
+    range_header: Option<&HeaderValue>, // Add parameter for Range header
+    state: Option<&AppState>, // Add optional state for event emission
+    machine_id: Option<Uuid> // Add optional machine ID for tracking
+) -> Result<(ReceiverStream<Result<Bytes, Error>>, Option<u64>, Option<RangeLayout>), Error> { // Return size and range layout
+    info!("[STREAM_READ] Beginning read_file_as_stream for path: {}, range: {:?}, machine_id: {:?}", 
+          path.display(), range_header.map(|h| h.to_str().unwrap_or("invalid")), machine_id);
+
+    let file = fs::File::open(path).await.map_err(|e| Error::io(format!("Failed to open file {}", path.display()), e))?;
+    let (tx, rx) = mpsc::channel::<Result<Bytes, Error>>(32);
+    let path_buf = path.to_path_buf();
+    
+    // Get total file size
+    let metadata = fs::metadata(path).await.map_err(|e| Error::io(format!("Failed to get metadata {}", path.display()), e))?;
+    let total_size = metadata.len();
+    
+    // Overlapping and nearby ranges are merged so each disk read covers as much as possible
+    let ranges = match range_header.map(|range_val| range_val.to_str()) {
+        Some(Ok(range_str)) => {
+            let ranges = range_reader::parse_ranges(range_str, total_size)
+                .map(|ranges| range_reader::coalesce(ranges, range_reader::COALESCE_GAP));
+            if ranges.is_none() {
+                // Invalid range, serve the whole file
+                warn!("Invalid Range header format: {}", range_str);
+            }
+            ranges
+        },
+        Some(Err(_)) => {
+            warn!("Invalid Range header value (not UTF-8)");
+            None
+        },
+        None => None,
+    };
+
+    let (response_length, layout) = match ranges.as_deref() {
+        Some([(start, end)]) => (end - start + 1, Some(RangeLayout::Single(format!("bytes {}-{}/{}", start, end, total_size)))),
+        Some(ranges) => {
+            let boundary = Uuid::new_v4().simple().to_string();
+            (range_reader::multipart_length(&boundary, ranges, total_size), Some(RangeLayout::Multipart { boundary }))
+        },
+        None => (total_size, None),
+    };
+
+    let boundary = match &layout {
+        Some(RangeLayout::Multipart { boundary }) => Some(boundary.clone()),
+        _ => None,
+    };
+    // Clone state and machine_id needed for the background task *before* spawning
+    // Ensures owned values are moved into the async block, avoiding lifetime issues.
+    let task_state_owned = state.cloned(); // Creates Option<AppState>
+    let task_machine_id_copied = machine_id; // Copies Option<Uuid>
+
+    tokio::spawn(async move {
+        if let Some(ranges) = ranges {
+            // Range requests go through the read-ahead cache, so clients walking a file in
+            // small ranges hit the disk once per window rather than once per request
+            for (start, end) in ranges {
+                if let Some(boundary) = &boundary {
+                    let header = range_reader::part_header(boundary, start, end, total_size);
+                    if tx.send(Ok(Bytes::from(header))).await.is_err() {
+                        return;
+                    }
+                }
+
+                let data = match range_reader::read_range(&path_buf, start, end).await {
+                    Ok(data) => data,
+                    Err(e) => {
+                        error!("Failed to read range {}-{} of file {}: {}", start, end, path_buf.display(), e);
+                        let _ = tx.send(Err(Error::io("File range read error", e))).await;
+                        return;
+                    }
+                };
+
+                // For range requests, we use the end of the range as an indicator of download progress
+                if let (Some(state_ref), Some(machine_id_captured)) = (&task_state_owned, task_machine_id_copied) {
+                    if total_size > 0 {
+                        let effective_progress = start + data.len() as u64;
+                        info!("[RANGE_READ] Range request: start={}, bytes_read={}, total_size={}, effective_progress={}",
+                              start, data.len(), total_size, effective_progress);
+                        let owned_state = state_ref.clone();
+                        tokio::spawn(async move {
+                            track_download_progress(Some(machine_id_captured), effective_progress, total_size, owned_state).await;
+                        });
+                    }
+                }
+
+                if crate::chaos::inject(crate::chaos::Fault::ArtifactChunk) {
+                    warn!("Injected failure while sending range {}-{} of {}", start, end, path_buf.display());
+                    let _ = tx.send(Err(Error::Internal("Injected fault: artifact chunk failed".to_string()))).await;
+                    return;
+                }
+
+                if tx.send(Ok(data)).await.is_err() {
+                    warn!("Client stream receiver dropped for file {} while sending range", path_buf.display());
+                    return;
+                }
+            }
+
+            if let Some(boundary) = &boundary {
+                let _ = tx.send(Ok(Bytes::from(range_reader::multipart_trailer(boundary)))).await;
+            }
+        } else {
+            // Full file requests are read sequentially in large blocks
+            let mut file = file;
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::unix::io::AsRawFd;
+                // Ask the kernel for aggressive read-ahead; cuts seeks on spinning disks
+                unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL); }
+            }
+
+            let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+            let mut remaining = response_length; // For full file, response_length == total_size
+            let mut total_bytes_sent: u64 = 0;
+
+            while remaining > 0 {
+                let read_size = std::cmp::min(remaining as usize, buffer.len());
+                match file.read(&mut buffer[..read_size]).await {
+                    Ok(0) => {
+                        break; // EOF reached
+                    },
+                    Ok(n) => { // Handles n > 0
+                        let chunk = Bytes::copy_from_slice(&buffer[0..n]);
+                        remaining -= n as u64;
+                        total_bytes_sent += n as u64;
+
+                        debug!(path = %path_buf.display(), bytes_read = n, total_bytes_sent = total_bytes_sent, total_size = total_size, "[STREAM_READ_LOOP] Read chunk");
+
+                        // Use the owned/copied state and machine_id captured by the 'move' closure
+                        if let (Some(state_ref), Some(machine_id_captured)) = (&task_state_owned, task_machine_id_copied) {
+                            if total_size > 0 { // Avoid division by zero
+                                debug!("[PROGRESS_DEBUG][CACHE_READ] Calling track_download_progress (machine_id: {}, sent: {}, total: {})", machine_id_captured, total_bytes_sent, total_size);
+                                let owned_state = state_ref.clone();
+                                // Spawn progress tracking in a separate task to avoid blocking the stream
+                                tokio::spawn(async move {
+                                    track_download_progress(Some(machine_id_captured), total_bytes_sent, total_size, owned_state).await;
+                                });
+                            }
+                        }
+
+                        if crate::chaos::inject(crate::chaos::Fault::ArtifactChunk) {
+                            warn!("Injected failure after {} bytes of {}", total_bytes_sent, path_buf.display());
+                            let _ = tx.send(Err(Error::Internal("Injected fault: artifact chunk failed".to_string()))).await;
+                            break;
+                        }
+
+                        if tx.send(Ok(chunk)).await.is_err() {
+                            warn!("Client stream receiver dropped for file {}", path_buf.display());
+                            break; // Exit loop if receiver is gone
+                        }
+                    },
+                    Err(e) => {
+                        let err = Error::io(format!("File read error for {}", path_buf.display()), e);
+                        if tx.send(Err(err)).await.is_err() {
+                            warn!("Client stream receiver dropped while sending error for {}", path_buf.display());
+                        }
+                        break; // Exit loop on read error
+                    }
+                }
+            }
+        }
+        
+        // Task finishes, tx is dropped, stream closes.
+        debug!("Finished streaming task for: {}", path_buf.display());
+    });
+    
+    // Return the stream, the length of the *content being sent*, and how the ranges are laid out
+    Ok((tokio_stream::wrappers::ReceiverStream::new(rx), Some(response_length), layout))
+}
+
+// Full-file reads pull this much per syscall
+const STREAM_BUFFER_SIZE: usize = 1024 * 1024;
+
+const DEFAULT_ARTIFACT_DIR: &str = "/var/lib/dragonfly/ipxe-artifacts";
+
+/// Base directory for cached iPXE artifacts, from storage.artifact_dir or the default.
+pub fn artifact_base_dir() -> PathBuf {
+    let base_dir = crate::config::artifact_dir()
+        .unwrap_or_else(|| {
+            debug!("storage.artifact_dir not set, using default: {}", DEFAULT_ARTIFACT_DIR);
+            DEFAULT_ARTIFACT_DIR.to_string()
+        });
+    PathBuf::from(base_dir)
+}
+
+/// Upstream URL for a known downloadable artifact, keyed by its path under `/ipxe/`.
+pub fn remote_artifact_url(requested_path: &str) -> Option<&'static str> {
+    match requested_path {
+        // Alpine Linux netboot artifacts for Dragonfly Agent
+        "dragonfly-agent/vmlinuz" => Some("https://dl-cdn.alpinelinux.org/alpine/latest-stable/releases/x86_64/netboot/vmlinuz-lts"),
+        "dragonfly-agent/initramfs-lts" => Some("https://dl-cdn.alpinelinux.org/alpine/latest-stable/releases/x86_64/netboot/initramfs-lts"),
+        // iPXE EFI builds served to UEFI HTTP Boot firmware
+        "http-boot/ipxe-x86_64.efi" => Some(crate::http_boot::BootArch::X86_64.upstream_url()),
+        "http-boot/ipxe-aarch64.efi" => Some(crate::http_boot::BootArch::Aarch64.upstream_url()),
+        "dragonfly-agent/modloop" => Some("https://dl-cdn.alpinelinux.org/alpine/latest-stable/releases/x86_64/netboot/modloop-lts"),
+        // Ubuntu 22.04
+        "ubuntu/jammy-server-cloudimg-amd64.img" => Some("https://cloud-images.ubuntu.com/jammy/current/jammy-server-cloudimg-amd64.img"),
+        // Ubuntu 24.04
+        "ubuntu/noble-server-cloudimg-amd64.img" => Some("https://cloud-images.ubuntu.com/noble/current/noble-server-cloudimg-amd64.img"),
+        _ => None,
+    }
+}
+
+// Serve iPXE artifacts (scripts and binaries)
+// Function to serve an iPXE artifact file from a configured directory
+pub async fn serve_ipxe_artifact(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(requested_path): Path<String>,
+    State(state): State<AppState>, // Add AppState to access event manager and client_ip
+) -> Response {
+    // This request's own address, for per-client bandwidth limits
+    let client_addr = request_ip(&headers, addr).to_string();
+
+    // Define constants for directories and URLs
+    const ALLOWED_IPXE_SCRIPTS: &[&str] = &["hookos", "dragonfly-agent"]; // Define allowlist
+    const AGENT_APKOVL_PATH: &str = "/var/lib/dragonfly/ipxe-artifacts/dragonfly-agent/localhost.apkovl.tar.gz";
+    const AGENT_BINARY_URL: &str = "https://github.com/Zorlin/dragonfly/raw/refs/heads/main/dragonfly-agent-musl"; // TODO: Make configurable
+    
+    // Shutting down: let in-flight streams finish, but don't start new ones
+    if crate::drain::is_draining() {
+        return (StatusCode::SERVICE_UNAVAILABLE, [(axum::http::header::RETRY_AFTER, "30")], "Server is shutting down").into_response();
+    }
+
+    // --- Get Machine ID from Client IP --- 
+    let client_ip = state.client_ip.lock().await.clone();
+    let machine_id = if let Some(ip) = &client_ip {
+        // ADDED LOG: Log the IP being looked up
+        info!("[PROGRESS_DEBUG] Looking up machine by IP: {}", ip);
+        match db::get_machine_by_ip(ip).await {
+            Ok(Some(machine)) => {
+                // ADDED LOG: Log successful lookup
+                info!("[PROGRESS_DEBUG] Found machine ID {} for IP {}", machine.id, ip);
+                Some(machine.id)
+            },
+            Ok(None) => {
+                // Changed to INFO for visibility
+                info!("[PROGRESS_DEBUG] No machine found for IP {} requesting artifact {}", ip, requested_path);
+                None
+            },
+            Err(e) => {
+                // Changed to INFO for visibility
+                info!("[PROGRESS_DEBUG] DB error looking up machine by IP {}: {}", ip, e);
+                None
+            }
+        }
+    } else {
+        // Changed to INFO for visibility
+        info!("[PROGRESS_DEBUG] Client IP not found in state for artifact request {}", requested_path);
+        None
+    };
+    // ----------------------------------
+
+    // Get the base directory from env var or use default
+    let base_path = artifact_base_dir();
+    
+    // Path sanitization - Allow '/' but prevent '..'
+    if requested_path.contains("..") || requested_path.contains('\\') {
+        warn!("Attempted iPXE artifact path traversal using '..' or '\': {}", requested_path);
+        return (StatusCode::BAD_REQUEST, "Invalid artifact path").into_response();
+    }
+    
+    let artifact_path = base_path.join(&requested_path);
+
+    // --- Serve from Cache First ---
+    if artifact_path.exists() {
+        info!("[SERVE_ARTIFACT] Cached artifact exists at {}, will use read_file_as_stream", artifact_path.display());
+        // Determine content type AND if it's an IPXE script
+        let (content_type, is_ipxe) = if requested_path.ends_with(".ipxe") {
+            ("text/plain", true)
+        } else if requested_path.ends_with(".tar.gz") {
+            ("application/gzip", false) // Ensure this returns a tuple
+        } else {
+            ("application/octet-stream", false) // Ensure this returns a tuple
+        };
+
+        // Allowlist check for IPXE scripts from cache
+        if is_ipxe { // Check the boolean flag
+            let stem = StdPath::new(&requested_path).file_stem().and_then(|s| s.to_str());
+            if let Some(stem_str) = stem {
+                if !ALLOWED_IPXE_SCRIPTS.contains(&stem_str) {
+                    warn!("Attempt to serve non-allowlisted IPXE script stem from cache: {}", stem_str);
+                    return (StatusCode::NOT_FOUND, "iPXE Script Not Found").into_response();
+                }
+            } else {
+                 warn!("Could not extract stem from IPXE script path: {}", requested_path);
+                 return (StatusCode::BAD_REQUEST, "Invalid IPXE Script Path").into_response();
+            }
+        }
+        
+        // Serve allowed script or binary artifact from cache using streaming
+        // Pass the potentially found machine_id for progress tracking
+        match read_file_as_stream(&artifact_path, headers.get(axum::http::header::RANGE), Some(&state), machine_id).await {
+            Ok((stream, file_size, range_layout)) => {
+                info!("Streaming cached artifact from disk: {}", requested_path);
+                return create_streaming_response(&requested_path, stream, content_type, file_size, range_layout, Some(client_addr.clone())); // Pass range layout
+            },
+            Err(e) => {
+                error!("Failed to stream cached iPXE artifact: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Error reading iPXE artifact").into_response();
+            }
+        }
+    } else {
+        // --- File Not Found: Generate or Download --- 
+        info!("[SERVE_ARTIFACT] Artifact {} not found locally, will need to generate or download", requested_path);
+        
+        // FIRST check if it is the specific apkovl path that needs generation
+        // Compare against the RELATIVE path expected from the URL
+        if requested_path == "dragonfly-agent/localhost.apkovl.tar.gz" {
+            // --- Special Case: Generate apkovl on demand ---
+            // Use the full absolute path for generation logic
+            let generation_target_path = PathBuf::from(AGENT_APKOVL_PATH);
+            
+            // Serialize generation; if another request built it while we waited, just serve it
+            let lock = artifact_lock(&generation_target_path);
+            let _guard = lock.lock().await;
+            if generation_target_path.exists() {
+                info!("{} was generated by a concurrent request, serving it", generation_target_path.display());
+                return match read_file_as_stream(&generation_target_path, None, None, None).await {
+                    Ok((stream, file_size, _)) => create_streaming_response(&requested_path, stream, "application/gzip", file_size, None, Some(client_addr.clone())),
+                    Err(e) => {
+                        error!("Failed to stream apkovl {}: {}", generation_target_path.display(), e);
+                        (StatusCode::INTERNAL_SERVER_ERROR, "Error reading apkovl").into_response()
+                    }
+                };
+            }
+            info!("Generating {} on demand...", generation_target_path.display());
+
+            let base_url = match crate::config::base_url() {
+                Some(url) => url,
+                None => {
+                    error!("Cannot generate apkovl: server.base_url (DRAGONFLY_BASE_URL) is not set.");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Server configuration error for apkovl generation").into_response();
+                }
+            };
+
+            match generate_agent_apkovl(&generation_target_path, &base_url, AGENT_BINARY_URL).await {
+                Ok(()) => {
+                    info!("Successfully generated {}, now serving...", generation_target_path.display());
+                    // Serve the newly generated file (no range needed here as it was just created)
+                    match read_file_as_stream(&generation_target_path, None, None, None).await { 
+                        Ok((stream, file_size, _)) => {
+                            return create_streaming_response(&requested_path, stream, "application/gzip", file_size, None, Some(client_addr.clone())); 
+                        },
+                        Err(e) => {
+                            error!("Failed to stream newly generated apkovl {}: {}", generation_target_path.display(), e);
+                            return (StatusCode::INTERNAL_SERVER_ERROR, "Error reading newly generated apkovl").into_response();
+                        }
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to generate {}: {}", generation_target_path.display(), e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate {}: {}", generation_target_path.display(), e)).into_response();
+                }
+            }
+        } 
+        // NEXT check if it's a generic .ipxe script that needs generation
+        else if requested_path.ends_with(".ipxe") {
+            // --- Generate iPXE scripts on the fly ---
+            // Use the relative path for script generation lookup
+            match generate_ipxe_script(&requested_path).await {
+                Ok(script) => {
+                    info!("Generated {} script dynamically.", requested_path);
+                    // Cache in background using the full artifact_path
+                    let path_clone = artifact_path.clone(); 
+                    let script_clone = script.clone();
+                    let requested_path_clone = requested_path.clone(); // Clone for the task
+                    tokio::spawn(async move {
+                        // Write atomically under the path lock so readers never see a partial script
+                        let lock = artifact_lock(&path_clone);
+                        let _guard = lock.lock().await;
+                        if let Err(e) = write_artifact_atomic(&path_clone, script_clone.as_bytes()).await {
+                             warn!("Failed to cache generated {} script: {}", requested_path_clone, e);
+                        }
+                    });
+                    
+                    // For iPXE scripts, let's build our own response
+                    let content_length = script.len() as u64;
+                    
+                    // Create a response that's optimized for iPXE
+                    return Response::builder()
+                        .status(StatusCode::OK)
+                        .header(axum::http::header::CONTENT_TYPE, "text/plain")
+                        .header(axum::http::header::CONTENT_LENGTH, content_length.to_string())
+                        .header(axum::http::header::CONTENT_ENCODING, "identity") // No compression
+                        .body(Body::from(script))
+                        .unwrap_or_else(|_| {
+                            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response").into_response()
+                        });
+                },
+                Err(Error::NotFound { .. }) => {
+                    warn!("IPXE script {} not found or could not be generated.", requested_path);
+                    // Fall through to final 404
+                },
+                Err(e) => {
+                    // Other error during generation (e.g., missing env var)
+                    error!("Failed to generate {} script: {}", requested_path, e);
+                    return e.into_response();
+                }
+            }
+            // If we fall through here, it means generate_ipxe_script returned NotFound
+        }
+        // FINALLY, assume it's a binary artifact to download/stream
+        else {
+            // --- Download/Stream Other Binary Artifacts ---
+            let remote_url = match remote_artifact_url(&requested_path) {
+                Some(url) => url,
+                None => {
+                    // If it wasn't an .ipxe script and not a known binary, it's unknown.
+                    warn!("Unknown artifact requested: {}", requested_path);
+                    return (StatusCode::NOT_FOUND, "Unknown iPXE artifact").into_response();
+                }
+            };
+            
+            // Don't start a download that can't finish writing to the cache
+            if let Some(status) = crate::storage::low_storage() {
+                let message = crate::storage::low_storage_message(&status);
+                warn!("Refusing download of {}: {}", requested_path, message);
+                let _ = state.event_manager.send(format!("storage_low:{}:{}", status.name, status.free_bytes));
+                return (StatusCode::INSUFFICIENT_STORAGE, message).into_response();
+            }
+            
+            // Use the efficient streaming download with caching for known artifacts
+            // Use artifact_path (full path) for caching
+            match stream_download_with_caching(
+                remote_url, 
+                &artifact_path, 
+                headers.get(axum::http::header::RANGE),
+                machine_id, // Pass the machine_id found via IP lookup
+                Some(&state)
+            ).await {
+                Ok((stream, content_length, range_layout)) => {
+                    info!("Streaming artifact {} from remote source", requested_path);
+                    return create_streaming_response(&requested_path, stream, "application/octet-stream", content_length, range_layout, Some(client_addr.clone()));
+                },
+                Err(e) => {
+                    // Mirror failures surface as 502 rather than a generic 500
+                    error!("Failed to stream artifact {}: {}", requested_path, e);
+                    return e.into_response();
+                }
+            }
+        }
+
+        // If code reaches here, it means an IPXE script was requested but generate_ipxe_script 
+        // returned NotFound, so return 404.
+        (StatusCode::NOT_FOUND, "Unknown or Ungeneratable IPXE Script").into_response()
+    }
+}
+
+// Add this function after parse_range_header
+// Helper function to track and report image download progress
+async fn track_download_progress(
+    machine_id: Option<Uuid>, 
+    bytes_downloaded: u64, 
+    total_size: u64,
+    state: AppState // Changed from Option<&AppState> to AppState
+) {
+    info!(
+        machine_id = ?machine_id, 
+        bytes_downloaded = bytes_downloaded, 
+        total_size = total_size, 
+        "[PROGRESS_TRACK] CALLED track_download_progress with values: bytes_downloaded={}, total_size={}, machine_id={:?}",
+        bytes_downloaded, total_size, machine_id
+    );
+
+    debug!(
+        machine_id = ?machine_id, 
+        bytes_downloaded = bytes_downloaded, 
+        total_size = total_size, 
+        "[PROGRESS_DEBUG] Entering track_download_progress"
+    );
+
+    if total_size == 0 {
+        // Changed to INFO
+        info!("[PROGRESS_DEBUG] Exiting track_download_progress early: total_size is 0");
+        return; // Skip progress for zero-sized files
+    }
+    
+    let progress_float = (bytes_downloaded as f64 / total_size as f64) * 100.0;
+    let task_name = "stream image"; // TODO: Can we get the actual filename here?
+    
+    // If we have a machine ID, send task-specific event
+    if let Some(id) = machine_id {
+        debug!(machine_id = %id, progress = progress_float, task_name = task_name, "Updating DB progress");
+        // Update the machine's task progress in DB
+        if let Err(e) = db::update_installation_progress(
+            &id,
+            progress_float.min(100.0) as u8, // Convert to u8 for DB, clamped at 100
+            Some(task_name)
+        ).await {
+            warn!(machine_id = %id, error = %e, "Failed to update download progress in DB");
+        }
+        
+        // For real-time UI updates, emit a more detailed event with floating point precision
+        let task_progress_event = format!(
+            "task_progress:{}:{}:{:.3}:{}:{}",
+            id,                   // Machine ID
+            task_name,            // Task name 
+            progress_float,       // Floating point percentage (with 3 decimal precision)
+            bytes_downloaded,     // Current bytes
+            total_size            // Total bytes
+        );
+        
+        debug!(machine_id = %id, event = %task_progress_event, "Attempting to send task_progress event");
+        // Emit the detailed task progress event
+        if let Err(e) = state.event_manager.send(task_progress_event.clone()) { // Clone for logging
+            warn!(machine_id = %id, error = %e, "Failed to emit task_progress event: {}", task_progress_event);
+        }
+        
+        // Also emit standard machine updated event for compatibility
+        // debug!(machine_id = %id, "Sending generic machine_updated event");
+        // let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    }
+    
+    // Also send IP-based progress event for any HTTP requests
+    let client_ip_guard = state.client_ip.lock().await;
+    if let Some(client_ip) = client_ip_guard.as_ref() {
+        // Find machine by IP if possible (for cases where we don't have machine_id)
+        let ip_machine_id = if machine_id.is_none() {
+            match db::get_machine_by_ip(client_ip).await {
+                Ok(Some(machine)) => Some(machine.id),
+                _ => None,
+            }
+        } else {
+            machine_id
+        };
+        
+        // Emit IP-based progress event
+        let ip_progress_event_payload = serde_json::json!({ 
+            "ip": client_ip,
+            "progress": progress_float, // Send float
+            "bytes_downloaded": bytes_downloaded,
+            "total_size": total_size,
+            "file_name": task_name, // Still uses hardcoded "Stream image"
+            "machine_id": ip_machine_id
+        });
+
+        // Construct the event string
+        let ip_progress_event_string = format!("ip_download_progress:{}", ip_progress_event_payload.to_string());
+
+        info!(client_ip = %client_ip, event_payload = %ip_progress_event_payload, "[PROGRESS_SEND] Attempting to send ip_download_progress event NOW"); // ADDED LOUD LOG
+        let send_result = state.event_manager.send(ip_progress_event_string.clone()); // Clone for logging
+        
+        if let Err(e) = send_result {
+            warn!(client_ip = %client_ip, error = %e, "[PROGRESS_SEND] Failed to emit IP-based progress event: {}", ip_progress_event_string);
+        } else {
+            info!(client_ip = %client_ip, event_payload = %ip_progress_event_payload, "[PROGRESS_SEND] Successfully sent ip_download_progress event"); // ADDED SUCCESS LOG
+        }
+    } // End of: if let Some(client_ip) = client_ip_guard.as_ref()
+    
+    debug!("Exiting track_download_progress");
+}
+
+// Modify stream_download_with_caching to track progress
+async fn stream_download_with_caching(
+    url: &str,
+    cache_path: &StdPath,
+    range_header: Option<&HeaderValue>, // Add parameter for Range header
+    machine_id: Option<Uuid>, // Add optional machine ID for tracking
+    state: Option<&AppState>, // Add optional state for event emission
+) -> Result<(ReceiverStream<Result<Bytes, Error>>, Option<u64>, Option<RangeLayout>), Error> { // Return range layout
+    info!("[STREAM_DOWNLOAD] Beginning stream_download_with_caching for URL: {}, cache_path: {}, range: {:?}, machine_id: {:?}",
+          url, cache_path.display(), range_header.map(|h| h.to_str().unwrap_or("invalid")), machine_id);
+
+    // Create parent directory if needed
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent).await.map_err(|e| Error::io(format!("Failed to create directory {}", parent.display()), e))?;
+    }
+
+    // Only one download per artifact; later requests wait here and then find it cached
+    let download_guard = artifact_lock(cache_path).lock_owned().await;
+
+    // Check if file is already cached
+    if cache_path.exists() {
+        drop(download_guard);
+        // Even when serving from cache, track progress for range requests
+        if let (Some(machine_id), Some(state), Some(range_val)) = (machine_id, state, range_header) {
+            if let Ok(range_str) = range_val.to_str() {
+                let file_size = fs::metadata(cache_path).await
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+                    
+                if let Some((start, end)) = parse_range_header(range_str, file_size, None, Some(state)).await {
+                    let bytes_downloaded = end - start + 1;
+                    
+                    // Use the start position as a progress indicator for range requests
+                    // This gives a rough approximation of download progress across multiple range requests
+                    let effective_progress = start + bytes_downloaded;
+                    
+                    info!("[RANGE_PROGRESS] Cached file with range: start={}, end={}, bytes={}, total={}, effective_progress={}",
+                          start, end, bytes_downloaded, file_size, effective_progress);
+                          
+                    // Track download progress with the effective bytes downloaded
+                    tokio::spawn(track_download_progress(Some(machine_id), effective_progress, file_size, state.clone()));
+                }
+            }
+        }
+        
+        // info!("Serving cached artifact from: {:?}", cache_path); // Commented out log
+        return read_file_as_stream(cache_path, range_header, state, machine_id).await; // Pass Range header
+    }
+    
+    info!("Downloading and caching artifact from: {}", url);
+    
+    // Start HTTP request with reqwest feature for streaming
+    let client = reqwest::Client::new();
+    let response = client.get(url).send().await.map_err(|e| Error::upstream_with(url, "HTTP request failed", e))?;
+    
+    if !response.status().is_success() {
+        return Err(Error::upstream(url, format!("HTTP error: {}", response.status())));
+    }
+    
+    // Get content length if available
+    let content_length = response.content_length();
+    if let Some(length) = content_length {
+        info!("[PROGRESS_DEBUG] Download size from Content-Length: {} bytes", length);
+    } else {
+        info!("[PROGRESS_DEBUG] No Content-Length header received from remote server.");
+    }
+    
+    // Download into a temp file and rename on success so a partial download is never served as cached
+    let temp_path = temp_artifact_path(cache_path);
+    let file = fs::File::create(&temp_path).await.map_err(|e| Error::io("Failed to create cache file", e))?;
+    let file = Arc::new(tokio::sync::Mutex::new(file));
+    let (tx, rx) = mpsc::channel::<Result<Bytes, Error>>(32);
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel::<bool>();
+    
+    let url_clone = url.to_string();
+    let cache_path_clone = cache_path.to_path_buf();
+    
+    // For tracking download progress
+    let total_size = content_length.unwrap_or(0);
+    let mut total_bytes_downloaded: u64 = 0;
+    let tracking_machine_id = machine_id;
+    let app_state_clone = state.cloned();
+    // Held until the download is cached or discarded, so shutdown doesn't cut it off midway
+    let work = crate::drain::track(crate::drain::WorkKind::ArtifactDownload, url.to_string());
+    
+    tokio::spawn(async move {
+        let _work = work;
+        let mut client_disconnected = false;
+        let mut download_error = false;
+
+        // Get the stream. `bytes_stream` consumes the response object.
+        let mut stream = response.bytes_stream(); 
+
+        while let Some(chunk_result) = stream.next().await {
+            match chunk_result {
+                Ok(chunk) => {
+                    let chunk_clone = chunk.clone();
+                    let chunk_size = chunk.len() as u64;
+                    
+                    // Write chunk to cache file concurrently
+                    let file_clone = Arc::clone(&file);
+                    let write_handle = tokio::spawn(async move {
+                        let mut file = file_clone.lock().await;
+                        file.write_all(&chunk_clone).await
+                    });
+
+                    // Update progress tracking
+                    total_bytes_downloaded += chunk_size;
+                    
+                    // ADDED LOG: Log chunk size and total downloaded
+                    debug!(url = %url_clone, chunk_size = chunk_size, total_bytes_downloaded = total_bytes_downloaded, total_size = total_size, "[STREAM_DOWNLOAD_LOOP] Downloaded chunk");
+
+                    if let (Some(machine_id), Some(state)) = (tracking_machine_id, &app_state_clone) {
+                        if total_size > 0 {
+                            // ADDED LOG: Confirm call to track_download_progress
+                            debug!("[PROGRESS_DEBUG] Calling track_download_progress (machine_id: {}, downloaded: {}, total: {})", machine_id, total_bytes_downloaded, total_size);
+                            
+                            // ADDED LOG: Log before calling track_download_progress function
+                            debug!(url = %url_clone, machine_id = %machine_id, bytes_downloaded = total_bytes_downloaded, total_size = total_size, "[STREAM_DOWNLOAD_LOOP] PRE-PROGRESS CALL");
+                            
+                            track_download_progress(Some(machine_id), total_bytes_downloaded, total_size, state.clone()).await;
+                        }
+                    }
+                    
+                    // Attempt to send to client only if not already disconnected
+                    if !client_disconnected {
+                        if tx.send(Ok(chunk)).await.is_err() {
+                            warn!("Client stream receiver dropped for {}. Continuing download in background.", url_clone);
+                            client_disconnected = true;
+                            // DO NOT break here - let download continue for caching
+                        }
+                    }
+
+                    // Await the write operation regardless of client connection status
+                    match write_handle.await { // Await the JoinHandle itself
+                        Ok(Ok(())) => {
+                            // Write successful, continue loop
+                        },
+                        Ok(Err(e)) => {
+                            // Write operation failed
+                            warn!("Failed to write chunk to cache file {}: {}", cache_path_clone.display(), e);
+                            download_error = true;
+                            break; // Abort download if we can't write to cache
+                        },
+                        Err(e) => {
+                            // Task failed (e.g., panicked)
+                            warn!("Cache write task failed (join error) for {}: {}", cache_path_clone.display(), e);
+                            download_error = true;
+                            break; // Abort download if write task fails
+                        }
+                    }
+                },
+                Err(e) => { // e is reqwest::Error here
+                    error!("Download stream error for {}: {}", url_clone, e);
+                    // Send error to client if still connected
+                    if !client_disconnected {
+                        let err = Error::upstream_with(url_clone.as_str(), "Download stream error", e);
+                        if tx.send(Err(err)).await.is_err() {
+                             warn!("Client stream receiver dropped while sending download error for {}", url_clone);
+                             // Client disconnected while we were trying to send an error
+                             client_disconnected = true;
+                        }
+                    }
+                    download_error = true;
+                    break; // Stop processing on download error
+                }
+            }
+            // If download_error is true, the inner match already broke, so we'll exit.
+        }
+        
+        // Explicitly drop the response stream to release network resources potentially sooner
+        drop(stream);
+
+        // Report final progress on successful download
+        if !download_error && total_size > 0 {
+            if let (Some(machine_id), Some(state)) = (tracking_machine_id, &app_state_clone) {
+                track_download_progress(Some(machine_id), total_size, total_size, state.clone()).await;
+            }
+        }
+
+        // Ensure file is flushed and closed first
+        if let Ok(mut file) = Arc::try_unwrap(file).map_err(|_| "Failed to unwrap Arc").and_then(|mutex| Ok(mutex.into_inner())) {
+            if let Err(e) = file.flush().await {
+                warn!("Failed to flush cache file {}: {}", cache_path_clone.display(), e);
+                download_error = true;
+            } else if let Err(e) = file.sync_all().await {
+                warn!("Failed to sync cache file {}: {}", cache_path_clone.display(), e);
+                download_error = true;
+            }
+            // File is closed when it goes out of scope here
+        }
+        
+        // Publish the completed download atomically, or discard the partial file
+        if !download_error {
+            if let Err(e) = fs::rename(&temp_path, &cache_path_clone).await {
+                warn!("Failed to move downloaded artifact into cache {}: {}", cache_path_clone.display(), e);
+                download_error = true;
+            }
+        }
+        if download_error {
+            if let Err(e) = fs::remove_file(&temp_path).await {
+                warn!("Failed to remove incomplete download {}: {}", temp_path.display(), e);
+            }
+        }
+        drop(download_guard);
+        let _ = done_tx.send(!download_error);
+        
+        // Only send EOF signal if the download completed without error AND the client is still connected
+        if !download_error && !client_disconnected {
+            info!("Download complete for {}, client still connected.", url_clone);
+            // Removed explicit EOF signal
+            // debug!("Sending EOF signal for {}", url_clone);
+            // let _ = tx.send(Ok(Bytes::new())).await;
+        } else if !download_error && client_disconnected {
+            info!("Download complete and cached for {} after client disconnected.", url_clone);
+        } else {
+            // An error occurred during download or caching
+            warn!("Download for {} did not complete successfully due to errors.", url_clone);
+        }
+    });
+    
+    // After download completes or if error, handle the stream
+    let (stream, content_length) = (tokio_stream::wrappers::ReceiverStream::new(rx), content_length);
+
+    // We cached the full file, but the *initial* request might have been a range request.
+    // If so, we need to read the *cached* file with range support now.
+    if range_header.is_some() {
+        // Let the download run detached, wait for it to land in the cache, then serve the range
+        drop(stream);
+        match done_rx.await {
+            Ok(true) => {
+                info!("Download complete, now serving range request from cached file: {:?}", cache_path);
+                // Re-call read_file_as_stream with the range header on the now-cached file
+                read_file_as_stream(cache_path, range_header, state, machine_id).await // Pass machine_id here too
+            },
+            _ => Err(Error::upstream(url, "Download failed before range could be served")),
+        }
+    } else {
+        // No range requested initially, return the full stream we prepared during download
+        Ok((stream, content_length, None)) // No Content-Range for full file
+    }
+}
+
+// Helper to parse Range header. Returns (start, end)
+async fn parse_range_header(
+    range_str: &str,
+    total_size: u64,
+    _file_name: Option<&str>, // Marked unused, event logic removed
+    _state: Option<&AppState>, // Marked unused, event logic removed
+) -> Option<(u64, u64)> {
+    if !range_str.starts_with("bytes=") {
+        return None;
+    }
+    let range_val = &range_str[6..]; // Skip "bytes="
+    let parts: Vec<&str> = range_val.split('-').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+
+    let start_str = parts[0].trim();
+    let end_str = parts[1].trim();
+
+    let start = if start_str.is_empty() {
+        // Suffix range: "-<length>"
+        if end_str.is_empty() { return None; } // Invalid: "-"
+        let suffix_len = end_str.parse::<u64>().ok()?;
+        if suffix_len >= total_size { 0 } else { total_size - suffix_len }
+    } else {
+        // Normal range: "start-" or "start-end"
+        start_str.parse::<u64>().ok()?
+    };
+
+    let end = if end_str.is_empty() {
+        // Range "start-" means start to end of file
+        total_size.saturating_sub(1)
+    } else {
+        // Range "start-end"
+        end_str.parse::<u64>().ok()?
+    };
+
+    // Validate range: start <= end < total_size
+    if start > end || end >= total_size {
+        warn!("Invalid range request: start={}, end={}, total_size={}", start, end, total_size);
+        return None;
+    }
+
+    // Optional: Emit progress event for the range being served
+    // if let Some(s) = state { // Check if state exists before trying to use it
+    //     let bytes_downloaded = end - start + 1;
+    //     let event_data = serde_json::json!({
+    //         "progress": 100.0, // A single range request is considered 100% of that range
+    //         "bytes_downloaded": bytes_downloaded,
+    //         "total_size": total_size,
+    //         "file_name": file_name.unwrap_or("unknown")
+    //     }).to_string();
+
+    //     // Prefer emitting IP-based progress if possible
+    //     let client_ip_guard = s.client_ip.lock().await;
+    //     if let Some(client_ip) = client_ip_guard.as_ref() {
+    //          let ip_progress_event = format!("ip_download_progress:{{ \"ip\": \"{}\", {} }}", client_ip, &event_data[1..]); // Construct JSON manually
+    //          // info!("Sending event: {}", ip_progress_event); // Commented out log
+    //          let _ = s.event_manager.send(ip_progress_event);
+    //     } else if let Some(f_name) = file_name {
+    //         // Fallback to file-based progress if IP is unavailable
+    //         let file_progress_event = format!("file_progress:{}:{}:{}", f_name, 100.0, event_data);
+    //         let _ = s.event_manager.send(file_progress_event);
+    //     }
+    // }
+
+    Some((start, end))
+}
+
+// Restore original function name and intended purpose (returning HTML partial)
+pub async fn get_workflow_progress(
+    State(app_state): State<AppState>, // Add AppState
+    Path(id): Path<Uuid>
+) -> Response { 
+    info!("Request for workflow progress HTML partial for machine {}", id);
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(m)) => m,
+        Ok(None) => {
+            error!("Machine not found: {}", id);
+            return (StatusCode::NOT_FOUND, Html("<div>Machine not found</div>")).into_response();
+        },
+        Err(e) => {
+            error!("Error fetching machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html("<div>Database error</div>")).into_response();
+        }
+    };
+
+    if machine.status != MachineStatus::InstallingOS {
+        info!("Machine {} is not installing OS, status: {:?}", id, machine.status);
+        return (StatusCode::OK, Html("<div></div>")).into_response(); // Return empty div if not installing
+    }
+
+    match crate::tinkerbell::get_workflow_info(&machine).await {
+        Ok(Some(info)) => {
+            info!("Successfully got workflow info for machine {}: state={}, progress={}", id, info.state, info.progress);
+
+            // Create the context struct (as defined in ui.rs)
+            let context = ui::WorkflowProgressTemplate { 
+                machine_id: id,
+                workflow_info: info,
+            };
+            
+            // Render the partial using MiniJinja
+            ui::render_minijinja(&app_state, "partials/workflow_progress.html", context)
+        },
+        Ok(None) => {
+            info!("No workflow found for machine {}", id);
+            (StatusCode::OK, Html("<div></div>")).into_response()
+        },
+        Err(e) => {
+            error!("Error fetching workflow for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Html("<div>Error fetching workflow</div>")).into_response()
+        }
+    }
+}
+
+// ... (rest of api.rs) ...
+
+// Stub for heartbeat
+pub async fn heartbeat() -> Response {
+    (StatusCode::OK, "OK").into_response()
+}
+
+// Server and API versions, so agents can pick a route prefix during rolling upgrades
+async fn api_version() -> Response {
+    let schema_version = match db::schema_version().await {
+        Ok(version) => version,
+        Err(e) => {
+            error!("Failed to read the database schema version: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    let api_versions = dragonfly_common::models::API_VERSIONS;
+    Json(dragonfly_common::models::ApiVersionInfo {
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version,
+        api_versions: api_versions.iter().map(|v| v.to_string()).collect(),
+        current_api_version: api_versions[0].to_string(),
+    }).into_response()
+}
+
+// What this server supports, so agents and HookOS actions can adapt without sniffing versions
+async fn api_capabilities() -> Json<dragonfly_common::models::ServerCapabilities> {
+    // x86_64 is always served; aarch64 only once its HookOS kernel has been fetched
+    let hookos_dir = artifact_base_dir().join("hookos");
+    let mut architectures = vec!["x86_64".to_string()];
+    if ["vmlinuz-latest-lts-aarch64", "vmlinuz-aarch64"].iter().any(|f| hookos_dir.join(f).exists()) {
+        architectures.push("aarch64".to_string());
+    }
+    Json(dragonfly_common::models::ServerCapabilities {
+        architectures,
+        secure_boot_artifacts: crate::secure_boot::artifacts_available(),
+        http_boot: true,
+        ranged_downloads: true,
+        multi_range_downloads: true,
+        websocket_channel: true,
+        event_stream: true,
+        token_auth_scopes: crate::access_tokens::TokenScope::ALL.iter().map(|s| s.as_str().to_string()).collect(),
+        install_telemetry: true,
+    })
+}
+
+// Boot file URLs and DHCP settings for UEFI HTTP Boot
+async fn api_http_boot_guidance() -> Response {
+    match crate::config::base_url() {
+        Some(base_url) => Json(crate::http_boot::guidance(&base_url)).into_response(),
+        None => Error::Config("Server is missing required DRAGONFLY_BASE_URL configuration.".to_string()).into_response(),
+    }
+}
+
+// Add stubs for functions called from mode.rs
+pub async fn check_hookos_artifacts() -> bool {
+    // Check for the following four files
+    let files = vec![
+        "vmlinuz-latest-lts-x86_64",
+        "initramfs-latest-lts-x86_64",
+        "vmlinuz-latest-lts-aarch64",
+        "initramfs-latest-lts-aarch64",
+        "dtbs-latest-lts-aarch64.tar.gz",
+        "vmlinuz-x86_64",
+        "initramfs-x86_64",
+        "vmlinuz-aarch64",
+        "initramfs-aarch64",
+        "dtbs-aarch64.tar.gz",
+    ];
+
+    for file in files {
+        let path = FilePath::new("/var/lib/dragonfly/ipxe-artifacts/hookos").join(file);
+        if !path.exists() {
+            return false;
+        }
+    }
+
+    info!("All HookOS artifacts found");
+    true
+}
+
+pub async fn download_hookos_artifacts(version: &str) -> anyhow::Result<()> {
+    // Create directory structure if it doesn't exist
+    let hookos_dir = FilePath::new("/var/lib/dragonfly/ipxe-artifacts/hookos");
+    if !hookos_dir.exists() {
+        info!("Creating directory structure: {:?}", hookos_dir);
+        std::fs::create_dir_all(hookos_dir)?;
+    }
+    
+    // Download checksum file
+    let checksum_url = format!("https://github.com/tinkerbell/hook/releases/download/{}/checksum.txt", version);
+    let checksum_path = hookos_dir.join("checksum.txt");
+    let checksum_response = reqwest::get(checksum_url).await?;
+    let checksum_content = checksum_response.text().await?;
+    std::fs::write(checksum_path, checksum_content)?;
+
+    // Files to download
+    let files = vec![
+        "hook_x86_64.tar.gz",
+        "hook_aarch64.tar.gz",
+        "hook_latest-lts-x86_64.tar.gz",
+        "hook_latest-lts-aarch64.tar.gz",
+    ];
+
+    // Create a vector of download futures
+    let download_futures = files.iter().map(|file| {
+        let file = file.to_string();
+        let version = version.to_string();
+        let hookos_dir = hookos_dir.to_path_buf();
+        
+        // Return a future for each download
+        async move {
+            let url = format!("https://github.com/tinkerbell/hook/releases/download/{}/{}", version, file);
+            info!("Downloading {} in parallel", url);
+            let response = reqwest::get(&url).await?;
+            let content = response.bytes().await?;
+            let tarball_path = hookos_dir.join(&file);
+            std::fs::write(&tarball_path, content)?;
+            info!("Downloaded {} to {:?}", file, tarball_path);
+            Ok::<_, anyhow::Error>(tarball_path)
+        }
+    }).collect::<Vec<_>>();
+    
+    // Execute all downloads in parallel
+    let download_results = futures::future::try_join_all(download_futures).await?;
+    info!("All HookOS artifacts downloaded in parallel successfully");
+
+    // Create a vector of extraction futures
+    let extraction_futures = download_results.into_iter().map(|tarball_path| {
+        let hookos_dir = hookos_dir.to_path_buf();
+        
+        // Return a future for each extraction
+        async move {
+            let file_name = tarball_path.file_name().unwrap().to_string_lossy().to_string();
+            info!("Extracting {:?} in parallel", tarball_path);
+            
+            // Check if the file exists and has content before trying to extract
+            let metadata = match std::fs::metadata(&tarball_path) {
+                Ok(meta) => meta,
+                Err(e) => {
+                    warn!("Skipping extraction of {:?}: file not accessible: {}", tarball_path, e);
+                    return Ok::<_, anyhow::Error>(tarball_path);
+                }
+            };
+            
+            if metadata.len() == 0 {
+                warn!("Skipping extraction of {:?}: file is empty", tarball_path);
+                return Ok::<_, anyhow::Error>(tarball_path);
+            }
+            
+            // Open the file for reading
+            let tar_file = match File::open(&tarball_path) {
+                Ok(f) => f,
+                Err(e) => {
+                    warn!("Failed to open {:?} for extraction: {}", tarball_path, e);
+                    return Ok::<_, anyhow::Error>(tarball_path);
+                }
+            };
+            
+            // Create the archive and extract, handling any errors
+            // Check if the file is a .tar.gz file
+            if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+                // Use GzDecoder for gzipped files
+                let gz = GzDecoder::new(tar_file);
+                let mut archive = Archive::new(gz);
+                match archive.unpack(&hookos_dir) {
+                    Ok(_) => info!("Successfully extracted gzipped archive {:?}", tarball_path),
+                    Err(e) => warn!("Failed to extract gzipped archive {:?}: {}", tarball_path, e),
+                }
+            } else {
+                // For non-gzipped files, use directly
+                let mut archive = Archive::new(tar_file);
+                match archive.unpack(&hookos_dir) {
+                    Ok(_) => info!("Successfully extracted archive {:?}", tarball_path),
+                    Err(e) => warn!("Failed to extract archive {:?}: {}", tarball_path, e),
+                }
+            }
+            
+            Ok::<_, anyhow::Error>(tarball_path)
+        }
+    }).collect::<Vec<_>>();
+    
+    // Execute all extractions in parallel
+    let extraction_results = futures::future::try_join_all(extraction_futures).await?;
+    info!("All HookOS artifacts extracted in parallel successfully");
+    
+    // Remove all tarballs in parallel
+    let cleanup_futures = extraction_results.into_iter().map(|tarball_path| {
+        async move {
+            // Remove the tarball after extraction
+            if let Err(e) = std::fs::remove_file(&tarball_path) {
+                warn!("Failed to remove tarball {:?}: {}", tarball_path, e);
+            } else {
+                info!("Removed tarball {:?}", tarball_path);
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+    }).collect::<Vec<_>>();
+    
+    // Execute all cleanup operations in parallel
+    futures::future::try_join_all(cleanup_futures).await?;
+    
+    if let Err(e) = crate::stack::record_hook_version(hookos_dir, version) {
+        warn!("Failed to record HookOS version: {}", e);
+    }
+
+    info!("HookOS artifacts downloaded, extracted, and cleaned up successfully to {:?}", hookos_dir);
+    Ok(())
+}
+
+// OS information struct
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct OsInfo {
+    pub name: String,
+    pub icon: String,
+}
+
+// Get OS icon for a specific OS
+pub fn get_os_icon(os: &str) -> String {
+    let os_lower = os.to_lowercase();
+    match os_lower.as_str() {
+        os if os.contains("ubuntu") => "<i class=\"fab fa-ubuntu text-orange-500 dark:text-orange-500 no-invert\"></i>",
+        os if os.contains("debian") => "<i class=\"fab fa-debian text-red-500\"></i>",
+        "proxmox" => "<i class=\"fas fa-server text-blue-500\"></i>",
+        "talos" => "<i class=\"fas fa-robot text-purple-500\"></i>",
+        os if os.contains("windows") => "<i class=\"fab fa-windows text-blue-400\"></i>",
+        os if os.contains("rocky") => "<i class=\"fas fa-mountain text-green-500\"></i>",
+        os if os.contains("fedora") => "<i class=\"fab fa-fedora text-blue-600\"></i>",
+        os if os.contains("alma") => "<i class=\"fas fa-hat-cowboy text-amber-600\"></i>",
+        _ => "<i class=\"fas fa-square-question text-gray-500\"></i>", // Unknown OS
+    }.to_string()
+}
+
+// Make format_os_name public
+pub fn format_os_name(os: &str) -> String {
+    if let Some(known) = OsIdentifier::from_alias(os) {
+        return known.display_name().to_string();
+    }
+    let os_lower = os.to_lowercase();
+    
+    // Handle Ubuntu formats
+    if os_lower.contains("ubuntu") {
+        if os_lower.contains("22.04") || os_lower.contains("2204") {
+            return "Ubuntu 22.04".to_string();
+        } else if os_lower.contains("24.04") || os_lower.contains("2404") {
+            return "Ubuntu 24.04".to_string();
+        } else if let Some(version) = os_lower.split(&['(', ')', ' ', '-', '_'][..])
+                                              .find(|s| s.contains(".") && s.len() <= 6) {
+            return format!("Ubuntu {}", version);
+        } else {
+            return "Ubuntu".to_string();
+        }
+    }
+    
+    // Handle Debian formats
+    if os_lower.contains("debian") {
+        if os_lower.contains("12") || os_lower.contains("bookworm") {
+            return "Debian 12".to_string();
+        } else if let Some(version) = os_lower.split(&[' ', '(', ')', '-', '_'][..])
+                                              .find(|s| s.parse::<u32>().is_ok()) {
+            return format!("Debian {}", version);
+        } else {
+            return "Debian".to_string();
+        }
+    }
+    
+    // Return original string if no match
+    os.to_string()
+}
+
+// Get both OS name and icon
+pub fn get_os_info(os: &str) -> OsInfo {
+    OsInfo {
+        name: format_os_name(os),
+        icon: get_os_icon(os),
+    }
+}
+
+async fn update_installation_progress(
+    State(state): State<AppState>, // State is used for event manager
+    _auth_session: AuthSession, // Mark as unused - updates come from agent/tinkerbell
+    Path(id): Path<Uuid>,
+    Json(payload): Json<InstallationProgressUpdateRequest>,
+) -> Response {
+    // Remove admin check - allow agent/tinkerbell to post updates
+    /*
+    if let Err(response) = crate::auth::require_admin(&auth_session) {
+        return response;
+    }
+    */
+
+    if let Err(errors) = payload.validate() {
+        return validation_error_response(errors);
+    }
+
+    info!("Updating installation progress for machine {} to {}% (step: {:?})",
+          id, payload.progress, payload.step);
+
+    match db::update_installation_progress(&id, payload.progress, payload.step.as_deref()).await {
+        Ok(true) => {
+            // Emit machine updated event so the UI fetches new progress HTML
+            let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            (StatusCode::OK, Json(json!({ "status": "progress_updated", "machine_id": id }))).into_response()
+        },
+        Ok(false) => {
+            let error_response = ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response)).into_response()
+        },
+        Err(e) => {
+            error!("Failed to update installation progress for machine {}: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+// Settings exposed over the JSON API (credentials are deliberately omitted)
+#[derive(Debug, serde::Serialize)]
+struct ApiSettingsResponse {
+    require_login: bool,
+    default_os: Option<String>,
+    setup_completed: bool,
+    retention: crate::retention::RetentionSettings,
+    last_retention_report: Option<crate::retention::PurgeReport>,
+    system: crate::system_config::SystemConfig,
+    branding: crate::branding::Branding,
+    server: crate::setup::ServerSettings,
+}
+
+// Partial settings update; omitted fields are left unchanged
+#[derive(Debug, Deserialize)]
+struct ApiSettingsUpdate {
+    require_login: Option<bool>,
+    default_os: Option<String>,
+    retention: Option<crate::retention::RetentionSettings>,
+    system: Option<crate::system_config::SystemConfig>,
+    branding: Option<crate::branding::Branding>,
+}
+
+fn settings_response(settings: &crate::auth::Settings) -> ApiSettingsResponse {
+    ApiSettingsResponse {
+        require_login: settings.require_login,
+        default_os: settings.default_os.clone(),
+        setup_completed: settings.setup_completed,
+        retention: settings.retention.clone(),
+        last_retention_report: crate::retention::last_report(),
+        system: settings.system.clone(),
+        branding: settings.branding.clone(),
+        server: settings.server.clone(),
+    }
+}
+
+async fn api_get_settings(State(state): State<AppState>, auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match state.repos.settings.get().await {
+        Ok(settings) => (StatusCode::OK, Json(settings_response(&settings))).into_response(),
+        Err(e) => {
+            error!("Failed to load settings: {}", e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+async fn api_update_settings(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<ApiSettingsUpdate>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let mut settings = match state.repos.settings.get().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load settings: {}", e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+        }
+    };
+
+    if let Some(require_login) = payload.require_login {
+        settings.require_login = require_login;
+    }
+    if let Some(default_os) = payload.default_os {
+        settings.default_os = Some(default_os).filter(|os| !os.is_empty());
+    }
+    if let Some(retention) = payload.retention {
+        settings.retention = retention;
+    }
+    if let Some(system) = payload.system {
+        match system.normalize() {
+            Ok(system) => settings.system = system,
+            Err(e) => {
+                return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                    error: "Invalid System Settings".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+        }
+    }
+    if let Some(branding) = payload.branding {
+        match branding.normalize() {
+            Ok(branding) => settings.branding = branding,
+            Err(e) => {
+                return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                    error: "Invalid Branding".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+        }
+    }
+
+    if let Err(e) = state.repos.settings.save(&settings).await {
+        error!("Failed to save settings: {}", e);
+        let error_response = ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        };
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+    }
+
+    *state.settings.lock().await = settings.clone();
+    (StatusCode::OK, Json(settings_response(&settings))).into_response()
+}
+
+// The setup wizard runs before anyone can have logged in, so it's open until setup is completed
+async fn setup_unauthorized(state: &AppState, auth_session: &AuthSession) -> Option<Response> {
+    if auth_session.user.is_some() || !state.settings.lock().await.setup_completed {
+        return None;
+    }
+    Some((StatusCode::UNAUTHORIZED, Json(json!({
+        "error": "Unauthorized",
+        "message": "Admin authentication required for this operation"
+    }))).into_response())
+}
+
+// What the setup wizard needs to offer choices: interfaces, operating systems and current values
+async fn api_get_setup(State(state): State<AppState>, auth_session: AuthSession) -> Response {
+    if let Some(response) = setup_unauthorized(&state, &auth_session).await {
+        return response;
+    }
+
+    let settings = state.settings.lock().await.clone();
+    let config = crate::config::get();
+    Json(json!({
+        "setup_completed": settings.setup_completed,
+        "admin_username": settings.admin_username,
+        "interfaces": crate::setup::host_interfaces().await,
+        "operating_systems": OsIdentifier::ALL.iter()
+            .map(|os| json!({"id": os, "name": os.display_name()}))
+            .collect::<Vec<_>>(),
+        "default_os": settings.default_os,
+        "network_interface": settings.server.network_interface,
+        "port": config.server.port,
+        "base_url": crate::config::base_url(),
+        "base_url_configured": config.server.base_url.is_some(),
+        "artifact_dir": artifact_base_dir(),
+        "artifact_dir_configured": config.storage.artifact_dir.is_some(),
+    })).into_response()
+}
+
+// Save the setup wizard's answers and mark setup completed in one transaction
+async fn api_complete_setup(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<crate::setup::SetupRequest>,
+) -> Response {
+    if let Some(response) = setup_unauthorized(&state, &auth_session).await {
+        return response;
+    }
+
+    let notifications = match db::get_notification_settings().await {
+        Ok(notifications) => notifications,
+        Err(e) => {
+            error!("Failed to load notification settings: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    let interfaces = crate::setup::host_interfaces().await;
+    let setup = match payload.validate(&interfaces, crate::config::get().server.port, &notifications) {
+        Ok(setup) => setup,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Setup".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    if let Some(dir) = &setup.server.artifact_dir {
+        if let Err(e) = crate::setup::prepare_artifact_dir(dir).await {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Setup".to_string(),
+                message: format!("{:#}", e),
+            })).into_response();
+        }
+    }
+
+    let username = state.settings.lock().await.admin_username.clone();
+    let credentials = match crate::auth::Credentials::create(username, setup.admin_password.clone()) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            error!("Failed to hash the admin password: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Internal Server Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    if let Err(e) = db::complete_setup(&credentials, &setup).await {
+        error!("Failed to complete setup: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    {
+        let mut settings = state.settings.lock().await;
+        settings.setup_completed = true;
+        settings.default_os = setup.default_os.clone();
+        settings.server = setup.server.clone();
+        settings.admin_password_hash = credentials.password_hash.clone();
+    }
+    crate::setup::apply(&setup.server);
+    // The generated password no longer works
+    if StdPath::new("initial_password.txt").exists() {
+        if let Err(e) = fs::remove_file("initial_password.txt").await {
+            warn!("Failed to remove initial_password.txt: {}", e);
+        }
+    }
+    let _ = state.event_manager.send("setup_completed".to_string());
+
+    // A deployment mode is still to be picked on a fresh install
+    let next = match crate::mode::get_current_mode().await {
+        Ok(Some(_)) => "/",
+        _ => "/welcome",
+    };
+    info!("Setup completed through the wizard");
+    Json(json!({
+        "setup_completed": true,
+        "server": setup.server,
+        "next": crate::base_path::url(next),
+    })).into_response()
+}
+
+// Run the retention job immediately and return what it purged
+async fn api_run_retention(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let settings = match db::get_app_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load settings: {}", e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+        }
+    };
+
+    match crate::retention::run_cleanup(&settings.retention).await {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => {
+            error!("Retention cleanup failed: {}", e);
+            let error_response = ErrorResponse {
+                error: "Retention Cleanup Failed".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ResolveMachineQuery {
+    q: String,
+}
+
+// Resolve a hostname, memorable name, MAC or UUID (or a fragment of one) to machines
+async fn api_resolve_machine(axum::extract::Query(query): axum::extract::Query<ResolveMachineQuery>) -> Response {
+    match db::get_all_machines().await {
+        Ok(machines) => {
+            let matches = crate::resolve::resolve_machines(&query.q, &machines);
+            (StatusCode::OK, Json(MachineResolveResponse { query: query.q, matches })).into_response()
+        },
+        Err(e) => {
+            error!("Failed to load machines for resolution: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Issue a shareable link to a machine's public install status page
+async fn api_create_status_link(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        },
+        Err(e) => {
+            error!("Failed to fetch machine {} for status link: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    let memorable_name = match &machine.memorable_name {
+        Some(name) => name.clone(),
+        None => {
+            return (StatusCode::CONFLICT, Json(ErrorResponse {
+                error: "No Memorable Name".to_string(),
+                message: format!("Machine {} has no memorable name to build a status link from", id),
+            })).into_response();
+        }
+    };
+
+    match crate::access_tokens::issue(crate::access_tokens::TokenScope::MachineStatus, Some(&machine.id)).await {
+        Ok(token) => (StatusCode::OK, Json(json!({
+            "token": token,
+            "url": crate::base_path::url(&format!("/status/{}?token={}", memorable_name, token)),
+        }))).into_response(),
+        Err(e) => {
+            error!("Failed to issue status token for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ShareLinkRequest {
+    ttl_hours: Option<u32>,
+}
+
+// Issue an expiring, signed link to a machine's status page for someone without an account
+async fn api_create_share_link(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    payload: Option<Json<ShareLinkRequest>>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let ttl_hours = payload.map(|Json(p)| p).unwrap_or_default().ttl_hours
+        .unwrap_or(crate::share_links::DEFAULT_TTL_HOURS);
+    if ttl_hours == 0 || ttl_hours > crate::share_links::MAX_TTL_HOURS {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Expiry".to_string(),
+            message: format!("ttl_hours must be between 1 and {}", crate::share_links::MAX_TTL_HOURS),
+        })).into_response();
+    }
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        },
+        Err(e) => {
+            error!("Failed to fetch machine {} for share link: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    let Some(memorable_name) = machine.memorable_name.clone() else {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "No Memorable Name".to_string(),
+            message: format!("Machine {} has no memorable name to build a share link from", id),
+        })).into_response();
+    };
+
+    let (token, expires_at) = crate::share_links::issue(&machine.id, ttl_hours);
+    info!("Issued share link for machine {} expiring {}", id, expires_at);
+    (StatusCode::OK, Json(json!({
+        "url": crate::base_path::url(&format!("/status/{}?share={}", memorable_name, token)),
+        "expires_at": expires_at,
+    }))).into_response()
+}
+
+// Invalidate every share link issued for a machine so far
+async fn api_revoke_share_links(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::revoke_share_links(&id).await {
+        Ok(revoked_before) => {
+            info!("Revoked share links for machine {}", id);
+            Json(json!({ "revoked_before": revoked_before })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to revoke share links for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Agents in daemon mode hold this open so the server can reach them, e.g. for terminals
+async fn agent_channel_ws(ws: WebSocketUpgrade, Path(id): Path<Uuid>, headers: HeaderMap) -> Response {
+    // Only agents holding the enrollment token get the channel; anyone else could
+    // otherwise replace a machine's channel and receive its admin terminal sessions
+    if let Err(response) = crate::agent_signing::authorize_channel(&headers, &id).await {
+        return response;
+    }
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(_)) => ws.on_upgrade(move |socket| crate::terminal::run_agent_channel(socket, id)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine with ID {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to fetch machine {} for agent channel: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TerminalQuery {
+    cols: Option<u16>,
+    rows: Option<u16>,
+}
+
+// Open an audited shell on a machine whose agent is connected
+async fn machine_terminal_ws(
+    auth_session: AuthSession,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+    Path(id): Path<Uuid>,
+    axum::extract::Query(query): axum::extract::Query<TerminalQuery>,
+) -> Response {
+    let Some(user) = auth_session.user else {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    };
+
+    // Browsers send cookies on cross-site websocket requests, so only accept our own pages
+    let origin_host = headers.get(axum::http::header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|origin| origin.split_once("://").map(|(_, host)| host.to_string()));
+    let host = headers.get(axum::http::header::HOST).and_then(|v| v.to_str().ok());
+    if origin_host.is_some() && origin_host.as_deref() != host {
+        return (StatusCode::FORBIDDEN, Json(ErrorResponse {
+            error: "Forbidden".to_string(),
+            message: "Terminal connections must come from the Dragonfly UI".to_string(),
+        })).into_response();
+    }
+
+    if !crate::terminal::agent_connected(&id) {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Agent Not Connected".to_string(),
+            message: format!("The agent on machine {} is not running in daemon mode", id),
+        })).into_response();
+    }
+
+    let (cols, rows) = (query.cols.unwrap_or(80), query.rows.unwrap_or(24));
+    ws.on_upgrade(move |socket| crate::terminal::run_browser_session(socket, id, user.username, cols, rows))
+        .into_response()
+}
+
+// Audit log of terminal sessions opened on a machine
+async fn api_list_terminal_sessions(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_terminal_sessions(&id).await {
+        Ok(sessions) => Json(json!({
+            "agent_connected": crate::terminal::agent_connected(&id),
+            "sessions": sessions,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to list terminal sessions for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Issue a read-only token for a wallboard display
+async fn api_create_wallboard_token(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::access_tokens::issue(crate::access_tokens::TokenScope::Wallboard, None).await {
+        Ok(token) => (StatusCode::OK, Json(json!({
+            "token": token,
+            "url": format!("/wallboard?token={}", token),
+        }))).into_response(),
+        Err(e) => {
+            error!("Failed to issue wallboard token: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// List registrations held because their hardware matches a known machine
+async fn api_list_pending_merges(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_pending_merges().await {
+        Ok(merges) => (StatusCode::OK, Json(merges)).into_response(),
+        Err(e) => {
+            error!("Failed to list pending merges: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Merge a held registration into the machine it matched
+async fn api_approve_merge(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::dedupe::approve(&id).await {
+        Ok(machine_id) => {
+            let _ = state.event_manager.send(format!("machine_updated:{}", machine_id));
+            (StatusCode::OK, Json(json!({ "success": true, "machine_id": machine_id }))).into_response()
+        },
+        Err(e) => {
+            error!("Failed to approve merge {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Merge Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Register a held registration as a separate machine
+async fn api_reject_merge(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::dedupe::reject(&id).await {
+        Ok(machine_id) => {
+            let _ = state.event_manager.send(format!("machine_discovered:{}", machine_id));
+            (StatusCode::OK, Json(json!({ "success": true, "machine_id": machine_id }))).into_response()
+        },
+        Err(e) => {
+            error!("Failed to reject merge {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Merge Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// List registrations held by the onboarding allowlist, pending and rejected
+async fn api_list_approvals(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_approvals().await {
+        Ok(approvals) => (StatusCode::OK, Json(approvals)).into_response(),
+        Err(e) => {
+            error!("Failed to list approvals: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Register a held machine
+async fn api_approve_registration(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::onboarding::approve(&id).await {
+        Ok(machine_id) => {
+            let _ = state.event_manager.send(format!("machine_discovered:{}", machine_id));
+            (StatusCode::OK, Json(json!({ "success": true, "machine_id": machine_id }))).into_response()
+        },
+        Err(e) => {
+            error!("Failed to approve registration {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Approval Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Turn a held machine away; its retries are refused from then on
+async fn api_reject_registration(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::onboarding::reject(&id).await {
+        Ok(approval) => (StatusCode::OK, Json(approval)).into_response(),
+        Err(e) => {
+            error!("Failed to reject registration {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Rejection Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Forget a held or rejected registration, so the machine is asked about afresh next time
+async fn api_delete_approval(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::delete_approval(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Approval {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete approval {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Server health at a glance, including Tinkerbell changes waiting for Kubernetes
+async fn api_status() -> Response {
+    match crate::tinkerbell_outbox::status().await {
+        Ok(tinkerbell) => Json(json!({
+            "server_version": env!("CARGO_PKG_VERSION"),
+            "is_leader": crate::leader::is_leader(),
+            "tinkerbell": tinkerbell,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load Tinkerbell backlog: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Queued Tinkerbell operations and unsynced hardware records, with their last errors
+async fn api_get_tinkerbell_outbox(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let pending = async {
+        let hardware = db::list_pending_tinkerbell_syncs().await?;
+        let operations = db::list_tinkerbell_ops().await?;
+        anyhow::Ok((hardware, operations))
+    };
+    match pending.await {
+        Ok((hardware, operations)) => Json(json!({
+            "hardware": hardware.iter().map(|sync| json!({
+                "machine_id": sync.machine_id,
+                "attempts": sync.attempts,
+                "last_error": sync.last_error,
+                "last_attempt_at": sync.last_attempt_at,
+            })).collect::<Vec<_>>(),
+            "operations": operations,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to list queued Tinkerbell changes: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Drop a queued Tinkerbell operation that's no longer wanted
+async fn api_delete_tinkerbell_op(auth_session: AuthSession, Path(id): Path<i64>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::delete_tinkerbell_op(id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Queued operation {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete queued Tinkerbell operation {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TemplateRenderQuery {
+    name: String,
+    machine_id: Option<Uuid>,
+    theme: Option<String>,
+}
+
+// Render a template with a real machine, or demo data without one, so template changes can be
+// checked without reaching the page that uses them
+async fn api_render_template(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+    headers: HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<TemplateRenderQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if !ui::is_previewable_template(&query.name) {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Template Name".to_string(),
+            message: format!("'{}' is not a template name; expected a path like machine_details.html", query.name),
+        })).into_response();
+    }
+
+    let demo_machines = ui::generate_demo_machines();
+    let (machine, machines) = match query.machine_id {
+        Some(id) => match db::get_machine_by_id(&id).await {
+            Ok(Some(machine)) => {
+                let machines = vec![machine.clone()];
+                (machine, machines)
+            }
+            Ok(None) => return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine {} not found", id),
+            })).into_response(),
+            Err(e) => {
+                error!("Failed to load machine {} for template preview: {}", id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                    error: "Database Error".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+        },
+        None => (demo_machines[0].clone(), demo_machines),
+    };
+
+    let theme = query.theme.unwrap_or_else(|| ui::get_theme_from_cookie(&headers));
+    let context = ui::template_preview_context(&machine, &machines, &theme);
+    match ui::render_to_string(&app_state, &query.name, context) {
+        Ok(html) => Html(html).into_response(),
+        Err(e) if e.kind() == minijinja::ErrorKind::TemplateNotFound => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Template Not Found".to_string(),
+            message: format!("No template named '{}'", query.name),
+        })).into_response(),
+        Err(e) => {
+            // Template authors want the line and the reason, so the full error goes back
+            let mut message = format!("{}\n{}", e, e.display_debug_info());
+            let mut source = std::error::Error::source(&e);
+            while let Some(cause) = source {
+                message.push_str(&format!("\ncaused by: {}", cause));
+                source = cause.source();
+            }
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse {
+                error: "Template Error".to_string(),
+                message,
+            })).into_response()
+        }
+    }
+}
+
+// List diagnostics bundles collected for a machine
+async fn api_list_diagnostics(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_diagnostics_bundles(&id).await {
+        Ok(bundles) => (StatusCode::OK, Json(bundles)).into_response(),
+        Err(e) => {
+            error!("Failed to list diagnostics bundles for machine {}: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+// Download a single diagnostics bundle as a JSON attachment
+async fn api_get_diagnostics_bundle(
+    auth_session: AuthSession,
+    Path((id, bundle_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_diagnostics_bundle(&id, &bundle_id).await {
+        Ok(Some(bundle_json)) => {
+            let disposition = format!("attachment; filename=\"diagnostics-{}-{}.json\"", id, bundle_id);
+            (
+                StatusCode::OK,
+                [
+                    (axum::http::header::CONTENT_TYPE, "application/json".to_string()),
+                    (axum::http::header::CONTENT_DISPOSITION, disposition),
+                ],
+                bundle_json,
+            ).into_response()
+        },
+        Ok(None) => {
+            let error_response = ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Diagnostics bundle {} not found for machine {}", bundle_id, id),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response)).into_response()
+        },
+        Err(e) => {
+            error!("Failed to load diagnostics bundle {} for machine {}: {}", bundle_id, id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+// Agents stream large files about their machine here (installer logs, dmesg, sosreports),
+// whole or in Content-Range pieces
+async fn api_upload_machine_report(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    signed: Option<axum::Extension<crate::agent_signing::SignedAgent>>,
+    Path((id, name)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    // Reports land on disk, so only take them from agents holding the enrollment token
+    if auth_session.user.is_none() && signed.is_none() {
+        warn!("Rejected unsigned report upload for machine {}", id);
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Reports must be signed with the enrollment token or uploaded by an admin",
+        }))).into_response();
+    }
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let target = format!("machine {} report {}", id, name);
+    if let Err(e) = crate::machine_reports::validate_name(&name) {
+        return artifact_upload_error(&target, e);
+    }
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {} for report upload: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    }
+    let range = match header("content-range").map(crate::artifact_upload::parse_content_range).transpose() {
+        Ok(range) => range,
+        Err(e) => return artifact_upload_error(&target, e),
+    };
+    let content_length = header("content-length").and_then(|v| v.parse().ok());
+
+    match crate::machine_reports::receive(&id, &name, range, content_length, header("content-type"), body).await {
+        Ok((progress, None)) => (StatusCode::ACCEPTED, Json(progress)).into_response(),
+        Ok((progress, Some(report))) => {
+            let _ = state.event_manager.send(format!("machine_report:{}:{}", id, report.id));
+            (StatusCode::CREATED, Json(json!({
+                "url": report.url(),
+                "report": report,
+                "upload": progress,
+            }))).into_response()
+        }
+        Err(e) => artifact_upload_error(&target, e),
+    }
+}
+
+// Files agents have uploaded for a machine, newest first
+async fn api_list_machine_reports(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_machine_reports(&id).await {
+        Ok(reports) => Json(reports.iter().map(|report| json!({
+            "url": report.url(),
+            "report": report,
+        })).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            error!("Failed to list reports for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Download an uploaded report as an attachment
+async fn api_get_machine_report(
+    auth_session: AuthSession,
+    Path((id, report_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let report = match db::get_machine_report(&id, &report_id).await {
+        Ok(Some(report)) => report,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Report {} not found for machine {}", report_id, id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to load report {} for machine {}: {}", report_id, id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    let path = crate::machine_reports::report_path(&report);
+    match read_file_as_stream(&path, None, None, None).await {
+        Ok((stream, size, _)) => {
+            let content_type = report.content_type.as_deref().unwrap_or("application/octet-stream");
+            let mut response = create_streaming_response(&report.url(), stream, content_type, size, None, None);
+            let disposition = format!("attachment; filename=\"{}-{}\"", id, report.name);
+            if let Ok(value) = HeaderValue::from_str(&disposition) {
+                response.headers_mut().insert(axum::http::header::CONTENT_DISPOSITION, value);
+            }
+            response
+        }
+        Err(e) => {
+            error!("Failed to read report file {}: {}", path.display(), e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Storage Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_delete_machine_report(
+    auth_session: AuthSession,
+    Path((id, report_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::machine_reports::delete(&id, &report_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Report {} not found for machine {}", report_id, id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete report {} for machine {}: {}", report_id, id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Add new handler for getting machine tags
+#[axum::debug_handler]
+async fn api_get_machine_tags(
+    Path(id): Path<Uuid>,
+) -> Response {
+    match get_machine_tags(&id).await {
+        Ok(tags) => (StatusCode::OK, Json(tags)).into_response(),
+        Err(e) => {
+            error!("Failed to get tags for machine {}: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: format!("Failed to retrieve tags: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+// Add new handler for updating machine tags
+#[axum::debug_handler]
+async fn api_update_machine_tags(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(tags): Json<Vec<String>>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if let Err(response) = crate::auth::require_admin(&auth_session) {
+        return response;
+    }
+
+    match db_update_machine_tags(&id, &tags).await {
+        Ok(true) => {
+            // Emit machine updated event
+            let _ = state.event_manager.send(format!("machine_updated:{}", id)); 
+            (StatusCode::OK, Json(json!({ "success": true, "message": "Tags updated" }))).into_response()
+        }
+                    Ok(false) => {
+            let error_response = ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response)).into_response()
+        }
+                Err(e) => {
+            error!("Failed to update tags for machine {}: {}", id, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: format!("Failed to update tags: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+// New handler to get the current installation status
+#[axum::debug_handler]
+async fn get_install_status() -> Response {
+    // Read the current state from the global static
+    let install_state_arc_mutex: Option<Arc<tokio::sync::Mutex<InstallationState>>> = {
+        // Acquire read lock, clone the Arc if it exists, then drop the lock immediately
+        INSTALL_STATE_REF.read().unwrap().as_ref().cloned()
+    };
+    
+    match install_state_arc_mutex {
+        Some(state_ref) => {
+            // Clone the state inside the read guard
+            let current_state = state_ref.lock().await.clone();
+            // Serialize the state to JSON
+             let payload = json!({
+                "status": current_state,
+                "message": current_state.get_message(),
+                "animation": current_state.get_animation_class(),
+            });
+            (StatusCode::OK, Json(payload)).into_response()
+        }
+        None => {
+            // Not in install mode
+             let payload = json!({
+                "status": "NotInstalling",
+                "message": "Dragonfly is not currently installing.",
+                "animation": "",
+            });
+            (StatusCode::OK, Json(payload)).into_response()
+        }
+    }
+}
+
+// Installer progress with stage history, read from the on-disk state so it survives installer restarts
+async fn get_install_progress() -> Response {
+    let progress = match crate::install_state::load().await {
+        Ok(progress) => progress,
+        Err(e) => {
+            error!("Failed to load install state: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Install State Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    let Some(progress) = progress else {
+        return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: "No installation has been started on this host".to_string(),
+        })).into_response();
+    };
+
+    // A running installer knows the live state; the file may lag it by one write
+    let live_state: Option<Arc<tokio::sync::Mutex<InstallationState>>> = {
+        INSTALL_STATE_REF.read().unwrap().as_ref().cloned()
+    };
+    let current = match live_state {
+        Some(state_ref) => state_ref.lock().await.clone(),
+        None => progress.current.clone(),
+    };
+
+    let history: Vec<serde_json::Value> = progress.history.iter()
+        .map(|record| json!({
+            "stage": record.stage,
+            "message": record.stage.get_message(),
+            "entered_at": record.entered_at,
+        }))
+        .collect();
+
+    Json(json!({
+        "status": current,
+        "message": current.get_message(),
+        "started_at": progress.started_at,
+        "updated_at": progress.updated_at,
+        "resume_stage": progress.resume_stage(),
+        "bootstrap_ip": progress.bootstrap_ip,
+        "history": history,
+    })).into_response()
+}
+
+// Per-node status of the k3s cluster Dragonfly runs on
+async fn get_cluster_nodes(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::status::get_cluster_nodes().await {
+        Ok(nodes) => Json(nodes).into_response(),
+        Err(e) => {
+            error!("Failed to list cluster nodes: {:#}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorResponse {
+                error: "Cluster Unavailable".to_string(),
+                message: format!("{:#}", e),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExternalClusterRequest {
+    kubeconfig: String,
+    /// Address PXE clients and agents use to reach Dragonfly on the cluster
+    public_ip: String,
+    storage_class: Option<String>,
+    /// Only run the capability checks
+    #[serde(default)]
+    validate_only: bool,
+}
+
+// Point Flight mode at an existing Kubernetes cluster (EKS, AKS, kubeadm, ...) instead of k3s.
+// Validates the cluster synchronously, then deploys Tinkerbell and Dragonfly in the background.
+async fn api_configure_external_cluster(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+    Json(request): Json<ExternalClusterRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if request.public_ip.parse::<std::net::IpAddr>().is_err() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: format!("'{}' is not a valid IP address", request.public_ip),
+        })).into_response();
+    }
+
+    let client = match crate::external_cluster::parse_kubeconfig(&request.kubeconfig) {
+        Ok(kubeconfig) => crate::external_cluster::client_for(kubeconfig).await,
+        Err(e) => Err(e),
+    };
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Kubeconfig".to_string(),
+                message: format!("{:#}", e),
+            })).into_response();
+        }
+    };
+
+    let report = crate::external_cluster::validate(&client, request.storage_class.as_deref()).await;
+    if !report.passed() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(report)).into_response();
+    }
+    if request.validate_only {
+        return Json(report).into_response();
+    }
+
+    let path = match crate::external_cluster::save_kubeconfig(&request.kubeconfig).await {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Failed to save external kubeconfig: {:#}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Internal Server Error".to_string(),
+                message: format!("{:#}", e),
+            })).into_response();
+        }
+    };
+    env::set_var("KUBECONFIG", &path);
+
+    let opts = crate::external_cluster::DeployOptions {
+        public_ip: request.public_ip,
+        // validate() only passes with a storage class chosen
+        storage_class: report.storage_class.clone().unwrap_or_default(),
+    };
+    let event_manager = app_state.event_manager.clone();
+    tokio::spawn(async move {
+        let result = match crate::external_cluster::deploy(client, &opts).await {
+            Ok(()) => crate::mode::configure_flight_mode().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                info!("Flight mode configured on external cluster");
+                let _ = event_manager.send("mode_configured:flight".to_string());
+            }
+            Err(e) => {
+                error!("Failed to configure Flight mode on external cluster: {:#}", e);
+                let _ = event_manager.send(format!("mode_configuration_failed:flight:{}", e));
+            }
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(report)).into_response()
+}
+
+// Editable Helm values for each managed component
+async fn api_get_component_values(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::components::all_values().await {
+        Ok(values) => Json(json!({
+            "components": values,
+            "redeploy_running": crate::components::redeploy_running(),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load component values: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Replace a component's saved values. Nothing changes in the cluster until a redeploy.
+async fn api_update_component_values(
+    auth_session: AuthSession,
+    Path(component): Path<String>,
+    Json(values): Json<crate::components::ComponentValues>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let Some(component) = crate::components::Component::parse(&component) else {
+        return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Unknown component '{}'", component),
+        })).into_response();
+    };
+    if let Err(e) = values.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    match db::save_component_values(component.as_str(), &values).await {
+        Ok(()) => Json(values).into_response(),
+        Err(e) => {
+            error!("Failed to save values for {}: {}", component.as_str(), e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct RedeployRequest {
+    /// Defaults to every component
+    components: Option<Vec<crate::components::Component>>,
+}
+
+// Re-render saved values and apply them with helm in the background
+async fn api_redeploy_components(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+    request: Option<Json<RedeployRequest>>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if crate::components::redeploy_running() {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Conflict".to_string(),
+            message: "A redeploy is already in progress".to_string(),
+        })).into_response();
+    }
+
+    let components = request
+        .and_then(|Json(r)| r.components)
+        .unwrap_or_else(|| crate::components::Component::ALL.to_vec());
+    let names: Vec<&str> = components.iter().map(|c| c.as_str()).collect();
+    info!("Redeploying components: {}", names.join(", "));
+
+    let event_manager = app_state.event_manager.clone();
+    tokio::spawn(async move {
+        match crate::components::redeploy(&components).await {
+            Ok(applied) => {
+                let applied: Vec<&str> = applied.iter().map(|c| c.as_str()).collect();
+                info!("Redeploy finished; applied values to: {}", applied.join(", "));
+                let _ = event_manager.send(format!("redeploy_completed:{}", applied.join(",")));
+            }
+            Err(e) => {
+                error!("Redeploy failed: {:#}", e);
+                let _ = event_manager.send(format!("redeploy_failed:{}", e));
+            }
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(json!({ "components": names }))).into_response()
+}
+
+// Deployed Tinkerbell stack versions checked against the support matrix
+async fn api_get_stack_conformance(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::stack::check_deployed().await {
+        Ok(report) => Json(json!({
+            "report": report,
+            "support_matrix": crate::stack::SUPPORT_MATRIX,
+            "upgrade_running": crate::components::redeploy_running(),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to check Tinkerbell stack versions: {:#}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorResponse {
+                error: "Cluster Unavailable".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Which replica holds the background task lease, as seen from this one
+async fn api_get_leader(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_lease(crate::leader::LEASE_NAME).await {
+        Ok(lease) => Json(json!({
+            "replica_id": crate::leader::replica_id(),
+            "is_leader": crate::leader::is_leader(),
+            "lease_expired": lease.as_ref().is_some_and(|l| l.expired(chrono::Utc::now())),
+            "lease": lease,
+            "lease_ttl_secs": crate::leader::LEASE_TTL.as_secs(),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load leader lease: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Upgrade the Tinkerbell stack to the recommended versions in the background
+async fn api_upgrade_stack(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if crate::components::redeploy_running() {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Conflict".to_string(),
+            message: "A redeploy is already in progress".to_string(),
+        })).into_response();
+    }
+
+    let target = crate::stack::SUPPORT_MATRIX[0].name;
+    info!("Upgrading Tinkerbell stack to {}", target);
+    let event_manager = app_state.event_manager.clone();
+    tokio::spawn(async move {
+        match crate::stack::upgrade().await {
+            Ok(report) if report.supported => {
+                info!("Tinkerbell stack upgrade finished");
+                let _ = event_manager.send("stack_upgrade_completed".to_string());
+            }
+            Ok(report) => {
+                warn!("Tinkerbell stack upgraded but still unsupported: {}", report.problems.join(", "));
+                let _ = event_manager.send(format!("stack_upgrade_failed:still unsupported: {}", report.problems.join(", ")));
+            }
+            Err(e) => {
+                error!("Tinkerbell stack upgrade failed: {:#}", e);
+                let _ = event_manager.send(format!("stack_upgrade_failed:{}", e));
+            }
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(json!({ "target": target }))).into_response()
+}
+
+#[derive(Deserialize)]
+struct ValidateTemplateQuery {
+    /// Look action images up in their registries (default true)
+    resolve_images: Option<bool>,
+}
+
+// Lint a custom template (YAML request body) and report what's wrong with it
+async fn api_validate_template(
+    auth_session: AuthSession,
+    axum::extract::Query(query): axum::extract::Query<ValidateTemplateQuery>,
+    body: String,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if body.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: "Send the template YAML as the request body".to_string(),
+        })).into_response();
+    }
+
+    let client = reqwest::Client::new();
+    let client = query.resolve_images.unwrap_or(true).then_some(&client);
+    Json(crate::template_validation::validate(&body, client).await).into_response()
+}
+
+fn os_policy_db_error(e: anyhow::Error) -> Response {
+    error!("OS policy database error: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+        error: "Database Error".to_string(),
+        message: e.to_string(),
+    })).into_response()
+}
+
+// OS policies in the order they're evaluated
+async fn api_list_os_policies(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_os_policies().await {
+        Ok(policies) => Json(policies).into_response(),
+        Err(e) => os_policy_db_error(e),
+    }
+}
+
+async fn api_create_os_policy(
+    auth_session: AuthSession,
+    Json(input): Json<crate::os_policy::OsPolicyInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if let Err(e) = input.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    let policy = input.into_policy(Uuid::new_v4(), Utc::now());
+    match db::save_os_policy(&policy).await {
+        Ok(()) => {
+            info!("Created OS policy '{}' ({})", policy.name, policy.id);
+            (StatusCode::CREATED, Json(policy)).into_response()
+        }
+        Err(e) => os_policy_db_error(e),
+    }
+}
+
+async fn api_update_os_policy(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(input): Json<crate::os_policy::OsPolicyInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if let Err(e) = input.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Bad Request".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    let existing = match db::get_os_policy(&id).await {
+        Ok(Some(existing)) => existing,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("OS policy {} not found", id),
+            })).into_response();
+        }
+        Err(e) => return os_policy_db_error(e),
+    };
+
+    // Keep the original creation time so tie-breaking between equal priorities is stable
+    let policy = input.into_policy(id, existing.created_at);
+    match db::save_os_policy(&policy).await {
+        Ok(()) => Json(policy).into_response(),
+        Err(e) => os_policy_db_error(e),
+    }
+}
+
+async fn api_delete_os_policy(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::delete_os_policy(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("OS policy {} not found", id),
+        })).into_response(),
+        Err(e) => os_policy_db_error(e),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct EvaluatePoliciesRequest {
+    /// Defaults to every machine awaiting assignment
+    machine_id: Option<Uuid>,
+    /// Candidate policies to try instead of the saved ones
+    policies: Option<Vec<crate::os_policy::OsPolicyInput>>,
+}
+
+// Dry run: which OS each machine would get, without assigning anything
+async fn api_evaluate_os_policies(
+    auth_session: AuthSession,
+    request: Option<Json<EvaluatePoliciesRequest>>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+
+    let policies = match request.policies {
+        Some(inputs) => {
+            if let Some(e) = inputs.iter().find_map(|p| p.validate().err()) {
+                return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                    error: "Bad Request".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+            // Preserve submission order among equal priorities
+            let now = Utc::now();
+            inputs.into_iter()
+                .enumerate()
+                .map(|(i, input)| input.into_policy(Uuid::new_v4(), now + chrono::Duration::milliseconds(i as i64)))
+                .collect()
+        }
+        None => match db::list_os_policies().await {
+            Ok(policies) => policies,
+            Err(e) => return os_policy_db_error(e),
+        },
+    };
+
+    let machines = match request.machine_id {
+        Some(id) => match db::get_machine_by_id(&id).await {
+            Ok(Some(machine)) => vec![machine],
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                    error: "Not Found".to_string(),
+                    message: format!("Machine {} not found", id),
+                })).into_response();
+            }
+            Err(e) => return os_policy_db_error(e),
+        },
+        None => match db::get_machines_by_status(MachineStatus::AwaitingAssignment).await {
+            Ok(machines) => machines,
+            Err(e) => return os_policy_db_error(e),
+        },
+    };
+
+    let default_os = match db::get_app_settings().await {
+        Ok(settings) => settings.default_os,
+        Err(e) => return os_policy_db_error(e),
+    };
+
+    let mut decisions = Vec::with_capacity(machines.len());
+    for machine in &machines {
+        match crate::os_policy::decide(machine, &policies, default_os.as_deref()).await {
+            Ok(decision) => decisions.push(decision),
+            Err(e) => return os_policy_db_error(e),
+        }
+    }
+    Json(decisions).into_response()
+}
+
+// Recent boot menu selections and the machines they were applied to
+async fn api_list_boot_selections(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_boot_selections().await {
+        Ok(selections) => Json(json!({
+            "enabled": crate::boot_menu::enabled(),
+            "selections": selections,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to list boot menu selections: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DiagnoseRequest {
+    target: String,
+}
+
+// Schedule a one-shot diagnostics boot; the machine's next network boot runs it, later boots are normal
+// Re-run OS and hardware detection, e.g. after the OS was changed outside Dragonfly. An
+// agent with its channel open does it straight away; otherwise the request waits for the
+// agent's next start, which always reports what it detects.
+async fn api_request_redetect(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(_)) => {},
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    }
+
+    let requested_at = Utc::now();
+    if let Err(e) = db::set_redetect_requested(&id, requested_at).await {
+        error!("Failed to record redetection request for machine {}: {}", id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    let via = if crate::terminal::request_redetect(&id) {
+        info!("Asked the agent on machine {} to redetect its OS and hardware", id);
+        "agent_channel"
+    } else {
+        info!("Agent for machine {} isn't connected; redetection will happen when it next starts", id);
+        "next_boot"
+    };
+    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    (StatusCode::ACCEPTED, Json(json!({
+        "machine_id": id,
+        "via": via,
+        "requested_at": requested_at,
+    }))).into_response()
+}
+
+async fn api_get_redetect(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_redetect_requested(&id).await {
+        Ok(requested_at) => Json(json!({
+            "machine_id": id,
+            "pending": requested_at.is_some(),
+            "requested_at": requested_at,
+            "agent_connected": crate::terminal::agent_connected(&id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load redetection request for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_request_diagnose(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<DiagnoseRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let Some(target) = crate::diagnostic_boot::DiagnosticTarget::parse(&payload.target) else {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Target".to_string(),
+            message: format!("Unknown diagnostic target '{}'", payload.target),
+        })).into_response();
+    };
+    if !target.is_available() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse {
+            error: "Artifact Missing".to_string(),
+            message: format!("No artifact uploaded for diagnostic target '{}'", target.as_str()),
+        })).into_response();
+    }
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    if machine.status == MachineStatus::InstallingOS {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Conflict".to_string(),
+            message: "Machine is installing an OS; wait for it to finish before running diagnostics".to_string(),
+        })).into_response();
+    }
+
+    let boot = crate::diagnostic_boot::DiagnosticBoot::new(id, &target);
+    if let Err(e) = db::create_diagnostic_boot(&boot).await {
+        error!("Failed to schedule diagnostics boot for machine {}: {}", id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("Scheduled '{}' diagnostics boot for machine {}", boot.target, id);
+    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    (StatusCode::CREATED, Json(json!({
+        "diagnostic_boot": boot,
+        "reports_results": target.reports_results(),
+    }))).into_response()
+}
+
+// A machine's diagnostics history, plus what it could be booted into
+async fn api_get_diagnose(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_diagnostic_boots(&id).await {
+        Ok(boots) => {
+            let targets: Vec<String> = crate::diagnostic_boot::available_targets().iter().map(|t| t.as_str()).collect();
+            Json(json!({
+                "available_targets": targets,
+                "diagnostic_boots": boots,
+            })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to list diagnostics boots for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_cancel_diagnose(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::cancel_diagnostic_boot(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} has no pending diagnostics boot", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to cancel diagnostics boot for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Results posted by the agent after a hardware-check boot
+async fn api_diagnose_report(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(report): Json<dragonfly_common::models::HardwareDiagnosticsReport>,
+) -> Response {
+    match db::complete_diagnostic_boot(&id, &report).await {
+        Ok(true) => {
+            let failed = report.checks.iter().filter(|c| !c.passed).count();
+            if failed == 0 {
+                info!("Hardware diagnostics passed on machine {}", id);
+            } else {
+                warn!("Hardware diagnostics on machine {} found {} failed checks", id, failed);
+            }
+            let _ = state.event_manager.send(format!("diagnostics_completed:{}", id));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} has no diagnostics boot awaiting results", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to store diagnostics report for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Stage timestamps reported by an install template from inside HookOS. Keyed by MAC since
+// that's what the workflow knows the machine by; only accepted while the machine installs.
+async fn api_install_telemetry(
+    Path(mac): Path<String>,
+    Json(report): Json<dragonfly_common::models::InstallTelemetryReport>,
+) -> Response {
+    let mac = mac.to_lowercase();
+    let machine = match db::get_machine_by_mac(&mac).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("No machine with MAC {}", mac),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine with MAC {}: {}", mac, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    if machine.status != MachineStatus::InstallingOS {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Not Installing".to_string(),
+            message: format!("Machine {} is not installing an OS", machine.id),
+        })).into_response();
+    }
+
+    match crate::install_telemetry::record(&machine, &report).await {
+        Ok(recorded) => Json(json!({ "recorded": recorded })).into_response(),
+        Err(e) => {
+            warn!("Rejected install telemetry for machine {}: {}", machine.id, e);
+            (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Telemetry".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PartitioningRequest {
+    partitioning: Option<String>,
+}
+
+// A machine's partitioning profile, how it parses, and which disks it would image
+fn partitioning_response(machine: &Machine, profile: Option<String>) -> Response {
+    match crate::disk_layout::DiskLayout::parse(profile.as_deref()) {
+        Ok(layout) => Json(json!({
+            "partitioning": profile,
+            "layout": layout,
+            "target_disks": layout.target_disks(&machine.disks),
+        })).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Partitioning Profile".to_string(),
+            message: e.to_string(),
+        })).into_response(),
+    }
+}
+
+async fn api_get_partitioning(Path(id): Path<Uuid>) -> Response {
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to load machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    match db::get_provisioning_config(&id).await {
+        Ok(config) => partitioning_response(&machine, config.and_then(|c| c.partitioning)),
+        Err(e) => {
+            error!("Failed to load provisioning config for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Set the partitioning profile (and with it multi-disk imaging) used by the next install
+async fn api_set_partitioning(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(request): Json<PartitioningRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to load machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    let profile = request.partitioning.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    if let Err(e) = crate::disk_layout::DiskLayout::parse(profile.as_deref()) {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Partitioning Profile".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    let result = match db::get_provisioning_config(&id).await {
+        Ok(config) => {
+            let config = crate::definition::ProvisioningConfig {
+                partitioning: profile.clone(),
+                ..config.unwrap_or_default()
+            };
+            db::save_provisioning_config(&id, &config).await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => partitioning_response(&machine, profile),
+        Err(e) => {
+            error!("Failed to save partitioning profile for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// The script install templates run to image additional disks
+async fn api_multi_disk_script() -> Response {
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], crate::disk_layout::IMAGING_SCRIPT).into_response()
+}
+
+// Per-disk progress from the multi-disk imaging script, keyed by MAC like the stage reports
+async fn api_disk_imaging_progress(
+    State(state): State<AppState>,
+    Path(mac): Path<String>,
+    Json(report): Json<crate::disk_layout::DiskProgressReport>,
+) -> Response {
+    let mac = mac.to_lowercase();
+    let machine = match db::get_machine_by_mac(&mac).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("No machine with MAC {}", mac),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine with MAC {}: {}", mac, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    if machine.status != MachineStatus::InstallingOS {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Not Installing".to_string(),
+            message: format!("Machine {} is not installing an OS", machine.id),
+        })).into_response();
+    }
+
+    match crate::disk_layout::record_progress(&machine.id, &machine.disks, &report).await {
+        Ok(()) => {
+            if report.state == crate::disk_layout::DiskImagingState::Failed {
+                warn!("Imaging {} failed on machine {}", report.device, machine.id);
+            }
+            let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Disk".to_string(),
+            message: e.to_string(),
+        })).into_response(),
+    }
+}
+
+async fn api_get_disk_progress(Path(id): Path<Uuid>) -> Response {
+    Json(crate::disk_layout::progress(&id).await).into_response()
+}
+
+// The script install templates run to verify the installed system before booting it
+async fn api_verify_script() -> Response {
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], crate::install_verification::VERIFY_SCRIPT).into_response()
+}
+
+// Post-install verification results, keyed by MAC like the stage reports
+async fn api_install_verification(
+    State(state): State<AppState>,
+    Path(mac): Path<String>,
+    Json(report): Json<dragonfly_common::models::InstallVerificationReport>,
+) -> Response {
+    let mac = mac.to_lowercase();
+    let machine = match db::get_machine_by_mac(&mac).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("No machine with MAC {}", mac),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine with MAC {}: {}", mac, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    if machine.status != MachineStatus::InstallingOS {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Not Installing".to_string(),
+            message: format!("Machine {} is not installing an OS", machine.id),
+        })).into_response();
+    }
+
+    match crate::install_verification::record(&machine, &report).await {
+        Ok(()) => {
+            let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("Failed to store install verification for machine {}: {}", machine.id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_get_install_verification(Path(id): Path<Uuid>) -> Response {
+    match db::get_install_verification(&id).await {
+        Ok(Some(verification)) => Json(verification).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} has no install verification report", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load install verification for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// How long each stage of the machine's current or last install took, as its installer reported
+async fn api_get_install_telemetry(Path(id): Path<Uuid>) -> Response {
+    match db::list_install_stage_timings(&id).await {
+        Ok(stages) => Json(stages).into_response(),
+        Err(e) => {
+            error!("Failed to load install stage timings for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Status changes with time spent in each, oldest first
+async fn api_get_machine_history(Path(id): Path<Uuid>) -> Response {
+    match crate::state_history::history(&id).await {
+        Ok(history) => Json(history).into_response(),
+        Err(e) => {
+            error!("Failed to load status history for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// The machine's boot loop warning, if it has one
+async fn api_get_boot_loop(Path(id): Path<Uuid>) -> Response {
+    match db::get_boot_loop(&id).await {
+        Ok(boot_loop) => Json(json!({ "boot_loop": boot_loop.is_some(), "details": boot_loop })).into_response(),
+        Err(e) => {
+            error!("Failed to load boot loop state for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Clear a boot loop warning once the cause is fixed, handing the machine back to automation
+async fn api_clear_boot_loop(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::boot_loop::clear(&id).await {
+        Ok(cleared) => {
+            if cleared {
+                info!("Boot loop warning cleared for machine {}", id);
+                let _ = state.event_manager.send(format!("machine_updated:{}", id));
+            }
+            Json(json!({ "boot_loop": false, "details": null })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to clear boot loop warning for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Upload a diagnostics artifact: "memtest" for the memtest86+ EFI binary, anything else a vendor ISO
+async fn api_upload_diagnostic_artifact(
+    auth_session: AuthSession,
+    Path(name): Path<String>,
+    body: Body,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let target = if name == "memtest" {
+        crate::diagnostic_boot::DiagnosticTarget::Memtest
+    } else if crate::diagnostic_boot::is_valid_artifact_name(&name) {
+        crate::diagnostic_boot::DiagnosticTarget::Vendor(name.clone())
+    } else {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Name".to_string(),
+            message: "Artifact names may only contain letters, digits, '-' and '_'".to_string(),
+        })).into_response();
+    };
+    let dest = artifact_base_dir().join(target.artifact_path().unwrap_or_default());
+
+    // Write to a temporary file first so a half-finished upload is never served
+    let partial = dest.with_extension("partial");
+    let result: anyhow::Result<u64> = async {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut file = fs::File::create(&partial).await?;
+        let mut stream = body.into_data_stream();
+        let mut written = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+        fs::rename(&partial, &dest).await?;
+        Ok(written)
+    }.await;
+
+    match result {
+        Ok(written) => {
+            info!("Stored diagnostics artifact '{}' ({} bytes) at {:?}", target.as_str(), written, dest);
+            (StatusCode::CREATED, Json(json!({
+                "target": target.as_str(),
+                "size": written,
+            }))).into_response()
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial).await;
+            error!("Failed to store diagnostics artifact '{}': {}", name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Upload Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Artifact uploads take an admin session or an artifact upload token, so build hosts can push without logging in
+async fn authorize_artifact_upload(auth_session: &AuthSession, headers: &HeaderMap) -> Result<(), Response> {
+    if auth_session.user.is_some() {
+        return Ok(());
+    }
+    let token = headers.get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    match crate::access_tokens::verify(token, crate::access_tokens::TokenScope::ArtifactUpload, None).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::UNAUTHORIZED, Json(ErrorResponse {
+            error: "Unauthorized".to_string(),
+            message: "Admin authentication or an artifact upload token is required".to_string(),
+        })).into_response()),
+        Err(e) => {
+            error!("Failed to verify artifact upload token: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response())
+        }
+    }
+}
+
+fn artifact_upload_error(target: &str, e: crate::artifact_upload::UploadError) -> Response {
+    use crate::artifact_upload::UploadError;
+    let (status, error) = match &e {
+        UploadError::Invalid(_) => (StatusCode::BAD_REQUEST, "Invalid Upload"),
+        UploadError::OffsetMismatch { received } => {
+            return (StatusCode::CONFLICT, Json(json!({
+                "error": "Offset Mismatch",
+                "message": e.to_string(),
+                "received": received,
+            }))).into_response();
+        }
+        UploadError::ChecksumMismatch { .. } => (StatusCode::UNPROCESSABLE_ENTITY, "Checksum Mismatch"),
+        UploadError::Busy => (StatusCode::CONFLICT, "Upload In Progress"),
+        UploadError::TooLarge { .. } => (StatusCode::PAYLOAD_TOO_LARGE, "Upload Too Large"),
+        UploadError::Other(_) => {
+            error!("Failed to store upload to {}: {}", target, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Upload Failed")
+        }
+    };
+    (status, Json(ErrorResponse { error: error.to_string(), message: e.to_string() })).into_response()
+}
+
+// Upload a file into the artifact store, whole or in Content-Range pieces
+async fn api_upload_artifact(
+    auth_session: AuthSession,
+    headers: HeaderMap,
+    Path(path): Path<String>,
+    body: Body,
+) -> Response {
+    if let Err(response) = authorize_artifact_upload(&auth_session, &headers).await {
+        return response;
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let path = match crate::artifact_upload::validate_path(&path) {
+        Ok(path) => path,
+        Err(e) => return artifact_upload_error(&path, e),
+    };
+    let range = match header("content-range").map(crate::artifact_upload::parse_content_range).transpose() {
+        Ok(range) => range,
+        Err(e) => return artifact_upload_error(&path, e),
+    };
+
+    match crate::artifact_upload::receive(&path, range, header("x-checksum-sha256"), body).await {
+        Ok(progress @ crate::artifact_upload::Progress::Partial { .. }) => (StatusCode::ACCEPTED, Json(progress)).into_response(),
+        Ok(progress) => (StatusCode::CREATED, Json(json!({
+            "path": path,
+            "url": crate::base_path::url(&format!("/ipxe/{}", path)),
+            "upload": progress,
+        }))).into_response(),
+        Err(e) => artifact_upload_error(&path, e),
+    }
+}
+
+// An artifact's size and the progress of any unfinished upload to it, for resuming
+async fn api_get_artifact(
+    auth_session: AuthSession,
+    headers: HeaderMap,
+    Path(path): Path<String>,
+) -> Response {
+    if let Err(response) = authorize_artifact_upload(&auth_session, &headers).await {
+        return response;
+    }
+
+    let path = match crate::artifact_upload::validate_path(&path) {
+        Ok(path) => path,
+        Err(e) => return artifact_upload_error(&path, e),
+    };
+    match crate::artifact_upload::info(&path).await {
+        Ok(info) if info.size.is_none() && info.upload.is_none() => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No artifact or upload at {}", path),
+        })).into_response(),
+        Ok(info) => Json(info).into_response(),
+        Err(e) => artifact_upload_error(&path, e.into()),
+    }
+}
+
+// Discard an unfinished upload so it can start over
+async fn api_abort_artifact_upload(
+    auth_session: AuthSession,
+    headers: HeaderMap,
+    Path(path): Path<String>,
+) -> Response {
+    if let Err(response) = authorize_artifact_upload(&auth_session, &headers).await {
+        return response;
+    }
+
+    let path = match crate::artifact_upload::validate_path(&path) {
+        Ok(path) => path,
+        Err(e) => return artifact_upload_error(&path, e),
+    };
+    match crate::artifact_upload::abort(&path).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No unfinished upload to {}", path),
+        })).into_response(),
+        Err(e) => artifact_upload_error(&path, e),
+    }
+}
+
+// Issue a token that lets a build host upload artifacts
+async fn api_create_artifact_upload_token(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::access_tokens::issue(crate::access_tokens::TokenScope::ArtifactUpload, None).await {
+        Ok(token) => (StatusCode::OK, Json(json!({
+            "token": token,
+            "url": "/api/artifacts/{path}",
+        }))).into_response(),
+        Err(e) => {
+            error!("Failed to issue artifact upload token: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// This primary's artifact manifest, signed with the replication key, for secondaries to pull
+async fn api_get_replication_manifest() -> Response {
+    let settings = match crate::replication::settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load replication settings: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    let key = match (settings.role, settings.key.as_deref()) {
+        (crate::replication::Role::Primary, Some(key)) => key.to_string(),
+        _ => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: "This server is not a replication primary".to_string(),
+            })).into_response();
+        }
+    };
+
+    let body = match crate::replication::build_manifest(&settings).await.and_then(|m| serde_json::to_vec(&m).map_err(Into::into)) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to build replication manifest: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Manifest Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    let signature = crate::replication::sign_manifest(&key, &body, Utc::now().timestamp());
+    (
+        StatusCode::OK,
+        [
+            ("content-type", "application/json".to_string()),
+            (dragonfly_common::signing::SIGNATURE_HEADER, signature),
+        ],
+        body,
+    ).into_response()
+}
+
+// A secondary's report of its last pull, signed with the replication key
+async fn api_post_replication_status(
+    Path(site): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let header = headers.get(dragonfly_common::signing::SIGNATURE_HEADER).and_then(|v| v.to_str().ok());
+    match crate::replication::receive_report(&site, header, &body).await {
+        Ok(status) => {
+            info!("Replication report from site {}: {}/{} artifacts in sync", site, status.in_sync, status.artifacts);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            warn!("Rejected replication report from site {}: {}", site, e);
+            (StatusCode::FORBIDDEN, Json(ErrorResponse {
+                error: "Forbidden".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Replication settings and every site's last reported status
+async fn api_get_replication(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let loaded = async {
+        let settings = crate::replication::settings().await?;
+        let sites = crate::replication::sites().await?;
+        anyhow::Ok((settings, sites))
+    };
+    match loaded.await {
+        Ok((settings, sites)) => Json(json!({
+            "settings": crate::replication::SettingsView::from(&settings),
+            "sites": sites,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load replication status: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_replication_settings(
+    auth_session: AuthSession,
+    Json(payload): Json<crate::replication::SettingsUpdate>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::replication::update(payload.settings, payload.key).await {
+        Ok(settings) => {
+            info!("Replication settings updated ({:?}, site {:?})", settings.role, settings.site);
+            Json(crate::replication::SettingsView::from(&settings)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Replication Settings".to_string(),
+            message: e.to_string(),
+        })).into_response(),
+    }
+}
+
+// Generate a new replication key; it's shown once and has to be given to every secondary
+async fn api_rotate_replication_key(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::replication::rotate_key().await {
+        Ok(key) => {
+            info!("Replication key rotated");
+            Json(json!({ "key": key })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to rotate replication key: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Pull from the primary now instead of waiting for the next interval (secondaries)
+async fn api_run_replication(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::replication::sync_now().await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Replication Unavailable".to_string(),
+            message: e.to_string(),
+        })).into_response(),
+    }
+}
+
+// Every installable OS: the built-ins plus custom images that are cached and ready
+async fn api_get_os_catalog(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::custom_images::catalog().await {
+        Ok(entries) => Json(json!({ "operating_systems": entries })).into_response(),
+        Err(e) => {
+            error!("Failed to build OS catalog: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_list_custom_images(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_custom_images().await {
+        Ok(images) => Json(json!({ "images": images })).into_response(),
+        Err(e) => {
+            error!("Failed to list custom images: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Which image versions the fleet runs; custom image versions in use stay cached
+async fn api_image_usage(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::custom_images::usage().await {
+        Ok(usage) => Json(json!({ "images": usage })).into_response(),
+        Err(e) => {
+            error!("Failed to summarize image usage: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UsageReportQuery {
+    format: Option<String>,
+    months: Option<u32>,
+}
+
+// Machine counts, monthly installs and artifact bandwidth, for chargeback and capacity reports
+async fn api_usage_report(
+    auth_session: AuthSession,
+    axum::extract::Query(query): axum::extract::Query<UsageReportQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let months = query.months.unwrap_or(crate::usage::DEFAULT_MONTHS);
+    if months == 0 || months > crate::usage::MAX_MONTHS {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Months".to_string(),
+            message: format!("months must be between 1 and {}", crate::usage::MAX_MONTHS),
+        })).into_response();
+    }
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Format".to_string(),
+                message: format!("Unknown format '{}' (expected json or csv)", other),
+            })).into_response();
+        }
+    };
+
+    let report = match crate::usage::report(months).await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Failed to build usage report: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    if csv {
+        let disposition = format!("attachment; filename=\"dragonfly-usage-{}.csv\"", report.generated_at.format("%Y-%m-%d"));
+        (
+            StatusCode::OK,
+            [
+                (axum::http::header::CONTENT_TYPE, "text/csv".to_string()),
+                (axum::http::header::CONTENT_DISPOSITION, disposition),
+            ],
+            report.to_csv(),
+        ).into_response()
+    } else {
+        Json(report).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct SlaReportQuery {
+    days: Option<u32>,
+}
+
+// Provisioning SLAs over the last `days`: discovery to Ready, and time spent in error states
+async fn api_sla_report(
+    auth_session: AuthSession,
+    axum::extract::Query(query): axum::extract::Query<SlaReportQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let days = query.days.unwrap_or(crate::state_history::DEFAULT_DAYS);
+    if days == 0 || days > crate::state_history::MAX_DAYS {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Days".to_string(),
+            message: format!("days must be between 1 and {}", crate::state_history::MAX_DAYS),
+        })).into_response();
+    }
+
+    match crate::state_history::report(days).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            error!("Failed to build SLA report: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// A year, well past any sensible retention
+const MAX_SENSOR_HOURS: i64 = 24 * 366;
+
+#[derive(Deserialize)]
+struct SensorsQuery {
+    hours: Option<i64>,
+}
+
+// Sensor samples from the machine's BMC over the last `hours` (default 24), plus the latest readings
+async fn api_get_machine_sensors(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    axum::extract::Query(query): axum::extract::Query<SensorsQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let hours = query.hours.unwrap_or(24);
+    if !(1..=MAX_SENSOR_HOURS).contains(&hours) {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Hours".to_string(),
+            message: format!("hours must be between 1 and {}", MAX_SENSOR_HOURS),
+        })).into_response();
+    }
+    let since = Utc::now() - chrono::Duration::hours(hours);
+    match db::sensor_samples_since(&id, &since).await {
+        Ok(samples) => Json(json!({
+            "machine_id": id,
+            "since": since,
+            "latest": crate::sensors::latest(&id),
+            "samples": samples,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load sensor samples for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WarrantyReportQuery {
+    days: Option<i64>,
+}
+
+// Machines whose warranty runs out within `days` (default: the alert window), soonest first
+async fn api_warranty_report(
+    auth_session: AuthSession,
+    axum::extract::Query(query): axum::extract::Query<WarrantyReportQuery>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let days = query.days.unwrap_or_else(crate::warranty::alert_days);
+    if days < 0 {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Days".to_string(),
+            message: "days must not be negative".to_string(),
+        })).into_response();
+    }
+    match crate::warranty::expiring(days).await {
+        Ok(machines) => Json(json!({
+            "within_days": days,
+            "machines": machines,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to build warranty report: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_get_machine_warranty(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_warranty(&id).await {
+        Ok(Some(warranty)) => Json(warranty).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No warranty information for machine {}", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load warranty for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Shared by the admin endpoint and the CI webhook
+async fn register_custom_image(state: &AppState, input: crate::custom_images::CustomImageInput) -> Response {
+    if let Err(e) = input.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Image".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    match crate::custom_images::register(input, state.event_manager.clone()).await {
+        Ok(image) => {
+            let _ = state.event_manager.send(format!("custom_image_registered:{}", image.name));
+            (StatusCode::ACCEPTED, Json(json!({
+                "image": image,
+                "os_choice": image.os_choice(),
+            }))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to register custom image: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_register_custom_image(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(input): Json<crate::custom_images::CustomImageInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    register_custom_image(&state, input).await
+}
+
+// Called by CI when a pipeline publishes a new image version; authenticated with an image webhook token
+async fn api_custom_image_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(input): Json<crate::custom_images::CustomImageInput>,
+) -> Response {
+    let token = headers.get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    match crate::access_tokens::verify(token, crate::access_tokens::TokenScope::ImageWebhook, None).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Rejected custom image webhook with an invalid token");
+            return (StatusCode::UNAUTHORIZED, Json(ErrorResponse {
+                error: "Unauthorized".to_string(),
+                message: "A valid image webhook token is required".to_string(),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to verify image webhook token: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    }
+
+    info!("Image webhook: '{}' version {} published", input.name, input.version);
+    register_custom_image(&state, input).await
+}
+
+async fn api_create_image_webhook_token(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::access_tokens::issue(crate::access_tokens::TokenScope::ImageWebhook, None).await {
+        Ok(token) => (StatusCode::OK, Json(json!({
+            "token": token,
+            "url": "/api/images/webhook",
+        }))).into_response(),
+        Err(e) => {
+            error!("Failed to issue image webhook token: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Retry caching an image now instead of waiting for the next prefetch pass
+async fn api_prefetch_custom_image(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(name): Path<String>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_custom_image(&name).await {
+        Ok(Some(_)) => {
+            let event_manager = state.event_manager.clone();
+            tokio::spawn(async move {
+                let _ = crate::custom_images::prefetch(&name, &event_manager).await;
+            });
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Custom image '{}' not found", name),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to look up custom image '{}': {}", name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Remove an image from the catalog. Its cached file is deleted; machines already installed are unaffected.
+async fn api_delete_custom_image(auth_session: AuthSession, Path(name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::custom_images::remove(&name).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Custom image '{}' not found", name),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete custom image '{}': {}", name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// The client address, preferring X-Real-IP like track_client_ip does
+fn request_ip(headers: &HeaderMap, addr: SocketAddr) -> std::net::IpAddr {
+    headers.get("X-Real-IP")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| addr.ip())
+}
+
+// Peer settings for the sync script; 404 tells it to fetch everything from the server
+async fn api_peer_config() -> Response {
+    if !crate::peer_distribution::enabled() {
+        return (StatusCode::NOT_FOUND, "Peer distribution is disabled").into_response();
+    }
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], crate::peer_distribution::script_config()).into_response()
+}
+
+#[derive(Deserialize)]
+struct PeerAnnounce {
+    index: String,
+    have: usize,
+    port: u16,
+}
+
+// Posted by syncing machines as they write chunks; form-encoded so busybox wget can send it
+async fn api_peer_announce(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Form(announce): Form<PeerAnnounce>,
+) -> Response {
+    if !crate::peer_distribution::enabled() {
+        return (StatusCode::NOT_FOUND, "Peer distribution is disabled").into_response();
+    }
+    let ip = request_ip(&headers, addr);
+    match crate::peer_distribution::announce(ip, announce.port, &announce.index, announce.have) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            warn!("Rejected peer announcement from {}: {}", ip, e);
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+    }
+}
+
+// Peers that can serve a chunk, one base URL per line
+async fn api_peer_lookup(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(hash): Path<String>,
+) -> Response {
+    if !crate::peer_distribution::enabled() {
+        return (StatusCode::NOT_FOUND, "Peer distribution is disabled").into_response();
+    }
+    let peers = crate::peer_distribution::peers_for(request_ip(&headers, addr), &hash);
+    let body: String = peers.iter().map(|p| format!("{}\n", p)).collect();
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], body).into_response()
+}
+
+async fn api_list_peers(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    Json(json!({
+        "enabled": crate::peer_distribution::enabled(),
+        "peers": crate::peer_distribution::status(),
+    })).into_response()
+}
+
+// Artifact bandwidth limits and who is using the bandwidth right now
+async fn api_get_bandwidth(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    Json(crate::bandwidth::status()).into_response()
+}
+
+#[derive(Deserialize)]
+struct AgentSigningUpdate {
+    mode: String,
+}
+
+// Whether agent payloads must be signed with the enrollment token
+async fn api_get_agent_signing(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::agent_signing::settings().await {
+        Ok(settings) => Json(crate::agent_signing::SigningStatus::from(&settings)).into_response(),
+        Err(e) => {
+            error!("Failed to load agent signing settings: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_agent_signing(auth_session: AuthSession, Json(update): Json<AgentSigningUpdate>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let Some(mode) = crate::agent_signing::SigningMode::parse(&update.mode) else {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Mode".to_string(),
+            message: format!("Unknown signing mode '{}' (expected off, optional or required)", update.mode),
+        })).into_response();
+    };
+    match crate::agent_signing::set_mode(mode).await {
+        Ok(settings) => {
+            info!("Agent payload signing set to {}", mode.as_str());
+            Json(crate::agent_signing::SigningStatus::from(&settings)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Signing Not Updated".to_string(),
+            message: e.to_string(),
+        })).into_response(),
+    }
+}
+
+// Generate a new enrollment token; it's only ever shown in this response
+async fn api_rotate_enrollment_token(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::agent_signing::rotate_token().await {
+        Ok(token) => {
+            info!("Agent enrollment token rotated");
+            Json(json!({ "token": token })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to rotate enrollment token: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Connection pool utilization and recent slow database operations
+async fn api_get_db_stats(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_db_stats().await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => {
+            error!("Failed to collect database stats: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Prometheus scrape endpoint
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let body = crate::bandwidth::render_metrics()
+        + &state.event_manager.metrics().render_prometheus()
+        + &crate::sensors::render_metrics();
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+async fn api_get_desired_state(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_desired_state(&id).await {
+        Ok(Some(state)) => Json(state).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} has no desired state", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load desired state for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Set how a machine should be; the reconciler converges it on its next pass
+async fn api_put_desired_state(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<crate::desired_state::DesiredStateInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if let Err(e) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Desired State".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    if payload.boot_profile == crate::desired_state::BootProfile::Memtest && !crate::boot_menu::memtest_available() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse {
+            error: "Artifact Missing".to_string(),
+            message: format!("Upload {} before using the memtest boot profile", crate::boot_menu::MEMTEST_ARTIFACT),
+        })).into_response();
+    }
+
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    }
+
+    let desired = payload.into_state(id);
+    if let Err(e) = db::save_desired_state(&desired).await {
+        error!("Failed to save desired state for machine {}: {}", id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("Desired state updated for machine {}", id);
+    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    Json(desired).into_response()
+}
+
+// Stop managing a machine; its current state is left as it is
+async fn api_delete_desired_state(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::delete_desired_state(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} has no desired state", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete desired state for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(serde::Serialize, Deserialize)]
+struct SecureBootSetting {
+    enabled: bool,
+}
+
+// Whether a machine boots through the signed shim/GRUB chain instead of iPXE
+async fn api_get_secure_boot(Path(id): Path<Uuid>) -> Response {
+    match db::is_secure_boot_enabled(&id).await {
+        Ok(enabled) => Json(SecureBootSetting { enabled }).into_response(),
+        Err(e) => {
+            error!("Failed to check Secure Boot for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_secure_boot(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<SecureBootSetting>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if payload.enabled && !crate::secure_boot::artifacts_available() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse {
+            error: "Artifact Missing".to_string(),
+            message: format!(
+                "Place signed {} and {} in the {} artifact directory before enabling Secure Boot",
+                crate::secure_boot::SHIM_FILE, crate::secure_boot::GRUB_FILE, crate::secure_boot::ARTIFACT_SUBDIR
+            ),
+        })).into_response();
+    }
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    if let Err(e) = db::set_secure_boot(&id, payload.enabled).await {
+        error!("Failed to update Secure Boot for machine {}: {}", id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    // Re-register so Tinkerbell stops (or resumes) offering this machine iPXE
+    crate::tinkerbell_sync::sync_or_queue(&*state.tinkerbell, &machine).await;
+
+    info!("Secure Boot {} for machine {}", if payload.enabled { "enabled" } else { "disabled" }, id);
+    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    Json(payload).into_response()
+}
+
+// Where a machine differs from its desired state, and what the reconciler will do about it
+async fn api_get_drift(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::desired_state::drift_report(&id).await {
+        Ok(Some(report)) => Json(report).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} not found or has no desired state", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to compute drift for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_export_definition(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    // Definitions carry cloud-init user data, which often holds secrets
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let definition = match crate::definition::export(&id).await {
+        Ok(Some(definition)) => definition,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to export definition of machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    match definition.to_yaml() {
+        Ok(yaml) => {
+            let name = definition.hostname.clone().unwrap_or_else(|| id.to_string());
+            let disposition = format!("attachment; filename=\"{}.definition.yaml\"", name);
+            (
+                StatusCode::OK,
+                [
+                    (axum::http::header::CONTENT_TYPE, "application/yaml".to_string()),
+                    (axum::http::header::CONTENT_DISPOSITION, disposition),
+                ],
+                yaml,
+            ).into_response()
+        }
+        Err(e) => {
+            error!("Failed to serialize definition of machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Serialization Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_import_definition(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    body: String,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let definition = match crate::definition::MachineDefinition::from_yaml(&body) {
+        Ok(definition) => definition,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Definition".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    match crate::definition::import(&definition, &id, state.event_manager.clone()).await {
+        Ok(Some(report)) => (StatusCode::OK, Json(report)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine with ID {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to import definition onto machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Import Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CloneProfileRequest {
+    name: String,
+}
+
+// Capture a machine's OS choice, provisioning config and tags as a reusable profile
+async fn api_clone_profile(
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(request): Json<CloneProfileRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let name = match crate::provisioning_profiles::validate_name(&request.name) {
+        Ok(name) => name,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Profile".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    match db::provisioning_profile_name_taken(&name).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return (StatusCode::CONFLICT, Json(ErrorResponse {
+                error: "Conflict".to_string(),
+                message: format!("A profile named '{}' already exists", name),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up provisioning profiles: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    }
+
+    match crate::provisioning_profiles::capture(&id, &name).await {
+        Ok(Some(profile)) => (StatusCode::CREATED, Json(profile)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine with ID {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to clone machine {} into a profile: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Clone Failed".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_list_profiles(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_provisioning_profiles().await {
+        Ok(profiles) => Json(profiles).into_response(),
+        Err(e) => {
+            error!("Failed to list provisioning profiles: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Load a profile, mapping a missing one to 404
+async fn load_profile(id: &Uuid) -> Result<crate::provisioning_profiles::ProvisioningProfile, Response> {
+    match db::get_provisioning_profile(id).await {
+        Ok(Some(profile)) => Ok(profile),
+        Ok(None) => Err((StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Profile with ID {} not found", id),
+        })).into_response()),
+        Err(e) => {
+            error!("Failed to load provisioning profile {}: {}", id, e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response())
+        }
+    }
+}
+
+async fn api_get_profile(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match load_profile(&id).await {
+        Ok(profile) => Json(profile).into_response(),
+        Err(response) => response,
+    }
+}
+
+// Delete a profile; tags using it as their default are left without one
+async fn api_delete_profile(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::delete_provisioning_profile(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Profile with ID {} not found", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete provisioning profile {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ApplyProfileRequest {
+    machine_ids: Vec<Uuid>,
+}
+
+// Apply a profile to each listed machine
+async fn api_apply_profile(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ApplyProfileRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let profile = match load_profile(&id).await {
+        Ok(profile) => profile,
+        Err(response) => return response,
+    };
+
+    let mut applied = Vec::new();
+    let mut not_found = Vec::new();
+    let mut failed = Vec::new();
+    for machine_id in request.machine_ids {
+        match crate::provisioning_profiles::apply(&profile, &machine_id, state.event_manager.clone()).await {
+            Ok(true) => applied.push(machine_id),
+            Ok(false) => not_found.push(machine_id),
+            Err(e) => {
+                error!("Failed to apply profile '{}' to machine {}: {}", profile.name, machine_id, e);
+                failed.push(json!({ "machine_id": machine_id, "error": e.to_string() }));
+            }
+        }
+    }
+    Json(json!({ "profile_id": id, "applied": applied, "not_found": not_found, "failed": failed })).into_response()
+}
+
+// Parse a fleet document and plan it, mapping failures to 400/422 responses
+async fn plan_fleet_document(body: &str) -> Result<crate::fleet::Plan, Response> {
+    let document = crate::fleet::parse(body).map_err(|e| {
+        (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Fleet Document".to_string(),
+            message: e.to_string(),
+        })).into_response()
+    })?;
+    crate::fleet::plan(&document).await.map_err(|e| {
+        error!("Failed to plan fleet document: {}", e);
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse {
+            error: "Plan Failed".to_string(),
+            message: e.to_string(),
+        })).into_response()
+    })
+}
+
+async fn api_fleet_plan(auth_session: AuthSession, body: String) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match plan_fleet_document(&body).await {
+        Ok(plan) => (StatusCode::OK, Json(plan)).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn api_fleet_apply(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    body: String,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let plan = match plan_fleet_document(&body).await {
+        Ok(plan) => plan,
+        Err(response) => return response,
+    };
+    if let Err(e) = crate::fleet::apply(&plan, state.event_manager.clone()).await {
+        error!("Failed to apply fleet document: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Apply Failed".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    (StatusCode::OK, Json(plan)).into_response()
+}
+
+// Middleware to track client IP address - fixed with proper state extraction
+// Now prioritizes X-Real-IP header
+pub async fn track_client_ip(
+    State(state): State<AppState>,             // State first
+    ConnectInfo(addr): ConnectInfo<SocketAddr>, // Then other FromRequestParts extractors
+    request: axum::http::Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> { // Return Result based on example
+    // Try to get IP from X-Real-IP header first
+    let real_ip_header = request.headers()
+        .get("X-Real-IP")
+        .and_then(|value| value.to_str().ok());
+
+    let ip = match real_ip_header {
+        Some(real_ip) => {
+            // Log that we found the header and its value
+            info!("[track_client_ip] Found X-Real-IP header: {}", real_ip);
+            real_ip.to_string()
+        },
+        None => {
+            // Log that the header was missing and we're falling back
+            let fallback_ip = addr.ip().to_string();
+            info!("[track_client_ip] X-Real-IP header not found. Falling back to ConnectInfo IP: {}", fallback_ip);
+            fallback_ip
+        }
+    };
+
+    // Log the IP being stored in state
+    info!("[track_client_ip] Storing client IP in state: {}", ip);
+    *state.client_ip.lock().await = Some(ip);
+
+    // Proceed with the request
+    Ok(next.run(request).await)
+}
+
+// Function to add machine IP tracking to API router helpers
+async fn api_get_machine_by_ip(ip: &str) -> Option<Machine> {
+    match db::get_machine_by_ip(ip).await {
+        Ok(Some(machine)) => Some(machine),
+        _ => None,
+    }
+}
+
+// Add handler for deleting a specific machine tag
+#[axum::debug_handler]
+async fn api_delete_machine_tag(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path((id, tag)): Path<(Uuid, String)>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    // Get current tags for the machine
+    let result = match db::get_machine_tags(&id).await {
+        Ok(tags) => {
+            // Filter out the tag to delete
+            let new_tags: Vec<String> = tags.into_iter()
+                .filter(|t| t != &tag)
+                .collect();
+            
+            // Update with the filtered tags
+            match db::update_machine_tags(&id, &new_tags).await {
+                Ok(true) => {
+                    // Emit machine updated event
+                    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+                    (StatusCode::OK, Json(json!({"success": true, "message": "Tag deleted"})))
+                },
+                Ok(false) => {
+                    (StatusCode::NOT_FOUND, Json(json!({"error": "Machine not found"})))
+                },
+                Err(e) => {
+                    error!("Failed to update tags after deletion for machine {}: {}", id, e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+                        "error": "Database error", 
+                        "message": format!("Failed to update tags: {}", e)
+                    })))
+                }
+            }
+        },
+        Err(e) => {
+            error!("Failed to get tags for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+                "error": "Database error", 
+                "message": format!("Failed to retrieve tags: {}", e)
+            })))
+        }
+    };
+
+    result.into_response()
+}
+
+// NEW HANDLER for the partial update
+#[axum::debug_handler]
+async fn get_machine_status_and_progress_partial(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response { // Explicitly return Response
+    info!("Request for status-and-progress partial for machine {}", id);
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return (StatusCode::NOT_FOUND, Html("<!-- Machine not found -->")).into_response(),
+        Err(e) => {
+            error!("DB error fetching machine {} for partial: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html("<!-- DB Error -->")).into_response();
+        }
+    };
+
+    let workflow_info = if machine.status == MachineStatus::InstallingOS {
+        match crate::tinkerbell::get_workflow_info(&machine).await {
+            Ok(info_opt) => info_opt, // Can be Some(info) or None
+            Err(e) => {
+                error!("Tinkerbell error fetching workflow info for {}: {}", id, e);
+                None // Treat error as no info
+            }
+        }
+    } else {
+        None // Not installing, no workflow info needed
+    };
+
+    // Prepare context for the partial template
+    // Note: The partial will need access to machine and workflow_info
+    let context = json!({
+        "machine": machine,
+        "workflow_info": workflow_info, // Will be null if not installing or error
+    });
+
+    // Render the new partial template using render_minijinja directly
+    // REMOVE THE MATCH BLOCK BELOW
+    /*
+    match ui::render_minijinja(&state, "partials/status_and_progress.html", context) {
+        Ok(html) => (StatusCode::OK, Html(html)).into_response(), // Add .into_response() back
+        Err(e) => {
+            error!("Failed to render status_and_progress partial: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Html("<!-- Render Error -->")).into_response() // Add .into_response() back
+        }
+    }
+    */
+    // CALL THE FUNCTION DIRECTLY INSTEAD
+    ui::render_minijinja(&state, "partials/status_and_progress.html", context)
+}
+
+// Utility function to extract client IP
+
+// --- Tag Management API ---
+/// Get all tags in the system
+#[axum::debug_handler]
+async fn api_get_tags(
+    State(_state): State<AppState>,
+    auth_session: AuthSession,
+) -> Response {
+    // Check if user is authenticated as admin
+    if let Err(response) = crate::auth::require_admin(&auth_session) {
+        return response;
+    }
+
+    match db::get_all_tags().await {
+        Ok(tags) => (StatusCode::OK, Json(tags)).into_response(),
+        Err(e) => {
+            error!("Failed to get all tags: {}", e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: format!("Failed to retrieve tags: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+/// Create a new tag
+#[axum::debug_handler]
+async fn api_create_tag(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<serde_json::Value>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if let Err(response) = crate::auth::require_admin(&auth_session) {
+        return response;
+    }
+
+    // Extract tag name from JSON payload
+    let tag_name = match payload.get("name").and_then(|v| v.as_str()) {
+        Some(name) => name.to_string(),
+        None => {
+            return (
+                StatusCode::BAD_REQUEST, 
+                Json(json!({"error": "Missing tag name", "message": "Tag name is required"}))
+            ).into_response();
+        }
+    };
+
+    // Validate tag name - no empty tags
+    if tag_name.trim().is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Invalid tag name", "message": "Tag name cannot be empty"}))
+        ).into_response();
+    }
+
+    match db::create_tag(&tag_name).await {
+        Ok(true) => {
+            // Emit tag created event
+            let _ = state.event_manager.send("tags_updated".to_string());
+            (StatusCode::CREATED, Json(json!({"success": true, "message": "Tag created"}))).into_response()
+        },
+        Ok(false) => {
+            (
+                StatusCode::CONFLICT,
+                Json(json!({"error": "Tag exists", "message": "A tag with this name already exists"}))
+            ).into_response()
+        },
+        Err(e) => {
+            error!("Failed to create tag '{}': {}", tag_name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": "Database error", "message": format!("Failed to create tag: {}", e)}))
+            ).into_response()
+        }
+    }
+}
+
+/// Delete a tag from the system
+#[axum::debug_handler]
+async fn api_delete_tag(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if let Err(response) = crate::auth::require_admin(&auth_session) {
+        return response;
+    }
+
+    match db::delete_tag(&tag_name).await {
+        Ok(true) => {
+            // Emit tag deleted event
+            let _ = state.event_manager.send("tags_updated".to_string());
+            (StatusCode::OK, Json(json!({"success": true, "message": "Tag deleted"}))).into_response()
+        },
+        Ok(false) => {
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": "Not found", "message": "Tag not found"}))
+            ).into_response()
+        },
+        Err(e) => {
+            error!("Failed to delete tag '{}': {}", tag_name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": "Database error", "message": format!("Failed to delete tag: {}", e)}))
+            ).into_response()
+        }
+    }
+}
+
+/// Get all machines with a specific tag
+#[axum::debug_handler]
+async fn api_get_machines_by_tag(
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+) -> Response {
+    // Check if user is authenticated as admin
+    if let Err(response) = crate::auth::require_admin(&auth_session) {
+        return response;
+    }
+
+    match db::get_machines_by_tag(&tag_name).await {
+        Ok(machines) => (StatusCode::OK, Json(machines)).into_response(),
+        Err(e) => {
+            error!("Failed to get machines for tag {}: {}", tag_name, e);
+            let error_response = ErrorResponse {
+                error: "Database Error".to_string(),
+                message: format!("Failed to retrieve machines: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        }
+    }
+}
+
+// Re-register machines with Tinkerbell after their VLAN or boot NICs changed, so DHCP follows
+async fn resync_hardware(state: &AppState, machines: &[Machine]) {
+    for machine in machines {
+        crate::tinkerbell_sync::sync_or_queue(&*state.tinkerbell, machine).await;
+        let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+    }
+}
+
+// The VLAN a machine provisions on, and whether it comes from the machine or one of its tags
+async fn api_get_machine_vlan(Path(id): Path<Uuid>) -> Response {
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    match crate::vlan::for_machine(&machine).await {
+        Ok(vlan) => Json(vlan).into_response(),
+        Err(e) => {
+            error!("Failed to look up VLAN for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_machine_vlan(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<crate::vlan::VlanInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if let Some(Err(e)) = payload.vlan_id.map(crate::vlan::validate) {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid VLAN".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    if let Err(e) = db::set_machine_vlan(&id, payload.vlan_id).await {
+        error!("Failed to update VLAN for machine {}: {}", id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("VLAN for machine {} set to {:?}", id, payload.vlan_id);
+    resync_hardware(&state, std::slice::from_ref(&machine)).await;
+    match crate::vlan::for_machine(&machine).await {
+        Ok(vlan) => Json(vlan).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response(),
+    }
+}
+
+// Which NIC a multi-homed machine network-boots from, and the NICs it falls back to
+async fn api_get_boot_interfaces(Path(id): Path<Uuid>) -> Response {
+    match crate::boot_interface::for_machine(&id).await {
+        Ok(interfaces) => Json(interfaces).into_response(),
+        Err(e) => {
+            error!("Failed to load boot interfaces for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Save a boot interface preference, then push it to Tinkerbell and (if there is one) the
+// machine's Redfish BMC. A BMC that won't take the boot order doesn't fail the request.
+async fn api_put_boot_interfaces(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<crate::boot_interface::BootInterfacesInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let interfaces = match payload.validate(id) {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Boot Interfaces".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    // Another machine's NIC can't be claimed
+    for mac in interfaces.macs() {
+        let owner = match crate::boot_interface::machine_for_mac(mac).await {
+            Ok(owner) => owner,
+            Err(e) => {
+                error!("Failed to look up owner of MAC {}: {}", mac, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                    error: "Database Error".to_string(),
+                    message: e.to_string(),
+                })).into_response();
+            }
+        };
+        if let Some(owner) = owner.filter(|owner| owner.id != id) {
+            return (StatusCode::CONFLICT, Json(ErrorResponse {
+                error: "Conflict".to_string(),
+                message: format!("MAC {} belongs to machine {}", mac, owner.id),
+            })).into_response();
+        }
+    }
+
+    if let Err(e) = db::save_boot_interfaces(&interfaces).await {
+        error!("Failed to save boot interfaces for machine {}: {}", id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    info!("Machine {} now network-boots from {} (fallbacks: {:?})", id, interfaces.boot_mac, interfaces.fallback_macs);
+    resync_hardware(&state, std::slice::from_ref(&machine)).await;
+
+    let (bmc_boot_order, bmc_error) = match crate::sensors::redfish_credentials(&machine) {
+        Some(credentials) => {
+            let applied = match crate::sensors::redfish_client() {
+                Ok(client) => crate::boot_interface::apply_redfish_boot_order(&client, credentials, &interfaces).await,
+                Err(e) => Err(e.into()),
+            };
+            match applied {
+                Ok(order) => (Some(order), None),
+                Err(e) => {
+                    warn!("Failed to set the BMC boot order for machine {}: {:#}", id, e);
+                    (None, Some(format!("{:#}", e)))
+                }
+            }
+        }
+        None => (None, None),
+    };
+    Json(json!({
+        "interfaces": interfaces,
+        "bmc_boot_order": bmc_boot_order,
+        "bmc_error": bmc_error,
+    })).into_response()
+}
+
+async fn api_delete_boot_interfaces(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::delete_boot_interfaces(&id).await {
+        Ok(true) => {
+            info!("Boot interface preference for machine {} removed", id);
+            if let Ok(Some(machine)) = db::get_machine_by_id(&id).await {
+                resync_hardware(&state, std::slice::from_ref(&machine)).await;
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Machine {} has no boot interface preference", id),
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to delete boot interfaces for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// VLAN shared by every machine carrying a tag (fleet groups tag their members)
+async fn api_get_tag_vlan(auth_session: AuthSession, Path(tag_name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_tag_vlans().await {
+        Ok(vlans) => {
+            let vlan_id = vlans.into_iter().find(|(tag, _)| *tag == tag_name).map(|(_, vlan_id)| vlan_id);
+            Json(json!({ "tag": tag_name, "vlan_id": vlan_id })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to look up VLAN for tag {}: {}", tag_name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_tag_vlan(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+    Json(payload): Json<crate::vlan::VlanInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if let Some(Err(e)) = payload.vlan_id.map(crate::vlan::validate) {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid VLAN".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    if let Err(e) = db::set_tag_vlan(&tag_name, payload.vlan_id).await {
+        error!("Failed to update VLAN for tag {}: {}", tag_name, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("VLAN for tag {} set to {:?}", tag_name, payload.vlan_id);
+    match db::get_machines_by_tag(&tag_name).await {
+        Ok(machines) => resync_hardware(&state, &machines).await,
+        Err(e) => warn!("Failed to list machines tagged {} for Tinkerbell update: {}", tag_name, e),
+    }
+    Json(json!({ "tag": tag_name, "vlan_id": payload.vlan_id })).into_response()
+}
+
+// Timezone, NTP and locale a machine's next install gets (tags override the global settings)
+async fn api_get_machine_system_config(Path(id): Path<Uuid>) -> Response {
+    let machine = match db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => {
+            error!("Failed to look up machine {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    match crate::system_config::for_machine(&machine).await {
+        Ok(config) => Json(config).into_response(),
+        Err(e) => {
+            error!("Failed to look up system settings for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+// Timezone, NTP and locale overrides for every machine carrying a tag
+async fn api_get_tag_system_config(auth_session: AuthSession, Path(tag_name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_tag_system_configs().await {
+        Ok(configs) => {
+            let config = configs.into_iter().find(|(tag, _)| *tag == tag_name).map(|(_, config)| config).unwrap_or_default();
+            Json(json!({ "tag": tag_name, "system": config })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to look up system settings for tag {}: {}", tag_name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_tag_system_config(
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+    Json(payload): Json<crate::system_config::SystemConfig>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let config = match payload.normalize() {
+        Ok(config) => config,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid System Settings".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+
+    // An empty config removes the override
+    if let Err(e) = db::set_tag_system_config(&tag_name, Some(&config)).await {
+        error!("Failed to update system settings for tag {}: {}", tag_name, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("System settings for tag {} set to {:?}", tag_name, config);
+    Json(json!({ "tag": tag_name, "system": config })).into_response()
+}
+
+// Default provisioning profile for machines carrying a tag
+async fn api_get_tag_profile(auth_session: AuthSession, Path(tag_name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_tag_profiles().await {
+        Ok(defaults) => {
+            let profile = defaults.into_iter().find(|(tag, _)| *tag == tag_name).map(|(_, profile)| profile);
+            Json(json!({ "tag": tag_name, "profile": profile })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to look up the default profile for tag {}: {}", tag_name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TagProfileRequest {
+    profile_id: Uuid,
+}
+
+async fn api_put_tag_profile(
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+    Json(payload): Json<TagProfileRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let profile = match load_profile(&payload.profile_id).await {
+        Ok(profile) => profile,
+        Err(response) => return response,
+    };
+    if let Err(e) = db::set_tag_profile(&tag_name, Some(&profile.id)).await {
+        error!("Failed to set the default profile for tag {}: {}", tag_name, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("Default profile for tag {} set to '{}'", tag_name, profile.name);
+    Json(json!({ "tag": tag_name, "profile": profile })).into_response()
+}
+
+async fn api_delete_tag_profile(auth_session: AuthSession, Path(tag_name): Path<String>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    if let Err(e) = db::set_tag_profile(&tag_name, None).await {
+        error!("Failed to clear the default profile for tag {}: {}", tag_name, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("Cleared the default profile for tag {}", tag_name);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// Two-person rule for destructive actions
+async fn api_get_action_approval_settings(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match crate::action_approvals::settings().await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to load action approval settings: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_action_approval_settings(
+    auth_session: AuthSession,
+    Json(payload): Json<crate::action_approvals::ApprovalSettings>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let settings = match payload.normalize() {
+        Ok(settings) => settings,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Approval Settings".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    if let Err(e) = db::save_action_approval_settings(&settings).await {
+        error!("Failed to save action approval settings: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("Audit: two-person rule for destructive actions {} ({} approver(s))",
+        if settings.enabled { "enabled" } else { "disabled" }, settings.approvers.len());
+    Json(settings).into_response()
+}
+
+// Notification channels, with their quiet hours and digest settings
+async fn api_get_notification_settings(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_notification_settings().await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to load notification settings: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_notification_settings(
+    auth_session: AuthSession,
+    Json(mut payload): Json<crate::notifications::NotificationSettings>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    for channel in &mut payload.channels {
+        channel.name = channel.name.trim().to_string();
+    }
+    if let Err(e) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Notification Settings".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    if let Err(e) = db::save_notification_settings(&payload).await {
+        error!("Failed to save notification settings: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+
+    info!("Notification settings updated ({} channel(s))", payload.channels.len());
+    Json(payload).into_response()
+}
+
+// Onboarding mode and the MAC/OUI allowlist
+async fn api_get_onboarding_settings(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_onboarding_settings().await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to load onboarding settings: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
+    }
+}
+
+async fn api_put_onboarding_settings(
+    auth_session: AuthSession,
+    Json(payload): Json<crate::onboarding::OnboardingSettings>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let settings = match payload.normalize() {
+        Ok(settings) => settings,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Onboarding Settings".to_string(),
+                message: e.to_string(),
+            })).into_response();
+        }
+    };
+    if let Err(e) = db::save_onboarding_settings(&settings).await {
+        error!("Failed to save onboarding settings: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
 
-        info!(client_ip = %client_ip, event_payload = %ip_progress_event_payload, "[PROGRESS_SEND] Attempting to send ip_download_progress event NOW"); // ADDED LOUD LOG
-        let send_result = state.event_manager.send(ip_progress_event_string.clone()); // Clone for logging
-        
-        if let Err(e) = send_result {
-            warn!(client_ip = %client_ip, error = %e, "[PROGRESS_SEND] Failed to emit IP-based progress event: {}", ip_progress_event_string);
-        } else {
-            info!(client_ip = %client_ip, event_payload = %ip_progress_event_payload, "[PROGRESS_SEND] Successfully sent ip_download_progress event"); // ADDED SUCCESS LOG
+    info!("Onboarding settings updated ({:?}, {} allowlist entries)", settings.mode, settings.allowlist.len());
+    Json(settings).into_response()
+}
+
+// Read-only public inventory API: on/off, an optional port of its own and field redaction
+async fn api_get_public_api_settings(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::get_public_api_settings().await {
+        Ok(settings) => Json(json!({
+            "settings": settings,
+            "public_fields": crate::public_api::PUBLIC_FIELDS,
+        })).into_response(),
+        Err(e) => {
+            error!("Failed to load public API settings: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
         }
-    } // End of: if let Some(client_ip) = client_ip_guard.as_ref()
-    
-    debug!("Exiting track_download_progress");
+    }
 }
 
-// Modify stream_download_with_caching to track progress
-async fn stream_download_with_caching(
-    url: &str,
-    cache_path: &StdPath,
-    range_header: Option<&HeaderValue>, // Add parameter for Range header
-    machine_id: Option<Uuid>, // Add optional machine ID for tracking
-    state: Option<&AppState>, // Add optional state for event emission
-) -> Result<(ReceiverStream<Result<Bytes, Error>>, Option<u64>, Option<String>), Error> { // Return Content-Range
-    info!("[STREAM_DOWNLOAD] Beginning stream_download_with_caching for URL: {}, cache_path: {}, range: {:?}, machine_id: {:?}",
-          url, cache_path.display(), range_header.map(|h| h.to_str().unwrap_or("invalid")), machine_id);
+async fn api_put_public_api_settings(
+    auth_session: AuthSession,
+    Json(mut payload): Json<crate::public_api::PublicApiSettings>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
 
-    // Create parent directory if needed
-    if let Some(parent) = cache_path.parent() {
-        fs::create_dir_all(parent).await.map_err(|e| Error::Internal(format!("Failed to create directory: {}", e)))?;
+    payload.redacted_fields = payload.redacted_fields.iter()
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect();
+    if let Err(e) = payload.validate(crate::config::get().server.port) {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Public API Settings".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    let previous_port = db::get_public_api_settings().await.ok().and_then(|s| s.port);
+    if let Err(e) = db::save_public_api_settings(&payload).await {
+        error!("Failed to save public API settings: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
     }
 
-    // Check if file is already cached
-    if cache_path.exists() {
-        // Even when serving from cache, track progress for range requests
-        if let (Some(machine_id), Some(state), Some(range_val)) = (machine_id, state, range_header) {
-            if let Ok(range_str) = range_val.to_str() {
-                let file_size = fs::metadata(cache_path).await
-                    .map(|m| m.len())
-                    .unwrap_or(0);
-                    
-                if let Some((start, end)) = parse_range_header(range_str, file_size, None, Some(state)).await {
-                    let bytes_downloaded = end - start + 1;
-                    
-                    // Use the start position as a progress indicator for range requests
-                    // This gives a rough approximation of download progress across multiple range requests
-                    let effective_progress = start + bytes_downloaded;
-                    
-                    info!("[RANGE_PROGRESS] Cached file with range: start={}, end={}, bytes={}, total={}, effective_progress={}",
-                          start, end, bytes_downloaded, file_size, effective_progress);
-                          
-                    // Track download progress with the effective bytes downloaded
-                    tokio::spawn(track_download_progress(Some(machine_id), effective_progress, file_size, state.clone()));
-                }
-            }
-        }
-        
-        // info!("Serving cached artifact from: {:?}", cache_path); // Commented out log
-        return read_file_as_stream(cache_path, range_header, state, machine_id).await; // Pass Range header
+    info!("Public API {} ({} redacted field(s))", if payload.enabled { "enabled" } else { "disabled" }, payload.redacted_fields.len());
+    Json(json!({
+        "settings": payload,
+        "restart_required": payload.port != previous_port,
+    })).into_response()
+}
+
+// Power schedules: off-hours windows per fleet group during which Ready machines are powered off
+async fn api_get_power_settings(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
-    
-    info!("Downloading and caching artifact from: {}", url);
-    
-    // Start HTTP request with reqwest feature for streaming
-    let client = reqwest::Client::new();
-    let response = client.get(url).send().await.map_err(|e| Error::Internal(format!("HTTP request failed: {}", e)))?;
-    
-    if !response.status().is_success() {
-        return Err(Error::Internal(format!("HTTP error: {}", response.status())));
+
+    match db::get_power_settings().await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to load power settings: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
     }
-    
-    // Get content length if available
-    let content_length = response.content_length();
-    if let Some(length) = content_length {
-        info!("[PROGRESS_DEBUG] Download size from Content-Length: {} bytes", length);
-    } else {
-        info!("[PROGRESS_DEBUG] No Content-Length header received from remote server.");
+}
+
+async fn api_put_power_settings(
+    auth_session: AuthSession,
+    Json(mut payload): Json<crate::power::PowerSettings>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
-    
-    let file = fs::File::create(cache_path).await.map_err(|e| Error::Internal(format!("Failed to create cache file: {}", e)))?;
-    let file = Arc::new(tokio::sync::Mutex::new(file));
-    let (tx, rx) = mpsc::channel::<Result<Bytes, Error>>(32);
-    
-    let url_clone = url.to_string();
-    let cache_path_clone = cache_path.to_path_buf();
-    
-    // For tracking download progress
-    let total_size = content_length.unwrap_or(0);
-    let mut total_bytes_downloaded: u64 = 0;
-    let tracking_machine_id = machine_id;
-    let app_state_clone = state.cloned();
-    
-    tokio::spawn(async move {
-        let mut client_disconnected = false;
-        let mut download_error = false;
 
-        // Get the stream. `bytes_stream` consumes the response object.
-        let mut stream = response.bytes_stream(); 
+    for schedule in &mut payload.schedules {
+        schedule.name = schedule.name.trim().to_string();
+        schedule.tags = schedule.tags.iter()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+    }
+    if let Err(e) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "Invalid Power Settings".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
+    if let Err(e) = db::save_power_settings(&payload).await {
+        error!("Failed to save power settings: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response();
+    }
 
-        while let Some(chunk_result) = stream.next().await {
-            match chunk_result {
-                Ok(chunk) => {
-                    let chunk_clone = chunk.clone();
-                    let chunk_size = chunk.len() as u64;
-                    
-                    // Write chunk to cache file concurrently
-                    let file_clone = Arc::clone(&file);
-                    let write_handle = tokio::spawn(async move {
-                        let mut file = file_clone.lock().await;
-                        file.write_all(&chunk_clone).await
-                    });
+    info!("Power settings updated ({} schedule(s))", payload.schedules.len());
+    Json(payload).into_response()
+}
 
-                    // Update progress tracking
-                    total_bytes_downloaded += chunk_size;
-                    
-                    // ADDED LOG: Log chunk size and total downloaded
-                    debug!(url = %url_clone, chunk_size = chunk_size, total_bytes_downloaded = total_bytes_downloaded, total_size = total_size, "[STREAM_DOWNLOAD_LOOP] Downloaded chunk");
+#[derive(serde::Deserialize)]
+struct PowerActionRequest {
+    #[serde(default)]
+    machine_ids: Vec<Uuid>,
+    // A fleet group; its members are added to machine_ids
+    tag: Option<String>,
+}
 
-                    if let (Some(machine_id), Some(state)) = (tracking_machine_id, &app_state_clone) {
-                        if total_size > 0 {
-                            // ADDED LOG: Confirm call to track_download_progress
-                            debug!("[PROGRESS_DEBUG] Calling track_download_progress (machine_id: {}, downloaded: {}, total: {})", machine_id, total_bytes_downloaded, total_size);
-                            
-                            // ADDED LOG: Log before calling track_download_progress function
-                            debug!(url = %url_clone, machine_id = %machine_id, bytes_downloaded = total_bytes_downloaded, total_size = total_size, "[STREAM_DOWNLOAD_LOOP] PRE-PROGRESS CALL");
-                            
-                            track_download_progress(Some(machine_id), total_bytes_downloaded, total_size, state.clone()).await;
-                        }
-                    }
-                    
-                    // Attempt to send to client only if not already disconnected
-                    if !client_disconnected {
-                        if tx.send(Ok(chunk)).await.is_err() {
-                            warn!("Client stream receiver dropped for {}. Continuing download in background.", url_clone);
-                            client_disconnected = true;
-                            // DO NOT break here - let download continue for caching
-                        }
-                    }
+// The machines a power action is for, or an error response
+async fn power_action_machines(payload: &PowerActionRequest) -> Result<Vec<Machine>, Response> {
+    let db_error = |e: anyhow::Error| {
+        error!("Failed to load machines for power action: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response()
+    };
 
-                    // Await the write operation regardless of client connection status
-                    match write_handle.await { // Await the JoinHandle itself
-                        Ok(Ok(())) => {
-                            // Write successful, continue loop
-                        },
-                        Ok(Err(e)) => {
-                            // Write operation failed
-                            warn!("Failed to write chunk to cache file {}: {}", cache_path_clone.display(), e);
-                            download_error = true;
-                            break; // Abort download if we can't write to cache
-                        },
-                        Err(e) => {
-                            // Task failed (e.g., panicked)
-                            warn!("Cache write task failed (join error) for {}: {}", cache_path_clone.display(), e);
-                            download_error = true;
-                            break; // Abort download if write task fails
-                        }
-                    }
-                },
-                Err(e) => { // e is reqwest::Error here
-                    error!("Download stream error for {}: {}", url_clone, e);
-                    // Send error to client if still connected
-                    if !client_disconnected {
-                        let err = Error::Internal(format!("Download stream error: {}", e));
-                        if tx.send(Err(err)).await.is_err() {
-                             warn!("Client stream receiver dropped while sending download error for {}", url_clone);
-                             // Client disconnected while we were trying to send an error
-                             client_disconnected = true;
-                        }
-                    }
-                    download_error = true;
-                    break; // Stop processing on download error
-                }
+    let mut machines: Vec<Machine> = Vec::new();
+    for id in &payload.machine_ids {
+        match db::get_machine_by_id(id).await {
+            Ok(Some(machine)) => machines.push(machine),
+            Ok(None) => return Err((StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response()),
+            Err(e) => return Err(db_error(e)),
+        }
+    }
+    if let Some(tag) = payload.tag.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        for machine in db::get_machines_by_tag(tag).await.map_err(db_error)? {
+            if !machines.iter().any(|m| m.id == machine.id) {
+                machines.push(machine);
             }
-            // If download_error is true, the inner match already broke, so we'll exit.
         }
-        
-        // Explicitly drop the response stream to release network resources potentially sooner
-        drop(stream);
+    }
+    if machines.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse {
+            error: "No Machines".to_string(),
+            message: "Select machines with machine_ids or a tag".to_string(),
+        })).into_response());
+    }
+    Ok(machines)
+}
+
+fn power_client() -> Result<reqwest::Client, Response> {
+    crate::sensors::redfish_client().map_err(|e| {
+        error!("Failed to create Redfish HTTP client: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Internal Error".to_string(),
+            message: e.to_string(),
+        })).into_response()
+    })
+}
+
+fn power_result(machine_id: &Uuid, result: anyhow::Result<crate::power::PowerMethod>) -> serde_json::Value {
+    match result {
+        Ok(method) => json!({ "machine_id": machine_id, "ok": true, "method": method }),
+        Err(e) => json!({ "machine_id": machine_id, "ok": false, "error": format!("{:#}", e) }),
+    }
+}
+
+// Gracefully power off selected Ready machines until they're woken
+async fn api_fleet_hibernate(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<PowerActionRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let machines = match power_action_machines(&payload).await {
+        Ok(machines) => machines,
+        Err(response) => return response,
+    };
+    let client = match power_client() {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    let mut results = Vec::new();
+    for machine in &machines {
+        let result = match crate::maintenance::status(&machine.id).await {
+            Ok(status) if status.maintenance => Err(anyhow::anyhow!("Machine is {}", status.describe())),
+            _ => crate::power::hibernate(&client, &state.event_manager, machine, None).await,
+        };
+        results.push(power_result(&machine.id, result));
+    }
+    Json(json!({ "results": results })).into_response()
+}
+
+// Power managed-off machines back on
+async fn api_fleet_wake(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<PowerActionRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
 
-        // Report final progress on successful download
-        if !download_error && total_size > 0 {
-            if let (Some(machine_id), Some(state)) = (tracking_machine_id, &app_state_clone) {
-                track_download_progress(Some(machine_id), total_size, total_size, state.clone()).await;
-            }
-        }
+    let machines = match power_action_machines(&payload).await {
+        Ok(machines) => machines,
+        Err(response) => return response,
+    };
+    let client = match power_client() {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
 
-        // Ensure file is flushed and closed first
-        if let Ok(mut file) = Arc::try_unwrap(file).map_err(|_| "Failed to unwrap Arc").and_then(|mutex| Ok(mutex.into_inner())) {
-            if let Err(e) = file.flush().await {
-                warn!("Failed to flush cache file {}: {}", cache_path_clone.display(), e);
-            }
-            // File is closed when it goes out of scope here
-        }
-        
-        // Only send EOF signal if the download completed without error AND the client is still connected
-        if !download_error && !client_disconnected {
-            info!("Download complete for {}, client still connected.", url_clone);
-            // Removed explicit EOF signal
-            // debug!("Sending EOF signal for {}", url_clone);
-            // let _ = tx.send(Ok(Bytes::new())).await;
-        } else if !download_error && client_disconnected {
-            info!("Download complete and cached for {} after client disconnected.", url_clone);
-        } else {
-            // An error occurred during download or caching
-            warn!("Download for {} did not complete successfully due to errors.", url_clone);
-            // Optionally remove the potentially incomplete cache file
-            // if let Err(e) = fs::remove_file(&cache_path_clone).await {
-            //     warn!("Failed to remove incomplete cache file {}: {}", cache_path_clone.display(), e);
-            // }
-        }
-    });
-    
-    // After download completes or if error, handle the stream
-    let (stream, content_length) = (tokio_stream::wrappers::ReceiverStream::new(rx), content_length);
+    let mut results = Vec::new();
+    for machine in &machines {
+        let result = crate::power::wake(&client, &state.event_manager, machine).await;
+        results.push(power_result(&machine.id, result));
+    }
+    Json(json!({ "results": results })).into_response()
+}
 
-    // We cached the full file, but the *initial* request might have been a range request.
-    // If so, we need to read the *cached* file with range support now.
-    if range_header.is_some() {
-        info!("Download complete, now serving range request from cached file: {:?}", cache_path);
-        // Re-call read_file_as_stream with the range header on the now-cached file
-        read_file_as_stream(cache_path, range_header, state, machine_id).await // Pass machine_id here too
-    } else {
-        // No range requested initially, return the full stream we prepared during download
-        Ok((stream, content_length, None)) // No Content-Range for full file
+async fn api_list_managed_power_offs(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    match db::list_managed_power_offs().await {
+        Ok(records) => Json(json!({ "machines": records })).into_response(),
+        Err(e) => {
+            error!("Failed to list managed power-offs: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
+        }
     }
 }
 
-// Helper to parse Range header. Returns (start, end)
-async fn parse_range_header(
-    range_str: &str,
-    total_size: u64,
-    _file_name: Option<&str>, // Marked unused, event logic removed
-    _state: Option<&AppState>, // Marked unused, event logic removed
-) -> Option<(u64, u64)> {
-    if !range_str.starts_with("bytes=") {
-        return None;
+// Refusal for actions that maintenance mode blocks
+pub(crate) fn maintenance_blocked(id: &Uuid, status: &crate::maintenance::MaintenanceStatus) -> Response {
+    (StatusCode::CONFLICT, Json(ErrorResponse {
+        error: "Machine In Maintenance".to_string(),
+        message: format!("Machine {} is {}", id, status.describe()),
+    })).into_response()
+}
+
+fn maintenance_input_error(e: anyhow::Error) -> Response {
+    (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+        error: "Invalid Maintenance Window".to_string(),
+        message: e.to_string(),
+    })).into_response()
+}
+
+fn maintenance_db_error(context: &str, e: anyhow::Error) -> Response {
+    error!("Failed to {}: {}", context, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+        error: "Database Error".to_string(),
+        message: e.to_string(),
+    })).into_response()
+}
+
+// Whether a machine is in maintenance, and whether that comes from the machine or one of its tags
+async fn api_get_machine_maintenance(Path(id): Path<Uuid>) -> Response {
+    match crate::maintenance::status(&id).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => maintenance_db_error(&format!("look up maintenance for machine {}", id), e),
     }
-    let range_val = &range_str[6..]; // Skip "bytes="
-    let parts: Vec<&str> = range_val.split('-').collect();
-    if parts.len() != 2 {
-        return None;
+}
+
+async fn api_put_machine_maintenance(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<crate::maintenance::MaintenanceInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
 
-    let start_str = parts[0].trim();
-    let end_str = parts[1].trim();
+    match db::get_machine_by_id(&id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
+        }
+        Err(e) => return maintenance_db_error(&format!("look up machine {}", id), e),
+    }
 
-    let start = if start_str.is_empty() {
-        // Suffix range: "-<length>"
-        if end_str.is_empty() { return None; } // Invalid: "-"
-        let suffix_len = end_str.parse::<u64>().ok()?;
-        if suffix_len >= total_size { 0 } else { total_size - suffix_len }
-    } else {
-        // Normal range: "start-" or "start-end"
-        start_str.parse::<u64>().ok()?
+    let window = match payload.into_window(chrono::Utc::now()) {
+        Ok(window) => window,
+        Err(e) => return maintenance_input_error(e),
     };
+    if let Err(e) = db::set_machine_maintenance(&id, window.as_ref()).await {
+        return maintenance_db_error(&format!("update maintenance for machine {}", id), e);
+    }
 
-    let end = if end_str.is_empty() {
-        // Range "start-" means start to end of file
-        total_size.saturating_sub(1)
-    } else {
-        // Range "start-end"
-        end_str.parse::<u64>().ok()?
+    match &window {
+        Some(window) => info!("Machine {} entered maintenance (reason: {:?}, until: {:?})", id, window.reason, window.until),
+        None => info!("Machine {} left maintenance", id),
+    }
+    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    match crate::maintenance::status(&id).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => maintenance_db_error(&format!("look up maintenance for machine {}", id), e),
+    }
+}
+
+// The maintenance window set on a tag, if any
+async fn api_get_tag_maintenance(Path(tag_name): Path<String>) -> Response {
+    match db::list_tag_maintenance().await {
+        Ok(windows) => {
+            let window = windows.into_iter().find(|(tag, _)| *tag == tag_name).map(|(_, window)| window);
+            let active = window.as_ref().is_some_and(|w| w.is_active(chrono::Utc::now()));
+            Json(json!({ "tag": tag_name, "maintenance": active, "window": window })).into_response()
+        }
+        Err(e) => maintenance_db_error(&format!("look up maintenance for tag {}", tag_name), e),
+    }
+}
+
+async fn api_put_tag_maintenance(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(tag_name): Path<String>,
+    Json(payload): Json<crate::maintenance::MaintenanceInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let window = match payload.into_window(chrono::Utc::now()) {
+        Ok(window) => window,
+        Err(e) => return maintenance_input_error(e),
     };
+    if let Err(e) = db::set_tag_maintenance(&tag_name, window.as_ref()).await {
+        return maintenance_db_error(&format!("update maintenance for tag {}", tag_name), e);
+    }
 
-    // Validate range: start <= end < total_size
-    if start > end || end >= total_size {
-        warn!("Invalid range request: start={}, end={}, total_size={}", start, end, total_size);
-        return None;
+    match &window {
+        Some(window) => info!("Tag {} entered maintenance (reason: {:?}, until: {:?})", tag_name, window.reason, window.until),
+        None => info!("Tag {} left maintenance", tag_name),
+    }
+    match db::get_machines_by_tag(&tag_name).await {
+        Ok(machines) => {
+            for machine in machines {
+                let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+            }
+        }
+        Err(e) => warn!("Failed to list machines tagged {} after maintenance change: {}", tag_name, e),
     }
+    Json(json!({ "tag": tag_name, "maintenance": window.is_some(), "window": window })).into_response()
+}
 
-    // Optional: Emit progress event for the range being served
-    // if let Some(s) = state { // Check if state exists before trying to use it
-    //     let bytes_downloaded = end - start + 1;
-    //     let event_data = serde_json::json!({
-    //         "progress": 100.0, // A single range request is considered 100% of that range
-    //         "bytes_downloaded": bytes_downloaded,
-    //         "total_size": total_size,
-    //         "file_name": file_name.unwrap_or("unknown")
-    //     }).to_string();
+fn ephemeral_db_error(context: &str, e: anyhow::Error) -> Response {
+    error!("Failed to {}: {}", context, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+        error: "Database Error".to_string(),
+        message: e.to_string(),
+    })).into_response()
+}
 
-    //     // Prefer emitting IP-based progress if possible
-    //     let client_ip_guard = s.client_ip.lock().await;
-    //     if let Some(client_ip) = client_ip_guard.as_ref() {
-    //          let ip_progress_event = format!("ip_download_progress:{{ \"ip\": \"{}\", {} }}", client_ip, &event_data[1..]); // Construct JSON manually
-    //          // info!("Sending event: {}", ip_progress_event); // Commented out log
-    //          let _ = s.event_manager.send(ip_progress_event);
-    //     } else if let Some(f_name) = file_name {
-    //         // Fallback to file-based progress if IP is unavailable
-    //         let file_progress_event = format!("file_progress:{}:{}:{}", f_name, 100.0, event_data);
-    //         let _ = s.event_manager.send(file_progress_event);
-    //     }
-    // }
+async fn ephemeral_response(id: &Uuid) -> Response {
+    let config = match db::get_ephemeral_config(id).await {
+        Ok(config) => config,
+        Err(e) => return ephemeral_db_error(&format!("look up ephemeral mode for machine {}", id), e),
+    };
+    let last_boot = match db::get_ephemeral_boot(id).await {
+        Ok(boot) => boot,
+        Err(e) => return ephemeral_db_error(&format!("look up last live boot for machine {}", id), e),
+    };
+    Json(json!({ "ephemeral": config.is_some(), "config": config, "last_boot": last_boot })).into_response()
+}
 
-    Some((start, end))
+// Whether a machine netboots a live image, which one, and how its last boot went
+async fn api_get_ephemeral(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    // The config carries cloud-init user data, which often holds secrets
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    ephemeral_response(&id).await
 }
 
-// Restore original function name and intended purpose (returning HTML partial)
-pub async fn get_workflow_progress(
-    State(app_state): State<AppState>, // Add AppState
-    Path(id): Path<Uuid>
-) -> Response { 
-    info!("Request for workflow progress HTML partial for machine {}", id);
+async fn api_put_ephemeral(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<crate::ephemeral::EphemeralInput>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
 
     let machine = match db::get_machine_by_id(&id).await {
-        Ok(Some(m)) => m,
+        Ok(Some(machine)) => machine,
         Ok(None) => {
-            error!("Machine not found: {}", id);
-            return (StatusCode::NOT_FOUND, Html("<div>Machine not found</div>")).into_response();
-        },
-        Err(e) => {
-            error!("Error fetching machine {}: {}", id, e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Html("<div>Database error</div>")).into_response();
+            return (StatusCode::NOT_FOUND, Json(ErrorResponse {
+                error: "Not Found".to_string(),
+                message: format!("Machine with ID {} not found", id),
+            })).into_response();
         }
+        Err(e) => return ephemeral_db_error(&format!("look up machine {}", id), e),
     };
-
-    if machine.status != MachineStatus::InstallingOS {
-        info!("Machine {} is not installing OS, status: {:?}", id, machine.status);
-        return (StatusCode::OK, Html("<div></div>")).into_response(); // Return empty div if not installing
+    if machine.status == MachineStatus::InstallingOS {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Install In Progress".to_string(),
+            message: format!("Machine {} is installing an OS; wait for it to finish first", id),
+        })).into_response();
     }
 
-    match crate::tinkerbell::get_workflow_info(&machine).await {
-        Ok(Some(info)) => {
-            info!("Successfully got workflow info for machine {}: state={}, progress={}", id, info.state, info.progress);
-
-            // Create the context struct (as defined in ui.rs)
-            let context = ui::WorkflowProgressTemplate { 
-                machine_id: id,
-                workflow_info: info,
-            };
-            
-            // Render the partial using MiniJinja
-            ui::render_minijinja(&app_state, "partials/workflow_progress.html", context)
-        },
-        Ok(None) => {
-            info!("No workflow found for machine {}", id);
-            (StatusCode::OK, Html("<div></div>")).into_response()
-        },
+    let config = match payload.into_config(chrono::Utc::now()) {
+        Ok(config) => config,
         Err(e) => {
-            error!("Error fetching workflow for machine {}: {}", id, e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Html("<div>Error fetching workflow</div>")).into_response()
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                error: "Invalid Ephemeral Config".to_string(),
+                message: e.to_string(),
+            })).into_response();
         }
+    };
+    if let Err(e) = db::set_ephemeral_config(&id, config.as_ref()).await {
+        return ephemeral_db_error(&format!("update ephemeral mode for machine {}", id), e);
     }
-}
 
-// ... (rest of api.rs) ...
+    match &config {
+        Some(config) => info!("Machine {} is ephemeral, booting kernel {}", id, config.kernel),
+        None => {
+            info!("Machine {} left ephemeral mode", id);
+            // Nothing is installed, so it's back to waiting for an OS
+            if machine.status == MachineStatus::BootedLive {
+                if let Err(e) = db::update_status(&id, MachineStatus::AwaitingAssignment).await {
+                    return ephemeral_db_error(&format!("update status of machine {}", id), e);
+                }
+            }
+        }
+    }
+    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+    ephemeral_response(&id).await
+}
 
-// Stub for heartbeat
-pub async fn heartbeat() -> Response {
-    (StatusCode::OK, "OK").into_response()
+// NoCloud meta-data for a live boot. Unauthenticated like the boot scripts; the boot ID
+// is random and only valid until the machine boots again.
+pub async fn ephemeral_meta_data(Path(boot_id): Path<Uuid>) -> Response {
+    let machine = match db::find_ephemeral_boot(&boot_id).await {
+        Ok(Some(machine_id)) => db::get_machine_by_id(&machine_id).await,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Unknown boot").into_response(),
+        Err(e) => Err(e),
+    };
+    match machine {
+        Ok(Some(machine)) => {
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], crate::ephemeral::meta_data(&boot_id, &machine)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Unknown boot").into_response(),
+        Err(e) => ephemeral_db_error(&format!("look up live boot {}", boot_id), e),
+    }
 }
 
-// Add stubs for functions called from mode.rs
-pub async fn check_hookos_artifacts() -> bool {
-    // Check for the following four files
-    let files = vec![
-        "vmlinuz-latest-lts-x86_64",
-        "initramfs-latest-lts-x86_64",
-        "vmlinuz-latest-lts-aarch64",
-        "initramfs-latest-lts-aarch64",
-        "dtbs-latest-lts-aarch64.tar.gz",
-        "vmlinuz-x86_64",
-        "initramfs-x86_64",
-        "vmlinuz-aarch64",
-        "initramfs-aarch64",
-        "dtbs-aarch64.tar.gz",
-    ];
+// NoCloud user-data for a live boot; the live system asking for it means it came up
+pub async fn ephemeral_user_data(State(state): State<AppState>, Path(boot_id): Path<Uuid>) -> Response {
+    let machine_id = match db::find_ephemeral_boot(&boot_id).await {
+        Ok(Some(machine_id)) => machine_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Unknown boot").into_response(),
+        Err(e) => return ephemeral_db_error(&format!("look up live boot {}", boot_id), e),
+    };
+    let config = match db::get_ephemeral_config(&machine_id).await {
+        Ok(Some(config)) => config,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Unknown boot").into_response(),
+        Err(e) => return ephemeral_db_error(&format!("look up ephemeral mode for machine {}", machine_id), e),
+    };
 
-    for file in files {
-        let path = FilePath::new("/var/lib/dragonfly/ipxe-artifacts/hookos").join(file);
-        if !path.exists() {
-            return false;
+    match db::mark_ephemeral_booted(&boot_id, chrono::Utc::now()).await {
+        Ok(true) => {
+            info!("Machine {} booted live (boot {})", machine_id, boot_id);
+            match db::update_status(&machine_id, MachineStatus::BootedLive).await {
+                Ok(_) => { let _ = state.event_manager.send(format!("machine_updated:{}", machine_id)); }
+                Err(e) => error!("Failed to mark machine {} as booted live: {}", machine_id, e),
+            }
         }
+        Ok(false) => {}
+        Err(e) => error!("Failed to record live boot {}: {}", boot_id, e),
     }
+    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/plain")], crate::ephemeral::user_data(&config)).into_response()
+}
 
-    info!("All HookOS artifacts found");
-    true
+#[derive(Deserialize)]
+struct BulkTagsRequest {
+    #[serde(flatten)]
+    selector: crate::bulk::MachineSelector,
+    #[serde(flatten)]
+    change: crate::bulk::TagChange,
+    #[serde(default)]
+    dry_run: bool,
 }
 
-pub async fn download_hookos_artifacts(version: &str) -> anyhow::Result<()> {
-    // Create directory structure if it doesn't exist
-    let hookos_dir = FilePath::new("/var/lib/dragonfly/ipxe-artifacts/hookos");
-    if !hookos_dir.exists() {
-        info!("Creating directory structure: {:?}", hookos_dir);
-        std::fs::create_dir_all(hookos_dir)?;
-    }
-    
-    // Download checksum file
-    let checksum_url = format!("https://github.com/tinkerbell/hook/releases/download/{}/checksum.txt", version);
-    let checksum_path = hookos_dir.join("checksum.txt");
-    let checksum_response = reqwest::get(checksum_url).await?;
-    let checksum_content = checksum_response.text().await?;
-    std::fs::write(checksum_path, checksum_content)?;
+#[derive(Deserialize)]
+struct BulkStatusRequest {
+    #[serde(flatten)]
+    selector: crate::bulk::MachineSelector,
+    /// State key, e.g. "ready"
+    status: String,
+    #[serde(default)]
+    dry_run: bool,
+}
 
-    // Files to download
-    let files = vec![
-        "hook_x86_64.tar.gz",
-        "hook_aarch64.tar.gz",
-        "hook_latest-lts-x86_64.tar.gz",
-        "hook_latest-lts-aarch64.tar.gz",
-    ];
+#[derive(Deserialize)]
+struct BulkReinstallRequest {
+    #[serde(flatten)]
+    selector: crate::bulk::MachineSelector,
+    #[serde(default)]
+    dry_run: bool,
+}
 
-    // Create a vector of download futures
-    let download_futures = files.iter().map(|file| {
-        let file = file.to_string();
-        let version = version.to_string();
-        let hookos_dir = hookos_dir.to_path_buf();
-        
-        // Return a future for each download
-        async move {
-            let url = format!("https://github.com/tinkerbell/hook/releases/download/{}/{}", version, file);
-            info!("Downloading {} in parallel", url);
-            let response = reqwest::get(&url).await?;
-            let content = response.bytes().await?;
-            let tarball_path = hookos_dir.join(&file);
-            std::fs::write(&tarball_path, content)?;
-            info!("Downloaded {} to {:?}", file, tarball_path);
-            Ok::<_, anyhow::Error>(tarball_path)
-        }
-    }).collect::<Vec<_>>();
-    
-    // Execute all downloads in parallel
-    let download_results = futures::future::try_join_all(download_futures).await?;
-    info!("All HookOS artifacts downloaded in parallel successfully");
+fn bulk_bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(ErrorResponse {
+        error: "Bad Request".to_string(),
+        message,
+    })).into_response()
+}
 
-    // Create a vector of extraction futures
-    let extraction_futures = download_results.into_iter().map(|tarball_path| {
-        let hookos_dir = hookos_dir.to_path_buf();
-        
-        // Return a future for each extraction
-        async move {
-            let file_name = tarball_path.file_name().unwrap().to_string_lossy().to_string();
-            info!("Extracting {:?} in parallel", tarball_path);
-            
-            // Check if the file exists and has content before trying to extract
-            let metadata = match std::fs::metadata(&tarball_path) {
-                Ok(meta) => meta,
-                Err(e) => {
-                    warn!("Skipping extraction of {:?}: file not accessible: {}", tarball_path, e);
-                    return Ok::<_, anyhow::Error>(tarball_path);
-                }
-            };
-            
-            if metadata.len() == 0 {
-                warn!("Skipping extraction of {:?}: file is empty", tarball_path);
-                return Ok::<_, anyhow::Error>(tarball_path);
-            }
-            
-            // Open the file for reading
-            let tar_file = match File::open(&tarball_path) {
-                Ok(f) => f,
-                Err(e) => {
-                    warn!("Failed to open {:?} for extraction: {}", tarball_path, e);
-                    return Ok::<_, anyhow::Error>(tarball_path);
-                }
-            };
-            
-            // Create the archive and extract, handling any errors
-            // Check if the file is a .tar.gz file
-            if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
-                // Use GzDecoder for gzipped files
-                let gz = GzDecoder::new(tar_file);
-                let mut archive = Archive::new(gz);
-                match archive.unpack(&hookos_dir) {
-                    Ok(_) => info!("Successfully extracted gzipped archive {:?}", tarball_path),
-                    Err(e) => warn!("Failed to extract gzipped archive {:?}: {}", tarball_path, e),
-                }
-            } else {
-                // For non-gzipped files, use directly
-                let mut archive = Archive::new(tar_file);
-                match archive.unpack(&hookos_dir) {
-                    Ok(_) => info!("Successfully extracted archive {:?}", tarball_path),
-                    Err(e) => warn!("Failed to extract archive {:?}: {}", tarball_path, e),
-                }
-            }
-            
-            Ok::<_, anyhow::Error>(tarball_path)
-        }
-    }).collect::<Vec<_>>();
-    
-    // Execute all extractions in parallel
-    let extraction_results = futures::future::try_join_all(extraction_futures).await?;
-    info!("All HookOS artifacts extracted in parallel successfully");
-    
-    // Remove all tarballs in parallel
-    let cleanup_futures = extraction_results.into_iter().map(|tarball_path| {
-        async move {
-            // Remove the tarball after extraction
-            if let Err(e) = std::fs::remove_file(&tarball_path) {
-                warn!("Failed to remove tarball {:?}: {}", tarball_path, e);
-            } else {
-                info!("Removed tarball {:?}", tarball_path);
-            }
-            Ok::<(), anyhow::Error>(())
-        }
-    }).collect::<Vec<_>>();
-    
-    // Execute all cleanup operations in parallel
-    futures::future::try_join_all(cleanup_futures).await?;
-    
-    info!("HookOS artifacts downloaded, extracted, and cleaned up successfully to {:?}", hookos_dir);
-    Ok(())
+// The machines a bulk operation applies to, with their tags, or an error response
+async fn bulk_targets(selector: &crate::bulk::MachineSelector) -> Result<Vec<(Machine, Vec<String>)>, Response> {
+    let db_error = |e: anyhow::Error| {
+        error!("Failed to load machines for bulk operation: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response()
+    };
+    let machines = db::get_all_machines().await.map_err(db_error)?;
+    let mut tags = db::get_all_machine_tags().await.map_err(db_error)?;
+    let fleet: Vec<(Machine, Vec<String>)> = machines.into_iter()
+        .map(|machine| {
+            let machine_tags = tags.remove(&machine.id).unwrap_or_default();
+            (machine, machine_tags)
+        })
+        .collect();
+    match crate::bulk::select(selector, &fleet) {
+        Ok(selected) => Ok(selected.into_iter().cloned().collect()),
+        Err(e) => Err(bulk_bad_request(e.to_string())),
+    }
 }
 
-// OS information struct
-#[derive(Debug, Clone, serde::Serialize)]
-pub struct OsInfo {
-    pub name: String,
-    pub icon: String,
+fn bulk_machine_name(machine: &Machine) -> String {
+    machine.hostname.clone()
+        .or_else(|| machine.memorable_name.clone())
+        .unwrap_or_else(|| machine.mac_address.clone())
 }
 
-// Get OS icon for a specific OS
-pub fn get_os_icon(os: &str) -> String {
-    let os_lower = os.to_lowercase();
-    match os_lower.as_str() {
-        os if os.contains("ubuntu") => "<i class=\"fab fa-ubuntu text-orange-500 dark:text-orange-500 no-invert\"></i>",
-        os if os.contains("debian") => "<i class=\"fab fa-debian text-red-500\"></i>",
-        "proxmox" => "<i class=\"fas fa-server text-blue-500\"></i>",
-        "talos" => "<i class=\"fas fa-robot text-purple-500\"></i>",
-        os if os.contains("windows") => "<i class=\"fab fa-windows text-blue-400\"></i>",
-        os if os.contains("rocky") => "<i class=\"fas fa-mountain text-green-500\"></i>",
-        os if os.contains("fedora") => "<i class=\"fab fa-fedora text-blue-600\"></i>",
-        os if os.contains("alma") => "<i class=\"fas fa-hat-cowboy text-amber-600\"></i>",
-        _ => "<i class=\"fas fa-square-question text-gray-500\"></i>", // Unknown OS
-    }.to_string()
+// The message of an error response built by a single-machine handler
+pub(crate) async fn response_message(response: Response) -> String {
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), 64 * 1024).await.unwrap_or_default();
+    serde_json::from_slice::<serde_json::Value>(&body).ok()
+        .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(str::to_string))
+        .unwrap_or_else(|| status.to_string())
 }
 
-// Make format_os_name public
-pub fn format_os_name(os: &str) -> String {
-    let os_lower = os.to_lowercase();
-    
-    // Handle Ubuntu formats
-    if os_lower.contains("ubuntu") {
-        if os_lower.contains("22.04") || os_lower.contains("2204") {
-            return "Ubuntu 22.04".to_string();
-        } else if os_lower.contains("24.04") || os_lower.contains("2404") {
-            return "Ubuntu 24.04".to_string();
-        } else if let Some(version) = os_lower.split(&['(', ')', ' ', '-', '_'][..])
-                                              .find(|s| s.contains(".") && s.len() <= 6) {
-            return format!("Ubuntu {}", version);
-        } else {
-            return "Ubuntu".to_string();
-        }
+// Add and remove tags on every selected machine
+async fn api_bulk_tags(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<BulkTagsRequest>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
-    
-    // Handle Debian formats
-    if os_lower.contains("debian") {
-        if os_lower.contains("12") || os_lower.contains("bookworm") {
-            return "Debian 12".to_string();
-        } else if let Some(version) = os_lower.split(&[' ', '(', ')', '-', '_'][..])
-                                              .find(|s| s.parse::<u32>().is_ok()) {
-            return format!("Debian {}", version);
-        } else {
-            return "Debian".to_string();
+
+    let change = match payload.change.normalize() {
+        Ok(change) => change,
+        Err(e) => return bulk_bad_request(e.to_string()),
+    };
+    let targets = match bulk_targets(&payload.selector).await {
+        Ok(targets) => targets,
+        Err(response) => return response,
+    };
+
+    let mut results = Vec::new();
+    let mut changed = 0;
+    for (machine, tags) in &targets {
+        let mut result = json!({
+            "machine_id": machine.id,
+            "name": bulk_machine_name(machine),
+            "tags_before": tags,
+        });
+        match change.apply(tags) {
+            None => {
+                result["tags_after"] = json!(tags);
+                result["changed"] = json!(false);
+            }
+            Some(new_tags) => {
+                result["tags_after"] = json!(new_tags);
+                result["changed"] = json!(true);
+                changed += 1;
+                if !payload.dry_run {
+                    match db::update_machine_tags(&machine.id, &new_tags).await {
+                        Ok(_) => {
+                            let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+                        }
+                        Err(e) => {
+                            error!("Failed to update tags for machine {}: {}", machine.id, e);
+                            result["error"] = json!(e.to_string());
+                        }
+                    }
+                }
+            }
         }
+        results.push(result);
     }
-    
-    // Handle specific formats
-    match os_lower.as_str() {
-        "ubuntu-2204" => "Ubuntu 22.04",
-        "ubuntu-2404" => "Ubuntu 24.04",
-        "debian-12" => "Debian 12",
-        "proxmox" => "Proxmox VE",
-        "talos" => "Talos",
-        _ => os, // Return original string if no match
-    }.to_string()
-}
-
-// Get both OS name and icon
-pub fn get_os_info(os: &str) -> OsInfo {
-    OsInfo {
-        name: format_os_name(os),
-        icon: get_os_icon(os),
+    if !payload.dry_run {
+        info!("Bulk tag change (add {:?}, remove {:?}) updated {} of {} machines", change.add, change.remove, changed, targets.len());
     }
+    Json(json!({
+        "dry_run": payload.dry_run,
+        "matched": targets.len(),
+        "changed": changed,
+        "machines": results,
+    })).into_response()
 }
 
-async fn update_installation_progress(
-    State(state): State<AppState>, // State is used for event manager
-    _auth_session: AuthSession, // Mark as unused - updates come from agent/tinkerbell
-    Path(id): Path<Uuid>,
-    Json(payload): Json<InstallationProgressUpdateRequest>,
+// Set the status of every selected machine
+async fn api_bulk_status(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<BulkStatusRequest>,
 ) -> Response {
-    // Remove admin check - allow agent/tinkerbell to post updates
-    /*
-    if let Err(response) = crate::auth::require_admin(&auth_session) {
-        return response;
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
-    */
 
-    info!("Updating installation progress for machine {} to {}% (step: {:?})",
-          id, payload.progress, payload.step);
+    let key = payload.status.trim().to_lowercase();
+    let Some(status) = crate::bulk::settable_status(&key) else {
+        return bulk_bad_request(format!(
+            "Status '{}' can't be set in bulk; use ready, awaiting_assignment, offline or error (or a reinstall)",
+            payload.status
+        ));
+    };
+    let targets = match bulk_targets(&payload.selector).await {
+        Ok(targets) => targets,
+        Err(response) => return response,
+    };
 
-    match db::update_installation_progress(&id, payload.progress, payload.step.as_deref()).await {
-        Ok(true) => {
-            // Emit machine updated event so the UI fetches new progress HTML
-            let _ = state.event_manager.send(format!("machine_updated:{}", id));
-            (StatusCode::OK, Json(json!({ "status": "progress_updated", "machine_id": id }))).into_response()
-        },
-        Ok(false) => {
-            let error_response = ErrorResponse {
-                error: "Not Found".to_string(),
-                message: format!("Machine with ID {} not found", id),
-            };
-            (StatusCode::NOT_FOUND, Json(error_response)).into_response()
-        },
-        Err(e) => {
-            error!("Failed to update installation progress for machine {}: {}", id, e);
-            let error_response = ErrorResponse {
-                error: "Database Error".to_string(),
-                message: e.to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+    let mut results = Vec::new();
+    let mut changed = 0;
+    for (machine, _) in &targets {
+        let before = crate::usage::state_key(&machine.status);
+        let mut result = json!({
+            "machine_id": machine.id,
+            "name": bulk_machine_name(machine),
+            "status_before": before,
+            "changed": before != key,
+        });
+        if before != key {
+            changed += 1;
+            if !payload.dry_run {
+                match db::update_status(&machine.id, status.clone()).await {
+                    Ok(_) => {
+                        let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+                    }
+                    Err(e) => {
+                        error!("Failed to set status of machine {}: {}", machine.id, e);
+                        result["error"] = json!(e.to_string());
+                    }
+                }
+            }
         }
+        results.push(result);
     }
+    if !payload.dry_run {
+        info!("Bulk status change to {} updated {} of {} machines", key, changed, targets.len());
+    }
+    Json(json!({
+        "dry_run": payload.dry_run,
+        "status": key,
+        "matched": targets.len(),
+        "changed": changed,
+        "machines": results,
+    })).into_response()
 }
 
-// Add new handler for getting machine tags
-#[axum::debug_handler]
-async fn api_get_machine_tags(
-    Path(id): Path<Uuid>,
+// Reinstall every selected machine with its assigned OS
+async fn api_bulk_reinstall(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Json(payload): Json<BulkReinstallRequest>,
 ) -> Response {
-    match get_machine_tags(&id).await {
-        Ok(tags) => (StatusCode::OK, Json(tags)).into_response(),
-        Err(e) => {
-            error!("Failed to get tags for machine {}: {}", id, e);
-            let error_response = ErrorResponse {
-                error: "Database Error".to_string(),
-                message: format!("Failed to retrieve tags: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let targets = match bulk_targets(&payload.selector).await {
+        Ok(targets) => targets,
+        Err(response) => return response,
+    };
+
+    // Reinstalling machines in service needs a second person when the two-person rule is on
+    if !payload.dry_run && targets.iter().any(|(machine, _)| machine.status == MachineStatus::Ready) {
+        let machines: Vec<&Machine> = targets.iter().map(|(machine, _)| machine).collect();
+        let action = crate::action_approvals::DestructiveAction::ReinstallMachines {
+            machine_ids: machines.iter().map(|machine| machine.id).collect(),
+        };
+        if let Some(response) = held_for_approval(&auth_session, action, action_summary("Reinstall", &machines)).await {
+            return response;
         }
     }
+
+    let mut results = Vec::new();
+    let mut started = 0;
+    for (machine, _) in &targets {
+        let mut result = json!({
+            "machine_id": machine.id,
+            "name": bulk_machine_name(machine),
+        });
+        let outcome = match reimage_preflight(&state, &machine.id).await {
+            Ok((machine, os_choice)) => {
+                result["os_choice"] = json!(os_choice);
+                if payload.dry_run {
+                    Ok(())
+                } else {
+                    start_reimage(&state, &machine, &os_choice).await
+                }
+            }
+            Err(response) => Err(response),
+        };
+        match outcome {
+            Ok(()) => {
+                started += 1;
+                result["ok"] = json!(true);
+            }
+            Err(response) => {
+                result["ok"] = json!(false);
+                result["error"] = json!(response_message(response).await);
+            }
+        }
+        results.push(result);
+    }
+    if !payload.dry_run {
+        info!("Bulk reinstall started on {} of {} machines", started, targets.len());
+    }
+    Json(json!({
+        "dry_run": payload.dry_run,
+        "matched": targets.len(),
+        "started": if payload.dry_run { 0 } else { started },
+        "machines": results,
+    })).into_response()
 }
 
-// Add new handler for updating machine tags
-#[axum::debug_handler]
-async fn api_update_machine_tags(
+#[derive(Deserialize)]
+struct BulkDeleteRequest {
+    #[serde(flatten)]
+    selector: crate::bulk::MachineSelector,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// Delete every selected machine
+async fn api_bulk_delete(
     State(state): State<AppState>,
     auth_session: AuthSession,
-    Path(id): Path<Uuid>,
-    Json(tags): Json<Vec<String>>,
+    Json(payload): Json<BulkDeleteRequest>,
 ) -> Response {
-    // Check if user is authenticated as admin
-    if let Err(response) = crate::auth::require_admin(&auth_session) {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let targets = match bulk_targets(&payload.selector).await {
+        Ok(targets) => targets,
+        Err(response) => return response,
+    };
+    let machine_ids: Vec<Uuid> = targets.iter().map(|(machine, _)| machine.id).collect();
+    if payload.dry_run {
+        let machines: Vec<serde_json::Value> = targets.iter()
+            .map(|(machine, _)| json!({ "machine_id": machine.id, "name": bulk_machine_name(machine) }))
+            .collect();
+        return Json(json!({ "dry_run": true, "matched": targets.len(), "deleted": 0, "machines": machines })).into_response();
+    }
+
+    let machines: Vec<&Machine> = targets.iter().map(|(machine, _)| machine).collect();
+    let action = crate::action_approvals::DestructiveAction::DeleteMachines { machine_ids };
+    if let Some(response) = held_for_approval(&auth_session, action.clone(), action_summary("Delete", &machines)).await {
         return response;
     }
+    Json(run_destructive_action(&state, &action).await).into_response()
+}
 
-    match db_update_machine_tags(&id, &tags).await {
-        Ok(true) => {
-            // Emit machine updated event
-            let _ = state.event_manager.send(format!("machine_updated:{}", id)); 
-            (StatusCode::OK, Json(json!({ "success": true, "message": "Tags updated" }))).into_response()
-        }
-                    Ok(false) => {
-            let error_response = ErrorResponse {
-                error: "Not Found".to_string(),
-                message: format!("Machine with ID {} not found", id),
-            };
-            (StatusCode::NOT_FOUND, Json(error_response)).into_response()
-        }
-                Err(e) => {
-            error!("Failed to update tags for machine {}: {}", id, e);
-            let error_response = ErrorResponse {
-                error: "Database Error".to_string(),
-                message: format!("Failed to update tags: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+// "Delete machine web-1" or "Reinstall 3 machines: web-1, web-2, web-3"
+fn action_summary(verb: &str, machines: &[&Machine]) -> String {
+    const LISTED: usize = 5;
+    match machines {
+        [machine] => format!("{} machine {}", verb, bulk_machine_name(machine)),
+        _ => {
+            let mut names: Vec<String> = machines.iter().take(LISTED).map(|m| bulk_machine_name(m)).collect();
+            if machines.len() > LISTED {
+                names.push(format!("and {} more", machines.len() - LISTED));
+            }
+            format!("{} {} machines: {}", verb, machines.len(), names.join(", "))
         }
     }
 }
 
-// New handler to get the current installation status
-#[axum::debug_handler]
-async fn get_install_status() -> Response {
-    // Read the current state from the global static
-    let install_state_arc_mutex: Option<Arc<tokio::sync::Mutex<InstallationState>>> = {
-        // Acquire read lock, clone the Arc if it exists, then drop the lock immediately
-        INSTALL_STATE_REF.read().unwrap().as_ref().cloned()
+// With the two-person rule on, file a destructive action for approval instead of running it.
+// Returns the response to send when the action was held, or when the rule couldn't be checked
+async fn held_for_approval(
+    auth_session: &AuthSession,
+    action: crate::action_approvals::DestructiveAction,
+    summary: String,
+) -> Option<Response> {
+    let db_error = |e: anyhow::Error| {
+        error!("Failed to file destructive action for approval: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+            error: "Database Error".to_string(),
+            message: e.to_string(),
+        })).into_response()
     };
-    
-    match install_state_arc_mutex {
-        Some(state_ref) => {
-            // Clone the state inside the read guard
-            let current_state = state_ref.lock().await.clone();
-            // Serialize the state to JSON
-             let payload = json!({
-                "status": current_state,
-                "message": current_state.get_message(),
-                "animation": current_state.get_animation_class(),
-            });
-            (StatusCode::OK, Json(payload)).into_response()
+    match crate::action_approvals::required().await {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(e) => return Some(db_error(e)),
+    }
+    let requested_by = auth_session.user.as_ref().map(|user| user.username.clone()).unwrap_or_default();
+    match crate::action_approvals::request(action, summary, &requested_by).await {
+        Ok(approval) => Some((StatusCode::ACCEPTED, Json(json!({
+            "approval_required": true,
+            "message": format!("\"{}\" needs a second person's approval before it runs", approval.summary),
+            "approval": approval,
+        }))).into_response()),
+        Err(e) => Some(db_error(e)),
+    }
+}
+
+// Carry out an approved destructive action, reporting what happened to each machine
+async fn run_destructive_action(state: &AppState, action: &crate::action_approvals::DestructiveAction) -> serde_json::Value {
+    use crate::action_approvals::DestructiveAction;
+    let mut results = Vec::new();
+    match action {
+        DestructiveAction::DeleteMachines { machine_ids } => {
+            for id in machine_ids {
+                let response = remove_machine(state, id).await;
+                let ok = response.status().is_success();
+                let mut result = json!({ "machine_id": id, "ok": ok });
+                if !ok {
+                    result["error"] = json!(response_message(response).await);
+                }
+                results.push(result);
+            }
         }
-        None => {
-            // Not in install mode
-             let payload = json!({
-                "status": "NotInstalling",
-                "message": "Dragonfly is not currently installing.",
-                "animation": "",
-            });
-            (StatusCode::OK, Json(payload)).into_response()
+        DestructiveAction::ReinstallMachines { machine_ids } => {
+            for id in machine_ids {
+                let outcome = match reimage_preflight(state, id).await {
+                    Ok((machine, os_choice)) => start_reimage(state, &machine, &os_choice).await,
+                    Err(response) => Err(response),
+                };
+                let mut result = json!({ "machine_id": id, "ok": outcome.is_ok() });
+                if let Err(response) = outcome {
+                    result["error"] = json!(response_message(response).await);
+                }
+                results.push(result);
+            }
+        }
+        DestructiveAction::StartRollout { campaign_id, strategy } => {
+            let started = match db::get_campaign(campaign_id).await {
+                Ok(Some(mut campaign)) => crate::campaign::begin_rollout(state, &mut campaign, strategy.clone()).await
+                    .map(|()| campaign),
+                Ok(None) => Err(anyhow::anyhow!("Campaign {} not found", campaign_id)),
+                Err(e) => Err(e),
+            };
+            match started {
+                Ok(campaign) => {
+                    let canary = campaign.rollout.as_ref().map(|rollout| rollout.canary.clone()).unwrap_or_default();
+                    for member in campaign.members.iter().filter(|m| canary.contains(&m.machine_id)) {
+                        let ok = member.state != crate::campaign::MemberState::Failed;
+                        let mut result = json!({ "machine_id": member.machine_id, "ok": ok });
+                        if !ok {
+                            result["error"] = json!(member.error);
+                        }
+                        results.push(result);
+                    }
+                }
+                Err(e) => results.push(json!({ "campaign_id": campaign_id, "ok": false, "error": e.to_string() })),
+            }
         }
     }
+    let succeeded = results.iter().filter(|r| r["ok"] == json!(true)).count();
+    json!({ "succeeded": succeeded, "failed": results.len() - succeeded, "machines": results })
 }
 
-// Middleware to track client IP address - fixed with proper state extraction
-// Now prioritizes X-Real-IP header
-pub async fn track_client_ip(
-    State(state): State<AppState>,             // State first
-    ConnectInfo(addr): ConnectInfo<SocketAddr>, // Then other FromRequestParts extractors
-    request: axum::http::Request<Body>,
-    next: Next,
-) -> Result<Response, StatusCode> { // Return Result based on example
-    // Try to get IP from X-Real-IP header first
-    let real_ip_header = request.headers()
-        .get("X-Real-IP")
-        .and_then(|value| value.to_str().ok());
-
-    let ip = match real_ip_header {
-        Some(real_ip) => {
-            // Log that we found the header and its value
-            info!("[track_client_ip] Found X-Real-IP header: {}", real_ip);
-            real_ip.to_string()
-        },
-        None => {
-            // Log that the header was missing and we're falling back
-            let fallback_ip = addr.ip().to_string();
-            info!("[track_client_ip] X-Real-IP header not found. Falling back to ConnectInfo IP: {}", fallback_ip);
-            fallback_ip
+/// Approve or reject a pending destructive action, running it when approved. Shared by the API
+/// and the emailed confirmation page.
+pub(crate) async fn decide_action_approval(
+    state: &AppState,
+    id: &Uuid,
+    approve: bool,
+    decided_by: &str,
+    via: crate::action_approvals::DecidedVia,
+) -> Result<crate::action_approvals::ActionApproval, Response> {
+    use crate::action_approvals::DecisionError;
+    let mut approval = match crate::action_approvals::decide(id, approve, decided_by, via).await {
+        Ok(Ok(approval)) => approval,
+        Ok(Err(e)) => {
+            let status = match e {
+                DecisionError::NotFound => StatusCode::NOT_FOUND,
+                DecisionError::SelfApproval | DecisionError::BadLink => StatusCode::FORBIDDEN,
+                DecisionError::AlreadyDecided | DecisionError::Expired => StatusCode::CONFLICT,
+            };
+            return Err((status, Json(ErrorResponse {
+                error: "Approval Not Possible".to_string(),
+                message: e.to_string(),
+            })).into_response());
+        }
+        Err(e) => {
+            error!("Failed to decide action approval {}: {}", id, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response());
         }
     };
 
-    // Log the IP being stored in state
-    info!("[track_client_ip] Storing client IP in state: {}", ip);
-    *state.client_ip.lock().await = Some(ip);
+    if approve {
+        let outcome = run_destructive_action(state, &approval.action).await;
+        if let Err(e) = crate::action_approvals::record_outcome(&mut approval, outcome).await {
+            error!("Failed to record the outcome of action approval {}: {}", id, e);
+        }
+    }
+    Ok(approval)
+}
 
-    // Proceed with the request
-    Ok(next.run(request).await)
+#[derive(Deserialize)]
+struct ActionApprovalsQuery {
+    #[serde(default = "default_action_approvals_limit")]
+    limit: i64,
 }
 
-// Function to add machine IP tracking to API router helpers
-async fn api_get_machine_by_ip(ip: &str) -> Option<Machine> {
-    match db::get_machine_by_ip(ip).await {
-        Ok(Some(machine)) => Some(machine),
-        _ => None,
-    }
+fn default_action_approvals_limit() -> i64 {
+    100
 }
 
-// Add handler for deleting a specific machine tag
-#[axum::debug_handler]
-async fn api_delete_machine_tag(
-    State(state): State<AppState>,
+// Destructive action requests and what became of them, newest first
+async fn api_list_action_approvals(
     auth_session: AuthSession,
-    Path((id, tag)): Path<(Uuid, String)>,
+    axum::extract::Query(query): axum::extract::Query<ActionApprovalsQuery>,
 ) -> Response {
-    // Check if user is authenticated as admin
     if auth_session.user.is_none() {
         return (StatusCode::UNAUTHORIZED, Json(json!({
             "error": "Unauthorized",
@@ -2775,232 +10147,316 @@ async fn api_delete_machine_tag(
         }))).into_response();
     }
 
-    // Get current tags for the machine
-    let result = match db::get_machine_tags(&id).await {
-        Ok(tags) => {
-            // Filter out the tag to delete
-            let new_tags: Vec<String> = tags.into_iter()
-                .filter(|t| t != &tag)
+    match db::list_action_approvals(query.limit.clamp(1, 1000)).await {
+        Ok(approvals) => {
+            let now = chrono::Utc::now();
+            let approvals: Vec<_> = approvals.into_iter()
+                .map(|mut approval| {
+                    approval.state = approval.state_at(now);
+                    approval
+                })
                 .collect();
-            
-            // Update with the filtered tags
-            match db::update_machine_tags(&id, &new_tags).await {
-                Ok(true) => {
-                    // Emit machine updated event
-                    let _ = state.event_manager.send(format!("machine_updated:{}", id));
-                    (StatusCode::OK, Json(json!({"success": true, "message": "Tag deleted"})))
-                },
-                Ok(false) => {
-                    (StatusCode::NOT_FOUND, Json(json!({"error": "Machine not found"})))
-                },
-                Err(e) => {
-                    error!("Failed to update tags after deletion for machine {}: {}", id, e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
-                        "error": "Database error", 
-                        "message": format!("Failed to update tags: {}", e)
-                    })))
-                }
-            }
-        },
+            Json(approvals).into_response()
+        }
         Err(e) => {
-            error!("Failed to get tags for machine {}: {}", id, e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
-                "error": "Database error", 
-                "message": format!("Failed to retrieve tags: {}", e)
-            })))
+            error!("Failed to list action approvals: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
         }
-    };
-
-    result.into_response()
+    }
 }
 
-// NEW HANDLER for the partial update
-#[axum::debug_handler]
-async fn get_machine_status_and_progress_partial(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>,
-) -> Response { // Explicitly return Response
-    info!("Request for status-and-progress partial for machine {}", id);
+async fn api_get_action_approval(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
 
-    let machine = match db::get_machine_by_id(&id).await {
-        Ok(Some(m)) => m,
-        Ok(None) => return (StatusCode::NOT_FOUND, Html("<!-- Machine not found -->")).into_response(),
+    match db::get_action_approval(&id).await {
+        Ok(Some(mut approval)) => {
+            approval.state = approval.state_at(chrono::Utc::now());
+            Json(approval).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Approval request {} not found", id),
+        })).into_response(),
         Err(e) => {
-            error!("DB error fetching machine {} for partial: {}", id, e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Html("<!-- DB Error -->")).into_response();
+            error!("Failed to load action approval {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
         }
-    };
+    }
+}
 
-    let workflow_info = if machine.status == MachineStatus::InstallingOS {
-        match crate::tinkerbell::get_workflow_info(&machine).await {
-            Ok(info_opt) => info_opt, // Can be Some(info) or None
-            Err(e) => {
-                error!("Tinkerbell error fetching workflow info for {}: {}", id, e);
-                None // Treat error as no info
-            }
-        }
-    } else {
-        None // Not installing, no workflow info needed
+async fn decide_action_as_admin(state: AppState, auth_session: AuthSession, id: Uuid, approve: bool) -> Response {
+    let Some(user) = auth_session.user else {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     };
+    match decide_action_approval(&state, &id, approve, &user.username, crate::action_approvals::DecidedVia::Ui).await {
+        Ok(approval) => Json(approval).into_response(),
+        Err(response) => response,
+    }
+}
 
-    // Prepare context for the partial template
-    // Note: The partial will need access to machine and workflow_info
-    let context = json!({
-        "machine": machine,
-        "workflow_info": workflow_info, // Will be null if not installing or error
-    });
+async fn api_approve_action(State(state): State<AppState>, auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    decide_action_as_admin(state, auth_session, id, true).await
+}
 
-    // Render the new partial template using render_minijinja directly
-    // REMOVE THE MATCH BLOCK BELOW
-    /*
-    match ui::render_minijinja(&state, "partials/status_and_progress.html", context) {
-        Ok(html) => (StatusCode::OK, Html(html)).into_response(), // Add .into_response() back
+async fn api_reject_action(State(state): State<AppState>, auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    decide_action_as_admin(state, auth_session, id, false).await
+}
+
+// Metadata registration hooks attached to a machine
+async fn api_get_machine_enrichment(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    match db::get_machine_enrichment(&id).await {
+        Ok(Some(enrichment)) => Json(enrichment).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("No enrichment recorded for machine {}", id),
+        })).into_response(),
         Err(e) => {
-            error!("Failed to render status_and_progress partial: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Html("<!-- Render Error -->")).into_response() // Add .into_response() back
+            error!("Failed to load enrichment for machine {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database Error".to_string(),
+                message: e.to_string(),
+            })).into_response()
         }
     }
-    */
-    // CALL THE FUNCTION DIRECTLY INSTEAD
-    ui::render_minijinja(&state, "partials/status_and_progress.html", context)
 }
 
-// Utility function to extract client IP
+#[derive(Deserialize)]
+struct CreateCampaignRequest {
+    name: String,
+    /// Notification channel names; every enabled channel when empty
+    #[serde(default)]
+    recipients: Vec<String>,
+    #[serde(default)]
+    deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Machines to start with; more can be added later
+    #[serde(flatten)]
+    selector: crate::bulk::MachineSelector,
+}
 
-// --- Tag Management API ---
-/// Get all tags in the system
-#[axum::debug_handler]
-async fn api_get_tags(
-    State(_state): State<AppState>,
-    auth_session: AuthSession,
-) -> Response {
-    // Check if user is authenticated as admin
-    if let Err(response) = crate::auth::require_admin(&auth_session) {
-        return response;
+fn campaign_db_error(e: anyhow::Error) -> Response {
+    error!("Failed to access provisioning campaigns: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+        error: "Database Error".to_string(),
+        message: e.to_string(),
+    })).into_response()
+}
+
+// A campaign by ID, or an error response
+async fn load_campaign(id: &Uuid) -> Result<crate::campaign::Campaign, Response> {
+    match db::get_campaign(id).await {
+        Ok(Some(campaign)) => Ok(campaign),
+        Ok(None) => Err((StatusCode::NOT_FOUND, Json(ErrorResponse {
+            error: "Not Found".to_string(),
+            message: format!("Campaign with ID {} not found", id),
+        })).into_response()),
+        Err(e) => Err(campaign_db_error(e)),
     }
+}
 
-    match db::get_all_tags().await {
-        Ok(tags) => (StatusCode::OK, Json(tags)).into_response(),
-        Err(e) => {
-            error!("Failed to get all tags: {}", e);
-            let error_response = ErrorResponse {
-                error: "Database Error".to_string(),
-                message: format!("Failed to retrieve tags: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
-        }
+async fn api_list_campaigns(auth_session: AuthSession) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+    match db::list_campaigns().await {
+        Ok(campaigns) => Json(campaigns).into_response(),
+        Err(e) => campaign_db_error(e),
     }
 }
 
-/// Create a new tag
-#[axum::debug_handler]
-async fn api_create_tag(
+// Start a provisioning campaign, optionally with an initial set of machines
+async fn api_create_campaign(
     State(state): State<AppState>,
     auth_session: AuthSession,
-    Json(payload): Json<serde_json::Value>,
+    Json(payload): Json<CreateCampaignRequest>,
 ) -> Response {
-    // Check if user is authenticated as admin
-    if let Err(response) = crate::auth::require_admin(&auth_session) {
-        return response;
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
 
-    // Extract tag name from JSON payload
-    let tag_name = match payload.get("name").and_then(|v| v.as_str()) {
-        Some(name) => name.to_string(),
-        None => {
-            return (
-                StatusCode::BAD_REQUEST, 
-                Json(json!({"error": "Missing tag name", "message": "Tag name is required"}))
-            ).into_response();
-        }
+    let now = chrono::Utc::now();
+    let mut campaign = match crate::campaign::Campaign::new(&payload.name, payload.recipients, payload.deadline, now) {
+        Ok(campaign) => campaign,
+        Err(e) => return bulk_bad_request(e.to_string()),
+    };
+    let channels = match db::get_notification_settings().await {
+        Ok(settings) => settings.channels,
+        Err(e) => return campaign_db_error(e),
     };
+    if let Some(unknown) = campaign.recipients.iter().find(|r| !channels.iter().any(|c| c.name.trim().eq_ignore_ascii_case(r))) {
+        return bulk_bad_request(format!("No notification channel named '{}'", unknown));
+    }
+    if !payload.selector.is_empty() {
+        let targets = match bulk_targets(&payload.selector).await {
+            Ok(targets) => targets,
+            Err(response) => return response,
+        };
+        campaign.add_machines(targets.iter().map(|(machine, _)| machine.id), now);
+    }
+    if let Err(e) = db::save_campaign(&campaign).await {
+        return campaign_db_error(e);
+    }
+    info!("Started provisioning campaign '{}' with {} machine(s)", campaign.name, campaign.members.len());
+    let _ = state.event_manager.send(format!("campaign_updated:{}", campaign.id));
+    (StatusCode::CREATED, Json(campaign)).into_response()
+}
 
-    // Validate tag name - no empty tags
-    if tag_name.trim().is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({"error": "Invalid tag name", "message": "Tag name cannot be empty"}))
-        ).into_response();
+async fn api_get_campaign(auth_session: AuthSession, Path(id): Path<Uuid>) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
+    match load_campaign(&id).await {
+        Ok(campaign) => Json(campaign).into_response(),
+        Err(response) => response,
+    }
+}
 
-    match db::create_tag(&tag_name).await {
-        Ok(true) => {
-            // Emit tag created event
-            let _ = state.event_manager.send("tags_updated".to_string());
-            (StatusCode::CREATED, Json(json!({"success": true, "message": "Tag created"}))).into_response()
-        },
-        Ok(false) => {
-            (
-                StatusCode::CONFLICT,
-                Json(json!({"error": "Tag exists", "message": "A tag with this name already exists"}))
-            ).into_response()
-        },
-        Err(e) => {
-            error!("Failed to create tag '{}': {}", tag_name, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": "Database error", "message": format!("Failed to create tag: {}", e)}))
-            ).into_response()
-        }
+// Add the selected machines to a running campaign
+async fn api_add_campaign_machines(
+    State(state): State<AppState>,
+    auth_session: AuthSession,
+    Path(id): Path<Uuid>,
+    Json(selector): Json<crate::bulk::MachineSelector>,
+) -> Response {
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
+    }
+
+    let mut campaign = match load_campaign(&id).await {
+        Ok(campaign) => campaign,
+        Err(response) => return response,
+    };
+    if !campaign.is_active() {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Conflict".to_string(),
+            message: "Campaign has already finished".to_string(),
+        })).into_response();
+    }
+    let targets = match bulk_targets(&selector).await {
+        Ok(targets) => targets,
+        Err(response) => return response,
+    };
+    let added = campaign.add_machines(targets.iter().map(|(machine, _)| machine.id), chrono::Utc::now());
+    if let Err(e) = db::save_campaign(&campaign).await {
+        return campaign_db_error(e);
     }
+    let _ = state.event_manager.send(format!("campaign_updated:{}", campaign.id));
+    Json(json!({
+        "matched": targets.len(),
+        "added": added,
+        "campaign": campaign,
+    })).into_response()
 }
 
-/// Delete a tag from the system
-#[axum::debug_handler]
-async fn api_delete_tag(
+// End a campaign now, sending its report with whatever has finished so far
+async fn api_finish_campaign(
     State(state): State<AppState>,
     auth_session: AuthSession,
-    Path(tag_name): Path<String>,
+    Path(id): Path<Uuid>,
 ) -> Response {
-    // Check if user is authenticated as admin
-    if let Err(response) = crate::auth::require_admin(&auth_session) {
-        return response;
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
 
-    match db::delete_tag(&tag_name).await {
-        Ok(true) => {
-            // Emit tag deleted event
-            let _ = state.event_manager.send("tags_updated".to_string());
-            (StatusCode::OK, Json(json!({"success": true, "message": "Tag deleted"}))).into_response()
-        },
-        Ok(false) => {
-            (
-                StatusCode::NOT_FOUND,
-                Json(json!({"error": "Not found", "message": "Tag not found"}))
-            ).into_response()
-        },
-        Err(e) => {
-            error!("Failed to delete tag '{}': {}", tag_name, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": "Database error", "message": format!("Failed to delete tag: {}", e)}))
-            ).into_response()
-        }
+    let mut campaign = match load_campaign(&id).await {
+        Ok(campaign) => campaign,
+        Err(response) => return response,
+    };
+    if !campaign.is_active() {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Conflict".to_string(),
+            message: "Campaign has already finished".to_string(),
+        })).into_response();
+    }
+    match crate::campaign::finish(&mut campaign, &state.event_manager).await {
+        Ok(()) => Json(campaign).into_response(),
+        Err(e) => campaign_db_error(e),
     }
 }
 
-/// Get all machines with a specific tag
-#[axum::debug_handler]
-async fn api_get_machines_by_tag(
+// Install a campaign's waiting machines as a rollout: the canary wave now, the rest once the
+// canaries succeed
+async fn api_start_rollout(
+    State(state): State<AppState>,
     auth_session: AuthSession,
-    Path(tag_name): Path<String>,
+    Path(id): Path<Uuid>,
+    Json(strategy): Json<crate::campaign::RolloutStrategy>,
 ) -> Response {
-    // Check if user is authenticated as admin
-    if let Err(response) = crate::auth::require_admin(&auth_session) {
-        return response;
+    if auth_session.user.is_none() {
+        return (StatusCode::UNAUTHORIZED, Json(json!({
+            "error": "Unauthorized",
+            "message": "Admin authentication required for this operation"
+        }))).into_response();
     }
 
-    match db::get_machines_by_tag(&tag_name).await {
-        Ok(machines) => (StatusCode::OK, Json(machines)).into_response(),
-        Err(e) => {
-            error!("Failed to get machines for tag {}: {}", tag_name, e);
-            let error_response = ErrorResponse {
-                error: "Database Error".to_string(),
-                message: format!("Failed to retrieve machines: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+    let mut campaign = match load_campaign(&id).await {
+        Ok(campaign) => campaign,
+        Err(response) => return response,
+    };
+    if !campaign.is_active() || campaign.rollout.is_some() {
+        return (StatusCode::CONFLICT, Json(ErrorResponse {
+            error: "Conflict".to_string(),
+            message: if campaign.is_active() { "Campaign already has a rollout" } else { "Campaign has already finished" }.to_string(),
+        })).into_response();
+    }
+    if let Err(e) = campaign.check_rollout(&strategy) {
+        return bulk_bad_request(e.to_string());
+    }
+
+    // Reinstalling machines in service needs a second person when the two-person rule is on
+    let machines = match db::get_all_machines().await {
+        Ok(machines) => machines,
+        Err(e) => return campaign_db_error(e),
+    };
+    let waiting: Vec<&Machine> = machines.iter()
+        .filter(|machine| campaign.members.iter().any(|m| m.machine_id == machine.id && m.state == crate::campaign::MemberState::Pending))
+        .collect();
+    if waiting.iter().any(|machine| machine.status == MachineStatus::Ready) {
+        let action = crate::action_approvals::DestructiveAction::StartRollout { campaign_id: id, strategy: strategy.clone() };
+        let summary = format!("{} (campaign '{}', canary rollout)", action_summary("Reinstall", &waiting), campaign.name);
+        if let Some(response) = held_for_approval(&auth_session, action, summary).await {
+            return response;
         }
     }
+
+    match crate::campaign::begin_rollout(&state, &mut campaign, strategy).await {
+        Ok(()) => Json(campaign).into_response(),
+        Err(e) => campaign_db_error(e),
+    }
 }
 
 // New reimage handler
@@ -3018,45 +10474,145 @@ async fn reimage_machine(
         }))).into_response();
     }
 
-    info!("Initiating reimage for machine {}", id);
-    
+    let (machine, os_choice) = match reimage_preflight(&_state, &id).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+
+    // Reinstalling a machine that's in service needs a second person when the two-person rule is on
+    if machine.status == MachineStatus::Ready {
+        let action = crate::action_approvals::DestructiveAction::ReinstallMachines { machine_ids: vec![id] };
+        if let Some(response) = held_for_approval(&auth_session, action, action_summary("Reinstall", &[&machine])).await {
+            if response.status() != StatusCode::ACCEPTED {
+                return response;
+            }
+            let response_html = format!(r###"
+                <div class="p-4 mb-4 text-sm text-yellow-700 bg-yellow-100 rounded-lg" role="alert">
+                    <span class="font-medium">Approval required.</span> Reimaging machine {} waits for a second person to approve it.
+                </div>
+            "###, id);
+            return (StatusCode::ACCEPTED, [(axum::http::header::CONTENT_TYPE, "text/html")], response_html).into_response();
+        }
+    }
+
+    match start_reimage(&_state, &machine, &os_choice).await {
+        Ok(()) => {
+            // Return success response
+            let response_html = format!(r###"
+                <div class="p-4 mb-4 text-sm text-green-700 bg-green-100 rounded-lg" role="alert">
+                    <span class="font-medium">Success!</span> Reimaging machine {} with {}. 
+                    <p>Installation has started and may take several minutes to complete.</p>
+                </div>
+            "###, id, os_choice);
+
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/html")], response_html).into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+// Everything that has to hold before a machine can be reimaged; the machine and the OS to
+// install on success, an error response otherwise
+async fn reimage_preflight(state: &AppState, id: &Uuid) -> Result<(Machine, String), Response> {
+    match crate::maintenance::status(id).await {
+        Ok(status) if status.maintenance => return Err(maintenance_blocked(id, &status)),
+        Ok(_) => {}
+        Err(e) => return Err(maintenance_db_error(&format!("check maintenance for machine {}", id), e)),
+    }
+
+    match db::get_ephemeral_config(id).await {
+        Ok(Some(_)) => {
+            return Err((StatusCode::CONFLICT, Json(ErrorResponse {
+                error: "Machine Is Ephemeral".to_string(),
+                message: format!("Machine {} boots a live image and has nothing to reimage; turn off ephemeral mode first", id),
+            })).into_response());
+        }
+        Ok(None) => {}
+        Err(e) => return Err(ephemeral_db_error(&format!("check ephemeral mode for machine {}", id), e)),
+    }
+
     // Get the machine first to make sure we have a valid OS choice
-    let machine = match db::get_machine_by_id(&id).await {
+    let machine = match db::get_machine_by_id(id).await {
         Ok(Some(machine)) => machine,
         Ok(None) => {
-            return (StatusCode::NOT_FOUND, Json(json!({
+            return Err((StatusCode::NOT_FOUND, Json(json!({
                 "error": "Not Found",
                 "message": format!("Machine with ID {} not found", id)
-            }))).into_response();
+            }))).into_response());
         },
         Err(e) => {
             error!("Failed to get machine {}: {}", id, e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
                 "error": "Database Error",
                 "message": e.to_string()
-            }))).into_response();
+            }))).into_response());
         }
     };
     
     // Make sure there's an OS choice to reimage with
     let os_choice = match machine.os_choice {
-        Some(ref os) if !os.is_empty() => os,
+        Some(ref os) if !os.is_empty() => os.clone(),
         _ => {
-            return (StatusCode::BAD_REQUEST, Json(json!({
+            return Err((StatusCode::BAD_REQUEST, Json(json!({
                 "error": "Bad Request",
                 "message": "No OS choice set for this machine. Please assign an OS first."
-            }))).into_response();
+            }))).into_response());
         }
     };
     
+    // Refuse to start an install if artifact or database storage is low
+    if let Some(status) = crate::storage::low_storage() {
+        let message = crate::storage::low_storage_message(&status);
+        warn!("Refusing reimage of machine {}: {}", id, message);
+        let _ = state.event_manager.send(format!("storage_low:{}:{}", status.name, status.free_bytes));
+        return Err((StatusCode::INSUFFICIENT_STORAGE, Json(json!({
+            "error": "Insufficient Storage",
+            "message": message
+        }))).into_response());
+    }
+
+    Ok((machine, os_choice))
+}
+
+/// Assign an OS (when given) and start installing it, for installs started outside a request
+/// such as a campaign rollout. Errors are the message the reinstall endpoint would return.
+pub(crate) async fn start_campaign_install(state: &AppState, id: &Uuid, os_choice: Option<&str>) -> Result<(), String> {
+    if let Some(os_choice) = os_choice {
+        match db::assign_os(id, os_choice).await {
+            Ok(true) => {}
+            Ok(false) => return Err(format!("Machine with ID {} not found", id)),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let outcome = match reimage_preflight(state, id).await {
+        Ok((machine, os_choice)) => start_reimage(state, &machine, &os_choice).await,
+        Err(response) => Err(response),
+    };
+    match outcome {
+        Ok(()) => Ok(()),
+        Err(response) => Err(response_message(response).await),
+    }
+}
+
+// Set the machine installing, create its workflow and (for Proxmox VMs) reboot it into PXE
+async fn start_reimage(state: &AppState, machine: &Machine, os_choice: &str) -> Result<(), Response> {
+    let id = machine.id;
+    info!("Initiating reimage for machine {}", id);
+
     // Set the machine status to InstallingOS
     match db::reimage_machine(&id).await {
         Ok(true) => {
             // Create a workflow for OS installation
-            match crate::tinkerbell::create_workflow(&machine, &os_choice).await {
-                Ok(_) => {
+            match crate::tinkerbell_outbox::create_workflow(&*state.tinkerbell, machine, os_choice).await {
+                Ok(outcome) => {
                     // Emit machine updated event
-                    let _ = _state.event_manager.send(format!("machine_updated:{}", id));
+                    let _ = state.event_manager.send(format!("machine_updated:{}", id));
+                    
+                    // Rebooting now would only boot the agent again, with no workflow to run
+                    if outcome == crate::tinkerbell_outbox::Outcome::Queued {
+                        info!("Workflow for machine {} is queued until Kubernetes is reachable, skipping reboot", id);
+                        return Ok(());
+                    }
                     
                     // If this is a Proxmox VM, reboot it into PXE boot mode
                     if machine.proxmox_vmid.is_some() && machine.proxmox_node.is_some() {
@@ -3068,7 +10624,7 @@ async fn reimage_machine(
                         
                         // Call the power action handler
                         match crate::handlers::machines::bmc_power_action_handler(
-                            State(_state.clone()),
+                            State(state.clone()),
                             Path(id),
                             Json(power_action),
                         ).await {
@@ -3080,41 +10636,42 @@ async fn reimage_machine(
                                 error!("Failed to reboot Proxmox VM {}: {:?}", id, e);
                             }
                         }
+                    } else if !crate::terminal::agent_connected(&id) {
+                        // No BMC or agent to do it; reboot to PXE over SSH when credentials are stored
+                        match crate::ssh::credentials_for(&id).await {
+                            Ok(Some(_)) => match crate::ssh::run_action(machine, crate::ssh::Action::RebootPxe).await {
+                                Ok(_) => info!("Rebooted machine {} into PXE over SSH for reimage", id),
+                                Err(e) => error!("Failed to reboot machine {} over SSH: {:#}", id, e),
+                            },
+                            Ok(None) => info!("Machine {} is not a Proxmox VM and has no SSH credentials, skipping reboot", id),
+                            Err(e) => error!("Failed to load SSH credentials for machine {}: {}", id, e),
+                        }
                     } else {
                         info!("Machine {} is not a Proxmox VM, skipping reboot", id);
                     }
-                    
-                    // Return success response
-                    let response_html = format!(r###"
-                        <div class="p-4 mb-4 text-sm text-green-700 bg-green-100 rounded-lg" role="alert">
-                            <span class="font-medium">Success!</span> Reimaging machine {} with {}. 
-                            <p>Installation has started and may take several minutes to complete.</p>
-                        </div>
-                    "###, id, os_choice);
-                    
-                    (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "text/html")], response_html).into_response()
+                    Ok(())
                 },
                 Err(e) => {
                     error!("Failed to create workflow for machine {}: {}", id, e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+                    Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
                         "error": "Workflow Error",
                         "message": format!("Failed to create installation workflow: {}", e)
-                    }))).into_response()
+                    }))).into_response())
                 }
             }
         },
         Ok(false) => {
-            return (StatusCode::NOT_FOUND, Json(json!({
+            Err((StatusCode::NOT_FOUND, Json(json!({
                 "error": "Not Found",
                 "message": format!("Machine with ID {} not found", id)
-            }))).into_response();
+            }))).into_response())
         },
         Err(e) => {
             error!("Failed to set machine {} status to InstallingOS: {}", id, e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
                 "error": "Database Error",
                 "message": e.to_string()
-            }))).into_response();
+            }))).into_response())
         }
     }
 }
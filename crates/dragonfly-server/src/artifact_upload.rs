@@ -0,0 +1,299 @@
+// Artifact uploads: push locally built images, custom kernels or apkovl overlays straight into
+// the artifact store, without shell access to the server host.
+//
+// `PUT /api/artifacts/{path}` with the whole file stores it in one request. Large files can be
+// sent in pieces instead, each with a `Content-Range: bytes start-end/total` header. Pieces must
+// arrive in order and are appended to a staging file under `.uploads/`, so an interrupted upload
+// picks up from the `received` offset reported by `GET /api/artifacts/{path}`. Nothing appears at
+// the artifact's path (served as /ipxe/{path}) until the last byte is in and, if the client sent
+// an X-Checksum-Sha256 header with it, the checksum matches.
+
+use anyhow::Result;
+use axum::body::Body;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use thiserror::Error;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+const STAGING_DIR: &str = ".uploads";
+const MAX_DEPTH: usize = 8;
+const MAX_COMPONENT_LEN: usize = 128;
+
+/// Where a piece of an upload goes in the whole file; `end` is inclusive, as in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    pub start: u64,
+    pub end: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum UploadError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Upload of this artifact has {received} bytes; the next piece must start there")]
+    OffsetMismatch { received: u64 },
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Another upload to this path is in progress")]
+    Busy,
+    #[error("Uploads are limited to {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        UploadError::Other(e.into())
+    }
+}
+
+/// What a request did to an upload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Progress {
+    /// More pieces are expected
+    Partial { received: u64, total: u64 },
+    /// The artifact is in place
+    Complete { size: u64, sha256: String },
+}
+
+// Kept next to the staging file, so pieces from a different upload aren't mixed in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UploadState {
+    path: String,
+    total: u64,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadProgress {
+    pub received: u64,
+    pub total: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactInfo {
+    pub path: String,
+    /// Where machines download it
+    pub url: String,
+    /// None until an upload has completed
+    pub size: Option<u64>,
+    /// An unfinished chunked upload, if any
+    pub upload: Option<UploadProgress>,
+}
+
+/// Normalize a relative artifact path such as "kernels/custom/vmlinuz". Hidden names are
+/// refused, which keeps uploads out of the staging area and away from `..`.
+pub fn validate_path(path: &str) -> Result<String, UploadError> {
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let valid = parts.len() <= MAX_DEPTH
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part.len() <= MAX_COMPONENT_LEN
+                && !part.starts_with('.')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+        });
+    if !valid {
+        return Err(UploadError::Invalid(format!(
+            "'{}' is not a valid artifact path: use up to {} segments of letters, digits, '-', '_', '.' and '+', not starting with '.'",
+            path, MAX_DEPTH
+        )));
+    }
+    Ok(parts.join("/"))
+}
+
+/// Parse a `Content-Range: bytes start-end/total` header.
+pub fn parse_content_range(value: &str) -> Result<ContentRange, UploadError> {
+    let invalid = || UploadError::Invalid(format!("Content-Range '{}' should look like 'bytes 0-1048575/5000000'", value));
+    let (range, total) = value.trim().strip_prefix("bytes ").and_then(|v| v.split_once('/')).ok_or_else(invalid)?;
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let range = ContentRange {
+        start: start.trim().parse().map_err(|_| invalid())?,
+        end: end.trim().parse().map_err(|_| invalid())?,
+        total: total.trim().parse().map_err(|_| invalid())?,
+    };
+    if range.start > range.end || range.end >= range.total {
+        return Err(invalid());
+    }
+    Ok(range)
+}
+
+fn destination(path: &str) -> PathBuf {
+    crate::api::artifact_base_dir().join(path)
+}
+
+// Staging file and state for a path; hashed so nested paths share one flat directory
+fn staging(path: &str) -> (PathBuf, PathBuf) {
+    let key = format!("{:x}", Sha256::digest(path.as_bytes()));
+    let dir = crate::api::artifact_base_dir().join(STAGING_DIR);
+    (dir.join(format!("{}.partial", key)), dir.join(format!("{}.json", key)))
+}
+
+// Paths with a request writing to them, so two clients can't interleave pieces
+static IN_FLIGHT: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Held while a request writes to an upload; a second writer to the same key gets `Busy`.
+pub(crate) struct InFlight(String);
+
+impl InFlight {
+    pub(crate) fn claim(path: &str) -> Result<Self, UploadError> {
+        if !IN_FLIGHT.lock().unwrap().insert(path.to_string()) {
+            return Err(UploadError::Busy);
+        }
+        Ok(InFlight(path.to_string()))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.0);
+    }
+}
+
+async fn read_state(state_file: &Path) -> Result<Option<UploadState>> {
+    match fs::read(state_file).await {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn remove_staging(partial: &Path, state_file: &Path) {
+    let _ = fs::remove_file(partial).await;
+    let _ = fs::remove_file(state_file).await;
+}
+
+/// Take the body of a PUT: the whole artifact when `range` is None, otherwise the next piece.
+pub async fn receive(path: &str, range: Option<ContentRange>, expected_sha256: Option<&str>, body: Body) -> Result<Progress, UploadError> {
+    let _claim = InFlight::claim(path)?;
+    let (partial, state_file) = staging(path);
+    if let Some(dir) = partial.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    // Where this piece goes, starting a new staging file when it's the first one
+    let (offset, total) = match range {
+        Some(range) if range.start > 0 => {
+            let state = read_state(&state_file).await?;
+            let received = match &state {
+                Some(state) if state.path == path && state.total == range.total => fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0),
+                _ => 0,
+            };
+            if received != range.start {
+                return Err(UploadError::OffsetMismatch { received });
+            }
+            (range.start, Some(range.total))
+        }
+        _ => {
+            let total = range.map(|r| r.total);
+            fs::File::create(&partial).await?;
+            let state = UploadState { path: path.to_string(), total: total.unwrap_or(0), started_at: Utc::now() };
+            fs::write(&state_file, serde_json::to_vec(&state).map_err(anyhow::Error::from)?).await?;
+            (0, total)
+        }
+    };
+
+    let mut file = fs::OpenOptions::new().append(true).open(&partial).await?;
+    let mut stream = body.into_data_stream();
+    let mut written = 0u64;
+    let appended: Result<()> = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+        Ok(())
+    }.await;
+
+    let expected_len = range.map(|r| r.end - r.start + 1);
+    if let Err(e) = appended {
+        // Drop whatever part of the piece arrived, so it can simply be sent again
+        file.set_len(offset).await?;
+        return Err(e.into());
+    }
+    if let Some(expected_len) = expected_len.filter(|len| *len != written) {
+        file.set_len(offset).await?;
+        return Err(UploadError::Invalid(format!("Content-Range covers {} bytes but the body had {}", expected_len, written)));
+    }
+    drop(file);
+
+    let received = offset + written;
+    if let Some(total) = total.filter(|total| received < *total) {
+        return Ok(Progress::Partial { received, total });
+    }
+
+    let sha256 = crate::custom_images::hash_file(&partial).await?;
+    if let Some(expected) = expected_sha256.map(|s| s.trim().to_lowercase()) {
+        if expected != sha256 {
+            remove_staging(&partial, &state_file).await;
+            return Err(UploadError::ChecksumMismatch { expected, actual: sha256 });
+        }
+    }
+    let dest = destination(path);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(&partial, &dest).await?;
+    let _ = fs::remove_file(&state_file).await;
+    info!("Stored uploaded artifact {} ({} bytes, sha256 {})", path, received, sha256);
+    Ok(Progress::Complete { size: received, sha256 })
+}
+
+/// The artifact at a path and any unfinished upload to it.
+pub async fn info(path: &str) -> Result<ArtifactInfo> {
+    let size = fs::metadata(destination(path)).await.ok().filter(|m| m.is_file()).map(|m| m.len());
+    let (partial, state_file) = staging(path);
+    let upload = match read_state(&state_file).await? {
+        Some(state) if state.path == path => Some(UploadProgress {
+            received: fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0),
+            total: state.total,
+            started_at: state.started_at,
+        }),
+        _ => None,
+    };
+    Ok(ArtifactInfo { path: path.to_string(), url: crate::base_path::url(&format!("/ipxe/{}", path)), size, upload })
+}
+
+/// Throw away an unfinished upload; the stored artifact, if any, is left alone.
+pub async fn abort(path: &str) -> Result<bool, UploadError> {
+    let _claim = InFlight::claim(path)?;
+    let (partial, state_file) = staging(path);
+    let existed = fs::try_exists(&state_file).await.unwrap_or(false);
+    remove_staging(&partial, &state_file).await;
+    Ok(existed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_path() {
+        assert_eq!(validate_path("/kernels/custom/vmlinuz-6.8+lab/").unwrap(), "kernels/custom/vmlinuz-6.8+lab");
+        assert_eq!(validate_path("dragonfly-agent/localhost.apkovl.tar.gz").unwrap(), "dragonfly-agent/localhost.apkovl.tar.gz");
+        for bad in ["", "../etc/passwd", "a/../b", ".uploads/x.partial", "a//b", "a b", "a/.hidden", "a\\b"] {
+            assert!(validate_path(bad).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-1023/4096").unwrap(), ContentRange { start: 0, end: 1023, total: 4096 });
+        assert_eq!(parse_content_range("bytes 4095-4095/4096").unwrap().end, 4095);
+        for bad in ["bytes 0-4096/4096", "bytes 10-5/100", "bytes */100", "0-10/100", "bytes 0-10/*"] {
+            assert!(parse_content_range(bad).is_err(), "{} should be rejected", bad);
+        }
+    }
+}
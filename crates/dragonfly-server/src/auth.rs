@@ -158,7 +158,7 @@ impl IntoResponse for AuthError {
         // For now, just return the status code and a simple message.
 
         // Redirect back to login page with an error message
-        let redirect_url = format!("/login?error={}", urlencoding::encode(&user_message));
+        let redirect_url = crate::base_path::url(&format!("/login?error={}", urlencoding::encode(&user_message)));
         (status, Redirect::to(&redirect_url)).into_response()
 
         // Alternatively, return JSON:
@@ -185,6 +185,18 @@ pub struct Settings {
     pub proxmox_password: Option<String>,
     pub proxmox_port: Option<u16>,
     pub proxmox_skip_tls_verify: Option<bool>,
+
+    // How long to keep timing, event and job history data
+    pub retention: crate::retention::RetentionSettings,
+
+    // Timezone, NTP and locale for installed systems (tags can override)
+    pub system: crate::system_config::SystemConfig,
+
+    // Organization name, support contact and MOTD shown in the UI and on installed systems
+    pub branding: crate::branding::Branding,
+
+    // Base URL, network interface and artifact directory chosen in the setup wizard
+    pub server: crate::setup::ServerSettings,
 }
 
 impl Default for Settings {
@@ -205,6 +217,10 @@ impl Default for Settings {
             proxmox_password: None,
             proxmox_port: None,
             proxmox_skip_tls_verify: Some(false),
+            retention: crate::retention::RetentionSettings::default(),
+            system: crate::system_config::SystemConfig::default(),
+            branding: crate::branding::Branding::default(),
+            server: crate::setup::ServerSettings::default(),
         }
     }
 }
@@ -360,7 +376,7 @@ async fn login_page(
     Query(params): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
     // Check if we're in demo mode
-    let is_demo_mode = std::env::var("DRAGONFLY_DEMO_MODE").is_ok();
+    let is_demo_mode = app_state.config.server.demo_mode;
     
     // Check for error parameter
     let error = params.get("error").cloned();
@@ -411,7 +427,7 @@ async fn login_handler(
     Form(form): Form<LoginForm>,
 ) -> Response {
     // Check if we're in demo mode
-    let is_demo_mode = std::env::var("DRAGONFLY_DEMO_MODE").is_ok();
+    let is_demo_mode = crate::config::get().server.demo_mode;
     
     if is_demo_mode {
         // In demo mode, simply create a demo user and force-login without authentication
@@ -431,7 +447,7 @@ async fn login_handler(
         match auth_session.login(&demo_user).await {
             Ok(_) => {
                 info!("Demo mode: Login successful for user '{}'", demo_user.username);
-                return Redirect::to("/").into_response();
+                return Redirect::to(&crate::base_path::url("/")).into_response();
             },
             Err(e) => {
                 error!("Demo mode: Failed to set user session: {}", e);
@@ -462,11 +478,11 @@ async fn login_handler(
             }
             
             info!("Login successful for user '{}'", user.username);
-            Redirect::to("/").into_response()
+            Redirect::to(&crate::base_path::url("/")).into_response()
         }
         Ok(None) => {
             info!("Authentication failed for user '{}'", form.username);
-            Redirect::to("/login?error=invalid_credentials").into_response()
+            Redirect::to(&crate::base_path::url("/login?error=invalid_credentials")).into_response()
         }
         Err(e) => {
             error!("Error during authentication: {}", e);
@@ -477,7 +493,7 @@ async fn login_handler(
 
 async fn logout(mut auth_session: AuthSession) -> Response {
     match auth_session.logout().await {
-        Ok(_) => Redirect::to("/login")
+        Ok(_) => Redirect::to(&crate::base_path::url("/login"))
             .into_response()
             .add_alert(AlertMessage::success("Successfully logged out.")),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR
@@ -598,12 +614,12 @@ pub async fn save_settings(settings: &Settings) -> io::Result<()> {
 pub fn require_admin(auth_session: &AuthSession) -> Result<(), Response> {
     match auth_session.user {
         Some(_) => Ok(()),
-        None => Err(Redirect::to("/login").into_response()),
+        None => Err(Redirect::to(&crate::base_path::url("/login")).into_response()),
     }
 }
 
 async fn login_test_handler(auth_session: AuthSession) -> impl IntoResponse {
-    let is_demo_mode = std::env::var("DRAGONFLY_DEMO_MODE").is_ok();
+    let is_demo_mode = crate::config::get().server.demo_mode;
     let is_authenticated = auth_session.user.is_some();
     
     let username = auth_session.user
@@ -640,13 +656,15 @@ async fn login_test_handler(auth_session: AuthSession) -> impl IntoResponse {
                 </div>
                 
                 <div>
-                    <a href="/">Go to Dashboard</a> | 
-                    <a href="/login">Go to Login</a>
+                    <a href="{home_url}">Go to Dashboard</a> | 
+                    <a href="{login_url}">Go to Login</a>
                 </div>
             </div>
         </body>
         </html>
         "#,
+        home_url = crate::base_path::url("/"),
+        login_url = crate::base_path::url("/login"),
         demo_class = if is_demo_mode { "demo" } else { "" },
         is_demo = if is_demo_mode { "Enabled" } else { "Disabled" },
         is_auth = if is_authenticated { "Authenticated" } else { "Not Authenticated" },
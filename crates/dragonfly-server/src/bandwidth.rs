@@ -0,0 +1,253 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const GLOBAL_LIMIT_ENV_VAR: &str = "DRAGONFLY_ARTIFACT_BANDWIDTH_MBPS";
+const CLIENT_LIMIT_ENV_VAR: &str = "DRAGONFLY_ARTIFACT_CLIENT_BANDWIDTH_MBPS";
+
+// Buckets hold this much burst, so short requests like iPXE scripts aren't slowed
+const BURST: Duration = Duration::from_secs(1);
+
+// Throughput is averaged over this window
+const METER_WINDOW_SECS: u64 = 10;
+
+// Forget clients that haven't pulled anything for this long
+const CLIENT_IDLE: Duration = Duration::from_secs(300);
+
+// Limits are given in megabits per second, like link speeds
+fn limit_from_env(var: &str) -> Option<u64> {
+    crate::config::var(var)
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|mbps| *mbps > 0.0)
+        .map(|mbps| (mbps * 1_000_000.0 / 8.0) as u64)
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64, now: Instant) -> Self {
+        let rate = bytes_per_sec as f64;
+        let capacity = rate * BURST.as_secs_f64();
+        Self { rate, capacity, tokens: capacity, last: now }
+    }
+
+    // Spend `bytes` and return how long to wait before sending them. Tokens can go
+    // negative, so concurrent senders queue up behind each other instead of bursting.
+    fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+// Bytes per second over the last few seconds
+#[derive(Default)]
+struct Meter {
+    seconds: VecDeque<(u64, u64)>,
+    total: u64,
+}
+
+impl Meter {
+    fn record(&mut self, second: u64, bytes: u64) {
+        self.total += bytes;
+        match self.seconds.back_mut() {
+            Some((s, b)) if *s == second => *b += bytes,
+            _ => self.seconds.push_back((second, bytes)),
+        }
+        self.trim(second);
+    }
+
+    fn trim(&mut self, second: u64) {
+        while self.seconds.front().is_some_and(|(s, _)| *s + METER_WINDOW_SECS <= second) {
+            self.seconds.pop_front();
+        }
+    }
+
+    fn rate(&mut self, second: u64) -> u64 {
+        self.trim(second);
+        self.seconds.iter().map(|(_, b)| b).sum::<u64>() / METER_WINDOW_SECS
+    }
+}
+
+struct ClientState {
+    bucket: Option<TokenBucket>,
+    meter: Meter,
+    last_seen: Instant,
+}
+
+struct Limiter {
+    started: Instant,
+    global_limit: Option<u64>,
+    client_limit: Option<u64>,
+    global: Option<TokenBucket>,
+    global_meter: Meter,
+    clients: HashMap<String, ClientState>,
+}
+
+impl Limiter {
+    fn new(global_limit: Option<u64>, client_limit: Option<u64>, now: Instant) -> Self {
+        Self {
+            started: now,
+            global_limit,
+            client_limit,
+            global: global_limit.map(|rate| TokenBucket::new(rate, now)),
+            global_meter: Meter::default(),
+            clients: HashMap::new(),
+        }
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.started).as_secs()
+    }
+
+    fn take(&mut self, client: Option<&str>, bytes: usize, now: Instant) -> Duration {
+        let second = self.second(now);
+        self.global_meter.record(second, bytes as u64);
+        let mut wait = self.global.as_mut().map_or(Duration::ZERO, |b| b.take(bytes, now));
+
+        if let Some(client) = client {
+            let client_limit = self.client_limit;
+            let state = self.clients.entry(client.to_string()).or_insert_with(|| ClientState {
+                bucket: client_limit.map(|rate| TokenBucket::new(rate, now)),
+                meter: Meter::default(),
+                last_seen: now,
+            });
+            state.last_seen = now;
+            state.meter.record(second, bytes as u64);
+            if let Some(bucket) = state.bucket.as_mut() {
+                wait = wait.max(bucket.take(bytes, now));
+            }
+        }
+        wait
+    }
+
+    fn snapshot(&mut self, now: Instant) -> BandwidthStatus {
+        let second = self.second(now);
+        self.clients.retain(|_, c| now.saturating_duration_since(c.last_seen) < CLIENT_IDLE);
+        let mut clients: Vec<ClientThroughput> = self.clients.iter_mut()
+            .map(|(client, state)| ClientThroughput {
+                client: client.clone(),
+                bytes_per_sec: state.meter.rate(second),
+                bytes_total: state.meter.total,
+            })
+            .collect();
+        clients.sort_by(|a, b| b.bytes_per_sec.cmp(&a.bytes_per_sec).then_with(|| a.client.cmp(&b.client)));
+        BandwidthStatus {
+            global_limit_bytes_per_sec: self.global_limit,
+            client_limit_bytes_per_sec: self.client_limit,
+            bytes_per_sec: self.global_meter.rate(second),
+            bytes_total: self.global_meter.total,
+            clients,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientThroughput {
+    pub client: String,
+    pub bytes_per_sec: u64,
+    pub bytes_total: u64,
+}
+
+/// Artifact serving limits and current throughput.
+#[derive(Debug, Clone, Serialize)]
+pub struct BandwidthStatus {
+    pub global_limit_bytes_per_sec: Option<u64>,
+    pub client_limit_bytes_per_sec: Option<u64>,
+    pub bytes_per_sec: u64,
+    pub bytes_total: u64,
+    pub clients: Vec<ClientThroughput>,
+}
+
+static LIMITER: once_cell::sync::Lazy<Mutex<Limiter>> = once_cell::sync::Lazy::new(|| {
+    Mutex::new(Limiter::new(
+        limit_from_env(GLOBAL_LIMIT_ENV_VAR),
+        limit_from_env(CLIENT_LIMIT_ENV_VAR),
+        Instant::now(),
+    ))
+});
+
+/// Account for `bytes` about to be sent to `client`, sleeping as long as the limits require.
+pub async fn throttle(client: Option<&str>, bytes: usize) {
+    let wait = LIMITER.lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .take(client, bytes, Instant::now());
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+pub fn status() -> BandwidthStatus {
+    LIMITER.lock().unwrap_or_else(|p| p.into_inner()).snapshot(Instant::now())
+}
+
+/// Throughput in Prometheus text format.
+pub fn render_metrics() -> String {
+    let status = status();
+    let mut out = String::new();
+    out.push_str("# HELP dragonfly_artifact_bytes_total Bytes of artifacts served.\n");
+    out.push_str("# TYPE dragonfly_artifact_bytes_total counter\n");
+    out.push_str(&format!("dragonfly_artifact_bytes_total {}\n", status.bytes_total));
+    out.push_str("# HELP dragonfly_artifact_throughput_bytes Artifact throughput averaged over the last 10 seconds.\n");
+    out.push_str("# TYPE dragonfly_artifact_throughput_bytes gauge\n");
+    out.push_str(&format!("dragonfly_artifact_throughput_bytes {}\n", status.bytes_per_sec));
+    for client in &status.clients {
+        out.push_str(&format!("dragonfly_artifact_throughput_bytes{{client=\"{}\"}} {}\n", client.client, client.bytes_per_sec));
+    }
+    out.push_str("# HELP dragonfly_artifact_bandwidth_limit_bytes Configured artifact bandwidth limit (0 = unlimited).\n");
+    out.push_str("# TYPE dragonfly_artifact_bandwidth_limit_bytes gauge\n");
+    out.push_str(&format!("dragonfly_artifact_bandwidth_limit_bytes{{scope=\"global\"}} {}\n", status.global_limit_bytes_per_sec.unwrap_or(0)));
+    out.push_str(&format!("dragonfly_artifact_bandwidth_limit_bytes{{scope=\"client\"}} {}\n", status.client_limit_bytes_per_sec.unwrap_or(0)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_waits() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000, now);
+        assert_eq!(bucket.take(1000, now), Duration::ZERO);
+        assert_eq!(bucket.take(500, now), Duration::from_millis(500));
+        // Refilled after waiting
+        assert_eq!(bucket.take(0, now + Duration::from_millis(500)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_per_client_limit_is_separate() {
+        let now = Instant::now();
+        let mut limiter = Limiter::new(None, Some(1000), now);
+        assert_eq!(limiter.take(Some("10.0.0.1"), 1000, now), Duration::ZERO);
+        assert!(limiter.take(Some("10.0.0.1"), 1000, now) > Duration::ZERO);
+        assert_eq!(limiter.take(Some("10.0.0.2"), 1000, now), Duration::ZERO);
+        // Without a global limit, anonymous transfers are never held back
+        assert_eq!(limiter.take(None, 1_000_000, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_meter_averages_over_window() {
+        let now = Instant::now();
+        let mut limiter = Limiter::new(None, None, now);
+        limiter.take(Some("10.0.0.1"), 5000, now);
+        limiter.take(Some("10.0.0.1"), 5000, now + Duration::from_secs(1));
+        let status = limiter.snapshot(now + Duration::from_secs(1));
+        assert_eq!(status.bytes_total, 10_000);
+        assert_eq!(status.bytes_per_sec, 1000);
+        assert_eq!(status.clients[0].bytes_per_sec, 1000);
+        assert_eq!(limiter.snapshot(now + Duration::from_secs(20)).bytes_per_sec, 0);
+    }
+}
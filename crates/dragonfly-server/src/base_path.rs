@@ -0,0 +1,116 @@
+// Subpath deployment: serve the UI and API under a prefix such as /dragonfly, so Dragonfly
+// can sit behind a corporate reverse proxy alongside other tools. Set server.base_path
+// (DRAGONFLY_BASE_PATH) to the prefix the proxy forwards, and have the proxy pass it through
+// unstripped.
+//
+// Machines and agents reach the server directly (DRAGONFLY_BASE_URL) rather than through
+// the proxy, so every route also stays reachable at the root.
+
+use anyhow::{bail, Result};
+use axum::{response::Redirect, routing, Router};
+use once_cell::sync::Lazy;
+use tracing::{error, info};
+
+/// "/dragonfly/", "dragonfly" and "/dragonfly" all mean "/dragonfly"; "" and "/" mean the root.
+pub fn normalize(raw: &str) -> Result<String> {
+    let trimmed = raw.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    if trimmed.split('/').any(|part| part.is_empty() || part == "." || part == "..")
+        || !trimmed.chars().all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+    {
+        bail!("'{}' is not a usable URL path prefix", raw);
+    }
+    Ok(format!("/{}", trimmed))
+}
+
+static BASE_PATH: Lazy<String> = Lazy::new(|| {
+    let raw = crate::config::get().server.base_path.as_deref().unwrap_or_default();
+    match normalize(raw) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Ignoring server.base_path, serving from the root: {}", e);
+            String::new()
+        }
+    }
+});
+
+/// The configured prefix, e.g. "/dragonfly"; empty when served from the root.
+pub fn get() -> &'static str {
+    BASE_PATH.as_str()
+}
+
+fn join(base: &str, path: &str) -> String {
+    if path == "/" && !base.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}{}", base, path)
+    }
+}
+
+fn strip_from<'a>(base: &str, path: &'a str) -> &'a str {
+    match path.strip_prefix(base) {
+        Some("") => "/",
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
+
+/// A request path as the app sees it, without the base path it may have arrived under.
+pub fn strip(path: &str) -> &str {
+    strip_from(get(), path)
+}
+
+/// A root-relative path ("/login") as the browser should request it.
+pub fn url(path: &str) -> String {
+    join(get(), path)
+}
+
+/// Serve the app under the base path as well as at the root.
+pub fn wrap(app: Router) -> Router {
+    let base = get();
+    if base.is_empty() {
+        return app;
+    }
+    info!("Serving the UI and API under {}", base);
+    Router::new()
+        // Nested "/" only matches without the trailing slash
+        .route(&format!("{}/", base), routing::get(move || async move { Redirect::permanent(base) }))
+        .nest(base, app.clone())
+        .merge(app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("").unwrap(), "");
+        assert_eq!(normalize(" / ").unwrap(), "");
+        assert_eq!(normalize("dragonfly").unwrap(), "/dragonfly");
+        assert_eq!(normalize("/tools/dragonfly/").unwrap(), "/tools/dragonfly");
+        assert!(normalize("/tools//dragonfly").is_err());
+        assert!(normalize("/../etc").is_err());
+        assert!(normalize("/dragon fly").is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("", "/"), "/");
+        assert_eq!(join("", "/login"), "/login");
+        assert_eq!(join("/dragonfly", "/"), "/dragonfly");
+        assert_eq!(join("/dragonfly", "/login?error=x"), "/dragonfly/login?error=x");
+    }
+
+    #[test]
+    fn test_strip_from() {
+        assert_eq!(strip_from("", "/machines"), "/machines");
+        assert_eq!(strip_from("/dragonfly", "/dragonfly"), "/");
+        assert_eq!(strip_from("/dragonfly", "/dragonfly/"), "/");
+        assert_eq!(strip_from("/dragonfly", "/dragonfly/machines/1"), "/machines/1");
+        assert_eq!(strip_from("/dragonfly", "/machines"), "/machines");
+        assert_eq!(strip_from("/dragonfly", "/dragonflyish"), "/dragonflyish");
+    }
+}
@@ -0,0 +1,140 @@
+// What each machine was last provisioned with: the agent build it registered with, the HookOS
+// release it booted to install, and how it booted (PXE, HTTP Boot or USB). Comparing these
+// with what the server ships finds machines due a refresh; the machine list filters on it
+// with ?environment=<filter>.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::AgentEnvironment;
+use serde::Serialize;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::http_boot::BootMethod;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BootEnvironment {
+    pub agent_version: Option<String>,
+    pub agent_reported_at: Option<DateTime<Utc>>,
+    /// HookOS release served when the machine last booted to install
+    pub hookos_version: Option<String>,
+    pub hookos_booted_at: Option<DateTime<Utc>>,
+    pub boot_method: Option<BootMethod>,
+}
+
+/// The agent version this server ships; agents are built from the same workspace.
+pub fn current_agent_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+// Numeric parts of a version ("v0.10.1-rc1" -> [0, 10, 1]), for ordering
+fn version_parts(version: &str) -> Vec<u64> {
+    version.trim().trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether `version` is older than `current`. Versions that don't parse aren't called outdated.
+pub fn is_older(version: &str, current: &str) -> bool {
+    let (version, current) = (version_parts(version), version_parts(current));
+    !version.is_empty() && !current.is_empty() && version < current
+}
+
+impl BootEnvironment {
+    pub fn agent_outdated(&self) -> bool {
+        self.agent_version.as_deref().is_some_and(|v| is_older(v, current_agent_version()))
+    }
+
+    pub fn hookos_outdated(&self) -> bool {
+        self.hookos_version.as_deref().is_some_and(|v| is_older(v, crate::stack::RECOMMENDED_HOOK_RELEASE))
+    }
+}
+
+/// Store what an agent reported about itself; a boot method it names replaces the one the
+/// server saw, since only the agent knows about local boot media.
+pub async fn record_agent(machine_id: &Uuid, mac_address: &str, environment: &AgentEnvironment) -> Result<()> {
+    crate::db::record_agent_version(machine_id, environment.agent_version.trim()).await?;
+    if let Some(method) = &environment.boot_method {
+        match BootMethod::parse(method) {
+            Some(method) => crate::db::record_boot_method(mac_address, method).await?,
+            None => warn!("Ignoring unknown boot method '{}' reported by machine {}", method, machine_id),
+        }
+    }
+    Ok(())
+}
+
+/// Machine list filters on boot environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvironmentFilter {
+    /// Registered with an older agent, or installed with an older HookOS
+    Outdated,
+    OutdatedAgent,
+    OutdatedHookos,
+    /// Nothing recorded yet
+    Unknown,
+}
+
+impl EnvironmentFilter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnvironmentFilter::Outdated => "outdated",
+            EnvironmentFilter::OutdatedAgent => "outdated_agent",
+            EnvironmentFilter::OutdatedHookos => "outdated_hookos",
+            EnvironmentFilter::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "outdated" => Some(EnvironmentFilter::Outdated),
+            "outdated_agent" => Some(EnvironmentFilter::OutdatedAgent),
+            "outdated_hookos" => Some(EnvironmentFilter::OutdatedHookos),
+            "unknown" => Some(EnvironmentFilter::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, environment: Option<&BootEnvironment>) -> bool {
+        match self {
+            EnvironmentFilter::Outdated => environment.is_some_and(|e| e.agent_outdated() || e.hookos_outdated()),
+            EnvironmentFilter::OutdatedAgent => environment.is_some_and(BootEnvironment::agent_outdated),
+            EnvironmentFilter::OutdatedHookos => environment.is_some_and(BootEnvironment::hookos_outdated),
+            EnvironmentFilter::Unknown => match environment {
+                Some(e) => e.agent_version.is_none() && e.hookos_version.is_none(),
+                None => true,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_older() {
+        assert!(is_older("v0.9.2", "v0.10.0"));
+        assert!(is_older("0.1.0", "0.1.1"));
+        assert!(!is_older("v0.10.0", "v0.10.0"));
+        assert!(!is_older("0.2.0", "0.1.9"));
+        assert!(!is_older("custom-build", "0.1.0"));
+    }
+
+    #[test]
+    fn test_filters() {
+        let old_hook = BootEnvironment { hookos_version: Some("v0.8.1".to_string()), ..Default::default() };
+        let current = BootEnvironment {
+            agent_version: Some(current_agent_version().to_string()),
+            hookos_version: Some(crate::stack::RECOMMENDED_HOOK_RELEASE.to_string()),
+            ..Default::default()
+        };
+        assert!(EnvironmentFilter::Outdated.matches(Some(&old_hook)));
+        assert!(EnvironmentFilter::OutdatedHookos.matches(Some(&old_hook)));
+        assert!(!EnvironmentFilter::OutdatedAgent.matches(Some(&old_hook)));
+        assert!(!EnvironmentFilter::Outdated.matches(Some(&current)));
+        assert!(EnvironmentFilter::Unknown.matches(None));
+        assert!(EnvironmentFilter::Unknown.matches(Some(&BootEnvironment { boot_method: Some(BootMethod::Pxe), ..Default::default() })));
+        assert_eq!(EnvironmentFilter::parse(EnvironmentFilter::OutdatedHookos.as_str()), Some(EnvironmentFilter::OutdatedHookos));
+    }
+}
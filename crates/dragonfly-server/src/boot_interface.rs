@@ -0,0 +1,288 @@
+// Boot interface preferences for multi-homed machines.
+//
+// A machine with several NICs can network-boot from the wrong one: firmware tries them in its
+// own order and iPXE takes DHCP on whichever link answers first, so installs stall on a network
+// without the provisioning stack. An admin can name the NIC that should PXE and the NICs to fall
+// back to, in order. The preference is applied in three places:
+//
+// - iPXE: when /{mac} is requested from any other NIC, the script closes it, brings the boot NIC
+//   up (retrying DHCP a few times) and chains /{mac} from there, moving down the fallback list if
+//   a NIC can't get a lease.
+// - Redfish: the BMC's UEFI boot order is rewritten so the preferred NICs' PXE entries come first.
+// - Tinkerbell: the preferred NICs get hardware interfaces allowed to PXE; the registered NIC is
+//   not allowed to unless it's one of them.
+//
+// The boot NIC needn't be the MAC the machine registered with. HookOS is then told to identify
+// itself with the registered MAC, which is what Tinkerbell workflows are keyed on.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{BmcCredentials, Machine};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// DHCP attempts per NIC unless the preference sets its own
+pub const DEFAULT_DHCP_ATTEMPTS: u8 = 3;
+const MAX_DHCP_ATTEMPTS: u8 = 10;
+// A machine rarely has more NICs than this worth trying
+const MAX_FALLBACKS: usize = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BootInterfaces {
+    pub machine_id: Uuid,
+    /// The NIC that should network-boot
+    pub boot_mac: String,
+    /// NICs to try, in order, when the boot NIC can't get a DHCP lease
+    pub fallback_macs: Vec<String>,
+    pub dhcp_attempts: u8,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Boot interface preference as submitted by an admin.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BootInterfacesInput {
+    pub boot_mac: String,
+    #[serde(default)]
+    pub fallback_macs: Vec<String>,
+    #[serde(default)]
+    pub dhcp_attempts: Option<u8>,
+}
+
+// Lower-case, colon-separated form iPXE prints ${netN/mac} in
+fn normalize_mac(mac: &str) -> Result<String> {
+    let mac = mac.trim();
+    if !dragonfly_common::validation::is_valid_mac(mac) {
+        bail!("'{}' is not a MAC address", mac);
+    }
+    Ok(mac.to_lowercase().replace('-', ":"))
+}
+
+impl BootInterfacesInput {
+    pub fn validate(self, machine_id: Uuid) -> Result<BootInterfaces> {
+        let boot_mac = normalize_mac(&self.boot_mac)?;
+        let mut fallback_macs: Vec<String> = Vec::new();
+        for mac in &self.fallback_macs {
+            let mac = normalize_mac(mac)?;
+            if mac == boot_mac || fallback_macs.contains(&mac) {
+                bail!("MAC {} is listed more than once", mac);
+            }
+            fallback_macs.push(mac);
+        }
+        if fallback_macs.len() > MAX_FALLBACKS {
+            bail!("At most {} fallback interfaces can be set", MAX_FALLBACKS);
+        }
+        let dhcp_attempts = self.dhcp_attempts.unwrap_or(DEFAULT_DHCP_ATTEMPTS);
+        if !(1..=MAX_DHCP_ATTEMPTS).contains(&dhcp_attempts) {
+            bail!("DHCP attempts must be between 1 and {}", MAX_DHCP_ATTEMPTS);
+        }
+        Ok(BootInterfaces { machine_id, boot_mac, fallback_macs, dhcp_attempts, updated_at: Utc::now() })
+    }
+}
+
+impl BootInterfaces {
+    /// The NICs allowed to network-boot, preferred first.
+    pub fn macs(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.boot_mac.as_str()).chain(self.fallback_macs.iter().map(String::as_str))
+    }
+
+    pub fn allows_pxe(&self, mac: &str) -> bool {
+        self.macs().any(|m| m.eq_ignore_ascii_case(mac))
+    }
+
+    /// iPXE script that moves a boot that arrived on `arrived_on` over to the preferred NIC,
+    /// falling back through the list, and re-requests /{mac} from whichever NIC gets a lease.
+    pub fn switch_script(&self, base_url: &str, arrived_on: &str) -> String {
+        let macs: Vec<&str> = self.macs().collect();
+        let mut script = format!(
+            "#!ipxe\n\
+             # Machine {id} network-boots from {boot}; this boot arrived on {arrived}\n\
+             echo Switching to boot interface {boot}\n\
+             ifclose\n\
+             goto iface_0\n",
+            id = self.machine_id,
+            boot = self.boot_mac,
+            arrived = arrived_on,
+        );
+        for (i, mac) in macs.iter().enumerate() {
+            script.push_str(&format!(":iface_{}\nset wanted {}\nset next iface_{}\ngoto find\n", i, mac, i + 1));
+        }
+        script.push_str(&format!(
+            ":iface_{last}\n\
+             echo No boot interface could get a DHCP lease\n\
+             sleep 10\n\
+             reboot\n\
+             :find\n\
+             set nic:int32 0\n\
+             :find_loop\n\
+             isset ${{net${{nic}}/mac}} || goto ${{next}}\n\
+             iseq ${{net${{nic}}/mac}} ${{wanted}} && goto found ||\n\
+             inc nic\n\
+             goto find_loop\n\
+             :found\n\
+             set try:int32 0\n\
+             :dhcp_retry\n\
+             dhcp net${{nic}} && goto chain ||\n\
+             inc try\n\
+             iseq ${{try}} {attempts} && goto ${{next}} ||\n\
+             echo DHCP on ${{wanted}} failed, retrying\n\
+             sleep 5\n\
+             goto dhcp_retry\n\
+             :chain\n\
+             chain {base}/${{net${{nic}}/mac}}?switched=1\n",
+            last = macs.len(),
+            attempts = self.dhcp_attempts,
+            base = base_url.trim_end_matches('/'),
+        ));
+        script
+    }
+}
+
+/// A machine's boot interface preference, if it has one.
+pub async fn for_machine(machine_id: &Uuid) -> Result<Option<BootInterfaces>> {
+    crate::db::get_boot_interfaces(machine_id).await
+}
+
+/// The machine a MAC belongs to: its registered MAC, or one of its preferred boot NICs.
+pub async fn machine_for_mac(mac: &str) -> Result<Option<Machine>> {
+    if let Some(machine) = crate::db::get_machine_by_mac(mac).await? {
+        return Ok(Some(machine));
+    }
+    match crate::db::find_boot_interfaces_by_mac(&mac.to_lowercase()).await? {
+        Some(interfaces) => crate::db::get_machine_by_id(&interfaces.machine_id).await,
+        None => Ok(None),
+    }
+}
+
+// Whether a UEFI device path names a NIC, e.g. "PciRoot(0x0)/.../MAC(0CC47A1B2C3D,0x1)/IPv4(...)"
+fn device_path_has_mac(device_path: &str, mac: &str) -> bool {
+    let wanted = format!("MAC({},", mac.replace(':', "")).to_ascii_uppercase();
+    device_path.to_ascii_uppercase().contains(&wanted)
+}
+
+/// The BMC boot order with the preferred NICs' boot options moved to the front, in preference
+/// order. `options` are (BootOptionReference, UefiDevicePath) pairs. None if no option
+/// belongs to a preferred NIC.
+pub fn reorder_boot_order(current: &[String], options: &[(String, String)], interfaces: &BootInterfaces) -> Option<Vec<String>> {
+    let mut preferred: Vec<String> = Vec::new();
+    for mac in interfaces.macs() {
+        // Keep each NIC's own entries (IPv4, IPv6, HTTP) in the order the BMC had them
+        let mut entries: Vec<&String> = options.iter()
+            .filter(|(reference, path)| device_path_has_mac(path, mac) && !preferred.contains(reference))
+            .map(|(reference, _)| reference)
+            .collect();
+        entries.sort_by_key(|reference| current.iter().position(|r| r == *reference).unwrap_or(usize::MAX));
+        preferred.extend(entries.into_iter().cloned());
+    }
+    if preferred.is_empty() {
+        return None;
+    }
+    let rest = current.iter().filter(|reference| !preferred.contains(reference)).cloned();
+    Some(preferred.iter().cloned().chain(rest).collect())
+}
+
+/// Put the preferred NICs first in the BMC's UEFI boot order. Returns the new order.
+pub async fn apply_redfish_boot_order(
+    client: &reqwest::Client,
+    credentials: &BmcCredentials,
+    interfaces: &BootInterfaces,
+) -> Result<Vec<String>> {
+    let base = crate::sensors::base_url(&credentials.address);
+    let systems = crate::sensors::get(client, credentials, &format!("{}/redfish/v1/Systems", base)).await?;
+    let path = systems["Members"][0]["@odata.id"].as_str()
+        .context("BMC reports no computer systems")?;
+    let system = crate::sensors::get(client, credentials, &format!("{}{}", base, path)).await?;
+    let current: Vec<String> = system["Boot"]["BootOrder"].as_array()
+        .context("BMC doesn't expose a boot order")?
+        .iter()
+        .filter_map(|r| r.as_str().map(str::to_string))
+        .collect();
+
+    let options_path = system["Boot"]["BootOptions"]["@odata.id"].as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{}/BootOptions", path));
+    let collection = crate::sensors::get(client, credentials, &format!("{}{}", base, options_path)).await?;
+    let mut options = Vec::new();
+    for member in collection["Members"].as_array().into_iter().flatten() {
+        let Some(option_path) = member["@odata.id"].as_str() else {
+            continue;
+        };
+        let option = crate::sensors::get(client, credentials, &format!("{}{}", base, option_path)).await?;
+        if let (Some(reference), Some(device_path)) = (option["BootOptionReference"].as_str(), option["UefiDevicePath"].as_str()) {
+            options.push((reference.to_string(), device_path.to_string()));
+        }
+    }
+
+    let Some(order) = reorder_boot_order(&current, &options, interfaces) else {
+        bail!("BMC has no boot option for {}", interfaces.macs().collect::<Vec<_>>().join(", "));
+    };
+    if order != current {
+        let url = format!("{}{}", base, path);
+        let response = client.patch(&url)
+            .basic_auth(&credentials.username, credentials.password.as_deref())
+            .json(&serde_json::json!({ "Boot": { "BootOrder": order } }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("{} returned {}", url, response.status());
+        }
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interfaces() -> BootInterfaces {
+        BootInterfacesInput {
+            boot_mac: "0C-C4-7A-1B-2C-3D".to_string(),
+            fallback_macs: vec!["0c:c4:7a:1b:2c:3e".to_string()],
+            dhcp_attempts: None,
+        }.validate(Uuid::new_v4()).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let interfaces = interfaces();
+        assert_eq!(interfaces.boot_mac, "0c:c4:7a:1b:2c:3d");
+        assert_eq!(interfaces.dhcp_attempts, DEFAULT_DHCP_ATTEMPTS);
+        assert!(interfaces.allows_pxe("0C:C4:7A:1B:2C:3E"));
+        assert!(!interfaces.allows_pxe("0c:c4:7a:1b:2c:3f"));
+
+        let input = |boot_mac: &str, fallback: &str, attempts| BootInterfacesInput {
+            boot_mac: boot_mac.to_string(),
+            fallback_macs: vec![fallback.to_string()],
+            dhcp_attempts: Some(attempts),
+        };
+        assert!(input("0c:c4:7a:1b:2c:3d", "0C:C4:7A:1B:2C:3D", 3).validate(Uuid::nil()).is_err());
+        assert!(input("eth0", "0c:c4:7a:1b:2c:3e", 3).validate(Uuid::nil()).is_err());
+        assert!(input("0c:c4:7a:1b:2c:3d", "0c:c4:7a:1b:2c:3e", 0).validate(Uuid::nil()).is_err());
+    }
+
+    #[test]
+    fn test_switch_script() {
+        let script = interfaces().switch_script("http://10.0.0.1:3000/", "0c:c4:7a:1b:2c:3f");
+        assert!(script.starts_with("#!ipxe\n"));
+        assert!(script.contains(":iface_0\nset wanted 0c:c4:7a:1b:2c:3d\nset next iface_1\n"));
+        assert!(script.contains(":iface_1\nset wanted 0c:c4:7a:1b:2c:3e\nset next iface_2\n"));
+        assert!(script.contains(":iface_2\necho No boot interface could get a DHCP lease"));
+        assert!(script.contains("iseq ${try} 3 && goto ${next} ||"));
+        assert!(script.contains("chain http://10.0.0.1:3000/${net${nic}/mac}?switched=1"));
+    }
+
+    #[test]
+    fn test_reorder_boot_order() {
+        let current: Vec<String> = ["Boot0001", "Boot0002", "Boot0003", "Boot0004"].iter().map(|s| s.to_string()).collect();
+        let options = vec![
+            ("Boot0001".to_string(), "HD(1,GPT,...)/\\EFI\\ubuntu\\shimx64.efi".to_string()),
+            ("Boot0002".to_string(), "PciRoot(0x0)/Pci(0x1,0x0)/MAC(0CC47A1B2C3F,0x1)/IPv4(0.0.0.0)".to_string()),
+            ("Boot0003".to_string(), "PciRoot(0x0)/Pci(0x2,0x0)/MAC(0cc47a1b2c3e,0x1)/IPv4(0.0.0.0)".to_string()),
+            ("Boot0004".to_string(), "PciRoot(0x0)/Pci(0x3,0x0)/MAC(0CC47A1B2C3D,0x1)/IPv4(0.0.0.0)".to_string()),
+        ];
+        assert_eq!(
+            reorder_boot_order(&current, &options, &interfaces()),
+            Some(vec!["Boot0004".to_string(), "Boot0003".to_string(), "Boot0001".to_string(), "Boot0002".to_string()])
+        );
+        assert_eq!(reorder_boot_order(&current, &options[..2], &interfaces()), None);
+    }
+}
@@ -0,0 +1,232 @@
+// Network boot loop detection.
+//
+// A machine stuck in a PXE loop shows up as the agent registering over and over, minutes apart,
+// without the machine ever getting anywhere: the install workflow never starts, or whatever is
+// installed never boots. Each registration is remembered for a while with the status the machine
+// was in; enough of them inside the window, with no Ready in between, flags the machine with a
+// `BootLoop` warning listing likely causes.
+//
+// While flagged, OS policies don't assign the machine an OS and the workflow watchdog doesn't
+// restart its workflow, so automation stops feeding the loop. Reimages started by hand still
+// work. The flag stays until an admin clears it.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+const DEFAULT_REGISTRATIONS: usize = 3;
+const DEFAULT_WINDOW_MINUTES: i64 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootLoopConfig {
+    /// Registrations within the window that count as a loop
+    pub registrations: usize,
+    pub window: Duration,
+}
+
+impl BootLoopConfig {
+    // None when detection is turned off (a threshold or window of 0)
+    fn from_env() -> Option<Self> {
+        let number = |name: &str, default: i64| {
+            crate::config::var(name)
+                .and_then(|v| v.trim().parse::<i64>().ok())
+                .unwrap_or(default)
+        };
+        let registrations = number("DRAGONFLY_BOOT_LOOP_REGISTRATIONS", DEFAULT_REGISTRATIONS as i64);
+        let minutes = number("DRAGONFLY_BOOT_LOOP_MINUTES", DEFAULT_WINDOW_MINUTES);
+        // A single registration is never a loop
+        (registrations > 1 && minutes > 0).then(|| BootLoopConfig {
+            registrations: registrations as usize,
+            window: Duration::minutes(minutes),
+        })
+    }
+}
+
+static CONFIG: Lazy<Option<BootLoopConfig>> = Lazy::new(BootLoopConfig::from_env);
+
+/// A registration as seen by the detector.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registration {
+    pub at: DateTime<Utc>,
+    /// The machine's status when it registered, None the first time it was seen
+    pub previous_status: Option<MachineStatus>,
+    pub os_choice: Option<String>,
+    pub os_installed: Option<String>,
+}
+
+/// The warning stored for a machine that's looping.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootLoop {
+    pub machine_id: Uuid,
+    /// Registrations in the window when it was detected, and since
+    pub registrations: usize,
+    pub first_registration_at: DateTime<Utc>,
+    pub last_registration_at: DateTime<Utc>,
+    pub detected_at: DateTime<Utc>,
+    pub likely_causes: Vec<String>,
+}
+
+// Recent registrations per machine, oldest first
+static RECENT: Lazy<Mutex<HashMap<Uuid, Vec<Registration>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Add a registration to a machine's recent ones; true when they now amount to a loop.
+pub fn observe(recent: &mut Vec<Registration>, registration: Registration, config: &BootLoopConfig) -> bool {
+    // Reaching Ready since the last registration is progress; start counting again
+    if registration.previous_status == Some(MachineStatus::Ready) {
+        recent.clear();
+    }
+    recent.retain(|r| registration.at - r.at <= config.window);
+    recent.push(registration);
+    recent.len() >= config.registrations
+}
+
+/// Best guesses at why a machine keeps network booting, most specific first.
+pub fn likely_causes(recent: &[Registration]) -> Vec<String> {
+    let mut causes = Vec::new();
+    if recent.iter().any(|r| r.previous_status == Some(MachineStatus::InstallingOS)) {
+        causes.push("The install workflow never starts: the machine boots the Dragonfly agent again instead of HookOS. \
+            Check its workflow in Tinkerbell and that Smee/Tinkerbell can reach it.".to_string());
+    }
+    if recent.iter().any(|r| r.previous_status.as_ref().is_some_and(|s| matches!(s, MachineStatus::Error(_)))) {
+        causes.push("Installs keep failing and the machine falls back to network boot. Check its install logs.".to_string());
+    }
+    if recent.iter().any(|r| r.os_installed.is_some()) {
+        causes.push("The firmware boot order puts network boot ahead of the installed disk. \
+            Move the disk first, or set a one-time PXE boot for installs.".to_string());
+    }
+    if recent.iter().all(|r| r.os_choice.is_none()) {
+        causes.push("The machine reboots while waiting for an OS (a crashing agent, a kernel panic or a hardware watchdog). \
+            Check its console.".to_string());
+    }
+    causes.push("Another DHCP server on the network, or a second NIC, hands out a different boot file. \
+        Check the DHCP logs for this MAC.".to_string());
+    causes
+}
+
+/// Note a registration; returns the new warning the first time the machine is found looping.
+pub async fn record_registration(machine_id: &Uuid, previous: Option<&Machine>) -> Option<BootLoop> {
+    let config = (*CONFIG)?;
+    let registration = Registration {
+        at: Utc::now(),
+        previous_status: previous.map(|m| m.status.clone()),
+        os_choice: previous.and_then(|m| m.os_choice.clone()),
+        os_installed: previous.and_then(|m| m.os_installed.clone()),
+    };
+
+    let recent = {
+        let mut all = RECENT.lock().unwrap();
+        let recent = all.entry(*machine_id).or_default();
+        if !observe(recent, registration, &config) {
+            return None;
+        }
+        recent.clone()
+    };
+
+    let existing = match crate::db::get_boot_loop(machine_id).await {
+        Ok(existing) => existing,
+        Err(e) => {
+            error!("Failed to look up boot loop state for machine {}: {}", machine_id, e);
+            return None;
+        }
+    };
+    let first = recent.first().map(|r| r.at).unwrap_or_else(Utc::now);
+    let last = recent.last().map(|r| r.at).unwrap_or_else(Utc::now);
+    let (boot_loop, is_new) = match existing {
+        Some(existing) => (BootLoop {
+            registrations: existing.registrations + 1,
+            last_registration_at: last,
+            ..existing
+        }, false),
+        None => (BootLoop {
+            machine_id: *machine_id,
+            registrations: recent.len(),
+            first_registration_at: first,
+            last_registration_at: last,
+            detected_at: Utc::now(),
+            likely_causes: likely_causes(&recent),
+        }, true),
+    };
+    if let Err(e) = crate::db::save_boot_loop(&boot_loop).await {
+        error!("Failed to save boot loop warning for machine {}: {}", machine_id, e);
+        return None;
+    }
+    if is_new {
+        warn!("Machine {} registered {} times in {} minutes without progressing; flagged as a boot loop",
+            machine_id, boot_loop.registrations, (last - first).num_minutes());
+        Some(boot_loop)
+    } else {
+        None
+    }
+}
+
+/// Whether the machine is flagged, so automation should leave it alone.
+pub async fn is_flagged(machine_id: &Uuid) -> bool {
+    match crate::db::get_boot_loop(machine_id).await {
+        Ok(boot_loop) => boot_loop.is_some(),
+        Err(e) => {
+            warn!("Failed to look up boot loop state for machine {}: {}", machine_id, e);
+            false
+        }
+    }
+}
+
+/// Clear the warning and start counting registrations afresh.
+pub async fn clear(machine_id: &Uuid) -> Result<bool> {
+    RECENT.lock().unwrap().remove(machine_id);
+    crate::db::delete_boot_loop(machine_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(minute: i64, previous_status: Option<MachineStatus>) -> Registration {
+        let start = DateTime::parse_from_rfc3339("2026-10-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        Registration { at: start + Duration::minutes(minute), previous_status, os_choice: None, os_installed: None }
+    }
+
+    #[test]
+    fn test_observe() {
+        let config = BootLoopConfig { registrations: 3, window: Duration::minutes(15) };
+        let mut recent = Vec::new();
+        assert!(!observe(&mut recent, registration(0, None), &config));
+        assert!(!observe(&mut recent, registration(5, Some(MachineStatus::AwaitingAssignment)), &config));
+        assert!(observe(&mut recent, registration(10, Some(MachineStatus::AwaitingAssignment)), &config));
+
+        // Spread out beyond the window
+        let mut recent = Vec::new();
+        for minute in [0, 10, 20, 30] {
+            assert!(!observe(&mut recent, registration(minute * 2, Some(MachineStatus::AwaitingAssignment)), &config));
+        }
+
+        // Reaching Ready in between resets the count
+        let mut recent = Vec::new();
+        assert!(!observe(&mut recent, registration(0, None), &config));
+        assert!(!observe(&mut recent, registration(3, Some(MachineStatus::InstallingOS)), &config));
+        assert!(!observe(&mut recent, registration(6, Some(MachineStatus::Ready)), &config));
+        assert!(!observe(&mut recent, registration(9, Some(MachineStatus::AwaitingAssignment)), &config));
+        assert!(observe(&mut recent, registration(12, Some(MachineStatus::AwaitingAssignment)), &config));
+    }
+
+    #[test]
+    fn test_likely_causes() {
+        let installing = vec![
+            Registration { os_choice: Some("ubuntu-2404".to_string()), ..registration(0, Some(MachineStatus::InstallingOS)) },
+            Registration { os_choice: Some("ubuntu-2404".to_string()), ..registration(5, Some(MachineStatus::InstallingOS)) },
+        ];
+        let causes = likely_causes(&installing);
+        assert!(causes[0].contains("workflow never starts"));
+        assert!(!causes.iter().any(|c| c.contains("waiting for an OS")));
+
+        let idle = vec![registration(0, None), registration(5, Some(MachineStatus::AwaitingAssignment))];
+        let causes = likely_causes(&idle);
+        assert!(causes[0].contains("waiting for an OS"));
+        assert!(causes.last().unwrap().contains("DHCP"));
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use dragonfly_common::OsIdentifier;
+use serde::Serialize;
+use uuid::Uuid;
+
+const MENU_ENV_VAR: &str = "DRAGONFLY_IPXE_MENU";
+const TIMEOUT_ENV_VAR: &str = "DRAGONFLY_IPXE_MENU_TIMEOUT";
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Memtest86+ isn't downloaded automatically; upload the EFI binary (or drop it here under the artifact dir) to offer it.
+pub const MEMTEST_ARTIFACT: &str = "memtest/memtest64.efi";
+
+/// What a technician picked from the boot menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuChoice {
+    /// Register with Dragonfly and have this OS assigned
+    Os(OsIdentifier),
+    /// Register with Dragonfly without choosing an OS (also the timeout default)
+    Agent,
+    Memtest,
+    Shell,
+    /// Plain Alpine live environment without the Dragonfly agent
+    Rescue,
+}
+
+impl MenuChoice {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "agent" => Some(MenuChoice::Agent),
+            "memtest" => Some(MenuChoice::Memtest),
+            "shell" => Some(MenuChoice::Shell),
+            "rescue" => Some(MenuChoice::Rescue),
+            os => os.strip_prefix("os-")
+                .and_then(|os| os.parse().ok())
+                .map(MenuChoice::Os),
+        }
+    }
+
+    pub fn as_str(&self) -> String {
+        match self {
+            MenuChoice::Os(os) => format!("os-{}", os),
+            MenuChoice::Agent => "agent".to_string(),
+            MenuChoice::Memtest => "memtest".to_string(),
+            MenuChoice::Shell => "shell".to_string(),
+            MenuChoice::Rescue => "rescue".to_string(),
+        }
+    }
+}
+
+/// A boot menu selection, kept so an OS choice can be applied once the machine registers.
+#[derive(Debug, Clone, Serialize)]
+pub struct BootSelection {
+    pub mac_address: String,
+    pub choice: String,
+    pub selected_at: DateTime<Utc>,
+    /// The machine the selection was applied to, once it registered
+    pub machine_id: Option<Uuid>,
+}
+
+/// Whether unknown machines get the interactive menu instead of booting straight into the agent.
+pub fn enabled() -> bool {
+    crate::config::var(MENU_ENV_VAR).is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+fn timeout_secs() -> u64 {
+    crate::config::var(TIMEOUT_ENV_VAR)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_SECS)
+}
+
+pub fn memtest_available() -> bool {
+    crate::api::artifact_base_dir().join(MEMTEST_ARTIFACT).exists()
+}
+
+/// The iPXE menu for an unknown machine. Each entry chains back to the server so the
+/// selection is recorded before anything boots.
+pub fn render_menu(base_url: &str, mac: &str, memtest: bool) -> String {
+    render_menu_with_timeout(base_url, mac, memtest, timeout_secs())
+}
+
+fn render_menu_with_timeout(base_url: &str, mac: &str, memtest: bool, timeout_secs: u64) -> String {
+    // Every built-in OS is offered
+    let mut choices: Vec<(String, String)> = OsIdentifier::ALL.into_iter()
+        .map(|os| (MenuChoice::Os(os).as_str(), format!("Install {}", os.display_name())))
+        .collect();
+    let os_count = choices.len();
+    choices.push((MenuChoice::Agent.as_str(), "Register with Dragonfly".to_string()));
+    if memtest {
+        choices.push((MenuChoice::Memtest.as_str(), "Memtest86+".to_string()));
+    }
+    choices.push((MenuChoice::Rescue.as_str(), "Rescue environment".to_string()));
+    choices.push((MenuChoice::Shell.as_str(), "iPXE shell".to_string()));
+
+    let mut script = format!("#!ipxe\nmenu Dragonfly boot menu ({})\nitem --gap Operating systems\n", mac);
+    for (i, (key, label)) in choices.iter().enumerate() {
+        if i == os_count {
+            script.push_str("item --gap Utilities\n");
+        }
+        script.push_str(&format!("item {} {}\n", key, label));
+    }
+    script.push_str(&format!(
+        "choose --default agent --timeout {} selected || set selected agent\nchain {}/boot-menu/{}/${{selected}}\n",
+        timeout_secs * 1000, base_url, mac
+    ));
+    script
+}
+
+/// The script to run once a choice has been recorded.
+pub fn render_choice(base_url: &str, choice: &MenuChoice) -> String {
+    match choice {
+        MenuChoice::Os(_) | MenuChoice::Agent => format!("#!ipxe\nchain {}/ipxe/dragonfly-agent.ipxe\n", base_url),
+        MenuChoice::Memtest => format!("#!ipxe\nchain {}/ipxe/{}\n", base_url, MEMTEST_ARTIFACT),
+        MenuChoice::Shell => "#!ipxe\nshell\n".to_string(),
+        MenuChoice::Rescue => alpine_boot_script(base_url, false, &[]),
+    }
+}
+
+/// Boot the Alpine netboot image the agent runs in, optionally with the agent overlay
+/// and extra kernel arguments.
+pub fn alpine_boot_script(base_url: &str, with_agent: bool, extra_args: &[&str]) -> String {
+    let mut args = vec![
+        "ip=dhcp".to_string(),
+        "alpine_repo=http://dl-cdn.alpinelinux.org/alpine/v3.21/main".to_string(),
+        "modules=loop,squashfs,sd-mod,usb-storage".to_string(),
+        "initrd=initramfs-lts".to_string(),
+        format!("modloop={}/ipxe/dragonfly-agent/modloop", base_url),
+    ];
+    if with_agent {
+        args.push(format!("apkovl={}/ipxe/dragonfly-agent/localhost.apkovl.tar.gz", base_url));
+    }
+    args.extend(extra_args.iter().map(|a| a.to_string()));
+    args.push("rw".to_string());
+    format!(
+        "#!ipxe\nkernel {}/ipxe/dragonfly-agent/vmlinuz \\\n  {}\ninitrd {}/ipxe/dragonfly-agent/initramfs-lts\nboot\n",
+        base_url,
+        args.join(" \\\n  "),
+        base_url
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_choices() {
+        assert_eq!(MenuChoice::parse("os-debian-12"), Some(MenuChoice::Os(OsIdentifier::Debian12)));
+        assert_eq!(MenuChoice::parse("shell"), Some(MenuChoice::Shell));
+        assert_eq!(MenuChoice::parse("os-windows"), None);
+        assert_eq!(MenuChoice::parse("debian-12"), None);
+        for os in OsIdentifier::ALL {
+            let choice = MenuChoice::Os(os);
+            assert_eq!(MenuChoice::parse(&choice.as_str()), Some(choice));
+        }
+    }
+
+    #[test]
+    fn test_menu_lists_catalog() {
+        let menu = render_menu_with_timeout("http://10.0.0.1:3000", "aa:bb:cc:dd:ee:ff", false, 30);
+        assert!(menu.starts_with("#!ipxe\n"));
+        for os in OsIdentifier::ALL {
+            assert!(menu.contains(&format!("item os-{} ", os)));
+        }
+        assert!(menu.contains("--timeout 30000"));
+        assert!(menu.contains("chain http://10.0.0.1:3000/boot-menu/aa:bb:cc:dd:ee:ff/${selected}"));
+        assert!(!menu.contains("memtest"));
+    }
+
+    #[test]
+    fn test_rescue_skips_agent_overlay() {
+        let script = render_choice("http://10.0.0.1:3000", &MenuChoice::Rescue);
+        assert!(script.contains("modloop="));
+        assert!(!script.contains("apkovl"));
+    }
+}
@@ -0,0 +1,159 @@
+// Organization branding: who manages the machines and how to get help.
+//
+// Set in /api/settings. The web UI header shows the organization and support contact, and
+// installed systems get a MOTD rendered from the template (or a default naming the
+// organization). The MOTD reaches install templates base64-encoded through the workflow's
+// hardware map, where the bundled templates write it to /etc/motd via cloud-init.
+
+use anyhow::{bail, Result};
+use base64::Engine;
+use dragonfly_common::models::Machine;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_CONTACT_LEN: usize = 200;
+const MAX_MOTD_LEN: usize = 4096;
+
+/// Unset fields leave the UI and installed systems unbranded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Branding {
+    /// e.g. "Acme Research Computing"
+    #[serde(default)]
+    pub organization: Option<String>,
+    /// An email address, phone number or URL
+    #[serde(default)]
+    pub support_contact: Option<String>,
+    /// MOTD for installed systems; {organization}, {support_contact} and {hostname} are filled in
+    #[serde(default)]
+    pub motd_template: Option<String>,
+}
+
+fn single_line(field: &str, value: &str, max_len: usize) -> Result<()> {
+    if value.chars().count() > max_len {
+        bail!("{} must be at most {} characters", field, max_len);
+    }
+    if value.chars().any(char::is_control) {
+        bail!("{} must be a single line", field);
+    }
+    Ok(())
+}
+
+impl Branding {
+    /// Trim fields, dropping empty ones, and check they're fit for a header and a MOTD.
+    pub fn normalize(mut self) -> Result<Self> {
+        let trim = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        self.organization = trim(self.organization);
+        self.support_contact = trim(self.support_contact);
+        self.motd_template = trim(self.motd_template.map(|m| m.replace("\r\n", "\n")));
+
+        if let Some(organization) = &self.organization {
+            single_line("Organization name", organization, MAX_NAME_LEN)?;
+        }
+        if let Some(contact) = &self.support_contact {
+            single_line("Support contact", contact, MAX_CONTACT_LEN)?;
+        }
+        if let Some(motd) = &self.motd_template {
+            if motd.chars().count() > MAX_MOTD_LEN {
+                bail!("MOTD template must be at most {} characters", MAX_MOTD_LEN);
+            }
+            // Escape sequences in a MOTD can mess with the terminal of whoever logs in
+            if motd.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+                bail!("MOTD template must not contain control characters");
+            }
+        }
+        Ok(self)
+    }
+
+    /// The MOTD for a machine, or None when there's nothing to brand it with.
+    pub fn render_motd(&self, machine: &Machine) -> Option<String> {
+        let template = match &self.motd_template {
+            Some(template) => template.clone(),
+            None => {
+                let organization = self.organization.as_ref()?;
+                let mut default = format!("This machine is managed by {}.", organization);
+                if self.support_contact.is_some() {
+                    default.push_str("\nFor help, contact {support_contact}.");
+                }
+                default
+            }
+        };
+        let hostname = machine.hostname.clone()
+            .or_else(|| machine.memorable_name.clone())
+            .unwrap_or_else(|| machine.mac_address.clone());
+        let mut motd = template
+            .replace("{organization}", self.organization.as_deref().unwrap_or_default())
+            .replace("{support_contact}", self.support_contact.as_deref().unwrap_or_default())
+            .replace("{hostname}", &hostname);
+        motd.push('\n');
+        Some(motd)
+    }
+
+    /// Values for the workflow hardware map; empty when unbranded, which templates test for.
+    pub fn hardware_map(&self, machine: &Machine) -> [(&'static str, String); 1] {
+        let motd = self.render_motd(machine)
+            .map(|motd| base64::engine::general_purpose::STANDARD.encode(motd))
+            .unwrap_or_default();
+        [("motd_b64", motd)]
+    }
+}
+
+// The branding last loaded or saved, for page templates
+static CURRENT: Lazy<RwLock<Branding>> = Lazy::new(|| RwLock::new(Branding::default()));
+
+pub fn current() -> Branding {
+    CURRENT.read().map(|b| b.clone()).unwrap_or_default()
+}
+
+pub fn set_current(branding: &Branding) {
+    if let Ok(mut current) = CURRENT.write() {
+        *current = branding.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branding(organization: Option<&str>, support_contact: Option<&str>, motd_template: Option<&str>) -> Branding {
+        Branding {
+            organization: organization.map(str::to_string),
+            support_contact: support_contact.map(str::to_string),
+            motd_template: motd_template.map(str::to_string),
+        }
+    }
+
+    fn machine(hostname: &str) -> Machine {
+        Machine {
+            hostname: Some(hostname.to_string()),
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalized = branding(Some(" Acme "), Some(""), Some("Hi\r\nthere ")).normalize().unwrap();
+        assert_eq!(normalized, branding(Some("Acme"), None, Some("Hi\nthere")));
+        assert!(branding(Some("Acme\nInc"), None, None).normalize().is_err());
+        assert!(branding(None, None, Some("\x1b[2Jgotcha")).normalize().is_err());
+        assert!(branding(None, None, Some("line one\n\tline two")).normalize().is_ok());
+    }
+
+    #[test]
+    fn test_render_motd() {
+        let web01 = machine("web01");
+        assert_eq!(Branding::default().render_motd(&web01), None);
+        assert_eq!(branding(Some("Acme"), None, None).render_motd(&web01).unwrap(), "This machine is managed by Acme.\n");
+        assert_eq!(
+            branding(Some("Acme"), Some("help@acme.example"), None).render_motd(&web01).unwrap(),
+            "This machine is managed by Acme.\nFor help, contact help@acme.example.\n"
+        );
+        assert_eq!(
+            branding(Some("Acme"), Some("x1234"), Some("{hostname} belongs to {organization} ({support_contact})")).render_motd(&web01).unwrap(),
+            "web01 belongs to Acme (x1234)\n"
+        );
+        assert_eq!(branding(None, None, None).hardware_map(&web01)[0], ("motd_b64", String::new()));
+        assert_eq!(branding(Some("Acme"), None, Some("hi")).hardware_map(&web01)[0], ("motd_b64", "aGkK".to_string()));
+    }
+}
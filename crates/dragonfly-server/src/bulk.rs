@@ -0,0 +1,183 @@
+// Bulk machine operations: tag edits, status changes and reinstalls applied to every machine
+// a selector picks out, by ID list or by filter (tags, status, OS). Each endpoint takes
+// dry_run to list the machines it would touch without changing anything.
+
+use anyhow::{bail, Result};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const MAX_TAG_LEN: usize = 64;
+
+/// Picks machines out of the fleet. Filters narrow the ID list when one is given, and the
+/// whole fleet otherwise; a selector without any criteria matches nothing.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MachineSelector {
+    #[serde(default)]
+    pub machine_ids: Vec<Uuid>,
+    /// Machines carrying every one of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Machines carrying none of these tags
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    /// State key as in usage reports, e.g. "ready" or "error"
+    pub status: Option<String>,
+    /// OS choice, e.g. "ubuntu-2404"
+    pub os: Option<String>,
+}
+
+impl MachineSelector {
+    pub fn is_empty(&self) -> bool {
+        self.machine_ids.is_empty()
+            && self.tags.is_empty()
+            && self.exclude_tags.is_empty()
+            && self.status.is_none()
+            && self.os.is_none()
+    }
+
+    /// Whether a machine (with its tags) passes the filters; the ID list is applied separately.
+    pub fn matches(&self, machine: &Machine, machine_tags: &[String]) -> bool {
+        let has_tag = |tag: &String| machine_tags.iter().any(|t| t.eq_ignore_ascii_case(tag.trim()));
+        self.tags.iter().all(has_tag)
+            && !self.exclude_tags.iter().any(has_tag)
+            && filter_matches(self.status.as_deref(), crate::usage::state_key(&machine.status))
+            && filter_matches(self.os.as_deref(), machine.os_choice.as_deref().unwrap_or_default())
+    }
+}
+
+// An unset filter matches anything
+fn filter_matches(wanted: Option<&str>, value: &str) -> bool {
+    match wanted {
+        Some(wanted) => wanted.trim().eq_ignore_ascii_case(value),
+        None => true,
+    }
+}
+
+/// Tags to add to and remove from every selected machine.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TagChange {
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+impl TagChange {
+    /// Trim tag names and check there's something sensible to do.
+    pub fn normalize(mut self) -> Result<Self> {
+        let clean = |tags: Vec<String>| tags.into_iter()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>();
+        self.add = clean(self.add);
+        self.remove = clean(self.remove);
+        if self.add.is_empty() && self.remove.is_empty() {
+            bail!("Give tags to add or remove");
+        }
+        for tag in &self.add {
+            if tag.chars().count() > MAX_TAG_LEN || tag.chars().any(|c| c.is_control() || c == '/') {
+                bail!("Invalid tag name '{}'", tag);
+            }
+        }
+        if let Some(tag) = self.add.iter().find(|t| self.remove.iter().any(|r| r.eq_ignore_ascii_case(t))) {
+            bail!("Tag '{}' is both added and removed", tag);
+        }
+        Ok(self)
+    }
+
+    /// A machine's tags after the change, sorted; None when the change leaves them as they are.
+    pub fn apply(&self, current: &[String]) -> Option<Vec<String>> {
+        let mut tags: Vec<String> = current.iter()
+            .filter(|t| !self.remove.iter().any(|r| r.eq_ignore_ascii_case(t)))
+            .cloned()
+            .collect();
+        for tag in &self.add {
+            if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                tags.push(tag.clone());
+            }
+        }
+        tags.sort();
+        let mut before = current.to_vec();
+        before.sort();
+        (tags != before).then_some(tags)
+    }
+}
+
+/// Statuses that can be set in bulk, by state key. Installing is started with a reinstall
+/// instead, and the remaining states are reported by machines rather than set.
+pub fn settable_status(key: &str) -> Option<MachineStatus> {
+    match key {
+        "ready" => Some(MachineStatus::Ready),
+        "awaiting_assignment" => Some(MachineStatus::AwaitingAssignment),
+        "offline" => Some(MachineStatus::Offline),
+        "error" => Some(MachineStatus::Error("Manual error state".to_string())),
+        _ => None,
+    }
+}
+
+/// Machines a selector picks from the fleet, given each machine's tags.
+pub fn select<'a>(selector: &MachineSelector, machines: &'a [(Machine, Vec<String>)]) -> Result<Vec<&'a (Machine, Vec<String>)>> {
+    if selector.is_empty() {
+        bail!("Select machines with machine_ids or a filter (tags, exclude_tags, status, os)");
+    }
+    if let Some(id) = selector.machine_ids.iter().find(|id| !machines.iter().any(|(m, _)| m.id == **id)) {
+        bail!("Machine with ID {} not found", id);
+    }
+    Ok(machines.iter()
+        .filter(|(machine, _)| selector.machine_ids.is_empty() || selector.machine_ids.contains(&machine.id))
+        .filter(|(machine, tags)| selector.matches(machine, tags))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(status: MachineStatus, os: Option<&str>) -> Machine {
+        Machine {
+            os_choice: os.map(str::to_string),
+            status,
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let fleet = vec![
+            (machine(MachineStatus::Ready, Some("ubuntu-2404")), tags(&["rack-a", "gpu"])),
+            (machine(MachineStatus::Ready, Some("ubuntu-2204")), tags(&["rack-a"])),
+            (machine(MachineStatus::Error("boom".into()), Some("ubuntu-2404")), tags(&["rack-b"])),
+        ];
+        let ids = |selected: Vec<&(Machine, Vec<String>)>| selected.iter().map(|(m, _)| m.id).collect::<Vec<_>>();
+
+        let rack_a = MachineSelector { tags: tags(&["Rack-A"]), exclude_tags: tags(&["gpu"]), ..Default::default() };
+        assert_eq!(ids(select(&rack_a, &fleet).unwrap()), vec![fleet[1].0.id]);
+
+        let errored = MachineSelector { status: Some("error".into()), os: Some("ubuntu-2404".into()), ..Default::default() };
+        assert_eq!(ids(select(&errored, &fleet).unwrap()), vec![fleet[2].0.id]);
+
+        let by_id = MachineSelector { machine_ids: vec![fleet[0].0.id, fleet[2].0.id], status: Some("ready".into()), ..Default::default() };
+        assert_eq!(ids(select(&by_id, &fleet).unwrap()), vec![fleet[0].0.id]);
+
+        assert!(select(&MachineSelector::default(), &fleet).is_err());
+        let unknown = MachineSelector { machine_ids: vec![Uuid::new_v4()], ..Default::default() };
+        assert!(select(&unknown, &fleet).is_err());
+    }
+
+    #[test]
+    fn test_tag_change() {
+        let change = TagChange { add: tags(&[" db ", "rack-a"]), remove: tags(&["old"]) }.normalize().unwrap();
+        assert_eq!(change.add, tags(&["db", "rack-a"]));
+        assert_eq!(change.apply(&tags(&["old", "rack-a"])), Some(tags(&["db", "rack-a"])));
+        assert_eq!(change.apply(&tags(&["db", "rack-a"])), None);
+
+        assert!(TagChange::default().normalize().is_err());
+        assert!(TagChange { add: tags(&["x"]), remove: tags(&["X"]) }.normalize().is_err());
+        assert!(TagChange { add: tags(&["a/b"]), remove: Vec::new() }.normalize().is_err());
+    }
+}
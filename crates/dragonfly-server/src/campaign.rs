@@ -0,0 +1,738 @@
+// Provisioning campaigns: a named batch of machines being installed together, e.g. a rack
+// brought up during an install window. Members are followed as their installs start and
+// finish; when every one is done, or the deadline passes, a summary report (outcome counts,
+// install durations, failures with links) is stored and sent to the campaign's notification
+// channels.
+//
+// A campaign can also install its machines as a rollout: a canary wave first, then, once the
+// canaries have installed and phoned home, the rest. When more canaries fail than the rollout
+// allows, it halts and the remaining machines are left alone.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+use crate::AppState;
+
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+const MAX_NAME_LEN: usize = 100;
+const DEFAULT_CANARY_SIZE: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberState {
+    /// No install seen yet
+    Pending,
+    Installing,
+    Succeeded,
+    Failed,
+    /// Still pending or installing when the campaign ended
+    Incomplete,
+    /// Never installed because the rollout halted
+    Skipped,
+}
+
+impl MemberState {
+    pub fn is_final(&self) -> bool {
+        matches!(self, MemberState::Succeeded | MemberState::Failed | MemberState::Incomplete | MemberState::Skipped)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CampaignMember {
+    pub machine_id: Uuid,
+    pub state: MemberState,
+    pub added_at: DateTime<Utc>,
+    #[serde(default)]
+    pub install_started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub duration_secs: Option<i64>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl CampaignMember {
+    pub fn new(machine_id: Uuid, now: DateTime<Utc>) -> Self {
+        CampaignMember {
+            machine_id,
+            state: MemberState::Pending,
+            added_at: now,
+            install_started_at: None,
+            finished_at: None,
+            duration_secs: None,
+            error: None,
+        }
+    }
+
+    /// Move the member along from its machine's current state. Returns whether anything changed.
+    pub fn observe(&mut self, machine: Option<&Machine>, now: DateTime<Utc>) -> bool {
+        if self.state.is_final() {
+            return false;
+        }
+        let Some(machine) = machine else {
+            return self.finish(MemberState::Failed, Some("Machine was deleted".to_string()), now);
+        };
+        // A machine that went from pending straight to done between checks changed after it joined
+        let changed_since_added = machine.updated_at > self.added_at;
+        match &machine.status {
+            MachineStatus::InstallingOS if self.state == MemberState::Pending => {
+                self.state = MemberState::Installing;
+                self.install_started_at = Some(now);
+                true
+            }
+            MachineStatus::Ready | MachineStatus::ExistingOS
+                if self.state == MemberState::Installing
+                    || (changed_since_added && machine.last_deployment_duration.is_some()) =>
+            {
+                self.duration_secs = machine.last_deployment_duration
+                    .or_else(|| self.install_started_at.map(|started| (now - started).num_seconds()));
+                self.finish(MemberState::Succeeded, None, now)
+            }
+            MachineStatus::Error(message) if self.state == MemberState::Installing || changed_since_added => {
+                self.finish(MemberState::Failed, Some(message.clone()), now)
+            }
+            _ => false,
+        }
+    }
+
+    fn finish(&mut self, state: MemberState, error: Option<String>, now: DateTime<Utc>) -> bool {
+        self.state = state;
+        self.error = error;
+        self.finished_at = Some(now);
+        true
+    }
+}
+
+/// How a rollout installs a campaign's machines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RolloutStrategy {
+    /// OS to assign before installing; each machine keeps its own choice when unset
+    #[serde(default)]
+    pub os_choice: Option<String>,
+    /// Machines in the canary wave (default 1)
+    #[serde(default)]
+    pub canary_size: Option<usize>,
+    /// Canary wave as a share of the machines, rounded up; instead of canary_size
+    #[serde(default)]
+    pub canary_percent: Option<u8>,
+    /// Canary failures tolerated; one more halts the rollout
+    #[serde(default)]
+    pub max_failures: usize,
+}
+
+impl RolloutStrategy {
+    pub fn validate(&self) -> Result<()> {
+        if self.canary_size.is_some() && self.canary_percent.is_some() {
+            bail!("Give canary_size or canary_percent, not both");
+        }
+        if self.canary_size == Some(0) {
+            bail!("canary_size must be at least 1");
+        }
+        if self.canary_percent.is_some_and(|percent| percent == 0 || percent > 100) {
+            bail!("canary_percent must be 1-100");
+        }
+        if let Some(os_choice) = &self.os_choice {
+            dragonfly_common::os::check_os_choice(os_choice)?;
+        }
+        Ok(())
+    }
+
+    // Size of the canary wave out of `total` machines
+    fn canary_count(&self, total: usize) -> usize {
+        let wanted = match self.canary_percent {
+            Some(percent) => (total * percent as usize).div_ceil(100),
+            None => self.canary_size.unwrap_or(DEFAULT_CANARY_SIZE),
+        };
+        wanted.clamp(1, total.max(1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutPhase {
+    /// Installing the canary wave
+    Canary,
+    /// The canaries passed and the remaining machines are installing
+    Continuing,
+    /// Too many canaries failed; machines not yet started were skipped
+    Halted,
+    Completed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rollout {
+    pub strategy: RolloutStrategy,
+    pub phase: RolloutPhase,
+    pub canary: Vec<Uuid>,
+    /// Machines the rollout has started installs on
+    #[serde(default)]
+    pub started: Vec<Uuid>,
+    pub started_at: DateTime<Utc>,
+    pub phase_changed_at: DateTime<Utc>,
+    #[serde(default)]
+    pub halt_reason: Option<String>,
+}
+
+impl Rollout {
+    fn set_phase(&mut self, phase: RolloutPhase, now: DateTime<Utc>) {
+        self.phase = phase;
+        self.phase_changed_at = now;
+    }
+
+    fn halt(&mut self, reason: String, members: &mut [CampaignMember], now: DateTime<Utc>) {
+        self.set_phase(RolloutPhase::Halted, now);
+        self.halt_reason = Some(reason);
+        self.skip_unstarted(members, now);
+    }
+
+    // Members the rollout never reached won't be installed by it
+    fn skip_unstarted(&self, members: &mut [CampaignMember], now: DateTime<Utc>) {
+        for member in members.iter_mut() {
+            if member.state == MemberState::Pending && !self.started.contains(&member.machine_id) {
+                member.finish(MemberState::Skipped, None, now);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Campaign {
+    pub id: Uuid,
+    pub name: String,
+    /// Notification channels the report goes to; every enabled channel when empty
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+    pub started_at: DateTime<Utc>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub members: Vec<CampaignMember>,
+    #[serde(default)]
+    pub report: Option<CampaignReport>,
+    #[serde(default)]
+    pub rollout: Option<Rollout>,
+}
+
+impl Campaign {
+    pub fn new(name: &str, recipients: Vec<String>, deadline: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Result<Self> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            bail!("Campaign name must be 1-{} characters", MAX_NAME_LEN);
+        }
+        if deadline.is_some_and(|deadline| deadline <= now) {
+            bail!("Deadline must be in the future");
+        }
+        Ok(Campaign {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            recipients: recipients.into_iter().map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).collect(),
+            deadline,
+            started_at: now,
+            finished_at: None,
+            members: Vec::new(),
+            report: None,
+            rollout: None,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.finished_at.is_none()
+    }
+
+    /// Add machines not already in the campaign; returns how many were added.
+    pub fn add_machines(&mut self, ids: impl IntoIterator<Item = Uuid>, now: DateTime<Utc>) -> usize {
+        let mut added = 0;
+        for id in ids {
+            if !self.members.iter().any(|m| m.machine_id == id) {
+                self.members.push(CampaignMember::new(id, now));
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Whether the campaign should end now: every member is done, or the deadline has passed.
+    pub fn due(&self, now: DateTime<Utc>) -> bool {
+        self.is_active()
+            && ((!self.members.is_empty() && self.members.iter().all(|m| m.state.is_final()))
+                || self.deadline.is_some_and(|deadline| now >= deadline))
+    }
+
+    /// Move members along from their machines' states. Members a rollout hasn't started yet
+    /// wait, so a machine already in service isn't taken for one the rollout installed.
+    pub fn observe(&mut self, machines: &HashMap<Uuid, Machine>, now: DateTime<Utc>) -> bool {
+        let mut changed = false;
+        for member in &mut self.members {
+            if self.rollout.as_ref().is_some_and(|rollout| !rollout.started.contains(&member.machine_id)) {
+                continue;
+            }
+            changed |= member.observe(machines.get(&member.machine_id), now);
+        }
+        changed
+    }
+
+    /// Whether a rollout with this strategy can start now.
+    pub fn check_rollout(&self, strategy: &RolloutStrategy) -> Result<()> {
+        strategy.validate()?;
+        if !self.is_active() {
+            bail!("Campaign has already finished");
+        }
+        if self.rollout.is_some() {
+            bail!("Campaign already has a rollout");
+        }
+        if !self.members.iter().any(|m| m.state == MemberState::Pending) {
+            bail!("No machines in the campaign are waiting to install");
+        }
+        Ok(())
+    }
+
+    /// Begin a rollout over the members that haven't installed yet. Returns the canary wave for
+    /// the caller to start installing.
+    pub fn start_rollout(&mut self, strategy: RolloutStrategy, now: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        self.check_rollout(&strategy)?;
+        let pending: Vec<Uuid> = self.members.iter()
+            .filter(|m| m.state == MemberState::Pending)
+            .map(|m| m.machine_id)
+            .collect();
+        let canary = pending[..strategy.canary_count(pending.len())].to_vec();
+        self.rollout = Some(Rollout {
+            strategy,
+            phase: RolloutPhase::Canary,
+            canary: canary.clone(),
+            started: canary.clone(),
+            started_at: now,
+            phase_changed_at: now,
+            halt_reason: None,
+        });
+        Ok(canary)
+    }
+
+    /// Move the rollout on after the members have been observed: halt when too many canaries
+    /// failed, or continue once they're all done. Returns machines to start installing now.
+    pub fn advance_rollout(&mut self, now: DateTime<Utc>) -> Vec<Uuid> {
+        let Some(rollout) = self.rollout.as_mut() else {
+            return Vec::new();
+        };
+        let state_of = |members: &[CampaignMember], id: &Uuid| members.iter().find(|m| m.machine_id == *id).map(|m| m.state);
+        match rollout.phase {
+            RolloutPhase::Canary => {
+                let failed = rollout.canary.iter()
+                    .filter(|id| state_of(&self.members, id) == Some(MemberState::Failed))
+                    .count();
+                if failed > rollout.strategy.max_failures {
+                    let reason = format!("{} of {} canary install(s) failed", failed, rollout.canary.len());
+                    rollout.halt(reason, &mut self.members, now);
+                    return Vec::new();
+                }
+                if !rollout.canary.iter().all(|id| state_of(&self.members, id).is_none_or(|state| state.is_final())) {
+                    return Vec::new();
+                }
+                rollout.set_phase(RolloutPhase::Continuing, now);
+            }
+            RolloutPhase::Continuing => {}
+            RolloutPhase::Halted => {
+                // Machines added to the campaign after it halted
+                rollout.skip_unstarted(&mut self.members, now);
+                return Vec::new();
+            }
+            RolloutPhase::Completed => return Vec::new(),
+        }
+
+        // Everything not started yet, including machines added since the rollout began
+        let next: Vec<Uuid> = self.members.iter()
+            .filter(|m| m.state == MemberState::Pending && !rollout.started.contains(&m.machine_id))
+            .map(|m| m.machine_id)
+            .collect();
+        rollout.started.extend(next.iter().copied());
+        if next.is_empty() && self.members.iter().all(|m| m.state.is_final()) {
+            rollout.set_phase(RolloutPhase::Completed, now);
+        }
+        next
+    }
+
+    /// A machine whose install the rollout couldn't start has failed.
+    pub fn launch_failed(&mut self, machine_id: &Uuid, error: String, now: DateTime<Utc>) {
+        if let Some(member) = self.members.iter_mut().find(|m| m.machine_id == *machine_id && !m.state.is_final()) {
+            member.finish(MemberState::Failed, Some(error), now);
+        }
+    }
+
+    /// End the campaign: members still underway are marked incomplete and the report is built.
+    pub fn finish(&mut self, names: &HashMap<Uuid, String>, now: DateTime<Utc>) -> &CampaignReport {
+        if let Some(rollout) = self.rollout.as_mut() {
+            match rollout.phase {
+                RolloutPhase::Canary => {
+                    rollout.halt("Campaign ended before the canary wave finished".to_string(), &mut self.members, now);
+                }
+                RolloutPhase::Continuing => rollout.set_phase(RolloutPhase::Completed, now),
+                RolloutPhase::Halted | RolloutPhase::Completed => {}
+            }
+        }
+        for member in self.members.iter_mut().filter(|m| !m.state.is_final()) {
+            member.state = MemberState::Incomplete;
+            member.finished_at = Some(now);
+        }
+        self.finished_at = Some(now);
+        self.report.insert(CampaignReport::build(self, names, now))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedMachine {
+    pub machine_id: Uuid,
+    pub name: String,
+    pub state: MemberState,
+    pub error: Option<String>,
+    /// Machine page in the web UI
+    pub link: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CampaignReport {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub incomplete: usize,
+    /// Left alone because the rollout halted
+    #[serde(default)]
+    pub skipped: usize,
+    /// Why the rollout halted, when it did
+    #[serde(default)]
+    pub rollout_halted: Option<String>,
+    /// Install durations of the machines that succeeded, in seconds
+    pub shortest_install_secs: Option<i64>,
+    pub average_install_secs: Option<i64>,
+    pub longest_install_secs: Option<i64>,
+    /// From the start of the campaign to the end
+    pub elapsed_secs: i64,
+    pub deadline_reached: bool,
+    /// Failed and incomplete machines
+    pub failures: Vec<FailedMachine>,
+    pub generated_at: DateTime<Utc>,
+}
+
+// Link to a machine's page, absolute when DRAGONFLY_BASE_URL is set
+fn machine_link(id: &Uuid) -> String {
+    let path = crate::base_path::url(&format!("/machines/{}", id));
+    match crate::config::base_url() {
+        Some(base) => format!("{}{}", base.trim_end_matches('/'), path),
+        None => path,
+    }
+}
+
+fn format_duration(secs: i64) -> String {
+    match secs {
+        s if s >= 3600 => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+        s if s >= 60 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+impl CampaignReport {
+    fn build(campaign: &Campaign, names: &HashMap<Uuid, String>, now: DateTime<Utc>) -> Self {
+        let count = |state: MemberState| campaign.members.iter().filter(|m| m.state == state).count();
+        let durations: Vec<i64> = campaign.members.iter()
+            .filter(|m| m.state == MemberState::Succeeded)
+            .filter_map(|m| m.duration_secs)
+            .collect();
+        let failures = campaign.members.iter()
+            .filter(|m| matches!(m.state, MemberState::Failed | MemberState::Incomplete))
+            .map(|m| FailedMachine {
+                machine_id: m.machine_id,
+                name: names.get(&m.machine_id).cloned().unwrap_or_else(|| m.machine_id.to_string()),
+                state: m.state,
+                error: m.error.clone(),
+                link: machine_link(&m.machine_id),
+            })
+            .collect();
+        CampaignReport {
+            total: campaign.members.len(),
+            succeeded: count(MemberState::Succeeded),
+            failed: count(MemberState::Failed),
+            incomplete: count(MemberState::Incomplete),
+            skipped: count(MemberState::Skipped),
+            rollout_halted: campaign.rollout.as_ref().and_then(|rollout| rollout.halt_reason.clone()),
+            shortest_install_secs: durations.iter().min().copied(),
+            average_install_secs: (!durations.is_empty()).then(|| durations.iter().sum::<i64>() / durations.len() as i64),
+            longest_install_secs: durations.iter().max().copied(),
+            elapsed_secs: (now - campaign.started_at).num_seconds(),
+            deadline_reached: campaign.deadline.is_some_and(|deadline| now >= deadline),
+            failures,
+            generated_at: now,
+        }
+    }
+
+    /// Plain-text summary for chat and email.
+    pub fn to_text(&self, campaign_name: &str) -> String {
+        let mut lines = vec![
+            format!("Provisioning campaign \"{}\" {}", campaign_name, if self.deadline_reached { "reached its deadline" } else { "finished" }),
+            format!(
+                "{} machine(s): {} succeeded, {} failed, {} incomplete, in {}",
+                self.total, self.succeeded, self.failed, self.incomplete, format_duration(self.elapsed_secs)
+            ),
+        ];
+        if let Some(reason) = &self.rollout_halted {
+            lines.push(format!("Rollout halted: {}; {} machine(s) were not installed", reason, self.skipped));
+        }
+        if let (Some(shortest), Some(average), Some(longest)) = (self.shortest_install_secs, self.average_install_secs, self.longest_install_secs) {
+            lines.push(format!(
+                "Install time: {} average ({} shortest, {} longest)",
+                format_duration(average), format_duration(shortest), format_duration(longest)
+            ));
+        }
+        for failure in &self.failures {
+            let reason = match (&failure.state, &failure.error) {
+                (_, Some(error)) => error.clone(),
+                (MemberState::Incomplete, None) => "did not finish".to_string(),
+                _ => "failed".to_string(),
+            };
+            lines.push(format!("• {}: {} ({})", failure.name, reason, failure.link));
+        }
+        lines.join("\n")
+    }
+}
+
+fn machine_name(machine: &Machine) -> String {
+    machine.hostname.clone()
+        .or_else(|| machine.memorable_name.clone())
+        .unwrap_or_else(|| machine.mac_address.clone())
+}
+
+// Finish a campaign, store its report and send it to the campaign's channels
+pub async fn finish(campaign: &mut Campaign, event_manager: &EventManager) -> Result<()> {
+    let names: HashMap<Uuid, String> = crate::db::get_all_machines().await?
+        .iter()
+        .map(|m| (m.id, machine_name(m)))
+        .collect();
+    let name = campaign.name.clone();
+    let text = campaign.finish(&names, Utc::now()).to_text(&name);
+    crate::db::save_campaign(campaign).await?;
+    info!("Provisioning campaign '{}' finished", campaign.name);
+    let _ = event_manager.send(format!("campaign_completed:{}", campaign.id));
+
+    let body = serde_json::json!({
+        "campaign_id": campaign.id,
+        "campaign": campaign.name,
+        "report": campaign.report,
+    });
+    match crate::notifications::send_direct(&campaign.recipients, &text, body).await {
+        Ok(0) => warn!("Report for campaign '{}' went to no channels", campaign.name),
+        Ok(sent) => info!("Sent report for campaign '{}' to {} channel(s)", campaign.name, sent),
+        Err(e) => warn!("Failed to send report for campaign '{}': {}", campaign.name, e),
+    }
+    Ok(())
+}
+
+// Start installs on the machines a rollout has reached; a machine whose install can't start fails
+async fn launch(state: &AppState, campaign: &mut Campaign, machine_ids: Vec<Uuid>) {
+    let os_choice = campaign.rollout.as_ref().and_then(|rollout| rollout.strategy.os_choice.clone());
+    for id in machine_ids {
+        if let Err(e) = crate::api::start_campaign_install(state, &id, os_choice.as_deref()).await {
+            warn!("Rollout for campaign '{}' could not start an install on machine {}: {}", campaign.name, id, e);
+            campaign.launch_failed(&id, e, Utc::now());
+        }
+    }
+}
+
+/// Start a rollout over a campaign's waiting machines, installing the canary wave now. The
+/// campaign task continues it.
+pub async fn begin_rollout(state: &AppState, campaign: &mut Campaign, strategy: RolloutStrategy) -> Result<()> {
+    let canary = campaign.start_rollout(strategy, Utc::now())?;
+    info!("Starting rollout for campaign '{}' with {} canary machine(s)", campaign.name, canary.len());
+    launch(state, campaign, canary).await;
+    crate::db::save_campaign(campaign).await?;
+    let _ = state.event_manager.send(format!("campaign_updated:{}", campaign.id));
+    Ok(())
+}
+
+// Advance every active campaign's members and rollout, and finish the ones that are due
+async fn check_campaigns(state: &AppState) -> Result<()> {
+    let mut campaigns = crate::db::list_campaigns().await?;
+    campaigns.retain(Campaign::is_active);
+    if campaigns.is_empty() {
+        return Ok(());
+    }
+    let machines: HashMap<Uuid, Machine> = crate::db::get_all_machines().await?
+        .into_iter()
+        .map(|m| (m.id, m))
+        .collect();
+    let now = Utc::now();
+    for mut campaign in campaigns {
+        let mut changed = campaign.observe(&machines, now);
+        let rollout_before = campaign.rollout.clone();
+        let next = campaign.advance_rollout(now);
+        if let Some(rollout) = &campaign.rollout {
+            if Some(rollout.phase) != rollout_before.as_ref().map(|r| r.phase) {
+                match rollout.phase {
+                    RolloutPhase::Halted => warn!(
+                        "Rollout for campaign '{}' halted: {}",
+                        campaign.name, rollout.halt_reason.as_deref().unwrap_or_default()
+                    ),
+                    phase => info!("Rollout for campaign '{}' is now {:?}", campaign.name, phase),
+                }
+            }
+        }
+        if !next.is_empty() {
+            info!("Rollout for campaign '{}' continuing with {} machine(s)", campaign.name, next.len());
+            launch(state, &mut campaign, next).await;
+        }
+        if campaign.rollout != rollout_before {
+            changed = true;
+            let _ = state.event_manager.send(format!("campaign_updated:{}", campaign.id));
+        }
+
+        if campaign.due(now) {
+            finish(&mut campaign, &state.event_manager).await?;
+        } else if changed {
+            crate::db::save_campaign(&campaign).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Follow active campaigns, carry their rollouts forward and send their reports when they finish.
+pub async fn start_campaign_task(state: Arc<AppState>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        loop {
+            if crate::leader::is_leader() {
+                if let Err(e) = check_campaigns(&state).await {
+                    error!("Failed to check provisioning campaigns: {:#}", e);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping campaign task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn machine(status: MachineStatus, updated_at: DateTime<Utc>, duration: Option<i64>) -> Machine {
+        Machine {
+            hostname: Some("web01".to_string()),
+            os_choice: Some("ubuntu-2404".to_string()),
+            status,
+            created_at: updated_at,
+            updated_at,
+            last_deployment_duration: duration,
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    #[test]
+    fn test_member_progress() {
+        let start = Utc::now();
+        let mut member = CampaignMember::new(Uuid::new_v4(), start);
+
+        // Already Ready from before the campaign: not a result of it
+        assert!(!member.observe(Some(&machine(MachineStatus::Ready, start - Duration::hours(1), Some(300))), start));
+        assert!(member.observe(Some(&machine(MachineStatus::InstallingOS, start, None)), start + Duration::minutes(1)));
+        assert_eq!(member.state, MemberState::Installing);
+        assert!(member.observe(Some(&machine(MachineStatus::Ready, start + Duration::minutes(9), Some(480))), start + Duration::minutes(10)));
+        assert_eq!((member.state, member.duration_secs), (MemberState::Succeeded, Some(480)));
+        assert!(!member.observe(None, start + Duration::minutes(11)));
+
+        let mut failed = CampaignMember::new(Uuid::new_v4(), start);
+        assert!(failed.observe(Some(&machine(MachineStatus::Error("disk not found".into()), start + Duration::minutes(2), None)), start + Duration::minutes(2)));
+        assert_eq!((failed.state, failed.error.as_deref()), (MemberState::Failed, Some("disk not found")));
+    }
+
+    #[test]
+    fn test_finish_and_report() {
+        let start = Utc::now();
+        let mut campaign = Campaign::new("Rack A", vec![" ops ".into(), "".into()], Some(start + Duration::hours(2)), start).unwrap();
+        assert_eq!(campaign.recipients, vec!["ops".to_string()]);
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        assert_eq!(campaign.add_machines(ids.clone(), start), 3);
+        assert_eq!(campaign.add_machines([ids[0]], start), 0);
+
+        campaign.members[0].finish(MemberState::Succeeded, None, start);
+        campaign.members[0].duration_secs = Some(600);
+        campaign.members[1].finish(MemberState::Failed, Some("workflow timed out".into()), start);
+        assert!(!campaign.due(start + Duration::hours(1)));
+        assert!(campaign.due(start + Duration::hours(2)));
+
+        let names = HashMap::from([(ids[1], "web02".to_string())]);
+        let report = campaign.finish(&names, start + Duration::hours(2)).clone();
+        assert_eq!((report.total, report.succeeded, report.failed, report.incomplete), (3, 1, 1, 1));
+        assert_eq!(report.average_install_secs, Some(600));
+        assert!(report.deadline_reached);
+        assert_eq!(report.failures[0].name, "web02");
+        assert!(report.failures[0].link.ends_with(&format!("/machines/{}", ids[1])));
+
+        let text = report.to_text(&campaign.name);
+        assert!(text.contains("3 machine(s): 1 succeeded, 1 failed, 1 incomplete, in 2h 0m"));
+        assert!(text.contains("• web02: workflow timed out"));
+        assert!(!campaign.is_active());
+
+        assert!(Campaign::new(" ", Vec::new(), None, start).is_err());
+        assert!(Campaign::new("Late", Vec::new(), Some(start - Duration::minutes(1)), start).is_err());
+    }
+
+    #[test]
+    fn test_rollout_waves() {
+        let start = Utc::now();
+        let strategy = RolloutStrategy { os_choice: Some("ubuntu-2404".into()), canary_size: None, canary_percent: Some(25), max_failures: 0 };
+        let mut campaign = Campaign::new("Rack B", Vec::new(), None, start).unwrap();
+        assert!(campaign.check_rollout(&strategy).is_err());
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        campaign.add_machines(ids.clone(), start);
+
+        // 25% of 5 rounds up to two canaries; nothing else starts until they're done
+        assert_eq!(campaign.start_rollout(strategy.clone(), start).unwrap(), ids[..2].to_vec());
+        assert!(campaign.check_rollout(&strategy).is_err());
+        campaign.members[0].finish(MemberState::Succeeded, None, start);
+        assert!(campaign.advance_rollout(start).is_empty());
+
+        // A machine already in service isn't mistaken for one the rollout installed
+        let in_service: HashMap<Uuid, Machine> = ids.iter()
+            .map(|id| (*id, Machine { id: *id, ..machine(MachineStatus::Ready, start + Duration::minutes(1), Some(300)) }))
+            .collect();
+        campaign.members[1].state = MemberState::Installing;
+        assert!(campaign.observe(&in_service, start + Duration::minutes(2)));
+        assert_eq!(campaign.members[4].state, MemberState::Pending);
+
+        assert_eq!(campaign.advance_rollout(start), ids[2..].to_vec());
+        assert_eq!(campaign.rollout.as_ref().unwrap().phase, RolloutPhase::Continuing);
+        for member in &mut campaign.members[2..] {
+            member.finish(MemberState::Succeeded, None, start);
+        }
+        assert!(campaign.advance_rollout(start).is_empty());
+        assert_eq!(campaign.rollout.as_ref().unwrap().phase, RolloutPhase::Completed);
+
+        // Failures past the threshold halt the rollout and skip the machines it hadn't reached
+        let mut halted = Campaign::new("Rack C", Vec::new(), None, start).unwrap();
+        halted.add_machines((0..4).map(|_| Uuid::new_v4()), start);
+        let canary = halted.start_rollout(RolloutStrategy { canary_size: Some(2), canary_percent: None, ..strategy.clone() }, start).unwrap();
+        halted.launch_failed(&canary[0], "No OS choice set".into(), start);
+        assert!(halted.advance_rollout(start).is_empty());
+        let rollout = halted.rollout.clone().unwrap();
+        assert_eq!((rollout.phase, rollout.halt_reason.as_deref()), (RolloutPhase::Halted, Some("1 of 2 canary install(s) failed")));
+        assert_eq!(halted.members.iter().filter(|m| m.state == MemberState::Skipped).count(), 2);
+
+        let report = halted.finish(&HashMap::new(), start).clone();
+        assert_eq!((report.failed, report.incomplete, report.skipped), (1, 1, 2));
+        assert!(report.to_text("Rack C").contains("Rollout halted: 1 of 2 canary install(s) failed; 2 machine(s) were not installed"));
+
+        let bad = RolloutStrategy { canary_size: Some(1), ..strategy };
+        assert!(bad.validate().is_err());
+    }
+}
@@ -0,0 +1,264 @@
+// Developer-only fault injection, for exercising retry and error handling on demand.
+//
+// Enabled with DRAGONFLY_CHAOS=1. Faults are drawn from seeded generators (one per
+// fault kind, DRAGONFLY_CHAOS_SEED), so a given seed and request sequence fails the
+// same way every run.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::Value;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::tinkerbell_client::{ResourceKind, TinkerbellClient};
+
+const ENABLE_ENV_VAR: &str = "DRAGONFLY_CHAOS";
+
+/// The places faults can be injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// An artifact download chunk errors, aborting the transfer mid-stream
+    ArtifactChunk,
+    /// A Tinkerbell API call fails after its delay
+    TinkerbellCall,
+    /// An SSE connection is closed instead of delivering the next event
+    SseEvent,
+}
+
+impl Fault {
+    const ALL: [Fault; 3] = [Fault::ArtifactChunk, Fault::TinkerbellCall, Fault::SseEvent];
+
+    fn index(self) -> usize {
+        match self {
+            Fault::ArtifactChunk => 0,
+            Fault::TinkerbellCall => 1,
+            Fault::SseEvent => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    pub chunk_failure_rate: f64,
+    /// Upper bound of the random delay added to each Tinkerbell call
+    pub tinkerbell_max_delay: Duration,
+    pub tinkerbell_failure_rate: f64,
+    pub sse_drop_rate: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            chunk_failure_rate: 0.05,
+            tinkerbell_max_delay: Duration::from_millis(2000),
+            tinkerbell_failure_rate: 0.1,
+            sse_drop_rate: 0.02,
+        }
+    }
+}
+
+fn env_rate(name: &str, default: f64) -> f64 {
+    crate::config::var(name)
+        .and_then(|v| v.parse::<f64>().ok())
+        .map(|rate| rate.clamp(0.0, 1.0))
+        .unwrap_or(default)
+}
+
+impl ChaosConfig {
+    /// None unless DRAGONFLY_CHAOS is set to a truthy value.
+    pub fn from_env() -> Option<Self> {
+        let enabled = crate::config::var(ENABLE_ENV_VAR)
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        let defaults = Self::default();
+        Some(Self {
+            seed: crate::config::var("DRAGONFLY_CHAOS_SEED")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.seed),
+            chunk_failure_rate: env_rate("DRAGONFLY_CHAOS_CHUNK_FAILURE_RATE", defaults.chunk_failure_rate),
+            tinkerbell_max_delay: crate::config::var("DRAGONFLY_CHAOS_TINKERBELL_DELAY_MS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.tinkerbell_max_delay),
+            tinkerbell_failure_rate: env_rate("DRAGONFLY_CHAOS_TINKERBELL_FAILURE_RATE", defaults.tinkerbell_failure_rate),
+            sse_drop_rate: env_rate("DRAGONFLY_CHAOS_SSE_DROP_RATE", defaults.sse_drop_rate),
+        })
+    }
+
+    fn rate(&self, fault: Fault) -> f64 {
+        match fault {
+            Fault::ArtifactChunk => self.chunk_failure_rate,
+            Fault::TinkerbellCall => self.tinkerbell_failure_rate,
+            Fault::SseEvent => self.sse_drop_rate,
+        }
+    }
+}
+
+/// Seeded fault decisions. Each fault kind has its own generator so, for example,
+/// SSE traffic doesn't shift which artifact chunk fails.
+pub struct Injector {
+    config: ChaosConfig,
+    rngs: [Mutex<StdRng>; 3],
+}
+
+impl Injector {
+    pub fn new(config: ChaosConfig) -> Self {
+        let rng = |fault: Fault| Mutex::new(StdRng::seed_from_u64(config.seed.wrapping_add(fault.index() as u64)));
+        let rngs = Fault::ALL.map(rng);
+        Self { config, rngs }
+    }
+
+    pub fn config(&self) -> &ChaosConfig {
+        &self.config
+    }
+
+    fn with_rng<T>(&self, fault: Fault, f: impl FnOnce(&mut StdRng) -> T) -> T {
+        let mut rng = self.rngs[fault.index()].lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut rng)
+    }
+
+    /// Whether this occurrence of `fault` should fail.
+    pub fn should_fail(&self, fault: Fault) -> bool {
+        let rate = self.config.rate(fault);
+        if rate <= 0.0 {
+            return false;
+        }
+        self.with_rng(fault, |rng| rng.gen_bool(rate))
+    }
+
+    /// Delay to add before the next Tinkerbell call.
+    pub fn tinkerbell_delay(&self) -> Duration {
+        let max = self.config.tinkerbell_max_delay.as_millis() as u64;
+        if max == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(self.with_rng(Fault::TinkerbellCall, |rng| rng.gen_range(0..=max)))
+    }
+}
+
+static INJECTOR: OnceLock<Option<Injector>> = OnceLock::new();
+
+/// The process-wide injector, if fault injection is enabled.
+pub fn injector() -> Option<&'static Injector> {
+    INJECTOR.get_or_init(|| {
+        let config = ChaosConfig::from_env()?;
+        warn!("FAULT INJECTION ENABLED ({}): {:?}", ENABLE_ENV_VAR, config);
+        Some(Injector::new(config))
+    }).as_ref()
+}
+
+/// Whether to inject `fault` now. Always false when fault injection is off.
+pub fn inject(fault: Fault) -> bool {
+    let fail = injector().is_some_and(|injector| injector.should_fail(fault));
+    if fail {
+        debug!("Injecting {:?} fault", fault);
+    }
+    fail
+}
+
+/// Wraps a Tinkerbell client with delays and failures when fault injection is on.
+pub fn wrap_tinkerbell(client: Arc<dyn TinkerbellClient>) -> Arc<dyn TinkerbellClient> {
+    match injector() {
+        Some(injector) => Arc::new(ChaosTinkerbellClient { inner: client, injector }),
+        None => client,
+    }
+}
+
+struct ChaosTinkerbellClient {
+    inner: Arc<dyn TinkerbellClient>,
+    injector: &'static Injector,
+}
+
+impl ChaosTinkerbellClient {
+    async fn disturb(&self, operation: &str, kind: ResourceKind, name: &str) -> Result<()> {
+        tokio::time::sleep(self.injector.tinkerbell_delay()).await;
+        if self.injector.should_fail(Fault::TinkerbellCall) {
+            warn!("Injected failure for Tinkerbell {} of {} {}", operation, kind.kind(), name);
+            return Err(anyhow!("Injected fault: Tinkerbell {} of {} {} failed", operation, kind.kind(), name));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TinkerbellClient for ChaosTinkerbellClient {
+    async fn connect(&self) -> Result<()> {
+        tokio::time::sleep(self.injector.tinkerbell_delay()).await;
+        self.inner.connect().await
+    }
+
+    async fn get(&self, kind: ResourceKind, name: &str) -> Result<Option<Value>> {
+        self.disturb("get", kind, name).await?;
+        self.inner.get(kind, name).await
+    }
+
+    async fn create(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+        self.disturb("create", kind, name).await?;
+        self.inner.create(kind, name, body).await
+    }
+
+    async fn patch(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+        self.disturb("patch", kind, name).await?;
+        self.inner.patch(kind, name, body).await
+    }
+
+    async fn delete(&self, kind: ResourceKind, name: &str) -> Result<bool> {
+        self.disturb("delete", kind, name).await?;
+        self.inner.delete(kind, name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rate: f64) -> ChaosConfig {
+        ChaosConfig {
+            seed: 42,
+            chunk_failure_rate: rate,
+            tinkerbell_max_delay: Duration::from_millis(500),
+            tinkerbell_failure_rate: rate,
+            sse_drop_rate: rate,
+        }
+    }
+
+    fn sequence(injector: &Injector, fault: Fault) -> Vec<bool> {
+        (0..64).map(|_| injector.should_fail(fault)).collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_faults() {
+        let a = Injector::new(config(0.3));
+        let b = Injector::new(config(0.3));
+        assert_eq!(sequence(&a, Fault::ArtifactChunk), sequence(&b, Fault::ArtifactChunk));
+        assert!(sequence(&a, Fault::ArtifactChunk).contains(&true));
+    }
+
+    #[test]
+    fn test_fault_kinds_are_independent() {
+        let a = Injector::new(config(0.3));
+        let b = Injector::new(config(0.3));
+        // Drawing SSE faults on one injector must not shift its chunk faults
+        sequence(&a, Fault::SseEvent);
+        assert_eq!(sequence(&a, Fault::ArtifactChunk), sequence(&b, Fault::ArtifactChunk));
+    }
+
+    #[test]
+    fn test_rate_bounds() {
+        assert!(!sequence(&Injector::new(config(0.0)), Fault::TinkerbellCall).contains(&true));
+        assert!(!sequence(&Injector::new(config(1.0)), Fault::TinkerbellCall).contains(&false));
+    }
+
+    #[test]
+    fn test_delay_within_bound() {
+        let injector = Injector::new(config(0.0));
+        assert!((0..32).all(|_| injector.tinkerbell_delay() <= Duration::from_millis(500)));
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::{anyhow, bail, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Chunks live here under the artifact dir, addressed by the SHA-256 of their contents.
+pub const CHUNK_DIR: &str = "chunks";
+
+/// The script HookOS runs to sync a disk against a chunk index, served from the chunk dir.
+pub const SYNC_SCRIPT_NAME: &str = "delta-sync.sh";
+
+// Chunks are fixed-size and aligned so chunk N always maps to the same disk offset;
+// content-defined chunking would save nothing when updating a block device in place
+pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+const INDEX_MAGIC: &str = "dragonfly-chunks";
+const INDEX_VERSION: &str = "v1";
+const INDEX_EXTENSION: &str = "chunks";
+
+// Compares each disk chunk with the index and only downloads the ones that differ.
+// Zero chunks aren't stored; they're written from /dev/zero. When the tracker has peer
+// distribution on, chunks already on disk are served to peers over busybox httpd, and
+// chunks are fetched from peers first (verified against the index) before the server.
+const SYNC_SCRIPT: &str = r#"#!/bin/sh
+set -eu
+: "${DEST_DISK:?}" "${INDEX_URL:?}" "${CHUNK_URL:?}"
+wget -qO /tmp/index "$INDEX_URL"
+read -r magic version chunk_size total_size < /tmp/index
+if [ "$magic" != "dragonfly-chunks" ] || [ "$version" != "v1" ]; then
+    echo "Unsupported chunk index" >&2
+    exit 1
+fi
+zero=$(dd if=/dev/zero bs="$chunk_size" count=1 2>/dev/null | sha256sum | cut -d' ' -f1)
+tail -n +2 /tmp/index > /tmp/hashes
+echo 0 > /tmp/have
+
+peer_port=""
+if [ -n "${TRACKER_URL:-}" ] && wget -qO /tmp/peer-config "$TRACKER_URL/api/peers/config" 2>/dev/null; then
+    read -r peer_port linger < /tmp/peer-config
+    mkdir -p /tmp/www/cgi-bin
+    cat > /tmp/www/cgi-bin/chunk <<CGI
+#!/bin/sh
+hash="\${QUERY_STRING#hash=}"
+case "\$hash" in ""|*[!0-9a-f]*) printf 'Status: 404 Not Found\r\n\r\n'; exit 0;; esac
+line=\$(grep -n -m1 -x "\$hash" /tmp/hashes | cut -d: -f1)
+if [ -z "\$line" ] || [ "\$line" -gt "\$(cat /tmp/have)" ]; then
+    printf 'Status: 404 Not Found\r\n\r\n'; exit 0
+fi
+printf 'Content-Type: application/octet-stream\r\n\r\n'
+dd if="$DEST_DISK" bs="$chunk_size" skip=\$((line - 1)) count=1 2>/dev/null | gzip -c
+CGI
+    chmod +x /tmp/www/cgi-bin/chunk
+    busybox httpd -p "$peer_port" -h /tmp/www
+fi
+
+announce() {
+    echo "$1" > /tmp/have
+    [ -n "$peer_port" ] || return 0
+    wget -qO /dev/null --post-data "index=$INDEX_PATH&have=$1&port=$peer_port" "$TRACKER_URL/api/peers/announce" 2>/dev/null || true
+}
+
+fetch_chunk() {
+    if [ -n "$peer_port" ]; then
+        for peer in $(wget -qO- "$TRACKER_URL/api/peers/chunks/$1" 2>/dev/null); do
+            if wget -qO- -T 10 "$peer/cgi-bin/chunk?hash=$1" 2>/dev/null | gunzip > /tmp/chunk 2>/dev/null \
+                && [ "$(sha256sum < /tmp/chunk | cut -d' ' -f1)" = "$1" ]; then
+                from_peer=$((from_peer + 1))
+                return 0
+            fi
+        done
+    fi
+    wget -qO- "$CHUNK_URL/$(echo "$1" | cut -c1-2)/$1.gz" | gunzip > /tmp/chunk
+}
+
+i=0; fetched=0; from_peer=0; total=$(wc -l < /tmp/hashes)
+while read -r hash; do
+    current=$(dd if="$DEST_DISK" bs="$chunk_size" skip="$i" count=1 2>/dev/null | sha256sum | cut -d' ' -f1)
+    if [ "$current" != "$hash" ]; then
+        if [ "$hash" = "$zero" ]; then
+            dd if=/dev/zero of="$DEST_DISK" bs="$chunk_size" seek="$i" count=1 conv=notrunc 2>/dev/null
+        else
+            fetch_chunk "$hash"
+            dd if=/tmp/chunk of="$DEST_DISK" bs="$chunk_size" seek="$i" count=1 conv=notrunc 2>/dev/null
+            fetched=$((fetched + 1))
+        fi
+    fi
+    i=$((i + 1))
+    [ $((i % 16)) -ne 0 ] || announce "$i"
+done < /tmp/hashes
+sync
+announce "$i"
+echo "Synced $total chunks of $total_size bytes, downloaded $fetched ($from_peer from peers)"
+
+# Keep seeding for a while so machines still syncing can use this one
+if [ -n "$peer_port" ] && [ "${linger:-0}" -gt 0 ]; then
+    end=$(($(date +%s) + linger))
+    while [ "$(date +%s)" -lt "$end" ]; do
+        sleep 30
+        announce "$i"
+    done
+fi
+"#;
+
+/// The chunk layout of one image: SHA-256 of each fixed-size chunk, the last one zero-padded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkIndex {
+    pub chunk_size: u64,
+    pub total_size: u64,
+    pub chunks: Vec<String>,
+}
+
+impl ChunkIndex {
+    pub fn render(&self) -> String {
+        let mut out = format!("{} {} {} {}\n", INDEX_MAGIC, INDEX_VERSION, self.chunk_size, self.total_size);
+        for hash in &self.chunks {
+            out.push_str(hash);
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut lines = content.lines();
+        let header: Vec<&str> = lines.next().unwrap_or_default().split_whitespace().collect();
+        let [magic, version, chunk_size, total_size] = header[..] else {
+            bail!("Malformed chunk index header");
+        };
+        if magic != INDEX_MAGIC || version != INDEX_VERSION {
+            bail!("Unsupported chunk index {} {}", magic, version);
+        }
+        Ok(Self {
+            chunk_size: chunk_size.parse()?,
+            total_size: total_size.parse()?,
+            chunks: lines.filter(|l| !l.is_empty()).map(str::to_string).collect(),
+        })
+    }
+}
+
+fn chunk_root() -> PathBuf {
+    crate::api::artifact_base_dir().join(CHUNK_DIR)
+}
+
+fn chunk_path(root: &Path, hash: &str) -> PathBuf {
+    root.join(&hash[..2]).join(format!("{}.gz", hash))
+}
+
+/// Where the index for an artifact is kept, relative to the artifact dir (and to `/ipxe/`).
+pub fn index_artifact_path(artifact_path: &str) -> String {
+    format!("{}.{}", artifact_path, INDEX_EXTENSION)
+}
+
+// Chunk an image into the store, writing only chunks that aren't already there
+fn build_index_in(image: &Path, root: &Path, chunk_size: u64) -> Result<(ChunkIndex, usize)> {
+    let mut file = fs::File::open(image)?;
+    let total_size = file.metadata()?.len();
+    let zero_hash = format!("{:x}", Sha256::digest(vec![0u8; chunk_size as usize]));
+    let mut buffer = vec![0u8; chunk_size as usize];
+    let mut chunks = Vec::new();
+    let mut stored = 0;
+
+    loop {
+        // Fill the whole buffer; short reads are fine, EOF ends the image
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match file.read(&mut buffer[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        buffer[filled..].fill(0);
+
+        let hash = format!("{:x}", Sha256::digest(&buffer));
+        let path = chunk_path(root, &hash);
+        if hash != zero_hash && !path.exists() {
+            fs::create_dir_all(path.parent().ok_or_else(|| anyhow!("Invalid chunk path"))?)?;
+            let partial = path.with_extension("partial");
+            let mut encoder = GzEncoder::new(fs::File::create(&partial)?, Compression::fast());
+            encoder.write_all(&buffer)?;
+            encoder.finish()?.sync_all()?;
+            fs::rename(&partial, &path)?;
+            stored += 1;
+        }
+        chunks.push(hash);
+
+        if filled < buffer.len() {
+            break;
+        }
+    }
+
+    Ok((ChunkIndex { chunk_size, total_size, chunks }, stored))
+}
+
+/// Chunk a cached raw image into the store and write its index next to it.
+pub async fn build_index(artifact_path: &str) -> Result<ChunkIndex> {
+    let base = crate::api::artifact_base_dir();
+    let image = base.join(artifact_path);
+    let index_path = base.join(index_artifact_path(artifact_path));
+    let root = chunk_root();
+
+    let index = tokio::task::spawn_blocking(move || -> Result<ChunkIndex> {
+        let (index, stored) = build_index_in(&image, &root, CHUNK_SIZE)?;
+        fs::create_dir_all(&root)?;
+        fs::write(root.join(SYNC_SCRIPT_NAME), SYNC_SCRIPT)?;
+        fs::write(&index_path, index.render())?;
+        info!("Chunked {:?} into {} chunks ({} new)", image, index.chunks.len(), stored);
+        Ok(index)
+    }).await??;
+    Ok(index)
+}
+
+fn collect_referenced(dir: &Path, referenced: &mut HashSet<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_referenced(&path, referenced);
+        } else if path.extension().is_some_and(|e| e == INDEX_EXTENSION) {
+            match fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|c| ChunkIndex::parse(&c)) {
+                Ok(index) => referenced.extend(index.chunks),
+                Err(e) => warn!("Skipping unreadable chunk index {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+fn remove_unreferenced(root: &Path, referenced: &HashSet<String>) -> Result<(usize, u64)> {
+    let (mut removed, mut freed) = (0, 0);
+    let Ok(prefixes) = fs::read_dir(root) else {
+        return Ok((0, 0));
+    };
+    for prefix in prefixes.flatten().filter(|e| e.path().is_dir()) {
+        for chunk in fs::read_dir(prefix.path())?.flatten() {
+            let name = chunk.file_name().to_string_lossy().to_string();
+            let hash = name.strip_suffix(".gz").unwrap_or(&name);
+            if !referenced.contains(hash) {
+                freed += chunk.metadata().map(|m| m.len()).unwrap_or(0);
+                fs::remove_file(chunk.path())?;
+                removed += 1;
+            }
+        }
+    }
+    Ok((removed, freed))
+}
+
+/// Delete chunks no index under `index_dir` (relative to the artifact dir) refers to any more.
+pub async fn garbage_collect(index_dir: &str) -> Result<()> {
+    let indexes = crate::api::artifact_base_dir().join(index_dir);
+    let root = chunk_root();
+    let (removed, freed) = tokio::task::spawn_blocking(move || {
+        let mut referenced = HashSet::new();
+        collect_referenced(&indexes, &mut referenced);
+        remove_unreferenced(&root, &referenced)
+    }).await??;
+    if removed > 0 {
+        info!("Removed {} unreferenced chunks, freeing {} bytes", removed, freed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_round_trip() {
+        let index = ChunkIndex { chunk_size: 16, total_size: 40, chunks: vec!["ab".repeat(32), "cd".repeat(32)] };
+        assert_eq!(ChunkIndex::parse(&index.render()).unwrap(), index);
+        assert!(ChunkIndex::parse("casync v9 1 1\n").is_err());
+    }
+
+    #[test]
+    fn test_build_dedupes_and_skips_zero_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.raw");
+        let mut data = Vec::new();
+        data.extend([1u8; 16]);
+        data.extend([0u8; 16]);
+        data.extend([1u8; 16]);
+        data.extend([2u8; 4]);
+        fs::write(&image, &data).unwrap();
+
+        let root = dir.path().join("chunks");
+        let (index, stored) = build_index_in(&image, &root, 16).unwrap();
+        assert_eq!(index.total_size, 52);
+        assert_eq!(index.chunks.len(), 4);
+        assert_eq!(index.chunks[0], index.chunks[2]);
+        // Two distinct non-zero chunks; the repeat and the zero chunk aren't stored
+        assert_eq!(stored, 2);
+        assert!(!chunk_path(&root, &index.chunks[1]).exists());
+
+        // Rebuilding stores nothing new
+        let (_, stored) = build_index_in(&image, &root, 16).unwrap();
+        assert_eq!(stored, 0);
+    }
+
+    #[test]
+    fn test_unreferenced_chunks_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.raw");
+        fs::write(&image, [7u8; 16]).unwrap();
+        let root = dir.path().join("chunks");
+        let (index, _) = build_index_in(&image, &root, 16).unwrap();
+
+        let (removed, _) = remove_unreferenced(&root, &index.chunks.iter().cloned().collect()).unwrap();
+        assert_eq!(removed, 0);
+        let (removed, _) = remove_unreferenced(&root, &HashSet::new()).unwrap();
+        assert_eq!(removed, 1);
+    }
+}
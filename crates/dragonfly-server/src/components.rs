@@ -0,0 +1,348 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::fs;
+use tracing::{debug, info};
+
+const CHARTS_REPO: &str = "https://github.com/Zorlin/dragonfly-charts.git";
+const NAMESPACE: &str = "tink";
+
+// Only one redeploy may drive helm at a time
+static REDEPLOY_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// A Helm release Dragonfly deploys and manages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Component {
+    TinkStack,
+    Dragonfly,
+}
+
+impl Component {
+    pub const ALL: [Component; 2] = [Component::TinkStack, Component::Dragonfly];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Component::TinkStack => "tink-stack",
+            Component::Dragonfly => "dragonfly",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == name)
+    }
+
+    pub fn release(&self) -> &'static str {
+        self.as_str()
+    }
+
+    /// Chart location within the dragonfly-charts repository.
+    fn chart_path(&self, repo_dir: &Path) -> PathBuf {
+        match self {
+            Component::TinkStack => repo_dir.join("tinkerbell").join("stack"),
+            Component::Dragonfly => repo_dir.join("dragonfly"),
+        }
+    }
+
+    /// Where the chart keeps the settings for its main workload.
+    fn values_prefix(&self) -> &'static [&'static str] {
+        match self {
+            Component::TinkStack => &["stack"],
+            Component::Dragonfly => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServiceType {
+    ClusterIP,
+    NodePort,
+    LoadBalancer,
+}
+
+/// Kubernetes resource requests and limits, as quantities like "500m" or "1Gi".
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceSettings {
+    pub cpu_request: Option<String>,
+    pub cpu_limit: Option<String>,
+    pub memory_request: Option<String>,
+    pub memory_limit: Option<String>,
+}
+
+/// Admin-editable Helm values for a component. Unset fields keep whatever the
+/// release is already running with.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ComponentValues {
+    pub image_tag: Option<String>,
+    #[serde(default)]
+    pub resources: ResourceSettings,
+    #[serde(default)]
+    pub node_selector: BTreeMap<String, String>,
+    pub service_type: Option<ServiceType>,
+}
+
+// Plain decimal with an optional Kubernetes suffix, e.g. 250m, 0.5, 512Mi, 2G
+fn is_quantity(value: &str) -> bool {
+    const SUFFIXES: [&str; 13] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei", "m", "k", "M", "G", "T", "P", "E"];
+    let number = SUFFIXES.iter()
+        .find_map(|suffix| value.strip_suffix(suffix))
+        .unwrap_or(value);
+    !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && number.matches('.').count() <= 1
+        && number.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+impl ComponentValues {
+    /// Reject values helm would accept but Kubernetes would refuse at apply time.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(tag) = &self.image_tag {
+            if tag.is_empty() || tag.chars().any(|c| c.is_whitespace() || c == ':' || c == '@') {
+                bail!("Invalid image tag '{}'", tag);
+            }
+        }
+        let quantities = [
+            ("cpu_request", &self.resources.cpu_request),
+            ("cpu_limit", &self.resources.cpu_limit),
+            ("memory_request", &self.resources.memory_request),
+            ("memory_limit", &self.resources.memory_limit),
+        ];
+        for (field, value) in quantities {
+            if let Some(value) = value {
+                if !is_quantity(value) {
+                    bail!("Invalid {} '{}'; expected a quantity like 500m or 1Gi", field, value);
+                }
+            }
+        }
+        for (key, value) in &self.node_selector {
+            if key.is_empty() || key.contains(char::is_whitespace) || value.contains(char::is_whitespace) {
+                bail!("Invalid nodeSelector entry '{}={}'", key, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == ComponentValues::default()
+    }
+
+    /// Render as a Helm values overlay nested under the given prefix.
+    pub fn to_overlay(&self, prefix: &[&str]) -> Value {
+        let mut values = Mapping::new();
+
+        if let Some(tag) = &self.image_tag {
+            values.insert("image".into(), mapping([("tag", Value::from(tag.as_str()))]));
+        }
+
+        let quantities = |cpu: &Option<String>, memory: &Option<String>| -> Mapping {
+            [("cpu", cpu), ("memory", memory)].into_iter()
+                .filter_map(|(key, value)| value.as_ref().map(|v| (Value::from(key), Value::from(v.as_str()))))
+                .collect()
+        };
+        let requests = quantities(&self.resources.cpu_request, &self.resources.memory_request);
+        let limits = quantities(&self.resources.cpu_limit, &self.resources.memory_limit);
+        if !requests.is_empty() || !limits.is_empty() {
+            let mut resources = Mapping::new();
+            if !requests.is_empty() {
+                resources.insert("requests".into(), Value::Mapping(requests));
+            }
+            if !limits.is_empty() {
+                resources.insert("limits".into(), Value::Mapping(limits));
+            }
+            values.insert("resources".into(), Value::Mapping(resources));
+        }
+
+        if !self.node_selector.is_empty() {
+            let selector = self.node_selector.iter()
+                .map(|(k, v)| (Value::from(k.as_str()), Value::from(v.as_str())))
+                .collect();
+            values.insert("nodeSelector".into(), Value::Mapping(selector));
+        }
+
+        if let Some(service_type) = self.service_type {
+            let name = match service_type {
+                ServiceType::ClusterIP => "ClusterIP",
+                ServiceType::NodePort => "NodePort",
+                ServiceType::LoadBalancer => "LoadBalancer",
+            };
+            values.insert("service".into(), mapping([("type", Value::from(name))]));
+        }
+
+        prefix.iter().rev().fold(Value::Mapping(values), |inner, key| mapping([(*key, inner)]))
+    }
+}
+
+fn mapping<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Mapping(entries.into_iter().map(|(k, v)| (Value::from(k), v)).collect())
+}
+
+/// Clone the dragonfly-charts repository into `dest` and build the Tinkerbell stack's dependencies.
+pub fn fetch_charts(dest: &Path) -> Result<()> {
+    info!("Fetching Dragonfly Helm charts from GitHub...");
+    let clone = Command::new("git")
+        .args(["clone", "--depth", "1", CHARTS_REPO])
+        .arg(dest)
+        .output()
+        .context("Failed to run git")?;
+    if !clone.status.success() {
+        bail!("Failed to clone Helm charts: {}", String::from_utf8_lossy(&clone.stderr).trim());
+    }
+
+    let dependency_build = Command::new("helm")
+        .args(["dependency", "build"])
+        .current_dir(Component::TinkStack.chart_path(dest))
+        .output()
+        .context("Failed to run helm dependency build")?;
+    if !dependency_build.status.success() {
+        bail!("Failed to build Helm chart dependencies: {}", String::from_utf8_lossy(&dependency_build.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Saved values for every component, defaulting to empty for ones never edited.
+pub async fn all_values() -> Result<BTreeMap<&'static str, ComponentValues>> {
+    let mut saved = crate::db::get_component_values().await?;
+    Ok(Component::ALL.into_iter()
+        .map(|c| (c.as_str(), saved.remove(c.as_str()).unwrap_or_default()))
+        .collect())
+}
+
+async fn upgrade(component: Component, repo_dir: &Path, work_dir: &Path, values: &ComponentValues, chart_defaults: bool) -> Result<()> {
+    let chart = component.chart_path(repo_dir);
+    let overlay_path = work_dir.join(format!("{}-overrides.yaml", component.as_str()));
+    fs::write(&overlay_path, serde_yaml::to_string(&values.to_overlay(component.values_prefix()))?).await
+        .with_context(|| format!("Failed to write {}", overlay_path.display()))?;
+
+    // --reuse-values keeps what the installer set (public IP, trusted proxies, DHCP mode);
+    // --reset-then-reuse-values does too, but picks up the new chart's defaults (image versions)
+    let reuse = if chart_defaults { "--reset-then-reuse-values" } else { "--reuse-values" };
+    let output = Command::new("helm")
+        .args(["upgrade", component.release()])
+        .arg(&chart)
+        .args(["--namespace", NAMESPACE, reuse, "--wait", "--timeout", "10m", "-f"])
+        .arg(&overlay_path)
+        .output()
+        .with_context(|| format!("Failed to run helm for {}", component.release()))?;
+    if !output.status.success() {
+        bail!("helm upgrade of {} failed: {}", component.release(), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    debug!("helm upgrade output: {}", String::from_utf8_lossy(&output.stdout));
+    Ok(())
+}
+
+/// Re-render each component's saved values and apply them with `helm upgrade`.
+/// Components without any saved values are skipped.
+pub async fn redeploy(components: &[Component]) -> Result<Vec<Component>> {
+    if REDEPLOY_RUNNING.swap(true, Ordering::SeqCst) {
+        bail!("A redeploy is already in progress");
+    }
+    let result = redeploy_inner(components).await;
+    REDEPLOY_RUNNING.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Upgrade a component to the chart in dragonfly-charts, keeping the values it was installed
+/// with plus any saved customizations. Shares the redeploy lock.
+pub async fn upgrade_release(component: Component) -> Result<()> {
+    if REDEPLOY_RUNNING.swap(true, Ordering::SeqCst) {
+        bail!("A redeploy is already in progress");
+    }
+    let result = upgrade_release_inner(component).await;
+    REDEPLOY_RUNNING.store(false, Ordering::SeqCst);
+    result
+}
+
+async fn upgrade_release_inner(component: Component) -> Result<()> {
+    let values = crate::db::get_component_values().await?
+        .remove(component.as_str())
+        .unwrap_or_default();
+    let work_dir = tempfile::tempdir().context("Failed to create working directory")?;
+    let repo_dir = work_dir.path().join("dragonfly-charts");
+    fetch_charts(&repo_dir)?;
+    info!("Upgrading {} to the current chart", component.as_str());
+    upgrade(component, &repo_dir, work_dir.path(), &values, true).await
+}
+
+/// Whether a redeploy is currently running.
+pub fn redeploy_running() -> bool {
+    REDEPLOY_RUNNING.load(Ordering::SeqCst)
+}
+
+async fn redeploy_inner(components: &[Component]) -> Result<Vec<Component>> {
+    let mut saved = crate::db::get_component_values().await?;
+    let pending: Vec<(Component, ComponentValues)> = components.iter()
+        .filter_map(|c| saved.remove(c.as_str()).map(|v| (*c, v)))
+        .filter(|(_, v)| !v.is_empty())
+        .collect();
+    if pending.is_empty() {
+        info!("No customized values to apply");
+        return Ok(Vec::new());
+    }
+
+    let work_dir = tempfile::tempdir().context("Failed to create working directory")?;
+    let repo_dir = work_dir.path().join("dragonfly-charts");
+    fetch_charts(&repo_dir)?;
+
+    let mut applied = Vec::new();
+    for (component, values) in pending {
+        info!("Redeploying {} with customized values", component.as_str());
+        upgrade(component, &repo_dir, work_dir.path(), &values, false).await
+            .map_err(|e| anyhow!("{:#} (already applied: {:?})", e, applied.iter().map(Component::as_str).collect::<Vec<_>>()))?;
+        applied.push(component);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantities() {
+        for ok in ["500m", "1", "0.5", "512Mi", "2Gi", "1G"] {
+            assert!(is_quantity(ok), "{} should be valid", ok);
+        }
+        for bad in ["", "Mi", "1.2.3", "-1", "1 Gi", "lots"] {
+            assert!(!is_quantity(bad), "{} should be invalid", bad);
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_values() {
+        let values = ComponentValues { image_tag: Some("v1.2 ".to_string()), ..Default::default() };
+        assert!(values.validate().is_err());
+
+        let mut values = ComponentValues::default();
+        values.resources.memory_limit = Some("1GB".to_string());
+        assert!(values.validate().is_err());
+    }
+
+    #[test]
+    fn test_overlay_nests_under_prefix() {
+        let mut values = ComponentValues {
+            image_tag: Some("v0.5.0".to_string()),
+            service_type: Some(ServiceType::NodePort),
+            ..Default::default()
+        };
+        values.resources.cpu_limit = Some("2".to_string());
+        values.node_selector.insert("kubernetes.io/arch".to_string(), "amd64".to_string());
+
+        let overlay = values.to_overlay(&["stack"]);
+        let stack = &overlay["stack"];
+        assert_eq!(stack["image"]["tag"].as_str(), Some("v0.5.0"));
+        assert_eq!(stack["resources"]["limits"]["cpu"].as_str(), Some("2"));
+        assert!(stack["resources"].get("requests").is_none());
+        assert_eq!(stack["nodeSelector"]["kubernetes.io/arch"].as_str(), Some("amd64"));
+        assert_eq!(stack["service"]["type"].as_str(), Some("NodePort"));
+    }
+
+    #[test]
+    fn test_empty_overlay() {
+        let overlay = ComponentValues::default().to_overlay(&[]);
+        assert_eq!(overlay.as_mapping().map(|m| m.len()), Some(0));
+    }
+}
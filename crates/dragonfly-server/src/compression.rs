@@ -0,0 +1,112 @@
+// Response compression for the dashboard and API (gzip or brotli, whichever the browser
+// prefers), to cut bandwidth on slow links.
+//
+// Only the content types listed below are ever compressed. Artifact streams (kernels,
+// images, iPXE and GRUB scripts) must reach boot firmware as-is with working range
+// requests, so octet-stream and text/plain responses are left alone, as is anything that
+// already sets Content-Encoding (artifact handlers send `identity`) or Content-Range.
+// Server-sent events are skipped too, since compression buffers the stream.
+//
+// DRAGONFLY_COMPRESSION=off disables compression entirely, and
+// DRAGONFLY_COMPRESSION_EXCLUDE takes a comma-separated list of content types (or
+// prefixes, e.g. "image/") to drop from the list.
+
+use http_body::Body;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+use tower_http::compression::CompressionLayer;
+use tracing::info;
+
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "text/html",
+    "application/json",
+    "text/css",
+    "application/javascript",
+    "text/javascript",
+    "image/svg+xml",
+];
+
+/// Which content types get compressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentTypePolicy {
+    types: Vec<&'static str>,
+}
+
+impl ContentTypePolicy {
+    pub fn new(enabled: bool, excluded: &[String]) -> Self {
+        let types = if enabled {
+            COMPRESSIBLE_TYPES.iter()
+                .copied()
+                .filter(|t| !excluded.iter().any(|e| !e.is_empty() && t.starts_with(e.as_str())))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self { types }
+    }
+
+    fn from_env() -> Self {
+        let enabled = !matches!(
+            crate::config::var("DRAGONFLY_COMPRESSION").map(|v| v.trim().to_ascii_lowercase()).as_deref(),
+            Some("off" | "false" | "0" | "no")
+        );
+        let excluded: Vec<String> = crate::config::var("DRAGONFLY_COMPRESSION_EXCLUDE")
+            .unwrap_or_default()
+            .split(',')
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        Self::new(enabled, &excluded)
+    }
+
+    /// Whether a Content-Type header value is one we compress (parameters like charset are ignored).
+    pub fn allows(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        self.types.iter().any(|t| *t == essence)
+    }
+}
+
+impl Predicate for ContentTypePolicy {
+    fn should_compress<B>(&self, response: &http::Response<B>) -> bool
+    where
+        B: Body,
+    {
+        response.headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|content_type| self.allows(content_type))
+    }
+}
+
+/// The compression layer for the main router.
+pub fn layer() -> CompressionLayer<impl Predicate> {
+    let policy = ContentTypePolicy::from_env();
+    if policy.types.is_empty() {
+        info!("Response compression disabled");
+    } else {
+        info!("Compressing responses of type: {}", policy.types.join(", "));
+    }
+    // The default predicate adds the minimum size and the event-stream/gRPC/image exclusions
+    CompressionLayer::new().compress_when(DefaultPredicate::new().and(policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type_policy() {
+        let policy = ContentTypePolicy::new(true, &[]);
+        assert!(policy.allows("text/html; charset=utf-8"));
+        assert!(policy.allows("application/json"));
+        assert!(!policy.allows("application/octet-stream"));
+        assert!(!policy.allows("text/plain"));
+        assert!(!policy.allows("text/event-stream"));
+
+        let policy = ContentTypePolicy::new(true, &["image/".to_string(), "text/css".to_string()]);
+        assert!(!policy.allows("image/svg+xml"));
+        assert!(!policy.allows("text/css"));
+        assert!(policy.allows("text/javascript"));
+
+        assert!(!ContentTypePolicy::new(false, &[]).allows("text/html"));
+    }
+}
@@ -0,0 +1,482 @@
+// Server configuration.
+//
+// Settings come from, lowest precedence first: built-in defaults, a dragonfly.toml file
+// (--config, DRAGONFLY_CONFIG, or /etc/dragonfly/dragonfly.toml when it exists), the
+// environment, and command-line flags. The core settings are typed; any other DRAGONFLY_*
+// or TINKERBELL_* tunable can be set from the file's [env] table:
+//
+//     [server]
+//     port = 3000
+//     base_url = "http://10.0.0.5:3000"
+//
+//     [storage]
+//     db_path = "/var/lib/dragonfly/sqlite.db"
+//
+//     [http]
+//     sse_keepalive_secs = 10
+//     idle_timeout_secs = 120
+//
+//     [env]
+//     DRAGONFLY_SHUTDOWN_GRACE_SECS = "60"
+//
+// Untyped tunables set only in the environment are collected into `env` when the
+// configuration loads, so `var()` is the one place to read any of them.
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+pub const CONFIG_ENV_VAR: &str = "DRAGONFLY_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/dragonfly/dragonfly.toml";
+
+// Variables with a typed setting; they can't also be set from [env]
+const TYPED_VARS: [&str; 12] = [
+    "DRAGONFLY_PORT",
+    "DRAGONFLY_BASE_URL",
+    "DRAGONFLY_BASE_PATH",
+    "DRAGONFLY_DEMO_MODE",
+    "DRAGONFLY_DB_PATH",
+    "DRAGONFLY_IPXE_ARTIFACT_DIR",
+    "DRAGONFLY_SSE_KEEPALIVE_SECS",
+    "DRAGONFLY_HTTP_IDLE_TIMEOUT_SECS",
+    "DRAGONFLY_SSE_MAX_SUBSCRIBERS",
+    "TINKERBELL_GRPC_AUTHORITY",
+    "TINKERBELL_SYSLOG_HOST",
+    "TINKERBELL_TLS",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// Address machines use to reach this server, e.g. "http://10.0.0.5:3000"
+    pub base_url: Option<String>,
+    /// Prefix when served behind a reverse proxy, e.g. "/dragonfly"
+    pub base_path: Option<String>,
+    pub demo_mode: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { port: 3000, base_url: None, base_path: None, demo_mode: false }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub db_path: String,
+    /// Where iPXE artifacts and OS images are cached
+    pub artifact_dir: Option<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self { db_path: "sqlite.db".to_string(), artifact_dir: None }
+    }
+}
+
+/// Connection tuning, for clients behind proxies that drop connections they think are idle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Seconds between keep-alive comments on event streams
+    pub sse_keepalive_secs: u64,
+    /// Close connections that have sent and received nothing for this many seconds
+    pub idle_timeout_secs: Option<u64>,
+    /// Most event streams (/api/events) open at once; unlimited when unset
+    pub max_sse_subscribers: Option<usize>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { sse_keepalive_secs: 15, idle_timeout_secs: None, max_sse_subscribers: None }
+    }
+}
+
+impl HttpConfig {
+    pub fn sse_keepalive(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.sse_keepalive_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<std::time::Duration> {
+        self.idle_timeout_secs.map(std::time::Duration::from_secs)
+    }
+}
+
+/// Tinkerbell endpoints handed to booting machines; derived from the base URL when unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TinkerbellConfig {
+    pub grpc_authority: Option<String>,
+    pub syslog_host: Option<String>,
+    pub tls: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub http: HttpConfig,
+    pub tinkerbell: TinkerbellConfig,
+    /// Other DRAGONFLY_* / TINKERBELL_* variables, applied unless already set in the environment
+    pub env: BTreeMap<String, String>,
+}
+
+/// Command-line flags, which take precedence over the file and the environment.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub config_file: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub base_url: Option<String>,
+    pub db_path: Option<String>,
+}
+
+/// A resolved configuration and the file it was read from, if any.
+#[derive(Debug, Clone)]
+pub struct Loaded {
+    pub config: Config,
+    pub file: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Layer environment variables (looked up through `var`) over this configuration.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(port) = var("DRAGONFLY_PORT") {
+            self.server.port = port.trim().parse()
+                .with_context(|| format!("DRAGONFLY_PORT '{}' is not a port number", port))?;
+        }
+        if let Some(url) = var("DRAGONFLY_BASE_URL") {
+            self.server.base_url = Some(url);
+        }
+        if let Some(path) = var("DRAGONFLY_BASE_PATH") {
+            self.server.base_path = Some(path);
+        }
+        // Set at all means on, as it always has
+        if var("DRAGONFLY_DEMO_MODE").is_some() {
+            self.server.demo_mode = true;
+        }
+        if let Some(path) = var("DRAGONFLY_DB_PATH") {
+            self.storage.db_path = path;
+        }
+        if let Some(dir) = var("DRAGONFLY_IPXE_ARTIFACT_DIR") {
+            self.storage.artifact_dir = Some(dir);
+        }
+        if let Some(secs) = var("DRAGONFLY_SSE_KEEPALIVE_SECS") {
+            self.http.sse_keepalive_secs = secs.trim().parse()
+                .with_context(|| format!("DRAGONFLY_SSE_KEEPALIVE_SECS '{}' is not a number of seconds", secs))?;
+        }
+        if let Some(secs) = var("DRAGONFLY_HTTP_IDLE_TIMEOUT_SECS") {
+            self.http.idle_timeout_secs = Some(secs.trim().parse()
+                .with_context(|| format!("DRAGONFLY_HTTP_IDLE_TIMEOUT_SECS '{}' is not a number of seconds", secs))?);
+        }
+        if let Some(max) = var("DRAGONFLY_SSE_MAX_SUBSCRIBERS") {
+            self.http.max_sse_subscribers = Some(max.trim().parse()
+                .with_context(|| format!("DRAGONFLY_SSE_MAX_SUBSCRIBERS '{}' is not a number", max))?);
+        }
+        if let Some(authority) = var("TINKERBELL_GRPC_AUTHORITY") {
+            self.tinkerbell.grpc_authority = Some(authority);
+        }
+        if let Some(host) = var("TINKERBELL_SYSLOG_HOST") {
+            self.tinkerbell.syslog_host = Some(host);
+        }
+        if let Some(tls) = var("TINKERBELL_TLS") {
+            self.tinkerbell.tls = Some(tls.trim().parse()
+                .with_context(|| format!("TINKERBELL_TLS '{}' is not true or false", tls))?);
+        }
+        for (key, value) in self.env.iter_mut() {
+            if let Some(from_env) = var(key) {
+                *value = from_env;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take untyped DRAGONFLY_* / TINKERBELL_* variables the [env] table doesn't mention.
+    pub fn collect_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        for (key, value) in vars {
+            if (key.starts_with("DRAGONFLY_") || key.starts_with("TINKERBELL_"))
+                && !TYPED_VARS.contains(&key.as_str())
+                && key != CONFIG_ENV_VAR
+            {
+                self.env.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &Overrides) {
+        if let Some(port) = overrides.port {
+            self.server.port = port;
+        }
+        if let Some(url) = &overrides.base_url {
+            self.server.base_url = Some(url.clone());
+        }
+        if let Some(path) = &overrides.db_path {
+            self.storage.db_path = path.clone();
+        }
+    }
+
+    /// Every problem with the configuration, not just the first.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.server.port == 0 {
+            problems.push("server.port must not be 0".to_string());
+        }
+        if let Some(url) = &self.server.base_url {
+            match url::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host_str().is_some() => {}
+                _ => problems.push(format!("server.base_url '{}' is not an http(s) URL", url)),
+            }
+        }
+        if let Some(path) = &self.server.base_path {
+            if let Err(e) = crate::base_path::normalize(path) {
+                problems.push(format!("server.base_path: {}", e));
+            }
+        }
+        if self.storage.db_path.trim().is_empty() {
+            problems.push("storage.db_path must not be empty".to_string());
+        }
+        if self.http.sse_keepalive_secs == 0 {
+            problems.push("http.sse_keepalive_secs must not be 0".to_string());
+        }
+        match self.http.idle_timeout_secs {
+            Some(0) => problems.push("http.idle_timeout_secs must not be 0".to_string()),
+            // Otherwise quiet event streams are closed between keep-alives
+            Some(secs) if secs <= self.http.sse_keepalive_secs => problems.push(format!(
+                "http.idle_timeout_secs ({}) must be longer than http.sse_keepalive_secs ({})",
+                secs, self.http.sse_keepalive_secs
+            )),
+            _ => {}
+        }
+        if self.http.max_sse_subscribers == Some(0) {
+            problems.push("http.max_sse_subscribers must not be 0".to_string());
+        }
+        if let Some(authority) = &self.tinkerbell.grpc_authority {
+            let port = authority.rsplit_once(':').map(|(_, port)| port.parse::<u16>());
+            if !matches!(port, Some(Ok(_))) {
+                problems.push(format!("tinkerbell.grpc_authority '{}' must be host:port", authority));
+            }
+        }
+        for key in self.env.keys() {
+            if TYPED_VARS.contains(&key.as_str()) {
+                problems.push(format!("env.{} has a typed setting; set that instead", key));
+            } else if !key.starts_with("DRAGONFLY_") && !key.starts_with("TINKERBELL_") {
+                problems.push(format!("env.{} is not a DRAGONFLY_ or TINKERBELL_ variable", key));
+            }
+        }
+        problems
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            bail!("Invalid configuration: {}", problems.join("; "));
+        }
+        Ok(())
+    }
+
+    /// The configuration as TOML, with secret-looking [env] values masked.
+    pub fn to_toml(&self, mask_secrets: bool) -> Result<String> {
+        let mut shown = self.clone();
+        if mask_secrets {
+            for (key, value) in shown.env.iter_mut() {
+                if ["SECRET", "TOKEN", "PASSWORD", "KEY"].iter().any(|s| key.contains(s)) {
+                    *value = "********".to_string();
+                }
+            }
+        }
+        Ok(toml::to_string_pretty(&shown)?)
+    }
+}
+
+/// The file to read: the flag, then DRAGONFLY_CONFIG, then the default path if it exists.
+fn config_file(overrides: &Overrides) -> Option<PathBuf> {
+    overrides.config_file.clone()
+        .or_else(|| std::env::var_os(CONFIG_ENV_VAR).map(PathBuf::from))
+        .or_else(|| Path::new(DEFAULT_CONFIG_PATH).exists().then(|| PathBuf::from(DEFAULT_CONFIG_PATH)))
+}
+
+/// Resolve the configuration without installing it.
+pub fn load(overrides: &Overrides) -> Result<Loaded> {
+    let file = config_file(overrides);
+    let mut config = match &file {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Config::from_toml(&content).with_context(|| format!("Failed to parse {}", path.display()))?
+        }
+        None => Config::default(),
+    };
+    config.apply_env(|key| std::env::var(key).ok())?;
+    config.collect_env(std::env::vars());
+    config.apply_overrides(overrides);
+    Ok(Loaded { config, file })
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+// The file the configuration was read from and its contents then, to spot later edits
+static SOURCE: OnceCell<(PathBuf, String)> = OnceCell::new();
+
+/// Load, validate and install the configuration for this process. Call once, before
+/// anything reads `get()`.
+pub fn init(overrides: &Overrides) -> Result<&'static Config> {
+    let Loaded { config, file } = load(overrides)?;
+    config.validate()?;
+    if let Some(path) = &file {
+        info!("Loaded configuration from {}", path.display());
+        if let Ok(content) = std::fs::read_to_string(path) {
+            let _ = SOURCE.set((path.clone(), content));
+        }
+    }
+    CONFIG.set(config).map_err(|_| anyhow::anyhow!("Configuration was already loaded"))?;
+    Ok(get())
+}
+
+/// Whether the configuration file has been edited since it was loaded. Its settings are fixed
+/// for the life of the process, so an edit only takes effect on restart.
+pub fn file_changed() -> Result<Option<PathBuf>> {
+    let Some((path, loaded)) = SOURCE.get() else {
+        return Ok(None);
+    };
+    let current = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let changed = Config::from_toml(&current)
+        .with_context(|| format!("Failed to parse {}", path.display()))?
+        != Config::from_toml(loaded)?;
+    Ok(changed.then(|| path.clone()))
+}
+
+/// The base URL machines use to reach the server: the configured one, or the one saved by
+/// the setup wizard.
+pub fn base_url() -> Option<String> {
+    get().server.base_url.clone().or_else(|| crate::setup::saved().base_url)
+}
+
+/// Where iPXE artifacts are cached: the configured directory, or the one saved by the setup
+/// wizard.
+pub fn artifact_dir() -> Option<String> {
+    get().storage.artifact_dir.clone().or_else(|| crate::setup::saved().artifact_dir)
+}
+
+/// A tunable without a typed setting, from the environment or the file's [env] table.
+pub fn var(key: &str) -> Option<String> {
+    get().env.get(key).cloned()
+}
+
+/// The process configuration. Without an explicit `init` (the installer, tests), it's
+/// loaded from the default file and environment on first use.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| match load(&Overrides::default()) {
+        Ok(Loaded { config, .. }) => config,
+        Err(e) => {
+            error!("Failed to load configuration, using defaults: {:#}", e);
+            let mut config = Config::default();
+            config.collect_env(std::env::vars());
+            config
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const FILE: &str = r#"
+[server]
+port = 8080
+base_url = "http://10.0.0.5:8080"
+
+[http]
+sse_keepalive_secs = 10
+
+[tinkerbell]
+tls = true
+
+[env]
+DRAGONFLY_SHUTDOWN_GRACE_SECS = "60"
+DRAGONFLY_WEBHOOK_TOKEN = "hunter2"
+"#;
+
+    #[test]
+    fn test_layering() {
+        let mut config = Config::from_toml(FILE).unwrap();
+        assert_eq!(config.storage.db_path, "sqlite.db");
+
+        let env: HashMap<&str, &str> = [
+            ("DRAGONFLY_PORT", "9000"),
+            ("DRAGONFLY_SHUTDOWN_GRACE_SECS", "5"),
+            ("DRAGONFLY_DEMO_MODE", ""),
+            ("DRAGONFLY_HTTP_IDLE_TIMEOUT_SECS", "120"),
+        ].into_iter().collect();
+        config.apply_env(|key| env.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert!(config.server.demo_mode);
+        assert_eq!(config.env["DRAGONFLY_SHUTDOWN_GRACE_SECS"], "5");
+        assert_eq!(config.server.base_url.as_deref(), Some("http://10.0.0.5:8080"));
+        assert_eq!(config.http.sse_keepalive_secs, 10);
+        assert_eq!(config.http.idle_timeout_secs, Some(120));
+
+        config.collect_env([
+            ("DRAGONFLY_DNS_ZONE", "lab.example"),
+            ("DRAGONFLY_SHUTDOWN_GRACE_SECS", "99"),
+            ("DRAGONFLY_PORT", "1"),
+            ("HOME", "/root"),
+        ].map(|(key, value)| (key.to_string(), value.to_string())));
+        assert_eq!(config.env["DRAGONFLY_DNS_ZONE"], "lab.example");
+        assert_eq!(config.env["DRAGONFLY_SHUTDOWN_GRACE_SECS"], "5");
+        assert!(!config.env.contains_key("DRAGONFLY_PORT") && !config.env.contains_key("HOME"));
+
+        config.apply_overrides(&Overrides { port: Some(3001), db_path: Some("/tmp/df.db".to_string()), ..Default::default() });
+        assert_eq!(config.server.port, 3001);
+        assert_eq!(config.storage.db_path, "/tmp/df.db");
+        assert!(config.validate().is_ok());
+
+        let shown = config.to_toml(true).unwrap();
+        assert!(shown.contains("DRAGONFLY_WEBHOOK_TOKEN = \"********\""));
+        assert!(!shown.contains("hunter2"));
+
+        assert!(config.clone().apply_env(|key| (key == "DRAGONFLY_PORT").then(|| "http".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(Config::from_toml("[server]\nprot = 1").is_err());
+
+        let config = Config::from_toml(r#"
+[server]
+port = 0
+base_url = "10.0.0.5"
+
+[http]
+sse_keepalive_secs = 30
+idle_timeout_secs = 30
+max_sse_subscribers = 0
+
+[tinkerbell]
+grpc_authority = "10.0.0.5"
+
+[env]
+DRAGONFLY_PORT = "3000"
+HOME = "/root"
+"#).unwrap();
+        assert_eq!(config.problems(), vec![
+            "server.port must not be 0",
+            "server.base_url '10.0.0.5' is not an http(s) URL",
+            "http.idle_timeout_secs (30) must be longer than http.sse_keepalive_secs (30)",
+            "http.max_sse_subscribers must not be 0",
+            "tinkerbell.grpc_authority '10.0.0.5' must be host:port",
+            "env.DRAGONFLY_PORT has a typed setting; set that instead",
+            "env.HOME is not a DRAGONFLY_ or TINKERBELL_ variable",
+        ]);
+        assert!(Config::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,761 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use dragonfly_common::models::Machine;
+use dragonfly_common::OsIdentifier;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+
+/// Custom images live under this directory in the artifact dir, one subdirectory per image.
+pub const ARTIFACT_DIR: &str = "custom";
+
+/// OS choices for custom images are the image name with this prefix, so they can't collide with built-ins.
+pub const OS_CHOICE_PREFIX: &str = dragonfly_common::os::CUSTOM_OS_PREFIX;
+
+// Retry failed or missing downloads this often
+const PREFETCH_INTERVAL_SECS: u64 = 3600;
+
+// Cached files that passed re-verification, with the size and mtime they had then. Hashing a
+// multi-gigabyte image before every install is slow, so a file is only hashed again once it
+// has changed on disk or its catalog checksum has.
+static VERIFIED: Lazy<Mutex<HashMap<PathBuf, Verified>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Verified {
+    size: u64,
+    modified: Option<SystemTime>,
+    checksum: String,
+}
+
+impl Verified {
+    fn new(metadata: &std::fs::Metadata, checksum: &str) -> Self {
+        Verified {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            checksum: checksum.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Qcow2,
+    Raw,
+}
+
+impl ImageFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageFormat::Qcow2 => "qcow2",
+            ImageFormat::Raw => "raw",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "qcow2" => Some(ImageFormat::Qcow2),
+            "raw" => Some(ImageFormat::Raw),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    /// Registered, download not started
+    Pending,
+    Downloading,
+    /// Downloaded and checksum verified; installable
+    Ready,
+    Failed,
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheStatus::Pending => "pending",
+            CacheStatus::Downloading => "downloading",
+            CacheStatus::Ready => "ready",
+            CacheStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [Self::Pending, Self::Downloading, Self::Ready, Self::Failed]
+            .into_iter()
+            .find(|s| s.as_str() == value)
+    }
+}
+
+/// An operator-supplied golden image, installable like any built-in OS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomImage {
+    pub name: String,
+    pub display_name: String,
+    pub version: String,
+    pub format: ImageFormat,
+    pub source_url: String,
+    /// Hex SHA-256 of the image file
+    pub checksum: String,
+    /// Free-form labels, e.g. build commit or pipeline run
+    pub metadata: BTreeMap<String, String>,
+    pub status: CacheStatus,
+    pub status_message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The image a machine was installed from, or is being installed from.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineImage {
+    pub machine_id: Uuid,
+    pub os_choice: String,
+    /// Custom image version; None for built-in templates
+    pub version: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// None while the install is running, or if it failed
+    pub installed_at: Option<DateTime<Utc>>,
+}
+
+/// Machines running one image version, as listed by GET /api/images/usage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageUsage {
+    pub os_choice: String,
+    pub custom: bool,
+    pub version: Option<String>,
+    /// Whether new installs get this version (custom images only)
+    pub current: bool,
+    pub machine_count: usize,
+    pub machines: Vec<Uuid>,
+}
+
+/// Group machines by the image version they run. Machines installed before versions were
+/// tracked fall back to their reported OS; registered custom images nobody runs are listed too.
+pub fn summarize_usage(machines: &[Machine], installs: &[MachineImage], images: &[CustomImage]) -> Vec<ImageUsage> {
+    let mut groups: BTreeMap<(String, Option<String>), Vec<Uuid>> = BTreeMap::new();
+    for machine in machines {
+        let key = match installs.iter().find(|i| i.machine_id == machine.id) {
+            Some(install) => (install.os_choice.clone(), install.version.clone()),
+            None => match &machine.os_installed {
+                Some(os) => (os.clone(), None),
+                None => continue,
+            },
+        };
+        groups.entry(key).or_default().push(machine.id);
+    }
+    for image in images {
+        groups.entry((image.os_choice(), Some(image.version.clone()))).or_default();
+    }
+
+    groups.into_iter().map(|((os_choice, version), machines)| {
+        let current = images.iter().any(|image| image.os_choice() == os_choice && Some(&image.version) == version.as_ref());
+        ImageUsage {
+            custom: os_choice.starts_with(OS_CHOICE_PREFIX),
+            current,
+            machine_count: machines.len(),
+            os_choice,
+            version,
+            machines,
+        }
+    }).collect()
+}
+
+// Cached files (images and chunk indexes) that belong to any of these versions
+fn pinned_files(versions: &[String]) -> HashSet<String> {
+    let mut files = HashSet::new();
+    for version in versions {
+        for format in [ImageFormat::Qcow2, ImageFormat::Raw] {
+            let file = format!("{}.{}", version, format.as_str());
+            files.insert(crate::chunk_store::index_artifact_path(&file));
+            files.insert(file);
+        }
+    }
+    files
+}
+
+/// Remember which image (and version) a machine is being installed from, so that version
+/// stays cached while the machine runs it.
+pub async fn record_install(machine_id: &Uuid, os_choice: &str) -> Result<()> {
+    let version = match os_choice.strip_prefix(OS_CHOICE_PREFIX) {
+        Some(name) => crate::db::get_custom_image(name).await?.map(|image| image.version),
+        None => None,
+    };
+    crate::db::record_machine_image(machine_id, os_choice, version.as_deref(), Utc::now()).await
+}
+
+/// What the fleet runs, by image version.
+pub async fn usage() -> Result<Vec<ImageUsage>> {
+    let machines = crate::db::get_all_machines().await?;
+    let installs = crate::db::list_machine_images().await?;
+    let images = crate::db::list_custom_images().await?;
+    Ok(summarize_usage(&machines, &installs, &images))
+}
+
+/// An image registration, from an admin or a CI webhook.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CustomImageInput {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub version: String,
+    pub format: ImageFormat,
+    pub url: String,
+    pub checksum: String,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// An entry in the list of installable operating systems.
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogEntry {
+    pub os_choice: String,
+    pub name: String,
+    pub custom: bool,
+}
+
+fn is_valid_segment(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 64
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !value.starts_with('.')
+}
+
+impl CustomImageInput {
+    pub fn validate(&self) -> Result<()> {
+        // The name becomes part of a Kubernetes object name, so keep it DNS-safe
+        if self.name.is_empty()
+            || self.name.len() > 48
+            || !self.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            || self.name.starts_with('-')
+            || self.name.ends_with('-')
+        {
+            bail!("Image name must be lowercase letters, digits and '-', at most 48 characters");
+        }
+        if !is_valid_segment(&self.version) {
+            bail!("Image version may only contain letters, digits, '-', '_' and '.'");
+        }
+        if !(self.url.starts_with("http://") || self.url.starts_with("https://")) {
+            bail!("Image URL must be http or https");
+        }
+        if self.checksum.len() != 64 || !self.checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Checksum must be a hex SHA-256 digest");
+        }
+        Ok(())
+    }
+
+    /// Build the registry entry. Re-registering an existing name keeps its creation time.
+    pub fn into_image(self, created_at: DateTime<Utc>) -> CustomImage {
+        let now = Utc::now();
+        CustomImage {
+            display_name: self.display_name.filter(|d| !d.trim().is_empty()).unwrap_or_else(|| self.name.clone()),
+            name: self.name,
+            version: self.version,
+            format: self.format,
+            source_url: self.url,
+            checksum: self.checksum.to_lowercase(),
+            metadata: self.metadata,
+            status: CacheStatus::Pending,
+            status_message: None,
+            created_at,
+            updated_at: now,
+        }
+    }
+}
+
+impl CustomImage {
+    pub fn os_choice(&self) -> String {
+        format!("{}{}", OS_CHOICE_PREFIX, self.name)
+    }
+
+    /// Path of the cached image relative to the artifact dir (and to `/ipxe/`).
+    pub fn artifact_path(&self) -> String {
+        format!("{}/{}/{}.{}", ARTIFACT_DIR, self.name, self.version, self.format.as_str())
+    }
+
+    /// Raw images are chunked so re-imaging only downloads the blocks that changed.
+    pub fn uses_delta_sync(&self) -> bool {
+        self.format == ImageFormat::Raw
+    }
+
+    // The action that writes the image to the first disk
+    fn write_action(&self, base_url_bare: &str) -> String {
+        if self.uses_delta_sync() {
+            format!(
+                r#"          - name: "sync image"
+            image: alpine:3.21
+            timeout: 9600
+            command: ["/bin/sh", "-c", "wget -qO /tmp/sync.sh \"$SCRIPT_URL\" && sh /tmp/sync.sh"]
+            environment:
+              DEST_DISK: {{{{ index .Hardware.Disks 0 }}}}
+              SCRIPT_URL: "http://{base}:3000/ipxe/{chunks}/{script}"
+              INDEX_URL: "http://{base}:3000/ipxe/{index}"
+              INDEX_PATH: "{index}"
+              CHUNK_URL: "http://{base}:3000/ipxe/{chunks}"
+              TRACKER_URL: "http://{base}:3000"
+"#,
+                base = base_url_bare,
+                chunks = crate::chunk_store::CHUNK_DIR,
+                script = crate::chunk_store::SYNC_SCRIPT_NAME,
+                index = crate::chunk_store::index_artifact_path(&self.artifact_path()),
+            )
+        } else {
+            format!(
+                r#"          - name: "stream image"
+            image: quay.io/tinkerbell/actions/qemuimg2disk:latest
+            timeout: 9600
+            environment:
+              DEST_DISK: {{{{ index .Hardware.Disks 0 }}}}
+              IMG_URL: "http://{base}:3000/ipxe/{path}"
+"#,
+                base = base_url_bare,
+                path = self.artifact_path(),
+            )
+        }
+    }
+
+    /// The Tinkerbell template that writes this image to the first disk and reboots into it.
+    pub fn render_template(&self, base_url_bare: &str) -> String {
+        let name = self.os_choice();
+        format!(
+            r#"apiVersion: tinkerbell.org/v1alpha1
+kind: Template
+metadata:
+  name: {name}
+  namespace: tink
+spec:
+  data: |
+    name: {name}
+    version: "{version}"
+    global_timeout: 9800
+    tasks:
+      - name: "os installation"
+        worker: "{{{{.device_1}}}}"
+        volumes:
+          - /dev:/dev
+          - /dev/console:/dev/console
+          - /lib/firmware:/lib/firmware:ro
+        actions:
+{write}
+          - name: "reboot"
+            image: ghcr.io/jacobweinstock/waitdaemon:latest
+            timeout: 90
+            pid: host
+            command: ["reboot"]
+            environment:
+              IMAGE: alpine
+              WAIT_SECONDS: 10
+            volumes:
+              - /var/run/docker.sock:/var/run/docker.sock
+"#,
+            name = name,
+            version = self.version,
+            write = self.write_action(base_url_bare),
+        )
+    }
+}
+
+/// Built-in operating systems followed by every custom image that is ready to install.
+pub async fn catalog() -> Result<Vec<CatalogEntry>> {
+    let mut entries: Vec<CatalogEntry> = OsIdentifier::ALL.iter()
+        .map(|os| CatalogEntry {
+            os_choice: os.to_string(),
+            name: os.display_name().to_string(),
+            custom: false,
+        })
+        .collect();
+    entries.extend(custom_catalog().await?);
+    Ok(entries)
+}
+
+/// Just the custom images that are ready to install.
+pub async fn custom_catalog() -> Result<Vec<CatalogEntry>> {
+    Ok(crate::db::list_custom_images().await?
+        .into_iter()
+        .filter(|image| image.status == CacheStatus::Ready)
+        .map(|image| CatalogEntry {
+            os_choice: image.os_choice(),
+            name: format!("{} ({})", image.display_name, image.version),
+            custom: true,
+        })
+        .collect())
+}
+
+// Download to a temporary file, hashing as we go, and only move it into place if the checksum matches
+async fn download_verified(image: &CustomImage) -> Result<u64> {
+    let dest = crate::api::artifact_base_dir().join(image.artifact_path());
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let partial = dest.with_extension("partial");
+
+    let response = reqwest::get(&image.source_url).await?;
+    if !response.status().is_success() {
+        bail!("Download returned HTTP {}", response.status());
+    }
+
+    let mut file = tokio::fs::File::create(&partial).await?;
+    let mut hasher = Sha256::new();
+    let mut written = 0u64;
+    let mut stream = response.bytes_stream();
+    let result: Result<()> = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+        Ok(())
+    }.await;
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(e);
+    }
+
+    let digest = format!("{:x}", hasher.finalize());
+    if digest != image.checksum {
+        let _ = tokio::fs::remove_file(&partial).await;
+        bail!("Checksum mismatch: expected {}, got {}", image.checksum, digest);
+    }
+    tokio::fs::rename(&partial, &dest).await?;
+    Ok(written)
+}
+
+// Drop cached files (and chunk indexes) from previous versions of an image, except versions
+// machines are still installed from
+async fn remove_stale_versions(image: &CustomImage) {
+    let dir: PathBuf = crate::api::artifact_base_dir().join(ARTIFACT_DIR).join(&image.name);
+    let mut versions = match crate::db::pinned_image_versions(&image.os_choice()).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            warn!("Not removing old versions of custom image '{}': failed to check which are in use: {}", image.name, e);
+            return;
+        }
+    };
+    if !versions.is_empty() {
+        info!("Keeping custom image '{}' versions {:?} cached for machines installed from them", image.name, versions);
+    }
+    versions.push(image.version.clone());
+    let keep = pinned_files(&versions);
+    let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !keep.contains(&file_name) {
+            if let Err(e) = tokio::fs::remove_file(entry.path()).await {
+                warn!("Failed to remove stale image file {:?}: {}", entry.path(), e);
+            }
+        }
+    }
+    release_chunks().await;
+}
+
+/// Free chunks that no custom image version uses any more.
+pub async fn release_chunks() {
+    if let Err(e) = crate::chunk_store::garbage_collect(ARTIFACT_DIR).await {
+        warn!("Failed to clean up the chunk store: {}", e);
+    }
+}
+
+// Write the image's template where the workflow preview looks for it, and install it in Tinkerbell
+async fn install_template(image: &CustomImage) -> Result<()> {
+    let base_url_bare = crate::os_templates::get_base_url_without_port()?;
+    let yaml = image.render_template(&base_url_bare);
+    crate::os_templates::save_local_template(&image.os_choice(), &yaml).await?;
+    let client = crate::tinkerbell::get_client().await
+        .map_err(|e| anyhow!("Failed to get Kubernetes client: {}", e))?;
+    crate::os_templates::apply_template_yaml(client, &image.os_choice(), &yaml).await
+}
+
+/// Fetch and verify an image into the artifact cache, then make it installable.
+pub async fn prefetch(name: &str, event_manager: &EventManager) -> Result<()> {
+    let Some(image) = crate::db::get_custom_image(name).await? else {
+        bail!("Custom image '{}' not found", name);
+    };
+    // Another prefetch of this version is already running
+    if !crate::db::claim_custom_image_download(&image.name, &image.version).await? {
+        return Ok(());
+    }
+
+    if let Some(status) = crate::storage::low_storage() {
+        let message = crate::storage::low_storage_message(&status);
+        crate::db::set_custom_image_status(&image.name, &image.version, CacheStatus::Failed, Some(&message)).await?;
+        bail!("Not prefetching '{}': {}", image.name, message);
+    }
+
+    info!("Prefetching custom image '{}' version {} from {}", image.name, image.version, image.source_url);
+    let outcome = async {
+        let bytes = download_verified(&image).await?;
+        if image.uses_delta_sync() {
+            crate::chunk_store::build_index(&image.artifact_path()).await?;
+        }
+        install_template(&image).await?;
+        Ok::<u64, anyhow::Error>(bytes)
+    }.await;
+    match outcome {
+        Ok(bytes) => {
+            info!("Custom image '{}' version {} cached ({} bytes)", image.name, image.version, bytes);
+            crate::db::set_custom_image_status(&image.name, &image.version, CacheStatus::Ready, None).await?;
+            remove_stale_versions(&image).await;
+            let _ = event_manager.send(format!("custom_image_ready:{}", image.name));
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to prefetch custom image '{}': {}", image.name, e);
+            crate::db::set_custom_image_status(&image.name, &image.version, CacheStatus::Failed, Some(&e.to_string())).await?;
+            let _ = event_manager.send(format!("custom_image_failed:{}", image.name));
+            Err(e)
+        }
+    }
+}
+
+// SHA-256 of a file, read in a blocking thread
+pub(crate) async fn hash_file(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }).await?
+}
+
+// Throw away a bad cached copy and download the image again in the background
+async fn redownload(image: &CustomImage, path: &Path, reason: &str) -> Result<()> {
+    VERIFIED.lock().unwrap().remove(path);
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove cached image file {:?}: {}", path, e);
+        }
+    }
+    crate::db::set_custom_image_status(&image.name, &image.version, CacheStatus::Failed, Some(reason)).await?;
+
+    let event_manager = crate::EVENT_MANAGER_REF.read().ok().and_then(|guard| guard.clone());
+    match event_manager {
+        Some(event_manager) => {
+            let _ = event_manager.send(format!("custom_image_failed:{}", image.name));
+            let name = image.name.clone();
+            tokio::spawn(async move {
+                let _ = prefetch(&name, &event_manager).await;
+            });
+        }
+        // The prefetch task picks failed images up on its next pass
+        None => warn!("Event manager not available; custom image '{}' will be re-downloaded by the prefetch task", image.name),
+    }
+    Ok(())
+}
+
+/// Check the cached file of a custom image against its catalog checksum before it's installed.
+/// Files are only re-hashed when their size or mtime changed since the last check. A missing
+/// or corrupt file is removed and downloaded again, and the install is refused meanwhile.
+/// Built-in OS choices pass through untouched.
+pub async fn verify_before_install(os_choice: &str) -> Result<()> {
+    let Some(name) = os_choice.strip_prefix(OS_CHOICE_PREFIX) else {
+        return Ok(());
+    };
+    let Some(image) = crate::db::get_custom_image(name).await? else {
+        bail!("Custom image '{}' is not registered", name);
+    };
+    if image.status != CacheStatus::Ready {
+        bail!("Custom image '{}' version {} is not cached yet (status: {})", image.name, image.version, image.status.as_str());
+    }
+
+    let path = crate::api::artifact_base_dir().join(image.artifact_path());
+    let metadata = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            let reason = format!("Cached file is missing ({}); re-downloading", e);
+            redownload(&image, &path, &reason).await?;
+            bail!("Custom image '{}': {}", image.name, reason);
+        }
+    };
+    let current = Verified::new(&metadata, &image.checksum);
+    if VERIFIED.lock().unwrap().get(&path) == Some(&current) {
+        return Ok(());
+    }
+
+    info!("Re-verifying cached custom image '{}' version {} before install", image.name, image.version);
+    let digest = hash_file(&path).await?;
+    if digest != image.checksum {
+        let reason = format!("Cached file failed checksum verification (expected {}, got {}); re-downloading", image.checksum, digest);
+        error!("Custom image '{}': {}", image.name, reason);
+        redownload(&image, &path, &reason).await?;
+        bail!("Custom image '{}': {}", image.name, reason);
+    }
+    VERIFIED.lock().unwrap().insert(path, current);
+    Ok(())
+}
+
+/// Register (or update to a new version) an image and start prefetching it in the background.
+pub async fn register(input: CustomImageInput, event_manager: Arc<EventManager>) -> Result<CustomImage> {
+    input.validate()?;
+    let created_at = crate::db::get_custom_image(&input.name).await?
+        .map(|existing| existing.created_at)
+        .unwrap_or_else(Utc::now);
+    let image = input.into_image(created_at);
+    crate::db::save_custom_image(&image).await?;
+    info!("Registered custom image '{}' version {}", image.name, image.version);
+
+    let name = image.name.clone();
+    tokio::spawn(async move {
+        let _ = prefetch(&name, &event_manager).await;
+    });
+    Ok(image)
+}
+
+/// Unregister an image and drop its cached files. Returns false if it wasn't registered.
+pub async fn remove(name: &str) -> Result<bool> {
+    if !crate::db::delete_custom_image(name).await? {
+        return Ok(false);
+    }
+    let dir = crate::api::artifact_base_dir().join(ARTIFACT_DIR).join(name);
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove cached files for custom image '{}': {}", name, e);
+        }
+    }
+    release_chunks().await;
+    Ok(true)
+}
+
+/// Periodically retry images that aren't cached, e.g. after a failed download or a restart mid-download.
+pub async fn start_prefetch_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        // Anything left "downloading" was interrupted by a restart
+        if let Err(e) = crate::db::reset_interrupted_custom_image_downloads().await {
+            error!("Failed to reset interrupted custom image downloads: {}", e);
+        }
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(PREFETCH_INTERVAL_SECS));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    match crate::db::list_custom_images().await {
+                        Ok(images) => {
+                            for image in images.into_iter().filter(|i| matches!(i.status, CacheStatus::Pending | CacheStatus::Failed)) {
+                                let _ = prefetch(&image.name, &event_manager).await;
+                            }
+                        }
+                        Err(e) => error!("Failed to list custom images for prefetch: {}", e),
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping custom image prefetch task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> CustomImageInput {
+        CustomImageInput {
+            name: "ubuntu-golden".to_string(),
+            display_name: Some("Ubuntu Golden".to_string()),
+            version: "2024.06.1".to_string(),
+            format: ImageFormat::Qcow2,
+            url: "https://images.example.com/ubuntu-golden.qcow2".to_string(),
+            checksum: "A".repeat(64),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_input() {
+        assert!(input().validate().is_ok());
+        assert!(CustomImageInput { name: "Ubuntu".to_string(), ..input() }.validate().is_err());
+        assert!(CustomImageInput { version: "../x".to_string(), ..input() }.validate().is_err());
+        assert!(CustomImageInput { url: "file:///etc/passwd".to_string(), ..input() }.validate().is_err());
+        assert!(CustomImageInput { checksum: "abc".to_string(), ..input() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_template_points_at_cached_image() {
+        let image = input().into_image(Utc::now());
+        assert_eq!(image.checksum, "a".repeat(64));
+        assert_eq!(image.os_choice(), "custom-ubuntu-golden");
+        assert_eq!(image.artifact_path(), "custom/ubuntu-golden/2024.06.1.qcow2");
+        let template = image.render_template("10.0.0.1");
+        assert!(template.contains("name: custom-ubuntu-golden"));
+        assert!(template.contains("IMG_URL: \"http://10.0.0.1:3000/ipxe/custom/ubuntu-golden/2024.06.1.qcow2\""));
+        assert!(template.contains("worker: \"{{.device_1}}\""));
+        assert!(template.contains("DEST_DISK: {{ index .Hardware.Disks 0 }}"));
+        assert!(!template.contains("INDEX_URL"));
+    }
+
+    #[test]
+    fn test_pinned_files_cover_both_formats() {
+        let files = pinned_files(&["2024.06.1".to_string()]);
+        assert!(files.contains("2024.06.1.qcow2"));
+        assert!(files.contains("2024.06.1.raw.chunks"));
+        assert!(!files.contains("2024.06.10.qcow2"));
+    }
+
+    #[test]
+    fn test_usage_groups_machines_by_version() {
+        let image = input().into_image(Utc::now());
+        let machine = |os_installed: Option<&str>| Machine {
+            mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+            os_installed: os_installed.map(str::to_string),
+            ..dragonfly_common::testing::machine()
+        };
+        let machines = vec![machine(None), machine(Some("Ubuntu 22.04")), machine(None)];
+        let install = |machine: &Machine, version: &str| MachineImage {
+            machine_id: machine.id,
+            os_choice: image.os_choice(),
+            version: Some(version.to_string()),
+            started_at: Utc::now(),
+            installed_at: Some(Utc::now()),
+        };
+        let installs = vec![install(&machines[0], "2024.05.3")];
+
+        let usage = summarize_usage(&machines, &installs, std::slice::from_ref(&image));
+        let summary: Vec<_> = usage.iter()
+            .map(|u| (u.os_choice.as_str(), u.version.as_deref(), u.current, u.machine_count))
+            .collect();
+        assert_eq!(summary, vec![
+            ("Ubuntu 22.04", None, false, 1),
+            ("custom-ubuntu-golden", Some("2024.05.3"), false, 1),
+            ("custom-ubuntu-golden", Some("2024.06.1"), true, 0),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_verification_is_invalidated_by_changes() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"image").unwrap();
+        let digest = hash_file(file.path()).await.unwrap();
+        assert_eq!(digest, format!("{:x}", Sha256::digest(b"image")));
+
+        let verified = Verified::new(&std::fs::metadata(file.path()).unwrap(), &digest);
+        assert_eq!(Verified::new(&std::fs::metadata(file.path()).unwrap(), &digest), verified);
+        assert_ne!(Verified::new(&std::fs::metadata(file.path()).unwrap(), "other"), verified);
+        std::io::Write::write_all(&mut file, b" corrupted").unwrap();
+        assert_ne!(Verified::new(&std::fs::metadata(file.path()).unwrap(), &digest), verified);
+    }
+
+    #[test]
+    fn test_raw_images_use_delta_sync() {
+        let image = CustomImageInput { format: ImageFormat::Raw, ..input() }.into_image(Utc::now());
+        let template = image.render_template("10.0.0.1");
+        assert!(template.contains("INDEX_URL: \"http://10.0.0.1:3000/ipxe/custom/ubuntu-golden/2024.06.1.raw.chunks\""));
+        assert!(template.contains("CHUNK_URL: \"http://10.0.0.1:3000/ipxe/chunks\""));
+        assert!(template.contains(r#"command: ["/bin/sh", "-c", "wget -qO /tmp/sync.sh \"$SCRIPT_URL\" && sh /tmp/sync.sh"]"#));
+        assert!(!template.contains("qemuimg2disk"));
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use sqlx::{Pool, Sqlite, SqlitePool, Row};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use std::str::FromStr;
+use std::time::Duration;
 use tokio::sync::OnceCell;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 use std::fs::{File, OpenOptions};
 use std::path::Path;
@@ -14,16 +17,20 @@ pub use dragonfly_common::models::{OsAssignmentRequest, RegisterResponse, ErrorR
 use crate::auth::{Credentials, Settings};
 use crate::tinkerbell::WorkflowInfo;
 
+// Bumped whenever a migration changes the database schema. init_db records it in the database's
+// user_version once migrations succeed, and /api/version reports what the database holds.
+pub const SCHEMA_VERSION: u32 = 1;
+
 // Global database pool
 static DB_POOL: OnceCell<Pool<Sqlite>> = OnceCell::const_new();
 
 // Initialize the database connection pool
 pub async fn init_db() -> Result<SqlitePool> {
-    // Create or open the SQLite database file
-    let db_path = "sqlite.db";
+    // Create or open the SQLite database file (storage.db_path / DRAGONFLY_DB_PATH)
+    let db_path = crate::config::get().storage.db_path.clone();
     
     // Check if the database file exists and create it if not
-    let db_exists = std::path::Path::new(db_path).exists();
+    let db_exists = std::path::Path::new(&db_path).exists();
     if !db_exists {
         info!("Database file doesn't exist, creating it");
     }
@@ -32,9 +39,7 @@ pub async fn init_db() -> Result<SqlitePool> {
     let database_url = format!("sqlite://{}?mode=rwc", db_path);
     
     // Connect to SQLite database
-    let pool = SqlitePool::connect(&database_url)
-        .await
-        .map_err(|e| anyhow!("Failed to connect to SQLite database at {}: {}", database_url, e))?;
+    let pool = setup_connection_pool(&database_url, &PoolSettings::from_env()).await?;
     
     // Initialize base tables for fresh installation
     create_base_tables(&pool).await?;
@@ -42,6 +47,7 @@ pub async fn init_db() -> Result<SqlitePool> {
     // Run migrations
     migrate_db(&pool).await?;
     migrate_add_proxmox_settings(&pool).await?;
+    record_schema_version(&pool).await?;
     
     // Store the pool globally - DB_POOL is previously defined as a OnceCell
     if let Err(e) = DB_POOL.set(pool.clone()) {
@@ -52,6 +58,153 @@ pub async fn init_db() -> Result<SqlitePool> {
     Ok(pool)
 }
 
+const JOURNAL_MODE_ENV_VAR: &str = "DRAGONFLY_DB_JOURNAL_MODE";
+const SYNCHRONOUS_ENV_VAR: &str = "DRAGONFLY_DB_SYNCHRONOUS";
+const BUSY_TIMEOUT_ENV_VAR: &str = "DRAGONFLY_DB_BUSY_TIMEOUT_MS";
+const MAX_CONNECTIONS_ENV_VAR: &str = "DRAGONFLY_DB_MAX_CONNECTIONS";
+const SLOW_QUERY_ENV_VAR: &str = "DRAGONFLY_DB_SLOW_QUERY_MS";
+
+// Slow queries kept for the stats endpoint
+const SLOW_QUERY_LOG_SIZE: usize = 50;
+
+/// SQLite pragmas and pool sizing. WAL lets readers carry on while a registration
+/// writes, and the busy timeout makes writers queue instead of failing with SQLITE_BUSY.
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub journal_mode: String,
+    pub synchronous: String,
+    pub busy_timeout_ms: u64,
+    pub max_connections: u32,
+    pub slow_query_ms: u64,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            journal_mode: "WAL".to_string(),
+            // NORMAL is durable across crashes in WAL mode and avoids an fsync per commit
+            synchronous: "NORMAL".to_string(),
+            busy_timeout_ms: 5000,
+            max_connections: 10,
+            slow_query_ms: 250,
+        }
+    }
+}
+
+impl PoolSettings {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |var: &str| crate::config::var(var).and_then(|v| v.parse::<u64>().ok());
+        Self {
+            journal_mode: crate::config::var(JOURNAL_MODE_ENV_VAR).unwrap_or(defaults.journal_mode),
+            synchronous: crate::config::var(SYNCHRONOUS_ENV_VAR).unwrap_or(defaults.synchronous),
+            busy_timeout_ms: parse(BUSY_TIMEOUT_ENV_VAR).unwrap_or(defaults.busy_timeout_ms),
+            max_connections: parse(MAX_CONNECTIONS_ENV_VAR)
+                .filter(|n| *n > 0)
+                .map_or(defaults.max_connections, |n| n as u32),
+            slow_query_ms: parse(SLOW_QUERY_ENV_VAR).unwrap_or(defaults.slow_query_ms),
+        }
+    }
+}
+
+static SLOW_QUERY_MS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(250);
+static SLOW_QUERIES_TOTAL: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static SLOW_QUERIES: std::sync::Mutex<std::collections::VecDeque<SlowQuery>> = std::sync::Mutex::new(std::collections::VecDeque::new());
+
+// Open the pool with the pragmas applied to every connection
+async fn setup_connection_pool(database_url: &str, settings: &PoolSettings) -> Result<SqlitePool> {
+    let journal_mode = SqliteJournalMode::from_str(&settings.journal_mode)
+        .map_err(|e| anyhow!("Invalid {} '{}': {}", JOURNAL_MODE_ENV_VAR, settings.journal_mode, e))?;
+    let synchronous = SqliteSynchronous::from_str(&settings.synchronous)
+        .map_err(|e| anyhow!("Invalid {} '{}': {}", SYNCHRONOUS_ENV_VAR, settings.synchronous, e))?;
+
+    let options = SqliteConnectOptions::from_str(database_url)?
+        .create_if_missing(true)
+        .journal_mode(journal_mode)
+        .synchronous(synchronous)
+        .busy_timeout(Duration::from_millis(settings.busy_timeout_ms));
+
+    SLOW_QUERY_MS.store(settings.slow_query_ms, std::sync::atomic::Ordering::Relaxed);
+    info!(
+        "Opening SQLite pool: journal_mode={}, synchronous={}, busy_timeout={}ms, max_connections={}",
+        settings.journal_mode, settings.synchronous, settings.busy_timeout_ms, settings.max_connections
+    );
+
+    SqlitePoolOptions::new()
+        .max_connections(settings.max_connections)
+        // Waiting for a connection should not outlast the busy timeout by much
+        .acquire_timeout(Duration::from_millis(settings.busy_timeout_ms.max(1000) * 2))
+        .connect_with(options)
+        .await
+        .map_err(|e| anyhow!("Failed to connect to SQLite database at {}: {}", database_url, e))
+}
+
+/// A database operation that took longer than the slow query threshold.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SlowQuery {
+    pub operation: String,
+    pub duration_ms: u64,
+    pub at: chrono::DateTime<Utc>,
+}
+
+// Time an operation, recording it if it was slow
+async fn timed<T>(operation: &str, fut: impl std::future::Future<Output = T>) -> T {
+    let started = std::time::Instant::now();
+    let result = fut.await;
+    let elapsed = started.elapsed();
+    if elapsed.as_millis() as u64 >= SLOW_QUERY_MS.load(std::sync::atomic::Ordering::Relaxed) {
+        warn!("Slow database operation {}: {}ms", operation, elapsed.as_millis());
+        SLOW_QUERIES_TOTAL.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut log = SLOW_QUERIES.lock().unwrap_or_else(|p| p.into_inner());
+        if log.len() >= SLOW_QUERY_LOG_SIZE {
+            log.pop_front();
+        }
+        log.push_back(SlowQuery { operation: operation.to_string(), duration_ms: elapsed.as_millis() as u64, at: Utc::now() });
+    }
+    result
+}
+
+/// Pool utilization and slow operations, for diagnosing lock contention.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DbStats {
+    pub pool_size: u32,
+    pub idle_connections: usize,
+    pub active_connections: u32,
+    pub max_connections: u32,
+    pub utilization_percent: f64,
+    pub journal_mode: String,
+    pub busy_timeout_ms: i64,
+    pub slow_query_threshold_ms: u64,
+    pub slow_queries_total: u64,
+    /// Most recent first
+    pub slow_queries: Vec<SlowQuery>,
+}
+
+pub async fn get_db_stats() -> Result<DbStats> {
+    let pool = get_pool().await?;
+    let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode").fetch_one(pool).await?;
+    let busy_timeout_ms: i64 = sqlx::query_scalar("PRAGMA busy_timeout").fetch_one(pool).await?;
+
+    let pool_size = pool.size();
+    let idle_connections = pool.num_idle();
+    let active_connections = pool_size.saturating_sub(idle_connections as u32);
+    let max_connections = pool.options().get_max_connections();
+    let slow_queries = SLOW_QUERIES.lock().unwrap_or_else(|p| p.into_inner()).iter().rev().cloned().collect();
+
+    Ok(DbStats {
+        pool_size,
+        idle_connections,
+        active_connections,
+        max_connections,
+        utilization_percent: if max_connections == 0 { 0.0 } else { active_connections as f64 * 100.0 / max_connections as f64 },
+        journal_mode,
+        busy_timeout_ms,
+        slow_query_threshold_ms: SLOW_QUERY_MS.load(std::sync::atomic::Ordering::Relaxed),
+        slow_queries_total: SLOW_QUERIES_TOTAL.load(std::sync::atomic::Ordering::Relaxed),
+        slow_queries,
+    })
+}
+
 // Create base tables for a fresh installation
 async fn create_base_tables(pool: &Pool<Sqlite>) -> Result<()> {
     // Check if machines table exists
@@ -180,6 +333,10 @@ pub async fn get_pool() -> Result<&'static Pool<Sqlite>> {
 
 // Register a new machine or update an existing one based on MAC address
 pub async fn register_machine(req: &RegisterRequest) -> Result<Uuid> {
+    timed("register_machine", register_machine_tx(req)).await
+}
+
+async fn register_machine_tx(req: &RegisterRequest) -> Result<Uuid> {
     let pool = get_pool().await?;
     let now = Utc::now();
     let now_str = now.to_rfc3339();
@@ -202,22 +359,27 @@ pub async fn register_machine(req: &RegisterRequest) -> Result<Uuid> {
         MachineStatus::AwaitingAssignment
     };
     let status_json = serde_json::to_string(&current_status)?;
+    let fingerprint_json = req.hardware_fingerprint.as_ref()
+        .filter(|fp| !fp.is_empty())
+        .map(serde_json::to_string)
+        .transpose()?;
 
     // Determine if this is being registered as a Proxmox host
     let is_proxmox_host = req.proxmox_node.is_some() && req.proxmox_vmid.is_none();
 
     // Begin transaction
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "register_machine");
     let mut tx = pool.begin().await?;
 
     // Check if machine exists by MAC address
-    let existing_machine_id: Option<String> = sqlx::query("SELECT id FROM machines WHERE mac_address = ?")
+    let existing_machine: Option<(String, String)> = sqlx::query("SELECT id, status FROM machines WHERE mac_address = ?")
         .bind(&req.mac_address)
         .fetch_optional(&mut *tx)
         .await?
-        .map(|row| row.get("id"));
+        .map(|row| (row.get("id"), row.get("status")));
 
-    let returned_id = match existing_machine_id {
-        Some(existing_id_str) => {
+    let (returned_id, previous_status) = match existing_machine {
+        Some((existing_id_str, previous_status)) => {
             // --- UPDATE existing machine --- 
             let existing_id = Uuid::parse_str(&existing_id_str)?;
             info!("Updating existing machine: ID={}, MAC={}", existing_id, req.mac_address);
@@ -241,7 +403,9 @@ pub async fn register_machine(req: &RegisterRequest) -> Result<Uuid> {
                     proxmox_vmid = ?,
                     proxmox_node = ?,
                     proxmox_cluster = ?, -- Added cluster
-                    is_proxmox_host = ? 
+                    is_proxmox_host = ?,
+                    hardware_fingerprint = COALESCE(?, hardware_fingerprint),
+                    tinkerbell_synced = FALSE -- Until the Tinkerbell hardware record is updated
                 WHERE id = ?
                 "#,
             )
@@ -261,11 +425,12 @@ pub async fn register_machine(req: &RegisterRequest) -> Result<Uuid> {
             .bind(req.proxmox_node.as_deref())
             .bind(req.proxmox_cluster.as_deref()) // Bind cluster
             .bind(is_proxmox_host) 
+            .bind(fingerprint_json.as_deref())
             .bind(existing_id.to_string())
             .execute(&mut *tx)
             .await?;
             
-            existing_id // Return the existing ID
+            (existing_id, Some(previous_status)) // Return the existing ID
         }
         None => {
             // --- INSERT new machine --- 
@@ -277,9 +442,10 @@ pub async fn register_machine(req: &RegisterRequest) -> Result<Uuid> {
                     id, mac_address, ip_address, hostname, status, os_choice, os_installed, 
                     disks, nameservers, memorable_name, created_at, updated_at, 
                     cpu_model, cpu_cores, total_ram_bytes, 
-                    proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host
+                    proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host,
+                    hardware_fingerprint, tinkerbell_synced
                 )
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, FALSE)
                 "#,
             )
             .bind(machine_id.to_string())
@@ -301,15 +467,17 @@ pub async fn register_machine(req: &RegisterRequest) -> Result<Uuid> {
             .bind(req.proxmox_node.as_deref())
             .bind(req.proxmox_cluster.as_deref()) // Bind cluster
             .bind(is_proxmox_host) 
+            .bind(fingerprint_json.as_deref())
             .execute(&mut *tx)
             .await?;
             
-            machine_id // Return the newly generated ID
+            (machine_id, None) // Return the newly generated ID
         }
     };
 
     // Commit transaction
     tx.commit().await?;
+    record_status_change(pool, &returned_id, previous_status.as_deref(), &status_json, &now_str).await;
     
     info!("Machine upsert complete: ID={}, MAC={}, IP={}, Hostname={:?}, ProxmoxNode={:?}, ProxmoxCluster={:?}, IsHost={}", 
           returned_id, req.mac_address, req.ip_address, req.hostname, req.proxmox_node, req.proxmox_cluster, is_proxmox_host);
@@ -329,7 +497,7 @@ pub async fn get_all_machines() -> Result<Vec<Machine>> {
             disks, nameservers, memorable_name, created_at, updated_at, bmc_credentials, 
             installation_progress, installation_step, last_deployment_duration, 
             cpu_model, cpu_cores, total_ram_bytes, 
-            proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host 
+            proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host, tinkerbell_synced
         FROM machines
         ORDER BY proxmox_cluster, is_proxmox_host DESC, hostname, memorable_name, mac_address
         "#,
@@ -362,7 +530,7 @@ pub async fn get_machine_by_id(id: &Uuid) -> Result<Option<Machine>> {
                disks, nameservers, memorable_name, created_at, updated_at, bmc_credentials, 
                installation_progress, installation_step, last_deployment_duration,
                cpu_model, cpu_cores, total_ram_bytes, 
-               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host
+               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host, tinkerbell_synced
         FROM machines 
         WHERE id = ?
         "#,
@@ -391,7 +559,7 @@ pub async fn get_machine_by_mac(mac_address: &str) -> Result<Option<Machine>> {
                disks, nameservers, memorable_name, created_at, updated_at, bmc_credentials, 
                installation_progress, installation_step, last_deployment_duration,
                cpu_model, cpu_cores, total_ram_bytes, 
-               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host
+               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host, tinkerbell_synced
         FROM machines 
         WHERE mac_address = ?
         "#,
@@ -420,7 +588,7 @@ pub async fn get_machine_by_proxmox_vmid(vmid: u32) -> Result<Option<Machine>> {
                disks, nameservers, memorable_name, created_at, updated_at, bmc_credentials, 
                installation_progress, installation_step, last_deployment_duration,
                cpu_model, cpu_cores, total_ram_bytes, 
-               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host
+               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host, tinkerbell_synced
         FROM machines 
         WHERE proxmox_vmid = ?
         "#,
@@ -500,6 +668,8 @@ pub async fn reimage_machine(id: &Uuid) -> Result<bool> {
     let now_str = now.to_rfc3339();
     
     // Set the machine status to InstallingOS
+    let status_json = serde_json::to_string(&MachineStatus::InstallingOS)?;
+    let previous_status = current_status(pool, id).await?;
     let result = sqlx::query(
         r#"
         UPDATE machines 
@@ -507,7 +677,7 @@ pub async fn reimage_machine(id: &Uuid) -> Result<bool> {
         WHERE id = ?
         "#,
     )
-    .bind(serde_json::to_string(&MachineStatus::InstallingOS)?)
+    .bind(&status_json)
     .bind(&now_str)
     .bind(id.to_string())
     .execute(pool)
@@ -515,6 +685,7 @@ pub async fn reimage_machine(id: &Uuid) -> Result<bool> {
     
     let success = result.rows_affected() > 0;
     if success {
+        record_status_change(pool, id, previous_status.as_deref(), &status_json, &now_str).await;
         info!("Reimage initiated for machine {}", id);
     } else {
         info!("No machine found with ID {} to reimage", id);
@@ -525,12 +696,17 @@ pub async fn reimage_machine(id: &Uuid) -> Result<bool> {
 
 // Update machine status
 pub async fn update_status(id: &Uuid, status: MachineStatus) -> Result<bool> {
+    timed("update_status", update_status_query(id, status)).await
+}
+
+async fn update_status_query(id: &Uuid, status: MachineStatus) -> Result<bool> {
     let pool = get_pool().await?;
     let now = Utc::now();
     let now_str = now.to_rfc3339();
     
     // Store the serialized enum value directly
     let status_json = serde_json::to_string(&status)?;
+    let previous_status = current_status(pool, id).await?;
     
     let result = sqlx::query(
         r#"
@@ -539,7 +715,7 @@ pub async fn update_status(id: &Uuid, status: MachineStatus) -> Result<bool> {
         WHERE id = ?
         "#,
     )
-    .bind(status_json)
+    .bind(&status_json)
     .bind(&now_str)
     .bind(id.to_string())
     .execute(pool)
@@ -547,6 +723,7 @@ pub async fn update_status(id: &Uuid, status: MachineStatus) -> Result<bool> {
     
     let success = result.rows_affected() > 0;
     if success {
+        record_status_change(pool, id, previous_status.as_deref(), &status_json, &now_str).await;
         info!("Status updated for machine {}: {:?}", id, status);
     } else {
         info!("No machine found with ID {} to update status", id);
@@ -563,6 +740,7 @@ pub async fn update_machine_status(id: Uuid, status: MachineStatus) -> Result<bo
     
     // Store the serialized enum value directly
     let status_json = serde_json::to_string(&status)?;
+    let previous_status = current_status(pool, &id).await?;
     
     let result = sqlx::query(
         r#"
@@ -571,7 +749,7 @@ pub async fn update_machine_status(id: Uuid, status: MachineStatus) -> Result<bo
         WHERE id = ?
         "#,
     )
-    .bind(status_json)
+    .bind(&status_json)
     .bind(&now_str)
     .bind(id.to_string())
     .execute(pool)
@@ -579,6 +757,7 @@ pub async fn update_machine_status(id: Uuid, status: MachineStatus) -> Result<bo
     
     let success = result.rows_affected() > 0;
     if success {
+        record_status_change(pool, &id, previous_status.as_deref(), &status_json, &now_str).await;
         info!("Machine status updated for {}: {:?}", id, status);
     } else {
         info!("No machine found with ID {} to update status", id);
@@ -794,7 +973,9 @@ fn parse_status(status_str: &str) -> MachineStatus {
         "AwaitingAssignment" => MachineStatus::AwaitingAssignment,
         "InstallingOS" => MachineStatus::InstallingOS,
         "Ready" => MachineStatus::Ready,
+        "BootedLive" | "Booted Live" => MachineStatus::BootedLive,
         "Offline" => MachineStatus::Offline,
+        "PoweredOff" | "Powered Off" => MachineStatus::PoweredOff,
         s if s.starts_with("Error: ") => {
             let message = s.trim_start_matches("Error: ").to_string();
             MachineStatus::Error(message)
@@ -816,6 +997,26 @@ fn parse_datetime(datetime_str: &str) -> chrono::DateTime<Utc> {
         .unwrap_or(dt)
 }
 
+// Mark the schema as migrated to this build's version. A database already migrated by a newer
+// server keeps its version.
+async fn record_schema_version(pool: &Pool<Sqlite>) -> Result<()> {
+    if read_schema_version(pool).await? < SCHEMA_VERSION {
+        // PRAGMA values can't be bound as parameters
+        sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION)).execute(pool).await?;
+    }
+    Ok(())
+}
+
+async fn read_schema_version(pool: &Pool<Sqlite>) -> Result<u32> {
+    let version: i64 = sqlx::query_scalar("PRAGMA user_version").fetch_one(pool).await?;
+    Ok(u32::try_from(version).unwrap_or(0))
+}
+
+// The schema version the database has been migrated to
+pub async fn schema_version() -> Result<u32> {
+    read_schema_version(get_pool().await?).await
+}
+
 // Apply database migrations
 async fn migrate_db(pool: &Pool<Sqlite>) -> Result<()> {
     // Check if os_installed column exists
@@ -1149,6 +1350,60 @@ async fn migrate_db(pool: &Pool<Sqlite>) -> Result<()> {
         info!("Backfill complete for is_proxmox_host. Updated {} rows.", backfill_result.rows_affected());
     }
     
+    // Add hardware_fingerprint column if it doesn't exist
+    let result = sqlx::query("SELECT COUNT(*) FROM pragma_table_info('machines') WHERE name = 'hardware_fingerprint'").fetch_one(pool).await?;
+    let column_exists: i64 = result.get(0);
+    if column_exists == 0 {
+        info!("Adding hardware_fingerprint column to machines table");
+        sqlx::query("ALTER TABLE machines ADD COLUMN hardware_fingerprint TEXT").execute(pool).await?;
+    }
+    
+    // Add Tinkerbell sync tracking columns if they don't exist; existing machines count as synced
+    for (column, definition) in [
+        ("tinkerbell_synced", "BOOLEAN DEFAULT TRUE NOT NULL"),
+        ("tinkerbell_sync_attempts", "INTEGER DEFAULT 0 NOT NULL"),
+        ("tinkerbell_sync_error", "TEXT"),
+        ("tinkerbell_sync_at", "TEXT"),
+    ] {
+        let result = sqlx::query("SELECT COUNT(*) FROM pragma_table_info('machines') WHERE name = ?")
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+        let column_exists: i64 = result.get(0);
+        if column_exists == 0 {
+            info!("Adding {} column to machines table", column);
+            sqlx::query(&format!("ALTER TABLE machines ADD COLUMN {} {}", column, definition)).execute(pool).await?;
+        }
+    }
+    
+    // Add retention columns to app_settings if they don't exist
+    for column in ["timing_retention_days", "event_retention_days", "job_history_retention_days", "sensor_retention_days"] {
+        let result = sqlx::query("SELECT COUNT(*) FROM pragma_table_info('app_settings') WHERE name = ?")
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+        let column_exists: i64 = result.get(0);
+        if column_exists == 0 {
+            info!("Adding {} column to app_settings table", column);
+            sqlx::query(&format!("ALTER TABLE app_settings ADD COLUMN {} INTEGER", column)).execute(pool).await?;
+        }
+    }
+    
+    // Add installed-system columns (timezone, NTP servers as JSON, locale), branding and the
+    // setup wizard's server settings to app_settings
+    for column in ["system_timezone", "system_ntp_servers", "system_locale", "branding_organization", "branding_support_contact", "branding_motd",
+                   "server_base_url", "server_network_interface", "server_artifact_dir"] {
+        let result = sqlx::query("SELECT COUNT(*) FROM pragma_table_info('app_settings') WHERE name = ?")
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+        let column_exists: i64 = result.get(0);
+        if column_exists == 0 {
+            info!("Adding {} column to app_settings table", column);
+            sqlx::query(&format!("ALTER TABLE app_settings ADD COLUMN {} TEXT", column)).execute(pool).await?;
+        }
+    }
+    
     Ok(())
 }
 
@@ -1176,6 +1431,69 @@ pub async fn delete_machine(id: &Uuid) -> Result<bool> {
     Ok(success)
 }
 
+// Record a successful Tinkerbell hardware sync
+pub async fn mark_tinkerbell_synced(id: &Uuid) -> Result<()> {
+    let pool = get_pool().await?;
+    sqlx::query(
+        r#"
+        UPDATE machines
+        SET tinkerbell_synced = TRUE, tinkerbell_sync_attempts = 0, tinkerbell_sync_error = NULL, tinkerbell_sync_at = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(Utc::now().to_rfc3339())
+    .bind(id.to_string())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Record a failed Tinkerbell hardware sync, leaving the machine queued for retry
+pub async fn mark_tinkerbell_sync_failed(id: &Uuid, error: &str) -> Result<()> {
+    let pool = get_pool().await?;
+    sqlx::query(
+        r#"
+        UPDATE machines
+        SET tinkerbell_synced = FALSE, tinkerbell_sync_attempts = tinkerbell_sync_attempts + 1,
+            tinkerbell_sync_error = ?, tinkerbell_sync_at = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(error)
+    .bind(Utc::now().to_rfc3339())
+    .bind(id.to_string())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Machines whose Tinkerbell hardware record still needs writing
+pub async fn list_pending_tinkerbell_syncs() -> Result<Vec<crate::tinkerbell_sync::PendingSync>> {
+    let pool = get_pool().await?;
+    let rows = sqlx::query(
+        r#"
+        SELECT id, tinkerbell_sync_attempts, tinkerbell_sync_error, tinkerbell_sync_at
+        FROM machines
+        WHERE tinkerbell_synced = FALSE
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut pending = Vec::new();
+    for row in rows {
+        let id: String = row.try_get("id")?;
+        let last_attempt_at: Option<String> = row.try_get("tinkerbell_sync_at")?;
+        pending.push(crate::tinkerbell_sync::PendingSync {
+            machine_id: Uuid::parse_str(&id)?,
+            attempts: row.try_get::<i64, _>("tinkerbell_sync_attempts")? as u32,
+            last_error: row.try_get("tinkerbell_sync_error")?,
+            last_attempt_at: last_attempt_at.as_deref().map(parse_datetime),
+        });
+    }
+    Ok(pending)
+}
+
 // Get admin credentials from database
 pub async fn get_admin_credentials() -> Result<Option<Credentials>> {
     let pool = get_pool().await?;
@@ -1210,6 +1528,7 @@ pub async fn save_admin_credentials(credentials: &Credentials) -> Result<()> {
     let now_str = now.to_rfc3339();
     
     // Use a transaction to ensure atomicity
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "save_admin_credentials");
     let mut tx = pool.begin().await?;
     
     // Check if credentials already exist
@@ -1292,7 +1611,12 @@ pub async fn get_app_settings() -> Result<Settings> {
     // Try to get settings
     let row = sqlx::query(
         r#"
-        SELECT require_login, default_os, setup_completed FROM app_settings WHERE id = 1
+        SELECT require_login, default_os, setup_completed,
+               timing_retention_days, event_retention_days, job_history_retention_days, sensor_retention_days,
+               system_timezone, system_ntp_servers, system_locale,
+               branding_organization, branding_support_contact, branding_motd,
+               server_base_url, server_network_interface, server_artifact_dir
+        FROM app_settings WHERE id = 1
         "#,
     )
     .fetch_optional(pool)
@@ -1307,6 +1631,35 @@ pub async fn get_app_settings() -> Result<Settings> {
         settings.default_os = row.get::<Option<String>, _>("default_os");
         settings.setup_completed = row.get::<bool, _>("setup_completed");
         
+        // Retention columns are NULL until first saved; keep defaults in that case
+        if let Some(days) = row.get::<Option<i64>, _>("timing_retention_days") {
+            settings.retention.timing_days = days.max(0) as u32;
+        }
+        if let Some(days) = row.get::<Option<i64>, _>("event_retention_days") {
+            settings.retention.event_days = days.max(0) as u32;
+        }
+        if let Some(days) = row.get::<Option<i64>, _>("job_history_retention_days") {
+            settings.retention.job_history_days = days.max(0) as u32;
+        }
+        if let Some(days) = row.get::<Option<i64>, _>("sensor_retention_days") {
+            settings.retention.sensor_days = days.max(0) as u32;
+        }
+        
+        settings.system.timezone = row.get::<Option<String>, _>("system_timezone");
+        settings.system.locale = row.get::<Option<String>, _>("system_locale");
+        if let Some(servers) = row.get::<Option<String>, _>("system_ntp_servers") {
+            settings.system.ntp_servers = serde_json::from_str(&servers).unwrap_or_default();
+        }
+        
+        settings.branding.organization = row.get::<Option<String>, _>("branding_organization");
+        settings.branding.support_contact = row.get::<Option<String>, _>("branding_support_contact");
+        settings.branding.motd_template = row.get::<Option<String>, _>("branding_motd");
+        crate::branding::set_current(&settings.branding);
+        
+        settings.server.base_url = row.get::<Option<String>, _>("server_base_url");
+        settings.server.network_interface = row.get::<Option<String>, _>("server_network_interface");
+        settings.server.artifact_dir = row.get::<Option<String>, _>("server_artifact_dir");
+        
         // Load admin credentials separately to populate those fields in the default settings struct
         // Note: This might introduce a small inconsistency if DB ops fail between here and AppState creation,
         // but it resolves the immediate panic. A better approach might involve restructuring Settings.
@@ -1348,23 +1701,118 @@ pub async fn save_app_settings(settings: &Settings) -> Result<()> {
     // Update existing settings or insert if they don't exist (upsert pattern)
     sqlx::query(
         r#"
-        INSERT INTO app_settings (id, require_login, default_os, setup_completed, created_at, updated_at)
-        VALUES (1, ?, ?, ?, ?, ?)
+        INSERT INTO app_settings (id, require_login, default_os, setup_completed,
+            timing_retention_days, event_retention_days, job_history_retention_days, sensor_retention_days,
+            system_timezone, system_ntp_servers, system_locale,
+            branding_organization, branding_support_contact, branding_motd,
+            server_base_url, server_network_interface, server_artifact_dir, created_at, updated_at)
+        VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         ON CONFLICT (id) DO UPDATE SET
         require_login = excluded.require_login,
         default_os = excluded.default_os,
         setup_completed = excluded.setup_completed,
+        timing_retention_days = excluded.timing_retention_days,
+        event_retention_days = excluded.event_retention_days,
+        job_history_retention_days = excluded.job_history_retention_days,
+        sensor_retention_days = excluded.sensor_retention_days,
+        system_timezone = excluded.system_timezone,
+        system_ntp_servers = excluded.system_ntp_servers,
+        system_locale = excluded.system_locale,
+        branding_organization = excluded.branding_organization,
+        branding_support_contact = excluded.branding_support_contact,
+        branding_motd = excluded.branding_motd,
+        server_base_url = excluded.server_base_url,
+        server_network_interface = excluded.server_network_interface,
+        server_artifact_dir = excluded.server_artifact_dir,
         updated_at = excluded.updated_at
         "#,
     )
     .bind(settings.require_login)
     .bind(&settings.default_os)
     .bind(settings.setup_completed)
+    .bind(settings.retention.timing_days as i64)
+    .bind(settings.retention.event_days as i64)
+    .bind(settings.retention.job_history_days as i64)
+    .bind(settings.retention.sensor_days as i64)
+    .bind(&settings.system.timezone)
+    .bind(serde_json::to_string(&settings.system.ntp_servers)?)
+    .bind(&settings.system.locale)
+    .bind(&settings.branding.organization)
+    .bind(&settings.branding.support_contact)
+    .bind(&settings.branding.motd_template)
+    .bind(&settings.server.base_url)
+    .bind(&settings.server.network_interface)
+    .bind(&settings.server.artifact_dir)
     .bind(&now_str)
     .bind(&now_str)
     .execute(pool)
     .await?;
     
+    crate::branding::set_current(&settings.branding);
+    Ok(())
+}
+
+// Save the setup wizard's answers and mark setup completed, all or nothing
+pub async fn complete_setup(credentials: &Credentials, setup: &crate::setup::Setup) -> Result<()> {
+    let pool = get_pool().await?;
+    // Both create their tables (and the settings row) if they don't exist yet
+    get_app_settings().await?;
+    ensure_notification_tables(pool).await?;
+    let now_str = Utc::now().to_rfc3339();
+    
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "complete_setup");
+    let mut tx = pool.begin().await?;
+    
+    let updated = sqlx::query(
+        r#"
+        UPDATE admin_credentials SET password_hash = ?, updated_at = ?
+        WHERE id = (SELECT id FROM admin_credentials ORDER BY id DESC LIMIT 1)
+        "#,
+    )
+    .bind(&credentials.password_hash)
+    .bind(&now_str)
+    .execute(&mut *tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        sqlx::query("INSERT INTO admin_credentials (username, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?)")
+            .bind(&credentials.username)
+            .bind(&credentials.password_hash)
+            .bind(&now_str)
+            .bind(&now_str)
+            .execute(&mut *tx)
+            .await?;
+    }
+    
+    sqlx::query(
+        r#"
+        UPDATE app_settings SET default_os = ?, server_base_url = ?, server_network_interface = ?,
+            server_artifact_dir = ?, setup_completed = 1, updated_at = ?
+        WHERE id = 1
+        "#,
+    )
+    .bind(&setup.default_os)
+    .bind(&setup.server.base_url)
+    .bind(&setup.server.network_interface)
+    .bind(&setup.server.artifact_dir)
+    .bind(&now_str)
+    .execute(&mut *tx)
+    .await?;
+    
+    if let Some(notifications) = &setup.notifications {
+        sqlx::query(
+            "INSERT INTO notification_settings (id, settings, updated_at) VALUES (1, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET
+                settings = excluded.settings,
+                updated_at = excluded.updated_at"
+        )
+        .bind(serde_json::to_string(notifications)?)
+        .bind(&now_str)
+        .execute(&mut *tx)
+        .await?;
+    }
+    
+    tx.commit().await?;
+    info!("Setup completed");
     Ok(())
 }
 
@@ -1427,6 +1875,8 @@ pub async fn update_machine(machine: &Machine) -> Result<bool> {
     let nameservers_json = serde_json::to_string(&machine.nameservers)?;
     let disks_json = serde_json::to_string(&machine.disks)?;
 
+    let previous_status = current_status(pool, &machine.id).await?;
+
     // Log the update attempt with detailed info, including hardware
     info!("Updating machine {} in database: status={:?}, cpu={:?}, cores={:?}, ram={:?}", 
           machine.id, machine.status, machine.cpu_model, machine.cpu_cores, machine.total_ram_bytes);
@@ -1474,6 +1924,9 @@ pub async fn update_machine(machine: &Machine) -> Result<bool> {
         Ok(result) => {
             let rows_affected = result.rows_affected();
             info!("Database update for machine {} affected {} rows", machine.id, rows_affected);
+            if rows_affected > 0 {
+                record_status_change(pool, &machine.id, previous_status.as_deref(), &status_json, &machine.updated_at.to_rfc3339()).await;
+            }
             Ok(rows_affected > 0)
         },
         Err(e) => {
@@ -1511,10 +1964,10 @@ pub async fn save_template_timing(template_name: &str, action_name: &str, durati
     
     // Create a plain SQL query to insert or update timing data
     let query = "
-        INSERT INTO template_timings (template_name, action_name, durations)
-        VALUES ($1, $2, $3)
+        INSERT INTO template_timings (template_name, action_name, durations, updated_at)
+        VALUES ($1, $2, $3, $4)
         ON CONFLICT (template_name, action_name) 
-        DO UPDATE SET durations = $3
+        DO UPDATE SET durations = $3, updated_at = $4
     ";
     
     // Execute the query
@@ -1522,6 +1975,7 @@ pub async fn save_template_timing(template_name: &str, action_name: &str, durati
         .bind(template_name)
         .bind(action_name)
         .bind(durations_json)
+        .bind(Utc::now().to_rfc3339())
         .execute(pool)
         .await?;
     
@@ -1584,9 +2038,59 @@ pub async fn init_timing_tables() -> Result<()> {
         .execute(pool)
         .await?;
     
+    // Add updated_at column (used for retention) if it doesn't exist
+    let result = sqlx::query("SELECT COUNT(*) FROM pragma_table_info('template_timings') WHERE name = 'updated_at'").fetch_one(pool).await?;
+    let column_exists: i64 = result.get(0);
+    if column_exists == 0 {
+        info!("Adding updated_at column to template_timings table");
+        sqlx::query("ALTER TABLE template_timings ADD COLUMN updated_at TEXT").execute(pool).await?;
+        // Existing rows count as fresh so they aren't purged on the first retention run
+        sqlx::query("UPDATE template_timings SET updated_at = ?")
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+    }
+    
+    // Create table for completed workflow history if it doesn't exist
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS completed_workflows (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            machine_id TEXT NOT NULL,
+            workflow_info TEXT NOT NULL,
+            completed_at DATETIME NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
     Ok(())
 }
 
+// Delete template timings that haven't been updated since the cutoff
+pub async fn purge_template_timings_before(cutoff: &chrono::DateTime<Utc>) -> Result<u64> {
+    let pool = get_pool().await?;
+    
+    let result = sqlx::query("DELETE FROM template_timings WHERE updated_at < ?")
+        .bind(cutoff.to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected())
+}
+
+// Delete completed workflow records older than the cutoff
+pub async fn purge_completed_workflows_before(cutoff: &chrono::DateTime<Utc>) -> Result<u64> {
+    let pool = get_pool().await?;
+    
+    // completed_at is stored with SQLite's datetime('now') format
+    let result = sqlx::query("DELETE FROM completed_workflows WHERE completed_at < ?")
+        .bind(cutoff.format("%Y-%m-%d %H:%M:%S").to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected())
+}
+
 // Get statistics about the template timing database
 pub async fn get_timing_database_stats() -> Result<(usize, usize, usize)> {
     let pool = get_pool().await?;
@@ -1672,62 +2176,4512 @@ pub async fn get_completed_workflow(machine_id: &Uuid) -> Result<Option<(Workflo
     }
 }
 
-// Get all machines with a specific status
-pub async fn get_machines_by_status(status: dragonfly_common::models::MachineStatus) -> Result<Vec<dragonfly_common::models::Machine>> {
+// Create the diagnostics bundle table if it doesn't exist
+async fn ensure_diagnostics_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS diagnostic_bundles (
+            id TEXT PRIMARY KEY,
+            machine_id TEXT NOT NULL,
+            bundle TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Store a diagnostics bundle collected after an install failure
+pub async fn save_diagnostics_bundle(bundle: &crate::diagnostics::DiagnosticsBundle) -> Result<()> {
     let pool = get_pool().await?;
+    ensure_diagnostics_table(pool).await?;
     
-    // Convert the status to a JSON string for comparison
-    let status_json = serde_json::to_string(&status)?;
+    let bundle_json = serde_json::to_string(bundle)?;
+    
+    sqlx::query(
+        "INSERT INTO diagnostic_bundles (id, machine_id, bundle, created_at) VALUES (?, ?, ?, ?)"
+    )
+    .bind(bundle.id.to_string())
+    .bind(bundle.machine_id.to_string())
+    .bind(bundle_json)
+    .bind(bundle.created_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// List diagnostics bundles for a machine, newest first
+pub async fn list_diagnostics_bundles(machine_id: &Uuid) -> Result<Vec<crate::diagnostics::DiagnosticsBundleSummary>> {
+    let pool = get_pool().await?;
+    ensure_diagnostics_table(pool).await?;
     
-    // Use regular query instead of query macro to avoid compile-time verification issues
     let rows = sqlx::query(
-        "SELECT * FROM machines WHERE status = ?"
+        "SELECT id, created_at FROM diagnostic_bundles WHERE machine_id = ? ORDER BY created_at DESC"
     )
-    .bind(status_json)
+    .bind(machine_id.to_string())
     .fetch_all(pool)
     .await?;
     
-    let mut machines = Vec::with_capacity(rows.len());
+    let mut bundles = Vec::new();
     for row in rows {
-        machines.push(map_row_to_machine_with_hardware(row)?);
+        let id_str: String = row.get(0);
+        let created_at: String = row.get(1);
+        let id = Uuid::parse_str(&id_str)?;
+        bundles.push(crate::diagnostics::DiagnosticsBundleSummary {
+            id,
+            machine_id: *machine_id,
+            created_at: parse_datetime(&created_at),
+            url: crate::diagnostics::bundle_url(machine_id, &id),
+        });
     }
     
-    Ok(machines)
+    Ok(bundles)
 }
 
-// NEW helper function to map a row including hardware info
-fn map_row_to_machine_with_hardware(row: sqlx::sqlite::SqliteRow) -> Result<Machine> {
-    use sqlx::Row;
-    
-    let id: String = row.try_get("id")?;
-    let mac_address: String = row.try_get("mac_address")?;
-    let status_str: String = row.try_get("status")?;
-    let disks_json: Option<String> = row.try_get("disks")?;
-    let nameservers_json: Option<String> = row.try_get("nameservers")?;
-    let bmc_credentials_json: Option<String> = row.try_get("bmc_credentials")?;
-    let last_deployment_duration: Option<i64> = row.try_get("last_deployment_duration").ok();
+// Get the raw JSON of a single diagnostics bundle
+pub async fn get_diagnostics_bundle(machine_id: &Uuid, bundle_id: &Uuid) -> Result<Option<String>> {
+    let pool = get_pool().await?;
+    ensure_diagnostics_table(pool).await?;
     
-    // Map hardware info (use try_get for Option types)
-    let cpu_model: Option<String> = row.try_get("cpu_model")?;
-    let cpu_cores_i64: Option<i64> = row.try_get("cpu_cores")?;
-    let cpu_cores: Option<u32> = cpu_cores_i64.map(|c| c as u32);
-    let total_ram_bytes_i64: Option<i64> = row.try_get("total_ram_bytes")?;
-    let total_ram_bytes: Option<u64> = total_ram_bytes_i64.map(|r| r as u64);
+    let row = sqlx::query(
+        "SELECT bundle FROM diagnostic_bundles WHERE id = ? AND machine_id = ?"
+    )
+    .bind(bundle_id.to_string())
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
     
-    // Map Proxmox specific fields
-    let proxmox_vmid_i64: Option<i64> = row.try_get("proxmox_vmid").ok();
-    let proxmox_vmid: Option<u32> = proxmox_vmid_i64.map(|vmid| vmid as u32);
-    let proxmox_node: Option<String> = row.try_get("proxmox_node").ok();
-    let memorable_name: Option<String> = row.try_get("memorable_name").ok();
-    let proxmox_cluster: Option<String> = row.try_get("proxmox_cluster").ok();
+    Ok(row.map(|row| row.get(0)))
+}
+
+// Delete diagnostics bundles older than the cutoff
+pub async fn purge_diagnostics_bundles_before(cutoff: &chrono::DateTime<Utc>) -> Result<u64> {
+    let pool = get_pool().await?;
+    ensure_diagnostics_table(pool).await?;
     
-    // Generate memorable name from MAC address if not already stored
-    let memorable_name = memorable_name.unwrap_or_else(|| 
-        dragonfly_common::mac_to_words::mac_to_words_safe(&mac_address)
-    );
+    let result = sqlx::query("DELETE FROM diagnostic_bundles WHERE created_at < ?")
+        .bind(cutoff.to_rfc3339())
+        .execute(pool)
+        .await?;
     
-    // Deserialize disks and nameservers from JSON or use empty vectors if null
-    let mut disks = if let Some(json) = disks_json {
+    Ok(result.rows_affected())
+}
+
+// Create the machine reports table if it doesn't exist (the files themselves are on disk)
+async fn ensure_machine_reports_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_reports (
+            id TEXT PRIMARY KEY,
+            machine_id TEXT NOT NULL,
+            report TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_machine_reports_machine ON machine_reports (machine_id, created_at)")
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn save_machine_report(report: &crate::machine_reports::MachineReport) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_reports_table(pool).await?;
+    
+    sqlx::query("INSERT INTO machine_reports (id, machine_id, report, created_at) VALUES (?, ?, ?, ?)")
+        .bind(report.id.to_string())
+        .bind(report.machine_id.to_string())
+        .bind(serde_json::to_string(report)?)
+        .bind(report.created_at.to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// A machine's uploaded reports, newest first
+pub async fn list_machine_reports(machine_id: &Uuid) -> Result<Vec<crate::machine_reports::MachineReport>> {
+    let pool = get_pool().await?;
+    ensure_machine_reports_table(pool).await?;
+    
+    let rows: Vec<String> = sqlx::query_scalar("SELECT report FROM machine_reports WHERE machine_id = ? ORDER BY created_at DESC")
+        .bind(machine_id.to_string())
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.iter().filter_map(|r| serde_json::from_str(r).ok()).collect())
+}
+
+pub async fn get_machine_report(machine_id: &Uuid, report_id: &Uuid) -> Result<Option<crate::machine_reports::MachineReport>> {
+    let pool = get_pool().await?;
+    ensure_machine_reports_table(pool).await?;
+    
+    let report: Option<String> = sqlx::query_scalar("SELECT report FROM machine_reports WHERE id = ? AND machine_id = ?")
+        .bind(report_id.to_string())
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(report.map(|r| serde_json::from_str(&r)).transpose()?)
+}
+
+pub async fn delete_machine_report(report_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_machine_reports_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM machine_reports WHERE id = ?")
+        .bind(report_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Reports uploaded before the cutoff, for retention
+pub async fn list_machine_reports_before(cutoff: &chrono::DateTime<Utc>) -> Result<Vec<crate::machine_reports::MachineReport>> {
+    let pool = get_pool().await?;
+    ensure_machine_reports_table(pool).await?;
+    
+    let rows: Vec<String> = sqlx::query_scalar("SELECT report FROM machine_reports WHERE created_at < ?")
+        .bind(cutoff.to_rfc3339())
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.iter().filter_map(|r| serde_json::from_str(r).ok()).collect())
+}
+
+// Find a machine registered under a different MAC whose hardware fingerprint matches
+pub async fn find_machine_by_fingerprint(
+    fingerprint: &dragonfly_common::models::HardwareFingerprint,
+    exclude_mac: &str,
+) -> Result<Option<Uuid>> {
+    let pool = get_pool().await?;
+    
+    let rows = sqlx::query(
+        "SELECT id, hardware_fingerprint FROM machines WHERE hardware_fingerprint IS NOT NULL AND mac_address != ?"
+    )
+    .bind(exclude_mac)
+    .fetch_all(pool)
+    .await?;
+    
+    for row in rows {
+        let stored: String = row.get("hardware_fingerprint");
+        let stored: dragonfly_common::models::HardwareFingerprint = match serde_json::from_str(&stored) {
+            Ok(fp) => fp,
+            Err(_) => continue,
+        };
+        if stored.matches(fingerprint) {
+            let id: String = row.get("id");
+            return Ok(Some(Uuid::parse_str(&id)?));
+        }
+    }
+    
+    Ok(None)
+}
+
+// Record the hardware fingerprint reported by a machine's agent
+pub async fn update_hardware_fingerprint(
+    id: &Uuid,
+    fingerprint: &dragonfly_common::models::HardwareFingerprint,
+) -> Result<bool> {
+    let pool = get_pool().await?;
+    
+    let result = sqlx::query("UPDATE machines SET hardware_fingerprint = ? WHERE id = ?")
+        .bind(serde_json::to_string(fingerprint)?)
+        .bind(id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Get the hardware fingerprint last reported for a machine
+pub async fn get_hardware_fingerprint(id: &Uuid) -> Result<Option<dragonfly_common::models::HardwareFingerprint>> {
+    let pool = get_pool().await?;
+    
+    let row = sqlx::query("SELECT hardware_fingerprint FROM machines WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    match row.and_then(|row| row.get::<Option<String>, _>("hardware_fingerprint")) {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+// Point an existing machine at a new NIC, keeping its ID, name and history
+pub async fn update_machine_identity(id: &Uuid, mac_address: &str, ip_address: &str) -> Result<bool> {
+    let pool = get_pool().await?;
+    
+    let result = sqlx::query(
+        "UPDATE machines SET mac_address = ?, ip_address = ?, updated_at = ? WHERE id = ?"
+    )
+    .bind(mac_address)
+    .bind(ip_address)
+    .bind(Utc::now().to_rfc3339())
+    .bind(id.to_string())
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the pending merge table if it doesn't exist
+async fn ensure_pending_merges_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS pending_merges (
+            id TEXT PRIMARY KEY,
+            existing_machine_id TEXT NOT NULL,
+            mac_address TEXT NOT NULL UNIQUE,
+            request TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Store (or refresh) a registration that looks like a known machine with a new NIC
+pub async fn save_pending_merge(merge: &crate::dedupe::PendingMerge) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_pending_merges_table(pool).await?;
+    
+    let request_json = serde_json::to_string(&merge.request)?;
+    
+    sqlx::query(
+        "INSERT INTO pending_merges (id, existing_machine_id, mac_address, request, created_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(mac_address) DO UPDATE SET
+            existing_machine_id = excluded.existing_machine_id,
+            request = excluded.request"
+    )
+    .bind(merge.id.to_string())
+    .bind(merge.existing_machine_id.to_string())
+    .bind(&merge.request.mac_address)
+    .bind(request_json)
+    .bind(merge.created_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn row_to_pending_merge(row: &sqlx::sqlite::SqliteRow) -> Result<crate::dedupe::PendingMerge> {
+    let id: String = row.get("id");
+    let existing_machine_id: String = row.get("existing_machine_id");
+    let request: String = row.get("request");
+    let created_at: String = row.get("created_at");
+    
+    Ok(crate::dedupe::PendingMerge {
+        id: Uuid::parse_str(&id)?,
+        existing_machine_id: Uuid::parse_str(&existing_machine_id)?,
+        request: serde_json::from_str(&request)?,
+        created_at: chrono::DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+    })
+}
+
+// List merges awaiting an admin decision, oldest first
+pub async fn list_pending_merges() -> Result<Vec<crate::dedupe::PendingMerge>> {
+    let pool = get_pool().await?;
+    ensure_pending_merges_table(pool).await?;
+    
+    let rows = sqlx::query("SELECT * FROM pending_merges ORDER BY created_at ASC")
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter().map(row_to_pending_merge).collect()
+}
+
+// Get a pending merge by ID
+pub async fn get_pending_merge(id: &Uuid) -> Result<Option<crate::dedupe::PendingMerge>> {
+    let pool = get_pool().await?;
+    ensure_pending_merges_table(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM pending_merges WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_pending_merge).transpose()
+}
+
+// Get the pending merge for a MAC address, if its registration is on hold
+pub async fn get_pending_merge_by_mac(mac_address: &str) -> Result<Option<crate::dedupe::PendingMerge>> {
+    let pool = get_pool().await?;
+    ensure_pending_merges_table(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM pending_merges WHERE mac_address = ?")
+        .bind(mac_address)
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_pending_merge).transpose()
+}
+
+// Remove a pending merge once it has been approved or rejected
+pub async fn delete_pending_merge(id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_pending_merges_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM pending_merges WHERE id = ?")
+        .bind(id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the Helm values override table if it doesn't exist
+async fn ensure_component_values_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS component_values (
+            component TEXT PRIMARY KEY,
+            settings TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Saved Helm values overrides, keyed by component name
+pub async fn get_component_values() -> Result<std::collections::HashMap<String, crate::components::ComponentValues>> {
+    let pool = get_pool().await?;
+    ensure_component_values_table(pool).await?;
+    
+    let rows = sqlx::query("SELECT component, settings FROM component_values")
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter()
+        .map(|row| {
+            let component: String = row.get("component");
+            let settings: String = row.get("settings");
+            Ok((component, serde_json::from_str(&settings)?))
+        })
+        .collect()
+}
+
+// Store the Helm values overrides for one component
+pub async fn save_component_values(component: &str, values: &crate::components::ComponentValues) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_component_values_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO component_values (component, settings, updated_at)
+         VALUES (?, ?, ?)
+         ON CONFLICT(component) DO UPDATE SET
+            settings = excluded.settings,
+            updated_at = excluded.updated_at"
+    )
+    .bind(component)
+    .bind(serde_json::to_string(values)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Create the OS policy table if it doesn't exist
+async fn ensure_os_policies_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS os_policies (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            priority INTEGER NOT NULL,
+            os_choice TEXT NOT NULL,
+            enabled BOOLEAN NOT NULL,
+            matcher TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn row_to_os_policy(row: &sqlx::sqlite::SqliteRow) -> Result<crate::os_policy::OsPolicy> {
+    let id: String = row.get("id");
+    let matcher: String = row.get("matcher");
+    let created_at: String = row.get("created_at");
+    
+    Ok(crate::os_policy::OsPolicy {
+        id: Uuid::parse_str(&id)?,
+        name: row.get("name"),
+        priority: row.get::<i64, _>("priority") as i32,
+        os_choice: row.get("os_choice"),
+        enabled: row.get("enabled"),
+        matcher: serde_json::from_str(&matcher)?,
+        created_at: chrono::DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+    })
+}
+
+// List OS policies in evaluation order
+pub async fn list_os_policies() -> Result<Vec<crate::os_policy::OsPolicy>> {
+    let pool = get_pool().await?;
+    ensure_os_policies_table(pool).await?;
+    
+    let rows = sqlx::query("SELECT * FROM os_policies")
+        .fetch_all(pool)
+        .await?;
+    
+    let mut policies = rows.iter().map(row_to_os_policy).collect::<Result<Vec<_>>>()?;
+    crate::os_policy::sort_policies(&mut policies);
+    Ok(policies)
+}
+
+// Get an OS policy by ID
+pub async fn get_os_policy(id: &Uuid) -> Result<Option<crate::os_policy::OsPolicy>> {
+    let pool = get_pool().await?;
+    ensure_os_policies_table(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM os_policies WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_os_policy).transpose()
+}
+
+// Create or replace an OS policy
+pub async fn save_os_policy(policy: &crate::os_policy::OsPolicy) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_os_policies_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO os_policies (id, name, priority, os_choice, enabled, matcher, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name,
+            priority = excluded.priority,
+            os_choice = excluded.os_choice,
+            enabled = excluded.enabled,
+            matcher = excluded.matcher"
+    )
+    .bind(policy.id.to_string())
+    .bind(&policy.name)
+    .bind(policy.priority as i64)
+    .bind(&policy.os_choice)
+    .bind(policy.enabled)
+    .bind(serde_json::to_string(&policy.matcher)?)
+    .bind(policy.created_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Delete an OS policy
+pub async fn delete_os_policy(id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_os_policies_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM os_policies WHERE id = ?")
+        .bind(id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the boot menu selection table if it doesn't exist
+async fn ensure_boot_selections_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS boot_menu_selections (
+            mac_address TEXT PRIMARY KEY,
+            choice TEXT NOT NULL,
+            selected_at TEXT NOT NULL,
+            machine_id TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Record what was picked from the boot menu, replacing any earlier pick for the MAC
+pub async fn save_boot_selection(mac_address: &str, choice: &str) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_boot_selections_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO boot_menu_selections (mac_address, choice, selected_at, machine_id)
+         VALUES (?, ?, ?, NULL)
+         ON CONFLICT(mac_address) DO UPDATE SET
+            choice = excluded.choice,
+            selected_at = excluded.selected_at,
+            machine_id = NULL"
+    )
+    .bind(mac_address.to_lowercase())
+    .bind(choice)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Claim a boot menu selection not yet applied to a machine, marking it applied to this one
+pub async fn take_boot_selection(mac_address: &str, machine_id: &Uuid) -> Result<Option<String>> {
+    let pool = get_pool().await?;
+    ensure_boot_selections_table(pool).await?;
+    
+    let row = sqlx::query(
+        "UPDATE boot_menu_selections SET machine_id = ?
+         WHERE mac_address = ? AND machine_id IS NULL
+         RETURNING choice"
+    )
+    .bind(machine_id.to_string())
+    .bind(mac_address.to_lowercase())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.map(|row| row.get("choice")))
+}
+
+// List boot menu selections, newest first
+pub async fn list_boot_selections() -> Result<Vec<crate::boot_menu::BootSelection>> {
+    let pool = get_pool().await?;
+    ensure_boot_selections_table(pool).await?;
+    
+    let rows = sqlx::query("SELECT * FROM boot_menu_selections ORDER BY selected_at DESC")
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter()
+        .map(|row| {
+            let selected_at: String = row.get("selected_at");
+            let machine_id: Option<String> = row.get("machine_id");
+            Ok(crate::boot_menu::BootSelection {
+                mac_address: row.get("mac_address"),
+                choice: row.get("choice"),
+                selected_at: chrono::DateTime::parse_from_rfc3339(&selected_at)?.with_timezone(&Utc),
+                machine_id: machine_id.map(|id| Uuid::parse_str(&id)).transpose()?,
+            })
+        })
+        .collect()
+}
+
+// Create the diagnostic boot table if it doesn't exist
+async fn ensure_diagnostic_boots_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS diagnostic_boots (
+            id TEXT PRIMARY KEY,
+            machine_id TEXT NOT NULL,
+            target TEXT NOT NULL,
+            status TEXT NOT NULL,
+            requested_at TEXT NOT NULL,
+            booted_at TEXT,
+            completed_at TEXT,
+            report TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn row_to_diagnostic_boot(row: &sqlx::sqlite::SqliteRow) -> Result<crate::diagnostic_boot::DiagnosticBoot> {
+    use crate::diagnostic_boot::{DiagnosticBoot, DiagnosticBootStatus};
+    
+    let parse_time = |value: Option<String>| -> Result<Option<chrono::DateTime<Utc>>> {
+        Ok(value.map(|v| chrono::DateTime::parse_from_rfc3339(&v)).transpose()?.map(|t| t.with_timezone(&Utc)))
+    };
+    let id: String = row.get("id");
+    let machine_id: String = row.get("machine_id");
+    let status: String = row.get("status");
+    let report: Option<String> = row.get("report");
+    Ok(DiagnosticBoot {
+        id: Uuid::parse_str(&id)?,
+        machine_id: Uuid::parse_str(&machine_id)?,
+        target: row.get("target"),
+        status: DiagnosticBootStatus::parse(&status)
+            .ok_or_else(|| anyhow!("Unknown diagnostic boot status '{}'", status))?,
+        requested_at: parse_time(Some(row.get("requested_at")))?.unwrap_or_else(Utc::now),
+        booted_at: parse_time(row.get("booted_at"))?,
+        completed_at: parse_time(row.get("completed_at"))?,
+        report: report.map(|r| serde_json::from_str(&r)).transpose()?,
+    })
+}
+
+// Request a diagnostics boot, replacing any request the machine hasn't booted yet
+pub async fn create_diagnostic_boot(boot: &crate::diagnostic_boot::DiagnosticBoot) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_diagnostic_boots_table(pool).await?;
+    
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "create_diagnostic_boot");
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE diagnostic_boots SET status = 'cancelled' WHERE machine_id = ? AND status = 'pending'")
+        .bind(boot.machine_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO diagnostic_boots (id, machine_id, target, status, requested_at)
+         VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(boot.id.to_string())
+    .bind(boot.machine_id.to_string())
+    .bind(&boot.target)
+    .bind(boot.status.as_str())
+    .bind(boot.requested_at.to_rfc3339())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    
+    Ok(())
+}
+
+// Claim the machine's pending diagnostics boot, marking it booted so it's only served once.
+// A request that has sat too long is expired instead.
+pub async fn take_pending_diagnostic_boot(machine_id: &Uuid) -> Result<Option<crate::diagnostic_boot::DiagnosticBoot>> {
+    let pool = get_pool().await?;
+    ensure_diagnostic_boots_table(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM diagnostic_boots WHERE machine_id = ? AND status = 'pending'")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let mut boot = row_to_diagnostic_boot(&row)?;
+    let now = Utc::now();
+    
+    if boot.is_expired(now) {
+        sqlx::query("UPDATE diagnostic_boots SET status = 'expired' WHERE id = ?")
+            .bind(boot.id.to_string())
+            .execute(pool)
+            .await?;
+        return Ok(None);
+    }
+    
+    let result = sqlx::query("UPDATE diagnostic_boots SET status = 'booted', booted_at = ? WHERE id = ? AND status = 'pending'")
+        .bind(now.to_rfc3339())
+        .bind(boot.id.to_string())
+        .execute(pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        // Cancelled or claimed in the meantime
+        return Ok(None);
+    }
+    boot.status = crate::diagnostic_boot::DiagnosticBootStatus::Booted;
+    boot.booted_at = Some(now);
+    Ok(Some(boot))
+}
+
+// Attach a report to the machine's most recently booted diagnostics run
+pub async fn complete_diagnostic_boot(
+    machine_id: &Uuid,
+    report: &dragonfly_common::models::HardwareDiagnosticsReport,
+) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_diagnostic_boots_table(pool).await?;
+    
+    let result = sqlx::query(
+        "UPDATE diagnostic_boots SET status = 'completed', completed_at = ?, report = ?
+         WHERE id = (
+            SELECT id FROM diagnostic_boots
+            WHERE machine_id = ? AND status = 'booted'
+            ORDER BY booted_at DESC LIMIT 1
+         )"
+    )
+    .bind(Utc::now().to_rfc3339())
+    .bind(serde_json::to_string(report)?)
+    .bind(machine_id.to_string())
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Cancel the machine's pending diagnostics boot, if any
+pub async fn cancel_diagnostic_boot(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_diagnostic_boots_table(pool).await?;
+    
+    let result = sqlx::query("UPDATE diagnostic_boots SET status = 'cancelled' WHERE machine_id = ? AND status = 'pending'")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// List a machine's diagnostics boots, newest first
+pub async fn list_diagnostic_boots(machine_id: &Uuid) -> Result<Vec<crate::diagnostic_boot::DiagnosticBoot>> {
+    let pool = get_pool().await?;
+    ensure_diagnostic_boots_table(pool).await?;
+    
+    let rows = sqlx::query("SELECT * FROM diagnostic_boots WHERE machine_id = ? ORDER BY requested_at DESC")
+        .bind(machine_id.to_string())
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter().map(row_to_diagnostic_boot).collect()
+}
+
+// Create the custom image registry table if it doesn't exist
+async fn ensure_custom_images_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS custom_images (
+            name TEXT PRIMARY KEY,
+            display_name TEXT NOT NULL,
+            version TEXT NOT NULL,
+            format TEXT NOT NULL,
+            source_url TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            metadata TEXT NOT NULL,
+            status TEXT NOT NULL,
+            status_message TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn row_to_custom_image(row: &sqlx::sqlite::SqliteRow) -> Result<crate::custom_images::CustomImage> {
+    use crate::custom_images::{CacheStatus, CustomImage, ImageFormat};
+    
+    let format: String = row.get("format");
+    let status: String = row.get("status");
+    let metadata: String = row.get("metadata");
+    let created_at: String = row.get("created_at");
+    let updated_at: String = row.get("updated_at");
+    Ok(CustomImage {
+        name: row.get("name"),
+        display_name: row.get("display_name"),
+        version: row.get("version"),
+        format: ImageFormat::parse(&format).ok_or_else(|| anyhow!("Unknown image format '{}'", format))?,
+        source_url: row.get("source_url"),
+        checksum: row.get("checksum"),
+        metadata: serde_json::from_str(&metadata)?,
+        status: CacheStatus::parse(&status).ok_or_else(|| anyhow!("Unknown image status '{}'", status))?,
+        status_message: row.get("status_message"),
+        created_at: chrono::DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+        updated_at: chrono::DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Utc),
+    })
+}
+
+// List registered custom images by name
+pub async fn list_custom_images() -> Result<Vec<crate::custom_images::CustomImage>> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    let rows = sqlx::query("SELECT * FROM custom_images ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter().map(row_to_custom_image).collect()
+}
+
+pub async fn get_custom_image(name: &str) -> Result<Option<crate::custom_images::CustomImage>> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM custom_images WHERE name = ?")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_custom_image).transpose()
+}
+
+// Insert or replace a custom image registration
+pub async fn save_custom_image(image: &crate::custom_images::CustomImage) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO custom_images (name, display_name, version, format, source_url, checksum, metadata, status, status_message, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(name) DO UPDATE SET
+            display_name = excluded.display_name,
+            version = excluded.version,
+            format = excluded.format,
+            source_url = excluded.source_url,
+            checksum = excluded.checksum,
+            metadata = excluded.metadata,
+            status = excluded.status,
+            status_message = excluded.status_message,
+            updated_at = excluded.updated_at"
+    )
+    .bind(&image.name)
+    .bind(&image.display_name)
+    .bind(&image.version)
+    .bind(image.format.as_str())
+    .bind(&image.source_url)
+    .bind(&image.checksum)
+    .bind(serde_json::to_string(&image.metadata)?)
+    .bind(image.status.as_str())
+    .bind(&image.status_message)
+    .bind(image.created_at.to_rfc3339())
+    .bind(image.updated_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Mark an image version as downloading, unless a download of it is already running
+pub async fn claim_custom_image_download(name: &str, version: &str) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    let result = sqlx::query(
+        "UPDATE custom_images SET status = 'downloading', status_message = NULL, updated_at = ?
+         WHERE name = ? AND version = ? AND status != 'downloading'"
+    )
+    .bind(Utc::now().to_rfc3339())
+    .bind(name)
+    .bind(version)
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Record the outcome of a download. Ignored if the image moved on to a newer version meanwhile.
+pub async fn set_custom_image_status(
+    name: &str,
+    version: &str,
+    status: crate::custom_images::CacheStatus,
+    message: Option<&str>,
+) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    sqlx::query("UPDATE custom_images SET status = ?, status_message = ?, updated_at = ? WHERE name = ? AND version = ?")
+        .bind(status.as_str())
+        .bind(message)
+        .bind(Utc::now().to_rfc3339())
+        .bind(name)
+        .bind(version)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Downloads can't survive a restart; put them back in the queue
+pub async fn reset_interrupted_custom_image_downloads() -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    sqlx::query("UPDATE custom_images SET status = 'pending' WHERE status = 'downloading'")
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn delete_custom_image(name: &str) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_custom_images_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM custom_images WHERE name = ?")
+        .bind(name)
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the desired machine state table if it doesn't exist
+async fn ensure_desired_states_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS desired_states (
+            machine_id TEXT PRIMARY KEY,
+            os_choice TEXT,
+            hostname TEXT,
+            tags TEXT,
+            boot_profile TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn row_to_desired_state(row: &sqlx::sqlite::SqliteRow) -> Result<crate::desired_state::DesiredState> {
+    use crate::desired_state::{BootProfile, DesiredState};
+    
+    let machine_id: String = row.get("machine_id");
+    let tags: Option<String> = row.get("tags");
+    let boot_profile: String = row.get("boot_profile");
+    let updated_at: String = row.get("updated_at");
+    Ok(DesiredState {
+        machine_id: Uuid::parse_str(&machine_id)?,
+        os_choice: row.get("os_choice"),
+        hostname: row.get("hostname"),
+        tags: tags.as_deref().map(serde_json::from_str).transpose()?,
+        boot_profile: BootProfile::parse(&boot_profile).ok_or_else(|| anyhow!("Unknown boot profile '{}'", boot_profile))?,
+        updated_at: chrono::DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Utc),
+    })
+}
+
+pub async fn get_desired_state(machine_id: &Uuid) -> Result<Option<crate::desired_state::DesiredState>> {
+    let pool = get_pool().await?;
+    ensure_desired_states_table(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM desired_states WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_desired_state).transpose()
+}
+
+pub async fn list_desired_states() -> Result<Vec<crate::desired_state::DesiredState>> {
+    let pool = get_pool().await?;
+    ensure_desired_states_table(pool).await?;
+    
+    // Skip states left behind by deleted machines
+    let rows = sqlx::query("SELECT d.* FROM desired_states d JOIN machines m ON m.id = d.machine_id")
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter().map(row_to_desired_state).collect()
+}
+
+// Insert or replace a machine's desired state
+pub async fn save_desired_state(state: &crate::desired_state::DesiredState) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_desired_states_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO desired_states (machine_id, os_choice, hostname, tags, boot_profile, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            os_choice = excluded.os_choice,
+            hostname = excluded.hostname,
+            tags = excluded.tags,
+            boot_profile = excluded.boot_profile,
+            updated_at = excluded.updated_at"
+    )
+    .bind(state.machine_id.to_string())
+    .bind(&state.os_choice)
+    .bind(&state.hostname)
+    .bind(state.tags.as_ref().map(serde_json::to_string).transpose()?)
+    .bind(state.boot_profile.as_str())
+    .bind(state.updated_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn delete_desired_state(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_desired_states_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM desired_states WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the per-machine provisioning config table if it doesn't exist
+async fn ensure_provisioning_configs_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS provisioning_configs (
+            machine_id TEXT PRIMARY KEY,
+            cloud_init TEXT,
+            partitioning TEXT,
+            kernel_args TEXT,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    // Tables created before kernel args were stored don't have the column
+    let has_kernel_args: i64 = sqlx::query(
+        "SELECT COUNT(*) FROM pragma_table_info('provisioning_configs') WHERE name = 'kernel_args'"
+    )
+    .fetch_one(pool)
+    .await?
+    .get(0);
+    if has_kernel_args == 0 {
+        sqlx::query("ALTER TABLE provisioning_configs ADD COLUMN kernel_args TEXT")
+            .execute(pool)
+            .await?;
+    }
+    
+    Ok(())
+}
+
+pub async fn get_provisioning_config(machine_id: &Uuid) -> Result<Option<crate::definition::ProvisioningConfig>> {
+    let pool = get_pool().await?;
+    ensure_provisioning_configs_table(pool).await?;
+    
+    let row = sqlx::query("SELECT cloud_init, partitioning, kernel_args FROM provisioning_configs WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(row.map(|row| crate::definition::ProvisioningConfig {
+        cloud_init: row.get("cloud_init"),
+        partitioning: row.get("partitioning"),
+        kernel_args: row.get("kernel_args"),
+    }))
+}
+
+// Insert or replace a machine's provisioning config
+pub async fn save_provisioning_config(machine_id: &Uuid, config: &crate::definition::ProvisioningConfig) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_provisioning_configs_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO provisioning_configs (machine_id, cloud_init, partitioning, kernel_args, updated_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            cloud_init = excluded.cloud_init,
+            partitioning = excluded.partitioning,
+            kernel_args = excluded.kernel_args,
+            updated_at = excluded.updated_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(&config.cloud_init)
+    .bind(&config.partitioning)
+    .bind(&config.kernel_args)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Create the table of machines that boot through the signed shim/GRUB chain
+async fn ensure_secure_boot_machines_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS secure_boot_machines (
+            machine_id TEXT PRIMARY KEY,
+            enabled_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn is_secure_boot_enabled(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_secure_boot_machines_table(pool).await?;
+    
+    let row = sqlx::query("SELECT 1 FROM secure_boot_machines WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(row.is_some())
+}
+
+// Select or deselect the secure boot chain for a machine
+pub async fn set_secure_boot(machine_id: &Uuid, enabled: bool) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_secure_boot_machines_table(pool).await?;
+    
+    if enabled {
+        sqlx::query("INSERT INTO secure_boot_machines (machine_id, enabled_at) VALUES (?, ?) ON CONFLICT(machine_id) DO NOTHING")
+            .bind(machine_id.to_string())
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+    } else {
+        sqlx::query("DELETE FROM secure_boot_machines WHERE machine_id = ?")
+            .bind(machine_id.to_string())
+            .execute(pool)
+            .await?;
+    }
+    
+    Ok(())
+}
+
+// Create the table recording how each MAC last reached the server (PXE or HTTP Boot)
+async fn ensure_boot_methods_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS boot_methods (
+            mac_address TEXT PRIMARY KEY,
+            method TEXT NOT NULL,
+            seen_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn record_boot_method(mac_address: &str, method: crate::http_boot::BootMethod) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_boot_methods_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO boot_methods (mac_address, method, seen_at) VALUES (?, ?, ?)
+         ON CONFLICT(mac_address) DO UPDATE SET
+            method = excluded.method,
+            seen_at = excluded.seen_at"
+    )
+    .bind(mac_address.to_lowercase())
+    .bind(method.as_str())
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_boot_method(mac_address: &str) -> Result<Option<crate::http_boot::BootMethod>> {
+    let pool = get_pool().await?;
+    ensure_boot_methods_table(pool).await?;
+    
+    let method: Option<String> = sqlx::query_scalar("SELECT method FROM boot_methods WHERE mac_address = ?")
+        .bind(mac_address.to_lowercase())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(method.and_then(|m| crate::http_boot::BootMethod::parse(&m)))
+}
+
+// Create the agent and HookOS version table if it doesn't exist
+async fn ensure_boot_environment_table(pool: &Pool<Sqlite>) -> Result<()> {
+    ensure_boot_methods_table(pool).await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_boot_environment (
+            machine_id TEXT PRIMARY KEY,
+            agent_version TEXT,
+            agent_reported_at TEXT,
+            hookos_version TEXT,
+            hookos_booted_at TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn record_agent_version(machine_id: &Uuid, version: &str) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_boot_environment_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO machine_boot_environment (machine_id, agent_version, agent_reported_at) VALUES (?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            agent_version = excluded.agent_version,
+            agent_reported_at = excluded.agent_reported_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(version)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn record_hookos_boot(machine_id: &Uuid, version: Option<&str>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_boot_environment_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO machine_boot_environment (machine_id, hookos_version, hookos_booted_at) VALUES (?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            hookos_version = excluded.hookos_version,
+            hookos_booted_at = excluded.hookos_booted_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(version)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+type BootEnvironmentRow = (String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>);
+
+// Versions by machine, with the boot method recorded for its MAC
+const BOOT_ENVIRONMENT_QUERY: &str =
+    "SELECT m.id, e.agent_version, e.agent_reported_at, e.hookos_version, e.hookos_booted_at, b.method
+     FROM machines m
+     LEFT JOIN machine_boot_environment e ON e.machine_id = m.id
+     LEFT JOIN boot_methods b ON b.mac_address = lower(m.mac_address)";
+
+fn parse_boot_environment(
+    (id, agent_version, agent_reported_at, hookos_version, hookos_booted_at, method): BootEnvironmentRow,
+) -> Option<(Uuid, crate::boot_environment::BootEnvironment)> {
+    Some((Uuid::parse_str(&id).ok()?, crate::boot_environment::BootEnvironment {
+        agent_version,
+        agent_reported_at: agent_reported_at.as_deref().and_then(parse_rfc3339),
+        hookos_version,
+        hookos_booted_at: hookos_booted_at.as_deref().and_then(parse_rfc3339),
+        boot_method: method.as_deref().and_then(crate::http_boot::BootMethod::parse),
+    }))
+}
+
+pub async fn get_boot_environment(machine_id: &Uuid) -> Result<Option<crate::boot_environment::BootEnvironment>> {
+    let pool = get_pool().await?;
+    ensure_boot_environment_table(pool).await?;
+    
+    let row: Option<BootEnvironmentRow> = sqlx::query_as(&format!("{} WHERE m.id = ?", BOOT_ENVIRONMENT_QUERY))
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(row.and_then(parse_boot_environment).map(|(_, environment)| environment))
+}
+
+pub async fn list_boot_environments() -> Result<std::collections::HashMap<Uuid, crate::boot_environment::BootEnvironment>> {
+    let pool = get_pool().await?;
+    ensure_boot_environment_table(pool).await?;
+    
+    let rows: Vec<BootEnvironmentRow> = sqlx::query_as(BOOT_ENVIRONMENT_QUERY)
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.into_iter().filter_map(parse_boot_environment).collect())
+}
+
+// Create the per-machine and per-tag VLAN tables if they don't exist
+async fn ensure_vlan_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_vlans (
+            machine_id TEXT PRIMARY KEY,
+            vlan_id INTEGER NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tag_vlans (
+            tag TEXT PRIMARY KEY,
+            vlan_id INTEGER NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_machine_vlan(machine_id: &Uuid) -> Result<Option<u16>> {
+    let pool = get_pool().await?;
+    ensure_vlan_tables(pool).await?;
+    
+    let vlan_id: Option<i64> = sqlx::query_scalar("SELECT vlan_id FROM machine_vlans WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(vlan_id.map(|v| v as u16))
+}
+
+// Set or clear (None) a machine's own VLAN
+pub async fn set_machine_vlan(machine_id: &Uuid, vlan_id: Option<u16>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_vlan_tables(pool).await?;
+    
+    match vlan_id {
+        Some(vlan_id) => {
+            sqlx::query(
+                "INSERT INTO machine_vlans (machine_id, vlan_id, updated_at) VALUES (?, ?, ?)
+                 ON CONFLICT(machine_id) DO UPDATE SET
+                    vlan_id = excluded.vlan_id,
+                    updated_at = excluded.updated_at"
+            )
+            .bind(machine_id.to_string())
+            .bind(vlan_id as i64)
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM machine_vlans WHERE machine_id = ?")
+                .bind(machine_id.to_string())
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+pub async fn list_tag_vlans() -> Result<Vec<(String, u16)>> {
+    let pool = get_pool().await?;
+    ensure_vlan_tables(pool).await?;
+    
+    let rows: Vec<(String, i64)> = sqlx::query_as("SELECT tag, vlan_id FROM tag_vlans ORDER BY tag")
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.into_iter().map(|(tag, vlan_id)| (tag, vlan_id as u16)).collect())
+}
+
+// Set or clear (None) the VLAN every machine carrying a tag provisions on
+pub async fn set_tag_vlan(tag: &str, vlan_id: Option<u16>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_vlan_tables(pool).await?;
+    
+    match vlan_id {
+        Some(vlan_id) => {
+            sqlx::query(
+                "INSERT INTO tag_vlans (tag, vlan_id, updated_at) VALUES (?, ?, ?)
+                 ON CONFLICT(tag) DO UPDATE SET
+                    vlan_id = excluded.vlan_id,
+                    updated_at = excluded.updated_at"
+            )
+            .bind(tag)
+            .bind(vlan_id as i64)
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM tag_vlans WHERE tag = ?")
+                .bind(tag)
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Create the table of DNS records registered for machines, so they can be removed later
+async fn ensure_dns_records_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS dns_records (
+            machine_id TEXT PRIMARY KEY,
+            fqdn TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_dns_record(machine_id: &Uuid) -> Result<Option<crate::dns::DnsRecord>> {
+    let pool = get_pool().await?;
+    ensure_dns_records_table(pool).await?;
+    
+    let row: Option<(String, String)> = sqlx::query_as("SELECT fqdn, ip_address FROM dns_records WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(row.and_then(|(fqdn, ip_address)| {
+        ip_address.parse().ok().map(|ip_address| crate::dns::DnsRecord { fqdn, ip_address })
+    }))
+}
+
+pub async fn save_dns_record(machine_id: &Uuid, record: &crate::dns::DnsRecord) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_dns_records_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO dns_records (machine_id, fqdn, ip_address, updated_at) VALUES (?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            fqdn = excluded.fqdn,
+            ip_address = excluded.ip_address,
+            updated_at = excluded.updated_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(&record.fqdn)
+    .bind(record.ip_address.to_string())
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn delete_dns_record(machine_id: &Uuid) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_dns_records_table(pool).await?;
+    
+    sqlx::query("DELETE FROM dns_records WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the table of share-link revocation cutoffs if it doesn't exist
+async fn ensure_share_link_revocations_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS share_link_revocations (
+            machine_id TEXT PRIMARY KEY,
+            revoked_before TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Invalidate every share link issued for a machine so far
+pub async fn revoke_share_links(machine_id: &Uuid) -> Result<chrono::DateTime<Utc>> {
+    let pool = get_pool().await?;
+    ensure_share_link_revocations_table(pool).await?;
+    
+    let now = Utc::now();
+    sqlx::query(
+        "INSERT INTO share_link_revocations (machine_id, revoked_before) VALUES (?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET revoked_before = excluded.revoked_before"
+    )
+    .bind(machine_id.to_string())
+    .bind(now.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(now)
+}
+
+pub async fn get_share_links_revoked_before(machine_id: &Uuid) -> Result<Option<chrono::DateTime<Utc>>> {
+    let pool = get_pool().await?;
+    ensure_share_link_revocations_table(pool).await?;
+    
+    let revoked_before: Option<String> = sqlx::query_scalar("SELECT revoked_before FROM share_link_revocations WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(revoked_before
+        .and_then(|r| chrono::DateTime::parse_from_rfc3339(&r).ok())
+        .map(|r| r.with_timezone(&Utc)))
+}
+
+async fn ensure_terminal_sessions_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS terminal_sessions (
+            id TEXT PRIMARY KEY,
+            machine_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            started_at TEXT NOT NULL,
+            ended_at TEXT,
+            exit_code INTEGER,
+            bytes_in INTEGER NOT NULL DEFAULT 0,
+            bytes_out INTEGER NOT NULL DEFAULT 0,
+            last_input_at TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Record that an admin opened a terminal on a machine
+pub async fn start_terminal_session(session_id: &Uuid, machine_id: &Uuid, username: &str) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_terminal_sessions_table(pool).await?;
+    
+    sqlx::query("INSERT INTO terminal_sessions (id, machine_id, username, started_at) VALUES (?, ?, ?, ?)")
+        .bind(session_id.to_string())
+        .bind(machine_id.to_string())
+        .bind(username)
+        .bind(Utc::now().to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Close out a terminal session's audit record with how much was typed and how it ended
+pub async fn finish_terminal_session(session_id: &Uuid, audit: &crate::terminal::SessionAudit) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_terminal_sessions_table(pool).await?;
+    
+    sqlx::query(
+        "UPDATE terminal_sessions
+         SET ended_at = ?, exit_code = ?, bytes_in = ?, bytes_out = ?, last_input_at = ?
+         WHERE id = ?"
+    )
+    .bind(Utc::now().to_rfc3339())
+    .bind(audit.exit_code)
+    .bind(audit.bytes_in as i64)
+    .bind(audit.bytes_out as i64)
+    .bind(audit.last_input_at.map(|at| at.to_rfc3339()))
+    .bind(session_id.to_string())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Terminal sessions opened on a machine, newest first
+pub async fn list_terminal_sessions(machine_id: &Uuid) -> Result<Vec<crate::terminal::TerminalSessionRecord>> {
+    let pool = get_pool().await?;
+    ensure_terminal_sessions_table(pool).await?;
+    
+    let rows: Vec<(String, String, String, Option<String>, Option<i32>, i64, i64, Option<String>)> = sqlx::query_as(
+        "SELECT id, username, started_at, ended_at, exit_code, bytes_in, bytes_out, last_input_at
+         FROM terminal_sessions WHERE machine_id = ? ORDER BY started_at DESC"
+    )
+    .bind(machine_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().map(|(id, username, started_at, ended_at, exit_code, bytes_in, bytes_out, last_input_at)| {
+        crate::terminal::TerminalSessionRecord {
+            id,
+            username,
+            started_at,
+            ended_at,
+            exit_code,
+            bytes_in: bytes_in as u64,
+            bytes_out: bytes_out as u64,
+            last_input_at,
+        }
+    }).collect())
+}
+
+// Create the maintenance window tables: one window per machine and one per tag
+async fn ensure_maintenance_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_maintenance (
+            machine_id TEXT PRIMARY KEY,
+            reason TEXT,
+            until TEXT,
+            started_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tag_maintenance (
+            tag TEXT PRIMARY KEY,
+            reason TEXT,
+            until TEXT,
+            started_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn parse_maintenance_window(reason: Option<String>, until: Option<String>, started_at: String) -> crate::maintenance::MaintenanceWindow {
+    let parse = |value: &str| chrono::DateTime::parse_from_rfc3339(value).ok().map(|dt| dt.with_timezone(&Utc));
+    crate::maintenance::MaintenanceWindow {
+        reason,
+        until: until.as_deref().and_then(parse),
+        started_at: parse(&started_at).unwrap_or_else(Utc::now),
+    }
+}
+
+pub async fn get_machine_maintenance(machine_id: &Uuid) -> Result<Option<crate::maintenance::MaintenanceWindow>> {
+    let pool = get_pool().await?;
+    ensure_maintenance_tables(pool).await?;
+    
+    let row: Option<(Option<String>, Option<String>, String)> = sqlx::query_as(
+        "SELECT reason, until, started_at FROM machine_maintenance WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.map(|(reason, until, started_at)| parse_maintenance_window(reason, until, started_at)))
+}
+
+// Put a machine into maintenance, or take it out (None)
+pub async fn set_machine_maintenance(machine_id: &Uuid, window: Option<&crate::maintenance::MaintenanceWindow>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_maintenance_tables(pool).await?;
+    
+    match window {
+        Some(window) => {
+            sqlx::query(
+                "INSERT INTO machine_maintenance (machine_id, reason, until, started_at) VALUES (?, ?, ?, ?)
+                 ON CONFLICT(machine_id) DO UPDATE SET
+                    reason = excluded.reason,
+                    until = excluded.until,
+                    started_at = excluded.started_at"
+            )
+            .bind(machine_id.to_string())
+            .bind(&window.reason)
+            .bind(window.until.map(|until| until.to_rfc3339()))
+            .bind(window.started_at.to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM machine_maintenance WHERE machine_id = ?")
+                .bind(machine_id.to_string())
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Maintenance windows set on tags, expired or not
+pub async fn list_tag_maintenance() -> Result<Vec<(String, crate::maintenance::MaintenanceWindow)>> {
+    let pool = get_pool().await?;
+    ensure_maintenance_tables(pool).await?;
+    
+    let rows: Vec<(String, Option<String>, Option<String>, String)> = sqlx::query_as(
+        "SELECT tag, reason, until, started_at FROM tag_maintenance ORDER BY tag"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter()
+        .map(|(tag, reason, until, started_at)| (tag, parse_maintenance_window(reason, until, started_at)))
+        .collect())
+}
+
+// Put every machine carrying a tag into maintenance, or clear the tag's window (None)
+pub async fn set_tag_maintenance(tag: &str, window: Option<&crate::maintenance::MaintenanceWindow>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_maintenance_tables(pool).await?;
+    
+    match window {
+        Some(window) => {
+            sqlx::query(
+                "INSERT INTO tag_maintenance (tag, reason, until, started_at) VALUES (?, ?, ?, ?)
+                 ON CONFLICT(tag) DO UPDATE SET
+                    reason = excluded.reason,
+                    until = excluded.until,
+                    started_at = excluded.started_at"
+            )
+            .bind(tag)
+            .bind(&window.reason)
+            .bind(window.until.map(|until| until.to_rfc3339()))
+            .bind(window.started_at.to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM tag_maintenance WHERE tag = ?")
+                .bind(tag)
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Create the notification tables: the channel settings (a single JSON row) and the queue of
+// routine notifications held for quiet hours or a digest
+async fn ensure_notification_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS notification_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            settings TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS notification_queue (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            channel TEXT NOT NULL,
+            notification TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_notification_queue_channel ON notification_queue (channel, id)")
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn get_notification_settings() -> Result<crate::notifications::NotificationSettings> {
+    let pool = get_pool().await?;
+    ensure_notification_tables(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT settings FROM notification_settings WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((settings,)) => Ok(serde_json::from_str(&settings)?),
+        None => Ok(Default::default()),
+    }
+}
+
+// Replace the notification settings, dropping anything queued for channels that no longer exist
+pub async fn save_notification_settings(settings: &crate::notifications::NotificationSettings) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_notification_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO notification_settings (id, settings, updated_at) VALUES (1, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            settings = excluded.settings,
+            updated_at = excluded.updated_at"
+    )
+    .bind(serde_json::to_string(settings)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    let names: Vec<&str> = settings.channels.iter().map(|c| c.name.as_str()).collect();
+    sqlx::query("DELETE FROM notification_queue WHERE channel NOT IN (SELECT value FROM json_each(?))")
+        .bind(serde_json::to_string(&names)?)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn queue_notification(channel: &str, notification: &crate::notifications::Notification) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_notification_tables(pool).await?;
+    
+    sqlx::query("INSERT INTO notification_queue (channel, notification, created_at) VALUES (?, ?, ?)")
+        .bind(channel)
+        .bind(serde_json::to_string(notification)?)
+        .bind(Utc::now().to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Notifications held for a channel, oldest first, with their queue IDs
+pub async fn queued_notifications(channel: &str) -> Result<Vec<(i64, crate::notifications::Notification)>> {
+    let pool = get_pool().await?;
+    ensure_notification_tables(pool).await?;
+    
+    let rows: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT id, notification FROM notification_queue WHERE channel = ? ORDER BY id"
+    )
+    .bind(channel)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter()
+        .filter_map(|(id, notification)| match serde_json::from_str(&notification) {
+            Ok(notification) => Some((id, notification)),
+            Err(e) => {
+                warn!("Skipping unreadable queued notification {}: {}", id, e);
+                None
+            }
+        })
+        .collect())
+}
+
+// Clear a channel's queue up to and including the given ID, once those have been sent
+pub async fn delete_queued_notifications(channel: &str, up_to_id: i64) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_notification_tables(pool).await?;
+    
+    sqlx::query("DELETE FROM notification_queue WHERE channel = ? AND id <= ?")
+        .bind(channel)
+        .bind(up_to_id)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the ephemeral machine table: the live image each diskless machine boots, and its latest boot
+async fn ensure_ephemeral_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS ephemeral_machines (
+            machine_id TEXT PRIMARY KEY,
+            kernel TEXT NOT NULL,
+            initrd TEXT NOT NULL,
+            kernel_args TEXT,
+            cloud_init TEXT,
+            updated_at TEXT NOT NULL,
+            boot_id TEXT UNIQUE,
+            boot_served_at TEXT,
+            booted_at TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn parse_rfc3339(value: &str) -> Option<chrono::DateTime<Utc>> {
+    chrono::DateTime::parse_from_rfc3339(value).ok().map(|dt| dt.with_timezone(&Utc))
+}
+
+pub async fn get_ephemeral_config(machine_id: &Uuid) -> Result<Option<crate::ephemeral::EphemeralConfig>> {
+    let pool = get_pool().await?;
+    ensure_ephemeral_table(pool).await?;
+    
+    let row: Option<(String, String, Option<String>, Option<String>, String)> = sqlx::query_as(
+        "SELECT kernel, initrd, kernel_args, cloud_init, updated_at FROM ephemeral_machines WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.map(|(kernel, initrd, kernel_args, cloud_init, updated_at)| crate::ephemeral::EphemeralConfig {
+        kernel,
+        initrd,
+        kernel_args,
+        cloud_init,
+        updated_at: parse_rfc3339(&updated_at).unwrap_or_else(Utc::now),
+    }))
+}
+
+// Make a machine ephemeral (keeping its boot history), or return it to installing an OS (None)
+pub async fn set_ephemeral_config(machine_id: &Uuid, config: Option<&crate::ephemeral::EphemeralConfig>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_ephemeral_table(pool).await?;
+    
+    match config {
+        Some(config) => {
+            sqlx::query(
+                "INSERT INTO ephemeral_machines (machine_id, kernel, initrd, kernel_args, cloud_init, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?)
+                 ON CONFLICT(machine_id) DO UPDATE SET
+                    kernel = excluded.kernel,
+                    initrd = excluded.initrd,
+                    kernel_args = excluded.kernel_args,
+                    cloud_init = excluded.cloud_init,
+                    updated_at = excluded.updated_at"
+            )
+            .bind(machine_id.to_string())
+            .bind(&config.kernel)
+            .bind(&config.initrd)
+            .bind(&config.kernel_args)
+            .bind(&config.cloud_init)
+            .bind(config.updated_at.to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM ephemeral_machines WHERE machine_id = ?")
+                .bind(machine_id.to_string())
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+pub async fn get_ephemeral_boot(machine_id: &Uuid) -> Result<Option<crate::ephemeral::EphemeralBoot>> {
+    let pool = get_pool().await?;
+    ensure_ephemeral_table(pool).await?;
+    
+    let row: Option<(Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT boot_id, boot_served_at, booted_at FROM ephemeral_machines WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.and_then(|(boot_id, served_at, booted_at)| {
+        Some(crate::ephemeral::EphemeralBoot {
+            boot_id: Uuid::parse_str(&boot_id?).ok()?,
+            served_at: parse_rfc3339(&served_at?)?,
+            booted_at: booted_at.as_deref().and_then(parse_rfc3339),
+        })
+    }))
+}
+
+// Start a new live boot; the previous boot ID stops working
+pub async fn record_ephemeral_boot(machine_id: &Uuid, boot_id: &Uuid, served_at: chrono::DateTime<Utc>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_ephemeral_table(pool).await?;
+    
+    sqlx::query("UPDATE ephemeral_machines SET boot_id = ?, boot_served_at = ?, booted_at = NULL WHERE machine_id = ?")
+        .bind(boot_id.to_string())
+        .bind(served_at.to_rfc3339())
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// The machine a current boot ID belongs to
+pub async fn find_ephemeral_boot(boot_id: &Uuid) -> Result<Option<Uuid>> {
+    let pool = get_pool().await?;
+    ensure_ephemeral_table(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT machine_id FROM ephemeral_machines WHERE boot_id = ?")
+        .bind(boot_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(row.and_then(|(machine_id,)| Uuid::parse_str(&machine_id).ok()))
+}
+
+// Note that the live system came up, returning whether this is the first time for this boot
+pub async fn mark_ephemeral_booted(boot_id: &Uuid, booted_at: chrono::DateTime<Utc>) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_ephemeral_table(pool).await?;
+    
+    let result = sqlx::query("UPDATE ephemeral_machines SET booted_at = ? WHERE boot_id = ? AND booted_at IS NULL")
+        .bind(booted_at.to_rfc3339())
+        .bind(boot_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the table recording which image (and custom image version) each machine was installed from
+async fn ensure_machine_images_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_images (
+            machine_id TEXT PRIMARY KEY,
+            os_choice TEXT NOT NULL,
+            version TEXT,
+            started_at TEXT NOT NULL,
+            installed_at TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// A machine started installing an image; replaces whatever it was installed from before
+pub async fn record_machine_image(machine_id: &Uuid, os_choice: &str, version: Option<&str>, started_at: chrono::DateTime<Utc>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_images_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO machine_images (machine_id, os_choice, version, started_at, installed_at) VALUES (?, ?, ?, ?, NULL)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            os_choice = excluded.os_choice,
+            version = excluded.version,
+            started_at = excluded.started_at,
+            installed_at = NULL"
+    )
+    .bind(machine_id.to_string())
+    .bind(os_choice)
+    .bind(version)
+    .bind(started_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn mark_machine_image_installed(machine_id: &Uuid, installed_at: chrono::DateTime<Utc>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_images_table(pool).await?;
+    
+    sqlx::query("UPDATE machine_images SET installed_at = ? WHERE machine_id = ?")
+        .bind(installed_at.to_rfc3339())
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Install records for machines that still exist
+pub async fn list_machine_images() -> Result<Vec<crate::custom_images::MachineImage>> {
+    let pool = get_pool().await?;
+    ensure_machine_images_table(pool).await?;
+    
+    let rows: Vec<(String, String, Option<String>, String, Option<String>)> = sqlx::query_as(
+        "SELECT mi.machine_id, mi.os_choice, mi.version, mi.started_at, mi.installed_at
+         FROM machine_images mi JOIN machines m ON m.id = mi.machine_id
+         ORDER BY mi.machine_id"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    let parse = |value: &str| chrono::DateTime::parse_from_rfc3339(value).ok().map(|dt| dt.with_timezone(&Utc));
+    Ok(rows.into_iter().filter_map(|(machine_id, os_choice, version, started_at, installed_at)| {
+        Some(crate::custom_images::MachineImage {
+            machine_id: Uuid::parse_str(&machine_id).ok()?,
+            os_choice,
+            version,
+            started_at: parse(&started_at).unwrap_or_else(Utc::now),
+            installed_at: installed_at.as_deref().and_then(parse),
+        })
+    }).collect())
+}
+
+// Custom image versions that existing machines were (or are being) installed from
+pub async fn pinned_image_versions(os_choice: &str) -> Result<Vec<String>> {
+    let pool = get_pool().await?;
+    ensure_machine_images_table(pool).await?;
+    
+    let rows: Vec<(String,)> = sqlx::query_as(
+        "SELECT DISTINCT mi.version FROM machine_images mi JOIN machines m ON m.id = mi.machine_id
+         WHERE mi.os_choice = ? AND mi.version IS NOT NULL
+         ORDER BY mi.version"
+    )
+    .bind(os_choice)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().map(|(version,)| version).collect())
+}
+
+// Create the table of per-tag timezone/NTP/locale overrides if it doesn't exist
+async fn ensure_tag_system_configs_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tag_system_configs (
+            tag TEXT PRIMARY KEY,
+            timezone TEXT,
+            ntp_servers TEXT NOT NULL,
+            locale TEXT,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn list_tag_system_configs() -> Result<Vec<(String, crate::system_config::SystemConfig)>> {
+    let pool = get_pool().await?;
+    ensure_tag_system_configs_table(pool).await?;
+    
+    let rows: Vec<(String, Option<String>, String, Option<String>)> = sqlx::query_as(
+        "SELECT tag, timezone, ntp_servers, locale FROM tag_system_configs ORDER BY tag"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().map(|(tag, timezone, ntp_servers, locale)| {
+        let config = crate::system_config::SystemConfig {
+            timezone,
+            ntp_servers: serde_json::from_str(&ntp_servers).unwrap_or_default(),
+            locale,
+        };
+        (tag, config)
+    }).collect())
+}
+
+// Set or clear (None, or an empty config) a tag's timezone/NTP/locale overrides
+pub async fn set_tag_system_config(tag: &str, config: Option<&crate::system_config::SystemConfig>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_tag_system_configs_table(pool).await?;
+    
+    match config.filter(|c| !c.is_empty()) {
+        Some(config) => {
+            sqlx::query(
+                "INSERT INTO tag_system_configs (tag, timezone, ntp_servers, locale, updated_at) VALUES (?, ?, ?, ?, ?)
+                 ON CONFLICT(tag) DO UPDATE SET
+                    timezone = excluded.timezone,
+                    ntp_servers = excluded.ntp_servers,
+                    locale = excluded.locale,
+                    updated_at = excluded.updated_at"
+            )
+            .bind(tag)
+            .bind(&config.timezone)
+            .bind(serde_json::to_string(&config.ntp_servers)?)
+            .bind(&config.locale)
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM tag_system_configs WHERE tag = ?")
+                .bind(tag)
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Create the tables of provisioning profiles and the tags that use one as their default
+async fn ensure_provisioning_profiles_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS provisioning_profiles (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            profile TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tag_profiles (
+            tag TEXT PRIMARY KEY,
+            profile_id TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn save_provisioning_profile(profile: &crate::provisioning_profiles::ProvisioningProfile) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    sqlx::query("INSERT INTO provisioning_profiles (id, name, profile, created_at) VALUES (?, ?, ?, ?)")
+        .bind(profile.id.to_string())
+        .bind(&profile.name)
+        .bind(serde_json::to_string(profile)?)
+        .bind(profile.created_at.to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn list_provisioning_profiles() -> Result<Vec<crate::provisioning_profiles::ProvisioningProfile>> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    let rows: Vec<(String,)> = sqlx::query_as("SELECT profile FROM provisioning_profiles ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.into_iter().filter_map(|(profile,)| serde_json::from_str(&profile).ok()).collect())
+}
+
+pub async fn get_provisioning_profile(id: &Uuid) -> Result<Option<crate::provisioning_profiles::ProvisioningProfile>> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT profile FROM provisioning_profiles WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(match row {
+        Some((profile,)) => Some(serde_json::from_str(&profile)?),
+        None => None,
+    })
+}
+
+pub async fn provisioning_profile_name_taken(name: &str) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM provisioning_profiles WHERE name = ?")
+        .bind(name)
+        .fetch_one(pool)
+        .await?;
+    
+    Ok(count > 0)
+}
+
+// Delete a profile, and with it any tag defaults pointing at it. Returns false if it didn't exist
+pub async fn delete_provisioning_profile(id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM tag_profiles WHERE profile_id = ?")
+        .bind(id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query("DELETE FROM provisioning_profiles WHERE id = ?")
+        .bind(id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Tags with a default profile, sorted by tag
+pub async fn list_tag_profiles() -> Result<Vec<(String, crate::provisioning_profiles::ProvisioningProfile)>> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT t.tag, p.profile FROM tag_profiles t
+         JOIN provisioning_profiles p ON p.id = t.profile_id
+         ORDER BY t.tag"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter()
+        .filter_map(|(tag, profile)| serde_json::from_str(&profile).ok().map(|profile| (tag, profile)))
+        .collect())
+}
+
+// Set or clear (None) a tag's default profile
+pub async fn set_tag_profile(tag: &str, profile_id: Option<&Uuid>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_provisioning_profiles_tables(pool).await?;
+    
+    match profile_id {
+        Some(profile_id) => {
+            sqlx::query(
+                "INSERT INTO tag_profiles (tag, profile_id, updated_at) VALUES (?, ?, ?)
+                 ON CONFLICT(tag) DO UPDATE SET
+                    profile_id = excluded.profile_id,
+                    updated_at = excluded.updated_at"
+            )
+            .bind(tag)
+            .bind(profile_id.to_string())
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM tag_profiles WHERE tag = ?")
+                .bind(tag)
+                .execute(pool)
+                .await?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Create the table of install stage timings reported by installers if it doesn't exist
+async fn ensure_install_stage_timings_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS install_stage_timings (
+            machine_id TEXT NOT NULL,
+            stage TEXT NOT NULL,
+            action TEXT,
+            started_at TEXT,
+            finished_at TEXT,
+            PRIMARY KEY (machine_id, stage)
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn install_stage_timing_from_row(
+    (stage, action, started_at, finished_at): (String, Option<String>, Option<String>, Option<String>),
+) -> Option<crate::install_telemetry::StageTiming> {
+    let started_at = started_at.as_deref().and_then(parse_rfc3339);
+    let finished_at = finished_at.as_deref().and_then(parse_rfc3339);
+    Some(crate::install_telemetry::StageTiming {
+        stage: dragonfly_common::models::InstallStage::parse(&stage)?,
+        action,
+        started_at,
+        finished_at,
+        duration_secs: crate::install_telemetry::duration_secs(started_at, finished_at),
+    })
+}
+
+// A stage (re)started; any earlier end for it is forgotten
+pub async fn record_install_stage_start(
+    machine_id: &Uuid,
+    stage: dragonfly_common::models::InstallStage,
+    action: Option<&str>,
+    at: chrono::DateTime<Utc>,
+) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_install_stage_timings_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO install_stage_timings (machine_id, stage, action, started_at, finished_at)
+         VALUES (?, ?, ?, ?, NULL)
+         ON CONFLICT(machine_id, stage) DO UPDATE SET
+            action = COALESCE(excluded.action, install_stage_timings.action),
+            started_at = excluded.started_at,
+            finished_at = NULL"
+    )
+    .bind(machine_id.to_string())
+    .bind(stage.as_str())
+    .bind(action)
+    .bind(at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// A stage ended; returns the stage's timing, or None if its end had already been recorded
+pub async fn record_install_stage_end(
+    machine_id: &Uuid,
+    stage: dragonfly_common::models::InstallStage,
+    action: Option<&str>,
+    at: chrono::DateTime<Utc>,
+) -> Result<Option<crate::install_telemetry::StageTiming>> {
+    let pool = get_pool().await?;
+    ensure_install_stage_timings_table(pool).await?;
+    
+    let result = sqlx::query(
+        "INSERT INTO install_stage_timings (machine_id, stage, action, started_at, finished_at)
+         VALUES (?, ?, ?, NULL, ?)
+         ON CONFLICT(machine_id, stage) DO UPDATE SET
+            action = COALESCE(excluded.action, install_stage_timings.action),
+            finished_at = excluded.finished_at
+         WHERE install_stage_timings.finished_at IS NULL"
+    )
+    .bind(machine_id.to_string())
+    .bind(stage.as_str())
+    .bind(action)
+    .bind(at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    
+    let row: Option<(String, Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT stage, action, started_at, finished_at FROM install_stage_timings WHERE machine_id = ? AND stage = ?"
+    )
+    .bind(machine_id.to_string())
+    .bind(stage.as_str())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.and_then(install_stage_timing_from_row))
+}
+
+// Stage timings from a machine's current or most recent install, in the order they started
+pub async fn list_install_stage_timings(machine_id: &Uuid) -> Result<Vec<crate::install_telemetry::StageTiming>> {
+    let pool = get_pool().await?;
+    ensure_install_stage_timings_table(pool).await?;
+    
+    let rows: Vec<(String, Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT stage, action, started_at, finished_at FROM install_stage_timings
+         WHERE machine_id = ? ORDER BY COALESCE(started_at, finished_at)"
+    )
+    .bind(machine_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().filter_map(install_stage_timing_from_row).collect())
+}
+
+pub async fn clear_install_stage_timings(machine_id: &Uuid) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_install_stage_timings_table(pool).await?;
+    
+    sqlx::query("DELETE FROM install_stage_timings WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the table of post-install verification reports if it doesn't exist
+async fn ensure_install_verifications_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS install_verifications (
+            machine_id TEXT PRIMARY KEY,
+            passed INTEGER NOT NULL,
+            report TEXT NOT NULL,
+            received_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Store a machine's verification report, replacing any from an earlier install
+pub async fn save_install_verification(
+    machine_id: &Uuid,
+    report: &dragonfly_common::models::InstallVerificationReport,
+) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_install_verifications_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO install_verifications (machine_id, passed, report, received_at)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            passed = excluded.passed,
+            report = excluded.report,
+            received_at = excluded.received_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(report.passed())
+    .bind(serde_json::to_string(report)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_install_verification(machine_id: &Uuid) -> Result<Option<crate::install_verification::Verification>> {
+    let pool = get_pool().await?;
+    ensure_install_verifications_table(pool).await?;
+    
+    let row: Option<(String, String)> = sqlx::query_as(
+        "SELECT report, received_at FROM install_verifications WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    match row {
+        Some((report, received_at)) => {
+            let report: dragonfly_common::models::InstallVerificationReport = serde_json::from_str(&report)?;
+            Ok(Some(crate::install_verification::Verification {
+                passed: report.passed(),
+                report,
+                received_at: parse_rfc3339(&received_at).unwrap_or_else(Utc::now),
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn clear_install_verification(machine_id: &Uuid) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_install_verifications_table(pool).await?;
+    
+    sqlx::query("DELETE FROM install_verifications WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the usage reporting tables if they don't exist
+async fn ensure_usage_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS install_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            machine_id TEXT NOT NULL,
+            os_choice TEXT,
+            succeeded INTEGER NOT NULL,
+            finished_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS artifact_bytes_served (
+            month TEXT PRIMARY KEY,
+            bytes INTEGER NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Record a finished install; kept for usage reporting, not purged by retention
+pub async fn record_install(machine_id: &Uuid, os_choice: Option<&str>, succeeded: bool, finished_at: chrono::DateTime<Utc>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_usage_tables(pool).await?;
+    
+    sqlx::query("INSERT INTO install_history (machine_id, os_choice, succeeded, finished_at) VALUES (?, ?, ?, ?)")
+        .bind(machine_id.to_string())
+        .bind(os_choice)
+        .bind(succeeded)
+        .bind(finished_at.to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Installs per month ("2026-09"), as (month, succeeded, failed), from `since_month` on
+pub async fn installs_by_month(since_month: &str) -> Result<Vec<(String, i64, i64)>> {
+    let pool = get_pool().await?;
+    ensure_usage_tables(pool).await?;
+    
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+        "SELECT substr(finished_at, 1, 7) AS month,
+                SUM(CASE WHEN succeeded THEN 1 ELSE 0 END),
+                SUM(CASE WHEN succeeded THEN 0 ELSE 1 END)
+         FROM install_history
+         WHERE substr(finished_at, 1, 7) >= ?
+         GROUP BY month
+         ORDER BY month"
+    )
+    .bind(since_month)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows)
+}
+
+pub async fn add_artifact_bytes_served(month: &str, bytes: u64) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_usage_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO artifact_bytes_served (month, bytes) VALUES (?, ?)
+         ON CONFLICT(month) DO UPDATE SET bytes = bytes + excluded.bytes"
+    )
+    .bind(month)
+    .bind(bytes as i64)
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn artifact_bytes_by_month(since_month: &str) -> Result<Vec<(String, i64)>> {
+    let pool = get_pool().await?;
+    ensure_usage_tables(pool).await?;
+    
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT month, bytes FROM artifact_bytes_served WHERE month >= ? ORDER BY month"
+    )
+    .bind(since_month)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows)
+}
+
+// Create the agent signing settings table if it doesn't exist
+async fn ensure_agent_signing_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS agent_signing (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            mode TEXT NOT NULL,
+            token TEXT,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_agent_signing() -> Result<crate::agent_signing::SigningSettings> {
+    let pool = get_pool().await?;
+    ensure_agent_signing_table(pool).await?;
+    
+    let row: Option<(String, Option<String>, String)> = sqlx::query_as(
+        "SELECT mode, token, updated_at FROM agent_signing WHERE id = 1"
+    )
+    .fetch_optional(pool)
+    .await?;
+    
+    match row {
+        Some((mode, token, updated_at)) => Ok(crate::agent_signing::SigningSettings {
+            mode: crate::agent_signing::SigningMode::parse(&mode).unwrap_or_default(),
+            // The enrollment token is stored encrypted
+            token: token.map(|t| crate::encryption::decrypt_string(&t)).transpose()?,
+            updated_at: parse_rfc3339(&updated_at),
+        }),
+        None => Ok(Default::default()),
+    }
+}
+
+pub async fn save_agent_signing(mode: crate::agent_signing::SigningMode, token: Option<&str>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_agent_signing_table(pool).await?;
+    
+    let token = token.map(crate::encryption::encrypt_string).transpose()?;
+    sqlx::query(
+        "INSERT INTO agent_signing (id, mode, token, updated_at) VALUES (1, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            mode = excluded.mode,
+            token = excluded.token,
+            updated_at = excluded.updated_at"
+    )
+    .bind(mode.as_str())
+    .bind(token)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Create the pending redetection table if it doesn't exist
+async fn ensure_redetect_requests_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS redetect_requests (
+            machine_id TEXT PRIMARY KEY,
+            requested_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn set_redetect_requested(machine_id: &Uuid, requested_at: chrono::DateTime<Utc>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_redetect_requests_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO redetect_requests (machine_id, requested_at) VALUES (?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET requested_at = excluded.requested_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(requested_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_redetect_requested(machine_id: &Uuid) -> Result<Option<chrono::DateTime<Utc>>> {
+    let pool = get_pool().await?;
+    ensure_redetect_requests_table(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as(
+        "SELECT requested_at FROM redetect_requests WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.and_then(|(at,)| parse_rfc3339(&at)))
+}
+
+// Returns whether a request was pending
+pub async fn clear_redetect_requested(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_redetect_requests_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM redetect_requests WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the machine warranty table if it doesn't exist
+async fn ensure_machine_warranty_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_warranty (
+            machine_id TEXT PRIMARY KEY,
+            vendor TEXT NOT NULL,
+            serial TEXT NOT NULL,
+            end_date TEXT,
+            service_level TEXT,
+            checked_at TEXT NOT NULL,
+            alerted_for TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+type WarrantyRow = (String, String, String, Option<String>, Option<String>, String, Option<String>);
+
+fn map_warranty_row(row: WarrantyRow) -> Option<(Uuid, crate::warranty::Warranty)> {
+    let (machine_id, vendor, serial, end_date, service_level, checked_at, alerted_for) = row;
+    let parse_date = |date: Option<String>| date.and_then(|d| chrono::NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok());
+    Some((Uuid::parse_str(&machine_id).ok()?, crate::warranty::Warranty {
+        vendor: crate::warranty::Vendor::parse(&vendor)?,
+        serial,
+        end_date: parse_date(end_date),
+        service_level,
+        checked_at: parse_rfc3339(&checked_at)?,
+        alerted_for: parse_date(alerted_for),
+    }))
+}
+
+pub async fn get_warranty(machine_id: &Uuid) -> Result<Option<crate::warranty::Warranty>> {
+    let pool = get_pool().await?;
+    ensure_machine_warranty_table(pool).await?;
+    
+    let row: Option<WarrantyRow> = sqlx::query_as(
+        "SELECT machine_id, vendor, serial, end_date, service_level, checked_at, alerted_for
+         FROM machine_warranty WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.and_then(map_warranty_row).map(|(_, warranty)| warranty))
+}
+
+pub async fn list_warranties() -> Result<Vec<(Uuid, crate::warranty::Warranty)>> {
+    let pool = get_pool().await?;
+    ensure_machine_warranty_table(pool).await?;
+    
+    let rows: Vec<WarrantyRow> = sqlx::query_as(
+        "SELECT machine_id, vendor, serial, end_date, service_level, checked_at, alerted_for
+         FROM machine_warranty ORDER BY end_date"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().filter_map(map_warranty_row).collect())
+}
+
+pub async fn save_warranty(machine_id: &Uuid, warranty: &crate::warranty::Warranty) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_warranty_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO machine_warranty (machine_id, vendor, serial, end_date, service_level, checked_at, alerted_for)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            vendor = excluded.vendor,
+            serial = excluded.serial,
+            end_date = excluded.end_date,
+            service_level = excluded.service_level,
+            checked_at = excluded.checked_at,
+            alerted_for = excluded.alerted_for"
+    )
+    .bind(machine_id.to_string())
+    .bind(warranty.vendor.as_str())
+    .bind(&warranty.serial)
+    .bind(warranty.end_date.map(|d| d.to_string()))
+    .bind(&warranty.service_level)
+    .bind(warranty.checked_at.to_rfc3339())
+    .bind(warranty.alerted_for.map(|d| d.to_string()))
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn mark_warranty_alerted(machine_id: &Uuid, end_date: chrono::NaiveDate) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_warranty_table(pool).await?;
+    
+    sqlx::query("UPDATE machine_warranty SET alerted_for = ? WHERE machine_id = ?")
+        .bind(end_date.to_string())
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the sensor samples table if it doesn't exist
+async fn ensure_sensor_samples_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS sensor_samples (
+            machine_id TEXT NOT NULL,
+            sampled_at TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            name TEXT NOT NULL,
+            value REAL NOT NULL,
+            unit TEXT NOT NULL,
+            inlet INTEGER NOT NULL DEFAULT 0
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_sensor_samples_machine ON sensor_samples (machine_id, sampled_at)")
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn insert_sensor_samples(
+    machine_id: &Uuid,
+    readings: &[crate::sensors::SensorReading],
+    sampled_at: chrono::DateTime<Utc>,
+) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_sensor_samples_table(pool).await?;
+    
+    let sampled_at = sampled_at.to_rfc3339();
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "insert_sensor_samples");
+    let mut tx = pool.begin().await?;
+    for reading in readings {
+        sqlx::query(
+            "INSERT INTO sensor_samples (machine_id, sampled_at, kind, name, value, unit, inlet)
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(machine_id.to_string())
+        .bind(&sampled_at)
+        .bind(reading.kind.as_str())
+        .bind(&reading.name)
+        .bind(reading.value)
+        .bind(&reading.unit)
+        .bind(reading.inlet)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    
+    Ok(())
+}
+
+pub async fn sensor_samples_since(machine_id: &Uuid, since: &chrono::DateTime<Utc>) -> Result<Vec<crate::sensors::SensorSample>> {
+    let pool = get_pool().await?;
+    ensure_sensor_samples_table(pool).await?;
+    
+    let rows: Vec<(String, String, String, f64, String, bool)> = sqlx::query_as(
+        "SELECT sampled_at, kind, name, value, unit, inlet FROM sensor_samples
+         WHERE machine_id = ? AND sampled_at >= ?
+         ORDER BY sampled_at, kind, name"
+    )
+    .bind(machine_id.to_string())
+    .bind(since.to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter()
+        .filter_map(|(sampled_at, kind, name, value, unit, inlet)| Some(crate::sensors::SensorSample {
+            at: parse_rfc3339(&sampled_at)?,
+            reading: crate::sensors::SensorReading {
+                kind: crate::sensors::SensorKind::parse(&kind)?,
+                name,
+                value,
+                unit,
+                inlet,
+            },
+        }))
+        .collect())
+}
+
+pub async fn purge_sensor_samples_before(cutoff: &chrono::DateTime<Utc>) -> Result<u64> {
+    let pool = get_pool().await?;
+    ensure_sensor_samples_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM sensor_samples WHERE sampled_at < ?")
+        .bind(cutoff.to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected())
+}
+
+// Create the power schedule and managed power-off tables if they don't exist
+async fn ensure_power_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS power_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            settings TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS managed_power_offs (
+            machine_id TEXT PRIMARY KEY,
+            previous_status TEXT NOT NULL,
+            schedule TEXT,
+            method TEXT NOT NULL,
+            powered_off_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_power_settings() -> Result<crate::power::PowerSettings> {
+    let pool = get_pool().await?;
+    ensure_power_tables(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT settings FROM power_settings WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((settings,)) => Ok(serde_json::from_str(&settings)?),
+        None => Ok(Default::default()),
+    }
+}
+
+pub async fn save_power_settings(settings: &crate::power::PowerSettings) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_power_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO power_settings (id, settings, updated_at) VALUES (1, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            settings = excluded.settings,
+            updated_at = excluded.updated_at"
+    )
+    .bind(serde_json::to_string(settings)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+type ManagedPowerOffRow = (String, String, Option<String>, String, String);
+
+fn parse_managed_power_off((machine_id, previous_status, schedule, method, powered_off_at): ManagedPowerOffRow) -> Option<crate::power::ManagedPowerOff> {
+    Some(crate::power::ManagedPowerOff {
+        machine_id: Uuid::parse_str(&machine_id).ok()?,
+        previous_status: parse_status(&previous_status),
+        schedule,
+        method: crate::power::PowerMethod::parse(&method)?,
+        powered_off_at: parse_rfc3339(&powered_off_at)?,
+    })
+}
+
+pub async fn save_managed_power_off(record: &crate::power::ManagedPowerOff) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_power_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO managed_power_offs (machine_id, previous_status, schedule, method, powered_off_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            previous_status = excluded.previous_status,
+            schedule = excluded.schedule,
+            method = excluded.method,
+            powered_off_at = excluded.powered_off_at"
+    )
+    .bind(record.machine_id.to_string())
+    .bind(serde_json::to_string(&record.previous_status)?)
+    .bind(&record.schedule)
+    .bind(record.method.as_str())
+    .bind(record.powered_off_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_managed_power_off(machine_id: &Uuid) -> Result<Option<crate::power::ManagedPowerOff>> {
+    let pool = get_pool().await?;
+    ensure_power_tables(pool).await?;
+    
+    let row: Option<ManagedPowerOffRow> = sqlx::query_as(
+        "SELECT machine_id, previous_status, schedule, method, powered_off_at FROM managed_power_offs WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.and_then(parse_managed_power_off))
+}
+
+pub async fn list_managed_power_offs() -> Result<Vec<crate::power::ManagedPowerOff>> {
+    let pool = get_pool().await?;
+    ensure_power_tables(pool).await?;
+    
+    let rows: Vec<ManagedPowerOffRow> = sqlx::query_as(
+        "SELECT machine_id, previous_status, schedule, method, powered_off_at FROM managed_power_offs ORDER BY powered_off_at"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().filter_map(parse_managed_power_off).collect())
+}
+
+pub async fn clear_managed_power_off(machine_id: &Uuid) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_power_tables(pool).await?;
+    
+    sqlx::query("DELETE FROM managed_power_offs WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the patch baseline table if it doesn't exist
+async fn ensure_machine_patches_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_patches (
+            machine_id TEXT PRIMARY KEY,
+            report TEXT NOT NULL,
+            reported_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn save_patch_report(machine_id: &Uuid, report: &dragonfly_common::models::PatchReport) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_patches_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO machine_patches (machine_id, report, reported_at) VALUES (?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            report = excluded.report,
+            reported_at = excluded.reported_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(serde_json::to_string(report)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_patch_status(machine_id: &Uuid) -> Result<Option<crate::patches::PatchStatus>> {
+    let pool = get_pool().await?;
+    ensure_machine_patches_table(pool).await?;
+    
+    let row: Option<(String, String)> = sqlx::query_as("SELECT report, reported_at FROM machine_patches WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(row.and_then(|(report, reported_at)| Some(crate::patches::PatchStatus {
+        report: serde_json::from_str(&report).ok()?,
+        reported_at: parse_rfc3339(&reported_at)?,
+    })))
+}
+
+pub async fn list_patch_statuses() -> Result<std::collections::HashMap<Uuid, crate::patches::PatchStatus>> {
+    let pool = get_pool().await?;
+    ensure_machine_patches_table(pool).await?;
+    
+    let rows: Vec<(String, String, String)> = sqlx::query_as("SELECT machine_id, report, reported_at FROM machine_patches")
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.into_iter()
+        .filter_map(|(machine_id, report, reported_at)| Some((
+            Uuid::parse_str(&machine_id).ok()?,
+            crate::patches::PatchStatus {
+                report: serde_json::from_str(&report).ok()?,
+                reported_at: parse_rfc3339(&reported_at)?,
+            },
+        )))
+        .collect())
+}
+
+// Create the registration enrichment table if it doesn't exist
+async fn ensure_machine_enrichment_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_enrichment (
+            machine_id TEXT PRIMARY KEY,
+            metadata TEXT NOT NULL,
+            hooks TEXT NOT NULL,
+            enriched_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn save_machine_enrichment(
+    machine_id: &Uuid,
+    metadata: &serde_json::Map<String, serde_json::Value>,
+    hooks: &[String],
+) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_machine_enrichment_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO machine_enrichment (machine_id, metadata, hooks, enriched_at) VALUES (?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            metadata = excluded.metadata,
+            hooks = excluded.hooks,
+            enriched_at = excluded.enriched_at"
+    )
+    .bind(machine_id.to_string())
+    .bind(serde_json::to_string(metadata)?)
+    .bind(serde_json::to_string(hooks)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_machine_enrichment(machine_id: &Uuid) -> Result<Option<crate::enrichment::MachineEnrichment>> {
+    let pool = get_pool().await?;
+    ensure_machine_enrichment_table(pool).await?;
+    
+    let row: Option<(String, String, String)> = sqlx::query_as(
+        "SELECT metadata, hooks, enriched_at FROM machine_enrichment WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(row.and_then(|(metadata, hooks, enriched_at)| Some(crate::enrichment::MachineEnrichment {
+        metadata: serde_json::from_str(&metadata).ok()?,
+        hooks: serde_json::from_str(&hooks).ok()?,
+        enriched_at: parse_rfc3339(&enriched_at)?,
+    })))
+}
+
+// Create the public API settings table if it doesn't exist
+async fn ensure_public_api_settings_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS public_api_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            settings TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_public_api_settings() -> Result<crate::public_api::PublicApiSettings> {
+    let pool = get_pool().await?;
+    ensure_public_api_settings_table(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT settings FROM public_api_settings WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((settings,)) => Ok(serde_json::from_str(&settings)?),
+        None => Ok(Default::default()),
+    }
+}
+
+pub async fn save_public_api_settings(settings: &crate::public_api::PublicApiSettings) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_public_api_settings_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO public_api_settings (id, settings, updated_at) VALUES (1, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            settings = excluded.settings,
+            updated_at = excluded.updated_at"
+    )
+    .bind(serde_json::to_string(settings)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Create the provisioning campaign table if it doesn't exist
+async fn ensure_campaigns_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS campaigns (
+            id TEXT PRIMARY KEY,
+            data TEXT NOT NULL,
+            started_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn save_campaign(campaign: &crate::campaign::Campaign) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_campaigns_table(pool).await?;
+
+    sqlx::query(
+        "INSERT INTO campaigns (id, data, started_at) VALUES (?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET data = excluded.data"
+    )
+    .bind(campaign.id.to_string())
+    .bind(serde_json::to_string(campaign)?)
+    .bind(campaign.started_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_campaign(id: &Uuid) -> Result<Option<crate::campaign::Campaign>> {
+    let pool = get_pool().await?;
+    ensure_campaigns_table(pool).await?;
+
+    let row: Option<(String,)> = sqlx::query_as("SELECT data FROM campaigns WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some((data,)) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+// Newest first
+pub async fn list_campaigns() -> Result<Vec<crate::campaign::Campaign>> {
+    let pool = get_pool().await?;
+    ensure_campaigns_table(pool).await?;
+
+    let rows: Vec<(String,)> = sqlx::query_as("SELECT data FROM campaigns ORDER BY started_at DESC")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter()
+        .filter_map(|(data,)| serde_json::from_str(&data).ok())
+        .collect())
+}
+
+// Create the machine adoption table if it doesn't exist
+async fn ensure_adoptions_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_adoptions (
+            machine_id TEXT PRIMARY KEY,
+            method TEXT NOT NULL,
+            os_installed TEXT NOT NULL,
+            adopted_at TEXT NOT NULL,
+            agent_connected_at TEXT,
+            FOREIGN KEY (machine_id) REFERENCES machines(id) ON DELETE CASCADE
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Record a machine's adoption. One bootstrapped over SSH is marked Ready straight away, with the
+// OS it runs recorded as installed; a script adoption waits for its agent to report in
+pub async fn adopt_machine(machine_id: &Uuid, method: crate::adoption::AdoptionMethod, os_installed: &str) -> Result<crate::adoption::Adoption> {
+    let pool = get_pool().await?;
+    ensure_adoptions_table(pool).await?;
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "adopt_machine");
+    let mut tx = pool.begin().await?;
+
+    let now = Utc::now();
+    sqlx::query(
+        "INSERT INTO machine_adoptions (machine_id, method, os_installed, adopted_at, agent_connected_at) VALUES (?, ?, ?, ?, NULL)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            method = excluded.method,
+            os_installed = excluded.os_installed,
+            adopted_at = excluded.adopted_at,
+            agent_connected_at = NULL"
+    )
+    .bind(machine_id.to_string())
+    .bind(method.as_str())
+    .bind(os_installed)
+    .bind(now.to_rfc3339())
+    .execute(&mut *tx)
+    .await?;
+
+    let adoption = crate::adoption::Adoption {
+        machine_id: *machine_id,
+        method,
+        os_installed: os_installed.to_string(),
+        adopted_at: now,
+        agent_connected_at: None,
+    };
+    if method == crate::adoption::AdoptionMethod::Script {
+        tx.commit().await?;
+        return Ok(adoption);
+    }
+
+    let previous_status: Option<String> = sqlx::query_scalar("SELECT status FROM machines WHERE id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(&mut *tx)
+        .await?;
+    let status_json = serde_json::to_string(&MachineStatus::Ready)?;
+    sqlx::query("UPDATE machines SET status = ?, os_installed = ?, updated_at = ? WHERE id = ?")
+        .bind(&status_json)
+        .bind(os_installed)
+        .bind(now.to_rfc3339())
+        .bind(machine_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    record_status_change(pool, machine_id, previous_status.as_deref(), &status_json, &now.to_rfc3339()).await;
+
+    Ok(adoption)
+}
+
+pub async fn get_adoption(machine_id: &Uuid) -> Result<Option<crate::adoption::Adoption>> {
+    let pool = get_pool().await?;
+    ensure_adoptions_table(pool).await?;
+
+    let row: Option<(String, String, String, Option<String>)> = sqlx::query_as(
+        "SELECT method, os_installed, adopted_at, agent_connected_at FROM machine_adoptions WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(method, os_installed, adopted_at, agent_connected_at)| crate::adoption::Adoption {
+        machine_id: *machine_id,
+        method: crate::adoption::AdoptionMethod::parse(&method).unwrap_or(crate::adoption::AdoptionMethod::Script),
+        os_installed,
+        adopted_at: parse_datetime(&adopted_at),
+        agent_connected_at: agent_connected_at.as_deref().and_then(parse_rfc3339),
+    }))
+}
+
+// Note the adopted machine's agent reporting in, the first time it does
+pub async fn mark_adoption_connected(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_adoptions_table(pool).await?;
+
+    let result = sqlx::query(
+        "UPDATE machine_adoptions SET agent_connected_at = ? WHERE machine_id = ? AND agent_connected_at IS NULL"
+    )
+    .bind(Utc::now().to_rfc3339())
+    .bind(machine_id.to_string())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the SSH credential tables if they don't exist
+async fn ensure_ssh_credentials_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_ssh_credentials (
+            machine_id TEXT PRIMARY KEY,
+            username TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            private_key TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (machine_id) REFERENCES machines(id) ON DELETE CASCADE
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tag_ssh_credentials (
+            tag TEXT PRIMARY KEY COLLATE NOCASE,
+            username TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            private_key TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+fn decrypt_ssh_credentials(username: String, port: i64, private_key: &str) -> Result<crate::ssh::SshCredentials> {
+    Ok(crate::ssh::SshCredentials {
+        username,
+        port: u16::try_from(port).unwrap_or(22),
+        private_key: crate::encryption::decrypt_string(private_key)?,
+    })
+}
+
+// The private key is stored encrypted
+pub async fn save_ssh_credentials(scope: &crate::ssh::CredentialScope, credentials: &crate::ssh::SshCredentials) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_ssh_credentials_tables(pool).await?;
+
+    let (sql, key) = match scope {
+        crate::ssh::CredentialScope::Machine(id) => (
+            "INSERT INTO machine_ssh_credentials (machine_id, username, port, private_key, updated_at) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(machine_id) DO UPDATE SET
+                username = excluded.username,
+                port = excluded.port,
+                private_key = excluded.private_key,
+                updated_at = excluded.updated_at",
+            id.to_string(),
+        ),
+        crate::ssh::CredentialScope::Tag(tag) => (
+            "INSERT INTO tag_ssh_credentials (tag, username, port, private_key, updated_at) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(tag) DO UPDATE SET
+                username = excluded.username,
+                port = excluded.port,
+                private_key = excluded.private_key,
+                updated_at = excluded.updated_at",
+            tag.clone(),
+        ),
+    };
+    sqlx::query(sql)
+        .bind(key)
+        .bind(&credentials.username)
+        .bind(i64::from(credentials.port))
+        .bind(crate::encryption::encrypt_string(&credentials.private_key)?)
+        .bind(Utc::now().to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_ssh_credentials(scope: &crate::ssh::CredentialScope) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_ssh_credentials_tables(pool).await?;
+
+    let result = match scope {
+        crate::ssh::CredentialScope::Machine(id) => sqlx::query("DELETE FROM machine_ssh_credentials WHERE machine_id = ?")
+            .bind(id.to_string())
+            .execute(pool)
+            .await?,
+        crate::ssh::CredentialScope::Tag(tag) => sqlx::query("DELETE FROM tag_ssh_credentials WHERE tag = ?")
+            .bind(tag)
+            .execute(pool)
+            .await?,
+    };
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_ssh_credentials(scope: &crate::ssh::CredentialScope) -> Result<Option<(crate::ssh::SshCredentials, chrono::DateTime<Utc>)>> {
+    let pool = get_pool().await?;
+    ensure_ssh_credentials_tables(pool).await?;
+
+    let row: Option<(String, i64, String, String)> = match scope {
+        crate::ssh::CredentialScope::Machine(id) => sqlx::query_as(
+            "SELECT username, port, private_key, updated_at FROM machine_ssh_credentials WHERE machine_id = ?"
+        )
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?,
+        crate::ssh::CredentialScope::Tag(tag) => sqlx::query_as(
+            "SELECT username, port, private_key, updated_at FROM tag_ssh_credentials WHERE tag = ?"
+        )
+        .bind(tag)
+        .fetch_optional(pool)
+        .await?,
+    };
+
+    row.map(|(username, port, private_key, updated_at)| {
+        Ok((decrypt_ssh_credentials(username, port, &private_key)?, parse_datetime(&updated_at)))
+    })
+    .transpose()
+}
+
+// Credentials that can't be decrypted (the encryption key changed) are skipped
+pub async fn list_tag_ssh_credentials() -> Result<Vec<(String, crate::ssh::SshCredentials, chrono::DateTime<Utc>)>> {
+    let pool = get_pool().await?;
+    ensure_ssh_credentials_tables(pool).await?;
+
+    let rows: Vec<(String, String, i64, String, String)> = sqlx::query_as(
+        "SELECT tag, username, port, private_key, updated_at FROM tag_ssh_credentials"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter()
+        .filter_map(|(tag, username, port, private_key, updated_at)| match decrypt_ssh_credentials(username, port, &private_key) {
+            Ok(credentials) => Some((tag, credentials, parse_datetime(&updated_at))),
+            Err(e) => {
+                warn!("Failed to decrypt SSH credentials for tag {}: {}", tag, e);
+                None
+            }
+        })
+        .collect())
+}
+
+// Create the boot interface preference table if it doesn't exist
+async fn ensure_boot_interfaces_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_boot_interfaces (
+            machine_id TEXT PRIMARY KEY,
+            boot_mac TEXT NOT NULL COLLATE NOCASE,
+            fallback_macs TEXT NOT NULL,
+            dhcp_attempts INTEGER NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (machine_id) REFERENCES machines(id) ON DELETE CASCADE
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+fn boot_interfaces_from_row(row: (String, String, String, i64, String)) -> Result<crate::boot_interface::BootInterfaces> {
+    let (machine_id, boot_mac, fallback_macs, dhcp_attempts, updated_at) = row;
+    Ok(crate::boot_interface::BootInterfaces {
+        machine_id: Uuid::parse_str(&machine_id)?,
+        boot_mac,
+        fallback_macs: serde_json::from_str(&fallback_macs)?,
+        dhcp_attempts: dhcp_attempts.clamp(1, u8::MAX as i64) as u8,
+        updated_at: parse_datetime(&updated_at),
+    })
+}
+
+pub async fn save_boot_interfaces(interfaces: &crate::boot_interface::BootInterfaces) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_boot_interfaces_table(pool).await?;
+
+    sqlx::query(
+        "INSERT INTO machine_boot_interfaces (machine_id, boot_mac, fallback_macs, dhcp_attempts, updated_at) VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET
+            boot_mac = excluded.boot_mac,
+            fallback_macs = excluded.fallback_macs,
+            dhcp_attempts = excluded.dhcp_attempts,
+            updated_at = excluded.updated_at"
+    )
+    .bind(interfaces.machine_id.to_string())
+    .bind(&interfaces.boot_mac)
+    .bind(serde_json::to_string(&interfaces.fallback_macs)?)
+    .bind(interfaces.dhcp_attempts as i64)
+    .bind(interfaces.updated_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Returns false if the machine had no preference
+pub async fn delete_boot_interfaces(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_boot_interfaces_table(pool).await?;
+
+    let result = sqlx::query("DELETE FROM machine_boot_interfaces WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_boot_interfaces(machine_id: &Uuid) -> Result<Option<crate::boot_interface::BootInterfaces>> {
+    let pool = get_pool().await?;
+    ensure_boot_interfaces_table(pool).await?;
+
+    let row: Option<(String, String, String, i64, String)> = sqlx::query_as(
+        "SELECT machine_id, boot_mac, fallback_macs, dhcp_attempts, updated_at FROM machine_boot_interfaces WHERE machine_id = ?"
+    )
+    .bind(machine_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(boot_interfaces_from_row).transpose()
+}
+
+// The preference naming a MAC as a boot or fallback NIC (MACs are stored lower-case)
+pub async fn find_boot_interfaces_by_mac(mac: &str) -> Result<Option<crate::boot_interface::BootInterfaces>> {
+    let pool = get_pool().await?;
+    ensure_boot_interfaces_table(pool).await?;
+
+    let row: Option<(String, String, String, i64, String)> = sqlx::query_as(
+        "SELECT machine_id, boot_mac, fallback_macs, dhcp_attempts, updated_at FROM machine_boot_interfaces
+         WHERE boot_mac = ? OR EXISTS (SELECT 1 FROM json_each(fallback_macs) WHERE value = ?)
+         LIMIT 1"
+    )
+    .bind(mac)
+    .bind(mac)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(boot_interfaces_from_row).transpose()
+}
+
+// Create the onboarding tables: the allowlist settings (a single JSON row) and registrations
+// from MACs that aren't on it
+async fn ensure_onboarding_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS onboarding_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            settings TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS pending_approvals (
+            id TEXT PRIMARY KEY,
+            mac_address TEXT NOT NULL UNIQUE,
+            status TEXT NOT NULL,
+            request TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            last_seen_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_onboarding_settings() -> Result<crate::onboarding::OnboardingSettings> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT settings FROM onboarding_settings WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((settings,)) => Ok(serde_json::from_str(&settings)?),
+        None => Ok(Default::default()),
+    }
+}
+
+pub async fn save_onboarding_settings(settings: &crate::onboarding::OnboardingSettings) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO onboarding_settings (id, settings, updated_at) VALUES (1, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            settings = excluded.settings,
+            updated_at = excluded.updated_at"
+    )
+    .bind(serde_json::to_string(settings)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Store (or refresh) a registration held for approval
+pub async fn save_approval(approval: &crate::onboarding::PendingApproval) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO pending_approvals (id, mac_address, status, request, attempts, created_at, last_seen_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            status = excluded.status,
+            request = excluded.request,
+            attempts = excluded.attempts,
+            last_seen_at = excluded.last_seen_at"
+    )
+    .bind(approval.id.to_string())
+    .bind(&approval.mac_address)
+    .bind(approval.status.as_str())
+    .bind(serde_json::to_string(&approval.request)?)
+    .bind(approval.attempts as i64)
+    .bind(approval.created_at.to_rfc3339())
+    .bind(approval.last_seen_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn row_to_approval(row: &sqlx::sqlite::SqliteRow) -> Result<crate::onboarding::PendingApproval> {
+    let id: String = row.get("id");
+    let status: String = row.get("status");
+    let request: String = row.get("request");
+    let created_at: String = row.get("created_at");
+    let last_seen_at: String = row.get("last_seen_at");
+    
+    Ok(crate::onboarding::PendingApproval {
+        id: Uuid::parse_str(&id)?,
+        mac_address: row.get("mac_address"),
+        status: crate::onboarding::ApprovalStatus::parse(&status)
+            .ok_or_else(|| anyhow!("Unknown approval status '{}'", status))?,
+        request: serde_json::from_str(&request)?,
+        attempts: row.get::<i64, _>("attempts").max(0) as u32,
+        created_at: parse_datetime(&created_at),
+        last_seen_at: parse_datetime(&last_seen_at),
+    })
+}
+
+// Registrations held for approval (and rejected ones), oldest first
+pub async fn list_approvals() -> Result<Vec<crate::onboarding::PendingApproval>> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    let rows = sqlx::query("SELECT * FROM pending_approvals ORDER BY created_at ASC")
+        .fetch_all(pool)
+        .await?;
+    
+    rows.iter().map(row_to_approval).collect()
+}
+
+pub async fn get_approval(id: &Uuid) -> Result<Option<crate::onboarding::PendingApproval>> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM pending_approvals WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_approval).transpose()
+}
+
+pub async fn get_approval_by_mac(mac_address: &str) -> Result<Option<crate::onboarding::PendingApproval>> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    let row = sqlx::query("SELECT * FROM pending_approvals WHERE mac_address = ?")
+        .bind(mac_address.to_lowercase())
+        .fetch_optional(pool)
+        .await?;
+    
+    row.as_ref().map(row_to_approval).transpose()
+}
+
+// Forget an approval once it's approved, or so a rejected MAC is asked about again
+pub async fn delete_approval(id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_onboarding_tables(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM pending_approvals WHERE id = ?")
+        .bind(id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the two-person rule settings and destructive action approval tables if they don't exist
+async fn ensure_action_approval_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS action_approval_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            settings TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS action_approvals (
+            id TEXT PRIMARY KEY,
+            approval TEXT NOT NULL,
+            state TEXT NOT NULL,
+            requested_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_action_approval_settings() -> Result<crate::action_approvals::ApprovalSettings> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT settings FROM action_approval_settings WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((settings,)) => Ok(serde_json::from_str(&settings)?),
+        None => Ok(Default::default()),
+    }
+}
+
+pub async fn save_action_approval_settings(settings: &crate::action_approvals::ApprovalSettings) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO action_approval_settings (id, settings, updated_at) VALUES (1, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            settings = excluded.settings,
+            updated_at = excluded.updated_at"
+    )
+    .bind(serde_json::to_string(settings)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+fn action_approval_state(approval: &crate::action_approvals::ActionApproval) -> Result<String> {
+    Ok(serde_json::to_value(approval.state)?.as_str().unwrap_or_default().to_string())
+}
+
+pub async fn save_action_approval(approval: &crate::action_approvals::ActionApproval) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    sqlx::query("INSERT INTO action_approvals (id, approval, state, requested_at) VALUES (?, ?, ?, ?)")
+        .bind(approval.id.to_string())
+        .bind(serde_json::to_string(approval)?)
+        .bind(action_approval_state(approval)?)
+        .bind(approval.requested_at.to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn get_action_approval(id: &Uuid) -> Result<Option<crate::action_approvals::ActionApproval>> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    let row: Option<(String,)> = sqlx::query_as("SELECT approval FROM action_approvals WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(match row {
+        Some((approval,)) => Some(serde_json::from_str(&approval)?),
+        None => None,
+    })
+}
+
+// Newest first
+pub async fn list_action_approvals(limit: i64) -> Result<Vec<crate::action_approvals::ActionApproval>> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    let rows: Vec<(String,)> = sqlx::query_as("SELECT approval FROM action_approvals ORDER BY requested_at DESC LIMIT ?")
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.into_iter().filter_map(|(approval,)| serde_json::from_str(&approval).ok()).collect())
+}
+
+// Store a decision, but only over a still pending request; false if it had already been decided
+pub async fn decide_action_approval(approval: &crate::action_approvals::ActionApproval) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    let result = sqlx::query("UPDATE action_approvals SET approval = ?, state = ? WHERE id = ? AND state = 'pending'")
+        .bind(serde_json::to_string(approval)?)
+        .bind(action_approval_state(approval)?)
+        .bind(approval.id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn update_action_approval(approval: &crate::action_approvals::ActionApproval) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_action_approval_tables(pool).await?;
+    
+    sqlx::query("UPDATE action_approvals SET approval = ?, state = ? WHERE id = ?")
+        .bind(serde_json::to_string(approval)?)
+        .bind(action_approval_state(approval)?)
+        .bind(approval.id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Create the machine status history table if it doesn't exist
+async fn ensure_status_history_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS machine_status_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            machine_id TEXT NOT NULL,
+            from_status TEXT,
+            to_status TEXT NOT NULL,
+            changed_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_machine_status_history_machine ON machine_status_history (machine_id, id)")
+        .execute(pool)
+        .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_machine_status_history_changed ON machine_status_history (changed_at)")
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// A machine's stored (JSON) status, read before it's overwritten
+async fn current_status(pool: &Pool<Sqlite>, id: &Uuid) -> Result<Option<String>> {
+    let status = sqlx::query_scalar("SELECT status FROM machines WHERE id = ?")
+        .bind(id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    Ok(status)
+}
+
+// Append a status change to the history; kept for SLA reporting, not purged by retention.
+// A failure here is logged rather than failing the status update it follows.
+async fn record_status_change(pool: &Pool<Sqlite>, id: &Uuid, from: Option<&str>, to: &str, changed_at: &str) {
+    if from == Some(to) {
+        return;
+    }
+    let result = async {
+        ensure_status_history_table(pool).await?;
+        sqlx::query("INSERT INTO machine_status_history (machine_id, from_status, to_status, changed_at) VALUES (?, ?, ?, ?)")
+            .bind(id.to_string())
+            .bind(from)
+            .bind(to)
+            .bind(changed_at)
+            .execute(pool)
+            .await?;
+        anyhow::Ok(())
+    }.await;
+    if let Err(e) = result {
+        warn!("Failed to record status history for machine {}: {}", id, e);
+    }
+}
+
+type StatusChangeRow = (String, Option<String>, String, String);
+
+fn parse_status_change((machine_id, from, to, changed_at): StatusChangeRow) -> Option<crate::state_history::StatusChange> {
+    Some(crate::state_history::StatusChange {
+        machine_id: Uuid::parse_str(&machine_id).ok()?,
+        from: from.as_deref().map(parse_status),
+        to: parse_status(&to),
+        changed_at: parse_rfc3339(&changed_at)?,
+    })
+}
+
+// A machine's status changes, oldest first
+pub async fn list_status_changes(machine_id: &Uuid) -> Result<Vec<crate::state_history::StatusChange>> {
+    let pool = get_pool().await?;
+    ensure_status_history_table(pool).await?;
+    
+    let rows: Vec<StatusChangeRow> = sqlx::query_as(
+        "SELECT machine_id, from_status, to_status, changed_at FROM machine_status_history
+         WHERE machine_id = ? ORDER BY id"
+    )
+    .bind(machine_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().filter_map(parse_status_change).collect())
+}
+
+// Status changes from `since` on, plus each machine's last change before it, oldest first
+pub async fn status_changes_since(since: chrono::DateTime<Utc>) -> Result<Vec<crate::state_history::StatusChange>> {
+    let pool = get_pool().await?;
+    ensure_status_history_table(pool).await?;
+    
+    let since = since.to_rfc3339();
+    let rows: Vec<StatusChangeRow> = sqlx::query_as(
+        "SELECT machine_id, from_status, to_status, changed_at FROM machine_status_history
+         WHERE changed_at >= ?
+            OR id IN (SELECT MAX(id) FROM machine_status_history WHERE changed_at < ? GROUP BY machine_id)
+         ORDER BY machine_id, id"
+    )
+    .bind(&since)
+    .bind(&since)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(rows.into_iter().filter_map(parse_status_change).collect())
+}
+
+// Create the boot loop warnings table if it doesn't exist
+async fn ensure_boot_loops_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS boot_loops (
+            machine_id TEXT PRIMARY KEY,
+            details TEXT NOT NULL,
+            detected_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_boot_loop(machine_id: &Uuid) -> Result<Option<crate::boot_loop::BootLoop>> {
+    let pool = get_pool().await?;
+    ensure_boot_loops_table(pool).await?;
+    
+    let details: Option<String> = sqlx::query_scalar("SELECT details FROM boot_loops WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .fetch_optional(pool)
+        .await?;
+    
+    Ok(details.map(|d| serde_json::from_str(&d)).transpose()?)
+}
+
+pub async fn save_boot_loop(boot_loop: &crate::boot_loop::BootLoop) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_boot_loops_table(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO boot_loops (machine_id, details, detected_at) VALUES (?, ?, ?)
+         ON CONFLICT(machine_id) DO UPDATE SET details = excluded.details, detected_at = excluded.detected_at"
+    )
+    .bind(boot_loop.machine_id.to_string())
+    .bind(serde_json::to_string(boot_loop)?)
+    .bind(boot_loop.detected_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn delete_boot_loop(machine_id: &Uuid) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_boot_loops_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM boot_loops WHERE machine_id = ?")
+        .bind(machine_id.to_string())
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the Tinkerbell outbox table if it doesn't exist
+async fn ensure_tinkerbell_outbox_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tinkerbell_outbox (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            machine_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            operation TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            created_at TEXT NOT NULL,
+            last_attempt_at TEXT
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Queue a Tinkerbell operation, replacing any queued operation of the same kind for the machine
+pub async fn enqueue_tinkerbell_op(machine_id: &Uuid, operation: &crate::tinkerbell_outbox::Operation) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_tinkerbell_outbox_table(pool).await?;
+    
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM tinkerbell_outbox WHERE machine_id = ? AND kind = ?")
+        .bind(machine_id.to_string())
+        .bind(operation.kind())
+        .execute(&mut *tx)
+        .await?;
+    // A queued workflow is moot once the machine's hardware is being deleted
+    if matches!(operation, crate::tinkerbell_outbox::Operation::DeleteHardware { .. }) {
+        sqlx::query("DELETE FROM tinkerbell_outbox WHERE machine_id = ? AND kind = 'create_workflow'")
+            .bind(machine_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query("INSERT INTO tinkerbell_outbox (machine_id, kind, operation, created_at) VALUES (?, ?, ?, ?)")
+        .bind(machine_id.to_string())
+        .bind(operation.kind())
+        .bind(serde_json::to_string(operation)?)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    
+    Ok(())
+}
+
+// Queued Tinkerbell operations, oldest first
+pub async fn list_tinkerbell_ops() -> Result<Vec<crate::tinkerbell_outbox::PendingOperation>> {
+    let pool = get_pool().await?;
+    ensure_tinkerbell_outbox_table(pool).await?;
+    
+    let rows = sqlx::query(
+        "SELECT id, machine_id, operation, attempts, last_error, created_at, last_attempt_at
+         FROM tinkerbell_outbox ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+    
+    let mut pending = Vec::new();
+    for row in rows {
+        let machine_id: String = row.try_get("machine_id")?;
+        let operation: String = row.try_get("operation")?;
+        let created_at: String = row.try_get("created_at")?;
+        let last_attempt_at: Option<String> = row.try_get("last_attempt_at")?;
+        pending.push(crate::tinkerbell_outbox::PendingOperation {
+            id: row.try_get("id")?,
+            machine_id: Uuid::parse_str(&machine_id)?,
+            operation: serde_json::from_str(&operation)?,
+            attempts: row.try_get::<i64, _>("attempts")? as u32,
+            last_error: row.try_get("last_error")?,
+            created_at: parse_datetime(&created_at),
+            last_attempt_at: last_attempt_at.as_deref().map(parse_datetime),
+        });
+    }
+    Ok(pending)
+}
+
+// Record a failed replay, leaving the operation queued
+pub async fn record_tinkerbell_op_failure(id: i64, error: &str) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_tinkerbell_outbox_table(pool).await?;
+    
+    sqlx::query("UPDATE tinkerbell_outbox SET attempts = attempts + 1, last_error = ?, last_attempt_at = ? WHERE id = ?")
+        .bind(error)
+        .bind(Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+pub async fn delete_tinkerbell_op(id: i64) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_tinkerbell_outbox_table(pool).await?;
+    
+    let result = sqlx::query("DELETE FROM tinkerbell_outbox WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+// Create the artifact replication tables if they don't exist
+async fn ensure_replication_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS replication_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            settings TEXT NOT NULL,
+            key TEXT,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS replication_sites (
+            site TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            reported_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_replication_settings() -> Result<crate::replication::ReplicationSettings> {
+    let pool = get_pool().await?;
+    ensure_replication_tables(pool).await?;
+    
+    let row: Option<(String, Option<String>)> = sqlx::query_as("SELECT settings, key FROM replication_settings WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((settings, key)) => {
+            let mut settings: crate::replication::ReplicationSettings = serde_json::from_str(&settings)?;
+            // The shared key is stored encrypted
+            settings.key = key.map(|k| crate::encryption::decrypt_string(&k)).transpose()?;
+            Ok(settings)
+        }
+        None => Ok(Default::default()),
+    }
+}
+
+pub async fn save_replication_settings(settings: &crate::replication::ReplicationSettings) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_replication_tables(pool).await?;
+    
+    let key = settings.key.as_deref().map(crate::encryption::encrypt_string).transpose()?;
+    sqlx::query(
+        "INSERT INTO replication_settings (id, settings, key, updated_at) VALUES (1, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            settings = excluded.settings,
+            key = excluded.key,
+            updated_at = excluded.updated_at"
+    )
+    .bind(serde_json::to_string(settings)?)
+    .bind(key)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn get_replication_site(site: &str) -> Result<Option<(crate::replication::SiteStatus, chrono::DateTime<Utc>)>> {
+    let pool = get_pool().await?;
+    ensure_replication_tables(pool).await?;
+    
+    let row: Option<(String, String)> = sqlx::query_as("SELECT status, reported_at FROM replication_sites WHERE site = ?")
+        .bind(site)
+        .fetch_optional(pool)
+        .await?;
+    
+    match row {
+        Some((status, reported_at)) => Ok(Some((serde_json::from_str(&status)?, parse_datetime(&reported_at)))),
+        None => Ok(None),
+    }
+}
+
+// Store a site's latest replication status, replacing its previous one
+pub async fn save_replication_site(status: &crate::replication::SiteStatus) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_replication_tables(pool).await?;
+    
+    sqlx::query(
+        "INSERT INTO replication_sites (site, status, reported_at) VALUES (?, ?, ?)
+         ON CONFLICT(site) DO UPDATE SET status = excluded.status, reported_at = excluded.reported_at"
+    )
+    .bind(&status.site)
+    .bind(serde_json::to_string(status)?)
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+pub async fn list_replication_sites() -> Result<Vec<(crate::replication::SiteStatus, chrono::DateTime<Utc>)>> {
+    let pool = get_pool().await?;
+    ensure_replication_tables(pool).await?;
+    
+    let rows: Vec<(String, String)> = sqlx::query_as("SELECT status, reported_at FROM replication_sites ORDER BY site")
+        .fetch_all(pool)
+        .await?;
+    
+    Ok(rows.into_iter()
+        .filter_map(|(status, reported_at)| Some((serde_json::from_str(&status).ok()?, parse_datetime(&reported_at))))
+        .collect())
+}
+
+// Create the leader lease table if it doesn't exist
+async fn ensure_leases_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS leases (
+            name TEXT PRIMARY KEY,
+            holder TEXT NOT NULL,
+            acquired_at TEXT NOT NULL,
+            renewed_at TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Take or renew a lease in one statement, so two replicas can't both win it. Succeeds when
+// the lease is free, expired or already ours.
+pub async fn try_acquire_lease(name: &str, holder: &str, ttl: std::time::Duration) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_leases_table(pool).await?;
+
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::from_std(ttl)?;
+    let result = sqlx::query(
+        "INSERT INTO leases (name, holder, acquired_at, renewed_at, expires_at) VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(name) DO UPDATE SET
+            acquired_at = CASE WHEN leases.holder = excluded.holder THEN leases.acquired_at ELSE excluded.acquired_at END,
+            holder = excluded.holder,
+            renewed_at = excluded.renewed_at,
+            expires_at = excluded.expires_at
+         WHERE leases.holder = excluded.holder OR leases.expires_at < ?"
+    )
+    .bind(name)
+    .bind(holder)
+    .bind(now.to_rfc3339())
+    .bind(now.to_rfc3339())
+    .bind(expires_at.timestamp_millis())
+    .bind(now.timestamp_millis())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Give up a lease we hold so another replica can take over without waiting for it to expire
+pub async fn release_lease(name: &str, holder: &str) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_leases_table(pool).await?;
+
+    sqlx::query("DELETE FROM leases WHERE name = ? AND holder = ?")
+        .bind(name)
+        .bind(holder)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_lease(name: &str) -> Result<Option<crate::leader::Lease>> {
+    let pool = get_pool().await?;
+    ensure_leases_table(pool).await?;
+
+    let row: Option<(String, String, String, i64)> = sqlx::query_as(
+        "SELECT holder, acquired_at, renewed_at, expires_at FROM leases WHERE name = ?"
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(holder, acquired_at, renewed_at, expires_at)| crate::leader::Lease {
+        holder,
+        acquired_at: parse_datetime(&acquired_at),
+        renewed_at: parse_datetime(&renewed_at),
+        expires_at: chrono::DateTime::from_timestamp_millis(expires_at).unwrap_or_else(Utc::now),
+    }))
+}
+
+// Create the limited-access token table if it doesn't exist
+async fn ensure_access_tokens_table(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS access_tokens (
+            token_hash TEXT PRIMARY KEY,
+            scope TEXT NOT NULL,
+            machine_id TEXT,
+            created_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+// Store the hash of a newly issued limited-access token
+pub async fn save_access_token(token_hash: &str, scope: &str, machine_id: Option<&Uuid>) -> Result<()> {
+    let pool = get_pool().await?;
+    ensure_access_tokens_table(pool).await?;
+    
+    sqlx::query("INSERT INTO access_tokens (token_hash, scope, machine_id, created_at) VALUES (?, ?, ?, ?)")
+        .bind(token_hash)
+        .bind(scope)
+        .bind(machine_id.map(|id| id.to_string()))
+        .bind(Utc::now().to_rfc3339())
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+// Check whether a token hash was issued for this scope (and machine, if bound to one)
+pub async fn access_token_exists(token_hash: &str, scope: &str, machine_id: Option<&Uuid>) -> Result<bool> {
+    let pool = get_pool().await?;
+    ensure_access_tokens_table(pool).await?;
+    
+    let row = sqlx::query(
+        "SELECT COUNT(*) FROM access_tokens WHERE token_hash = ? AND scope = ? AND machine_id IS ?"
+    )
+    .bind(token_hash)
+    .bind(scope)
+    .bind(machine_id.map(|id| id.to_string()))
+    .fetch_one(pool)
+    .await?;
+    
+    let count: i64 = row.get(0);
+    Ok(count > 0)
+}
+
+// Fetch a machine by its memorable name (used by the public status page)
+pub async fn get_machine_by_memorable_name(name: &str) -> Result<Option<Machine>> {
+    let pool = get_pool().await?;
+    
+    let result = sqlx::query(
+        r#"
+        SELECT 
+               id, mac_address, ip_address, hostname, status, os_choice, os_installed, 
+               disks, nameservers, memorable_name, created_at, updated_at, bmc_credentials, 
+               installation_progress, installation_step, last_deployment_duration,
+               cpu_model, cpu_cores, total_ram_bytes, 
+               proxmox_vmid, proxmox_node, proxmox_cluster, is_proxmox_host, tinkerbell_synced
+        FROM machines 
+        WHERE memorable_name = ?
+        LIMIT 1
+        "#,
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+    
+    match result {
+        Some(row) => Ok(Some(map_row_to_machine_with_hardware(row)?)),
+        None => Ok(None),
+    }
+}
+
+// Get all machines with a specific status
+pub async fn get_machines_by_status(status: dragonfly_common::models::MachineStatus) -> Result<Vec<dragonfly_common::models::Machine>> {
+    let pool = get_pool().await?;
+    
+    // Convert the status to a JSON string for comparison
+    let status_json = serde_json::to_string(&status)?;
+    
+    // Use regular query instead of query macro to avoid compile-time verification issues
+    let rows = sqlx::query(
+        "SELECT * FROM machines WHERE status = ?"
+    )
+    .bind(status_json)
+    .fetch_all(pool)
+    .await?;
+    
+    let mut machines = Vec::with_capacity(rows.len());
+    for row in rows {
+        machines.push(map_row_to_machine_with_hardware(row)?);
+    }
+    
+    Ok(machines)
+}
+
+// NEW helper function to map a row including hardware info
+fn map_row_to_machine_with_hardware(row: sqlx::sqlite::SqliteRow) -> Result<Machine> {
+    use sqlx::Row;
+    
+    let id: String = row.try_get("id")?;
+    let mac_address: String = row.try_get("mac_address")?;
+    let status_str: String = row.try_get("status")?;
+    let disks_json: Option<String> = row.try_get("disks")?;
+    let nameservers_json: Option<String> = row.try_get("nameservers")?;
+    let bmc_credentials_json: Option<String> = row.try_get("bmc_credentials")?;
+    let last_deployment_duration: Option<i64> = row.try_get("last_deployment_duration").ok();
+    
+    // Map hardware info (use try_get for Option types)
+    let cpu_model: Option<String> = row.try_get("cpu_model")?;
+    let cpu_cores_i64: Option<i64> = row.try_get("cpu_cores")?;
+    let cpu_cores: Option<u32> = cpu_cores_i64.map(|c| c as u32);
+    let total_ram_bytes_i64: Option<i64> = row.try_get("total_ram_bytes")?;
+    let total_ram_bytes: Option<u64> = total_ram_bytes_i64.map(|r| r as u64);
+    
+    // Map Proxmox specific fields
+    let proxmox_vmid_i64: Option<i64> = row.try_get("proxmox_vmid").ok();
+    let proxmox_vmid: Option<u32> = proxmox_vmid_i64.map(|vmid| vmid as u32);
+    let proxmox_node: Option<String> = row.try_get("proxmox_node").ok();
+    let memorable_name: Option<String> = row.try_get("memorable_name").ok();
+    let proxmox_cluster: Option<String> = row.try_get("proxmox_cluster").ok();
+    
+    // Generate memorable name from MAC address if not already stored
+    let memorable_name = memorable_name.unwrap_or_else(|| 
+        dragonfly_common::mac_to_words::mac_to_words_safe(&mac_address)
+    );
+    
+    // Deserialize disks and nameservers from JSON or use empty vectors if null
+    let mut disks = if let Some(json) = disks_json {
         serde_json::from_str::<Vec<dragonfly_common::models::DiskInfo>>(&json).unwrap_or_else(|_| Vec::new())
     } else {
         Vec::new()
@@ -1795,6 +6749,7 @@ fn map_row_to_machine_with_hardware(row: sqlx::sqlite::SqliteRow) -> Result<Mach
         proxmox_node,
         proxmox_cluster,
         is_proxmox_host: row.try_get("is_proxmox_host")?,
+        tinkerbell_synced: row.try_get::<Option<bool>, _>("tinkerbell_synced").unwrap_or(None).unwrap_or(true),
     })
 }
 
@@ -1941,6 +6896,22 @@ pub async fn get_machine_tags(id: &Uuid) -> Result<Vec<String>> {
     Ok(tags)
 }
 
+// Tags of every machine that has any, for filtering the fleet in one query
+pub async fn get_all_machine_tags() -> Result<std::collections::HashMap<Uuid, Vec<String>>> {
+    let pool = get_pool().await?;
+    let rows = sqlx::query("SELECT machine_id, tag_name FROM machine_tags ORDER BY tag_name ASC")
+        .fetch_all(pool)
+        .await?;
+
+    let mut tags: std::collections::HashMap<Uuid, Vec<String>> = std::collections::HashMap::new();
+    for row in rows {
+        if let Ok(id) = Uuid::parse_str(&row.get::<String, _>("machine_id")) {
+            tags.entry(id).or_default().push(row.get("tag_name"));
+        }
+    }
+    Ok(tags)
+}
+
 // Update tags for a specific machine
 pub async fn update_machine_tags(id: &Uuid, tags: &[String]) -> Result<bool> {
     let pool = DB_POOL.get().ok_or_else(|| anyhow!("Database not initialized"))?;
@@ -1956,6 +6927,7 @@ pub async fn update_machine_tags(id: &Uuid, tags: &[String]) -> Result<bool> {
     }
     
     // Start a transaction
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "update_machine_tags");
     let mut tx = pool.begin().await?;
     
     // Delete all existing tags for this machine
@@ -2661,6 +7633,7 @@ pub async fn update_proxmox_tokens(
     };
     
     // Update the tokens in one transaction
+    let _work = crate::drain::track(crate::drain::WorkKind::DbTransaction, "update_proxmox_tokens");
     let mut transaction = pool.begin().await?;
     
     sqlx::query(
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::RegisterRequest;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A registration from an unknown MAC whose hardware matches a known machine.
+/// It is held until an admin either merges it into that machine or accepts it as new.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingMerge {
+    pub id: Uuid,
+    pub existing_machine_id: Uuid,
+    pub request: RegisterRequest,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Check whether a registration is a known machine with a replaced NIC.
+/// Returns the pending merge (new or refreshed) if the registration should be held.
+pub async fn check_registration(req: &RegisterRequest) -> Result<Option<PendingMerge>> {
+    let fingerprint = match &req.hardware_fingerprint {
+        Some(fp) if !fp.is_empty() => fp,
+        _ => return Ok(None),
+    };
+
+    // Re-registration from a MAC we already know is a normal update
+    if crate::db::get_machine_by_mac(&req.mac_address).await?.is_some() {
+        return Ok(None);
+    }
+
+    let existing_machine_id = match crate::db::find_machine_by_fingerprint(fingerprint, &req.mac_address).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    // Agents retry registration, so keep one pending merge per MAC
+    let merge = match crate::db::get_pending_merge_by_mac(&req.mac_address).await? {
+        Some(existing) => PendingMerge {
+            existing_machine_id,
+            request: req.clone(),
+            ..existing
+        },
+        None => PendingMerge {
+            id: Uuid::new_v4(),
+            existing_machine_id,
+            request: req.clone(),
+            created_at: Utc::now(),
+        },
+    };
+
+    crate::db::save_pending_merge(&merge).await?;
+    info!(
+        "Registration from MAC {} matches hardware of machine {}; holding as pending merge {}",
+        req.mac_address, existing_machine_id, merge.id
+    );
+
+    Ok(Some(merge))
+}
+
+/// Move the existing machine onto the new NIC and drop the pending merge.
+pub async fn approve(merge_id: &Uuid) -> Result<Uuid> {
+    let merge = crate::db::get_pending_merge(merge_id).await?
+        .ok_or_else(|| anyhow!("Pending merge {} not found", merge_id))?;
+    let machine = crate::db::get_machine_by_id(&merge.existing_machine_id).await?
+        .ok_or_else(|| anyhow!("Machine {} no longer exists", merge.existing_machine_id))?;
+
+    // Tinkerbell hardware is keyed by MAC, so the old record has to go
+    if let Err(e) = crate::tinkerbell_outbox::delete_hardware(&*crate::tinkerbell_client::client(), &machine.id, &machine.mac_address).await {
+        warn!("Failed to remove old Tinkerbell hardware for {} (continuing anyway): {}", machine.mac_address, e);
+    }
+
+    crate::db::update_machine_identity(&machine.id, &merge.request.mac_address, &merge.request.ip_address).await?;
+
+    if let Some(updated) = crate::db::get_machine_by_id(&machine.id).await? {
+        crate::tinkerbell_sync::sync_or_queue(&*crate::tinkerbell_client::client(), &updated).await;
+    }
+
+    crate::db::delete_pending_merge(merge_id).await?;
+    info!(
+        "Merged registration from MAC {} into machine {} (was {})",
+        merge.request.mac_address, machine.id, machine.mac_address
+    );
+
+    Ok(machine.id)
+}
+
+/// Register the held request as a separate machine and drop the pending merge.
+pub async fn reject(merge_id: &Uuid) -> Result<Uuid> {
+    let merge = crate::db::get_pending_merge(merge_id).await?
+        .ok_or_else(|| anyhow!("Pending merge {} not found", merge_id))?;
+
+    let machine_id = crate::db::register_machine(&merge.request).await?;
+    if let Ok(Some(machine)) = crate::db::get_machine_by_id(&machine_id).await {
+        crate::tinkerbell_sync::sync_or_queue(&*crate::tinkerbell_client::client(), &machine).await;
+    }
+
+    crate::db::delete_pending_merge(merge_id).await?;
+    info!("Registered MAC {} as new machine {} instead of merging", merge.request.mac_address, machine_id);
+
+    Ok(machine_id)
+}
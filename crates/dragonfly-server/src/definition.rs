@@ -0,0 +1,303 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{DiskInfo, Machine};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+
+/// Bumped whenever the bundle layout changes incompatibly.
+pub const DEFINITION_VERSION: u32 = 1;
+
+/// Per-machine provisioning inputs that aren't part of the machine row.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProvisioningConfig {
+    /// Cloud-init user data, handed to Tinkerbell as the hardware record's userData
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_init: Option<String>,
+    /// Name of the partitioning profile the OS template should use, optionally followed by
+    /// multi-disk imaging options (see disk_layout)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partitioning: Option<String>,
+    /// Extra kernel command line arguments for the installed OS, passed to templates as the
+    /// kernel_args hardwareMap entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel_args: Option<String>,
+}
+
+impl ProvisioningConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Err(e) = crate::disk_layout::DiskLayout::parse(self.partitioning.as_deref()) {
+            bail!("Invalid partitioning profile: {}", e);
+        }
+        if let Some(args) = &self.kernel_args {
+            // Templates splice these into a single line of the bootloader config
+            if args.chars().any(|c| c.is_control()) || args.contains(['"', '\'']) {
+                bail!("Kernel arguments must be a single line without quotes");
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in whatever this config leaves unset from `fallback`.
+    pub fn or(self, fallback: &ProvisioningConfig) -> ProvisioningConfig {
+        ProvisioningConfig {
+            cloud_init: self.cloud_init.or_else(|| fallback.cloud_init.clone()),
+            partitioning: self.partitioning.or_else(|| fallback.partitioning.clone()),
+            kernel_args: self.kernel_args.or_else(|| fallback.kernel_args.clone()),
+        }
+    }
+}
+
+/// What the original hardware looked like, used to flag unsuitable replacements.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardwareIdentity {
+    pub mac_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_cores: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_ram_bytes: Option<u64>,
+    #[serde(default)]
+    pub disks: Vec<DiskInfo>,
+}
+
+/// A portable snapshot of everything needed to provision a machine the same way again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineDefinition {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub source_machine_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memorable_name: Option<String>,
+    pub hardware: HardwareIdentity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_choice: Option<String>,
+    #[serde(flatten)]
+    pub provisioning: ProvisioningConfig,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Outcome of restoring a definition onto a machine.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub machine_id: Uuid,
+    /// Fields written to the target machine
+    pub applied: Vec<String>,
+    /// Ways the target hardware falls short of the original
+    pub warnings: Vec<String>,
+}
+
+impl MachineDefinition {
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let definition: MachineDefinition = serde_yaml::from_str(yaml)
+            .map_err(|e| anyhow!("Invalid machine definition: {}", e))?;
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.version != DEFINITION_VERSION {
+            bail!("Unsupported definition version {} (expected {})", self.version, DEFINITION_VERSION);
+        }
+        if let Some(hostname) = &self.hostname {
+            if hostname.is_empty() || hostname.len() > 253 || !hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+                bail!("Invalid hostname '{}'", hostname);
+            }
+        }
+        if let Some(os_choice) = &self.os_choice {
+            dragonfly_common::os::check_os_choice(os_choice)?;
+        }
+        if self.tags.iter().any(|t| t.trim().is_empty()) {
+            bail!("Tags must not be empty");
+        }
+        self.provisioning.validate()
+    }
+}
+
+/// Compare a replacement machine against the hardware a definition was taken from.
+pub fn compatibility_warnings(original: &HardwareIdentity, target: &Machine) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    if target.disks.len() < original.disks.len() {
+        warnings.push(format!("Target has {} disks, original had {}", target.disks.len(), original.disks.len()));
+    }
+    // The OS image goes to the first disk, so that's the one that has to be big enough
+    if let (Some(old), Some(new)) = (original.disks.first(), target.disks.first()) {
+        if new.size_bytes < old.size_bytes {
+            warnings.push(format!("Target boot disk {} is {} bytes, original {} was {} bytes",
+                new.device, new.size_bytes, old.device, old.size_bytes));
+        }
+    }
+    if let (Some(old), Some(new)) = (original.total_ram_bytes, target.total_ram_bytes) {
+        if new < old {
+            warnings.push(format!("Target has {} bytes of RAM, original had {}", new, old));
+        }
+    }
+    if let (Some(old), Some(new)) = (original.cpu_cores, target.cpu_cores) {
+        if new < old {
+            warnings.push(format!("Target has {} CPU cores, original had {}", new, old));
+        }
+    }
+
+    warnings
+}
+
+/// Snapshot a machine's provisioning definition.
+pub async fn export(machine_id: &Uuid) -> Result<Option<MachineDefinition>> {
+    let machine = match crate::db::get_machine_by_id(machine_id).await? {
+        Some(machine) => machine,
+        None => return Ok(None),
+    };
+
+    Ok(Some(MachineDefinition {
+        version: DEFINITION_VERSION,
+        exported_at: Utc::now(),
+        source_machine_id: machine.id,
+        hostname: machine.hostname.clone(),
+        memorable_name: machine.memorable_name.clone(),
+        hardware: HardwareIdentity {
+            mac_address: machine.mac_address.clone(),
+            cpu_model: machine.cpu_model.clone(),
+            cpu_cores: machine.cpu_cores,
+            total_ram_bytes: machine.total_ram_bytes,
+            disks: machine.disks.clone(),
+        },
+        os_choice: machine.os_choice.clone(),
+        provisioning: crate::db::get_provisioning_config(machine_id).await?.unwrap_or_default(),
+        tags: crate::db::get_machine_tags(machine_id).await?,
+    }))
+}
+
+/// Restore a definition onto an already registered (typically replacement) machine.
+/// Hardware identity is only compared, never copied; the target keeps its own MAC and disks.
+pub async fn import(definition: &MachineDefinition, target_id: &Uuid, event_manager: Arc<EventManager>) -> Result<Option<ImportReport>> {
+    definition.validate()?;
+    let target = match crate::db::get_machine_by_id(target_id).await? {
+        Some(machine) => machine,
+        None => return Ok(None),
+    };
+
+    let warnings = compatibility_warnings(&definition.hardware, &target);
+    let mut applied = Vec::new();
+
+    if let Some(hostname) = &definition.hostname {
+        crate::db::update_hostname(target_id, hostname).await?;
+        applied.push("hostname".to_string());
+    }
+    if let Some(os_choice) = &definition.os_choice {
+        crate::db::assign_os(target_id, os_choice).await?;
+        applied.push("os_choice".to_string());
+    }
+    crate::db::save_provisioning_config(target_id, &definition.provisioning).await?;
+    applied.push("provisioning".to_string());
+    crate::db::update_machine_tags(target_id, &definition.tags).await?;
+    applied.push("tags".to_string());
+
+    // Push the new hostname and user data to the hardware record
+    if let Some(machine) = crate::db::get_machine_by_id(target_id).await? {
+        crate::tinkerbell_sync::sync_or_queue(&*crate::tinkerbell_client::client(), &machine).await;
+    }
+
+    info!("Restored definition of machine {} onto machine {}", definition.source_machine_id, target_id);
+    let _ = event_manager.send(format!("machine_updated:{}", target_id));
+    Ok(Some(ImportReport { machine_id: *target_id, applied, warnings }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(device: &str, size_bytes: u64) -> DiskInfo {
+        DiskInfo { device: device.to_string(), size_bytes, model: None, calculated_size: None }
+    }
+
+    fn definition() -> MachineDefinition {
+        MachineDefinition {
+            version: DEFINITION_VERSION,
+            exported_at: Utc::now(),
+            source_machine_id: Uuid::new_v4(),
+            hostname: Some("db-1".to_string()),
+            memorable_name: None,
+            hardware: HardwareIdentity {
+                mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+                cpu_model: None,
+                cpu_cores: Some(16),
+                total_ram_bytes: Some(64 << 30),
+                disks: vec![disk("/dev/sda", 500 << 30), disk("/dev/sdb", 500 << 30)],
+            },
+            os_choice: Some("debian-12".to_string()),
+            provisioning: ProvisioningConfig {
+                cloud_init: Some("#cloud-config\npackages: [postgresql]\n".to_string()),
+                partitioning: Some("lvm".to_string()),
+                kernel_args: Some("console=ttyS0,115200".to_string()),
+            },
+            tags: vec!["db".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let original = definition();
+        let restored = MachineDefinition::from_yaml(&original.to_yaml().unwrap()).unwrap();
+        assert_eq!(restored.provisioning, original.provisioning);
+        assert_eq!(restored.hostname, original.hostname);
+        assert_eq!(restored.hardware.disks.len(), 2);
+    }
+
+    #[test]
+    fn test_rejects_multiline_kernel_args() {
+        let mut injected = definition();
+        injected.provisioning.kernel_args = Some("quiet\nGRUB_TIMEOUT=0".to_string());
+        assert!(injected.validate().is_err());
+        injected.provisioning.kernel_args = Some("quiet\" init=/bin/sh".to_string());
+        assert!(injected.validate().is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let mut newer = definition();
+        newer.version = DEFINITION_VERSION + 1;
+        assert!(MachineDefinition::from_yaml(&newer.to_yaml().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_compatibility_warnings() {
+        let original = definition().hardware;
+        let mut target: Machine = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "mac_address": "11:22:33:44:55:66",
+            "ip_address": "10.0.0.9",
+            "hostname": null,
+            "os_choice": null,
+            "os_installed": null,
+            "status": "AwaitingAssignment",
+            "disks": [],
+            "nameservers": [],
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+            "last_deployment_duration": null,
+            "proxmox_cluster": null,
+            "is_proxmox_host": false,
+        })).unwrap();
+        target.disks = vec![disk("/dev/nvme0n1", 1 << 40), disk("/dev/nvme1n1", 1 << 40)];
+        target.cpu_cores = Some(32);
+        target.total_ram_bytes = Some(128 << 30);
+        assert!(compatibility_warnings(&original, &target).is_empty());
+
+        target.disks.truncate(1);
+        target.disks[0].size_bytes = 100 << 30;
+        target.total_ram_bytes = Some(32 << 30);
+        assert_eq!(compatibility_warnings(&original, &target).len(), 3);
+    }
+}
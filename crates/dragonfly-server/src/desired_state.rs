@@ -0,0 +1,375 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+
+const INTERVAL_ENV_VAR: &str = "DRAGONFLY_RECONCILE_INTERVAL_SECS";
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+
+/// What a known machine is handed when it PXE boots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BootProfile {
+    /// The usual flow: HookOS, which installs or boots the assigned OS
+    #[default]
+    Default,
+    /// Alpine live environment without the agent, for repairs
+    Rescue,
+    /// Memtest86+, booted every time until the profile is changed back
+    Memtest,
+}
+
+impl BootProfile {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "default" => Some(BootProfile::Default),
+            "rescue" => Some(BootProfile::Rescue),
+            "memtest" => Some(BootProfile::Memtest),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootProfile::Default => "default",
+            BootProfile::Rescue => "rescue",
+            BootProfile::Memtest => "memtest",
+        }
+    }
+
+    /// The iPXE script for this profile, or None for the default flow.
+    pub fn render_script(&self, base_url: &str) -> Option<String> {
+        match self {
+            BootProfile::Default => None,
+            BootProfile::Rescue => Some(crate::boot_menu::render_choice(base_url, &crate::boot_menu::MenuChoice::Rescue)),
+            BootProfile::Memtest => Some(crate::boot_menu::render_choice(base_url, &crate::boot_menu::MenuChoice::Memtest)),
+        }
+    }
+}
+
+/// How a machine should be. Fields left as None aren't managed and never show as drift.
+#[derive(Debug, Clone, Serialize)]
+pub struct DesiredState {
+    pub machine_id: Uuid,
+    pub os_choice: Option<String>,
+    pub hostname: Option<String>,
+    /// Compared as a set
+    pub tags: Option<Vec<String>>,
+    pub boot_profile: BootProfile,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Desired state as submitted by an admin.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DesiredStateInput {
+    pub os_choice: Option<String>,
+    pub hostname: Option<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub boot_profile: BootProfile,
+}
+
+impl DesiredStateInput {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(hostname) = &self.hostname {
+            if hostname.is_empty() || hostname.len() > 253 || !hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+                bail!("Invalid hostname '{}'", hostname);
+            }
+        }
+        if self.os_choice.as_deref().is_some_and(str::is_empty) {
+            bail!("os_choice must not be empty; omit it to leave the OS unmanaged");
+        }
+        if let Some(tags) = &self.tags {
+            if tags.iter().any(|t| t.trim().is_empty()) {
+                bail!("Tags must not be empty");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_state(self, machine_id: Uuid) -> DesiredState {
+        DesiredState {
+            machine_id,
+            os_choice: self.os_choice,
+            hostname: self.hostname,
+            tags: self.tags.map(normalize_tags),
+            boot_profile: self.boot_profile,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+fn normalize_tags(mut tags: Vec<String>) -> Vec<String> {
+    tags.iter_mut().for_each(|t| *t = t.trim().to_string());
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// The corrective action for one drifted field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftAction {
+    SetOsChoice,
+    SetHostname,
+    SetTags,
+    RegisterHardware,
+    /// Needs a reimage, which the reconciler never starts on its own
+    ReimageRequired,
+}
+
+/// One field where the machine differs from its desired state.
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftItem {
+    pub field: String,
+    pub desired: Option<String>,
+    pub actual: Option<String>,
+    pub action: DriftAction,
+    /// Whether the reconciler corrects this automatically
+    pub automatic: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftReport {
+    pub machine_id: Uuid,
+    pub in_sync: bool,
+    pub desired: DesiredState,
+    pub drift: Vec<DriftItem>,
+    /// False when Tinkerbell couldn't be checked
+    pub tinkerbell_checked: bool,
+}
+
+fn item(field: &str, desired: Option<String>, actual: Option<String>, action: DriftAction) -> DriftItem {
+    DriftItem {
+        field: field.to_string(),
+        desired,
+        actual,
+        automatic: action != DriftAction::ReimageRequired,
+        action,
+    }
+}
+
+/// Compare a machine against its desired state. `hardware_present` is None when
+/// Tinkerbell couldn't be checked.
+pub fn compute_drift(desired: &DesiredState, machine: &Machine, tags: &[String], hardware_present: Option<bool>) -> Vec<DriftItem> {
+    let mut drift = Vec::new();
+
+    if let Some(os) = &desired.os_choice {
+        if machine.os_choice.as_ref() != Some(os) {
+            drift.push(item("os_choice", Some(os.clone()), machine.os_choice.clone(), DriftAction::SetOsChoice));
+        }
+        // An installed OS only changes by reimaging
+        if machine.status == MachineStatus::Ready && machine.os_installed.as_ref().is_some_and(|installed| installed != os) {
+            drift.push(item("os_installed", Some(os.clone()), machine.os_installed.clone(), DriftAction::ReimageRequired));
+        }
+    }
+
+    if let Some(hostname) = &desired.hostname {
+        if machine.hostname.as_ref() != Some(hostname) {
+            drift.push(item("hostname", Some(hostname.clone()), machine.hostname.clone(), DriftAction::SetHostname));
+        }
+    }
+
+    if let Some(desired_tags) = &desired.tags {
+        let actual = normalize_tags(tags.to_vec());
+        if &actual != desired_tags {
+            drift.push(item("tags", Some(desired_tags.join(",")), Some(actual.join(",")), DriftAction::SetTags));
+        }
+    }
+
+    if hardware_present == Some(false) {
+        drift.push(item("tinkerbell_hardware", Some("present".to_string()), Some("missing".to_string()), DriftAction::RegisterHardware));
+    } else if !machine.tinkerbell_synced {
+        drift.push(item("tinkerbell_hardware", Some("synced".to_string()), Some("stale".to_string()), DriftAction::RegisterHardware));
+    }
+
+    drift
+}
+
+/// Current drift for a machine, or None if it has no desired state.
+pub async fn drift_report(machine_id: &Uuid) -> Result<Option<DriftReport>> {
+    let Some(desired) = crate::db::get_desired_state(machine_id).await? else {
+        return Ok(None);
+    };
+    let Some(machine) = crate::db::get_machine_by_id(machine_id).await? else {
+        return Ok(None);
+    };
+    let tags = crate::db::get_machine_tags(machine_id).await?;
+    let hardware_present = match crate::tinkerbell::hardware_exists(&*crate::tinkerbell_client::client(), &machine.mac_address).await {
+        Ok(present) => present,
+        Err(e) => {
+            warn!("Could not check Tinkerbell hardware for machine {}: {}", machine_id, e);
+            None
+        }
+    };
+    let drift = compute_drift(&desired, &machine, &tags, hardware_present);
+    Ok(Some(DriftReport {
+        machine_id: *machine_id,
+        in_sync: drift.is_empty(),
+        desired,
+        drift,
+        tinkerbell_checked: hardware_present.is_some(),
+    }))
+}
+
+// Bring one machine to its desired state, returning the fields corrected
+async fn reconcile_machine(machine_id: &Uuid, event_manager: &EventManager) -> Result<Vec<String>> {
+    // Drift is left alone while someone is working on the machine
+    if crate::maintenance::is_active(machine_id).await {
+        return Ok(Vec::new());
+    }
+    let Some(report) = drift_report(machine_id).await? else {
+        return Ok(Vec::new());
+    };
+    let desired = &report.desired;
+    let mut corrected = Vec::new();
+    let mut push_hardware = false;
+
+    for drift in report.drift.iter().filter(|d| d.automatic) {
+        match drift.action {
+            DriftAction::SetOsChoice => {
+                if let Some(os) = &desired.os_choice {
+                    crate::db::assign_os(machine_id, os).await?;
+                }
+            }
+            DriftAction::SetHostname => {
+                if let Some(hostname) = &desired.hostname {
+                    crate::db::update_hostname(machine_id, hostname).await?;
+                    // The Hardware resource carries the hostname too
+                    push_hardware = true;
+                }
+            }
+            DriftAction::SetTags => {
+                if let Some(tags) = &desired.tags {
+                    crate::db::update_machine_tags(machine_id, tags).await?;
+                }
+            }
+            DriftAction::RegisterHardware => push_hardware = true,
+            DriftAction::ReimageRequired => continue,
+        }
+        if drift.action != DriftAction::RegisterHardware {
+            corrected.push(drift.field.clone());
+        }
+    }
+
+    if push_hardware {
+        if let Some(machine) = crate::db::get_machine_by_id(machine_id).await? {
+            match crate::tinkerbell::register_machine(&*crate::tinkerbell_client::client(), &machine).await {
+                Ok(()) => {
+                    crate::db::mark_tinkerbell_synced(machine_id).await?;
+                    corrected.push("tinkerbell_hardware".to_string());
+                }
+                Err(e) => crate::db::mark_tinkerbell_sync_failed(machine_id, &e.to_string()).await?,
+            }
+        }
+    }
+
+    for field in &corrected {
+        let _ = event_manager.send(format!("drift_corrected:{}:{}", machine_id, field));
+    }
+    if !corrected.is_empty() {
+        info!("Reconciled machine {}: corrected {}", machine_id, corrected.join(", "));
+        let _ = event_manager.send(format!("machine_updated:{}", machine_id));
+    }
+    Ok(corrected)
+}
+
+/// Periodically bring every machine with a desired state back in line.
+pub async fn start_reconcile_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let interval_secs = crate::config::var(INTERVAL_ENV_VAR)
+        .and_then(|v| v.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
+                    let states = match crate::db::list_desired_states().await {
+                        Ok(states) => states,
+                        Err(e) => {
+                            error!("Failed to list desired machine states: {}", e);
+                            continue;
+                        }
+                    };
+                    for state in states {
+                        if let Err(e) = reconcile_machine(&state.machine_id, &event_manager).await {
+                            error!("Failed to reconcile machine {}: {}", state.machine_id, e);
+                        }
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping desired state reconciler.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        Machine {
+            mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+            hostname: Some("node-1".to_string()),
+            os_choice: Some("ubuntu-2204".to_string()),
+            os_installed: Some("ubuntu-2204".to_string()),
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    fn desired(os: Option<&str>, hostname: Option<&str>, tags: Option<&[&str]>) -> DesiredState {
+        DesiredStateInput {
+            os_choice: os.map(str::to_string),
+            hostname: hostname.map(str::to_string),
+            tags: tags.map(|t| t.iter().map(|s| s.to_string()).collect()),
+            boot_profile: BootProfile::Default,
+        }
+        .into_state(Uuid::new_v4())
+    }
+
+    #[test]
+    fn test_unmanaged_fields_never_drift() {
+        let drift = compute_drift(&desired(None, None, None), &machine(), &["web".to_string()], Some(true));
+        assert!(drift.is_empty());
+    }
+
+    #[test]
+    fn test_tags_compared_as_set() {
+        let state = desired(None, None, Some(&["web", "prod"]));
+        let tags = vec!["prod".to_string(), "web".to_string(), "web".to_string()];
+        assert!(compute_drift(&state, &machine(), &tags, None).is_empty());
+        let drift = compute_drift(&state, &machine(), &["prod".to_string()], None);
+        assert_eq!(drift[0].action, DriftAction::SetTags);
+    }
+
+    #[test]
+    fn test_os_drift_on_installed_machine_needs_reimage() {
+        let drift = compute_drift(&desired(Some("debian-12"), Some("node-2"), None), &machine(), &[], Some(false));
+        let actions: Vec<DriftAction> = drift.iter().map(|d| d.action).collect();
+        assert_eq!(actions, vec![
+            DriftAction::SetOsChoice,
+            DriftAction::ReimageRequired,
+            DriftAction::SetHostname,
+            DriftAction::RegisterHardware,
+        ]);
+        assert!(!drift[1].automatic);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_hostname() {
+        let input = DesiredStateInput { os_choice: None, hostname: Some("bad host".to_string()), tags: None, boot_profile: BootProfile::Default };
+        assert!(input.validate().is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Duration, Utc};
+use dragonfly_common::models::HardwareDiagnosticsReport;
+use serde::Serialize;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::boot_menu::{alpine_boot_script, MEMTEST_ARTIFACT};
+
+/// Vendor diagnostic ISOs live here under the artifact dir, one `<name>.iso` per tool.
+pub const VENDOR_ARTIFACT_DIR: &str = "diagnostics";
+
+// A request nobody booted for within this long is dropped rather than
+// surprising whoever next power-cycles the machine
+const REQUEST_TTL_HOURS: i64 = 24;
+
+/// Something a machine can be booted into instead of its normal path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticTarget {
+    Memtest,
+    /// The Dragonfly agent in diagnostics mode; reports results back
+    HardwareCheck,
+    /// An uploaded vendor diagnostics ISO, by name
+    Vendor(String),
+}
+
+impl DiagnosticTarget {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "memtest" => Some(DiagnosticTarget::Memtest),
+            "hardware-check" => Some(DiagnosticTarget::HardwareCheck),
+            other => other.strip_prefix("vendor:")
+                .filter(|name| is_valid_artifact_name(name))
+                .map(|name| DiagnosticTarget::Vendor(name.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> String {
+        match self {
+            DiagnosticTarget::Memtest => "memtest".to_string(),
+            DiagnosticTarget::HardwareCheck => "hardware-check".to_string(),
+            DiagnosticTarget::Vendor(name) => format!("vendor:{}", name),
+        }
+    }
+
+    /// Whether booting this target reports results back to Dragonfly.
+    pub fn reports_results(&self) -> bool {
+        matches!(self, DiagnosticTarget::HardwareCheck)
+    }
+
+    /// Artifact path under the artifact dir, for targets that need one.
+    pub fn artifact_path(&self) -> Option<String> {
+        match self {
+            DiagnosticTarget::Memtest => Some(MEMTEST_ARTIFACT.to_string()),
+            DiagnosticTarget::HardwareCheck => None,
+            DiagnosticTarget::Vendor(name) => Some(format!("{}/{}.iso", VENDOR_ARTIFACT_DIR, name)),
+        }
+    }
+
+    /// Whether everything needed to boot the target is in place.
+    pub fn is_available(&self) -> bool {
+        self.artifact_path()
+            .map_or(true, |path| crate::api::artifact_base_dir().join(path).exists())
+    }
+
+    /// The iPXE script that boots this target.
+    pub fn render_script(&self, base_url: &str) -> String {
+        match self {
+            DiagnosticTarget::Memtest => format!("#!ipxe\nchain {}/ipxe/{}\n", base_url, MEMTEST_ARTIFACT),
+            DiagnosticTarget::HardwareCheck => alpine_boot_script(base_url, true, &["dragonfly.diagnose=1"]),
+            // sanboot streams the ISO over HTTP, so nothing has to fit in RAM
+            DiagnosticTarget::Vendor(_) => format!(
+                "#!ipxe\nsanboot --no-describe {}/ipxe/{}\n",
+                base_url,
+                self.artifact_path().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Vendor names are used as file names, so keep them to a safe character set.
+pub fn is_valid_artifact_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Every target that could be booted right now.
+pub fn available_targets() -> Vec<DiagnosticTarget> {
+    let mut targets = vec![DiagnosticTarget::HardwareCheck];
+    if DiagnosticTarget::Memtest.is_available() {
+        targets.push(DiagnosticTarget::Memtest);
+    }
+    let vendor_dir: PathBuf = crate::api::artifact_base_dir().join(VENDOR_ARTIFACT_DIR);
+    if let Ok(entries) = std::fs::read_dir(vendor_dir) {
+        let mut vendors: Vec<String> = entries.flatten()
+            .filter_map(|e| e.file_name().to_str()?.strip_suffix(".iso").map(str::to_string))
+            .filter(|name| is_valid_artifact_name(name))
+            .collect();
+        vendors.sort();
+        targets.extend(vendors.into_iter().map(DiagnosticTarget::Vendor));
+    }
+    targets
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticBootStatus {
+    /// Waiting for the machine's next network boot
+    Pending,
+    /// Served once; the machine's boot is back to normal
+    Booted,
+    /// The agent reported results
+    Completed,
+    Cancelled,
+    /// Nobody booted the machine in time
+    Expired,
+}
+
+impl DiagnosticBootStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticBootStatus::Pending => "pending",
+            DiagnosticBootStatus::Booted => "booted",
+            DiagnosticBootStatus::Completed => "completed",
+            DiagnosticBootStatus::Cancelled => "cancelled",
+            DiagnosticBootStatus::Expired => "expired",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [Self::Pending, Self::Booted, Self::Completed, Self::Cancelled, Self::Expired]
+            .into_iter()
+            .find(|s| s.as_str() == value)
+    }
+}
+
+/// A one-shot diagnostics boot requested for a machine, and its results.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticBoot {
+    pub id: Uuid,
+    pub machine_id: Uuid,
+    pub target: String,
+    pub status: DiagnosticBootStatus,
+    pub requested_at: DateTime<Utc>,
+    pub booted_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub report: Option<HardwareDiagnosticsReport>,
+}
+
+impl DiagnosticBoot {
+    pub fn new(machine_id: Uuid, target: &DiagnosticTarget) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            machine_id,
+            target: target.as_str(),
+            status: DiagnosticBootStatus::Pending,
+            requested_at: Utc::now(),
+            booted_at: None,
+            completed_at: None,
+            report: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.status == DiagnosticBootStatus::Pending && now - self.requested_at > Duration::hours(REQUEST_TTL_HOURS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() {
+        assert_eq!(DiagnosticTarget::parse("memtest"), Some(DiagnosticTarget::Memtest));
+        assert_eq!(DiagnosticTarget::parse("vendor:dell-epsa"), Some(DiagnosticTarget::Vendor("dell-epsa".to_string())));
+        assert_eq!(DiagnosticTarget::parse("vendor:../etc/passwd"), None);
+        assert_eq!(DiagnosticTarget::parse("vendor:"), None);
+        assert_eq!(DiagnosticTarget::parse("bogus"), None);
+    }
+
+    #[test]
+    fn test_hardware_check_boots_agent_in_diagnose_mode() {
+        let script = DiagnosticTarget::HardwareCheck.render_script("http://10.0.0.1:3000");
+        assert!(script.contains("apkovl="));
+        assert!(script.contains("dragonfly.diagnose=1"));
+    }
+
+    #[test]
+    fn test_pending_request_expires() {
+        let mut boot = DiagnosticBoot::new(Uuid::new_v4(), &DiagnosticTarget::Memtest);
+        assert!(!boot.is_expired(Utc::now()));
+        assert!(boot.is_expired(Utc::now() + Duration::hours(REQUEST_TTL_HOURS + 1)));
+        boot.status = DiagnosticBootStatus::Booted;
+        assert!(!boot.is_expired(Utc::now() + Duration::hours(REQUEST_TTL_HOURS + 1)));
+    }
+}
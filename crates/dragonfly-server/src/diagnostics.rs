@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::Machine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::RecordedEvent;
+
+// Number of log lines to keep from each Tinkerbell pod
+const POD_LOG_TAIL_LINES: i64 = 500;
+
+/// Everything we know about a machine at the moment its install failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsBundle {
+    pub id: Uuid,
+    pub machine_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub machine: Machine,
+    pub workflow_status: Option<serde_json::Value>,
+    pub pod_logs: HashMap<String, String>,
+    pub recent_events: Vec<RecordedEvent>,
+    pub installation_progress: u8,
+    pub installation_step: Option<String>,
+    /// Collection steps that failed; the bundle is still stored with whatever was gathered.
+    pub collection_errors: Vec<String>,
+}
+
+/// Listing entry for a stored bundle (without the payload).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsBundleSummary {
+    pub id: Uuid,
+    pub machine_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub url: String,
+}
+
+/// API path a bundle can be downloaded from.
+pub fn bundle_url(machine_id: &Uuid, bundle_id: &Uuid) -> String {
+    crate::base_path::url(&format!("/api/machines/{}/diagnostics/{}", machine_id, bundle_id))
+}
+
+/// A failure message pointing at the bundle collected for it, if there is one.
+pub fn with_bundle_link(message: String, machine_id: &Uuid, bundle_id: Option<Uuid>) -> String {
+    match bundle_id {
+        Some(id) => format!("{} (diagnostics: {})", message, bundle_url(machine_id, &id)),
+        None => message,
+    }
+}
+
+// Recent events that mention the machine
+fn machine_events(events: Vec<RecordedEvent>, machine_id: &Uuid) -> Vec<RecordedEvent> {
+    let machine_id = machine_id.to_string();
+    events.into_iter().filter(|e| e.message.contains(&machine_id)).collect()
+}
+
+/// Gather a diagnostics bundle for a machine. Individual collection failures are
+/// recorded in the bundle rather than aborting the whole collection.
+pub async fn collect_bundle(machine: &Machine) -> DiagnosticsBundle {
+    let mut collection_errors = Vec::new();
+
+    let workflow_status = match crate::tinkerbell::get_workflow_status_raw(machine).await {
+        Ok(status) => status,
+        Err(e) => {
+            collection_errors.push(format!("workflow status: {}", e));
+            None
+        }
+    };
+
+    let pod_logs = match crate::tinkerbell::get_tink_pod_logs(POD_LOG_TAIL_LINES).await {
+        Ok(logs) => logs,
+        Err(e) => {
+            collection_errors.push(format!("pod logs: {}", e));
+            HashMap::new()
+        }
+    };
+
+    let recent_events = match crate::EVENT_MANAGER_REF.read() {
+        Ok(guard) => guard.as_ref()
+            .map(|em| machine_events(em.recent_events(), &machine.id))
+            .unwrap_or_default(),
+        Err(_) => {
+            collection_errors.push("recent events: event manager lock poisoned".to_string());
+            Vec::new()
+        }
+    };
+
+    DiagnosticsBundle {
+        id: Uuid::new_v4(),
+        machine_id: machine.id,
+        created_at: Utc::now(),
+        machine: machine.clone(),
+        workflow_status,
+        pod_logs,
+        recent_events,
+        installation_progress: machine.installation_progress,
+        installation_step: machine.installation_step.clone(),
+        collection_errors,
+    }
+}
+
+/// Collect a bundle, persist it, and announce it over SSE. Returns the bundle ID.
+pub async fn collect_and_store(machine: &Machine) -> Result<Uuid> {
+    info!("Collecting diagnostics bundle for machine {}", machine.id);
+
+    let bundle = collect_bundle(machine).await;
+    if !bundle.collection_errors.is_empty() {
+        warn!("Diagnostics bundle for machine {} is incomplete: {:?}", machine.id, bundle.collection_errors);
+    }
+
+    crate::db::save_diagnostics_bundle(&bundle).await
+        .map_err(|e| anyhow!("Failed to store diagnostics bundle: {}", e))?;
+
+    info!("Stored diagnostics bundle {} for machine {}", bundle.id, machine.id);
+
+    if let Ok(guard) = crate::EVENT_MANAGER_REF.read() {
+        if let Some(event_manager) = guard.as_ref() {
+            let _ = event_manager.send(format!("diagnostics_ready:{}:{}", machine.id, bundle.id));
+        }
+    }
+
+    Ok(bundle.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_events() {
+        let machine_id = Uuid::new_v4();
+        let event = |message: String| RecordedEvent { id: 0, timestamp: Utc::now(), message };
+        let events = vec![
+            event(format!("machine_updated:{}", machine_id)),
+            event(format!("machine_updated:{}", Uuid::new_v4())),
+            event(format!("task_progress:{}:Stream image:50.000:5:10", machine_id)),
+        ];
+        let kept: Vec<String> = machine_events(events, &machine_id).into_iter().map(|e| e.message).collect();
+        assert_eq!(kept, vec![
+            format!("machine_updated:{}", machine_id),
+            format!("task_progress:{}:Stream image:50.000:5:10", machine_id),
+        ]);
+    }
+
+    #[test]
+    fn test_failure_message_links_the_bundle() {
+        let machine_id = Uuid::new_v4();
+        let bundle_id = Uuid::new_v4();
+        assert_eq!(with_bundle_link("OS installation failed".to_string(), &machine_id, None), "OS installation failed");
+        assert_eq!(
+            with_bundle_link("OS installation failed".to_string(), &machine_id, Some(bundle_id)),
+            format!("OS installation failed (diagnostics: {})", bundle_url(&machine_id, &bundle_id)),
+        );
+        assert!(bundle_url(&machine_id, &bundle_id).ends_with(&format!("/api/machines/{}/diagnostics/{}", machine_id, bundle_id)));
+    }
+}
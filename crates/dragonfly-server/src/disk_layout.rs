@@ -0,0 +1,281 @@
+// Multi-disk imaging for machines with several identical disks (e.g. Ceph nodes).
+//
+// A machine's partitioning profile can carry imaging options after its layout name:
+//
+//     lvm,multi=replicate,disks=4
+//
+// `multi=parallel` writes the OS image to every target disk at once, while
+// `multi=replicate` streams it to the boot disk as usual and then clones that disk onto
+// the others in parallel. Targets are the disks the same size as the boot disk, capped
+// at `disks=N` when given. Install templates pick the mode and target list up from the
+// workflow's hardwareMap and run the script below, which reports progress for each disk
+// to /api/install-telemetry/{mac}/disks.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::DiskInfo;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImagingMode {
+    /// Only the boot disk is imaged
+    #[default]
+    Single,
+    /// The image is written to every target disk at once
+    Parallel,
+    /// The boot disk is imaged, then cloned onto the other targets
+    Replicate,
+}
+
+impl ImagingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImagingMode::Single => "single",
+            ImagingMode::Parallel => "parallel",
+            ImagingMode::Replicate => "replicate",
+        }
+    }
+}
+
+/// A partitioning profile split into its layout name and multi-disk options.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiskLayout {
+    pub name: Option<String>,
+    pub mode: ImagingMode,
+    pub max_disks: Option<usize>,
+}
+
+impl DiskLayout {
+    pub fn parse(profile: Option<&str>) -> Result<Self> {
+        let mut layout = DiskLayout::default();
+        for part in profile.unwrap_or("").split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                None if layout.name.is_none() => layout.name = Some(part.to_string()),
+                None => bail!("Partitioning profile has more than one layout name ('{}')", part),
+                Some(("multi", mode)) => {
+                    layout.mode = match mode.trim() {
+                        "parallel" => ImagingMode::Parallel,
+                        "replicate" => ImagingMode::Replicate,
+                        "single" | "" => ImagingMode::Single,
+                        other => bail!("Unknown multi-disk mode '{}' (expected parallel or replicate)", other),
+                    };
+                }
+                Some(("disks", count)) => match count.trim().parse::<usize>() {
+                    Ok(n) if n >= 1 => layout.max_disks = Some(n),
+                    _ => bail!("disks must be a positive number, got '{}'", count),
+                },
+                Some((key, _)) => bail!("Unknown partitioning option '{}'", key.trim()),
+            }
+        }
+        Ok(layout)
+    }
+
+    /// Disks the image ends up on, boot disk first. Only disks matching the boot disk's size
+    /// qualify, since a layout can't be replicated onto a smaller disk.
+    pub fn target_disks(&self, disks: &[DiskInfo]) -> Vec<String> {
+        let Some(boot) = disks.first() else {
+            return Vec::new();
+        };
+        if self.mode == ImagingMode::Single {
+            return vec![boot.device.clone()];
+        }
+        disks.iter()
+            .filter(|d| d.size_bytes == boot.size_bytes)
+            .take(self.max_disks.unwrap_or(usize::MAX))
+            .map(|d| d.device.clone())
+            .collect()
+    }
+
+    /// hardwareMap entries for the install templates; the mode is empty unless there's more
+    /// than one disk to image.
+    pub fn hardware_map(&self, disks: &[DiskInfo]) -> Vec<(&'static str, String)> {
+        let targets = self.target_disks(disks);
+        let mode = if targets.len() > 1 { self.mode.as_str() } else { "" };
+        vec![
+            // Post-install verification checks this layout exists
+            ("disk_layout", self.name.clone().unwrap_or_default()),
+            ("multi_disk_mode", mode.to_string()),
+            ("multi_disk_targets", targets.join(" ")),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskImagingState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// What the imaging script reports for one disk.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiskProgressReport {
+    pub device: String,
+    pub percent: u8,
+    pub state: DiskImagingState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiskProgress {
+    pub device: String,
+    pub percent: u8,
+    pub state: DiskImagingState,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Per-disk progress of each machine's running (or last) install. Kept in memory only; the
+// overall install progress is what gets persisted.
+static DISK_PROGRESS: Lazy<RwLock<HashMap<Uuid, BTreeMap<String, DiskProgress>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Store a disk's progress, rejecting devices that aren't among the machine's disks.
+pub async fn record_progress(machine_id: &Uuid, disks: &[DiskInfo], report: &DiskProgressReport) -> Result<()> {
+    if !disks.iter().any(|d| d.device == report.device) {
+        bail!("Machine has no disk {}", report.device);
+    }
+    let percent = match report.state {
+        DiskImagingState::Done => 100,
+        _ => report.percent.min(100),
+    };
+    DISK_PROGRESS.write().await
+        .entry(*machine_id)
+        .or_default()
+        .insert(report.device.clone(), DiskProgress {
+            device: report.device.clone(),
+            percent,
+            state: report.state,
+            updated_at: Utc::now(),
+        });
+    Ok(())
+}
+
+pub async fn progress(machine_id: &Uuid) -> Vec<DiskProgress> {
+    DISK_PROGRESS.read().await
+        .get(machine_id)
+        .map(|disks| disks.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Forget the previous install's per-disk progress when a new one starts.
+pub async fn reset(machine_id: &Uuid) {
+    DISK_PROGRESS.write().await.remove(machine_id);
+}
+
+/// Run by the templates' "image multiple disks" action (an Alpine container) with MODE,
+/// DISKS, IMG_URL and REPORT_URL set.
+pub const IMAGING_SCRIPT: &str = r#"#!/bin/sh
+set -u
+
+report() {
+    wget -q -O /dev/null -T 10 --header 'Content-Type: application/json' \
+        --post-data "{\"device\":\"$1\",\"percent\":$2,\"state\":\"$3\"}" "$REPORT_URL" || true
+}
+
+apk add --no-cache qemu-img >/dev/null || exit 1
+
+if [ "$MODE" = parallel ]; then
+    wget -q -O /tmp/source.img "$IMG_URL" || exit 1
+    SOURCE=/tmp/source.img
+    FORMAT=""
+    TARGETS="$DISKS"
+else
+    # Replicate: the boot disk (first in DISKS) was already imaged by the previous action
+    SOURCE="${DISKS%% *}"
+    FORMAT="-f raw"
+    TARGETS="${DISKS#* }"
+fi
+
+n=0
+for disk in $TARGETS; do
+    n=$((n + 1))
+    echo "$disk" > /tmp/disk.$n
+    report "$disk" 0 running
+    (
+        if qemu-img convert -p -n $FORMAT -O raw "$SOURCE" "$disk" > /tmp/progress.$n 2>&1; then
+            echo done > /tmp/status.$n
+        else
+            echo failed > /tmp/status.$n
+        fi
+    ) &
+done
+
+while :; do
+    sleep 10
+    running=0
+    i=1
+    while [ $i -le $n ]; do
+        disk=$(cat /tmp/disk.$i)
+        percent=$(tr '\r' '\n' < /tmp/progress.$i | sed -n 's/.*(\([0-9]*\)\.[0-9]*\/100%).*/\1/p' | tail -n 1)
+        if [ -f /tmp/reported.$i ]; then
+            :
+        elif [ -f /tmp/status.$i ]; then
+            report "$disk" "${percent:-0}" "$(cat /tmp/status.$i)"
+            touch /tmp/reported.$i
+        else
+            running=1
+            report "$disk" "${percent:-0}" running
+        fi
+        i=$((i + 1))
+    done
+    [ $running -eq 0 ] && break
+done
+
+! grep -q failed /tmp/status.* 2>/dev/null
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(device: &str, size_bytes: u64) -> DiskInfo {
+        DiskInfo { device: device.to_string(), size_bytes, model: None, calculated_size: None }
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        assert_eq!(DiskLayout::parse(None).unwrap(), DiskLayout::default());
+        assert_eq!(DiskLayout::parse(Some("lvm")).unwrap().name.as_deref(), Some("lvm"));
+
+        let layout = DiskLayout::parse(Some("lvm, multi=replicate, disks=3")).unwrap();
+        assert_eq!(layout.mode, ImagingMode::Replicate);
+        assert_eq!(layout.max_disks, Some(3));
+
+        assert_eq!(DiskLayout::parse(Some("multi=parallel")).unwrap().name, None);
+        assert!(DiskLayout::parse(Some("lvm,multi=raid")).is_err());
+        assert!(DiskLayout::parse(Some("lvm,disks=0")).is_err());
+        assert!(DiskLayout::parse(Some("lvm,zfs")).is_err());
+        assert!(DiskLayout::parse(Some("lvm,stripe=2")).is_err());
+    }
+
+    #[test]
+    fn test_target_disks() {
+        let disks = vec![disk("/dev/sda", 100), disk("/dev/sdb", 100), disk("/dev/sdc", 50), disk("/dev/sdd", 100)];
+
+        let single = DiskLayout::parse(Some("lvm")).unwrap();
+        assert_eq!(single.target_disks(&disks), vec!["/dev/sda"]);
+        assert_eq!(single.hardware_map(&disks)[0], ("disk_layout", "lvm".to_string()));
+        assert_eq!(single.hardware_map(&disks)[1].1, "");
+
+        let parallel = DiskLayout::parse(Some("multi=parallel")).unwrap();
+        assert_eq!(parallel.target_disks(&disks), vec!["/dev/sda", "/dev/sdb", "/dev/sdd"]);
+        assert_eq!(parallel.hardware_map(&disks), vec![
+            ("disk_layout", String::new()),
+            ("multi_disk_mode", "parallel".to_string()),
+            ("multi_disk_targets", "/dev/sda /dev/sdb /dev/sdd".to_string()),
+        ]);
+
+        let capped = DiskLayout::parse(Some("multi=replicate,disks=2")).unwrap();
+        assert_eq!(capped.target_disks(&disks), vec!["/dev/sda", "/dev/sdb"]);
+
+        // Nothing to replicate onto
+        let lone = vec![disk("/dev/sda", 100), disk("/dev/sdb", 50)];
+        assert_eq!(capped.hardware_map(&lone)[1].1, "");
+        assert!(capped.target_disks(&[]).is_empty());
+    }
+}
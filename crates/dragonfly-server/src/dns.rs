@@ -0,0 +1,530 @@
+// Registers A/AAAA and PTR records for machines in the site's DNS, and removes them when
+// a machine is deleted.
+//
+// Opt-in: nothing happens unless DRAGONFLY_DNS_SERVER and DRAGONFLY_DNS_ZONE are set.
+// Updates are RFC 2136 dynamic updates, TSIG-signed when a key is configured. Other
+// backends (provider APIs) plug in by implementing `DnsProvider`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use dragonfly_common::models::Machine;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sha2::{Sha256, Sha512};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+
+const SERVER_ENV_VAR: &str = "DRAGONFLY_DNS_SERVER";
+const ZONE_ENV_VAR: &str = "DRAGONFLY_DNS_ZONE";
+// PTR records are only managed when a reverse zone is configured
+const REVERSE_ZONE_ENV_VAR: &str = "DRAGONFLY_DNS_REVERSE_ZONE";
+const TTL_ENV_VAR: &str = "DRAGONFLY_DNS_TTL";
+const TSIG_KEY_NAME_ENV_VAR: &str = "DRAGONFLY_DNS_TSIG_KEY_NAME";
+const TSIG_SECRET_ENV_VAR: &str = "DRAGONFLY_DNS_TSIG_SECRET";
+const TSIG_ALGORITHM_ENV_VAR: &str = "DRAGONFLY_DNS_TSIG_ALGORITHM";
+
+const DEFAULT_TTL: u32 = 300;
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(5);
+const TSIG_FUDGE_SECS: u16 = 300;
+
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+const OPCODE_UPDATE: u16 = 5;
+
+/// The records Dragonfly keeps for one machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DnsRecord {
+    pub fqdn: String,
+    pub ip_address: IpAddr,
+}
+
+/// Something that can publish and withdraw machine records.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Replace whatever the name and address currently point at with this record.
+    async fn upsert(&self, record: &DnsRecord) -> Result<()>;
+    async fn remove(&self, record: &DnsRecord) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-sha256" => Some(TsigAlgorithm::HmacSha256),
+            "hmac-sha512" => Some(TsigAlgorithm::HmacSha512),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn mac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    pub server: SocketAddr,
+    pub zone: String,
+    pub reverse_zone: Option<String>,
+    pub ttl: u32,
+    pub tsig: Option<TsigKey>,
+}
+
+fn absolute(name: &str) -> String {
+    format!("{}.", name.trim().trim_end_matches('.').to_ascii_lowercase())
+}
+
+impl DnsConfig {
+    /// None when DNS registration isn't configured.
+    pub fn from_env() -> Result<Option<Self>> {
+        let (Some(server), Some(zone)) = (crate::config::var(SERVER_ENV_VAR), crate::config::var(ZONE_ENV_VAR)) else {
+            return Ok(None);
+        };
+        // A bare address means port 53
+        let server = server.parse::<SocketAddr>()
+            .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+            .map_err(|_| anyhow!("{} must be an IP address, optionally with a port: {}", SERVER_ENV_VAR, server))?;
+        let ttl = match crate::config::var(TTL_ENV_VAR) {
+            Some(ttl) => ttl.parse().with_context(|| format!("{} must be a number of seconds", TTL_ENV_VAR))?,
+            None => DEFAULT_TTL,
+        };
+        let tsig = match (crate::config::var(TSIG_KEY_NAME_ENV_VAR), crate::config::var(TSIG_SECRET_ENV_VAR)) {
+            (Some(name), Some(secret)) => {
+                let algorithm = match crate::config::var(TSIG_ALGORITHM_ENV_VAR) {
+                    Some(algorithm) => TsigAlgorithm::parse(&algorithm)
+                        .ok_or_else(|| anyhow!("Unsupported {}: {} (use hmac-sha256 or hmac-sha512)", TSIG_ALGORITHM_ENV_VAR, algorithm))?,
+                    None => TsigAlgorithm::HmacSha256,
+                };
+                let secret = base64::engine::general_purpose::STANDARD.decode(secret.trim())
+                    .with_context(|| format!("{} must be base64", TSIG_SECRET_ENV_VAR))?;
+                Some(TsigKey { name: absolute(&name), algorithm, secret })
+            }
+            (None, None) => None,
+            _ => bail!("{} and {} must be set together", TSIG_KEY_NAME_ENV_VAR, TSIG_SECRET_ENV_VAR),
+        };
+        Ok(Some(DnsConfig {
+            server,
+            zone: absolute(&zone),
+            reverse_zone: crate::config::var(REVERSE_ZONE_ENV_VAR).filter(|z| !z.is_empty()).map(|z| absolute(&z)),
+            ttl,
+            tsig,
+        }))
+    }
+}
+
+static CONFIG: Lazy<Option<DnsConfig>> = Lazy::new(|| match DnsConfig::from_env() {
+    Ok(config) => config,
+    Err(e) => {
+        error!("DNS registration disabled, configuration is invalid: {}", e);
+        None
+    }
+});
+
+fn valid_label(label: &str) -> bool {
+    !label.is_empty() && label.len() <= 63 && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// The name a hostname is registered under: short names go in the zone, and names already
+/// in the zone are kept. Anything else isn't ours to register.
+pub fn fqdn_for(hostname: &str, zone: &str) -> Option<String> {
+    let hostname = hostname.trim().trim_end_matches('.').to_ascii_lowercase();
+    let zone = absolute(zone);
+    let name = if hostname.contains('.') {
+        let name = absolute(&hostname);
+        if !name.ends_with(&format!(".{}", zone)) {
+            return None;
+        }
+        name
+    } else {
+        format!("{}.{}", hostname, zone)
+    };
+    name.trim_end_matches('.').split('.').all(valid_label).then_some(name)
+}
+
+/// The in-addr.arpa / ip6.arpa name for an address.
+pub fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa.", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa.");
+            name
+        }
+    }
+}
+
+/// The record a machine should have, if it has a usable hostname and address.
+pub fn desired_record(machine: &Machine, zone: &str) -> Option<DnsRecord> {
+    let ip_address: IpAddr = machine.ip_address.parse().ok()?;
+    if ip_address.is_unspecified() || ip_address.is_loopback() {
+        return None;
+    }
+    let fqdn = fqdn_for(machine.hostname.as_deref()?, zone)?;
+    Some(DnsRecord { fqdn, ip_address })
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+fn encode_rr(out: &mut Vec<u8>, name: &str, rr_type: u16, class: u16, ttl: u32, rdata: &[u8]) {
+    encode_name(out, name);
+    out.extend_from_slice(&rr_type.to_be_bytes());
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&ttl.to_be_bytes());
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend_from_slice(rdata);
+}
+
+/// One change in an update message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Delete every record of a type at a name
+    DeleteRrset { name: String, rr_type: u16 },
+    Add { name: String, rr_type: u16, ttl: u32, rdata: Vec<u8> },
+}
+
+fn address_rdata(ip: &IpAddr) -> (u16, Vec<u8>) {
+    match ip {
+        IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+    }
+}
+
+/// Changes that point the record's name and (optionally) reverse name at each other.
+pub fn upsert_changes(record: &DnsRecord, ttl: u32, with_ptr: bool) -> Vec<Change> {
+    let (rr_type, rdata) = address_rdata(&record.ip_address);
+    let mut changes = vec![
+        Change::DeleteRrset { name: record.fqdn.clone(), rr_type },
+        Change::Add { name: record.fqdn.clone(), rr_type, ttl, rdata },
+    ];
+    if with_ptr {
+        let reverse = reverse_name(&record.ip_address);
+        let mut target = Vec::new();
+        encode_name(&mut target, &record.fqdn);
+        changes.push(Change::DeleteRrset { name: reverse.clone(), rr_type: TYPE_PTR });
+        changes.push(Change::Add { name: reverse, rr_type: TYPE_PTR, ttl, rdata: target });
+    }
+    changes
+}
+
+pub fn remove_changes(record: &DnsRecord, with_ptr: bool) -> Vec<Change> {
+    let (rr_type, _) = address_rdata(&record.ip_address);
+    let mut changes = vec![Change::DeleteRrset { name: record.fqdn.clone(), rr_type }];
+    if with_ptr {
+        changes.push(Change::DeleteRrset { name: reverse_name(&record.ip_address), rr_type: TYPE_PTR });
+    }
+    changes
+}
+
+/// Build an RFC 2136 UPDATE message for a zone, signed with TSIG (RFC 8945) if a key is given.
+pub fn build_update(id: u16, zone: &str, changes: &[Change], tsig: Option<&TsigKey>, time_signed: u64) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(512);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+    msg.extend_from_slice(&1u16.to_be_bytes()); // zone count
+    msg.extend_from_slice(&0u16.to_be_bytes()); // prerequisites
+    msg.extend_from_slice(&(changes.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes()); // additional, bumped by TSIG below
+
+    encode_name(&mut msg, zone);
+    msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    for change in changes {
+        match change {
+            Change::DeleteRrset { name, rr_type } => encode_rr(&mut msg, name, *rr_type, CLASS_ANY, 0, &[]),
+            Change::Add { name, rr_type, ttl, rdata } => encode_rr(&mut msg, name, *rr_type, CLASS_IN, *ttl, rdata),
+        }
+    }
+
+    if let Some(key) = tsig {
+        let time = time_signed.to_be_bytes();
+        let mut timers = Vec::with_capacity(8);
+        timers.extend_from_slice(&time[2..]); // 48-bit time signed
+        timers.extend_from_slice(&TSIG_FUDGE_SECS.to_be_bytes());
+
+        // MAC covers the unsigned message followed by the TSIG variables
+        let mut signed = msg.clone();
+        encode_name(&mut signed, &key.name);
+        signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        signed.extend_from_slice(&0u32.to_be_bytes());
+        encode_name(&mut signed, key.algorithm.name());
+        signed.extend_from_slice(&timers);
+        signed.extend_from_slice(&0u16.to_be_bytes()); // error
+        signed.extend_from_slice(&0u16.to_be_bytes()); // other len
+        let mac = key.algorithm.mac(&key.secret, &signed);
+
+        let mut rdata = Vec::new();
+        encode_name(&mut rdata, key.algorithm.name());
+        rdata.extend_from_slice(&timers);
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&id.to_be_bytes());
+        rdata.extend_from_slice(&0u16.to_be_bytes()); // error
+        rdata.extend_from_slice(&0u16.to_be_bytes()); // other len
+        encode_rr(&mut msg, &key.name, TYPE_TSIG, CLASS_ANY, 0, &rdata);
+        msg[10..12].copy_from_slice(&1u16.to_be_bytes());
+    }
+    msg
+}
+
+fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "error",
+    }
+}
+
+/// Check the server's answer to an update with the given ID.
+pub fn check_response(id: u16, response: &[u8]) -> Result<()> {
+    if response.len() < 12 {
+        bail!("Truncated DNS response ({} bytes)", response.len());
+    }
+    if u16::from_be_bytes([response[0], response[1]]) != id {
+        bail!("DNS response ID doesn't match the update");
+    }
+    let rcode = u16::from_be_bytes([response[2], response[3]]) & 0x000f;
+    if rcode != 0 {
+        bail!("DNS server rejected the update: {} ({})", rcode_name(rcode), rcode);
+    }
+    Ok(())
+}
+
+/// RFC 2136 dynamic updates against the zone's primary server.
+pub struct Rfc2136Provider {
+    config: DnsConfig,
+}
+
+impl Rfc2136Provider {
+    pub fn new(config: DnsConfig) -> Self {
+        Self { config }
+    }
+
+    // PTRs only go to the configured reverse zone, and only for names inside it
+    fn reverse_zone_for(&self, ip: &IpAddr) -> Option<&str> {
+        let zone = self.config.reverse_zone.as_deref()?;
+        let name = reverse_name(ip);
+        (name == zone || name.ends_with(&format!(".{}", zone))).then_some(zone)
+    }
+
+    async fn send(&self, zone: &str, changes: &[Change]) -> Result<()> {
+        let id: u16 = rand::random();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let message = build_update(id, zone, changes, self.config.tsig.as_ref(), now);
+
+        let bind: SocketAddr = if self.config.server.is_ipv6() { "[::]:0".parse()? } else { "0.0.0.0:0".parse()? };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(self.config.server).await?;
+        socket.send(&message).await?;
+        let mut buf = [0u8; 4096];
+        let len = tokio::time::timeout(UPDATE_TIMEOUT, socket.recv(&mut buf)).await
+            .map_err(|_| anyhow!("No answer from DNS server {} within {:?}", self.config.server, UPDATE_TIMEOUT))??;
+        check_response(id, &buf[..len])
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136Provider {
+    async fn upsert(&self, record: &DnsRecord) -> Result<()> {
+        self.send(&self.config.zone, &upsert_changes(record, self.config.ttl, false)).await
+            .with_context(|| format!("Failed to register {}", record.fqdn))?;
+        if let Some(zone) = self.reverse_zone_for(&record.ip_address) {
+            self.send(zone, &upsert_changes(record, self.config.ttl, true)[2..]).await
+                .with_context(|| format!("Failed to register PTR for {}", record.ip_address))?;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, record: &DnsRecord) -> Result<()> {
+        self.send(&self.config.zone, &remove_changes(record, false)).await
+            .with_context(|| format!("Failed to remove {}", record.fqdn))?;
+        if let Some(zone) = self.reverse_zone_for(&record.ip_address) {
+            self.send(zone, &remove_changes(record, true)[1..]).await
+                .with_context(|| format!("Failed to remove PTR for {}", record.ip_address))?;
+        }
+        Ok(())
+    }
+}
+
+// Bring one machine's records in line with its hostname and address
+async fn sync_machine(provider: &dyn DnsProvider, zone: &str, machine_id: &Uuid) -> Result<()> {
+    let desired = crate::db::get_machine_by_id(machine_id).await?
+        .and_then(|machine| desired_record(&machine, zone));
+    let current = crate::db::get_dns_record(machine_id).await?;
+    if desired == current {
+        return Ok(());
+    }
+
+    if let Some(current) = &current {
+        provider.remove(current).await?;
+        crate::db::delete_dns_record(machine_id).await?;
+        info!("Removed DNS records {} -> {} for machine {}", current.fqdn, current.ip_address, machine_id);
+    }
+    if let Some(desired) = &desired {
+        provider.upsert(desired).await?;
+        crate::db::save_dns_record(machine_id, desired).await?;
+        info!("Registered DNS records {} -> {} for machine {}", desired.fqdn, desired.ip_address, machine_id);
+    }
+    Ok(())
+}
+
+/// Watch machine events and keep DNS in step with hostnames and addresses.
+pub async fn start_dns_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let Some(config) = CONFIG.clone() else {
+        debug!("DNS registration not configured ({} / {} unset)", SERVER_ENV_VAR, ZONE_ENV_VAR);
+        return;
+    };
+    info!("Registering machines in DNS zone {} via {}", config.zone, config.server);
+    let zone = config.zone.clone();
+    let provider: Arc<dyn DnsProvider> = Arc::new(Rfc2136Provider::new(config));
+
+    let mut events = event_manager.subscribe();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        warn!("DNS task fell too far behind on events and was disconnected");
+                        break;
+                    };
+                    let id = event.strip_prefix("machine_discovered:")
+                        .or_else(|| event.strip_prefix("machine_updated:"))
+                        .or_else(|| event.strip_prefix("machine_deleted:"))
+                        .and_then(|id| Uuid::parse_str(id).ok());
+                    if let Some(id) = id {
+                        if let Err(e) = sync_machine(provider.as_ref(), &zone, &id).await {
+                            error!("Failed to update DNS for machine {}: {:#}", id, e);
+                        }
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping DNS task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fqdn_for() {
+        assert_eq!(fqdn_for("node-1", "lab.example.com").as_deref(), Some("node-1.lab.example.com."));
+        assert_eq!(fqdn_for("Node-1.Lab.Example.com.", "lab.example.com.").as_deref(), Some("node-1.lab.example.com."));
+        assert_eq!(fqdn_for("node-1.other.org", "lab.example.com"), None);
+        assert_eq!(fqdn_for("bad_name", "lab.example.com"), None);
+    }
+
+    #[test]
+    fn test_reverse_names() {
+        assert_eq!(reverse_name(&"10.0.1.5".parse().unwrap()), "5.1.0.10.in-addr.arpa.");
+        assert!(reverse_name(&"2001:db8::1".parse().unwrap()).starts_with("1.0.0.0.0.0.0.0."));
+        assert!(reverse_name(&"2001:db8::1".parse().unwrap()).ends_with(".8.b.d.0.1.0.0.2.ip6.arpa."));
+    }
+
+    #[test]
+    fn test_update_message_layout() {
+        let record = DnsRecord { fqdn: "node-1.lab.".to_string(), ip_address: "10.0.0.5".parse().unwrap() };
+        let msg = build_update(0x1234, "lab.", &upsert_changes(&record, 300, false), None, 0);
+        assert_eq!(&msg[..12], &[0x12, 0x34, 0x28, 0x00, 0, 1, 0, 0, 0, 2, 0, 0]);
+        // Zone section: "lab." SOA IN
+        assert_eq!(&msg[12..21], &[3, b'l', b'a', b'b', 0, 0, 6, 0, 1]);
+        // The added A record ends with its address
+        assert_eq!(&msg[msg.len() - 4..], &[10, 0, 0, 5]);
+    }
+
+    #[test]
+    fn test_tsig_signed_update() {
+        let key = TsigKey { name: "dragonfly.".to_string(), algorithm: TsigAlgorithm::HmacSha256, secret: b"secret".to_vec() };
+        let record = DnsRecord { fqdn: "node-1.lab.".to_string(), ip_address: "10.0.0.5".parse().unwrap() };
+        let changes = remove_changes(&record, false);
+        let unsigned = build_update(7, "lab.", &changes, None, 0);
+        let signed = build_update(7, "lab.", &changes, Some(&key), 1_700_000_000);
+        assert_eq!(&signed[10..12], &[0, 1]);
+        assert_eq!(&signed[12..unsigned.len()], &unsigned[12..]);
+
+        // Recompute the MAC from the unsigned message and compare with the one sent
+        let mut data = unsigned.clone();
+        encode_name(&mut data, "dragonfly.");
+        data.extend_from_slice(&[0, 255, 0, 0, 0, 0]);
+        encode_name(&mut data, "hmac-sha256.");
+        data.extend_from_slice(&1_700_000_000u64.to_be_bytes()[2..]);
+        data.extend_from_slice(&[1, 44, 0, 0, 0, 0]);
+        let mac = TsigAlgorithm::HmacSha256.mac(b"secret", &data);
+        assert!(signed.windows(mac.len()).any(|w| w == mac.as_slice()));
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert!(check_response(7, &[0, 7, 0xa8, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]).is_ok());
+        let err = check_response(7, &[0, 7, 0xa8, 0x09, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap_err();
+        assert!(err.to_string().contains("NOTAUTH"));
+        assert!(check_response(8, &[0, 7, 0xa8, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+// Coordinated shutdown. Artifact streams, upstream artifact downloads and database
+// transactions register themselves here while they run. On shutdown the server stops
+// taking new artifact requests and waits for registered work to finish, for up to
+// DRAGONFLY_SHUTDOWN_GRACE_SECS (default 30), then exits and logs whatever it had to
+// abort. Event streams and idle connections aren't work and never hold shutdown up.
+
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{error, info};
+
+const DEFAULT_GRACE_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WorkKind {
+    ArtifactStream,
+    ArtifactDownload,
+    DbTransaction,
+}
+
+impl WorkKind {
+    fn describe(&self, count: usize) -> &'static str {
+        match (self, count) {
+            (WorkKind::ArtifactStream, 1) => "artifact stream",
+            (WorkKind::ArtifactStream, _) => "artifact streams",
+            (WorkKind::ArtifactDownload, 1) => "artifact download",
+            (WorkKind::ArtifactDownload, _) => "artifact downloads",
+            (WorkKind::DbTransaction, 1) => "database transaction",
+            (WorkKind::DbTransaction, _) => "database transactions",
+        }
+    }
+}
+
+/// A piece of work that was still running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveWork {
+    pub kind: WorkKind,
+    pub label: String,
+}
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static ACTIVE: Lazy<Mutex<BTreeMap<u64, ActiveWork>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Keeps shutdown waiting until dropped.
+#[must_use = "work is only tracked while the guard is alive"]
+pub struct WorkGuard {
+    id: u64,
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE.lock() {
+            active.remove(&self.id);
+        }
+    }
+}
+
+/// Register work that shutdown should wait for.
+pub fn track(kind: WorkKind, label: impl Into<String>) -> WorkGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut active) = ACTIVE.lock() {
+        active.insert(id, ActiveWork { kind, label: label.into() });
+    }
+    WorkGuard { id }
+}
+
+/// True once shutdown has started; new work should be turned away.
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+pub fn active() -> Vec<ActiveWork> {
+    ACTIVE.lock().map(|active| active.values().cloned().collect()).unwrap_or_default()
+}
+
+pub fn grace_period() -> Duration {
+    match crate::config::var("DRAGONFLY_SHUTDOWN_GRACE_SECS") {
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                error!("Invalid DRAGONFLY_SHUTDOWN_GRACE_SECS '{}', using {}s", value, DEFAULT_GRACE_SECS);
+                Duration::from_secs(DEFAULT_GRACE_SECS)
+            }
+        },
+        None => Duration::from_secs(DEFAULT_GRACE_SECS),
+    }
+}
+
+/// Stop taking new work and wait for running work to finish, up to `grace`. Returns what
+/// was still running when the grace period ran out.
+pub async fn drain(grace: Duration) -> Vec<ActiveWork> {
+    DRAINING.store(true, Ordering::Relaxed);
+    let deadline = tokio::time::Instant::now() + grace;
+    let mut reported = usize::MAX;
+    loop {
+        let remaining = active();
+        if remaining.is_empty() || tokio::time::Instant::now() >= deadline {
+            return remaining;
+        }
+        if remaining.len() != reported {
+            info!("Waiting for {} to finish", Summary(&remaining));
+            reported = remaining.len();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+/// "2 artifact streams (hookos/vmlinuz, ubuntu/noble.img), 1 database transaction (...)"
+pub struct Summary<'a>(pub &'a [ActiveWork]);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut by_kind: BTreeMap<WorkKind, Vec<&str>> = BTreeMap::new();
+        for work in self.0 {
+            by_kind.entry(work.kind).or_default().push(&work.label);
+        }
+        let parts: Vec<String> = by_kind.iter()
+            .map(|(kind, labels)| format!("{} {} ({})", labels.len(), kind.describe(labels.len()), labels.join(", ")))
+            .collect();
+        if parts.is_empty() {
+            write!(f, "nothing")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let work = vec![
+            ActiveWork { kind: WorkKind::DbTransaction, label: "reimage_machine".to_string() },
+            ActiveWork { kind: WorkKind::ArtifactStream, label: "hookos/vmlinuz-x86_64".to_string() },
+            ActiveWork { kind: WorkKind::ArtifactStream, label: "ubuntu/noble.img".to_string() },
+        ];
+        assert_eq!(
+            Summary(&work).to_string(),
+            "2 artifact streams (hookos/vmlinuz-x86_64, ubuntu/noble.img), 1 database transaction (reimage_machine)"
+        );
+        assert_eq!(Summary(&[]).to_string(), "nothing");
+    }
+
+    #[test]
+    fn test_guards_untrack_on_drop() {
+        let guard = track(WorkKind::ArtifactDownload, "test-download");
+        assert!(active().iter().any(|w| w.label == "test-download"));
+        drop(guard);
+        assert!(!active().iter().any(|w| w.label == "test-download"));
+    }
+}
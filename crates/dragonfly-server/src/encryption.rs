@@ -140,6 +140,15 @@ fn load_or_create_key_file(file_path: &str) -> Option<[u8; 32]> {
     }
 }
 
+// Derive a purpose-specific key (e.g. for signing links) so the encryption key itself is never reused
+pub fn derive_key(purpose: &str) -> [u8; 32] {
+    use hmac::{Hmac, Mac};
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(&get_encryption_key())
+        .expect("HMAC accepts any key length");
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
 // Encrypt a string using AES-GCM
 pub fn encrypt_string(plaintext: &str) -> Result<String, anyhow::Error> {
     let key = get_encryption_key();
@@ -0,0 +1,300 @@
+// Registration enrichment hooks: site-specific programs run on every agent registration that
+// can add tags, pick a hostname or OS, attach metadata, or turn the machine away. This lets
+// a site encode its own naming, placement or validation rules without patching the server.
+//
+// DRAGONFLY_ENRICHMENT_HOOKS is a comma-separated list of commands, run in order. Each one gets
+// the registration as JSON on stdin:
+//
+//     {"version": 1, "event": "registration", "existing_machine_id": null, "registration": {...}}
+//
+// and may print a JSON object on stdout (printing nothing accepts the machine unchanged):
+//
+//     {"decision": "accept", "tags": ["rack-a"], "hostname": "web-a01", "os_choice": "ubuntu-2404",
+//      "metadata": {"row": 4}}
+//     {"decision": "reject", "reason": "unknown vendor"}
+//
+// A WASM module runs through a WASI runtime, e.g. "wasmtime run /etc/dragonfly/hooks/naming.wasm".
+// Tags and metadata from all hooks are combined; a later hook's hostname or OS wins. The first
+// rejection stops registration. A hook that fails or runs past DRAGONFLY_ENRICHMENT_TIMEOUT_SECS
+// (default 10) is skipped, unless DRAGONFLY_ENRICHMENT_FAIL_POLICY=closed, which rejects instead.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::RegisterRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const PROTOCOL_VERSION: u32 = 1;
+// Stderr kept from a failing hook for the log
+const MAX_STDERR_LEN: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    #[default]
+    Accept,
+    Reject,
+}
+
+/// What a hook prints on stdout.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookResponse {
+    #[serde(default)]
+    pub decision: Decision,
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub hostname: Option<String>,
+    pub os_choice: Option<String>,
+    #[serde(default)]
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+}
+
+impl HookResponse {
+    pub fn parse(stdout: &[u8]) -> Result<Self> {
+        let text = String::from_utf8_lossy(stdout);
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let mut response: HookResponse = serde_json::from_str(text.trim()).context("output is not a valid hook response")?;
+        if !response.tags.is_empty() {
+            let change = crate::bulk::TagChange { add: response.tags, remove: Vec::new() };
+            response.tags = change.normalize()?.add;
+        }
+        if let Some(hostname) = &response.hostname {
+            if !dragonfly_common::validation::is_valid_hostname(hostname) {
+                bail!("invalid hostname '{}'", hostname);
+            }
+        }
+        response.os_choice = response.os_choice.map(|os| os.trim().to_string()).filter(|os| !os.is_empty());
+        if let Some(os_choice) = &response.os_choice {
+            if let Err(e) = dragonfly_common::os::check_os_choice(os_choice) {
+                bail!("invalid os_choice: {}", e);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// The combined result of every hook that accepted a registration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Enrichment {
+    pub tags: Vec<String>,
+    pub hostname: Option<String>,
+    pub os_choice: Option<String>,
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+    /// Hooks that ran successfully, in order
+    pub hooks: Vec<String>,
+}
+
+impl Enrichment {
+    pub fn merge(&mut self, hook: &str, response: HookResponse) {
+        for tag in response.tags {
+            if !self.tags.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+                self.tags.push(tag);
+            }
+        }
+        if response.hostname.is_some() {
+            self.hostname = response.hostname;
+        }
+        if response.os_choice.is_some() {
+            self.os_choice = response.os_choice;
+        }
+        self.metadata.extend(response.metadata);
+        self.hooks.push(hook.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.hostname.is_none() && self.os_choice.is_none() && self.metadata.is_empty()
+    }
+}
+
+/// Metadata hooks attached to a machine, as stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineEnrichment {
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+    pub hooks: Vec<String>,
+    pub enriched_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Accept,
+    Reject { hook: String, reason: String },
+}
+
+// Configured hook commands, each split into program and arguments
+fn parse_hooks(value: &str) -> Vec<Vec<String>> {
+    value.split(',')
+        .map(|hook| hook.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+        .filter(|hook| !hook.is_empty())
+        .collect()
+}
+
+fn hooks() -> Vec<Vec<String>> {
+    parse_hooks(&crate::config::var("DRAGONFLY_ENRICHMENT_HOOKS").unwrap_or_default())
+}
+
+fn timeout() -> Duration {
+    let secs = crate::config::var("DRAGONFLY_ENRICHMENT_TIMEOUT_SECS")
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+fn fail_closed() -> bool {
+    crate::config::var("DRAGONFLY_ENRICHMENT_FAIL_POLICY").is_some_and(|v| v.trim().eq_ignore_ascii_case("closed"))
+}
+
+async fn run_hook(command: &[String], input: &[u8], timeout: Duration) -> Result<HookResponse> {
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .env("DRAGONFLY_HOOK_EVENT", "registration")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start")?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?;
+    let output = tokio::time::timeout(timeout, async {
+        // A hook that doesn't read its input closes the pipe early; that's fine
+        let _ = stdin.write_all(input).await;
+        drop(stdin);
+        child.wait_with_output().await
+    })
+    .await
+    .map_err(|_| anyhow!("timed out after {}s", timeout.as_secs()))??;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("exited with {}: {}", output.status, stderr.trim().chars().take(MAX_STDERR_LEN).collect::<String>());
+    }
+    HookResponse::parse(&output.stdout)
+}
+
+/// Run the configured hooks on a registration, in order.
+pub async fn run(payload: &RegisterRequest, existing_machine_id: Option<Uuid>) -> (Outcome, Enrichment) {
+    let mut enrichment = Enrichment::default();
+    let hooks = hooks();
+    if hooks.is_empty() {
+        return (Outcome::Accept, enrichment);
+    }
+    let input = json!({
+        "version": PROTOCOL_VERSION,
+        "event": "registration",
+        "existing_machine_id": existing_machine_id,
+        "registration": payload,
+    })
+    .to_string();
+    let timeout = timeout();
+    for command in &hooks {
+        let name = command.join(" ");
+        match run_hook(command, input.as_bytes(), timeout).await {
+            Ok(response) if response.decision == Decision::Reject => {
+                let reason = response.reason.unwrap_or_else(|| "no reason given".to_string());
+                info!("Enrichment hook '{}' rejected registration for MAC {}: {}", name, payload.mac_address, reason);
+                return (Outcome::Reject { hook: name, reason }, enrichment);
+            }
+            Ok(response) => enrichment.merge(&name, response),
+            Err(e) if fail_closed() => {
+                warn!("Enrichment hook '{}' failed for MAC {}: {:#}", name, payload.mac_address, e);
+                return (Outcome::Reject { hook: name, reason: format!("hook failed: {:#}", e) }, enrichment);
+            }
+            Err(e) => warn!("Enrichment hook '{}' failed for MAC {} (skipping it): {:#}", name, payload.mac_address, e),
+        }
+    }
+    (Outcome::Accept, enrichment)
+}
+
+/// Apply what the hooks decided to a registered machine.
+pub async fn apply(machine_id: &Uuid, enrichment: &Enrichment) -> Result<()> {
+    if enrichment.is_empty() {
+        return Ok(());
+    }
+    if !enrichment.tags.is_empty() {
+        let current = crate::db::get_machine_tags(machine_id).await?;
+        let change = crate::bulk::TagChange { add: enrichment.tags.clone(), remove: Vec::new() };
+        if let Some(tags) = change.apply(&current) {
+            crate::db::update_machine_tags(machine_id, &tags).await?;
+        }
+    }
+    if let Some(hostname) = &enrichment.hostname {
+        crate::db::update_hostname(machine_id, hostname).await?;
+    }
+    if let Some(os_choice) = &enrichment.os_choice {
+        crate::db::assign_os(machine_id, os_choice).await?;
+    }
+    if !enrichment.metadata.is_empty() {
+        crate::db::save_machine_enrichment(machine_id, &enrichment.metadata, &enrichment.hooks).await?;
+    }
+    info!("Applied enrichment from {} hook(s) to machine {}", enrichment.hooks.len(), machine_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hooks() {
+        assert_eq!(
+            parse_hooks("/etc/dragonfly/hooks/name.sh, wasmtime run /etc/dragonfly/hooks/place.wasm,,"),
+            vec![
+                vec!["/etc/dragonfly/hooks/name.sh".to_string()],
+                vec!["wasmtime".to_string(), "run".to_string(), "/etc/dragonfly/hooks/place.wasm".to_string()],
+            ]
+        );
+        assert!(parse_hooks(" ").is_empty());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(HookResponse::parse(b"\n").unwrap().decision, Decision::Accept);
+
+        let response = HookResponse::parse(br#"{"tags": [" rack-a "], "hostname": "web-a01", "os_choice": " ", "metadata": {"row": 4}}"#).unwrap();
+        assert_eq!(response.tags, vec!["rack-a".to_string()]);
+        assert_eq!(response.os_choice, None);
+
+        let rejected = HookResponse::parse(br#"{"decision": "reject", "reason": "unknown vendor"}"#).unwrap();
+        assert_eq!((rejected.decision, rejected.reason.as_deref()), (Decision::Reject, Some("unknown vendor")));
+
+        assert!(HookResponse::parse(br#"{"hostname": "bad_name"}"#).is_err());
+        assert_eq!(HookResponse::parse(br#"{"os_choice": "debian-12"}"#).unwrap().os_choice.as_deref(), Some("debian-12"));
+        assert!(HookResponse::parse(br#"{"os_choice": "ubuntu"}"#).is_err());
+        assert!(HookResponse::parse(br#"{"tags": ["a/b"]}"#).is_err());
+        assert!(HookResponse::parse(br#"{"hostnme": "typo"}"#).is_err());
+        assert!(HookResponse::parse(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut enrichment = Enrichment::default();
+        assert!(enrichment.is_empty());
+        enrichment.merge("first", HookResponse::parse(br#"{"tags": ["gpu"], "hostname": "a", "metadata": {"row": 1, "rack": "a"}}"#).unwrap());
+        enrichment.merge("second", HookResponse::parse(br#"{"tags": ["GPU", "db"], "hostname": "b", "metadata": {"row": 2}}"#).unwrap());
+        assert_eq!(enrichment.tags, vec!["gpu".to_string(), "db".to_string()]);
+        assert_eq!(enrichment.hostname.as_deref(), Some("b"));
+        assert_eq!(enrichment.metadata, json!({"row": 2, "rack": "a"}).as_object().unwrap().clone());
+        assert_eq!(enrichment.hooks, vec!["first".to_string(), "second".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_run_hook() {
+        let sh = |script: &str| vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+        let timeout = Duration::from_secs(5);
+
+        let response = run_hook(&sh(r#"grep -q '"event":"registration"' && echo '{"tags": ["seen"]}'"#), br#"{"event":"registration"}"#, timeout).await.unwrap();
+        assert_eq!(response.tags, vec!["seen".to_string()]);
+        assert!(run_hook(&sh("echo nope >&2; exit 3"), b"{}", timeout).await.unwrap_err().to_string().contains("nope"));
+        assert!(run_hook(&sh("sleep 5"), b"{}", Duration::from_millis(100)).await.is_err());
+        assert!(run_hook(&["/nonexistent/hook".to_string()], b"{}", timeout).await.is_err());
+    }
+}
@@ -0,0 +1,176 @@
+// Ephemeral (diskless) machines: rather than installing an OS, the machine netboots a live
+// image every time it starts. Render farm nodes and kiosks that keep nothing on local disk.
+//
+// Every boot gets a fresh boot ID, which doubles as the cloud-init instance ID, so the
+// machine's user data runs in full on each boot. The live system reads it through the
+// NoCloud datasource at /ephemeral/{boot_id}/, and that fetch is what marks the machine
+// "Booted Live" (as opposed to "Ready", which means an installed OS).
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::Machine;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// User data is stored and served verbatim on every boot
+const MAX_CLOUD_INIT_BYTES: usize = 64 * 1024;
+
+/// The live image an ephemeral machine boots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EphemeralConfig {
+    /// http(s) URL, or a path under the artifact directory (served at /ipxe/{path})
+    pub kernel: String,
+    pub initrd: String,
+    pub kernel_args: Option<String>,
+    /// User data handed to cloud-init on every boot
+    pub cloud_init: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The machine's most recent live boot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EphemeralBoot {
+    pub boot_id: Uuid,
+    /// When the boot script was handed out
+    pub served_at: DateTime<Utc>,
+    /// When the live system fetched its cloud-init data; None while it's still coming up
+    pub booted_at: Option<DateTime<Utc>>,
+}
+
+/// Ephemeral mode as submitted by an admin; `ephemeral: false` turns it off.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EphemeralInput {
+    pub ephemeral: bool,
+    pub kernel: Option<String>,
+    pub initrd: Option<String>,
+    pub kernel_args: Option<String>,
+    pub cloud_init: Option<String>,
+}
+
+fn validate_source(field: &str, value: &str) -> Result<()> {
+    if value.starts_with("http://") || value.starts_with("https://") {
+        if value.chars().any(char::is_whitespace) {
+            bail!("{} URL must not contain whitespace", field);
+        }
+        return Ok(());
+    }
+    if value.is_empty() || value.starts_with('/') || value.split('/').any(|part| part == "..")
+        || !value.chars().all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+    {
+        bail!("{} must be an http(s) URL or a path under the artifact directory", field);
+    }
+    Ok(())
+}
+
+impl EphemeralInput {
+    /// The config to store, or None to leave ephemeral mode.
+    pub fn into_config(self, now: DateTime<Utc>) -> Result<Option<EphemeralConfig>> {
+        if !self.ephemeral {
+            return Ok(None);
+        }
+        let (Some(kernel), Some(initrd)) = (self.kernel, self.initrd) else {
+            bail!("Ephemeral mode needs both a kernel and an initrd");
+        };
+        let (kernel, initrd) = (kernel.trim().to_string(), initrd.trim().to_string());
+        validate_source("kernel", &kernel)?;
+        validate_source("initrd", &initrd)?;
+
+        let kernel_args = self.kernel_args.map(|a| a.trim().to_string()).filter(|a| !a.is_empty());
+        if kernel_args.as_ref().is_some_and(|a| a.contains('\n') || a.contains('\r')) {
+            bail!("Kernel arguments must be on a single line");
+        }
+        let cloud_init = self.cloud_init.filter(|c| !c.trim().is_empty());
+        if cloud_init.as_ref().is_some_and(|c| c.len() > MAX_CLOUD_INIT_BYTES) {
+            bail!("cloud-init user data must be at most {} KiB", MAX_CLOUD_INIT_BYTES / 1024);
+        }
+        Ok(Some(EphemeralConfig { kernel, initrd, kernel_args, cloud_init, updated_at: now }))
+    }
+}
+
+fn source_url(base_url: &str, source: &str) -> String {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        source.to_string()
+    } else {
+        format!("{}/ipxe/{}", base_url, source)
+    }
+}
+
+/// Where the live system finds its cloud-init data for one boot.
+pub fn seed_url(base_url: &str, boot_id: &Uuid) -> String {
+    format!("{}/ephemeral/{}/", base_url, boot_id)
+}
+
+/// The iPXE script for one live boot.
+pub fn render_script(base_url: &str, config: &EphemeralConfig, boot_id: &Uuid) -> String {
+    let mut args = vec!["initrd=initrd".to_string()];
+    args.extend(config.kernel_args.clone());
+    args.push(format!("ds=nocloud-net;s={}", seed_url(base_url, boot_id)));
+    format!(
+        "#!ipxe\n# Ephemeral boot {}\nkernel {} {}\ninitrd --name initrd {}\nboot\n",
+        boot_id,
+        source_url(base_url, &config.kernel),
+        args.join(" "),
+        source_url(base_url, &config.initrd)
+    )
+}
+
+/// NoCloud meta-data; a new instance ID per boot makes cloud-init run everything again.
+pub fn meta_data(boot_id: &Uuid, machine: &Machine) -> String {
+    let mut meta = format!("instance-id: {}\n", boot_id);
+    if let Some(hostname) = machine.hostname.as_deref().or(machine.memorable_name.as_deref()) {
+        meta.push_str(&format!("local-hostname: {}\n", hostname));
+    }
+    meta
+}
+
+/// NoCloud user-data: the machine's cloud-init, or an empty cloud-config.
+pub fn user_data(config: &EphemeralConfig) -> String {
+    config.cloud_init.clone().unwrap_or_else(|| "#cloud-config\n{}\n".to_string())
+}
+
+/// The boot script for an ephemeral machine, recording the new boot; None for installed machines.
+pub async fn boot_script(machine: &Machine, base_url: &str) -> Result<Option<String>> {
+    let Some(config) = crate::db::get_ephemeral_config(&machine.id).await? else {
+        return Ok(None);
+    };
+    let boot_id = Uuid::new_v4();
+    crate::db::record_ephemeral_boot(&machine.id, &boot_id, Utc::now()).await?;
+    Ok(Some(render_script(base_url, &config, &boot_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(kernel: &str, initrd: &str) -> EphemeralInput {
+        EphemeralInput {
+            ephemeral: true,
+            kernel: Some(kernel.to_string()),
+            initrd: Some(initrd.to_string()),
+            kernel_args: Some("  console=ttyS0 boot=live ".to_string()),
+            cloud_init: Some(" ".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_input_validation() {
+        let now = Utc::now();
+        let config = input("live/vmlinuz", "https://images.example.com/farm/initrd.img").into_config(now).unwrap().unwrap();
+        assert_eq!(config.kernel_args.as_deref(), Some("console=ttyS0 boot=live"));
+        assert_eq!(config.cloud_init, None);
+        assert!(input("../../etc/passwd", "live/initrd").into_config(now).is_err());
+        assert!(input("/var/lib/vmlinuz", "live/initrd").into_config(now).is_err());
+        assert!(EphemeralInput { kernel: None, ..input("a", "b") }.into_config(now).is_err());
+        assert_eq!(EphemeralInput { ephemeral: false, ..input("", "") }.into_config(now).unwrap(), None);
+    }
+
+    #[test]
+    fn test_render_script() {
+        let config = input("live/vmlinuz", "https://images.example.com/initrd.img").into_config(Utc::now()).unwrap().unwrap();
+        let boot_id = Uuid::nil();
+        let script = render_script("http://10.0.0.1:3000", &config, &boot_id);
+        assert!(script.contains("kernel http://10.0.0.1:3000/ipxe/live/vmlinuz initrd=initrd console=ttyS0 boot=live ds=nocloud-net;s=http://10.0.0.1:3000/ephemeral/00000000-0000-0000-0000-000000000000/\n"));
+        assert!(script.contains("initrd --name initrd https://images.example.com/initrd.img\n"));
+        assert_eq!(user_data(&config), "#cloud-config\n{}\n");
+    }
+}
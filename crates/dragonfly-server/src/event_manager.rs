@@ -1,5 +1,22 @@
-use tokio::sync::broadcast;
-use tracing::{info, warn};
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, info, warn};
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// Number of recently published events kept in memory, for diagnostics and for
+// replaying to SSE clients that reconnect with Last-Event-ID
+const RECENT_EVENT_CAPACITY: usize = 200;
+
+// Per-subscriber queue bound; progress events beyond it are dropped
+const SUBSCRIBER_QUEUE_CAPACITY: usize = 256;
+
+// Lifecycle events may overflow the bound up to this point, after which the
+// subscriber is cut off rather than buffering without limit
+const SUBSCRIBER_QUEUE_HARD_LIMIT: usize = 4096;
 
 // Event types that can be published
 #[derive(Debug, Clone)]
@@ -9,48 +26,394 @@ pub enum Event {
     MachineDeleted(String),
 }
 
+// An event as it was published, with its sequence number and the time it was sent
+#[derive(Debug, Clone, Serialize, serde::Deserialize)]
+pub struct RecordedEvent {
+    /// Increases by one per published event; restarts at 1 with the server
+    #[serde(default)]
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+/// How important an event is to deliver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPriority {
+    /// Superseded by the next update for the same thing; may be coalesced or dropped
+    Progress,
+    /// Machine and workflow state changes; never dropped for a live subscriber
+    Lifecycle,
+}
+
+impl EventPriority {
+    pub fn of(message: &str) -> Self {
+        let event_type = message.split(':').next().unwrap_or(message);
+        if event_type.ends_with("_progress") {
+            EventPriority::Progress
+        } else {
+            EventPriority::Lifecycle
+        }
+    }
+}
+
+// Progress events with the same key replace each other in a subscriber's queue.
+// The key is the event type plus its first field (normally the machine ID); JSON
+// payloads have no such field and are never coalesced.
+fn coalesce_key(message: &str) -> Option<&str> {
+    let mut fields = message.splitn(3, ':');
+    let event_type = fields.next()?;
+    let first = fields.next()?;
+    if first.starts_with('{') || fields.next().is_none() {
+        return None;
+    }
+    Some(&message[..event_type.len() + 1 + first.len()])
+}
+
+// Counters for the event fan-out, exported on /metrics
+#[derive(Debug, Default)]
+struct Counters {
+    published: AtomicU64,
+    delivered: AtomicU64,
+    coalesced: AtomicU64,
+    dropped: AtomicU64,
+    disconnected: AtomicU64,
+    refused: AtomicU64,
+}
+
+/// Snapshot of event fan-out counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventMetrics {
+    pub subscribers: usize,
+    pub published: u64,
+    pub delivered: u64,
+    /// Progress events replaced by a newer one before the subscriber read them
+    pub coalesced: u64,
+    /// Progress events discarded because a subscriber's queue was full
+    pub dropped: u64,
+    /// Subscribers cut off for falling too far behind on lifecycle events
+    pub disconnected: u64,
+    /// Event streams open to SSE clients
+    pub streams: usize,
+    /// SSE clients turned away at the stream limit
+    pub streams_refused: u64,
+}
+
+impl EventMetrics {
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP dragonfly_event_subscribers Connected event subscribers.\n");
+        out.push_str("# TYPE dragonfly_event_subscribers gauge\n");
+        out.push_str(&format!("dragonfly_event_subscribers {}\n", self.subscribers));
+        out.push_str("# HELP dragonfly_events_published_total Events published.\n");
+        out.push_str("# TYPE dragonfly_events_published_total counter\n");
+        out.push_str(&format!("dragonfly_events_published_total {}\n", self.published));
+        out.push_str("# HELP dragonfly_events_delivered_total Events queued to subscribers.\n");
+        out.push_str("# TYPE dragonfly_events_delivered_total counter\n");
+        out.push_str(&format!("dragonfly_events_delivered_total {}\n", self.delivered));
+        out.push_str("# HELP dragonfly_events_dropped_total Events not delivered to a subscriber.\n");
+        out.push_str("# TYPE dragonfly_events_dropped_total counter\n");
+        out.push_str(&format!("dragonfly_events_dropped_total{{reason=\"coalesced\"}} {}\n", self.coalesced));
+        out.push_str(&format!("dragonfly_events_dropped_total{{reason=\"queue_full\"}} {}\n", self.dropped));
+        out.push_str("# HELP dragonfly_event_subscribers_disconnected_total Subscribers cut off for lagging.\n");
+        out.push_str("# TYPE dragonfly_event_subscribers_disconnected_total counter\n");
+        out.push_str(&format!("dragonfly_event_subscribers_disconnected_total {}\n", self.disconnected));
+        out.push_str("# HELP dragonfly_event_streams Event streams open to SSE clients.\n");
+        out.push_str("# TYPE dragonfly_event_streams gauge\n");
+        out.push_str(&format!("dragonfly_event_streams {}\n", self.streams));
+        out.push_str("# HELP dragonfly_event_streams_refused_total SSE clients refused at the stream limit.\n");
+        out.push_str("# TYPE dragonfly_event_streams_refused_total counter\n");
+        out.push_str(&format!("dragonfly_event_streams_refused_total {}\n", self.streams_refused));
+        out
+    }
+}
+
+// What happened when an event was offered to one subscriber
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Offer {
+    Queued,
+    Coalesced,
+    Dropped,
+    Disconnect,
+}
+
+// One subscriber's pending events
+#[derive(Debug, Default)]
+struct SubscriberQueue {
+    events: Mutex<VecDeque<RecordedEvent>>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+impl SubscriberQueue {
+    fn offer(&self, event: &RecordedEvent) -> Offer {
+        let mut events = match self.events.lock() {
+            Ok(events) => events,
+            Err(_) => return Offer::Disconnect,
+        };
+        let offer = enqueue(&mut events, event, SUBSCRIBER_QUEUE_CAPACITY, SUBSCRIBER_QUEUE_HARD_LIMIT);
+        drop(events);
+        match offer {
+            Offer::Queued | Offer::Coalesced => self.notify.notify_one(),
+            Offer::Disconnect => self.close(),
+            Offer::Dropped => {}
+        }
+        offer
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+}
+
+// Queueing policy, kept free of locking so it can be tested directly
+fn enqueue(events: &mut VecDeque<RecordedEvent>, event: &RecordedEvent, capacity: usize, hard_limit: usize) -> Offer {
+    let priority = EventPriority::of(&event.message);
+
+    if priority == EventPriority::Progress {
+        if let Some(key) = coalesce_key(&event.message) {
+            if let Some(queued) = events.iter_mut().find(|e| coalesce_key(&e.message) == Some(key)) {
+                *queued = event.clone();
+                return Offer::Coalesced;
+            }
+        }
+        if events.len() >= capacity {
+            return Offer::Dropped;
+        }
+        events.push_back(event.clone());
+        return Offer::Queued;
+    }
+
+    // Make room for a lifecycle event by shedding the oldest progress event
+    if events.len() >= capacity {
+        if let Some(pos) = events.iter().position(|e| EventPriority::of(&e.message) == EventPriority::Progress) {
+            events.remove(pos);
+        }
+    }
+    if events.len() >= hard_limit {
+        return Offer::Disconnect;
+    }
+    events.push_back(event.clone());
+    Offer::Queued
+}
+
+/// Events published after `last_id`, or None if some of them are no longer buffered
+/// (or `last_id` is from before a server restart), in which case the client must resync.
+fn events_after(recent: &VecDeque<RecordedEvent>, last_id: u64, latest_id: u64) -> Option<Vec<RecordedEvent>> {
+    if last_id > latest_id {
+        return None;
+    }
+    if last_id < latest_id && !matches!(recent.front(), Some(oldest) if oldest.id <= last_id + 1) {
+        return None;
+    }
+    Some(recent.iter().filter(|e| e.id > last_id).cloned().collect())
+}
+
+/// A live subscription. Dropping it unsubscribes.
+pub struct Subscriber {
+    queue: Arc<SubscriberQueue>,
+}
+
+impl Subscriber {
+    /// Wait for the next event. Returns None once the subscriber has been cut off
+    /// for lagging; lifecycle events are never skipped while it returns Some.
+    pub async fn recv(&mut self) -> Option<String> {
+        self.recv_event().await.map(|event| event.message)
+    }
+
+    /// Like `recv`, with the event's ID and timestamp.
+    pub async fn recv_event(&mut self) -> Option<RecordedEvent> {
+        loop {
+            let next = self.queue.events.lock().ok().and_then(|mut events| events.pop_front());
+            if let Some(event) = next {
+                return Some(event);
+            }
+            if self.queue.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            self.queue.notify.notified().await;
+        }
+    }
+}
+
+/// A subscription held open by an SSE client. Dropping it unsubscribes and frees its slot.
+pub struct StreamSubscriber {
+    subscriber: Subscriber,
+    open: Arc<AtomicUsize>,
+}
+
+impl Deref for StreamSubscriber {
+    type Target = Subscriber;
+
+    fn deref(&self) -> &Subscriber {
+        &self.subscriber
+    }
+}
+
+impl DerefMut for StreamSubscriber {
+    fn deref_mut(&mut self) -> &mut Subscriber {
+        &mut self.subscriber
+    }
+}
+
+impl Drop for StreamSubscriber {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 // Event manager for publishing SSE events
 pub struct EventManager {
-    tx: broadcast::Sender<String>,
+    subscribers: Arc<Mutex<Vec<Weak<SubscriberQueue>>>>,
+    counters: Arc<Counters>,
+    // Holds the lock while an event is numbered and recorded, so IDs enter the buffer in order
+    recent: Arc<Mutex<VecDeque<RecordedEvent>>>,
+    last_id: Arc<AtomicU64>,
+    // SSE clients only; the server's own subscribers (DNS, notifications, ...) aren't limited
+    streams: Arc<AtomicUsize>,
 }
 
 impl EventManager {
     pub fn new() -> Self {
-        let (tx, _) = broadcast::channel(100);
-        Self { tx }
+        Self {
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            counters: Arc::new(Counters::default()),
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_EVENT_CAPACITY))),
+            last_id: Arc::new(AtomicU64::new(0)),
+            streams: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Create a new subscription to events, with its own bounded queue
+    pub fn subscribe(&self) -> Subscriber {
+        let queue = Arc::new(SubscriberQueue::default());
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(Arc::downgrade(&queue));
+        }
+        Subscriber { queue }
     }
 
-    // Create a new subscription to events
-    pub fn subscribe(&self) -> broadcast::Receiver<String> {
-        self.tx.subscribe()
+    // Subscribe on behalf of an SSE client, or None if `limit` streams are already open
+    pub fn subscribe_stream(&self, limit: Option<usize>) -> Option<StreamSubscriber> {
+        let claimed = self.streams.fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| match limit {
+            Some(max) if open >= max => None,
+            _ => Some(open + 1),
+        });
+        if claimed.is_err() {
+            self.counters.refused.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(StreamSubscriber { subscriber: self.subscribe(), open: self.streams.clone() })
     }
 
-    // Publish an event, returning Result to handle errors
+    // Publish an event to every subscriber's queue, returning how many accepted it.
+    // Never waits on a subscriber, so a slow one can't hold up the others.
     pub fn send(&self, message: String) -> Result<usize, broadcast::error::SendError<String>> {
-        let receivers = self.tx.receiver_count();
-        
-        // Only attempt to send if we have receivers to avoid log spam
-        if receivers > 0 {
-            match self.tx.send(message.clone()) {
-                Ok(n) => {
-                    info!("Event sent to {} receivers: {}", n, message);
-                    Ok(n)
+        let event = self.record(&message);
+        self.counters.published.fetch_add(1, Ordering::Relaxed);
+
+        let live: Vec<Arc<SubscriberQueue>> = match self.subscribers.lock() {
+            Ok(mut subscribers) => {
+                subscribers.retain(|s| s.strong_count() > 0);
+                subscribers.iter().filter_map(Weak::upgrade).collect()
+            },
+            Err(_) => Vec::new(),
+        };
+
+        if live.is_empty() {
+            // Create a more descriptive error when there are no receivers
+            debug!("No receivers for event: {}", message);
+            return Err(broadcast::error::SendError(message));
+        }
+
+        let mut delivered = 0;
+        for queue in live.iter().filter(|q| !q.closed.load(Ordering::Acquire)) {
+            match queue.offer(&event) {
+                Offer::Queued => delivered += 1,
+                Offer::Coalesced => {
+                    delivered += 1;
+                    self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+                },
+                Offer::Dropped => {
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                },
+                Offer::Disconnect => {
+                    warn!("Disconnecting event subscriber that fell {} events behind", SUBSCRIBER_QUEUE_HARD_LIMIT);
+                    self.counters.disconnected.fetch_add(1, Ordering::Relaxed);
                 },
-                Err(e) => {
-                    warn!("Failed to send event: {}", e);
-                    Err(e)
-                }
             }
-        } else {
-            // Create a more descriptive error when there are no receivers
-            warn!("No receivers for event: {}", message);
-            Err(broadcast::error::SendError(message))
+        }
+        self.counters.delivered.fetch_add(delivered as u64, Ordering::Relaxed);
+        if EventPriority::of(&message) == EventPriority::Lifecycle {
+            info!("Event sent to {} receivers: {}", delivered, message);
+        }
+        Ok(delivered)
+    }
+
+    // Current fan-out counters
+    pub fn metrics(&self) -> EventMetrics {
+        EventMetrics {
+            subscribers: self.receiver_count(),
+            published: self.counters.published.load(Ordering::Relaxed),
+            delivered: self.counters.delivered.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            disconnected: self.counters.disconnected.load(Ordering::Relaxed),
+            streams: self.streams.load(Ordering::Acquire),
+            streams_refused: self.counters.refused.load(Ordering::Relaxed),
         }
     }
     
     // Get the current receiver count
     pub fn receiver_count(&self) -> usize {
-        self.tx.receiver_count()
+        match self.subscribers.lock() {
+            Ok(subscribers) => subscribers.iter().filter(|s| s.strong_count() > 0).count(),
+            Err(_) => 0,
+        }
+    }
+
+    // Snapshot of recently published events, oldest first
+    pub fn recent_events(&self) -> Vec<RecordedEvent> {
+        match self.recent.lock() {
+            Ok(recent) => recent.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Events to replay to a client reconnecting after `last_id`; None means some were missed
+    // for good and the client should reload its state instead
+    pub fn events_after(&self, last_id: u64) -> Option<Vec<RecordedEvent>> {
+        let recent = self.recent.lock().ok()?;
+        events_after(&recent, last_id, self.last_id.load(Ordering::Acquire))
+    }
+
+    // Drop recorded events older than the cutoff, returning how many were removed
+    pub fn prune_before(&self, cutoff: &DateTime<Utc>) -> usize {
+        match self.recent.lock() {
+            Ok(mut recent) => {
+                let before = recent.len();
+                recent.retain(|e| e.timestamp >= *cutoff);
+                before - recent.len()
+            },
+            Err(_) => 0,
+        }
+    }
+
+    // Number an event and remember it in the bounded recent-events buffer
+    fn record(&self, message: &str) -> RecordedEvent {
+        let mut recent = self.recent.lock().ok();
+        let event = RecordedEvent {
+            id: self.last_id.fetch_add(1, Ordering::AcqRel) + 1,
+            timestamp: Utc::now(),
+            message: message.to_string(),
+        };
+        if let Some(recent) = recent.as_mut() {
+            if recent.len() == RECENT_EVENT_CAPACITY {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+        event
     }
 }
 
@@ -64,7 +427,103 @@ impl Default for EventManager {
 impl Clone for EventManager {
     fn clone(&self) -> Self {
         Self {
-            tx: self.tx.clone(),
+            subscribers: self.subscribers.clone(),
+            counters: self.counters.clone(),
+            recent: self.recent.clone(),
+            last_id: self.last_id.clone(),
+            streams: self.streams.clone(),
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(message: &str) -> RecordedEvent {
+        RecordedEvent { id: 0, timestamp: Utc::now(), message: message.to_string() }
+    }
+
+    impl PartialEq<&str> for RecordedEvent {
+        fn eq(&self, other: &&str) -> bool {
+            self.message == *other
+        }
+    }
+
+    #[test]
+    fn test_priority_and_coalesce_key() {
+        assert_eq!(EventPriority::of("task_progress:abc:Stream image:50.000:5:10"), EventPriority::Progress);
+        assert_eq!(EventPriority::of("machine_updated:abc"), EventPriority::Lifecycle);
+        assert_eq!(coalesce_key("task_progress:abc:Stream image:50.000:5:10"), Some("task_progress:abc"));
+        assert_eq!(coalesce_key("ip_download_progress:{\"ip\":\"10.0.0.1\"}"), None);
+    }
+
+    #[test]
+    fn test_progress_is_coalesced_then_dropped_when_full() {
+        let mut events = VecDeque::new();
+        assert_eq!(enqueue(&mut events, &event("task_progress:a:img:10:1:10"), 2, 4), Offer::Queued);
+        assert_eq!(enqueue(&mut events, &event("task_progress:a:img:20:2:10"), 2, 4), Offer::Coalesced);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], "task_progress:a:img:20:2:10");
+        assert_eq!(enqueue(&mut events, &event("task_progress:b:img:10:1:10"), 2, 4), Offer::Queued);
+        assert_eq!(enqueue(&mut events, &event("ip_download_progress:{}"), 2, 4), Offer::Dropped);
+    }
+
+    #[test]
+    fn test_lifecycle_evicts_progress_and_disconnects_at_hard_limit() {
+        let mut events = VecDeque::new();
+        enqueue(&mut events, &event("task_progress:a:img:10:1:10"), 2, 3);
+        enqueue(&mut events, &event("machine_updated:a"), 2, 3);
+        assert_eq!(enqueue(&mut events, &event("machine_updated:b"), 2, 3), Offer::Queued);
+        assert_eq!(events, ["machine_updated:a", "machine_updated:b"]);
+        assert_eq!(enqueue(&mut events, &event("machine_updated:c"), 2, 3), Offer::Queued);
+        assert_eq!(enqueue(&mut events, &event("machine_updated:d"), 2, 3), Offer::Disconnect);
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_does_not_affect_others() {
+        let manager = EventManager::new();
+        let mut fast = manager.subscribe();
+        let _slow = manager.subscribe();
+        for i in 0..SUBSCRIBER_QUEUE_CAPACITY + 10 {
+            let _ = manager.send(format!("ip_download_progress:{{\"n\":{}}}", i));
+            assert!(fast.recv().await.is_some());
+        }
+        let _ = manager.send("machine_updated:x".to_string());
+        assert_eq!(fast.recv().await.as_deref(), Some("machine_updated:x"));
+        assert_eq!(manager.metrics().dropped, 10);
+    }
+
+    #[test]
+    fn test_stream_limit() {
+        let manager = EventManager::new();
+        let _internal = manager.subscribe();
+        let first = manager.subscribe_stream(Some(2)).unwrap();
+        let _second = manager.subscribe_stream(Some(2)).unwrap();
+        assert!(manager.subscribe_stream(Some(2)).is_none());
+        drop(first);
+        assert!(manager.subscribe_stream(Some(2)).is_some());
+        assert!(manager.subscribe_stream(None).is_some());
+        let metrics = manager.metrics();
+        assert_eq!((metrics.streams, metrics.streams_refused), (1, 1));
+    }
+
+    #[test]
+    fn test_replay_after_last_event_id() {
+        let manager = EventManager::new();
+        for i in 0..RECENT_EVENT_CAPACITY + 5 {
+            let _ = manager.send(format!("machine_updated:{}", i));
+        }
+        let latest = (RECENT_EVENT_CAPACITY + 5) as u64;
+
+        let replay = manager.events_after(latest - 2).unwrap();
+        assert_eq!(replay.iter().map(|e| e.id).collect::<Vec<_>>(), [latest - 1, latest]);
+        assert_eq!(replay[1].message, format!("machine_updated:{}", RECENT_EVENT_CAPACITY + 4));
+        assert!(manager.events_after(latest).unwrap().is_empty());
+        // The oldest buffered event is 6, so the client missed event 5
+        assert!(manager.events_after(5).is_some());
+        assert!(manager.events_after(4).is_none());
+        // An ID from before a restart
+        assert!(manager.events_after(latest + 100).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+// SSE subscriptions scoped to tags (fleet groups tag their members with the group name).
+//
+// A client asking for /api/events?tag=rack-12 only gets events about machines carrying
+// that tag, plus `tags_updated` so it can notice membership changes. Membership is looked
+// up from the database and re-read at most every couple of seconds, so a machine that's
+// just been tagged starts showing up with its next event.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use uuid::Uuid;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct EventScope {
+    tags: Vec<String>,
+    members: HashSet<Uuid>,
+    loaded_at: Option<Instant>,
+}
+
+/// Tags from a comma-separated query value, ignoring blanks and duplicates.
+pub fn parse_tags(raw: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in raw.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// The machine an event is about, for events shaped `type:<machine id>[:...]`.
+pub fn event_machine(message: &str) -> Option<Uuid> {
+    let (_, payload) = message.split_once(':')?;
+    let id = payload.split(':').next()?;
+    Uuid::parse_str(id).ok()
+}
+
+/// Whether an event belongs in a scoped stream, given the scope's current members.
+pub fn in_scope(message: &str, members: &HashSet<Uuid>) -> bool {
+    if message == "tags_updated" {
+        return true;
+    }
+    event_machine(message).is_some_and(|id| members.contains(&id))
+}
+
+impl EventScope {
+    /// A scope for the given tags, or None if there aren't any (an unscoped stream).
+    pub fn new(tags: Vec<String>) -> Option<Self> {
+        if tags.is_empty() {
+            return None;
+        }
+        Some(Self { tags, members: HashSet::new(), loaded_at: None })
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    async fn refresh(&mut self) {
+        self.loaded_at = Some(Instant::now());
+        let mut members = HashSet::new();
+        for tag in &self.tags {
+            match crate::db::get_machines_by_tag(tag).await {
+                Ok(machines) => members.extend(machines.into_iter().map(|m| m.id)),
+                Err(e) => {
+                    // Keep the last known members rather than going quiet
+                    warn!("Failed to load machines tagged '{}' for scoped events: {}", tag, e);
+                    return;
+                }
+            }
+        }
+        self.members = members;
+    }
+
+    pub async fn allows(&mut self, message: &str) -> bool {
+        let stale = match self.loaded_at {
+            Some(at) => at.elapsed() >= REFRESH_INTERVAL,
+            None => true,
+        };
+        if stale && event_machine(message).is_some() {
+            self.refresh().await;
+        }
+        in_scope(message, &self.members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags("rack-12, rack-13,,rack-12"), vec!["rack-12", "rack-13"]);
+        assert!(parse_tags(" , ").is_empty());
+        assert!(EventScope::new(parse_tags("")).is_none());
+    }
+
+    #[test]
+    fn test_in_scope() {
+        let member = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let members: HashSet<Uuid> = [member].into_iter().collect();
+
+        assert!(in_scope(&format!("machine_updated:{}", member), &members));
+        assert!(in_scope(&format!("workflow_stalled:{}:preparing", member), &members));
+        assert!(!in_scope(&format!("machine_updated:{}", other), &members));
+        assert!(in_scope("tags_updated", &members));
+        // Fleet-wide events stay off scoped streams
+        assert!(!in_scope("storage_low:artifacts:95", &members));
+        assert!(!in_scope("templates_ready", &members));
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::{anyhow, bail, Context, Result};
+use k8s_openapi::api::core::v1::{Node, Service, ServicePort, ServiceSpec};
+use k8s_openapi::api::storage::v1::StorageClass;
+use kube::api::{DeleteParams, ListParams, PostParams};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Api, Client, Config};
+use serde::Serialize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tokio::fs;
+use tracing::{debug, info, warn};
+
+const DEFAULT_KUBECONFIG_PATH: &str = "/var/lib/dragonfly/external-kubeconfig.yaml";
+const KUBECONFIG_PATH_ENV_VAR: &str = "DRAGONFLY_EXTERNAL_KUBECONFIG";
+const DEFAULT_STORAGE_CLASS_ANNOTATION: &str = "storageclass.kubernetes.io/is-default-class";
+
+const LB_PROBE_NAMESPACE: &str = "default";
+const LB_PROBE_SERVICE: &str = "dragonfly-lb-probe";
+const LB_PROBE_TIMEOUT: Duration = Duration::from_secs(120);
+const LB_PROBE_POLL: Duration = Duration::from_secs(5);
+
+/// Outcome of one capability check against an external cluster.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterCheck {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+}
+
+/// Everything Flight mode needs from a cluster it didn't install itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub server_version: Option<String>,
+    /// The storage class Dragonfly's volumes will be created with
+    pub storage_class: Option<String>,
+    pub checks: Vec<ClusterCheck>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    fn check(&mut self, name: &str, result: Result<String>) {
+        let (passed, message) = match result {
+            Ok(message) => (true, message),
+            Err(e) => (false, format!("{:#}", e)),
+        };
+        self.checks.push(ClusterCheck { name: name.to_string(), passed, message });
+    }
+}
+
+/// Options for deploying the Tinkerbell stack and Dragonfly onto an external cluster.
+#[derive(Debug, Clone)]
+pub struct DeployOptions {
+    /// Address PXE clients and agents use to reach Dragonfly
+    pub public_ip: String,
+    pub storage_class: String,
+}
+
+/// Where the kubeconfig for an external cluster is kept.
+pub fn kubeconfig_path() -> PathBuf {
+    crate::config::var(KUBECONFIG_PATH_ENV_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_KUBECONFIG_PATH))
+}
+
+/// Whether Flight mode has been pointed at an external cluster.
+pub fn is_configured() -> bool {
+    kubeconfig_path().exists()
+}
+
+/// Point KUBECONFIG at the stored external kubeconfig, so the Kubernetes client,
+/// helm and kubectl all target that cluster. An explicit KUBECONFIG wins.
+pub fn activate() {
+    let path = kubeconfig_path();
+    if std::env::var_os("KUBECONFIG").is_none() && path.exists() {
+        info!("Using external Kubernetes cluster from {}", path.display());
+        std::env::set_var("KUBECONFIG", &path);
+    }
+}
+
+/// Parse kubeconfig YAML, rejecting anything kube can't use.
+pub fn parse_kubeconfig(contents: &str) -> Result<Kubeconfig> {
+    let kubeconfig = Kubeconfig::from_yaml(contents).context("Kubeconfig is not valid YAML")?;
+    if kubeconfig.clusters.is_empty() {
+        bail!("Kubeconfig doesn't define any clusters");
+    }
+    Ok(kubeconfig)
+}
+
+/// Build a client for the kubeconfig's current context.
+pub async fn client_for(kubeconfig: Kubeconfig) -> Result<Client> {
+    let config = Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default()).await
+        .context("Failed to load kubeconfig")?;
+    Client::try_from(config).context("Failed to create Kubernetes client")
+}
+
+/// Store the kubeconfig readable only by Dragonfly, since it carries cluster credentials.
+pub async fn save_kubeconfig(contents: &str) -> Result<PathBuf> {
+    let path = kubeconfig_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(&path, contents).await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).await
+        .with_context(|| format!("Failed to restrict permissions on {}", path.display()))?;
+    Ok(path)
+}
+
+/// Pick the storage class to use: the requested one if it exists, otherwise the cluster default.
+pub fn choose_storage_class(classes: &[(String, bool)], requested: Option<&str>) -> Result<String> {
+    match requested {
+        Some(name) => classes.iter()
+            .find(|(class, _)| class == name)
+            .map(|(class, _)| class.clone())
+            .ok_or_else(|| anyhow!("Storage class '{}' not found", name)),
+        None => {
+            let defaults: Vec<&String> = classes.iter().filter(|(_, default)| *default).map(|(c, _)| c).collect();
+            match defaults.as_slice() {
+                [class] => Ok((*class).clone()),
+                [] if classes.is_empty() => bail!("The cluster has no storage classes"),
+                [] => bail!("No default storage class; choose one of: {}",
+                    classes.iter().map(|(c, _)| c.as_str()).collect::<Vec<_>>().join(", ")),
+                _ => bail!("Multiple default storage classes; choose one explicitly"),
+            }
+        }
+    }
+}
+
+async fn check_storage_class(client: Client, requested: Option<&str>) -> Result<String> {
+    let api: Api<StorageClass> = Api::all(client);
+    let classes: Vec<(String, bool)> = api.list(&ListParams::default()).await
+        .context("Failed to list storage classes")?
+        .items
+        .into_iter()
+        .map(|sc| {
+            let is_default = sc.metadata.annotations.as_ref()
+                .and_then(|a| a.get(DEFAULT_STORAGE_CLASS_ANNOTATION))
+                .is_some_and(|v| v == "true");
+            (sc.metadata.name.unwrap_or_default(), is_default)
+        })
+        .collect();
+    choose_storage_class(&classes, requested)
+}
+
+// Create a throwaway LoadBalancer service and wait for the cluster to give it an address.
+// Dragonfly and Tinkerbell are exposed this way, so a cluster without a load balancer
+// controller (e.g. bare kubeadm without MetalLB) can't host Flight mode.
+async fn check_load_balancer(client: Client) -> Result<String> {
+    let services: Api<Service> = Api::namespaced(client, LB_PROBE_NAMESPACE);
+    let probe = Service {
+        metadata: kube::api::ObjectMeta {
+            name: Some(LB_PROBE_SERVICE.to_string()),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            type_: Some("LoadBalancer".to_string()),
+            // Selects nothing; only the address allocation matters
+            selector: Some([("app".to_string(), LB_PROBE_SERVICE.to_string())].into_iter().collect()),
+            ports: Some(vec![ServicePort { port: 80, ..Default::default() }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // A probe left behind by an interrupted check would make create fail
+    let _ = services.delete(LB_PROBE_SERVICE, &DeleteParams::default()).await;
+    services.create(&PostParams::default(), &probe).await
+        .context("Failed to create LoadBalancer probe service")?;
+
+    let result = wait_for_ingress(&services).await;
+    if let Err(e) = services.delete(LB_PROBE_SERVICE, &DeleteParams::default()).await {
+        warn!("Failed to delete LoadBalancer probe service: {}", e);
+    }
+    result
+}
+
+async fn wait_for_ingress(services: &Api<Service>) -> Result<String> {
+    let deadline = tokio::time::Instant::now() + LB_PROBE_TIMEOUT;
+    loop {
+        let service = services.get(LB_PROBE_SERVICE).await.context("Failed to read probe service")?;
+        let address = service.status
+            .and_then(|s| s.load_balancer)
+            .and_then(|lb| lb.ingress)
+            .and_then(|ingress| ingress.into_iter().find_map(|i| i.ip.or(i.hostname)));
+        if let Some(address) = address {
+            return Ok(format!("LoadBalancer services get addresses (probe got {})", address));
+        }
+        if tokio::time::Instant::now() >= deadline {
+            bail!("No LoadBalancer address was assigned within {}s; install a load balancer controller such as MetalLB",
+                LB_PROBE_TIMEOUT.as_secs());
+        }
+        debug!("Waiting for LoadBalancer probe address");
+        tokio::time::sleep(LB_PROBE_POLL).await;
+    }
+}
+
+/// Check an external cluster can host Flight mode: the API answers, there's a usable
+/// storage class, and LoadBalancer services get addresses.
+pub async fn validate(client: &Client, storage_class: Option<&str>) -> ValidationReport {
+    let mut report = ValidationReport { server_version: None, storage_class: None, checks: Vec::new() };
+
+    match client.apiserver_version().await {
+        Ok(info) => {
+            report.check("api", Ok(format!("Kubernetes {} reachable", info.git_version)));
+            report.server_version = Some(info.git_version);
+        }
+        Err(e) => {
+            // Nothing else can be checked without the API
+            report.check("api", Err(anyhow!(e).context("Kubernetes API unreachable")));
+            return report;
+        }
+    }
+
+    match check_storage_class(client.clone(), storage_class).await {
+        Ok(class) => {
+            report.check("storage_class", Ok(format!("Using storage class '{}'", class)));
+            report.storage_class = Some(class);
+        }
+        Err(e) => report.check("storage_class", Err(e)),
+    }
+
+    report.check("load_balancer", check_load_balancer(client.clone()).await);
+    report
+}
+
+// Pod CIDRs from the node specs. Clusters using VPC-native networking (EKS, AKS with
+// Azure CNI) don't set them, in which case Tinkerbell simply gets no trusted proxies.
+async fn pod_cidrs(client: Client) -> Result<Vec<String>> {
+    let nodes: Api<Node> = Api::all(client);
+    let cidrs = nodes.list(&ListParams::default()).await
+        .context("Failed to list nodes")?
+        .items
+        .into_iter()
+        .filter_map(|n| n.spec.and_then(|s| s.pod_cidr))
+        .collect();
+    Ok(cidrs)
+}
+
+/// Helm values for the Tinkerbell stack on an external cluster. Unlike a local install,
+/// DHCP is enabled from the start since the cluster exists only to serve Flight mode.
+pub fn tinkerbell_values(opts: &DeployOptions, trusted_proxies: &[String]) -> String {
+    let proxies = if trusted_proxies.is_empty() {
+        "  trustedProxies: []".to_string()
+    } else {
+        format!("  trustedProxies:\n{}",
+            trusted_proxies.iter().map(|p| format!("    - \"{}\"", p)).collect::<Vec<_>>().join("\n"))
+    };
+    format!(
+        r#"global:
+{proxies}
+  publicIP: {public_ip}
+  storageClass: {storage_class}
+smee:
+  dhcp:
+    enabled: true
+    allowUnknownHosts: true
+    mode: auto-proxy
+    httpIPXE:
+      scriptUrl:
+        scheme: "http"
+        host: "{public_ip}"
+        port: 3000
+        path: "/"
+  additionalArgs:
+    - "--dhcp-http-ipxe-script-prepend-mac=true"
+stack:
+  hook:
+    enabled: false # We handle Hook downloads via the Dragonfly server
+"#,
+        proxies = proxies,
+        public_ip = opts.public_ip,
+        storage_class = opts.storage_class,
+    )
+}
+
+/// Helm values for the Dragonfly chart on an external cluster.
+pub fn dragonfly_values(opts: &DeployOptions) -> String {
+    format!(
+        "global:\n  publicIP: {}\n  storageClass: {}\n",
+        opts.public_ip, opts.storage_class
+    )
+}
+
+fn helm_upgrade(release: &str, chart: &Path, values: &Path, kubeconfig: &Path) -> Result<()> {
+    let chart = chart.to_str().ok_or_else(|| anyhow!("Chart path is not valid UTF-8"))?;
+    let values = values.to_str().ok_or_else(|| anyhow!("Values path is not valid UTF-8"))?;
+    let output = Command::new("helm")
+        .args([
+            "upgrade", "--install", release, chart,
+            "--create-namespace",
+            "--namespace", "tink",
+            "--wait",
+            "--timeout", "10m",
+            "-f", values,
+        ])
+        .env("KUBECONFIG", kubeconfig)
+        .output()
+        .with_context(|| format!("Failed to run helm for {}", release))?;
+    if !output.status.success() {
+        bail!("helm upgrade of {} failed: {}", release, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Deploy the Tinkerbell stack and Dragonfly onto the cluster in the stored kubeconfig.
+pub async fn deploy(client: Client, opts: &DeployOptions) -> Result<()> {
+    let kubeconfig = kubeconfig_path();
+    let trusted_proxies = pod_cidrs(client).await?;
+    if trusted_proxies.is_empty() {
+        warn!("Nodes don't report pod CIDRs; deploying Tinkerbell without trusted proxies");
+    }
+
+    let work_dir = tempfile::tempdir().context("Failed to create working directory")?;
+    let repo_dir = work_dir.path().join("dragonfly-charts");
+    crate::components::fetch_charts(&repo_dir)?;
+    let stack_chart = repo_dir.join("tinkerbell").join("stack");
+
+    let stack_values = work_dir.path().join("tink-stack-values.yaml");
+    fs::write(&stack_values, tinkerbell_values(opts, &trusted_proxies)).await?;
+    info!("Deploying Tinkerbell stack to external cluster...");
+    helm_upgrade("tink-stack", &stack_chart, &stack_values, &kubeconfig)?;
+
+    let dragonfly_values_path = work_dir.path().join("dragonfly-values.yaml");
+    fs::write(&dragonfly_values_path, dragonfly_values(opts)).await?;
+    info!("Deploying Dragonfly to external cluster...");
+    helm_upgrade("dragonfly", &repo_dir.join("dragonfly"), &dragonfly_values_path, &kubeconfig)?;
+
+    info!("External cluster deployment complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes() -> Vec<(String, bool)> {
+        vec![("gp2".to_string(), false), ("gp3".to_string(), true)]
+    }
+
+    #[test]
+    fn test_storage_class_defaults() {
+        assert_eq!(choose_storage_class(&classes(), None).unwrap(), "gp3");
+        assert_eq!(choose_storage_class(&classes(), Some("gp2")).unwrap(), "gp2");
+        assert!(choose_storage_class(&classes(), Some("standard")).is_err());
+    }
+
+    #[test]
+    fn test_storage_class_without_default() {
+        let classes = vec![("local-path".to_string(), false)];
+        assert!(choose_storage_class(&classes, None).is_err());
+        assert!(choose_storage_class(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_tinkerbell_values_without_pod_cidrs() {
+        let opts = DeployOptions { public_ip: "10.0.0.5".to_string(), storage_class: "gp3".to_string() };
+        let values = tinkerbell_values(&opts, &[]);
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&values).unwrap();
+        assert_eq!(parsed["global"]["trustedProxies"].as_sequence().unwrap().len(), 0);
+        assert_eq!(parsed["smee"]["dhcp"]["enabled"].as_bool(), Some(true));
+        assert_eq!(parsed["global"]["storageClass"].as_str(), Some("gp3"));
+    }
+}
@@ -0,0 +1,479 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use dragonfly_common::models::Machine;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::custom_images::{CustomImage, CustomImageInput};
+use crate::desired_state::{BootProfile, DesiredState, DesiredStateInput};
+use crate::event_manager::EventManager;
+use crate::os_policy::{OsPolicy, OsPolicyInput};
+
+/// A declarative description of the fleet, usually kept in version control.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FleetDocument {
+    /// OS assignment rules, matched by name
+    #[serde(default, alias = "os_policies")]
+    pub boot_rules: Vec<OsPolicyInput>,
+    /// Custom OS catalog entries, matched by name
+    #[serde(default)]
+    pub images: Vec<CustomImageInput>,
+    #[serde(default)]
+    pub groups: Vec<GroupSpec>,
+    #[serde(default)]
+    pub machines: Vec<MachineSpec>,
+    /// Remove boot rules, images, group VLANs and desired states that aren't in the document
+    #[serde(default)]
+    pub prune: bool,
+}
+
+/// Machines sharing settings. Each member is tagged with the group name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupSpec {
+    pub name: String,
+    /// Hostnames, memorable names, MAC addresses or IDs; must match exactly
+    pub machines: Vec<String>,
+    pub os_choice: Option<String>,
+    pub boot_profile: Option<BootProfile>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// VLAN members provision on, unless a machine sets its own
+    pub vlan: Option<u16>,
+}
+
+/// Desired state for one machine; overrides its groups.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineSpec {
+    pub machine: String,
+    pub os_choice: Option<String>,
+    pub hostname: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub boot_profile: Option<BootProfile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// One line of a plan.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedChange {
+    /// "boot_rule", "image", "group_vlan" or "desired_state"
+    pub resource: String,
+    pub name: String,
+    pub action: ChangeAction,
+    /// Human-readable field changes, e.g. "os_choice: ubuntu-2204 -> debian-12"
+    pub details: Vec<String>,
+}
+
+// What applying a change does
+#[derive(Debug, Clone)]
+enum Operation {
+    SavePolicy(OsPolicy),
+    DeletePolicy(Uuid),
+    RegisterImage(CustomImageInput),
+    RemoveImage(String),
+    SetTagVlan(String, Option<u16>),
+    SaveDesiredState(DesiredState),
+    DeleteDesiredState(Uuid),
+}
+
+/// Everything needed to bring the database in line with a fleet document.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub changes: Vec<PlannedChange>,
+    #[serde(skip)]
+    operations: Vec<Operation>,
+}
+
+impl Plan {
+    fn push(&mut self, change: PlannedChange, operation: Operation) {
+        self.changes.push(change);
+        self.operations.push(operation);
+    }
+}
+
+fn change(resource: &str, name: &str, action: ChangeAction, details: Vec<String>) -> PlannedChange {
+    PlannedChange { resource: resource.to_string(), name: name.to_string(), action, details }
+}
+
+fn field_change<T: std::fmt::Debug + PartialEq>(details: &mut Vec<String>, field: &str, old: &T, new: &T) {
+    if old != new {
+        details.push(format!("{}: {:?} -> {:?}", field, old, new));
+    }
+}
+
+/// Parse and validate a fleet document.
+pub fn parse(yaml: &str) -> Result<FleetDocument> {
+    let document: FleetDocument = serde_yaml::from_str(yaml).map_err(|e| anyhow!("Invalid fleet document: {}", e))?;
+
+    let mut names = BTreeSet::new();
+    for rule in &document.boot_rules {
+        rule.validate()?;
+        if !names.insert(rule.name.trim()) {
+            bail!("Boot rule '{}' is defined more than once", rule.name.trim());
+        }
+    }
+    let mut names = BTreeSet::new();
+    for image in &document.images {
+        image.validate()?;
+        if !names.insert(image.name.as_str()) {
+            bail!("Image '{}' is defined more than once", image.name);
+        }
+    }
+    let mut names = BTreeSet::new();
+    for group in &document.groups {
+        if group.name.trim().is_empty() {
+            bail!("Group name is required");
+        }
+        if !names.insert(group.name.as_str()) {
+            bail!("Group '{}' is defined more than once", group.name);
+        }
+        if let Some(vlan) = group.vlan {
+            crate::vlan::validate(vlan).map_err(|e| anyhow!("Group '{}': {}", group.name, e))?;
+        }
+    }
+    Ok(document)
+}
+
+fn diff_boot_rules(plan: &mut Plan, rules: &[OsPolicyInput], existing: &[OsPolicy], prune: bool) {
+    for rule in rules {
+        let name = rule.name.trim();
+        match existing.iter().find(|p| p.name == name) {
+            Some(current) => {
+                let desired = rule.clone().into_policy(current.id, current.created_at);
+                let mut details = Vec::new();
+                field_change(&mut details, "priority", &current.priority, &desired.priority);
+                field_change(&mut details, "os_choice", &current.os_choice, &desired.os_choice);
+                field_change(&mut details, "enabled", &current.enabled, &desired.enabled);
+                field_change(&mut details, "matcher", &current.matcher, &desired.matcher);
+                if !details.is_empty() {
+                    plan.push(change("boot_rule", name, ChangeAction::Update, details), Operation::SavePolicy(desired));
+                }
+            }
+            None => {
+                let desired = rule.clone().into_policy(Uuid::new_v4(), Utc::now());
+                let details = vec![format!("os_choice: {}", desired.os_choice), format!("priority: {}", desired.priority)];
+                plan.push(change("boot_rule", name, ChangeAction::Create, details), Operation::SavePolicy(desired));
+            }
+        }
+    }
+    if prune {
+        for current in existing.iter().filter(|p| !rules.iter().any(|r| r.name.trim() == p.name)) {
+            plan.push(change("boot_rule", &current.name, ChangeAction::Delete, Vec::new()), Operation::DeletePolicy(current.id));
+        }
+    }
+}
+
+fn diff_images(plan: &mut Plan, images: &[CustomImageInput], existing: &[CustomImage], prune: bool) {
+    for image in images {
+        match existing.iter().find(|i| i.name == image.name) {
+            Some(current) => {
+                let desired = image.clone().into_image(current.created_at);
+                let mut details = Vec::new();
+                field_change(&mut details, "display_name", &current.display_name, &desired.display_name);
+                field_change(&mut details, "version", &current.version, &desired.version);
+                field_change(&mut details, "format", &current.format, &desired.format);
+                field_change(&mut details, "url", &current.source_url, &desired.source_url);
+                field_change(&mut details, "checksum", &current.checksum, &desired.checksum);
+                field_change(&mut details, "metadata", &current.metadata, &desired.metadata);
+                if !details.is_empty() {
+                    plan.push(change("image", &image.name, ChangeAction::Update, details), Operation::RegisterImage(image.clone()));
+                }
+            }
+            None => {
+                let details = vec![format!("version: {}", image.version), format!("url: {}", image.url)];
+                plan.push(change("image", &image.name, ChangeAction::Create, details), Operation::RegisterImage(image.clone()));
+            }
+        }
+    }
+    if prune {
+        for current in existing.iter().filter(|i| !images.iter().any(|d| d.name == i.name)) {
+            plan.push(change("image", &current.name, ChangeAction::Delete, Vec::new()), Operation::RemoveImage(current.name.clone()));
+        }
+    }
+}
+
+// Group VLANs are stored against the group's tag
+fn diff_group_vlans(plan: &mut Plan, groups: &[GroupSpec], existing: &[(String, u16)], prune: bool) {
+    for group in groups {
+        let Some(vlan) = group.vlan else { continue };
+        let current = existing.iter().find(|(tag, _)| *tag == group.name).map(|(_, v)| *v);
+        match current {
+            Some(current) if current == vlan => {}
+            Some(current) => {
+                let details = vec![format!("vlan: {} -> {}", current, vlan)];
+                plan.push(change("group_vlan", &group.name, ChangeAction::Update, details), Operation::SetTagVlan(group.name.clone(), Some(vlan)));
+            }
+            None => {
+                let details = vec![format!("vlan: {}", vlan)];
+                plan.push(change("group_vlan", &group.name, ChangeAction::Create, details), Operation::SetTagVlan(group.name.clone(), Some(vlan)));
+            }
+        }
+    }
+    if prune {
+        for (tag, _) in existing.iter().filter(|(tag, _)| !groups.iter().any(|g| g.vlan.is_some() && g.name == *tag)) {
+            plan.push(change("group_vlan", tag, ChangeAction::Delete, Vec::new()), Operation::SetTagVlan(tag.clone(), None));
+        }
+    }
+}
+
+// A fleet machine reference must name exactly one machine
+fn resolve_exact(reference: &str, machines: &[Machine]) -> Result<Uuid> {
+    let matches: Vec<_> = crate::resolve::resolve_machines(reference, machines)
+        .into_iter()
+        .filter(|m| m.exact)
+        .collect();
+    match matches.as_slice() {
+        [only] => Ok(only.id),
+        [] => bail!("No machine matches '{}'", reference),
+        _ => bail!("'{}' matches {} machines; use a MAC address or ID", reference, matches.len()),
+    }
+}
+
+// Fold groups, then machine entries, into one desired state per machine
+fn compile_desired_states(document: &FleetDocument, machines: &[Machine]) -> Result<BTreeMap<Uuid, DesiredStateInput>> {
+    let mut states: BTreeMap<Uuid, DesiredStateInput> = BTreeMap::new();
+    let blank = || DesiredStateInput { os_choice: None, hostname: None, tags: None, boot_profile: BootProfile::Default };
+
+    for group in &document.groups {
+        for reference in &group.machines {
+            let state = states.entry(resolve_exact(reference, machines)?).or_insert_with(blank);
+            if group.os_choice.is_some() {
+                state.os_choice = group.os_choice.clone();
+            }
+            if let Some(profile) = group.boot_profile {
+                state.boot_profile = profile;
+            }
+            let tags = state.tags.get_or_insert_with(Vec::new);
+            tags.push(group.name.clone());
+            tags.extend(group.tags.iter().cloned());
+        }
+    }
+
+    for spec in &document.machines {
+        let state = states.entry(resolve_exact(&spec.machine, machines)?).or_insert_with(blank);
+        if spec.os_choice.is_some() {
+            state.os_choice = spec.os_choice.clone();
+        }
+        if spec.hostname.is_some() {
+            state.hostname = spec.hostname.clone();
+        }
+        if let Some(profile) = spec.boot_profile {
+            state.boot_profile = profile;
+        }
+        if let Some(tags) = &spec.tags {
+            state.tags.get_or_insert_with(Vec::new).extend(tags.iter().cloned());
+        }
+    }
+
+    for state in states.values() {
+        state.validate()?;
+    }
+    Ok(states)
+}
+
+fn machine_label(id: &Uuid, machines: &[Machine]) -> String {
+    machines.iter()
+        .find(|m| m.id == *id)
+        .and_then(|m| m.hostname.clone().or_else(|| m.memorable_name.clone()))
+        .unwrap_or_else(|| id.to_string())
+}
+
+fn diff_desired_states(
+    plan: &mut Plan,
+    desired: BTreeMap<Uuid, DesiredStateInput>,
+    existing: &[DesiredState],
+    machines: &[Machine],
+    prune: bool,
+) {
+    for (id, input) in &desired {
+        let label = machine_label(id, machines);
+        let state = input.clone().into_state(*id);
+        match existing.iter().find(|s| s.machine_id == *id) {
+            Some(current) => {
+                let mut details = Vec::new();
+                field_change(&mut details, "os_choice", &current.os_choice, &state.os_choice);
+                field_change(&mut details, "hostname", &current.hostname, &state.hostname);
+                field_change(&mut details, "tags", &current.tags, &state.tags);
+                field_change(&mut details, "boot_profile", &current.boot_profile, &state.boot_profile);
+                if !details.is_empty() {
+                    plan.push(change("desired_state", &label, ChangeAction::Update, details), Operation::SaveDesiredState(state));
+                }
+            }
+            None => {
+                let mut details = Vec::new();
+                field_change(&mut details, "os_choice", &None, &state.os_choice);
+                field_change(&mut details, "hostname", &None, &state.hostname);
+                field_change(&mut details, "tags", &None, &state.tags);
+                field_change(&mut details, "boot_profile", &BootProfile::Default, &state.boot_profile);
+                plan.push(change("desired_state", &label, ChangeAction::Create, details), Operation::SaveDesiredState(state));
+            }
+        }
+    }
+    if prune {
+        for current in existing.iter().filter(|s| !desired.contains_key(&s.machine_id)) {
+            let label = machine_label(&current.machine_id, machines);
+            plan.push(change("desired_state", &label, ChangeAction::Delete, Vec::new()), Operation::DeleteDesiredState(current.machine_id));
+        }
+    }
+}
+
+/// Work out what applying a document would change, without changing anything.
+pub async fn plan(document: &FleetDocument) -> Result<Plan> {
+    let machines = crate::db::get_all_machines().await?;
+    let desired = compile_desired_states(document, &machines)?;
+    if desired.values().any(|s| s.boot_profile == BootProfile::Memtest) && !crate::boot_menu::memtest_available() {
+        bail!("Upload {} before using the memtest boot profile", crate::boot_menu::MEMTEST_ARTIFACT);
+    }
+
+    let mut plan = Plan { changes: Vec::new(), operations: Vec::new() };
+    diff_boot_rules(&mut plan, &document.boot_rules, &crate::db::list_os_policies().await?, document.prune);
+    diff_images(&mut plan, &document.images, &crate::db::list_custom_images().await?, document.prune);
+    diff_group_vlans(&mut plan, &document.groups, &crate::db::list_tag_vlans().await?, document.prune);
+    diff_desired_states(&mut plan, desired, &crate::db::list_desired_states().await?, &machines, document.prune);
+    Ok(plan)
+}
+
+/// Carry out a plan. Stops at the first failure; changes made before it stay applied.
+pub async fn apply(plan: &Plan, event_manager: Arc<EventManager>) -> Result<()> {
+    for operation in &plan.operations {
+        match operation {
+            Operation::SavePolicy(policy) => crate::db::save_os_policy(policy).await?,
+            Operation::DeletePolicy(id) => {
+                crate::db::delete_os_policy(id).await?;
+            }
+            Operation::RegisterImage(input) => {
+                crate::custom_images::register(input.clone(), event_manager.clone()).await?;
+            }
+            Operation::RemoveImage(name) => {
+                crate::custom_images::remove(name).await?;
+            }
+            Operation::SetTagVlan(tag, vlan) => {
+                crate::db::set_tag_vlan(tag, *vlan).await?;
+                for machine in crate::db::get_machines_by_tag(tag).await? {
+                    let _ = event_manager.send(format!("machine_updated:{}", machine.id));
+                }
+            }
+            Operation::SaveDesiredState(state) => {
+                crate::db::save_desired_state(state).await?;
+                let _ = event_manager.send(format!("machine_updated:{}", state.machine_id));
+            }
+            Operation::DeleteDesiredState(id) => {
+                crate::db::delete_desired_state(id).await?;
+            }
+        }
+    }
+    info!("Applied fleet plan with {} changes", plan.changes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dragonfly_common::models::MachineStatus;
+
+    fn machine(hostname: &str, mac: &str) -> Machine {
+        Machine {
+            mac_address: mac.to_string(),
+            hostname: Some(hostname.to_string()),
+            status: MachineStatus::AwaitingAssignment,
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    const FLEET: &str = r#"
+boot_rules:
+  - name: ceph
+    priority: 10
+    os_choice: debian-12
+    matcher:
+      min_disks: 4
+groups:
+  - name: storage
+    machines: [stor-1, "aa:bb:cc:dd:ee:02"]
+    os_choice: debian-12
+    tags: [ceph]
+    vlan: 20
+machines:
+  - machine: stor-1
+    hostname: stor-1.example.com
+    boot_profile: rescue
+"#;
+
+    #[test]
+    fn test_parse_rejects_unknown_fields_and_duplicates() {
+        assert!(parse(FLEET).is_ok());
+        assert!(parse("boot_rulez: []").is_err());
+        assert!(parse("groups:\n  - {name: a, machines: []}\n  - {name: a, machines: []}").is_err());
+        assert!(parse("groups:\n  - {name: a, machines: [], vlan: 4095}").is_err());
+    }
+
+    #[test]
+    fn test_groups_then_machines() {
+        let machines = vec![machine("stor-1", "aa:bb:cc:dd:ee:01"), machine("stor-2", "aa:bb:cc:dd:ee:02")];
+        let states = compile_desired_states(&parse(FLEET).unwrap(), &machines).unwrap();
+
+        let first = &states[&machines[0].id];
+        assert_eq!(first.os_choice.as_deref(), Some("debian-12"));
+        assert_eq!(first.hostname.as_deref(), Some("stor-1.example.com"));
+        assert_eq!(first.boot_profile, BootProfile::Rescue);
+        assert_eq!(first.tags, Some(vec!["storage".to_string(), "ceph".to_string()]));
+
+        let second = &states[&machines[1].id];
+        assert_eq!(second.hostname, None);
+        assert_eq!(second.boot_profile, BootProfile::Default);
+    }
+
+    #[test]
+    fn test_unknown_machine_is_an_error() {
+        let machines = vec![machine("stor-1", "aa:bb:cc:dd:ee:01")];
+        assert!(compile_desired_states(&parse(FLEET).unwrap(), &machines).is_err());
+    }
+
+    #[test]
+    fn test_boot_rule_diff() {
+        let document = parse(FLEET).unwrap();
+        let mut plan = Plan { changes: Vec::new(), operations: Vec::new() };
+        let stale = OsPolicyInput {
+            name: "old".to_string(),
+            priority: 0,
+            os_choice: "ubuntu-2204".to_string(),
+            enabled: true,
+            matcher: Default::default(),
+        }
+        .into_policy(Uuid::new_v4(), Utc::now());
+        diff_boot_rules(&mut plan, &document.boot_rules, &[stale.clone()], true);
+        let actions: Vec<ChangeAction> = plan.changes.iter().map(|c| c.action).collect();
+        assert_eq!(actions, vec![ChangeAction::Create, ChangeAction::Delete]);
+
+        // Applying the same rules again changes nothing
+        let current = document.boot_rules[0].clone().into_policy(Uuid::new_v4(), Utc::now());
+        let mut plan = Plan { changes: Vec::new(), operations: Vec::new() };
+        diff_boot_rules(&mut plan, &document.boot_rules, &[current, stale], false);
+        assert!(plan.changes.is_empty());
+    }
+
+    #[test]
+    fn test_group_vlan_diff() {
+        let document = parse(FLEET).unwrap();
+        let mut plan = Plan { changes: Vec::new(), operations: Vec::new() };
+        diff_group_vlans(&mut plan, &document.groups, &[("storage".to_string(), 10), ("old".to_string(), 30)], true);
+        let actions: Vec<ChangeAction> = plan.changes.iter().map(|c| c.action).collect();
+        assert_eq!(actions, vec![ChangeAction::Update, ChangeAction::Delete]);
+
+        let mut plan = Plan { changes: Vec::new(), operations: Vec::new() };
+        diff_group_vlans(&mut plan, &document.groups, &[("storage".to_string(), 20)], false);
+        assert!(plan.changes.is_empty());
+    }
+}
@@ -8,7 +8,8 @@ use serde_json::json;
 use crate::AppState;
 use crate::db;
 use dragonfly_common::models::{ErrorResponse, Machine, MachineStatus};
-use crate::tinkerbell;
+use dragonfly_common::OsIdentifier;
+use crate::tinkerbell_outbox;
 use crate::handlers::proxmox; // Import proxmox functions
 
 // Struct to receive the power action request
@@ -46,11 +47,58 @@ pub async fn bmc_power_action_handler(
         }
     };
 
+    // Power actions could undo whatever is being done to the machine by hand
+    match crate::maintenance::status(&machine_id).await {
+        Ok(status) if status.maintenance => {
+            warn!("Refusing BMC action '{}' for machine {}: {}", payload.action, machine_id, status.describe());
+            return Err(crate::api::maintenance_blocked(&machine_id, &status));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to check maintenance mode for machine {}: {}", machine_id, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                error: "Database error".to_string(),
+                message: e.to_string()
+            })).into_response());
+        }
+    }
+
     // 2. Check machine type and execute action
     // Determine if this is a Proxmox VM by checking if the Proxmox-specific fields are populated
     if machine.proxmox_vmid.is_some() && machine.proxmox_node.is_some() {
         info!("DEBUG: Identified as Proxmox VM: vmid={:?}, node={:?}", machine.proxmox_vmid, machine.proxmox_node);
         handle_proxmox_vm_action(state, &machine, &payload.action).await
+    } else if let Some(action) = crate::ssh::Action::parse(&payload.action).filter(|a| *a != crate::ssh::Action::CollectLogs) {
+        // No BMC to drive; fall back to SSH when credentials are stored for the machine or its tags
+        match crate::ssh::credentials_for(&machine_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse {
+                    error: "BMC actions not supported for this machine type".to_string(),
+                    message: "This machine has no BMC Dragonfly can drive and no SSH credentials to fall back on.".to_string()
+                })).into_response());
+            }
+            Err(e) => {
+                error!("Failed to load SSH credentials for machine {}: {}", machine_id, e);
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse {
+                    error: "Database error".to_string(),
+                    message: e.to_string()
+                })).into_response());
+            }
+        }
+        match crate::ssh::run_action(&machine, action).await {
+            Ok(_) => Ok((StatusCode::OK, Json(json!({
+                "message": format!("{} initiated over SSH", payload.action),
+                "transport": "ssh"
+            }))).into_response()),
+            Err(e) => {
+                error!("SSH fallback for '{}' failed on machine {}: {:#}", payload.action, machine_id, e);
+                Err((StatusCode::BAD_GATEWAY, Json(ErrorResponse {
+                    error: "SSH command failed".to_string(),
+                    message: format!("{:#}", e)
+                })).into_response())
+            }
+        }
     } else {
         error!(
             "BMC actions not supported for this machine type (not a Proxmox VM) for machine {}",
@@ -199,6 +247,16 @@ async fn handle_proxmox_vm_action(
                 }
             }
             
+            // Refuse to start an install if artifact or database storage is low
+            if let Some(status) = crate::storage::low_storage() {
+                let message = crate::storage::low_storage_message(&status);
+                warn!("Refusing reboot-pxe for machine {}: {}", machine.id, message);
+                return Err((StatusCode::INSUFFICIENT_STORAGE, Json(ErrorResponse {
+                    error: "Insufficient Storage".to_string(),
+                    message,
+                })).into_response());
+            }
+            
             // 3. Connect with power token for rebooting
             info!("Connecting to Proxmox for power operations (reboot) on VM {} (ID: {})", vmid, machine.id);
             let power_client = match proxmox::connect_to_proxmox(&state, "power").await {
@@ -250,13 +308,19 @@ async fn handle_proxmox_vm_action(
                     };
                     
                     // Use the os_choice from the machine if available, or default to a sensible fallback
-                    let os_choice = updated_machine.os_choice.as_deref().unwrap_or("ubuntu-2204");
-                    match tinkerbell::create_workflow(&updated_machine, os_choice).await {
-                        Ok(_) => {
+                    let os_choice = updated_machine.os_choice.as_deref().unwrap_or(OsIdentifier::default().as_str());
+                    match tinkerbell_outbox::create_workflow(&*state.tinkerbell, &updated_machine, os_choice).await {
+                        Ok(tinkerbell_outbox::Outcome::Done) => {
                             info!("Successfully created Tinkerbell workflow for machine {}", machine.id);
                             // Check the response from set_next_boot and reboot operations in the logs
                             info!("VM {} successfully set for PXE boot and rebooted, workflow created", vmid);
                         },
+                        Ok(tinkerbell_outbox::Outcome::Queued) => {
+                            return Ok((StatusCode::OK, Json(serde_json::json!({ 
+                                "message": "Proxmox reboot-pxe initiated successfully; Kubernetes is unreachable, so the workflow is queued and will be created once it is back",
+                                "machine_id": machine.id.to_string(),
+                            }))).into_response());
+                        },
                         Err(e) => {
                             // Log error but proceed, as Proxmox action succeeded
                             error!("Failed to create Tinkerbell workflow for machine {}: {}", machine.id, e);
@@ -1154,6 +1154,8 @@ async fn discover_and_register_proxmox_vms(
                                     disks: Vec::new(),
                                     nameservers: Vec::new(),
                                     cpu_model: None,
+                                    hardware_fingerprint: None,
+                                    agent_environment: None,
                                 };
             info!("Host req: {:?}, Attempting to register Proxmox host node with DB", host_req);
             match db::register_machine(&host_req).await { 
@@ -1545,6 +1547,8 @@ async fn discover_and_register_proxmox_vms(
                 proxmox_vmid: Some(vmid),
                 proxmox_node: Some(node_name.to_string()),
                 proxmox_cluster: Some(cluster_name.to_string()),
+                hardware_fingerprint: None,
+                agent_environment: None,
             };
 
             // DEBUG: Log the request before attempting registration
@@ -1557,11 +1561,8 @@ async fn discover_and_register_proxmox_vms(
                     
                     // Get the new machine to register with Tinkerbell
                     if let Ok(Some(machine)) = db::get_machine_by_id(&machine_id).await {
-                        // Register with Tinkerbell (don't fail if this fails)
-                        // Assuming tinkerbell module is accessible via crate::tinkerbell
-                        if let Err(e) = crate::tinkerbell::register_machine(&machine).await {
-                            warn!("Failed to register machine with Tinkerbell (continuing anyway): {}", e);
-                        }
+                        // Register with Tinkerbell (retried later if this fails)
+                        crate::tinkerbell_sync::sync_or_queue(&*_state.tinkerbell, &machine).await;
                         
                         // Update the machine status and OS - requires dbpool
                         let machine_status = match status {
@@ -1797,6 +1798,9 @@ pub async fn start_proxmox_sync_task(
         loop {
             tokio::select! {
                 _ = tokio::time::sleep(poll_interval) => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
                     info!("Running Proxmox machine sync check");
                     
                     // Check if Proxmox is configured either in memory or database
@@ -2225,12 +2229,16 @@ async fn sync_proxmox_machines(
     if !machines_to_prune.is_empty() {
         info!("Sync: Pruning {} machines not found in Proxmox API...", machines_to_prune.len());
         for machine_id in machines_to_prune {
-            // Pass ID by reference
-            match db::delete_machine(&machine_id).await {
-                Ok(_) => {
+            // With the two-person rule on, pruning waits for an admin to approve it
+            let summary = format!("Delete machine {}, which is no longer in Proxmox", machine_id);
+            match crate::action_approvals::delete_machine(&machine_id, summary, "proxmox-sync").await {
+                Ok(true) => {
                     info!("Sync: Successfully pruned machine {}", machine_id);
                     pruned_count += 1;
                 },
+                Ok(false) => {
+                    info!("Sync: Pruning machine {} is awaiting approval", machine_id);
+                },
                 Err(e) => {
                     error!("Sync: Failed to prune machine {}: {}", machine_id, e);
                 }
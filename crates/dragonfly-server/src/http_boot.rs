@@ -0,0 +1,205 @@
+// Native UEFI HTTP Boot, for firmware that has no PXE stack (or has it disabled).
+//
+// HTTP Boot firmware asks DHCP for a URL rather than a TFTP filename, so Dragonfly serves
+// an iPXE EFI binary at a stable URL per architecture. Once loaded, iPXE DHCPs again and
+// is handed the usual /{mac} script URL, so the rest of the boot is identical to PXE.
+// Which way a machine booted is remembered so boot rules can tell the two apart.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long after fetching the boot file a /{mac} request still counts as an HTTP Boot
+const CORRELATION_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Architectures Dragonfly serves an HTTP Boot file for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootArch {
+    X86_64,
+    Aarch64,
+}
+
+impl BootArch {
+    pub const ALL: &'static [BootArch] = &[BootArch::X86_64, BootArch::Aarch64];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootArch::X86_64 => "x86_64",
+            BootArch::Aarch64 => "aarch64",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "x86_64" | "x64" | "amd64" => Some(BootArch::X86_64),
+            "aarch64" | "arm64" => Some(BootArch::Aarch64),
+            _ => None,
+        }
+    }
+
+    /// DHCP client system architecture (option 93) HTTP Boot firmware sends
+    pub fn client_arch(&self) -> u16 {
+        match self {
+            BootArch::X86_64 => 0x10,
+            BootArch::Aarch64 => 0x13,
+        }
+    }
+
+    /// Where the boot file is cached, relative to the artifact directory
+    pub fn artifact_path(&self) -> &'static str {
+        match self {
+            BootArch::X86_64 => "http-boot/ipxe-x86_64.efi",
+            BootArch::Aarch64 => "http-boot/ipxe-aarch64.efi",
+        }
+    }
+
+    /// Upstream iPXE build fetched on first use; snponly drives the NIC through the
+    /// firmware's own network stack, which HTTP Boot firmware always has.
+    pub fn upstream_url(&self) -> &'static str {
+        match self {
+            BootArch::X86_64 => "https://boot.ipxe.org/x86_64-efi/snponly.efi",
+            BootArch::Aarch64 => "https://boot.ipxe.org/arm64-efi/snponly.efi",
+        }
+    }
+}
+
+/// How a machine's firmware reached Dragonfly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootMethod {
+    Pxe,
+    HttpBoot,
+    /// Local boot media; reported by the agent rather than seen by the server
+    Usb,
+}
+
+impl BootMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootMethod::Pxe => "pxe",
+            BootMethod::HttpBoot => "http_boot",
+            BootMethod::Usb => "usb",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pxe" => Some(BootMethod::Pxe),
+            "http_boot" => Some(BootMethod::HttpBoot),
+            "usb" => Some(BootMethod::Usb),
+            _ => None,
+        }
+    }
+}
+
+/// Stable URL of the HTTP Boot file for an architecture.
+pub fn boot_file_url(base_url: &str, arch: BootArch) -> String {
+    format!("{}/http-boot/{}/ipxe.efi", base_url.trim_end_matches('/'), arch.as_str())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootFile {
+    pub arch: String,
+    pub client_arch: u16,
+    pub url: String,
+}
+
+/// What to configure on the DHCP server, returned by GET /api/http-boot.
+#[derive(Debug, Clone, Serialize)]
+pub struct HttpBootGuidance {
+    pub boot_files: Vec<BootFile>,
+    /// Filename to hand iPXE (user class "iPXE") once it is running
+    pub ipxe_script_url: String,
+    pub dnsmasq: String,
+    pub isc_dhcpd: String,
+}
+
+pub fn guidance(base_url: &str) -> HttpBootGuidance {
+    let base = base_url.trim_end_matches('/');
+    // iPXE expands ${net0/mac} itself; the DHCP server passes it through verbatim
+    let ipxe_script_url = format!("{}/${{net0/mac}}", base);
+
+    let mut dnsmasq = String::from("# UEFI HTTP Boot: firmware must see \"HTTPClient\" echoed in option 60\n");
+    let mut isc_dhcpd = String::from("option client-arch code 93 = unsigned integer 16;\n");
+    dnsmasq.push_str("dhcp-userclass=set:ipxe,iPXE\n");
+    for arch in BootArch::ALL {
+        let tag = format!("httpboot-{}", arch.as_str());
+        let url = boot_file_url(base, *arch);
+        dnsmasq.push_str(&format!("dhcp-match=set:{},option:client-arch,{}\n", tag, arch.client_arch()));
+        dnsmasq.push_str(&format!("dhcp-option-force=tag:{},60,HTTPClient\n", tag));
+        dnsmasq.push_str(&format!("dhcp-boot=tag:{},tag:!ipxe,{}\n", tag, url));
+        isc_dhcpd.push_str(&format!(
+            "class \"httpboot-{}\" {{\n  match if substring(option vendor-class-identifier, 0, 10) = \"HTTPClient\" and option client-arch = {};\n  option vendor-class-identifier \"HTTPClient\";\n  filename \"{}\";\n}}\n",
+            arch.as_str(), arch.client_arch(), url
+        ));
+    }
+    dnsmasq.push_str(&format!("dhcp-boot=tag:ipxe,{}\n", ipxe_script_url));
+    isc_dhcpd.push_str(&format!(
+        "if exists user-class and option user-class = \"iPXE\" {{\n  filename \"{}\";\n}}\n",
+        ipxe_script_url
+    ));
+
+    HttpBootGuidance {
+        boot_files: BootArch::ALL.iter().map(|arch| BootFile {
+            arch: arch.as_str().to_string(),
+            client_arch: arch.client_arch(),
+            url: boot_file_url(base, *arch),
+        }).collect(),
+        ipxe_script_url,
+        dnsmasq,
+        isc_dhcpd,
+    }
+}
+
+// Clients that fetched an HTTP Boot file recently, by address
+static RECENT_CLIENTS: Lazy<Mutex<HashMap<IpAddr, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Remember that this address just fetched an HTTP Boot file.
+pub fn note_boot_file_fetch(ip: IpAddr) {
+    let mut clients = RECENT_CLIENTS.lock().unwrap_or_else(|e| e.into_inner());
+    clients.retain(|_, at| at.elapsed() < CORRELATION_WINDOW);
+    clients.insert(ip, Instant::now());
+}
+
+/// How the machine at this address booted, judged by whether it fetched an HTTP Boot file first.
+pub fn boot_method_for(ip: IpAddr) -> BootMethod {
+    let clients = RECENT_CLIENTS.lock().unwrap_or_else(|e| e.into_inner());
+    match clients.get(&ip) {
+        Some(at) if at.elapsed() < CORRELATION_WINDOW => BootMethod::HttpBoot,
+        _ => BootMethod::Pxe,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_file_urls() {
+        assert_eq!(boot_file_url("http://10.0.0.1:3000/", BootArch::X86_64), "http://10.0.0.1:3000/http-boot/x86_64/ipxe.efi");
+        assert_eq!(BootArch::parse("arm64"), Some(BootArch::Aarch64));
+        assert_eq!(BootArch::parse("riscv64"), None);
+    }
+
+    #[test]
+    fn test_guidance_covers_each_arch() {
+        let guidance = guidance("http://10.0.0.1:3000");
+        assert_eq!(guidance.boot_files.len(), BootArch::ALL.len());
+        assert_eq!(guidance.ipxe_script_url, "http://10.0.0.1:3000/${net0/mac}");
+        assert!(guidance.dnsmasq.contains("dhcp-match=set:httpboot-x86_64,option:client-arch,16"));
+        assert!(guidance.dnsmasq.contains("dhcp-boot=tag:httpboot-aarch64,tag:!ipxe,http://10.0.0.1:3000/http-boot/aarch64/ipxe.efi"));
+        assert!(guidance.isc_dhcpd.contains("option client-arch = 19;"));
+    }
+
+    #[test]
+    fn test_boot_method_correlation() {
+        let ip: IpAddr = "192.0.2.77".parse().unwrap();
+        assert_eq!(boot_method_for(ip), BootMethod::Pxe);
+        note_boot_file_fetch(ip);
+        assert_eq!(boot_method_for(ip), BootMethod::HttpBoot);
+        assert_eq!(BootMethod::parse(BootMethod::HttpBoot.as_str()), Some(BootMethod::HttpBoot));
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+
+use crate::InstallationState;
+
+const DEFAULT_STATE_FILE: &str = "/var/lib/dragonfly/install-state.json";
+const STATE_FILE_ENV_VAR: &str = "DRAGONFLY_INSTALL_STATE_FILE";
+
+/// When the installer entered a stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageRecord {
+    pub stage: InstallationState,
+    pub entered_at: DateTime<Utc>,
+}
+
+/// Installer progress as persisted on disk, so an interrupted install can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallProgress {
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub current: InstallationState,
+    pub history: Vec<StageRecord>,
+    /// Floating IP chosen for the bootstrap node; reused on resume since Tinkerbell may already be bound to it
+    pub bootstrap_ip: Option<String>,
+}
+
+impl InstallProgress {
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self {
+            started_at: now,
+            updated_at: now,
+            current: InstallationState::WaitingSudo,
+            history: Vec::new(),
+            bootstrap_ip: None,
+        }
+    }
+
+    /// Record entering a stage. Re-entering the current stage only bumps the timestamp.
+    pub fn enter(&mut self, stage: InstallationState) {
+        let now = Utc::now();
+        if self.current != stage || self.history.is_empty() {
+            self.history.push(StageRecord { stage: stage.clone(), entered_at: now });
+        }
+        self.current = stage;
+        self.updated_at = now;
+    }
+
+    /// The stage an interrupted install should restart from: the last one it entered
+    /// that isn't a failure. None if it finished or never got going.
+    pub fn resume_stage(&self) -> Option<InstallationState> {
+        if self.current == InstallationState::Ready {
+            return None;
+        }
+        self.history.iter()
+            .rev()
+            .map(|r| &r.stage)
+            .find(|s| !matches!(s, InstallationState::Failed(_) | InstallationState::WaitingSudo))
+            .cloned()
+    }
+}
+
+impl Default for InstallProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where installer state is kept; overridable for testing or non-standard layouts.
+pub fn state_file_path() -> PathBuf {
+    crate::config::var(STATE_FILE_ENV_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_FILE))
+}
+
+/// Load persisted progress, if an install has been started on this host.
+pub async fn load() -> Result<Option<InstallProgress>> {
+    let path = state_file_path();
+    match fs::read_to_string(&path).await {
+        Ok(contents) => {
+            let progress = serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse install state at {}", path.display()))?;
+            Ok(Some(progress))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read install state at {}", path.display())),
+    }
+}
+
+/// Persist progress. Written to a temporary file and renamed so a crash never leaves a torn file.
+pub async fn save(progress: &InstallProgress) -> Result<()> {
+    let path = state_file_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec_pretty(progress)?).await
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, &path).await
+        .with_context(|| format!("Failed to move install state into place at {}", path.display()))?;
+    Ok(())
+}
+
+/// Record a stage transition in the persisted state, starting a new record if none exists.
+pub async fn record_stage(stage: &InstallationState) -> Result<InstallProgress> {
+    let mut progress = load().await?.unwrap_or_default();
+    progress.enter(stage.clone());
+    save(&progress).await?;
+    Ok(progress)
+}
+
+/// Remember the bootstrap IP picked for this install.
+pub async fn record_bootstrap_ip(ip: &str) -> Result<()> {
+    let mut progress = load().await?.unwrap_or_default();
+    progress.bootstrap_ip = Some(ip.to_string());
+    progress.updated_at = Utc::now();
+    save(&progress).await
+}
+
+/// Forget any previous install attempt.
+pub async fn clear() -> Result<()> {
+    match fs::remove_file(state_file_path()).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context("Failed to remove install state"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_skips_repeated_stage() {
+        let mut progress = InstallProgress::new();
+        progress.enter(InstallationState::WaitingSudo);
+        progress.enter(InstallationState::InstallingK3s);
+        progress.enter(InstallationState::InstallingK3s);
+        assert_eq!(progress.history.len(), 2);
+        assert_eq!(progress.current, InstallationState::InstallingK3s);
+    }
+
+    #[test]
+    fn test_resume_after_failure() {
+        let mut progress = InstallProgress::new();
+        progress.enter(InstallationState::WaitingSudo);
+        progress.enter(InstallationState::WaitingK3s);
+        progress.enter(InstallationState::Failed("timeout".to_string()));
+        assert_eq!(progress.resume_stage(), Some(InstallationState::WaitingK3s));
+    }
+
+    #[test]
+    fn test_nothing_to_resume() {
+        let mut progress = InstallProgress::new();
+        progress.enter(InstallationState::WaitingSudo);
+        assert_eq!(progress.resume_stage(), None);
+
+        progress.enter(InstallationState::Ready);
+        assert_eq!(progress.resume_stage(), None);
+    }
+}
@@ -0,0 +1,145 @@
+// Install stage timings reported from inside the installer environment.
+//
+// Tinkerbell only reports how long each workflow action took as a whole, including image
+// pulls and container start-up. Install templates can instead report when each stage
+// actually started and ended (image download, image write, bootloader install) to
+// /api/install-telemetry/{mac}. A stage that names the workflow action it stands in for
+// feeds that action's historical timings, replacing the server-observed duration.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{InstallStage, InstallTelemetryReport, Machine, StageBoundary};
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+// More than this in one report is not a real installer
+const MAX_EVENTS_PER_REPORT: usize = 16;
+
+// Installer clocks before NTP can be wildly off; beyond this the receive time is used instead
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// One stage of a machine's current (or last) install.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StageTiming {
+    pub stage: InstallStage,
+    pub action: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_secs: Option<u64>,
+}
+
+/// Whole seconds between a stage's start and end; None if it hasn't both started and ended.
+pub fn duration_secs(started_at: Option<DateTime<Utc>>, finished_at: Option<DateTime<Utc>>) -> Option<u64> {
+    let seconds = (finished_at? - started_at?).num_seconds();
+    (seconds >= 0).then_some(seconds as u64)
+}
+
+/// The time to record for an event: the reported one, unless it's too far from our clock.
+pub fn event_time(reported: Option<DateTime<Utc>>, received_at: DateTime<Utc>) -> DateTime<Utc> {
+    match reported {
+        Some(at) if (at - received_at).num_seconds().abs() <= MAX_CLOCK_SKEW_SECS => at,
+        _ => received_at,
+    }
+}
+
+fn validate(report: &InstallTelemetryReport) -> Result<()> {
+    if report.events.is_empty() {
+        bail!("Report has no events");
+    }
+    if report.events.len() > MAX_EVENTS_PER_REPORT {
+        bail!("At most {} events can be reported at once", MAX_EVENTS_PER_REPORT);
+    }
+    for event in &report.events {
+        if let Some(action) = &event.action {
+            if action.trim().is_empty() || action.len() > 128 {
+                bail!("Invalid action name for stage {}", event.stage.as_str());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Record a report for a machine that's installing, feeding finished stages into the timing history.
+pub async fn record(machine: &Machine, report: &InstallTelemetryReport) -> Result<usize> {
+    validate(report)?;
+    let template = crate::tinkerbell::template_ref_for(machine.os_choice.as_deref()).to_string();
+    let received_at = Utc::now();
+
+    for event in &report.events {
+        let at = event_time(event.at, received_at);
+        let action = event.action.as_deref().map(str::trim);
+        match event.event {
+            StageBoundary::Start => {
+                crate::db::record_install_stage_start(&machine.id, event.stage, action, at).await?;
+            }
+            StageBoundary::End => {
+                // None when the end was already reported, so a retried report isn't counted twice
+                let Some(timing) = crate::db::record_install_stage_end(&machine.id, event.stage, action, at).await? else {
+                    continue;
+                };
+                info!("Machine {} finished install stage {} ({:?}s)", machine.id, event.stage.as_str(), timing.duration_secs);
+                if let (Some(action), Some(seconds)) = (timing.action.as_deref(), timing.duration_secs) {
+                    crate::tinkerbell::record_measured_timing(&template, action, seconds);
+                }
+            }
+        }
+    }
+    Ok(report.events.len())
+}
+
+/// Forget the previous install's stages when a new one starts.
+pub async fn reset(machine_id: &Uuid) {
+    if let Err(e) = crate::db::clear_install_stage_timings(machine_id).await {
+        warn!("Failed to clear install stage timings for machine {}: {}", machine_id, e);
+    }
+}
+
+/// Workflow actions this install has measured timings for, so the server-observed ones are skipped.
+pub async fn measured_actions(machine_id: &Uuid) -> Vec<String> {
+    match crate::db::list_install_stage_timings(machine_id).await {
+        Ok(stages) => stages.into_iter()
+            .filter(|s| s.duration_secs.is_some())
+            .filter_map(|s| s.action)
+            .collect(),
+        Err(e) => {
+            warn!("Failed to load install stage timings for machine {}: {}", machine_id, e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use dragonfly_common::models::InstallStageEvent;
+
+    #[test]
+    fn test_duration_and_event_time() {
+        let start = Utc::now();
+        assert_eq!(duration_secs(Some(start), Some(start + Duration::seconds(95))), Some(95));
+        assert_eq!(duration_secs(Some(start), None), None);
+        assert_eq!(duration_secs(Some(start), Some(start - Duration::seconds(1))), None);
+
+        let received = Utc::now();
+        let close = received - Duration::seconds(30);
+        assert_eq!(event_time(Some(close), received), close);
+        assert_eq!(event_time(Some(received - Duration::days(400)), received), received);
+        assert_eq!(event_time(None, received), received);
+    }
+
+    #[test]
+    fn test_report_parsing_and_validation() {
+        let report: InstallTelemetryReport = serde_json::from_str(
+            r#"{"events":[{"stage":"image_download","event":"start"},{"stage":"image_write","event":"end","action":"stream image"}]}"#
+        ).unwrap();
+        assert_eq!(report.events[1].stage, InstallStage::ImageWrite);
+        assert_eq!(report.events[1].event, StageBoundary::End);
+        assert!(validate(&report).is_ok());
+
+        let event = InstallStageEvent { stage: InstallStage::BootloaderInstall, event: StageBoundary::Start, at: None, action: Some(" ".to_string()) };
+        assert!(validate(&InstallTelemetryReport { events: vec![event] }).is_err());
+        assert!(validate(&InstallTelemetryReport { events: vec![] }).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+// Post-install verification.
+//
+// The install templates' "verify installation" action runs the script below from HookOS
+// once the image is written. It checks that the layout named by the partitioning profile
+// exists (LVM volume groups, ZFS pool members), that the installed filesystems mount, and
+// that /etc/fstab is well-formed and only refers to devices that exist, then posts the
+// results to /api/install-verification/{mac}. A failed verification stops the workflow
+// before kexec, and the machine goes to Error instead of Ready with the report attached.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{InstallVerificationReport, Machine};
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// The verification report stored for a machine's most recent install.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub passed: bool,
+    #[serde(flatten)]
+    pub report: InstallVerificationReport,
+    pub received_at: DateTime<Utc>,
+}
+
+/// One line naming what failed, for the machine's Error status.
+pub fn failure_summary(report: &InstallVerificationReport) -> String {
+    let failed: Vec<String> = report.checks.iter()
+        .filter(|c| !c.passed)
+        .map(|c| format!("{}: {}", c.name, c.detail))
+        .collect();
+    if failed.is_empty() {
+        "no checks were run".to_string()
+    } else {
+        failed.join("; ")
+    }
+}
+
+pub async fn record(machine: &Machine, report: &InstallVerificationReport) -> Result<()> {
+    crate::db::save_install_verification(&machine.id, report).await?;
+    if report.passed() {
+        info!("Post-install verification passed on machine {}", machine.id);
+    } else {
+        warn!("Post-install verification failed on machine {}: {}", machine.id, failure_summary(report));
+    }
+    Ok(())
+}
+
+/// Why the machine must not be marked Ready, if its install failed verification.
+pub async fn failure(machine_id: &Uuid) -> Option<String> {
+    match crate::db::get_install_verification(machine_id).await {
+        Ok(Some(verification)) if !verification.passed => {
+            Some(format!("Post-install verification failed: {}", failure_summary(&verification.report)))
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Failed to load install verification for machine {}: {}", machine_id, e);
+            None
+        }
+    }
+}
+
+/// Forget the previous install's report when a new one starts.
+pub async fn reset(machine_id: &Uuid) {
+    if let Err(e) = crate::db::clear_install_verification(machine_id).await {
+        warn!("Failed to clear install verification for machine {}: {}", machine_id, e);
+    }
+}
+
+/// Run by the templates' "verify installation" action (an Alpine container) with LAYOUT,
+/// ROOT_DEVICE, DISKS and REPORT_URL set. Exits non-zero when a check fails.
+pub const VERIFY_SCRIPT: &str = r#"#!/bin/sh
+set -u
+
+CHECKS=""
+FAILED=0
+
+check() {
+    detail=$(printf '%s' "$3" | tr -d '"\\' | tr '\n' ' ')
+    CHECKS="$CHECKS${CHECKS:+,}{\"name\":\"$1\",\"passed\":$2,\"detail\":\"$detail\"}"
+    [ "$2" = true ] || FAILED=1
+}
+
+finish() {
+    layout=null
+    [ -n "$LAYOUT" ] && layout="\"$LAYOUT\""
+    wget -q -O /dev/null -T 10 --header 'Content-Type: application/json' \
+        --post-data "{\"layout\":$layout,\"checks\":[$CHECKS]}" "$REPORT_URL" || true
+    exit $FAILED
+}
+
+# Resolve an fstab source (UUID=, LABEL=, PARTUUID= or a path) to a block device
+resolve() {
+    case "$1" in
+        UUID=*) blkid -U "${1#UUID=}" ;;
+        LABEL=*) blkid -L "${1#LABEL=}" ;;
+        PARTUUID=*) blkid -o device -t "PARTUUID=${1#PARTUUID=}" | head -n 1 ;;
+        /dev/*) [ -b "$1" ] && echo "$1" ;;
+    esac
+}
+
+apk add --no-cache blkid lvm2 >/dev/null 2>&1 || true
+mkdir -p /mnt/root /mnt/check
+
+# 1. The layout the partitioning profile asked for exists
+CANDIDATES="$ROOT_DEVICE"
+case "$LAYOUT" in
+    lvm)
+        lvm vgchange -ay >/dev/null 2>&1
+        volumes=$(lvm lvs --noheadings -o vg_name,lv_name 2>/dev/null | awk '{print $1 "/" $2}')
+        if [ -n "$volumes" ]; then
+            check layout true "LVM volumes: $volumes"
+            CANDIDATES=$(for v in $volumes; do echo "/dev/$v"; done)
+        else
+            check layout false "No LVM logical volumes found"
+        fi
+        ;;
+    zfs)
+        pools=$(blkid -t TYPE=zfs_member -o value -s LABEL 2>/dev/null | sort -u)
+        if [ -n "$pools" ]; then
+            check layout true "ZFS pool members found for: $pools"
+        else
+            check layout false "No ZFS pool members found on $DISKS"
+        fi
+        if ! apk add --no-cache zfs >/dev/null 2>&1 || ! modprobe zfs 2>/dev/null; then
+            check mount true "Skipped: no ZFS support in the installer environment"
+            finish
+        fi
+        CANDIDATES=""
+        for pool in $pools; do
+            zpool import -N -f -R /mnt/root -o readonly=on "$pool" >/dev/null 2>&1 && zfs mount -a 2>/dev/null
+        done
+        ;;
+    *)
+        fstype=$(blkid -s TYPE -o value "$ROOT_DEVICE" 2>/dev/null)
+        if [ -n "$fstype" ]; then
+            check layout true "$ROOT_DEVICE has a $fstype filesystem"
+        else
+            check layout false "No filesystem found on $ROOT_DEVICE"
+        fi
+        ;;
+esac
+
+# 2. The root filesystem mounts and holds an fstab
+root=""
+if [ -z "$CANDIDATES" ] && [ -f /mnt/root/etc/fstab ]; then
+    root="ZFS"
+fi
+for dev in $CANDIDATES; do
+    if mount -o ro "$dev" /mnt/root 2>/dev/null; then
+        if [ -f /mnt/root/etc/fstab ]; then
+            root="$dev"
+            break
+        fi
+        umount /mnt/root
+    fi
+done
+if [ -z "$root" ]; then
+    check mount false "No installed root filesystem with an /etc/fstab could be mounted"
+    finish
+fi
+
+# 3. /etc/fstab is well-formed, has a root entry and only names devices that exist
+problems=$(awk '
+    /^[[:space:]]*(#|$)/ { next }
+    NF < 4 { print "malformed line: " $0; next }
+    $3 != "swap" && seen[$2]++ { print "duplicate mount point " $2 }
+    $2 == "/" { root = 1 }
+    END { if (!root) print "no entry for /" }
+' /mnt/root/etc/fstab)
+mounted=""
+while read -r spec mountpoint fstype options _; do
+    case "$spec" in ""|\#*) continue ;; esac
+    case "$fstype" in proc|sysfs|tmpfs|devpts|nfs|nfs4|cifs|zfs|none|bind) continue ;; esac
+    dev=$(resolve "$spec")
+    if [ -z "$dev" ]; then
+        problems="$problems
+$spec ($mountpoint) does not resolve to a device"
+        continue
+    fi
+    # 4. Every other local filesystem mounts
+    case "$fstype" in swap) continue ;; esac
+    case "$options" in *noauto*) continue ;; esac
+    [ "$mountpoint" = / ] && continue
+    if mount -o ro -t "$fstype" "$dev" /mnt/check 2>/dev/null; then
+        umount /mnt/check
+        mounted="$mounted $mountpoint"
+    else
+        problems="$problems
+$mountpoint ($dev, $fstype) failed to mount"
+    fi
+done < /mnt/root/etc/fstab
+
+check mount true "Root filesystem $root mounted${mounted:+, also mounted$mounted}"
+if [ -z "$problems" ]; then
+    check fstab true "/etc/fstab entries are valid"
+else
+    check fstab false "$problems"
+fi
+
+umount /mnt/root 2>/dev/null
+command -v zpool >/dev/null && zpool export -a 2>/dev/null
+finish
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dragonfly_common::models::HardwareCheckResult;
+
+    fn check(name: &str, passed: bool, detail: &str) -> HardwareCheckResult {
+        HardwareCheckResult { name: name.to_string(), passed, detail: detail.to_string() }
+    }
+
+    #[test]
+    fn test_report_outcome() {
+        let passing = InstallVerificationReport {
+            layout: Some("lvm".to_string()),
+            checks: vec![check("layout", true, "LVM volumes: vg0/root"), check("fstab", true, "ok")],
+        };
+        assert!(passing.passed());
+
+        let failing = InstallVerificationReport {
+            layout: None,
+            checks: vec![check("layout", true, "ext4"), check("fstab", false, "no entry for /"), check("mount", false, "nothing mounted")],
+        };
+        assert!(!failing.passed());
+        assert_eq!(failure_summary(&failing), "fstab: no entry for /; mount: nothing mounted");
+
+        // A report with no checks proves nothing
+        let empty = InstallVerificationReport { layout: None, checks: vec![] };
+        assert!(!empty.passed());
+        assert_eq!(failure_summary(&empty), "no checks were run");
+    }
+
+    #[test]
+    fn test_script_report_parses() {
+        let body = r#"{"layout":null,"checks":[{"name":"layout","passed":true,"detail":"/dev/sda1 has a ext4 filesystem"},{"name":"fstab","passed":false,"detail":" LABEL=UEFI (/boot/efi) does not resolve to a device"}]}"#;
+        let report: InstallVerificationReport = serde_json::from_str(body).unwrap();
+        assert_eq!(report.layout, None);
+        assert!(!report.passed());
+    }
+}
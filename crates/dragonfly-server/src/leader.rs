@@ -0,0 +1,138 @@
+// Leader election between server replicas.
+//
+// Several replicas can share one database, but background tasks such as workflow polling,
+// reconciliation and cleanup must only run once. Replicas compete for a lease row in the
+// database: the holder renews it every few seconds and is the leader; if it stops renewing
+// (crash, partition) the lease expires and another replica takes over. Singleton tasks keep
+// their timers on every replica and skip a tick unless `is_leader()`. Tasks that react to
+// events (OS policies, DNS) stay on every replica, since each one only sees its own events.
+//
+// A single server always wins the lease, so nothing changes for ordinary installs.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::event_manager::EventManager;
+
+/// Lease singleton background tasks are run under
+pub const LEASE_NAME: &str = "background-tasks";
+/// How long a lease lasts without renewal; failover takes at most this long
+pub const LEASE_TTL: Duration = Duration::from_secs(30);
+const RENEW_INTERVAL: Duration = Duration::from_secs(10);
+
+static IS_LEADER: AtomicBool = AtomicBool::new(false);
+
+// DRAGONFLY_REPLICA_ID if set; otherwise the hostname plus a random suffix so two servers on
+// one host (or a restarted container) never share an identity
+static REPLICA_ID: Lazy<String> = Lazy::new(|| {
+    if let Some(id) = crate::config::var("DRAGONFLY_REPLICA_ID") {
+        if !id.trim().is_empty() {
+            return id.trim().to_string();
+        }
+    }
+    let host = std::env::var("HOSTNAME").ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "dragonfly".to_string());
+    format!("{}-{}", host, &uuid::Uuid::new_v4().simple().to_string()[..8])
+});
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Lease {
+    pub holder: String,
+    pub acquired_at: DateTime<Utc>,
+    pub renewed_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Lease {
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at < now
+    }
+}
+
+/// This replica's identity in the lease table.
+pub fn replica_id() -> &'static str {
+    &REPLICA_ID
+}
+
+/// Whether this replica should run singleton background tasks.
+pub fn is_leader() -> bool {
+    IS_LEADER.load(Ordering::Relaxed)
+}
+
+// Try to take or renew the lease and record the result; a database error counts as lost,
+// since another replica may take over once our lease runs out
+async fn renew(event_manager: &EventManager) {
+    let held = match crate::db::try_acquire_lease(LEASE_NAME, replica_id(), LEASE_TTL).await {
+        Ok(held) => held,
+        Err(e) => {
+            error!("Failed to renew leader lease: {}", e);
+            false
+        }
+    };
+    let was_leader = IS_LEADER.swap(held, Ordering::Relaxed);
+    if held && !was_leader {
+        info!("Replica {} is now the leader and runs background tasks", replica_id());
+        let _ = event_manager.send(format!("leader_changed:{}", replica_id()));
+    } else if !held && was_leader {
+        warn!("Replica {} lost the leader lease; pausing background tasks", replica_id());
+        let _ = event_manager.send(format!("leader_changed:{}", replica_id()));
+    }
+}
+
+/// Take part in the election. The first attempt happens before returning, so a lone server
+/// is leader by the time the other tasks start.
+pub async fn start_leader_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    renew(&event_manager).await;
+    if !is_leader() {
+        info!("Replica {} is standing by; another replica holds the leader lease", replica_id());
+    }
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(RENEW_INTERVAL) => renew(&event_manager).await,
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, releasing leader lease.");
+                    if IS_LEADER.swap(false, Ordering::Relaxed) {
+                        if let Err(e) = crate::db::release_lease(LEASE_NAME, replica_id()).await {
+                            warn!("Failed to release leader lease: {}", e);
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_expiry() {
+        let now = Utc::now();
+        let lease = Lease {
+            holder: replica_id().to_string(),
+            acquired_at: now,
+            renewed_at: now,
+            expires_at: now + chrono::Duration::from_std(LEASE_TTL).unwrap(),
+        };
+        assert!(!lease.expired(now));
+        assert!(lease.expired(now + chrono::Duration::seconds(31)));
+    }
+
+    #[test]
+    fn test_replica_id_is_stable() {
+        assert!(!replica_id().is_empty());
+        assert_eq!(replica_id(), replica_id());
+    }
+}
@@ -14,6 +14,7 @@ use tokio::sync::watch;
 use anyhow::{Context, anyhow};
 use listenfd::ListenFd;
 use axum::extract::MatchedPath;
+use axum::serve::ListenerExt;
 
 use crate::auth::{AdminBackend, auth_router, Settings};
 use crate::db::init_db;
@@ -25,7 +26,7 @@ use minijinja::{Environment};
 use minijinja_autoreload::AutoReloader;
 
 // Add Serialize for the enum
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 // Add back AtomicBool and Ordering imports
 use std::sync::atomic::{AtomicBool, Ordering};
 
@@ -45,6 +46,81 @@ pub mod tinkerbell;
 pub mod event_manager;
 pub mod os_templates;
 pub mod mode;
+pub mod diagnostics;
+pub mod retention;
+pub mod storage;
+pub mod dedupe;
+pub mod access_tokens;
+pub mod resolve;
+pub mod logs;
+pub mod install_state;
+pub mod external_cluster;
+pub mod components;
+pub mod os_policy;
+pub mod boot_menu;
+pub mod diagnostic_boot;
+pub mod custom_images;
+pub mod chunk_store;
+pub mod peer_distribution;
+pub mod bandwidth;
+pub mod range_reader;
+pub mod tinkerbell_sync;
+pub mod tinkerbell_outbox;
+pub mod tinkerbell_client;
+pub mod repo;
+pub mod desired_state;
+pub mod fleet;
+pub mod definition;
+pub mod chaos;
+pub mod recorder;
+pub mod problem;
+pub mod secure_boot;
+pub mod http_boot;
+pub mod vlan;
+pub mod dns;
+pub mod share_links;
+pub mod agent_signing;
+pub mod terminal;
+pub mod maintenance;
+pub mod notifications;
+pub mod ephemeral;
+pub mod system_config;
+pub mod base_path;
+pub mod compression;
+pub mod install_telemetry;
+pub mod disk_layout;
+pub mod install_verification;
+pub mod drain;
+pub mod config;
+pub mod usage;
+pub mod event_scope;
+pub mod warranty;
+pub mod sensors;
+pub mod power;
+pub mod branding;
+pub mod patches;
+pub mod stack;
+pub mod template_validation;
+pub mod bulk;
+pub mod campaign;
+pub mod enrichment;
+pub mod public_api;
+pub mod boot_environment;
+pub mod leader;
+pub mod adoption;
+pub mod ssh;
+pub mod boot_interface;
+pub mod setup;
+pub mod onboarding;
+pub mod state_history;
+pub mod boot_loop;
+pub mod artifact_upload;
+pub mod replication;
+pub mod systemd;
+pub mod machine_reports;
+pub mod provisioning_profiles;
+pub mod action_approvals;
+pub mod idle_timeout;
 
 // Expose status module for integration tests
 pub mod status;
@@ -92,6 +168,14 @@ pub async fn is_dragonfly_installed() -> bool {
         return false;
     }
     debug!("Installation check: Directory '{}' found.", dir_path);
+
+    // An interrupted install leaves its state file behind; that isn't an installation
+    if let Ok(Some(progress)) = install_state::load().await {
+        if progress.current != InstallationState::Ready {
+            info!("Installation check: Found unfinished installation (last stage {:?}).", progress.current);
+            return false;
+        }
+    }
     info!("Installation check: Detected installed state (directory exists).");
     true
 }
@@ -105,7 +189,7 @@ pub enum TemplateEnv {
 }
 
 // Define the InstallationState enum here or import it
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InstallationState {
     WaitingSudo,
     DetectingNetwork,
@@ -118,6 +202,20 @@ pub enum InstallationState {
 }
 
 impl InstallationState {
+    /// Position in the install sequence; failures sort last.
+    pub fn stage_index(&self) -> u8 {
+        match self {
+            InstallationState::WaitingSudo => 0,
+            InstallationState::DetectingNetwork => 1,
+            InstallationState::InstallingK3s => 2,
+            InstallationState::WaitingK3s => 3,
+            InstallationState::DeployingTinkerbell => 4,
+            InstallationState::DeployingDragonfly => 5,
+            InstallationState::Ready => 6,
+            InstallationState::Failed(_) => u8::MAX,
+        }
+    }
+
     pub fn get_message(&self) -> &str {
         match self {
             // Phase 1
@@ -180,6 +278,12 @@ pub struct AppState {
     pub dbpool: sqlx::Pool<sqlx::Sqlite>,
     // Store API tokens in memory for immediate use after creation
     pub tokens: Arc<Mutex<std::collections::HashMap<String, String>>>,
+    // Tinkerbell resource access; a fake can be swapped in for tests
+    pub tinkerbell: Arc<dyn tinkerbell_client::TinkerbellClient>,
+    // Machine, settings and workflow history storage; in-memory for tests
+    pub repos: repo::Repositories,
+    // Resolved dragonfly.toml / environment / CLI configuration
+    pub config: &'static config::Config,
 }
 
 // Clean up any existing processes
@@ -201,9 +305,16 @@ pub async fn run() -> anyhow::Result<()> {
 
     // Determine modes SECOND (after logging is set up)
     let is_installation_server = std::env::var("DRAGONFLY_INSTALL_SERVER_MODE").is_ok();
-    let is_explicit_demo_mode = std::env::var("DRAGONFLY_DEMO_MODE").is_ok();
+    let config = config::get();
+    let is_explicit_demo_mode = config.server.demo_mode;
     let setup_mode = std::env::var("DRAGONFLY_SETUP_MODE").is_ok();
 
+    // Flight mode on an existing cluster: make every Kubernetes client target it
+    external_cluster::activate();
+
+    // Developer fault injection; logs loudly when DRAGONFLY_CHAOS is set
+    let _ = chaos::injector();
+
     // Determine installation status
     let is_installed = is_dragonfly_installed().await;
 
@@ -299,8 +410,56 @@ pub async fn run() -> anyhow::Result<()> {
     // --- Graceful Shutdown Setup --- 
     let (shutdown_tx, shutdown_rx) = watch::channel(());
 
+    // Elect the replica that runs singleton background tasks (any lone server wins)
+    leader::start_leader_task(event_manager.clone(), shutdown_rx.clone()).await;
+
     // Start the timing cleanup task
     tinkerbell::start_timing_cleanup_task(shutdown_rx.clone()).await; // Essential
+
+    // Start the retention task (purges old timings, events and job history)
+    retention::start_retention_task(shutdown_rx.clone()).await;
+
+    // Start the storage monitor (alerts when artifact/database volumes run low)
+    storage::start_storage_monitor_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Apply OS policies as machines become ready for assignment
+    os_policy::start_policy_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Keep registered custom images cached and installable
+    custom_images::start_prefetch_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Retry Tinkerbell hardware registrations that failed after the machine was saved
+    tinkerbell_sync::start_sync_retry_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Converge machines that have a desired state
+    desired_state::start_reconcile_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Keep DNS records in step with machine hostnames and addresses (when configured)
+    dns::start_dns_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Send events to notification channels, holding routine ones for quiet hours and digests
+    notifications::start_notification_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Persist artifact bytes served for the monthly usage report
+    usage::start_usage_task(shutdown_rx.clone()).await;
+
+    // Look up vendor warranties and alert on ones about to expire (when a vendor is configured)
+    warranty::start_warranty_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Poll Redfish BMCs for power, temperature and fan readings
+    sensors::start_sensor_task(shutdown_rx.clone()).await;
+
+    // Power fleet groups off during their configured off-hours and back on after
+    power::start_power_task(event_manager.clone(), shutdown_rx.clone()).await;
+
+    // Check the deployed Tinkerbell stack against the versions Dragonfly supports (there's
+    // no cluster in demo mode)
+    if !is_demo_mode {
+        stack::start_stack_check_task(event_manager.clone(), shutdown_rx.clone()).await;
+    }
+
+    // Pull artifacts from the replication primary (when this site is a secondary)
+    replication::start_replication_task(shutdown_rx.clone()).await;
     
     // Event Manager already created and stored above
 
@@ -343,6 +502,9 @@ pub async fn run() -> anyhow::Result<()> {
         }
     };
 
+    // Fill in the base URL and artifact directory from setup when the configuration doesn't set them
+    setup::apply(&settings.server);
+
     // Reset setup flag if in setup mode
     if setup_mode {
         if !is_installation_server { info!("Setup mode enabled, resetting setup completion status"); } // Cond Log
@@ -451,6 +613,9 @@ pub async fn run() -> anyhow::Result<()> {
         dbpool: db_pool.clone(),
         // Store API tokens in memory for immediate use after creation
         tokens: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        tinkerbell: tinkerbell_client::client(),
+        repos: repo::Repositories::sqlite(),
+        config,
     };
 
     // Load Proxmox API tokens from database to memory for immediate use
@@ -466,6 +631,15 @@ pub async fn run() -> anyhow::Result<()> {
     info!("Starting Proxmox synchronization task with interval of 90s");
     handlers::proxmox::start_proxmox_sync_task(std::sync::Arc::new(app_state.clone()), shutdown_rx.clone()).await;
 
+    // Remediate installs whose workflows stop making progress - only in Flight mode
+    if is_flight_mode && !is_installation_server {
+        tinkerbell::start_workflow_watchdog_task(std::sync::Arc::new(app_state.clone()), shutdown_rx.clone()).await;
+    }
+
+    // Follow provisioning campaigns, carry rollouts through their waves and send reports when
+    // campaigns finish
+    campaign::start_campaign_task(std::sync::Arc::new(app_state.clone()), shutdown_rx.clone()).await;
+
     // Session store setup
     let session_store = SqliteStore::new(db_pool.clone()); // Create store from the pool
     session_store.migrate().await?;
@@ -490,8 +664,19 @@ pub async fn run() -> anyhow::Result<()> {
         .merge(ui::ui_router())
         .route("/favicon.ico", get(handle_favicon))
         .route("/{mac}", get(api::ipxe_script))
+        .route("/boot-menu/{mac}/{choice}", get(api::boot_menu_selection))
+        .route("/ephemeral/{boot_id}/meta-data", get(api::ephemeral_meta_data))
+        .route("/ephemeral/{boot_id}/user-data", get(api::ephemeral_user_data))
+        .route("/grub/grub.cfg", get(api::grub_bootstrap_config))
+        .route("/grub/{mac}/grub.cfg", get(api::grub_config))
+        .route("/http-boot/{arch}/ipxe.efi", get(api::http_boot_file))
         .route("/ipxe/{*path}", get(api::serve_ipxe_artifact))
+        .route("/metrics", get(api::metrics))
+        .nest("/api/v1", api::api_router())
+        // Unversioned alias, kept so agents older than version negotiation keep working
         .nest("/api", api::api_router())
+        // Unauthenticated read-only inventory; answers 404 unless enabled in settings
+        .nest("/public/v1", public_api::router())
         .nest_service("/static", {
             let preferred_path = "/opt/dragonfly/static";
             let fallback_path = "crates/dragonfly-server/static";
@@ -502,6 +687,8 @@ pub async fn run() -> anyhow::Result<()> {
             };
             ServeDir::new(static_path)
         })
+        // gzip/br for HTML, JSON and static assets; artifact streams are never compressed
+        .layer(compression::layer())
         .layer(CookieManagerLayer::new())
         .layer(auth_layer)
         .layer(Extension(db_pool.clone()))
@@ -534,6 +721,16 @@ pub async fn run() -> anyhow::Result<()> {
         )
         .with_state(app_state.clone()); // State applied here
 
+    // Behind a reverse proxy the same routes are also served under DRAGONFLY_BASE_PATH
+    let app = base_path::wrap(app);
+
+    // The public inventory API can also be served on a port of its own
+    public_api::start_public_listener(app_state.clone(), shutdown_rx.clone()).await;
+
+    // Under systemd: reload on SIGHUP and keep the watchdog fed
+    systemd::start_reload_handler(app_state.clone(), shutdown_rx.clone()).await;
+    systemd::start_watchdog_task(shutdown_rx.clone()).await;
+
     // Handoff listener setup 
     if let Some(mode) = &current_mode {
         if *mode == mode::DeploymentMode::Flight {
@@ -547,7 +744,7 @@ pub async fn run() -> anyhow::Result<()> {
     }
 
     // --- Start Server --- 
-    let server_port = 3000;
+    let server_port = config.server.port;
     let addr = SocketAddr::from(([0, 0, 0, 0], server_port));
     let mut listenfd = ListenFd::from_env();
     let socket_activation = std::env::var("LISTEN_FDS").is_ok();
@@ -588,6 +785,9 @@ pub async fn run() -> anyhow::Result<()> {
         info!("Dragonfly server listening on http://{}", listener.local_addr().context("Failed to get local address")?);
     }
 
+    // Tell systemd (when running under it) that the server is up
+    systemd::notify(&format!("READY=1\nSTATUS=Listening on port {}", server_port));
+
     // --- Shutdown Signal Handling --- 
     let shutdown_signal = async move {
         // Set up a simple future for Ctrl+C
@@ -618,19 +818,40 @@ pub async fn run() -> anyhow::Result<()> {
         }
         
         // Send the shutdown signal
+        systemd::notify("STOPPING=1");
         let _ = shutdown_tx.send(());
         info!("Sending shutdown signal to all components");
         
-        // Force exit after 5 seconds if graceful shutdown hasn't completed
+        // Let artifact streams, downloads and database transactions finish, then exit even if
+        // event streams or idle connections are still holding the server open
         tokio::spawn(async {
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            println!("Forcing exit after timeout");
+            let grace = drain::grace_period();
+            let aborted = drain::drain(grace).await;
+            if aborted.is_empty() {
+                info!("In-flight work finished");
+            } else {
+                warn!("Shutdown grace period of {}s expired, aborting {}", grace.as_secs(), drain::Summary(&aborted));
+                println!("Aborted after {}s: {}", grace.as_secs(), drain::Summary(&aborted));
+            }
+            // Give finished responses a moment to flush
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            println!("Exiting");
             std::process::exit(0);
         });
     };
 
     // Start serving with graceful shutdown
     println!("Server started, press Ctrl+C to stop");
+    let idle_timeout = config.http.idle_timeout();
+    if let Some(timeout) = idle_timeout {
+        if !is_installation_server { info!("Closing connections idle for {}s", timeout.as_secs()); }
+    }
+    // Event streams are many small writes; don't hold them back waiting for more
+    let listener = idle_timeout::IdleTimeoutListener::new(listener, idle_timeout).tap_io(|stream| {
+        if let Err(e) = stream.get_mut().set_nodelay(true) {
+            debug!("Failed to set TCP_NODELAY on incoming connection: {}", e);
+        }
+    });
     axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()) // Explicitly add ConnectInfo
         .with_graceful_shutdown(shutdown_signal)
         .await
@@ -0,0 +1,280 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Layer};
+
+// Records buffered per subscriber before a slow reader starts losing lines
+const CHANNEL_CAPACITY: usize = 1024;
+
+// How many recent records are kept in memory for the admin log viewer
+const DEFAULT_BUFFER_SIZE: usize = 2000;
+const BUFFER_SIZE_ENV_VAR: &str = "DRAGONFLY_LOG_BUFFER_SIZE";
+
+// Optional file the server also logs to, reopened on SIGHUP
+const LOG_FILE_ENV_VAR: &str = "DRAGONFLY_LOG_FILE";
+
+/// One captured log line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Monotonic sequence number, so clients can tell replayed records from live ones
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+static LOG_CHANNEL: Lazy<broadcast::Sender<LogRecord>> = Lazy::new(|| broadcast::channel(CHANNEL_CAPACITY).0);
+
+static LOG_SEQ: AtomicU64 = AtomicU64::new(1);
+
+static BUFFER_SIZE: Lazy<usize> = Lazy::new(|| {
+    crate::config::var(BUFFER_SIZE_ENV_VAR)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BUFFER_SIZE)
+});
+
+static LOG_BUFFER: Lazy<Mutex<VecDeque<LogRecord>>> = Lazy::new(|| Mutex::new(VecDeque::with_capacity(*BUFFER_SIZE)));
+
+// Append to the ring buffer, dropping the oldest record once full
+fn push_record(buffer: &mut VecDeque<LogRecord>, record: LogRecord, capacity: usize) {
+    if capacity == 0 {
+        return;
+    }
+    while buffer.len() >= capacity {
+        buffer.pop_front();
+    }
+    buffer.push_back(record);
+}
+
+// Records at or above `min`, newer than `since` and after `after_seq`, oldest first
+fn filter_records<'a>(
+    records: impl Iterator<Item = &'a LogRecord>,
+    min: &Level,
+    since: Option<DateTime<Utc>>,
+    after_seq: Option<u64>,
+) -> Vec<LogRecord> {
+    records
+        .filter(|r| level_at_least(r, min))
+        .filter(|r| since.is_none_or(|since| r.timestamp > since))
+        .filter(|r| after_seq.is_none_or(|after| r.seq > after))
+        .cloned()
+        .collect()
+}
+
+/// Buffered records at or above `min` (and newer than `since`, or after record `after_seq`, if
+/// given), oldest first. Pollers should page by `after_seq`: records can share a timestamp.
+pub fn recent(min: &Level, since: Option<DateTime<Utc>>, after_seq: Option<u64>) -> Vec<LogRecord> {
+    match LOG_BUFFER.lock() {
+        Ok(buffer) => filter_records(buffer.iter(), min, since, after_seq),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Receive every log record captured from now on.
+pub fn subscribe() -> broadcast::Receiver<LogRecord> {
+    LOG_CHANNEL.subscribe()
+}
+
+/// Parse a level name as used in `?level=` and `--level`; unknown names are rejected.
+pub fn parse_level(level: &str) -> Option<Level> {
+    level.parse::<Level>().ok()
+}
+
+/// True if a record is at least as severe as `min` (ERROR is the most severe).
+pub fn level_at_least(record: &LogRecord, min: &Level) -> bool {
+    match parse_level(&record.level) {
+        // tracing orders levels by verbosity, so "at least as severe" is <=
+        Some(level) => level <= *min,
+        None => true,
+    }
+}
+
+// Flattens the message and any structured fields into a single line
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+/// Tracing layer that keeps recent events in memory and forwards them to log subscribers.
+pub struct LogCaptureLayer;
+
+impl<S: Subscriber> Layer<S> for LogCaptureLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let metadata = event.metadata();
+        let mut record = LogRecord {
+            seq: 0,
+            timestamp: Utc::now(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: format!("{}{}", visitor.message, visitor.fields),
+        };
+        // Numbered under the buffer lock, so the buffer is in seq order for clients paging by seq
+        match LOG_BUFFER.lock() {
+            Ok(mut buffer) => {
+                record.seq = LOG_SEQ.fetch_add(1, Ordering::Relaxed);
+                push_record(&mut buffer, record.clone(), *BUFFER_SIZE);
+            }
+            Err(_) => record.seq = LOG_SEQ.fetch_add(1, Ordering::Relaxed),
+        }
+        // Only fails when nobody is subscribed
+        let _ = LOG_CHANNEL.send(record);
+    }
+}
+
+/// The layer to add to the global subscriber.
+pub fn capture_layer() -> LogCaptureLayer {
+    LogCaptureLayer
+}
+
+static LOG_FILE: Lazy<Mutex<Option<(PathBuf, File)>>> = Lazy::new(|| Mutex::new(None));
+
+fn open_append(path: &PathBuf) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes to the file named by DRAGONFLY_LOG_FILE, whichever file that currently is.
+#[derive(Debug, Clone, Copy)]
+pub struct LogFileWriter;
+
+impl std::io::Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match LOG_FILE.lock().ok().as_mut().and_then(|file| file.as_mut()) {
+            Some((_, file)) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match LOG_FILE.lock().ok().as_mut().and_then(|file| file.as_mut()) {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFileWriter {
+    type Writer = LogFileWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
+
+/// Open DRAGONFLY_LOG_FILE, if set, returning the writer for a file logging layer.
+pub fn open_log_file() -> std::io::Result<Option<LogFileWriter>> {
+    let Some(path) = crate::config::var(LOG_FILE_ENV_VAR).filter(|p| !p.is_empty()).map(PathBuf::from) else {
+        return Ok(None);
+    };
+    let file = open_append(&path)?;
+    *LOG_FILE.lock().unwrap() = Some((path, file));
+    Ok(Some(LogFileWriter))
+}
+
+/// Reopen the log file at its path, after logrotate has moved the old one away. Returns false
+/// when there's no log file.
+pub fn reopen_log_file() -> std::io::Result<bool> {
+    let mut current = LOG_FILE.lock().unwrap();
+    let Some((path, file)) = current.as_mut() else {
+        return Ok(false);
+    };
+    *file = open_append(path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(level: &str) -> LogRecord {
+        LogRecord {
+            seq: 0,
+            timestamp: Utc::now(),
+            level: level.to_string(),
+            target: "test".to_string(),
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_level_filtering() {
+        assert!(level_at_least(&record("ERROR"), &Level::WARN));
+        assert!(level_at_least(&record("WARN"), &Level::WARN));
+        assert!(!level_at_least(&record("INFO"), &Level::WARN));
+        assert!(!level_at_least(&record("DEBUG"), &Level::INFO));
+    }
+
+    #[test]
+    fn test_parse_level_is_case_insensitive() {
+        assert_eq!(parse_level("warn"), Some(Level::WARN));
+        assert_eq!(parse_level("ERROR"), Some(Level::ERROR));
+        assert_eq!(parse_level("loud"), None);
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_oldest() {
+        let mut buffer = VecDeque::new();
+        for seq in 0..5 {
+            push_record(&mut buffer, LogRecord { seq, ..record("INFO") }, 3);
+        }
+        let seqs: Vec<u64> = buffer.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_filter_by_level_and_since() {
+        let cutoff = Utc::now();
+        let old = LogRecord { timestamp: cutoff - chrono::Duration::seconds(5), ..record("ERROR") };
+        let new_warn = LogRecord { timestamp: cutoff + chrono::Duration::seconds(5), ..record("WARN") };
+        let new_debug = LogRecord { timestamp: cutoff + chrono::Duration::seconds(5), ..record("DEBUG") };
+        let records = [old, new_warn, new_debug];
+
+        assert_eq!(filter_records(records.iter(), &Level::INFO, None, None).len(), 2);
+        let since = filter_records(records.iter(), &Level::INFO, Some(cutoff), None);
+        assert_eq!(since.len(), 1);
+        assert_eq!(since[0].level, "WARN");
+    }
+
+    #[test]
+    fn test_filter_after_seq_keeps_records_sharing_a_timestamp() {
+        let now = Utc::now();
+        let records: Vec<LogRecord> = (1..=3).map(|seq| LogRecord { seq, timestamp: now, ..record("INFO") }).collect();
+
+        // Paging by timestamp would drop records 2 and 3, which arrived in the same instant as 1
+        assert!(filter_records(records.iter(), &Level::INFO, Some(now), None).is_empty());
+        let seqs: Vec<u64> = filter_records(records.iter(), &Level::INFO, None, Some(1)).iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+    }
+}
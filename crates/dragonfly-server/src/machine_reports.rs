@@ -0,0 +1,273 @@
+// Files agents send about their machine: installer logs, dmesg, sosreport tarballs.
+//
+// These are too big for the JSON payloads agents otherwise report with, so they're streamed to
+// `PUT /api/machines/{id}/reports/{name}` and written straight to disk, never held in memory.
+// Like artifact uploads, a file can come in one request (chunked transfer encoding is fine) or
+// in `Content-Range` pieces, each appended to a staging file until the last one arrives. Each
+// file is capped at DRAGONFLY_MACHINE_REPORT_MAX_MB and each machine at
+// DRAGONFLY_MACHINE_REPORT_QUOTA_MB; storing a file over the quota drops the machine's oldest
+// reports. Reports live in DRAGONFLY_MACHINE_REPORT_DIR, are listed next to the machine's
+// diagnostics bundles and expire with them. Only agents signing with the enrollment token, and
+// admins, can upload them.
+
+use anyhow::Result;
+use axum::body::Body;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::artifact_upload::{ContentRange, InFlight, Progress, UploadError};
+
+const DEFAULT_REPORT_DIR: &str = "/var/lib/dragonfly/machine-reports";
+const DEFAULT_MAX_MB: u64 = 512;
+const DEFAULT_QUOTA_MB: u64 = 2048;
+const MAX_NAME_LEN: usize = 128;
+
+fn megabytes(name: &str, default: u64) -> u64 {
+    crate::config::var(name)
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(default)
+        * 1024 * 1024
+}
+
+static MAX_BYTES: Lazy<u64> = Lazy::new(|| megabytes("DRAGONFLY_MACHINE_REPORT_MAX_MB", DEFAULT_MAX_MB));
+static QUOTA_BYTES: Lazy<u64> = Lazy::new(|| megabytes("DRAGONFLY_MACHINE_REPORT_QUOTA_MB", DEFAULT_QUOTA_MB));
+
+/// A stored report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineReport {
+    pub id: Uuid,
+    pub machine_id: Uuid,
+    /// The file name the agent sent it as, e.g. "dmesg.log"
+    pub name: String,
+    pub content_type: Option<String>,
+    pub size: u64,
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MachineReport {
+    /// API path the report can be downloaded from.
+    pub fn url(&self) -> String {
+        crate::base_path::url(&format!("/api/machines/{}/reports/{}", self.machine_id, self.id))
+    }
+}
+
+/// Base directory for stored reports, from DRAGONFLY_MACHINE_REPORT_DIR or the default.
+pub fn report_dir() -> PathBuf {
+    crate::config::var("DRAGONFLY_MACHINE_REPORT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_REPORT_DIR))
+}
+
+/// Where a stored report's contents are.
+pub fn report_path(report: &MachineReport) -> PathBuf {
+    report_dir().join(report.machine_id.to_string()).join(report.id.to_string())
+}
+
+fn staging_path(machine_id: &Uuid, name: &str) -> PathBuf {
+    report_dir().join(machine_id.to_string()).join(format!(".{}.partial", name))
+}
+
+/// Check a report's file name: one path segment, nothing hidden.
+pub fn validate_name(name: &str) -> Result<&str, UploadError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c));
+    if !valid {
+        return Err(UploadError::Invalid(format!(
+            "'{}' is not a valid report name: use up to {} letters, digits, '-', '_', '.' and '+', not starting with '.'",
+            name, MAX_NAME_LEN
+        )));
+    }
+    Ok(name)
+}
+
+/// Refuse a report that's declared bigger than the cap before reading any of it.
+pub fn check_size(declared: Option<u64>) -> Result<(), UploadError> {
+    match declared {
+        Some(size) if size > *MAX_BYTES => Err(UploadError::TooLarge { limit: *MAX_BYTES }),
+        _ => Ok(()),
+    }
+}
+
+/// Oldest reports to delete so `incoming` more bytes fit in `quota`; `existing` is oldest first.
+pub fn reports_to_evict(existing: &[MachineReport], incoming: u64, quota: u64) -> Vec<Uuid> {
+    let mut used: u64 = existing.iter().map(|r| r.size).sum::<u64>() + incoming;
+    let mut evict = Vec::new();
+    for report in existing {
+        if used <= quota {
+            break;
+        }
+        used -= report.size;
+        evict.push(report.id);
+    }
+    evict
+}
+
+/// Take the body of a PUT: the whole report when `range` is None, otherwise the next piece.
+/// `content_length` is checked against the cap up front when the client sent one.
+pub async fn receive(
+    machine_id: &Uuid,
+    name: &str,
+    range: Option<ContentRange>,
+    content_length: Option<u64>,
+    content_type: Option<&str>,
+    body: Body,
+) -> Result<(Progress, Option<MachineReport>), UploadError> {
+    check_size(range.map(|r| r.total).or(content_length))?;
+    let _claim = InFlight::claim(&format!("report:{}/{}", machine_id, name))?;
+    let partial = staging_path(machine_id, name);
+    if let Some(dir) = partial.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    // Pieces after the first must pick up where the staging file ends
+    let offset = match range {
+        Some(range) if range.start > 0 => {
+            let received = fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0);
+            if received != range.start {
+                return Err(UploadError::OffsetMismatch { received });
+            }
+            range.start
+        }
+        _ => {
+            fs::File::create(&partial).await?;
+            0
+        }
+    };
+
+    let mut file = fs::OpenOptions::new().append(true).open(&partial).await?;
+    let mut stream = body.into_data_stream();
+    let mut written = 0u64;
+    let appended: Result<(), UploadError> = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(anyhow::Error::from)?;
+            written += chunk.len() as u64;
+            // Undeclared sizes (chunked transfer encoding) are capped as they arrive
+            if offset + written > *MAX_BYTES {
+                return Err(UploadError::TooLarge { limit: *MAX_BYTES });
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }.await;
+    match appended {
+        Ok(()) => {}
+        Err(e @ UploadError::TooLarge { .. }) => {
+            drop(file);
+            let _ = fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        Err(e) => {
+            // Drop whatever part of the piece arrived, so it can simply be sent again
+            file.set_len(offset).await?;
+            return Err(e);
+        }
+    }
+    if let Some(expected) = range.map(|r| r.end - r.start + 1).filter(|len| *len != written) {
+        file.set_len(offset).await?;
+        return Err(UploadError::Invalid(format!("Content-Range covers {} bytes but the body had {}", expected, written)));
+    }
+    drop(file);
+
+    let received = offset + written;
+    if let Some(total) = range.map(|r| r.total).filter(|total| received < *total) {
+        return Ok((Progress::Partial { received, total }, None));
+    }
+
+    let report = MachineReport {
+        id: Uuid::new_v4(),
+        machine_id: *machine_id,
+        name: name.to_string(),
+        content_type: content_type.map(str::to_string),
+        size: received,
+        sha256: crate::custom_images::hash_file(&partial).await?,
+        created_at: Utc::now(),
+    };
+    make_room(machine_id, received).await?;
+    fs::rename(&partial, report_path(&report)).await?;
+    if let Err(e) = crate::db::save_machine_report(&report).await {
+        let _ = fs::remove_file(report_path(&report)).await;
+        return Err(e.into());
+    }
+    info!("Stored report {} ({} bytes) for machine {}", name, received, machine_id);
+    Ok((Progress::Complete { size: received, sha256: report.sha256.clone() }, Some(report)))
+}
+
+// Drop the machine's oldest reports until `incoming` more bytes fit in its quota
+async fn make_room(machine_id: &Uuid, incoming: u64) -> Result<()> {
+    let mut existing = crate::db::list_machine_reports(machine_id).await?;
+    existing.reverse();
+    for id in reports_to_evict(&existing, incoming, *QUOTA_BYTES) {
+        warn!("Machine {} is over its report quota, deleting report {}", machine_id, id);
+        delete(machine_id, &id).await?;
+    }
+    Ok(())
+}
+
+/// Delete a stored report; false when there's no such report.
+pub async fn delete(machine_id: &Uuid, report_id: &Uuid) -> Result<bool> {
+    let Some(report) = crate::db::get_machine_report(machine_id, report_id).await? else {
+        return Ok(false);
+    };
+    crate::db::delete_machine_report(report_id).await?;
+    if let Err(e) = fs::remove_file(report_path(&report)).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove report file {}: {}", report_path(&report).display(), e);
+        }
+    }
+    Ok(true)
+}
+
+/// Delete reports older than the cutoff, files included. Returns how many went.
+pub async fn purge_before(cutoff: &DateTime<Utc>) -> Result<u64> {
+    let expired = crate::db::list_machine_reports_before(cutoff).await?;
+    for report in &expired {
+        delete(&report.machine_id, &report.id).await?;
+    }
+    Ok(expired.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(size: u64) -> MachineReport {
+        MachineReport {
+            id: Uuid::new_v4(),
+            machine_id: Uuid::nil(),
+            name: "dmesg.log".to_string(),
+            content_type: None,
+            size,
+            sha256: String::new(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("sosreport-node1-2026-10-18.tar.xz").is_ok());
+        assert!(validate_name("installer+hook.log").is_ok());
+        for bad in ["", ".partial", "../dmesg.log", "logs/dmesg.log", "dmesg log"] {
+            assert!(validate_name(bad).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_reports_to_evict() {
+        let existing = vec![report(400), report(300), report(200)];
+        assert!(reports_to_evict(&existing, 100, 1000).is_empty());
+        // 900 stored + 300 incoming is 200 over; the oldest (400) has to go
+        assert_eq!(reports_to_evict(&existing, 300, 1000), vec![existing[0].id]);
+        assert_eq!(reports_to_evict(&existing, 900, 1000), vec![existing[0].id, existing[1].id, existing[2].id]);
+    }
+}
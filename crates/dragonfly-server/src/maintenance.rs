@@ -0,0 +1,181 @@
+// Maintenance mode: a per-machine (or per-tag, so per fleet group) flag that keeps Dragonfly's
+// automation away from hardware someone is working on.
+//
+// While a machine is in maintenance, desired-state reconciliation and OS policies skip it,
+// reimages and BMC power actions are refused, and its Tinkerbell sync failures don't raise
+// events. A maintenance window may carry an expiry, after which it simply stops applying.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A maintenance window as stored for a machine or a tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MaintenanceWindow {
+    pub reason: Option<String>,
+    /// None means until someone ends it
+    pub until: Option<DateTime<Utc>>,
+    pub started_at: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        !matches!(self.until, Some(until) if until <= now)
+    }
+}
+
+/// A machine's effective maintenance state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MaintenanceStatus {
+    pub maintenance: bool,
+    #[serde(flatten)]
+    pub window: Option<MaintenanceWindow>,
+    /// Tag the window was inherited from; None when set on the machine itself
+    pub source_tag: Option<String>,
+}
+
+impl MaintenanceStatus {
+    fn none() -> Self {
+        MaintenanceStatus { maintenance: false, window: None, source_tag: None }
+    }
+
+    /// Why automation skipped the machine, for logs and error messages.
+    pub fn describe(&self) -> String {
+        let mut text = match &self.source_tag {
+            Some(tag) => format!("in maintenance via tag '{}'", tag),
+            None => "in maintenance".to_string(),
+        };
+        if let Some(reason) = self.window.as_ref().and_then(|w| w.reason.as_deref()) {
+            text.push_str(&format!(" ({})", reason));
+        }
+        if let Some(until) = self.window.as_ref().and_then(|w| w.until) {
+            text.push_str(&format!(" until {}", until.to_rfc3339()));
+        }
+        text
+    }
+}
+
+/// Maintenance as submitted by an admin; `maintenance: false` ends it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceInput {
+    pub maintenance: bool,
+    pub reason: Option<String>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceInput {
+    /// The window to store, or None to clear it.
+    pub fn into_window(self, now: DateTime<Utc>) -> Result<Option<MaintenanceWindow>> {
+        if !self.maintenance {
+            return Ok(None);
+        }
+        if let Some(until) = self.until {
+            if until <= now {
+                bail!("Maintenance expiry {} is in the past", until.to_rfc3339());
+            }
+        }
+        let reason = self.reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+        if reason.as_ref().is_some_and(|r| r.len() > 500) {
+            bail!("Maintenance reason must be at most 500 characters");
+        }
+        Ok(Some(MaintenanceWindow { reason, until: self.until, started_at: now }))
+    }
+}
+
+/// The machine's own active window wins, else the first active window of its tags (by name).
+pub fn resolve(
+    machine_window: Option<MaintenanceWindow>,
+    tag_windows: &[(String, MaintenanceWindow)],
+    machine_tags: &[String],
+    now: DateTime<Utc>,
+) -> MaintenanceStatus {
+    if let Some(window) = machine_window.filter(|w| w.is_active(now)) {
+        return MaintenanceStatus { maintenance: true, window: Some(window), source_tag: None };
+    }
+    let mut matching: Vec<&(String, MaintenanceWindow)> = tag_windows.iter()
+        .filter(|(tag, window)| window.is_active(now) && machine_tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+        .collect();
+    matching.sort_by(|a, b| a.0.cmp(&b.0));
+    match matching.first() {
+        Some((tag, window)) => MaintenanceStatus {
+            maintenance: true,
+            window: Some(window.clone()),
+            source_tag: Some(tag.clone()),
+        },
+        None => MaintenanceStatus::none(),
+    }
+}
+
+/// A machine's effective maintenance state, by ID.
+pub async fn status(machine_id: &Uuid) -> Result<MaintenanceStatus> {
+    let now = Utc::now();
+    let machine_window = crate::db::get_machine_maintenance(machine_id).await?;
+    if machine_window.as_ref().is_some_and(|w| w.is_active(now)) {
+        return Ok(resolve(machine_window, &[], &[], now));
+    }
+    let tag_windows = crate::db::list_tag_maintenance().await?;
+    if tag_windows.is_empty() {
+        return Ok(MaintenanceStatus::none());
+    }
+    let tags = crate::db::get_machine_tags(machine_id).await?;
+    Ok(resolve(None, &tag_windows, &tags, now))
+}
+
+/// Whether automation should leave the machine alone. Lookup failures count as "not in
+/// maintenance" so a database hiccup doesn't silently stop automation everywhere.
+pub async fn is_active(machine_id: &Uuid) -> bool {
+    match status(machine_id).await {
+        Ok(status) => status.maintenance,
+        Err(e) => {
+            tracing::warn!("Failed to check maintenance mode for machine {}: {}", machine_id, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn window(reason: &str, until: Option<DateTime<Utc>>) -> MaintenanceWindow {
+        MaintenanceWindow { reason: Some(reason.to_string()), until, started_at: Utc::now() }
+    }
+
+    #[test]
+    fn test_expired_windows_stop_applying() {
+        let now = Utc::now();
+        assert!(resolve(Some(window("disk swap", None)), &[], &[], now).maintenance);
+        assert!(!resolve(Some(window("disk swap", Some(now - Duration::minutes(1)))), &[], &[], now).maintenance);
+        assert!(resolve(Some(window("disk swap", Some(now + Duration::hours(2)))), &[], &[], now).maintenance);
+    }
+
+    #[test]
+    fn test_machine_overrides_tags() {
+        let now = Utc::now();
+        let tag_windows = vec![
+            ("rack-b".to_string(), window("PDU work", None)),
+            ("ceph".to_string(), window("expired", Some(now - Duration::hours(1)))),
+        ];
+        let tags = vec!["Rack-B".to_string(), "ceph".to_string()];
+
+        let own = resolve(Some(window("disk swap", None)), &tag_windows, &tags, now);
+        assert_eq!(own.source_tag, None);
+        // The expired ceph window is skipped even though it sorts first
+        let inherited = resolve(None, &tag_windows, &tags, now);
+        assert_eq!(inherited.source_tag.as_deref(), Some("rack-b"));
+        assert_eq!(inherited.describe(), "in maintenance via tag 'rack-b' (PDU work)");
+        assert!(!resolve(None, &tag_windows, &["gpu".to_string()], now).maintenance);
+    }
+
+    #[test]
+    fn test_input_validation() {
+        let now = Utc::now();
+        let input = |maintenance, until| MaintenanceInput { maintenance, reason: Some("  ".to_string()), until };
+        assert_eq!(input(false, None).into_window(now).unwrap(), None);
+        assert!(input(true, Some(now - Duration::minutes(5))).into_window(now).is_err());
+        let window = input(true, None).into_window(now).unwrap().unwrap();
+        assert_eq!(window.reason, None);
+    }
+}
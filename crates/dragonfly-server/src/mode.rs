@@ -216,23 +216,7 @@ pub async fn generate_systemd_socket_unit(
     description: &str
 ) -> Result<()> {
     // Create the socket file for socket activation
-    let socket_content = format!(
-        r#"[Unit]
-Description={} Socket
-Documentation=https://github.com/your-repo/dragonfly
-
-[Socket]
-ListenStream=3000
-# Accept=no means we're using socket activation
-Accept=no
-SocketUser=root
-SocketMode=0666
-
-[Install]
-WantedBy=sockets.target
-"#,
-        description
-    );
+    let socket_content = crate::systemd::socket_unit(description, crate::config::get().server.port);
 
     // Write the socket file
     let socket_file = format!("/etc/systemd/system/{}.socket", service_name);
@@ -255,35 +239,7 @@ pub async fn generate_systemd_unit(
     generate_systemd_socket_unit(service_name, description).await?;
     
     // Now create the service file
-    let unit_content = format!(
-        r#"[Unit]
-Description={}
-Documentation=https://github.com/your-repo/dragonfly
-After=network.target
-Requires={}.socket
-
-[Service]
-Type=notify
-Environment="DRAGONFLY_SERVICE=1"
-ExecStart={}
-# Don't restart immediately; add a short delay
-Restart=on-failure
-RestartSec=1
-# Make sure the service starts only when the socket is ready
-# This ensures proper socket activation
-WatchdogSec=10
-
-# Hardening options
-ProtectSystem=full
-ProtectHome=read-only
-PrivateTmp=true
-NoNewPrivileges=true
-
-[Install]
-WantedBy=multi-user.target
-"#,
-        description, service_name, exec_path
-    );
+    let unit_content = crate::systemd::service_unit(service_name, description, exec_path);
 
     // Write the unit file
     let unit_file = format!("/etc/systemd/system/{}.service", service_name);
@@ -970,7 +926,7 @@ pub async fn configure_flight_mode() -> Result<()> {
     let hooks_download_fut = async {
         info!("Checking/Downloading HookOS artifacts...");
         // The download function itself should be idempotent or check existence
-        match crate::api::download_hookos_artifacts("v0.10.0").await {
+        match crate::api::download_hookos_artifacts(crate::stack::RECOMMENDED_HOOK_RELEASE).await {
             Ok(_) => info!("HookOS artifacts check/download complete."),
             Err(e) => {
                 warn!("Failed to download/verify HookOS artifacts: {}", e);
@@ -1818,6 +1774,7 @@ fn setup_logging(log_dir: &str) -> Result<(), anyhow::Error> {
     tracing_subscriber::registry()
         .with(fmt::layer().with_writer(non_blocking_writer))
         .with(fmt::layer().with_writer(std::io::stdout)) // Also log to stdout
+        .with(crate::logs::capture_layer()) // Feeds the admin log stream
         .with(EnvFilter::from_default_env() // Read RUST_LOG from environment
             .add_directive("info".parse()?) // Default level is info
             .add_directive("tower_http=warn".parse()?) // Quieter HTTP logs
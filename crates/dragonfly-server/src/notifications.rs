@@ -0,0 +1,460 @@
+// Outgoing notifications: server and machine events posted to webhook channels (Slack,
+// Mattermost, or anything else that accepts a JSON POST).
+//
+// Critical events (sync failures, low storage, failed builds) always go out straight away.
+// Routine ones (discoveries, completed diagnostics) are held during a channel's quiet hours
+// and, in digest mode, batched into hourly or daily summaries. Held notifications are queued
+// in the database so a restart doesn't lose them. Machines in maintenance don't notify.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+
+// How often held notifications are checked for delivery
+const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+const WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+// Items spelled out in a digest message; the rest are only counted
+const MAX_DIGEST_ITEMS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Routine,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestMode {
+    /// Routine notifications go out as they happen (outside quiet hours)
+    #[default]
+    Off,
+    Hourly,
+    Daily,
+}
+
+/// A daily window, in the channel's local time, during which routine notifications are held.
+/// The window may wrap midnight (22:00-07:00).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_digest_hour() -> u8 {
+    9
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationChannel {
+    pub name: String,
+    pub webhook_url: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Offset of the channel's local time from UTC, for quiet hours and daily digests
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    #[serde(default)]
+    pub digest: DigestMode,
+    /// Local hour at which the daily digest goes out
+    #[serde(default = "default_digest_hour")]
+    pub digest_hour: u8,
+}
+
+impl NotificationChannel {
+    fn local(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        (at + Duration::minutes(self.utc_offset_minutes as i64)).naive_utc()
+    }
+
+    fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        (local - Duration::minutes(self.utc_offset_minutes as i64)).and_utc()
+    }
+
+    pub fn in_quiet_hours(&self, now: DateTime<Utc>) -> bool {
+        self.quiet_hours.as_ref().is_some_and(|quiet| quiet.contains(self.local(now).time()))
+    }
+
+    /// Whether a notification should be sent now rather than held for later.
+    pub fn sends_immediately(&self, severity: Severity, now: DateTime<Utc>) -> bool {
+        severity == Severity::Critical || (self.digest == DigestMode::Off && !self.in_quiet_hours(now))
+    }
+
+    /// Whether notifications held since the last flush are due to go out.
+    pub fn flush_due(&self, last_flush: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        if self.in_quiet_hours(now) {
+            return false;
+        }
+        let local = self.local(now);
+        let boundary = match self.digest {
+            DigestMode::Off => return true,
+            DigestMode::Hourly => local.date().and_hms_opt(local.hour(), 0, 0),
+            DigestMode::Daily => {
+                let today = local.date().and_hms_opt(self.digest_hour as u32, 0, 0);
+                today.map(|t| if t > local { t - Duration::days(1) } else { t })
+            }
+        };
+        // A digest held back by quiet hours goes out as soon as they end
+        boundary.is_some_and(|boundary| last_flush < self.to_utc(boundary))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    #[serde(default)]
+    pub channels: Vec<NotificationChannel>,
+}
+
+impl NotificationSettings {
+    pub fn validate(&self) -> Result<()> {
+        let mut names = std::collections::HashSet::new();
+        for channel in &self.channels {
+            let name = channel.name.trim();
+            if name.is_empty() {
+                bail!("Channel name must not be empty");
+            }
+            if !names.insert(name.to_ascii_lowercase()) {
+                bail!("Duplicate channel name '{}'", name);
+            }
+            match url::Url::parse(&channel.webhook_url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => bail!("Channel '{}' needs an http(s) webhook URL", name),
+            }
+            if channel.utc_offset_minutes.abs() > 14 * 60 {
+                bail!("Channel '{}' has a UTC offset outside ±14 hours", name);
+            }
+            if channel.digest_hour > 23 {
+                bail!("Channel '{}' has a digest hour outside 0-23", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One thing worth telling people about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub event: String,
+    pub severity: Severity,
+    pub machine_id: Option<Uuid>,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Turn an event into a notification, or None for events nobody needs to hear about.
+pub fn classify(event: &str, at: DateTime<Utc>) -> Option<Notification> {
+    let (kind, payload) = event.split_once(':').unwrap_or((event, ""));
+    let mut parts = payload.split(':');
+    let first = parts.next().unwrap_or_default();
+    let machine_id = Uuid::parse_str(first).ok();
+    let (severity, message) = match kind {
+        "machine_discovered" => (Severity::Routine, "New machine discovered".to_string()),
+        "machine_deleted" => (Severity::Routine, "Machine deleted".to_string()),
+        "diagnostics_completed" => (Severity::Routine, "Hardware diagnostics completed".to_string()),
+        "drift_corrected" => (Severity::Routine, format!("Corrected drift in {}", parts.next().unwrap_or("machine state"))),
+        "merge_pending" => (Severity::Routine, "Duplicate registration waiting for a merge decision".to_string()),
+        "approval_pending" => (Severity::Routine, format!("Unknown MAC {} is waiting for onboarding approval", payload)),
+        "custom_image_ready" => (Severity::Routine, format!("Custom image {} is ready", payload)),
+        "redeploy_completed" => (Severity::Routine, format!("Redeploy of {} completed", payload)),
+        "storage_ok" => (Severity::Routine, "Free space is back above the threshold".to_string()),
+        "warranty_expiring" => (Severity::Routine, match parts.next().and_then(|days| days.parse::<i64>().ok()) {
+            Some(days) if days < 0 => "Warranty has expired".to_string(),
+            Some(0) => "Warranty expires today".to_string(),
+            Some(days) => format!("Warranty expires in {} day(s)", days),
+            None => "Warranty is about to expire".to_string(),
+        }),
+        "tinkerbell_sync_failed" => (Severity::Critical, "Tinkerbell registration failed, retrying".to_string()),
+        "workflow_stalled" => (Severity::Critical, match parts.next() {
+            Some("retry") => "Install stalled, restarting its workflow".to_string(),
+            Some("power_cycle") => "Install stalled, restarting its workflow and power-cycling".to_string(),
+            _ => "Install stalled, marked as failed".to_string(),
+        }),
+        "boot_loop" => (Severity::Critical, "Stuck in a network boot loop; automation paused until it's cleared".to_string()),
+        "storage_low" => (Severity::Critical, format!("Low free space on the {} volume", first)),
+        "custom_image_failed" => (Severity::Critical, format!("Custom image {} failed to build", payload)),
+        "redeploy_failed" => (Severity::Critical, format!("Redeploy failed: {}", payload)),
+        "mode_configuration_failed" => (Severity::Critical, format!("Configuring {} mode failed", first)),
+        _ => return None,
+    };
+    Some(Notification { event: kind.to_string(), severity, machine_id, message, at })
+}
+
+// "machine-name: message", falling back to the bare message for server-wide events
+async fn describe(notification: &Notification) -> String {
+    let Some(id) = notification.machine_id else {
+        return notification.message.clone();
+    };
+    let label = match crate::db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => machine.memorable_name.or(machine.hostname).unwrap_or_else(|| id.to_string()),
+        _ => id.to_string(),
+    };
+    format!("{}: {}", label, notification.message)
+}
+
+async fn post(client: &reqwest::Client, channel: &NotificationChannel, body: serde_json::Value) -> Result<()> {
+    let response = client.post(&channel.webhook_url).json(&body).send().await?;
+    if !response.status().is_success() {
+        bail!("webhook returned {}", response.status());
+    }
+    Ok(())
+}
+
+async fn send_now(client: &reqwest::Client, channel: &NotificationChannel, notification: &Notification) -> Result<()> {
+    let text = describe(notification).await;
+    let prefix = match notification.severity {
+        Severity::Critical => "[Dragonfly] CRITICAL",
+        Severity::Routine => "[Dragonfly]",
+    };
+    post(client, channel, json!({
+        // `text` is what Slack-compatible webhooks display
+        "text": format!("{} {}", prefix, text),
+        "digest": false,
+        "event": notification.event,
+        "severity": notification.severity,
+        "machine_id": notification.machine_id,
+        "message": text,
+        "at": notification.at,
+    })).await
+}
+
+async fn send_digest(client: &reqwest::Client, channel: &NotificationChannel, held: &[Notification]) -> Result<()> {
+    let mut lines = Vec::new();
+    let mut items = Vec::new();
+    for notification in held.iter().take(MAX_DIGEST_ITEMS) {
+        let text = describe(notification).await;
+        lines.push(format!("• {}", text));
+        items.push(json!({ "event": notification.event, "machine_id": notification.machine_id, "message": text, "at": notification.at }));
+    }
+    if held.len() > MAX_DIGEST_ITEMS {
+        lines.push(format!("…and {} more", held.len() - MAX_DIGEST_ITEMS));
+    }
+    post(client, channel, json!({
+        "text": format!("[Dragonfly] {} routine event(s)\n{}", held.len(), lines.join("\n")),
+        "digest": true,
+        "count": held.len(),
+        "items": items,
+    })).await
+}
+
+async fn handle_event(client: &reqwest::Client, event: &str) {
+    let now = Utc::now();
+    let Some(notification) = classify(event, now) else {
+        return;
+    };
+    if let Some(id) = notification.machine_id {
+        if crate::maintenance::is_active(&id).await {
+            debug!("Not notifying about {} on machine {} (in maintenance)", notification.event, id);
+            return;
+        }
+    }
+    let settings = match crate::db::get_notification_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load notification settings: {}", e);
+            return;
+        }
+    };
+    for channel in settings.channels.iter().filter(|c| c.enabled) {
+        if channel.sends_immediately(notification.severity, now) {
+            if let Err(e) = send_now(client, channel, &notification).await {
+                warn!("Failed to notify channel {}: {}", channel.name, e);
+            }
+        } else if let Err(e) = crate::db::queue_notification(&channel.name, &notification).await {
+            error!("Failed to queue notification for channel {}: {}", channel.name, e);
+        }
+    }
+}
+
+async fn flush_due_channels(client: &reqwest::Client, last_flush: &mut HashMap<String, DateTime<Utc>>) {
+    let now = Utc::now();
+    let settings = match crate::db::get_notification_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to load notification settings: {}", e);
+            return;
+        }
+    };
+    for channel in settings.channels.iter().filter(|c| c.enabled) {
+        let last = *last_flush.entry(channel.name.clone()).or_insert(now);
+        if !channel.flush_due(last, now) {
+            continue;
+        }
+        let held = match crate::db::queued_notifications(&channel.name).await {
+            Ok(held) => held,
+            Err(e) => {
+                error!("Failed to load queued notifications for channel {}: {}", channel.name, e);
+                continue;
+            }
+        };
+        if let Some((last_id, _)) = held.last() {
+            let notifications: Vec<Notification> = held.iter().map(|(_, n)| n.clone()).collect();
+            // Keep the queue for the next tick if the webhook is down
+            if let Err(e) = send_digest(client, channel, &notifications).await {
+                warn!("Failed to send digest to channel {}: {}", channel.name, e);
+                continue;
+            }
+            info!("Sent {} held notification(s) to channel {}", notifications.len(), channel.name);
+            if let Err(e) = crate::db::delete_queued_notifications(&channel.name, *last_id).await {
+                error!("Failed to clear queued notifications for channel {}: {}", channel.name, e);
+            }
+        }
+        last_flush.insert(channel.name.clone(), now);
+    }
+}
+
+/// Post a message straight to the named channels (every enabled channel when none are named),
+/// ignoring quiet hours and digests. Used for reports someone asked for, such as a campaign
+/// summary. Returns how many channels it reached.
+pub async fn send_direct(channel_names: &[String], text: &str, mut body: serde_json::Value) -> Result<usize> {
+    let settings = crate::db::get_notification_settings().await?;
+    let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build()?;
+    body["text"] = json!(format!("[Dragonfly] {}", text));
+    let mut sent = 0;
+    for channel in settings.channels.iter().filter(|c| c.enabled) {
+        if !channel_names.is_empty() && !channel_names.iter().any(|n| n.eq_ignore_ascii_case(channel.name.trim())) {
+            continue;
+        }
+        match post(&client, channel, body.clone()).await {
+            Ok(()) => sent += 1,
+            Err(e) => warn!("Failed to send to channel {}: {}", channel.name, e),
+        }
+    }
+    Ok(sent)
+}
+
+/// Deliver notifications for events as they happen, and send held ones when they're due.
+pub async fn start_notification_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create notification HTTP client: {}", e);
+            return;
+        }
+    };
+    let mut events = event_manager.subscribe();
+    tokio::spawn(async move {
+        let mut last_flush = HashMap::new();
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        warn!("Notification task fell too far behind on events and was disconnected");
+                        break;
+                    };
+                    handle_event(&client, &event).await;
+                }
+                _ = interval.tick() => flush_due_channels(&client, &mut last_flush).await,
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping notification task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn channel(json: serde_json::Value) -> NotificationChannel {
+        let mut base = json!({ "name": "ops", "webhook_url": "https://hooks.example.com/T000" });
+        base.as_object_mut().unwrap().extend(json.as_object().unwrap().clone());
+        serde_json::from_value(base).unwrap()
+    }
+
+    #[test]
+    fn test_quiet_hours_wrap_midnight() {
+        let night = QuietHours { start: time(22, 0), end: time(7, 0) };
+        assert!(night.contains(time(23, 30)));
+        assert!(night.contains(time(3, 0)));
+        assert!(!night.contains(time(7, 0)));
+        assert!(!night.contains(time(12, 0)));
+        let lunch = QuietHours { start: time(12, 0), end: time(13, 0) };
+        assert!(lunch.contains(time(12, 30)));
+        assert!(!lunch.contains(time(13, 30)));
+    }
+
+    #[test]
+    fn test_critical_events_ignore_quiet_hours() {
+        // Quiet 22:00-07:00 in UTC+10, so 17:00 UTC is 03:00 local
+        let channel = channel(json!({ "quiet_hours": { "start": "22:00:00", "end": "07:00:00" }, "utc_offset_minutes": 600 }));
+        let at_3am = utc("2026-03-01T17:00:00Z");
+        assert!(channel.in_quiet_hours(at_3am));
+        assert!(!channel.sends_immediately(Severity::Routine, at_3am));
+        assert!(channel.sends_immediately(Severity::Critical, at_3am));
+        assert!(channel.sends_immediately(Severity::Routine, utc("2026-03-01T02:00:00Z")));
+    }
+
+    #[test]
+    fn test_digest_flush_schedule() {
+        let hourly = channel(json!({ "digest": "hourly" }));
+        assert!(!hourly.flush_due(utc("2026-03-01T10:05:00Z"), utc("2026-03-01T10:59:00Z")));
+        assert!(hourly.flush_due(utc("2026-03-01T10:05:00Z"), utc("2026-03-01T11:00:00Z")));
+
+        // Daily at 08:00 local, quiet until 09:00: the digest waits for quiet hours to end
+        let daily = channel(json!({ "digest": "daily", "digest_hour": 8, "quiet_hours": { "start": "22:00:00", "end": "09:00:00" } }));
+        let last = utc("2026-03-01T12:00:00Z");
+        assert!(!daily.flush_due(last, utc("2026-03-02T07:59:00Z")));
+        assert!(!daily.flush_due(last, utc("2026-03-02T08:30:00Z")));
+        assert!(daily.flush_due(last, utc("2026-03-02T09:00:00Z")));
+        assert!(!daily.flush_due(utc("2026-03-02T09:00:00Z"), utc("2026-03-02T20:00:00Z")));
+    }
+
+    #[test]
+    fn test_classify_events() {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let discovered = classify(&format!("machine_discovered:{}", id), now).unwrap();
+        assert_eq!((discovered.severity, discovered.machine_id), (Severity::Routine, Some(id)));
+        let low = classify("storage_low:artifacts:1024", now).unwrap();
+        assert_eq!((low.severity, low.machine_id), (Severity::Critical, None));
+        assert_eq!(low.message, "Low free space on the artifacts volume");
+        let stalled = classify(&format!("workflow_stalled:{}:power_cycle", id), now).unwrap();
+        assert_eq!((stalled.severity, stalled.machine_id), (Severity::Critical, Some(id)));
+        assert_eq!(stalled.message, "Install stalled, restarting its workflow and power-cycling");
+        let warranty = classify(&format!("warranty_expiring:{}:30", id), now).unwrap();
+        assert_eq!((warranty.severity, warranty.message.as_str()), (Severity::Routine, "Warranty expires in 30 day(s)"));
+        assert!(classify(&format!("machine_updated:{}", id), now).is_none());
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        let settings = |channels: Vec<NotificationChannel>| NotificationSettings { channels };
+        assert!(settings(vec![channel(json!({}))]).validate().is_ok());
+        assert!(settings(vec![channel(json!({})), channel(json!({ "name": "OPS" }))]).validate().is_err());
+        assert!(settings(vec![channel(json!({ "webhook_url": "ftp://example.com" }))]).validate().is_err());
+        assert!(settings(vec![channel(json!({ "digest_hour": 24 }))]).validate().is_err());
+    }
+}
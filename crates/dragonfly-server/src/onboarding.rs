@@ -0,0 +1,252 @@
+// Onboarding allowlist: which unknown machines may register.
+//
+// Anything that network-boots on the provisioning VLAN reaches the agent and registers, and
+// with a default OS set it's imaged straight away. In allowlist mode only MACs an admin has
+// pre-registered, or that fall under an allowed OUI prefix, register as usual. Registrations
+// from any other MAC are held as pending approvals; nothing is created for them (so there's no
+// Tinkerbell hardware and nothing to image) until an admin approves. A rejected MAC stays on
+// record so its retries are turned away and it isn't given anything to boot.
+//
+// Machines Dragonfly already knows are never affected, so switching the mode on doesn't lock
+// out the existing fleet.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::RegisterRequest;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnboardingMode {
+    /// Any machine may register
+    #[default]
+    Open,
+    /// Only allowlisted MACs register; others wait for approval
+    Allowlist,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowlistEntry {
+    /// A full MAC address, or a three-byte OUI prefix such as "3c:ec:ef"
+    pub pattern: String,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnboardingSettings {
+    #[serde(default)]
+    pub mode: OnboardingMode,
+    #[serde(default)]
+    pub allowlist: Vec<AllowlistEntry>,
+}
+
+// Lower-case, colon-separated bytes; None unless it's a whole MAC or an OUI
+fn normalize_pattern(pattern: &str) -> Option<String> {
+    let bytes: Vec<&str> = pattern.trim().split([':', '-']).collect();
+    let valid = (bytes.len() == 3 || bytes.len() == 6)
+        && bytes.iter().all(|b| b.len() == 2 && b.chars().all(|c| c.is_ascii_hexdigit()));
+    valid.then(|| bytes.join(":").to_lowercase())
+}
+
+impl OnboardingSettings {
+    /// Validate the allowlist, normalizing patterns and dropping duplicates.
+    pub fn normalize(mut self) -> Result<Self> {
+        let mut seen = std::collections::HashSet::new();
+        let mut allowlist = Vec::new();
+        for entry in self.allowlist {
+            let Some(pattern) = normalize_pattern(&entry.pattern) else {
+                bail!("'{}' is neither a MAC address nor an OUI prefix like 3c:ec:ef", entry.pattern.trim());
+            };
+            if seen.insert(pattern.clone()) {
+                let note = entry.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+                allowlist.push(AllowlistEntry { pattern, note });
+            }
+        }
+        self.allowlist = allowlist;
+        Ok(self)
+    }
+
+    /// Whether a MAC may register without approval.
+    pub fn allows(&self, mac: &str) -> bool {
+        if self.mode == OnboardingMode::Open {
+            return true;
+        }
+        let Some(mac) = normalize_pattern(mac) else { return false };
+        self.allowlist.iter().any(|entry| mac == entry.pattern || mac.starts_with(&format!("{}:", entry.pattern)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalStatus {
+    Pending,
+    Rejected,
+}
+
+impl ApprovalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApprovalStatus::Pending => "pending",
+            ApprovalStatus::Rejected => "rejected",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(ApprovalStatus::Pending),
+            "rejected" => Some(ApprovalStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// A registration from a MAC that isn't allowlisted, waiting for (or turned away by) an admin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingApproval {
+    pub id: Uuid,
+    pub mac_address: String,
+    pub status: ApprovalStatus,
+    /// The latest registration the machine sent
+    pub request: RegisterRequest,
+    pub attempts: u32,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// What happens to a registration.
+#[derive(Debug, Clone)]
+pub enum Admission {
+    Allowed,
+    Pending(PendingApproval),
+    Rejected(PendingApproval),
+}
+
+/// Admit, hold or turn away a registration according to the onboarding settings.
+pub async fn check_registration(req: &RegisterRequest) -> Result<Admission> {
+    let settings = crate::db::get_onboarding_settings().await?;
+    if settings.allows(&req.mac_address) {
+        return Ok(Admission::Allowed);
+    }
+    // Re-registration from a machine we already know is a normal update
+    if crate::db::get_machine_by_mac(&req.mac_address).await?.is_some() {
+        return Ok(Admission::Allowed);
+    }
+
+    let now = Utc::now();
+    // Agents retry registration, so keep one approval per MAC
+    let approval = match crate::db::get_approval_by_mac(&req.mac_address).await? {
+        Some(existing) => PendingApproval {
+            request: req.clone(),
+            attempts: existing.attempts.saturating_add(1),
+            last_seen_at: now,
+            ..existing
+        },
+        None => PendingApproval {
+            id: Uuid::new_v4(),
+            mac_address: req.mac_address.to_lowercase(),
+            status: ApprovalStatus::Pending,
+            request: req.clone(),
+            attempts: 1,
+            created_at: now,
+            last_seen_at: now,
+        },
+    };
+    crate::db::save_approval(&approval).await?;
+
+    Ok(match approval.status {
+        ApprovalStatus::Pending => {
+            if approval.attempts == 1 {
+                info!("MAC {} is not allowlisted; holding its registration as pending approval {}", approval.mac_address, approval.id);
+            }
+            Admission::Pending(approval)
+        }
+        ApprovalStatus::Rejected => Admission::Rejected(approval),
+    })
+}
+
+/// Whether a MAC was rejected and shouldn't be given anything to boot.
+pub async fn boot_refused(mac: &str) -> bool {
+    let settings = match crate::db::get_onboarding_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!("Failed to load onboarding settings: {}", e);
+            return false;
+        }
+    };
+    if settings.mode == OnboardingMode::Open {
+        return false;
+    }
+    matches!(
+        crate::db::get_approval_by_mac(mac).await,
+        Ok(Some(approval)) if approval.status == ApprovalStatus::Rejected
+    )
+}
+
+/// Register the held request as a machine and drop the approval.
+pub async fn approve(id: &Uuid) -> Result<Uuid> {
+    let approval = crate::db::get_approval(id).await?
+        .ok_or_else(|| anyhow!("Approval {} not found", id))?;
+
+    let machine_id = crate::db::register_machine(&approval.request).await?;
+    if let Ok(Some(machine)) = crate::db::get_machine_by_id(&machine_id).await {
+        crate::tinkerbell_sync::sync_or_queue(&*crate::tinkerbell_client::client(), &machine).await;
+    }
+
+    crate::db::delete_approval(id).await?;
+    info!("Approved MAC {} as machine {}", approval.mac_address, machine_id);
+    Ok(machine_id)
+}
+
+/// Turn the MAC away; it stays rejected until approved or forgotten.
+pub async fn reject(id: &Uuid) -> Result<PendingApproval> {
+    let mut approval = crate::db::get_approval(id).await?
+        .ok_or_else(|| anyhow!("Approval {} not found", id))?;
+    approval.status = ApprovalStatus::Rejected;
+    crate::db::save_approval(&approval).await?;
+    info!("Rejected registration from MAC {}", approval.mac_address);
+    Ok(approval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pattern: &str) -> AllowlistEntry {
+        AllowlistEntry { pattern: pattern.to_string(), note: None }
+    }
+
+    #[test]
+    fn test_normalize_allowlist() {
+        let settings = OnboardingSettings {
+            mode: OnboardingMode::Allowlist,
+            allowlist: vec![entry("3C-EC-EF"), entry(" 00:11:22:33:44:55 "), entry("3c:ec:ef")],
+        };
+        let settings = settings.normalize().unwrap();
+        let patterns: Vec<&str> = settings.allowlist.iter().map(|e| e.pattern.as_str()).collect();
+        assert_eq!(patterns, vec!["3c:ec:ef", "00:11:22:33:44:55"]);
+
+        for bad in ["3c:ec", "3c:ec:ef:00", "zz:ec:ef", "3cecef"] {
+            let settings = OnboardingSettings { mode: OnboardingMode::Allowlist, allowlist: vec![entry(bad)] };
+            assert!(settings.normalize().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let settings = OnboardingSettings {
+            mode: OnboardingMode::Allowlist,
+            allowlist: vec![entry("3c:ec:ef"), entry("00:11:22:33:44:55")],
+        };
+        assert!(settings.allows("3C:EC:EF:01:02:03"));
+        assert!(settings.allows("00-11-22-33-44-55"));
+        assert!(!settings.allows("00:11:22:33:44:56"));
+        assert!(!settings.allows("3c:ec:e0:01:02:03"));
+        assert!(!settings.allows("not a mac"));
+
+        let open = OnboardingSettings { mode: OnboardingMode::Open, ..settings };
+        assert!(open.allows("aa:bb:cc:dd:ee:ff"));
+    }
+}
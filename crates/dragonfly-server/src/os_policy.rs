@@ -0,0 +1,340 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+use crate::http_boot::BootMethod;
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Conditions a machine must meet for a policy to apply. Every field that is set
+/// must match; an empty matcher matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PolicyMatcher {
+    pub min_disks: Option<usize>,
+    pub max_disks: Option<usize>,
+    /// The machine must carry all of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Case-insensitive substring of the DMI system vendor, e.g. "Dell"
+    pub vendor: Option<String>,
+    /// Case-insensitive substring of the DMI product name, e.g. "R640"
+    pub product: Option<String>,
+    /// Case-insensitive substring of the CPU model
+    pub cpu_model: Option<String>,
+    pub min_cpu_cores: Option<u32>,
+    pub min_ram_gib: Option<u64>,
+    /// How the firmware reached Dragonfly, e.g. "http_boot" for HTTP Boot-only machines
+    pub boot_method: Option<BootMethod>,
+}
+
+/// A rule assigning an OS to machines awaiting assignment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OsPolicy {
+    pub id: Uuid,
+    pub name: String,
+    /// Higher priorities are evaluated first; ties go to the older policy
+    pub priority: i32,
+    pub os_choice: String,
+    pub enabled: bool,
+    pub matcher: PolicyMatcher,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What policies are matched against, gathered from the machine, its tags and its fingerprint.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MachineFacts {
+    pub disk_count: usize,
+    pub tags: Vec<String>,
+    pub vendor: Option<String>,
+    pub product: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cpu_cores: Option<u32>,
+    pub total_ram_bytes: Option<u64>,
+    pub boot_method: Option<BootMethod>,
+}
+
+/// The OS a machine would get, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyDecision {
+    pub machine_id: Uuid,
+    pub os_choice: Option<String>,
+    /// The matching policy; None when falling back to the global default OS
+    pub policy_id: Option<Uuid>,
+    pub policy_name: Option<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// An OS policy as submitted by an admin.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsPolicyInput {
+    pub name: String,
+    #[serde(default)]
+    pub priority: i32,
+    pub os_choice: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub matcher: PolicyMatcher,
+}
+
+impl OsPolicyInput {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("Policy name is required");
+        }
+        if self.os_choice.trim().is_empty() {
+            bail!("Policy OS choice is required");
+        }
+        if let (Some(min), Some(max)) = (self.matcher.min_disks, self.matcher.max_disks) {
+            if min > max {
+                bail!("min_disks ({}) is greater than max_disks ({})", min, max);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_policy(self, id: Uuid, created_at: DateTime<Utc>) -> OsPolicy {
+        OsPolicy {
+            id,
+            name: self.name.trim().to_string(),
+            priority: self.priority,
+            os_choice: self.os_choice.trim().to_string(),
+            enabled: self.enabled,
+            matcher: self.matcher,
+            created_at,
+        }
+    }
+}
+
+fn contains_ignore_case(haystack: &Option<String>, needle: &str) -> bool {
+    haystack.as_ref().is_some_and(|h| h.to_lowercase().contains(&needle.to_lowercase()))
+}
+
+impl PolicyMatcher {
+    pub fn matches(&self, facts: &MachineFacts) -> bool {
+        if self.min_disks.is_some_and(|min| facts.disk_count < min) {
+            return false;
+        }
+        if self.max_disks.is_some_and(|max| facts.disk_count > max) {
+            return false;
+        }
+        if !self.tags.iter().all(|tag| facts.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))) {
+            return false;
+        }
+        if self.vendor.as_ref().is_some_and(|v| !contains_ignore_case(&facts.vendor, v)) {
+            return false;
+        }
+        if self.product.as_ref().is_some_and(|p| !contains_ignore_case(&facts.product, p)) {
+            return false;
+        }
+        if self.cpu_model.as_ref().is_some_and(|m| !contains_ignore_case(&facts.cpu_model, m)) {
+            return false;
+        }
+        // Unknown hardware doesn't satisfy a minimum
+        if self.min_cpu_cores.is_some_and(|min| facts.cpu_cores.map_or(true, |c| c < min)) {
+            return false;
+        }
+        if self.min_ram_gib.is_some_and(|min| facts.total_ram_bytes.map_or(true, |b| b < min * GIB)) {
+            return false;
+        }
+        if self.boot_method.is_some_and(|method| facts.boot_method != Some(method)) {
+            return false;
+        }
+        true
+    }
+}
+
+fn evaluation_order(a: &OsPolicy, b: &OsPolicy) -> std::cmp::Ordering {
+    b.priority.cmp(&a.priority).then(a.created_at.cmp(&b.created_at))
+}
+
+/// Order policies the way they're evaluated.
+pub fn sort_policies(policies: &mut [OsPolicy]) {
+    policies.sort_by(evaluation_order);
+}
+
+/// The first enabled policy matching the machine, in priority order.
+pub fn evaluate<'a>(policies: &'a [OsPolicy], facts: &MachineFacts) -> Option<&'a OsPolicy> {
+    let mut ordered: Vec<&OsPolicy> = policies.iter().filter(|p| p.enabled).collect();
+    ordered.sort_by(|a, b| evaluation_order(a, b));
+    ordered.into_iter().find(|p| p.matcher.matches(facts))
+}
+
+/// Gather the facts policies match against.
+pub async fn facts_for(machine: &Machine) -> Result<MachineFacts> {
+    let tags = crate::db::get_machine_tags(&machine.id).await?;
+    let fingerprint = crate::db::get_hardware_fingerprint(&machine.id).await?.unwrap_or_default();
+    let boot_method = crate::db::get_boot_method(&machine.mac_address).await?;
+    Ok(MachineFacts {
+        disk_count: machine.disks.len(),
+        tags,
+        vendor: fingerprint.system_vendor,
+        product: fingerprint.system_product,
+        cpu_model: machine.cpu_model.clone(),
+        cpu_cores: machine.cpu_cores,
+        total_ram_bytes: machine.total_ram_bytes,
+        boot_method,
+    })
+}
+
+/// Decide the OS for a machine using the given policies, falling back to the global default.
+pub async fn decide(machine: &Machine, policies: &[OsPolicy], default_os: Option<&str>) -> Result<PolicyDecision> {
+    let facts = facts_for(machine).await?;
+    let decision = match evaluate(policies, &facts) {
+        Some(policy) => PolicyDecision {
+            machine_id: machine.id,
+            os_choice: Some(policy.os_choice.clone()),
+            policy_id: Some(policy.id),
+            policy_name: Some(policy.name.clone()),
+        },
+        None => PolicyDecision {
+            machine_id: machine.id,
+            os_choice: default_os.map(str::to_string),
+            policy_id: None,
+            policy_name: None,
+        },
+    };
+    Ok(decision)
+}
+
+// Assign an OS to a machine that is waiting for one and hasn't been given a choice yet
+async fn apply_to_machine(id: &Uuid, event_manager: &EventManager) -> Result<()> {
+    let Some(machine) = crate::db::get_machine_by_id(id).await? else {
+        return Ok(());
+    };
+    if machine.status != MachineStatus::AwaitingAssignment || machine.os_choice.is_some() {
+        return Ok(());
+    }
+    if crate::maintenance::is_active(id).await {
+        debug!("Machine {} is in maintenance, not applying OS policies", id);
+        return Ok(());
+    }
+    if crate::db::get_ephemeral_config(id).await?.is_some() {
+        debug!("Machine {} is ephemeral, not applying OS policies", id);
+        return Ok(());
+    }
+    if crate::boot_loop::is_flagged(id).await {
+        debug!("Machine {} is flagged as boot looping, not applying OS policies", id);
+        return Ok(());
+    }
+
+    let policies = crate::db::list_os_policies().await?;
+    let settings = crate::db::get_app_settings().await?;
+    let decision = decide(&machine, &policies, settings.default_os.as_deref()).await?;
+    let Some(os_choice) = decision.os_choice else {
+        debug!("No OS policy or default OS applies to machine {}", id);
+        return Ok(());
+    };
+
+    // Assign the OS without triggering installation
+    if crate::db::assign_os(id, &os_choice).await? {
+        match &decision.policy_name {
+            Some(name) => info!("OS policy '{}' assigned '{}' to machine {}", name, os_choice, id),
+            None => info!("Default OS choice '{}' applied to machine {}", os_choice, id),
+        }
+        let _ = event_manager.send(format!("machine_updated:{}", id));
+    }
+    Ok(())
+}
+
+/// Watch machine events and apply OS policies to machines as they reach AwaitingAssignment.
+pub async fn start_policy_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let mut events = event_manager.subscribe();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Some(event) => event,
+                        None => {
+                            warn!("OS policy task fell too far behind on events and was disconnected");
+                            break;
+                        }
+                    };
+                    let id = event.strip_prefix("machine_discovered:")
+                        .or_else(|| event.strip_prefix("machine_updated:"))
+                        .and_then(|id| Uuid::parse_str(id).ok());
+                    if let Some(id) = id {
+                        if let Err(e) = apply_to_machine(&id, &event_manager).await {
+                            error!("Failed to apply OS policies to machine {}: {}", id, e);
+                        }
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping OS policy task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(name: &str, priority: i32, matcher: PolicyMatcher) -> OsPolicy {
+        OsPolicy {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            priority,
+            os_choice: format!("{}-os", name),
+            enabled: true,
+            matcher,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn ceph_node() -> MachineFacts {
+        MachineFacts {
+            disk_count: 10,
+            tags: vec!["ceph".to_string()],
+            vendor: Some("Dell Inc.".to_string()),
+            product: Some("PowerEdge R640".to_string()),
+            cpu_cores: Some(32),
+            total_ram_bytes: Some(256 * GIB),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_matcher_conditions() {
+        let facts = ceph_node();
+        assert!(PolicyMatcher::default().matches(&facts));
+        assert!(PolicyMatcher { min_disks: Some(8), tags: vec!["Ceph".to_string()], ..Default::default() }.matches(&facts));
+        assert!(PolicyMatcher { vendor: Some("dell".to_string()), product: Some("r640".to_string()), ..Default::default() }.matches(&facts));
+        assert!(!PolicyMatcher { max_disks: Some(4), ..Default::default() }.matches(&facts));
+        assert!(!PolicyMatcher { tags: vec!["ceph".to_string(), "gpu".to_string()], ..Default::default() }.matches(&facts));
+        assert!(!PolicyMatcher { min_ram_gib: Some(512), ..Default::default() }.matches(&facts));
+        assert!(!PolicyMatcher { cpu_model: Some("EPYC".to_string()), ..Default::default() }.matches(&facts));
+        assert!(!PolicyMatcher { boot_method: Some(BootMethod::HttpBoot), ..Default::default() }.matches(&facts));
+        let http_booted = MachineFacts { boot_method: Some(BootMethod::HttpBoot), ..ceph_node() };
+        assert!(PolicyMatcher { boot_method: Some(BootMethod::HttpBoot), ..Default::default() }.matches(&http_booted));
+    }
+
+    #[test]
+    fn test_highest_priority_wins() {
+        let policies = vec![
+            policy("dell", 10, PolicyMatcher { vendor: Some("Dell".to_string()), ..Default::default() }),
+            policy("ceph", 20, PolicyMatcher { tags: vec!["ceph".to_string()], ..Default::default() }),
+            policy("catchall", 0, PolicyMatcher::default()),
+        ];
+        assert_eq!(evaluate(&policies, &ceph_node()).map(|p| p.name.as_str()), Some("ceph"));
+        assert_eq!(evaluate(&policies, &MachineFacts::default()).map(|p| p.name.as_str()), Some("catchall"));
+    }
+
+    #[test]
+    fn test_disabled_policies_skipped() {
+        let mut disabled = policy("ceph", 20, PolicyMatcher::default());
+        disabled.enabled = false;
+        assert!(evaluate(&[disabled], &ceph_node()).is_none());
+    }
+}
@@ -7,7 +7,6 @@ use serde_yaml;
 use tracing::{info, error, warn};
 use std::path::Path;
 use tokio::fs;
-use std::env;
 use url::Url;
 use std::collections::HashMap;
 use reqwest;
@@ -38,18 +37,17 @@ pub async fn init_os_templates() -> Result<()> {
     Ok(())
 }
 
-/// Extract base URL without port from DRAGONFLY_BASE_URL environment variable
-fn get_base_url_without_port() -> Result<String> {
-    // Read required base URL from environment variable
-    let base_url = match env::var("DRAGONFLY_BASE_URL") {
-        Ok(url) => url,
-        Err(_) => {
-            // If DRAGONFLY_BASE_URL is not set, try DRAGONFLY_BASE_URL_BARE
-            match env::var("DRAGONFLY_BASE_URL_BARE") {
-                Ok(url) => url,
-                Err(_) => {
+/// Extract the host from the configured base URL
+pub(crate) fn get_base_url_without_port() -> Result<String> {
+    let base_url = match crate::config::base_url() {
+        Some(url) => url,
+        None => {
+            // If the base URL is not set, try DRAGONFLY_BASE_URL_BARE
+            match crate::config::var("DRAGONFLY_BASE_URL_BARE") {
+                Some(url) => url,
+                None => {
                     // If not set, default to localhost for development
-                    warn!("Neither DRAGONFLY_BASE_URL nor DRAGONFLY_BASE_URL_BARE set, using localhost as base URL for templates");
+                    warn!("Neither server.base_url nor DRAGONFLY_BASE_URL_BARE set, using localhost as base URL for templates");
                     "localhost".to_string()
                 }
             }
@@ -187,6 +185,47 @@ async fn install_template_from_file(client: &Client, template_name: &str, base_u
     }
 }
 
+/// Create a template in Tinkerbell from rendered YAML, replacing it if it already exists
+pub(crate) async fn apply_template_yaml(client: &Client, template_name: &str, template_yaml: &str) -> Result<()> {
+    let mut dynamic_obj: DynamicObject = serde_yaml::from_str(template_yaml)
+        .map_err(|e| anyhow!("Failed to parse template YAML: {}", e))?;
+    
+    let template_api_resource = kube::core::ApiResource {
+        group: "tinkerbell.org".to_string(),
+        version: "v1alpha1".to_string(),
+        kind: "Template".to_string(),
+        api_version: "tinkerbell.org/v1alpha1".to_string(),
+        plural: "templates".to_string(),
+    };
+    
+    let template_api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &template_api_resource);
+    
+    match template_api.get(template_name).await {
+        Ok(existing) => {
+            dynamic_obj.metadata.resource_version = existing.metadata.resource_version;
+            template_api.replace(template_name, &PostParams::default(), &dynamic_obj).await
+                .map_err(|e| anyhow!("Failed to replace template: {}", e))?;
+            info!("Updated template '{}'", template_name);
+        },
+        Err(KubeError::Api(ae)) if ae.code == 404 => {
+            template_api.create(&PostParams::default(), &dynamic_obj).await
+                .map_err(|e| anyhow!("Failed to create template: {}", e))?;
+            info!("Successfully created template '{}'", template_name);
+        },
+        Err(e) => return Err(anyhow!("Error checking for template: {}", e)),
+    }
+    Ok(())
+}
+
+/// Write a generated template to the local templates directory, alongside the built-in ones
+pub(crate) async fn save_local_template(template_name: &str, content: &str) -> Result<()> {
+    let os_templates_dir = Path::new("/var/lib/dragonfly/os-templates");
+    let dir = if os_templates_dir.exists() { os_templates_dir } else { Path::new("os-templates") };
+    fs::create_dir_all(dir).await?;
+    fs::write(dir.join(format!("{}.yml", template_name)), content).await?;
+    Ok(())
+}
+
 /// Download a template from GitHub
 async fn download_template_from_github(url: &str) -> Result<String> {
     info!("Downloading template from: {}", url);
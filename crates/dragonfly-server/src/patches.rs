@@ -0,0 +1,105 @@
+// OS patch baselines reported by agents running as daemons on installed machines: the
+// running kernel and how many updates (security ones among them) are waiting. The machine
+// list shows them and filters on them with ?patches=<filter>.
+
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::PatchReport;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PatchStatus {
+    #[serde(flatten)]
+    pub report: PatchReport,
+    pub reported_at: DateTime<Utc>,
+}
+
+/// Machine list filters on patch state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFilter {
+    /// Any updates pending
+    Pending,
+    /// Security updates pending
+    Security,
+    RebootRequired,
+    /// Reported with nothing pending
+    UpToDate,
+    /// No report, or a report without update counts
+    Unknown,
+}
+
+impl PatchFilter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatchFilter::Pending => "pending",
+            PatchFilter::Security => "security",
+            PatchFilter::RebootRequired => "reboot",
+            PatchFilter::UpToDate => "current",
+            PatchFilter::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(PatchFilter::Pending),
+            "security" => Some(PatchFilter::Security),
+            "reboot" => Some(PatchFilter::RebootRequired),
+            "current" => Some(PatchFilter::UpToDate),
+            "unknown" => Some(PatchFilter::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, status: Option<&PatchStatus>) -> bool {
+        let report = status.map(|s| &s.report);
+        let pending = report.and_then(|r| r.pending_updates);
+        let security = report.and_then(|r| r.pending_security_updates);
+        match self {
+            PatchFilter::Pending => pending.is_some_and(|n| n > 0),
+            PatchFilter::Security => security.is_some_and(|n| n > 0),
+            PatchFilter::RebootRequired => report.is_some_and(|r| r.reboot_required),
+            PatchFilter::UpToDate => pending == Some(0),
+            PatchFilter::Unknown => pending.is_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(pending: Option<u32>, security: Option<u32>, reboot_required: bool) -> PatchStatus {
+        PatchStatus {
+            report: PatchReport {
+                kernel_version: "6.8.0-45-generic".to_string(),
+                package_manager: pending.map(|_| "apt".to_string()),
+                pending_updates: pending,
+                pending_security_updates: security,
+                reboot_required,
+            },
+            reported_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        let behind = status(Some(12), Some(3), true);
+        let current = status(Some(0), Some(0), false);
+        let no_counts = status(None, None, false);
+
+        assert!(PatchFilter::Pending.matches(Some(&behind)));
+        assert!(PatchFilter::Security.matches(Some(&behind)));
+        assert!(PatchFilter::RebootRequired.matches(Some(&behind)));
+        assert!(!PatchFilter::UpToDate.matches(Some(&behind)));
+
+        assert!(PatchFilter::UpToDate.matches(Some(&current)));
+        assert!(!PatchFilter::Pending.matches(Some(&current)));
+
+        assert!(PatchFilter::Unknown.matches(Some(&no_counts)));
+        assert!(PatchFilter::Unknown.matches(None));
+        assert!(!PatchFilter::Security.matches(None));
+
+        for filter in [PatchFilter::Pending, PatchFilter::Security, PatchFilter::RebootRequired, PatchFilter::UpToDate, PatchFilter::Unknown] {
+            assert_eq!(PatchFilter::parse(filter.as_str()), Some(filter));
+        }
+    }
+}
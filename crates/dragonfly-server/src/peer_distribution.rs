@@ -0,0 +1,257 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+use crate::chunk_store::ChunkIndex;
+
+const ENABLED_ENV_VAR: &str = "DRAGONFLY_PEER_DISTRIBUTION";
+const SUBNET_PREFIX_ENV_VAR: &str = "DRAGONFLY_PEER_SUBNET_PREFIX";
+const LINGER_ENV_VAR: &str = "DRAGONFLY_PEER_LINGER_SECS";
+
+/// Port peers serve chunks on while syncing.
+pub const PEER_PORT: u16 = 7070;
+
+// Peers must sit on the client's subnet so chunk traffic stays off the uplink
+const DEFAULT_IPV4_PREFIX: u8 = 24;
+const DEFAULT_IPV6_PREFIX: u8 = 64;
+
+// How long a finished peer keeps seeding before it reboots into its OS
+const DEFAULT_LINGER_SECS: u64 = 300;
+
+// Peers announce every few chunks; one that goes quiet this long is gone
+const PEER_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Hand out a few candidates so a client can fall through a dead peer
+const MAX_PEERS_PER_LOOKUP: usize = 3;
+
+/// Whether syncing machines should seed chunks to each other.
+pub fn enabled() -> bool {
+    crate::config::var(ENABLED_ENV_VAR).is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+fn linger_secs() -> u64 {
+    crate::config::var(LINGER_ENV_VAR).and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_LINGER_SECS)
+}
+
+fn subnet_prefix(ip: &IpAddr) -> u8 {
+    let configured = crate::config::var(SUBNET_PREFIX_ENV_VAR).and_then(|v| v.parse().ok());
+    match ip {
+        IpAddr::V4(_) => configured.filter(|p| *p <= 32).unwrap_or(DEFAULT_IPV4_PREFIX),
+        IpAddr::V6(_) => DEFAULT_IPV6_PREFIX,
+    }
+}
+
+/// The settings line the sync script reads: "<port> <linger secs>". Only served when enabled.
+pub fn script_config() -> String {
+    format!("{} {}\n", PEER_PORT, linger_secs())
+}
+
+/// Whether two addresses share the first `prefix` bits.
+pub fn same_subnet(a: &IpAddr, b: &IpAddr, prefix: u8) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => {
+            let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix.min(32) as u32) };
+            u32::from(*a) & mask == u32::from(*b) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix.min(128) as u32) };
+            u128::from(*a) & mask == u128::from(*b) & mask
+        }
+        _ => false,
+    }
+}
+
+// Where each chunk hash first appears in an index; a peer that has synced the first
+// N chunks can serve every hash whose first position is below N
+struct IndexPositions {
+    positions: HashMap<String, usize>,
+    chunk_count: usize,
+}
+
+impl IndexPositions {
+    fn new(index: &ChunkIndex) -> Self {
+        let mut positions = HashMap::new();
+        for (i, hash) in index.chunks.iter().enumerate() {
+            positions.entry(hash.clone()).or_insert(i);
+        }
+        Self { positions, chunk_count: index.chunks.len() }
+    }
+}
+
+struct Peer {
+    addr: IpAddr,
+    port: u16,
+    index_path: String,
+    have: usize,
+    last_seen: Instant,
+    /// Times this peer was handed out; used to spread load
+    served: u64,
+}
+
+/// A peer as shown to admins.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStatus {
+    pub addr: IpAddr,
+    pub port: u16,
+    pub index_path: String,
+    pub have: usize,
+    pub total: usize,
+    pub served: u64,
+    pub last_seen_secs_ago: u64,
+}
+
+#[derive(Default)]
+struct Tracker {
+    peers: HashMap<IpAddr, Peer>,
+    indexes: HashMap<String, Arc<IndexPositions>>,
+}
+
+static TRACKER: once_cell::sync::Lazy<Mutex<Tracker>> = once_cell::sync::Lazy::new(Default::default);
+
+impl Tracker {
+    fn prune(&mut self, now: Instant) {
+        self.peers.retain(|_, peer| now.duration_since(peer.last_seen) < PEER_TIMEOUT);
+        let in_use: std::collections::HashSet<&String> = self.peers.values().map(|p| &p.index_path).collect();
+        self.indexes.retain(|path, _| in_use.contains(path));
+    }
+
+    fn announce(&mut self, addr: IpAddr, port: u16, index_path: &str, have: usize, positions: Arc<IndexPositions>, now: Instant) {
+        let have = have.min(positions.chunk_count);
+        self.indexes.insert(index_path.to_string(), positions);
+        let served = self.peers.get(&addr).filter(|p| p.index_path == index_path).map_or(0, |p| p.served);
+        self.peers.insert(addr, Peer { addr, port, index_path: index_path.to_string(), have, last_seen: now, served });
+    }
+
+    fn peers_for(&mut self, client: &IpAddr, hash: &str, prefix: u8, now: Instant) -> Vec<String> {
+        self.prune(now);
+        let indexes = &self.indexes;
+        let mut candidates: Vec<&mut Peer> = self.peers.values_mut()
+            .filter(|peer| peer.addr != *client && same_subnet(&peer.addr, client, prefix))
+            .filter(|peer| {
+                indexes.get(&peer.index_path)
+                    .and_then(|index| index.positions.get(hash))
+                    .is_some_and(|position| *position < peer.have)
+            })
+            .collect();
+        candidates.sort_by_key(|peer| peer.served);
+        candidates.truncate(MAX_PEERS_PER_LOOKUP);
+        if let Some(first) = candidates.first_mut() {
+            first.served += 1;
+        }
+        candidates.iter().map(|peer| format!("http://{}:{}", peer.addr, peer.port)).collect()
+    }
+}
+
+// Peers may only announce real chunk indexes in the artifact dir
+fn load_index(index_path: &str) -> Result<Arc<IndexPositions>> {
+    if index_path.contains("..") || !index_path.ends_with(".chunks") {
+        bail!("Invalid index path '{}'", index_path);
+    }
+    if let Some(positions) = TRACKER.lock().unwrap_or_else(|p| p.into_inner()).indexes.get(index_path) {
+        return Ok(positions.clone());
+    }
+    let content = std::fs::read_to_string(crate::api::artifact_base_dir().join(index_path))?;
+    Ok(Arc::new(IndexPositions::new(&ChunkIndex::parse(&content)?)))
+}
+
+/// Record that a peer has synced the first `have` chunks of an index and is serving them.
+pub fn announce(addr: IpAddr, port: u16, index_path: &str, have: usize) -> Result<()> {
+    let positions = load_index(index_path)?;
+    let mut tracker = TRACKER.lock().unwrap_or_else(|p| p.into_inner());
+    if !tracker.peers.contains_key(&addr) {
+        info!("Peer {}:{} joined distribution of {}", addr, port, index_path);
+    }
+    tracker.announce(addr, port, index_path, have, positions, Instant::now());
+    Ok(())
+}
+
+/// Peer base URLs on the client's subnet that can serve a chunk, least-used first.
+pub fn peers_for(client: IpAddr, hash: &str) -> Vec<String> {
+    let peers = TRACKER.lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .peers_for(&client, hash, subnet_prefix(&client), Instant::now());
+    debug!("{} peers for chunk {} requested by {}", peers.len(), hash, client);
+    peers
+}
+
+/// Every live peer, for the admin view.
+pub fn status() -> Vec<PeerStatus> {
+    let mut tracker = TRACKER.lock().unwrap_or_else(|p| p.into_inner());
+    let now = Instant::now();
+    tracker.prune(now);
+    let mut peers: Vec<PeerStatus> = tracker.peers.values()
+        .map(|peer| PeerStatus {
+            addr: peer.addr,
+            port: peer.port,
+            index_path: peer.index_path.clone(),
+            have: peer.have,
+            total: tracker.indexes.get(&peer.index_path).map_or(0, |i| i.chunk_count),
+            served: peer.served,
+            last_seen_secs_ago: now.duration_since(peer.last_seen).as_secs(),
+        })
+        .collect();
+    peers.sort_by_key(|p| p.addr);
+    peers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(chunks: &[&str]) -> Arc<IndexPositions> {
+        Arc::new(IndexPositions::new(&ChunkIndex {
+            chunk_size: 16,
+            total_size: 16 * chunks.len() as u64,
+            chunks: chunks.iter().map(|c| c.to_string()).collect(),
+        }))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_same_subnet() {
+        assert!(same_subnet(&ip("10.0.1.5"), &ip("10.0.1.200"), 24));
+        assert!(!same_subnet(&ip("10.0.1.5"), &ip("10.0.2.5"), 24));
+        assert!(same_subnet(&ip("10.0.1.5"), &ip("10.0.2.5"), 16));
+        assert!(same_subnet(&ip("fd00::1"), &ip("fd00::2"), 64));
+        assert!(!same_subnet(&ip("10.0.1.5"), &ip("fd00::1"), 0));
+    }
+
+    #[test]
+    fn test_only_peers_with_the_chunk_on_the_subnet() {
+        let mut tracker = Tracker::default();
+        let now = Instant::now();
+        let index = positions(&["a", "b", "c"]);
+        tracker.announce(ip("10.0.1.10"), PEER_PORT, "img.chunks", 1, index.clone(), now);
+        tracker.announce(ip("10.0.1.11"), PEER_PORT, "img.chunks", 3, index.clone(), now);
+        tracker.announce(ip("10.0.9.12"), PEER_PORT, "img.chunks", 3, index, now);
+
+        assert_eq!(tracker.peers_for(&ip("10.0.1.50"), "c", 24, now), vec!["http://10.0.1.11:7070"]);
+        assert_eq!(tracker.peers_for(&ip("10.0.1.50"), "a", 24, now).len(), 2);
+        assert!(tracker.peers_for(&ip("10.0.1.50"), "zzz", 24, now).is_empty());
+        // A peer is never told to fetch from itself
+        assert!(tracker.peers_for(&ip("10.0.1.11"), "c", 24, now).is_empty());
+    }
+
+    #[test]
+    fn test_load_spread_and_expiry() {
+        let mut tracker = Tracker::default();
+        let now = Instant::now();
+        let index = positions(&["a"]);
+        tracker.announce(ip("10.0.1.10"), PEER_PORT, "img.chunks", 1, index.clone(), now);
+        tracker.announce(ip("10.0.1.11"), PEER_PORT, "img.chunks", 1, index, now);
+
+        let first = tracker.peers_for(&ip("10.0.1.50"), "a", 24, now)[0].clone();
+        let second = tracker.peers_for(&ip("10.0.1.50"), "a", 24, now)[0].clone();
+        assert_ne!(first, second);
+
+        assert!(tracker.peers_for(&ip("10.0.1.50"), "a", 24, now + PEER_TIMEOUT).is_empty());
+        assert!(tracker.indexes.is_empty());
+    }
+}
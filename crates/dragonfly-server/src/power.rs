@@ -0,0 +1,369 @@
+// Managed power-off for energy saving.
+//
+// A power schedule names fleet groups (tags) and a daily off-hours window in local time.
+// When the window opens, Ready machines carrying any of the tags are shut down gracefully:
+// through their agent if it's connected, otherwise with a Redfish GracefulShutdown. They're
+// marked PoweredOff, which is a managed state rather than a fault. When the window closes
+// they're woken (Redfish power on, falling back to Wake-on-LAN) and put back to the status
+// they had. Machines can also be hibernated or woken on demand through the API.
+//
+// Schedules act when their window opens or closes, so a machine woken on demand during
+// off-hours stays up until the next window. Machines in maintenance are left alone.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use dragonfly_common::models::{BmcCredentials, Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+use crate::notifications::QuietHours;
+
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// Wake-on-LAN goes to the discard port on the local broadcast address
+const WOL_TARGET: &str = "255.255.255.255:9";
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerSchedule {
+    pub name: String,
+    /// Fleet groups the schedule applies to; a machine needs any one of them
+    pub tags: Vec<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Offset of the schedule's local time from UTC
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+    /// Local time window during which machines are kept powered off
+    pub off_hours: QuietHours,
+}
+
+impl PowerSchedule {
+    pub fn in_off_hours(&self, now: DateTime<Utc>) -> bool {
+        let local = (now + Duration::minutes(self.utc_offset_minutes as i64)).time();
+        self.off_hours.contains(local)
+    }
+
+    fn applies_to(&self, machine_tags: &[String]) -> bool {
+        machine_tags.iter().any(|tag| self.tags.contains(tag))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerSettings {
+    #[serde(default)]
+    pub schedules: Vec<PowerSchedule>,
+}
+
+impl PowerSettings {
+    pub fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for schedule in &self.schedules {
+            let name = schedule.name.trim();
+            if name.is_empty() {
+                bail!("Schedule name must not be empty");
+            }
+            if !names.insert(name.to_ascii_lowercase()) {
+                bail!("Duplicate schedule name '{}'", name);
+            }
+            if schedule.tags.iter().all(|t| t.trim().is_empty()) {
+                bail!("Schedule '{}' needs at least one tag", name);
+            }
+            if schedule.off_hours.start == schedule.off_hours.end {
+                bail!("Schedule '{}' has an empty off-hours window", name);
+            }
+            if schedule.utc_offset_minutes.abs() > 14 * 60 {
+                bail!("Schedule '{}' has a UTC offset outside ±14 hours", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a machine was powered off or woken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerMethod {
+    Agent,
+    Redfish,
+    WakeOnLan,
+}
+
+impl PowerMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerMethod::Agent => "agent",
+            PowerMethod::Redfish => "redfish",
+            PowerMethod::WakeOnLan => "wake_on_lan",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "agent" => Some(PowerMethod::Agent),
+            "redfish" => Some(PowerMethod::Redfish),
+            "wake_on_lan" => Some(PowerMethod::WakeOnLan),
+            _ => None,
+        }
+    }
+}
+
+/// Bookkeeping for a machine Dragonfly powered off, so it can be put back as it was.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagedPowerOff {
+    pub machine_id: Uuid,
+    pub previous_status: MachineStatus,
+    /// The schedule that powered it off, or None for an on-demand hibernate
+    pub schedule: Option<String>,
+    pub method: PowerMethod,
+    pub powered_off_at: DateTime<Utc>,
+}
+
+/// A Wake-on-LAN magic packet: six 0xFF bytes, then the MAC address sixteen times.
+pub fn magic_packet(mac: &str) -> Option<[u8; 102]> {
+    let bytes: Vec<u8> = mac.split([':', '-'])
+        .map(|part| u8::from_str_radix(part, 16).ok())
+        .collect::<Option<_>>()?;
+    if bytes.len() != 6 {
+        return None;
+    }
+    let mut packet = [0xFF; 102];
+    for chunk in packet[6..].chunks_mut(6) {
+        chunk.copy_from_slice(&bytes);
+    }
+    Some(packet)
+}
+
+async fn send_wake_on_lan(mac: &str) -> Result<()> {
+    let packet = magic_packet(mac).with_context(|| format!("'{}' is not a MAC address", mac))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&packet, WOL_TARGET).await?;
+    Ok(())
+}
+
+// ComputerSystem.Reset on the BMC's (first) system, e.g. "GracefulShutdown" or "On"
+async fn redfish_reset(client: &reqwest::Client, credentials: &BmcCredentials, reset_type: &str) -> Result<()> {
+    let base = crate::sensors::base_url(&credentials.address);
+    let systems = crate::sensors::get(client, credentials, &format!("{}/redfish/v1/Systems", base)).await?;
+    let path = systems["Members"][0]["@odata.id"].as_str()
+        .context("BMC reports no computer systems")?;
+    let url = format!("{}{}/Actions/ComputerSystem.Reset", base, path);
+    let response = client.post(&url)
+        .basic_auth(&credentials.username, credentials.password.as_deref())
+        .json(&serde_json::json!({ "ResetType": reset_type }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("{} returned {}", url, response.status());
+    }
+    Ok(())
+}
+
+/// Shut a Ready machine down gracefully and mark it PoweredOff.
+pub async fn hibernate(
+    client: &reqwest::Client,
+    event_manager: &EventManager,
+    machine: &Machine,
+    schedule: Option<&str>,
+) -> Result<PowerMethod> {
+    if machine.status != MachineStatus::Ready {
+        bail!("Only Ready machines can be powered off (machine is {})", machine.status);
+    }
+
+    let method = if crate::terminal::request_shutdown(&machine.id) {
+        PowerMethod::Agent
+    } else if let Some(credentials) = crate::sensors::redfish_credentials(machine) {
+        redfish_reset(client, credentials, "GracefulShutdown").await
+            .context("Redfish graceful shutdown failed")?;
+        PowerMethod::Redfish
+    } else {
+        bail!("Machine has no connected agent and no Redfish BMC to shut it down with");
+    };
+
+    crate::db::save_managed_power_off(&ManagedPowerOff {
+        machine_id: machine.id,
+        previous_status: machine.status.clone(),
+        schedule: schedule.map(str::to_string),
+        method,
+        powered_off_at: Utc::now(),
+    }).await?;
+    crate::db::update_status(&machine.id, MachineStatus::PoweredOff).await?;
+    let _ = event_manager.send(format!("machine_updated:{}", machine.id));
+    info!("Powered off machine {} via {}{}", machine.id, method.as_str(),
+        schedule.map(|s| format!(" (schedule '{}')", s)).unwrap_or_default());
+    Ok(method)
+}
+
+/// Power a managed-off machine back on and restore the status it had.
+pub async fn wake(client: &reqwest::Client, event_manager: &EventManager, machine: &Machine) -> Result<PowerMethod> {
+    if machine.status != MachineStatus::PoweredOff {
+        bail!("Machine is not powered off by Dragonfly (machine is {})", machine.status);
+    }
+
+    let redfish = match crate::sensors::redfish_credentials(machine) {
+        Some(credentials) => match redfish_reset(client, credentials, "On").await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Redfish power on failed for machine {}, trying Wake-on-LAN: {:#}", machine.id, e);
+                false
+            }
+        },
+        None => false,
+    };
+    let method = if redfish {
+        PowerMethod::Redfish
+    } else {
+        send_wake_on_lan(&machine.mac_address).await.context("Wake-on-LAN failed")?;
+        PowerMethod::WakeOnLan
+    };
+
+    // The machine is on its way up; it goes back to what it was rather than waiting on a
+    // check-in, since Ready machines don't necessarily run the agent
+    let previous = crate::db::get_managed_power_off(&machine.id).await?
+        .map(|record| record.previous_status)
+        .unwrap_or(MachineStatus::Ready);
+    crate::db::update_status(&machine.id, previous).await?;
+    crate::db::clear_managed_power_off(&machine.id).await?;
+    let _ = event_manager.send(format!("machine_updated:{}", machine.id));
+    info!("Woke machine {} via {}", machine.id, method.as_str());
+    Ok(method)
+}
+
+// Hibernate or wake the machines of schedules whose window has opened or closed since the
+// last check. `inside` remembers each schedule's last state; a schedule seen for the first
+// time acts on its current state, which catches up after a restart.
+async fn apply_schedules(
+    client: &reqwest::Client,
+    event_manager: &EventManager,
+    inside: &mut HashMap<String, bool>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let settings = crate::db::get_power_settings().await?;
+    inside.retain(|name, _| settings.schedules.iter().any(|s| s.enabled && s.name == *name));
+
+    for schedule in settings.schedules.iter().filter(|s| s.enabled) {
+        let now_inside = schedule.in_off_hours(now);
+        if inside.insert(schedule.name.clone(), now_inside) == Some(now_inside) {
+            continue;
+        }
+
+        if now_inside {
+            info!("Off-hours started for power schedule '{}'", schedule.name);
+            for machine in crate::db::get_all_machines().await? {
+                if machine.status != MachineStatus::Ready || crate::maintenance::is_active(&machine.id).await {
+                    continue;
+                }
+                let tags = crate::db::get_machine_tags(&machine.id).await.unwrap_or_default();
+                if !schedule.applies_to(&tags) {
+                    continue;
+                }
+                if let Err(e) = hibernate(client, event_manager, &machine, Some(&schedule.name)).await {
+                    warn!("Power schedule '{}' could not power off machine {}: {:#}", schedule.name, machine.id, e);
+                }
+            }
+        } else {
+            info!("Off-hours ended for power schedule '{}'", schedule.name);
+            for record in crate::db::list_managed_power_offs().await? {
+                if record.schedule.as_deref() != Some(schedule.name.as_str()) {
+                    continue;
+                }
+                let Some(machine) = crate::db::get_machine_by_id(&record.machine_id).await? else {
+                    crate::db::clear_managed_power_off(&record.machine_id).await?;
+                    continue;
+                };
+                if let Err(e) = wake(client, event_manager, &machine).await {
+                    warn!("Power schedule '{}' could not wake machine {}: {:#}", schedule.name, machine.id, e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Power machines off and on per the configured power schedules.
+pub async fn start_power_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let client = match crate::sensors::redfish_client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create Redfish HTTP client: {}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut inside = HashMap::new();
+        loop {
+            if crate::leader::is_leader() {
+                if let Err(e) = apply_schedules(&client, &event_manager, &mut inside, Utc::now()).await {
+                    error!("Failed to apply power schedules: {:#}", e);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping power schedule task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn schedule(start: (u32, u32), end: (u32, u32), utc_offset_minutes: i32) -> PowerSchedule {
+        PowerSchedule {
+            name: "nights".to_string(),
+            tags: vec!["rack-12".to_string()],
+            enabled: true,
+            utc_offset_minutes,
+            off_hours: QuietHours {
+                start: NaiveTime::from_hms_opt(start.0, start.1, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(end.0, end.1, 0).unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_in_off_hours_uses_local_time() {
+        // 20:00-06:00 in UTC+10
+        let nights = schedule((20, 0), (6, 0), 600);
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert!(nights.in_off_hours(at("2026-03-01T12:00:00Z")));
+        assert!(!nights.in_off_hours(at("2026-03-01T20:00:00Z")));
+        assert!(nights.applies_to(&["web".to_string(), "rack-12".to_string()]));
+        assert!(!nights.applies_to(&["web".to_string()]));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut settings = PowerSettings { schedules: vec![schedule((20, 0), (6, 0), 0)] };
+        assert!(settings.validate().is_ok());
+        settings.schedules.push(schedule((22, 0), (5, 0), 0));
+        assert!(settings.validate().is_err());
+        let empty = PowerSettings { schedules: vec![schedule((6, 0), (6, 0), 0)] };
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn test_magic_packet() {
+        let packet = magic_packet("52:54:00:12:34:5e").unwrap();
+        assert_eq!(&packet[..6], &[0xFF; 6]);
+        assert_eq!(&packet[6..12], &[0x52, 0x54, 0x00, 0x12, 0x34, 0x5e]);
+        assert_eq!(&packet[96..], &[0x52, 0x54, 0x00, 0x12, 0x34, 0x5e]);
+        assert_eq!(magic_packet("52-54-00-12-34-5E"), Some(packet));
+        assert!(magic_packet("52:54:00:12:34").is_none());
+        assert!(magic_packet("not a mac").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+// RFC 7807 problem details for every error the API returns.
+//
+// Handlers keep returning whatever they always have (ErrorResponse JSON, json! bodies);
+// the middleware here rewrites JSON and empty error responses into
+// `application/problem+json`, keeping any members it doesn't map as extension members
+// (merge_id on a registration conflict, a failed cluster validation report). Plain text
+// and HTML bodies, such as the fragments htmx swaps into the UI, are left alone.
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use dragonfly_common::validation::FieldError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+/// Echoed back on every API response; taken from the request if the caller sent one.
+pub const CORRELATION_HEADER: &str = "x-correlation-id";
+
+// Error bodies are small; anything larger is summarized rather than buffered
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub correlation_id: String,
+    /// Per-field validation failures
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    /// Whatever else the handler's body carried
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+// Members the problem document defines itself; a leftover one would be a duplicate key
+const RESERVED_MEMBERS: [&str; 7] = ["type", "title", "status", "detail", "instance", "correlation_id", "errors"];
+
+/// Stable problem type URI for a status code. These never change once published.
+pub fn type_uri(status: StatusCode) -> String {
+    let slug = match status.as_u16() {
+        400 => "bad-request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not-found",
+        405 => "method-not-allowed",
+        409 => "conflict",
+        413 => "payload-too-large",
+        415 => "unsupported-media-type",
+        422 => "validation-failed",
+        429 => "too-many-requests",
+        502 => "upstream-error",
+        503 => "service-unavailable",
+        507 => "insufficient-storage",
+        code if code < 500 => "client-error",
+        _ => "internal-error",
+    };
+    format!("urn:dragonfly:problem:{}", slug)
+}
+
+// Remove a member if it's a string
+fn take_str(map: &mut Map<String, Value>, key: &str) -> Option<String> {
+    match map.get(key) {
+        Some(Value::String(_)) => map.remove(key).and_then(|v| v.as_str().map(str::to_string)),
+        _ => None,
+    }
+}
+
+/// Build the problem document for an error response from the JSON body (or empty body) the
+/// handler produced.
+pub fn from_body(status: StatusCode, body: &[u8], instance: &str, correlation_id: &str) -> Problem {
+    let default_title = status.canonical_reason().unwrap_or("Error").to_string();
+    let mut map = match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+
+    // ErrorResponse and the ad-hoc json! bodies all use error/message (some add fields)
+    let title = take_str(&mut map, "error")
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or(default_title);
+    let detail = take_str(&mut map, "message").or_else(|| take_str(&mut map, "detail"));
+    let mut errors = Vec::new();
+    for key in ["fields", "errors"] {
+        if let Some(fields) = map.get(key).and_then(|f| serde_json::from_value::<Vec<FieldError>>(f.clone()).ok()) {
+            map.remove(key);
+            errors = fields;
+            break;
+        }
+    }
+    for key in RESERVED_MEMBERS {
+        map.remove(key);
+    }
+
+    Problem {
+        type_uri: type_uri(status),
+        title,
+        status: status.as_u16(),
+        detail,
+        instance: Some(instance.to_string()),
+        correlation_id: correlation_id.to_string(),
+        errors,
+        extensions: map,
+    }
+}
+
+fn correlation_id(headers: &HeaderMap) -> String {
+    headers.get(CORRELATION_HEADER)
+        .or_else(|| headers.get("x-request-id"))
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Middleware that tags API responses with a correlation ID and rewrites error
+/// responses as problem+json.
+pub async fn problem_responses(request: Request, next: Next) -> Response {
+    let correlation_id = correlation_id(request.headers());
+    let instance = request.extensions()
+        .get::<axum::extract::OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    let response = next.run(request).await;
+    let status = response.status();
+    let (mut parts, body) = response.into_parts();
+    if let Ok(value) = HeaderValue::from_str(&correlation_id) {
+        parts.headers.insert(CORRELATION_HEADER, value);
+    }
+
+    let content_type = parts.headers.get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let is_json = content_type.contains("json") || content_type.is_empty();
+    if !(status.is_client_error() || status.is_server_error()) || content_type.starts_with(CONTENT_TYPE) || !is_json {
+        return Response::from_parts(parts, body);
+    }
+
+    let bytes = match to_bytes(body, MAX_ERROR_BODY).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            warn!("{} error body over {} bytes (correlation {}), dropped", instance, MAX_ERROR_BODY, correlation_id);
+            Default::default()
+        }
+    };
+    // A body without a content type is passed on as it is unless it's empty
+    if content_type.is_empty() && !bytes.is_empty() {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    let problem = from_body(status, &bytes, &instance, &correlation_id);
+    if status.is_server_error() {
+        warn!("{} failed with {} (correlation {}): {}", instance, status, correlation_id, problem.detail.as_deref().unwrap_or(&problem.title));
+    } else {
+        debug!("{} rejected with {} (correlation {})", instance, status, correlation_id);
+    }
+
+    let json = serde_json::to_vec(&problem).unwrap_or_default();
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_error_response_json() {
+        let body = json!({ "error": "Not Found", "message": "Machine with ID 42 not found" }).to_string();
+        let problem = from_body(StatusCode::NOT_FOUND, body.as_bytes(), "/api/machines/42", "abc");
+        assert_eq!(problem.type_uri, "urn:dragonfly:problem:not-found");
+        assert_eq!(problem.title, "Not Found");
+        assert_eq!(problem.detail.as_deref(), Some("Machine with ID 42 not found"));
+        assert_eq!(problem.instance.as_deref(), Some("/api/machines/42"));
+        assert_eq!(problem.correlation_id, "abc");
+    }
+
+    #[test]
+    fn test_validation_fields_carried_over() {
+        let body = json!({
+            "error": "Validation Failed",
+            "message": "mac_address: bad",
+            "fields": [{ "field": "mac_address", "message": "bad" }],
+        }).to_string();
+        let problem = from_body(StatusCode::UNPROCESSABLE_ENTITY, body.as_bytes(), "/api/machines", "abc");
+        assert_eq!(problem.type_uri, "urn:dragonfly:problem:validation-failed");
+        assert_eq!(problem.errors, vec![FieldError { field: "mac_address".to_string(), message: "bad".to_string() }]);
+    }
+
+    #[test]
+    fn test_extension_members_kept() {
+        let body = json!({
+            "error": "Duplicate Machine",
+            "message": "Waiting for a merge decision",
+            "merge_id": "m1",
+            "existing_machine_id": "e1",
+            "status": "pending",
+        }).to_string();
+        let problem = from_body(StatusCode::CONFLICT, body.as_bytes(), "/api/machines", "abc");
+        assert_eq!(problem.extensions.get("merge_id"), Some(&json!("m1")));
+        assert_eq!(problem.extensions.get("existing_machine_id"), Some(&json!("e1")));
+        let json = serde_json::to_value(&problem).unwrap();
+        assert_eq!(json["status"], json!(409));
+        assert_eq!(json["merge_id"], json!("m1"));
+
+        // A bare report becomes extension members under the default title
+        let report = json!({ "server_version": "v1.31", "checks": [{ "name": "nodes", "passed": false }] }).to_string();
+        let problem = from_body(StatusCode::UNPROCESSABLE_ENTITY, report.as_bytes(), "/api/flight/validate", "abc");
+        assert_eq!(problem.title, "Unprocessable Entity");
+        assert_eq!(problem.extensions.get("checks"), Some(&json!([{ "name": "nodes", "passed": false }])));
+    }
+
+    #[test]
+    fn test_empty_body() {
+        let problem = from_body(StatusCode::BAD_GATEWAY, b"", "/api/x", "abc");
+        assert_eq!(problem.title, "Bad Gateway");
+        assert_eq!(problem.detail, None);
+        assert_eq!(problem.type_uri, "urn:dragonfly:problem:upstream-error");
+    }
+}
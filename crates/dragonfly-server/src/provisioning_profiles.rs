@@ -0,0 +1,155 @@
+// Reusable provisioning profiles, cloned from a machine that's set up the way others should be.
+//
+// `POST /api/machines/{id}/clone-profile` snapshots a machine's OS choice, provisioning config
+// (cloud-init, partitioning profile and kernel args) and tags under a name. Applying a profile to
+// other machines overwrites those fields on each of them. A profile can also be a tag's default:
+// members of the tag that leave cloud-init, partitioning or kernel args unset get the profile's
+// at install time. Like other per-tag settings, the first of a machine's tags (by name) with a
+// default wins.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::definition::ProvisioningConfig;
+use crate::event_manager::EventManager;
+
+const MAX_NAME_LEN: usize = 64;
+
+/// A named set of provisioning inputs that can be applied to any machine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvisioningProfile {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_choice: Option<String>,
+    #[serde(flatten)]
+    pub provisioning: ProvisioningConfig,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The machine the profile was cloned from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_machine_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Check and trim a profile name.
+pub fn validate_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.chars().any(char::is_control) {
+        bail!("Profile names must be 1 to {} characters on a single line", MAX_NAME_LEN);
+    }
+    Ok(name.to_string())
+}
+
+/// Clone a machine's provisioning into a new profile. None when there's no such machine.
+pub async fn capture(machine_id: &Uuid, name: &str) -> Result<Option<ProvisioningProfile>> {
+    let name = validate_name(name)?;
+    let Some(machine) = crate::db::get_machine_by_id(machine_id).await? else {
+        return Ok(None);
+    };
+
+    let profile = ProvisioningProfile {
+        id: Uuid::new_v4(),
+        name,
+        os_choice: machine.os_choice.clone(),
+        provisioning: crate::db::get_provisioning_config(machine_id).await?.unwrap_or_default(),
+        tags: crate::db::get_machine_tags(machine_id).await?,
+        source_machine_id: Some(machine.id),
+        created_at: Utc::now(),
+    };
+    crate::db::save_provisioning_profile(&profile).await?;
+    info!("Cloned machine {} into provisioning profile '{}' ({})", machine_id, profile.name, profile.id);
+    Ok(Some(profile))
+}
+
+/// Apply a profile to a machine, replacing its OS choice (when the profile has one),
+/// provisioning config and tags. Returns false when there's no such machine.
+pub async fn apply(profile: &ProvisioningProfile, machine_id: &Uuid, event_manager: Arc<EventManager>) -> Result<bool> {
+    profile.provisioning.validate()?;
+    if crate::db::get_machine_by_id(machine_id).await?.is_none() {
+        return Ok(false);
+    }
+
+    if let Some(os_choice) = &profile.os_choice {
+        crate::db::assign_os(machine_id, os_choice).await?;
+    }
+    crate::db::save_provisioning_config(machine_id, &profile.provisioning).await?;
+    crate::db::update_machine_tags(machine_id, &profile.tags).await?;
+
+    // Push the new user data to the hardware record
+    if let Some(machine) = crate::db::get_machine_by_id(machine_id).await? {
+        crate::tinkerbell_sync::sync_or_queue(&*crate::tinkerbell_client::client(), &machine).await;
+    }
+
+    info!("Applied provisioning profile '{}' to machine {}", profile.name, machine_id);
+    let _ = event_manager.send(format!("machine_updated:{}", machine_id));
+    Ok(true)
+}
+
+/// Fill the gaps in a machine's own config from the default profile of the first of its tags
+/// that has one. `defaults` is sorted by tag.
+pub fn resolve(own: ProvisioningConfig, defaults: &[(String, ProvisioningProfile)], tags: &[String]) -> ProvisioningConfig {
+    match defaults.iter().find(|(tag, _)| tags.contains(tag)) {
+        Some((_, profile)) => own.or(&profile.provisioning),
+        None => own,
+    }
+}
+
+/// The provisioning config a machine's next install uses.
+pub async fn effective_config(machine_id: &Uuid) -> Result<ProvisioningConfig> {
+    let own = crate::db::get_provisioning_config(machine_id).await?.unwrap_or_default();
+    let defaults = crate::db::list_tag_profiles().await?;
+    if defaults.is_empty() {
+        return Ok(own);
+    }
+    let tags = crate::db::get_machine_tags(machine_id).await?;
+    Ok(resolve(own, &defaults, &tags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(cloud_init: Option<&str>, kernel_args: Option<&str>) -> ProvisioningProfile {
+        ProvisioningProfile {
+            id: Uuid::new_v4(),
+            name: "web".to_string(),
+            os_choice: Some("ubuntu-2404".to_string()),
+            provisioning: ProvisioningConfig {
+                cloud_init: cloud_init.map(str::to_string),
+                partitioning: None,
+                kernel_args: kernel_args.map(str::to_string),
+            },
+            tags: vec![],
+            source_machine_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert_eq!(validate_name("  web servers ").unwrap(), "web servers");
+        assert!(validate_name(" ").is_err());
+        assert!(validate_name("web\nservers").is_err());
+        assert!(validate_name(&"x".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_fills_gaps_from_first_tag() {
+        let defaults = vec![
+            ("db".to_string(), profile(Some("#cloud-config\n"), Some("console=ttyS0"))),
+            ("web".to_string(), profile(None, Some("quiet"))),
+        ];
+        let own = ProvisioningConfig { kernel_args: Some("nomodeset".to_string()), ..Default::default() };
+        let tags = vec!["web".to_string(), "db".to_string()];
+
+        let resolved = resolve(own.clone(), &defaults, &tags);
+        assert_eq!(resolved.cloud_init.as_deref(), Some("#cloud-config\n"));
+        assert_eq!(resolved.kernel_args.as_deref(), Some("nomodeset"));
+        assert_eq!(resolve(own.clone(), &defaults, &["other".to_string()]), own);
+    }
+}
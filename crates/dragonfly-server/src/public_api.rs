@@ -0,0 +1,258 @@
+// Read-only public inventory API for CMDB scrapers and other consumers that can't log in.
+//
+// Off by default. When enabled in settings, GET /public/v1/machines and
+// /public/v1/machines/{id} serve the machine inventory without authentication, and the same
+// routes can also be served on a separate port (picked up at startup) so they can be exposed
+// to a different network than the admin UI.
+//
+// Only the fields listed in PUBLIC_FIELDS are ever served, so BMC credentials and anything
+// added to machines later stay private. Admins can redact more with field paths: "ip_address"
+// drops a field, "disks.model" drops a field inside every entry of a list.
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use dragonfly_common::models::Machine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::AppState;
+
+/// Machine fields that may be published; everything else is left out.
+pub const PUBLIC_FIELDS: &[&str] = &[
+    "id",
+    "mac_address",
+    "ip_address",
+    "hostname",
+    "memorable_name",
+    "os_choice",
+    "os_installed",
+    "status",
+    "disks",
+    "nameservers",
+    "cpu_model",
+    "cpu_cores",
+    "total_ram_bytes",
+    "proxmox_vmid",
+    "proxmox_node",
+    "proxmox_cluster",
+    "is_proxmox_host",
+    "last_deployment_duration",
+    "created_at",
+    "updated_at",
+    "tags",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicApiSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Also serve the public routes on this port; takes effect on restart
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Field paths left out of every machine, e.g. "ip_address" or "disks.model"
+    #[serde(default)]
+    pub redacted_fields: Vec<String>,
+}
+
+impl PublicApiSettings {
+    pub fn validate(&self, server_port: u16) -> Result<()> {
+        if let Some(port) = self.port {
+            if port == 0 || port == server_port {
+                bail!("Public API port must be non-zero and differ from the server port ({})", server_port);
+            }
+        }
+        for path in &self.redacted_fields {
+            let field = path.split('.').next().unwrap_or_default();
+            if !PUBLIC_FIELDS.contains(&field) || path.split('.').any(str::is_empty) {
+                bail!("Unknown field '{}'; redact one of {}", path, PUBLIC_FIELDS.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Remove a dotted path from a value, descending into every entry of lists on the way
+fn redact(value: &mut Value, path: &[&str]) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| redact(item, path)),
+        Value::Object(fields) => match path {
+            [field] => {
+                fields.remove(*field);
+            }
+            [field, rest @ ..] => {
+                if let Some(inner) = fields.get_mut(*field) {
+                    redact(inner, rest);
+                }
+            }
+            [] => {}
+        },
+        _ => {}
+    }
+}
+
+/// A machine as the public API shows it.
+pub fn public_view(machine: &Machine, tags: &[String], redacted_fields: &[String]) -> Value {
+    let mut all = match serde_json::to_value(machine) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    all.insert("tags".to_string(), json!(tags));
+    let mut view = Value::Object(
+        all.into_iter()
+            .filter(|(field, _)| PUBLIC_FIELDS.contains(&field.as_str()))
+            .collect(),
+    );
+    for path in redacted_fields {
+        redact(&mut view, &path.split('.').collect::<Vec<_>>());
+    }
+    view
+}
+
+fn error_response(status: StatusCode, error: &str, message: String) -> Response {
+    (status, Json(json!({ "error": error, "message": message }))).into_response()
+}
+
+// The settings when the public API is on; a 404 (as if the routes didn't exist) otherwise
+async fn enabled_settings() -> Result<PublicApiSettings, Response> {
+    match crate::db::get_public_api_settings().await {
+        Ok(settings) if settings.enabled => Ok(settings),
+        Ok(_) => Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => {
+            error!("Failed to load public API settings: {}", e);
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Database Error", "Failed to load settings".to_string()))
+        }
+    }
+}
+
+async fn list_machines() -> Response {
+    let settings = match enabled_settings().await {
+        Ok(settings) => settings,
+        Err(response) => return response,
+    };
+    let machines = crate::db::get_all_machines().await;
+    let tags = crate::db::get_all_machine_tags().await;
+    match (machines, tags) {
+        (Ok(machines), Ok(tags)) => Json(machines.iter()
+            .map(|m| public_view(m, tags.get(&m.id).map(Vec::as_slice).unwrap_or_default(), &settings.redacted_fields))
+            .collect::<Vec<_>>())
+            .into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to load machines for the public API: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Database Error", "Failed to load machines".to_string())
+        }
+    }
+}
+
+async fn get_machine(Path(id): Path<Uuid>) -> Response {
+    let settings = match enabled_settings().await {
+        Ok(settings) => settings,
+        Err(response) => return response,
+    };
+    match crate::db::get_machine_by_id(&id).await {
+        Ok(Some(machine)) => {
+            let tags = crate::db::get_machine_tags(&id).await.unwrap_or_default();
+            Json(public_view(&machine, &tags, &settings.redacted_fields)).into_response()
+        }
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "Not Found", format!("Machine with ID {} not found", id)),
+        Err(e) => {
+            error!("Failed to load machine {} for the public API: {}", id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Database Error", "Failed to load machine".to_string())
+        }
+    }
+}
+
+/// The read-only routes, mounted at /public/v1 and on the public port.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/machines", get(list_machines))
+        .route("/machines/{id}", get(get_machine))
+}
+
+/// Serve the public routes on their own port when one is configured.
+pub async fn start_public_listener(app_state: AppState, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let port = match crate::db::get_public_api_settings().await {
+        Ok(PublicApiSettings { port: Some(port), .. }) => port,
+        Ok(_) => return,
+        Err(e) => {
+            error!("Failed to load public API settings: {}", e);
+            return;
+        }
+    };
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind public API port {}: {}", port, e);
+            return;
+        }
+    };
+    info!("Serving the read-only public API on port {}", port);
+    let app = Router::new().nest("/public/v1", router()).with_state(app_state);
+    tokio::spawn(async move {
+        let shutdown = async move {
+            let _ = shutdown_rx.changed().await;
+        };
+        if let Err(e) = axum::serve(listener, app).with_graceful_shutdown(shutdown).await {
+            error!("Public API listener failed: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dragonfly_common::models::{BmcCredentials, BmcType, DiskInfo};
+
+    fn machine() -> Machine {
+        Machine {
+            hostname: Some("web01".to_string()),
+            disks: vec![DiskInfo { device: "/dev/sda".to_string(), size_bytes: 1 << 40, model: Some("Samsung".to_string()), calculated_size: None }],
+            bmc_credentials: Some(BmcCredentials {
+                address: "10.0.1.5".to_string(),
+                username: "root".to_string(),
+                password: Some("calvin".to_string()),
+                bmc_type: BmcType::IPMI,
+            }),
+            installation_step: Some("Writing image".to_string()),
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    #[test]
+    fn test_public_view() {
+        let machine = machine();
+        let view = public_view(&machine, &["rack-a".to_string()], &[]);
+        assert_eq!(view["hostname"], "web01");
+        assert_eq!(view["tags"], json!(["rack-a"]));
+        assert_eq!(view["disks"][0]["model"], "Samsung");
+        // Secrets and fields not on the list never appear
+        assert!(view.get("bmc_credentials").is_none());
+        assert!(view.get("installation_step").is_none());
+        assert!(view.get("tinkerbell_synced").is_none());
+
+        let view = public_view(&machine, &[], &["ip_address".to_string(), "disks.model".to_string()]);
+        assert!(view.get("ip_address").is_none());
+        assert!(view["disks"][0].get("model").is_none());
+        assert_eq!(view["disks"][0]["device"], "/dev/sda");
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        let settings = |port: Option<u16>, fields: &[&str]| PublicApiSettings {
+            enabled: true,
+            port,
+            redacted_fields: fields.iter().map(|f| f.to_string()).collect(),
+        };
+        assert!(settings(Some(3001), &["ip_address", "disks.model"]).validate(3000).is_ok());
+        assert!(settings(Some(3000), &[]).validate(3000).is_err());
+        assert!(settings(None, &["bmc_credentials"]).validate(3000).is_err());
+        assert!(settings(None, &["disks."]).validate(3000).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const WINDOW_ENV_VAR: &str = "DRAGONFLY_READ_AHEAD_MB";
+const CACHE_ENV_VAR: &str = "DRAGONFLY_READ_AHEAD_CACHE_MB";
+const DEFAULT_WINDOW_MB: u64 = 8;
+const DEFAULT_CACHE_MB: u64 = 256;
+
+/// Ranges closer together than this are merged into one part; the extra bytes cost
+/// less than another part header and another seek.
+pub const COALESCE_GAP: u64 = 64 * 1024;
+
+// Artifacts are opaque blobs to range clients, so every part is labelled the same
+const PART_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// How a ranged response is laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeLayout {
+    /// One range; the value is the Content-Range header
+    Single(String),
+    /// Several ranges as multipart/byteranges
+    Multipart { boundary: String },
+}
+
+/// Parse a Range header into inclusive byte ranges, in request order. Ends past the
+/// file are clamped; returns None if the header is malformed or nothing is satisfiable.
+pub fn parse_ranges(header: &str, total_size: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    if total_size == 0 {
+        return None;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let (start, end) = spec.trim().split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        let range = if start.is_empty() {
+            // Suffix range: the last N bytes
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 {
+                continue;
+            }
+            (total_size.saturating_sub(suffix), total_size - 1)
+        } else {
+            let start: u64 = start.parse().ok()?;
+            let end = if end.is_empty() { total_size - 1 } else { end.parse::<u64>().ok()?.min(total_size - 1) };
+            if start > end {
+                if start >= total_size {
+                    // Unsatisfiable on its own; other ranges may still be fine
+                    continue;
+                }
+                return None;
+            }
+            (start, end)
+        };
+        ranges.push(range);
+    }
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+/// Sort ranges and merge any that overlap or are within `gap` bytes of each other.
+pub fn coalesce(mut ranges: Vec<(u64, u64)>, gap: u64) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(gap).saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The header preceding one part of a multipart/byteranges body.
+pub fn part_header(boundary: &str, start: u64, end: u64, total_size: u64) -> String {
+    format!(
+        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+        boundary, PART_CONTENT_TYPE, start, end, total_size
+    )
+}
+
+pub fn multipart_trailer(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
+
+/// Exact length of a multipart/byteranges body, so it can be sent with a Content-Length.
+pub fn multipart_length(boundary: &str, ranges: &[(u64, u64)], total_size: u64) -> u64 {
+    ranges.iter()
+        .map(|(start, end)| part_header(boundary, *start, *end, total_size).len() as u64 + end - start + 1)
+        .sum::<u64>()
+        + multipart_trailer(boundary).len() as u64
+}
+
+fn mb_from_env(var: &str, default: u64) -> u64 {
+    crate::config::var(var).and_then(|v| v.parse().ok()).unwrap_or(default) * 1024 * 1024
+}
+
+// A block of a file read ahead of the requests for it
+struct Window {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    start: u64,
+    data: Bytes,
+    last_used: u64,
+}
+
+impl Window {
+    fn covers(&self, path: &Path, len: u64, modified: Option<SystemTime>, start: u64, end: u64) -> bool {
+        self.path == path
+            && self.len == len
+            && self.modified == modified
+            && start >= self.start
+            && end < self.start + self.data.len() as u64
+    }
+}
+
+// Small ranged GETs usually walk a file front to back, so each disk read fetches a whole
+// window and later ranges are sliced out of memory
+struct ReadAheadCache {
+    windows: Vec<Window>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl ReadAheadCache {
+    fn lookup(&mut self, path: &Path, len: u64, modified: Option<SystemTime>, start: u64, end: u64) -> Option<Bytes> {
+        self.tick += 1;
+        let tick = self.tick;
+        let window = self.windows.iter_mut().find(|w| w.covers(path, len, modified, start, end))?;
+        window.last_used = tick;
+        let offset = (start - window.start) as usize;
+        Some(window.data.slice(offset..offset + (end - start + 1) as usize))
+    }
+
+    fn insert(&mut self, window: Window) {
+        // Drop stale windows of the same file first, then least recently used
+        self.windows.retain(|w| w.path != window.path || (w.len == window.len && w.modified == window.modified));
+        while self.bytes_used() + window.data.len() > self.capacity && !self.windows.is_empty() {
+            let oldest = self.windows.iter().enumerate().min_by_key(|(_, w)| w.last_used).map(|(i, _)| i).unwrap_or(0);
+            self.windows.swap_remove(oldest);
+        }
+        self.windows.push(window);
+    }
+
+    fn bytes_used(&self) -> usize {
+        self.windows.iter().map(|w| w.data.len()).sum()
+    }
+}
+
+static CACHE: once_cell::sync::Lazy<Mutex<ReadAheadCache>> = once_cell::sync::Lazy::new(|| {
+    Mutex::new(ReadAheadCache {
+        windows: Vec::new(),
+        capacity: mb_from_env(CACHE_ENV_VAR, DEFAULT_CACHE_MB) as usize,
+        tick: 0,
+    })
+});
+
+async fn read_exact_at(path: &Path, start: u64, len: u64) -> std::io::Result<Bytes> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    let mut buffer = Vec::with_capacity(len as usize);
+    file.take(len).read_to_end(&mut buffer).await?;
+    Ok(Bytes::from(buffer))
+}
+
+/// Read an inclusive byte range, served from (and filling) the read-ahead cache when it fits in a window.
+pub async fn read_range(path: &Path, start: u64, end: u64) -> std::io::Result<Bytes> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let (len, modified) = (metadata.len(), metadata.modified().ok());
+    let window_size = mb_from_env(WINDOW_ENV_VAR, DEFAULT_WINDOW_MB);
+    let wanted = end - start + 1;
+
+    let cacheable = {
+        let mut cache = CACHE.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(data) = cache.lookup(path, len, modified, start, end) {
+            return Ok(data);
+        }
+        window_size > 0 && wanted < window_size && (window_size as usize) <= cache.capacity
+    };
+    if !cacheable {
+        return read_exact_at(path, start, wanted).await;
+    }
+
+    let window_len = window_size.min(len.saturating_sub(start));
+    let data = read_exact_at(path, start, window_len).await?;
+    if (data.len() as u64) < wanted {
+        // File shrank under us; hand back what's there
+        return Ok(data);
+    }
+    let slice = data.slice(..wanted as usize);
+    let mut cache = CACHE.lock().unwrap_or_else(|p| p.into_inner());
+    cache.tick += 1;
+    let last_used = cache.tick;
+    cache.insert(Window { path: path.to_path_buf(), len, modified, start, data, last_used });
+    Ok(slice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_ranges("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(parse_ranges("bytes=900-", 1000), Some(vec![(900, 999)]));
+        assert_eq!(parse_ranges("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(parse_ranges("bytes=990-2000", 1000), Some(vec![(990, 999)]));
+        assert_eq!(parse_ranges("bytes=0-9, 20-29", 1000), Some(vec![(0, 9), (20, 29)]));
+        assert_eq!(parse_ranges("bytes=0-9,5000-6000", 1000), Some(vec![(0, 9)]));
+        assert_eq!(parse_ranges("bytes=5000-6000", 1000), None);
+        assert_eq!(parse_ranges("bytes=50-10", 1000), None);
+        assert_eq!(parse_ranges("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        assert_eq!(coalesce(vec![(20, 29), (0, 9), (5, 12)], 0), vec![(0, 12), (20, 29)]);
+        assert_eq!(coalesce(vec![(0, 9), (10, 19)], 0), vec![(0, 19)]);
+        assert_eq!(coalesce(vec![(0, 9), (20, 29)], 10), vec![(0, 29)]);
+        assert_eq!(coalesce(vec![(0, 9), (100, 109)], 10), vec![(0, 9), (100, 109)]);
+    }
+
+    #[test]
+    fn test_multipart_length_matches_body() {
+        let ranges = [(0, 9), (100, 149)];
+        let mut body = String::new();
+        for (start, end) in ranges {
+            body.push_str(&part_header("b", start, end, 1000));
+            body.push_str(&"x".repeat((end - start + 1) as usize));
+        }
+        body.push_str(&multipart_trailer("b"));
+        assert_eq!(multipart_length("b", &ranges, 1000), body.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_read_range_served_from_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image");
+        let data: Vec<u8> = (0..=255u8).cycle().take(100_000).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let first = read_range(&path, 0, 999).await.unwrap();
+        assert_eq!(&first[..], &data[..1000]);
+        let next = read_range(&path, 1000, 4999).await.unwrap();
+        assert_eq!(&next[..], &data[1000..5000]);
+        let tail = read_range(&path, 99_990, 99_999).await.unwrap();
+        assert_eq!(&tail[..], &data[99_990..]);
+    }
+}
@@ -0,0 +1,231 @@
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+// Opt-in: agent payloads are only recorded when this points at a directory
+const RECORD_DIR_ENV_VAR: &str = "DRAGONFLY_RECORD_PAYLOADS_DIR";
+const RECORD_FILE_NAME: &str = "agent-payloads.jsonl";
+
+// Agent payloads are a few KB; anything this big isn't one
+const MAX_RECORDED_BODY: usize = 1024 * 1024;
+
+const REDACTED: &str = "[REDACTED]";
+
+// Field names whose values never reach the recording
+const SECRET_KEY_PATTERNS: &[&str] = &["password", "secret", "token", "api_key", "apikey", "authorization", "private_key", "credential"];
+
+/// Which agent call a recorded payload came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadKind {
+    Register,
+    Update,
+    Status,
+    OsInstalled,
+    Fingerprint,
+    Patches,
+    AgentEnvironment,
+}
+
+/// One line of the recording file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedPayload {
+    pub recorded_at: DateTime<Utc>,
+    pub kind: PayloadKind,
+    pub method: String,
+    /// Path under /api, e.g. `/machines/{id}/status`
+    pub path: String,
+    /// Machine the call targeted (None for registrations)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<Uuid>,
+    /// Machine ID the server handed out, for registrations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assigned_machine_id: Option<Uuid>,
+    pub status: u16,
+    /// Request body with secrets redacted; non-JSON bodies are kept as text
+    pub body: Value,
+}
+
+static RECORD_DIR: Lazy<Option<PathBuf>> = Lazy::new(|| {
+    crate::config::var(RECORD_DIR_ENV_VAR).filter(|d| !d.is_empty()).map(PathBuf::from)
+});
+
+// Serializes appends so concurrent agents don't interleave lines
+static WRITE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// Strip the /api or /api/v1 prefix, so recordings replay against either
+pub fn api_relative(path: &str) -> &str {
+    path.strip_prefix("/api/v1").or_else(|| path.strip_prefix("/api")).unwrap_or(path)
+}
+
+/// Agent calls worth recording, by method and path under /api.
+pub fn classify(method: &Method, path: &str) -> Option<(PayloadKind, Option<Uuid>)> {
+    let path = api_relative(path);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["machines"] if method == Method::POST => Some((PayloadKind::Register, None)),
+        ["machines", id, rest @ ..] if method == Method::PUT => {
+            let id = Uuid::parse_str(id).ok()?;
+            let kind = match rest {
+                [] => PayloadKind::Update,
+                ["status"] => PayloadKind::Status,
+                ["os-installed"] => PayloadKind::OsInstalled,
+                ["fingerprint"] => PayloadKind::Fingerprint,
+                ["patches"] => PayloadKind::Patches,
+                ["agent-environment"] => PayloadKind::AgentEnvironment,
+                _ => return None,
+            };
+            Some((kind, Some(id)))
+        },
+        _ => None,
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_KEY_PATTERNS.iter().any(|pattern| key.contains(pattern))
+}
+
+/// Replace the values of secret-looking fields, at any depth.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret_key(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        },
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {},
+    }
+}
+
+fn body_value(bytes: &[u8]) -> Value {
+    let mut value = serde_json::from_slice(bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()));
+    redact(&mut value);
+    value
+}
+
+async fn append(dir: &Path, payload: &RecordedPayload) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(payload)?;
+    line.push('\n');
+    let _guard = WRITE_LOCK.lock().await;
+    tokio::fs::create_dir_all(dir).await?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(RECORD_FILE_NAME))
+        .await?;
+    file.write_all(line.as_bytes()).await
+}
+
+/// Middleware that records raw agent registration and update payloads to
+/// $DRAGONFLY_RECORD_PAYLOADS_DIR/agent-payloads.jsonl, for `dragonfly replay`.
+pub async fn record_agent_payloads(request: Request, next: Next) -> Response {
+    let Some(dir) = RECORD_DIR.as_ref() else {
+        return next.run(request).await;
+    };
+    let path = request.extensions()
+        .get::<axum::extract::OriginalUri>()
+        .map(|uri| crate::base_path::strip(uri.path()).to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let Some((kind, machine_id)) = classify(request.method(), &path) else {
+        return next.run(request).await;
+    };
+
+    let method = request.method().to_string();
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, MAX_RECORDED_BODY).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read {:?} payload for recording: {}", kind, e);
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+        }
+    };
+    let body = body_value(&bytes);
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    let status = response.status();
+
+    // The new machine's ID lets replay map later updates onto the machine it creates
+    let (response, assigned_machine_id) = if kind == PayloadKind::Register && status.is_success() {
+        let (parts, body) = response.into_parts();
+        let bytes = to_bytes(body, MAX_RECORDED_BODY).await.unwrap_or_default();
+        let assigned = serde_json::from_slice::<Value>(&bytes).ok()
+            .and_then(|v| v.get("machine_id").and_then(|id| id.as_str()).and_then(|id| Uuid::parse_str(id).ok()));
+        (Response::from_parts(parts, Body::from(bytes)), assigned)
+    } else {
+        (response, None)
+    };
+
+    let payload = RecordedPayload {
+        recorded_at: Utc::now(),
+        kind,
+        method,
+        path: api_relative(&path).to_string(),
+        machine_id,
+        assigned_machine_id,
+        status: status.as_u16(),
+        body,
+    };
+    match append(dir, &payload).await {
+        Ok(()) => debug!("Recorded {:?} payload ({})", kind, status),
+        Err(e) => warn!("Failed to record {:?} payload to {}: {}", kind, dir.display(), e),
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_classify() {
+        let id = Uuid::new_v4();
+        assert_eq!(classify(&Method::POST, "/api/machines"), Some((PayloadKind::Register, None)));
+        assert_eq!(classify(&Method::PUT, &format!("/machines/{}", id)), Some((PayloadKind::Update, Some(id))));
+        assert_eq!(classify(&Method::PUT, &format!("/api/machines/{}/status", id)), Some((PayloadKind::Status, Some(id))));
+        assert_eq!(classify(&Method::PUT, &format!("/api/v1/machines/{}/status", id)), Some((PayloadKind::Status, Some(id))));
+        assert_eq!(classify(&Method::PUT, &format!("/api/machines/{}/fingerprint", id)), Some((PayloadKind::Fingerprint, Some(id))));
+        assert_eq!(classify(&Method::PUT, &format!("/api/v1/machines/{}/patches", id)), Some((PayloadKind::Patches, Some(id))));
+        assert_eq!(classify(&Method::PUT, &format!("/api/machines/{}/agent-environment", id)), Some((PayloadKind::AgentEnvironment, Some(id))));
+        assert_eq!(classify(&Method::GET, "/api/machines"), None);
+        assert_eq!(classify(&Method::PUT, &format!("/api/machines/{}/tags", id)), None);
+        assert_eq!(classify(&Method::PUT, "/api/machines/not-a-uuid"), None);
+    }
+
+    #[test]
+    fn test_redact_nested_secrets() {
+        let mut value = json!({
+            "mac_address": "aa:bb:cc:dd:ee:ff",
+            "proxmox": { "api_token": "abc", "Password": "hunter2" },
+            "disks": [{ "device": "/dev/sda", "secret_id": 7 }],
+        });
+        redact(&mut value);
+        assert_eq!(value["mac_address"], "aa:bb:cc:dd:ee:ff");
+        assert_eq!(value["proxmox"]["api_token"], REDACTED);
+        assert_eq!(value["proxmox"]["Password"], REDACTED);
+        assert_eq!(value["disks"][0]["secret_id"], REDACTED);
+        assert_eq!(value["disks"][0]["device"], "/dev/sda");
+    }
+
+    #[test]
+    fn test_non_json_body_kept_as_text() {
+        assert_eq!(body_value(b"not json"), Value::String("not json".to_string()));
+    }
+}
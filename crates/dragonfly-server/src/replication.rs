@@ -0,0 +1,607 @@
+// Multi-site artifact replication.
+//
+// One site is the primary: it publishes a manifest of its artifacts (path, size, SHA-256) at
+// /api/replication/manifest. Every other site is a secondary that pulls the manifest on a
+// schedule and downloads whatever is new or changed from the primary's /ipxe/ paths, checking
+// each file's checksum before moving it into place. Secondaries never delete anything, so
+// artifacts a site builds or uploads for itself are left alone.
+//
+// The sites share a replication key. The primary signs the manifest with it (the same
+// timestamped HMAC scheme agents use, see dragonfly_common::signing) and secondaries reject
+// anything unsigned, stale or older than what they've already applied. After each pull a
+// secondary reports its status back to the primary, signed the same way, so the primary's
+// admin API shows how every site is doing.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use dragonfly_common::signing::{self, SIGNATURE_HEADER};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+
+/// Path of the manifest, relative to the API base, as signed.
+pub const MANIFEST_PATH: &str = "/replication/manifest";
+
+const KEY_LENGTH: usize = 48;
+const DEFAULT_INTERVAL_MINUTES: u32 = 15;
+const MAX_INTERVAL_MINUTES: u32 = 24 * 60;
+
+// A manifest older than this was replayed or held back, not freshly served
+const MAX_MANIFEST_AGE_MINUTES: i64 = 60;
+
+// How often the task checks whether a pull is due
+const CHECK_INTERVAL_SECS: u64 = 60;
+
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Off,
+    /// Publishes the manifest and collects site reports
+    Primary,
+    /// Pulls artifacts from the primary
+    Secondary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationSettings {
+    pub role: Role,
+    /// This site's name, as it appears in the primary's site list
+    pub site: Option<String>,
+    /// Secondaries: the primary's base URL
+    pub primary_url: Option<String>,
+    /// Secondaries: minutes between pulls
+    pub interval_minutes: u32,
+    /// Primary: path prefixes to publish, e.g. "custom-images/"; empty publishes everything
+    pub include: Vec<String>,
+    /// Shared by every site; stored encrypted and never returned by the API
+    #[serde(skip)]
+    pub key: Option<String>,
+}
+
+impl Default for ReplicationSettings {
+    fn default() -> Self {
+        Self {
+            role: Role::Off,
+            site: None,
+            primary_url: None,
+            interval_minutes: DEFAULT_INTERVAL_MINUTES,
+            include: Vec::new(),
+            key: None,
+        }
+    }
+}
+
+fn valid_site_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl ReplicationSettings {
+    /// Check the settings make sense for their role, tidying names and URLs.
+    pub fn normalize(mut self) -> Result<Self> {
+        self.site = self.site.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        self.primary_url = self.primary_url.map(|u| u.trim().trim_end_matches('/').to_string()).filter(|u| !u.is_empty());
+        if let Some(site) = &self.site {
+            if !valid_site_name(site) {
+                bail!("Site name '{}' may only contain letters, digits, '-' and '_'", site);
+            }
+        }
+        if !(1..=MAX_INTERVAL_MINUTES).contains(&self.interval_minutes) {
+            bail!("The pull interval must be between 1 and {} minutes", MAX_INTERVAL_MINUTES);
+        }
+        self.include = self.include.iter()
+            .map(|prefix| crate::artifact_upload::validate_path(prefix).map_err(|e| anyhow!("{}", e)))
+            .collect::<Result<_>>()?;
+
+        if self.role != Role::Off && self.key.is_none() {
+            bail!("Generate a replication key on the primary (and enter it on secondaries) before turning replication on");
+        }
+        if self.role == Role::Secondary {
+            if self.site.is_none() {
+                bail!("A secondary needs a site name to report under");
+            }
+            let Some(primary_url) = &self.primary_url else {
+                bail!("A secondary needs the primary's URL");
+            };
+            let url = url::Url::parse(primary_url).with_context(|| format!("'{}' is not a URL", primary_url))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                bail!("The primary's URL must be http or https");
+            }
+        }
+        Ok(self)
+    }
+
+    fn publishes(&self, path: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|prefix| path == prefix || path.starts_with(&format!("{}/", prefix)))
+    }
+}
+
+/// Body of a settings update: the settings, plus the shared key when it's being set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SettingsUpdate {
+    #[serde(flatten)]
+    pub settings: ReplicationSettings,
+    pub key: Option<String>,
+}
+
+/// What the admin API shows of the settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsView {
+    #[serde(flatten)]
+    pub settings: ReplicationSettings,
+    pub key_configured: bool,
+}
+
+impl From<&ReplicationSettings> for SettingsView {
+    fn from(settings: &ReplicationSettings) -> Self {
+        Self { settings: settings.clone(), key_configured: settings.key.is_some() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The primary's site name
+    pub site: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub artifacts: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub path: String,
+    pub error: String,
+}
+
+/// How a secondary's last pull went; kept by the secondary and reported to the primary.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteStatus {
+    pub site: String,
+    pub interval_minutes: u32,
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// The last pull that left every artifact in sync
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Generation time of the last manifest applied
+    pub manifest_generated_at: Option<DateTime<Utc>>,
+    /// Artifacts in that manifest
+    pub artifacts: usize,
+    pub in_sync: usize,
+    /// Artifacts downloaded by the last pull
+    pub fetched: usize,
+    pub bytes_fetched: u64,
+    pub failures: Vec<Failure>,
+    /// Why the last pull didn't get as far as downloading, if it didn't
+    pub last_error: Option<String>,
+}
+
+/// A site's status as the admin API shows it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SiteReport {
+    #[serde(flatten)]
+    pub status: SiteStatus,
+    pub reported_at: DateTime<Utc>,
+    /// No report for three pull intervals
+    pub stale: bool,
+}
+
+impl SiteReport {
+    pub fn new(status: SiteStatus, reported_at: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        let stale = now - reported_at > Duration::minutes(3 * status.interval_minutes.max(1) as i64);
+        Self { status, reported_at, stale }
+    }
+}
+
+pub async fn settings() -> Result<ReplicationSettings> {
+    crate::db::get_replication_settings().await
+}
+
+/// Save new settings. A key given here replaces the stored one; otherwise it's kept.
+pub async fn update(mut settings: ReplicationSettings, key: Option<String>) -> Result<ReplicationSettings> {
+    settings.key = match key.map(|k| k.trim().to_string()).filter(|k| !k.is_empty()) {
+        Some(key) => Some(key),
+        None => self::settings().await?.key,
+    };
+    let settings = settings.normalize()?;
+    crate::db::save_replication_settings(&settings).await?;
+    Ok(settings)
+}
+
+/// Generate a new replication key; every secondary needs to be given it.
+pub async fn rotate_key() -> Result<String> {
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(KEY_LENGTH)
+        .map(char::from)
+        .collect();
+    let mut settings = settings().await?;
+    settings.key = Some(key.clone());
+    crate::db::save_replication_settings(&settings).await?;
+    Ok(key)
+}
+
+// A file's SHA-256, reused while its size and modification time are unchanged
+struct CachedHash {
+    size: u64,
+    modified: SystemTime,
+    sha256: String,
+}
+
+static HASHES: Lazy<Mutex<HashMap<PathBuf, CachedHash>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+async fn cached_hash(path: &Path, size: u64, modified: SystemTime) -> Result<String> {
+    if let Some(cached) = HASHES.lock().unwrap().get(path).filter(|c| c.size == size && c.modified == modified) {
+        return Ok(cached.sha256.clone());
+    }
+    let sha256 = crate::custom_images::hash_file(path).await?;
+    HASHES.lock().unwrap().insert(path.to_path_buf(), CachedHash { size, modified, sha256: sha256.clone() });
+    Ok(sha256)
+}
+
+// Every regular file under the artifact directory, skipping hidden names (upload staging)
+// and partial downloads
+fn list_artifacts(base: &Path) -> Vec<(String, u64, SystemTime)> {
+    let mut found = Vec::new();
+    let mut dirs = vec![base.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || name.ends_with(".partial") {
+                continue;
+            }
+            let Ok(file_type) = entry.file_type() else { continue };
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                let Ok(metadata) = entry.metadata() else { continue };
+                let Ok(relative) = entry.path().strip_prefix(base).map(|p| p.to_string_lossy().to_string()) else { continue };
+                found.push((relative, metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// The manifest this primary publishes.
+pub async fn build_manifest(settings: &ReplicationSettings) -> Result<Manifest> {
+    let base = crate::api::artifact_base_dir();
+    let files = {
+        let base = base.clone();
+        tokio::task::spawn_blocking(move || list_artifacts(&base)).await?
+    };
+    let mut artifacts = Vec::new();
+    for (path, size, modified) in files.into_iter().filter(|(path, _, _)| settings.publishes(path)) {
+        // Names the upload API wouldn't accept can't be fetched safely either
+        if crate::artifact_upload::validate_path(&path).is_err() {
+            continue;
+        }
+        let sha256 = cached_hash(&base.join(&path), size, modified).await?;
+        artifacts.push(ManifestEntry { path, size, sha256 });
+    }
+    Ok(Manifest { site: settings.site.clone(), generated_at: Utc::now(), artifacts })
+}
+
+/// Sign a manifest body for the response header.
+pub fn sign_manifest(key: &str, body: &[u8], now: i64) -> String {
+    signing::sign(key, now, "GET", MANIFEST_PATH, body)
+}
+
+/// Check a manifest's signature and age, and that it's newer than the last one applied.
+pub fn verify_manifest(key: &str, header: Option<&str>, body: &[u8], last_applied: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Result<Manifest> {
+    let header = header.ok_or_else(|| anyhow!("The primary's manifest isn't signed"))?;
+    signing::verify(key, header, "GET", MANIFEST_PATH, body, now.timestamp())
+        .map_err(|e| anyhow!("Manifest rejected: {} (do both sites have the same replication key?)", e))?;
+    let manifest: Manifest = serde_json::from_slice(body).context("Manifest is not valid JSON")?;
+    if now - manifest.generated_at > Duration::minutes(MAX_MANIFEST_AGE_MINUTES) {
+        bail!("Manifest was generated at {}, too long ago", manifest.generated_at);
+    }
+    if let Some(last_applied) = last_applied.filter(|last| manifest.generated_at < *last) {
+        bail!("Manifest from {} is older than the one already applied ({})", manifest.generated_at, last_applied);
+    }
+    Ok(manifest)
+}
+
+/// Whether an artifact needs downloading, given the local copy's size and hash (if present).
+pub fn needs_fetch(entry: &ManifestEntry, local: Option<(u64, &str)>) -> bool {
+    !matches!(local, Some((size, sha256)) if size == entry.size && sha256 == entry.sha256)
+}
+
+async fn local_copy(path: &Path) -> Option<(u64, String)> {
+    let metadata = tokio::fs::metadata(path).await.ok().filter(|m| m.is_file())?;
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    cached_hash(path, metadata.len(), modified).await.ok().map(|hash| (metadata.len(), hash))
+}
+
+// Download one artifact from the primary, verifying it before it replaces anything
+async fn fetch(client: &reqwest::Client, primary_url: &str, entry: &ManifestEntry) -> Result<u64> {
+    let dest = crate::api::artifact_base_dir().join(&entry.path);
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // foo.img.partial, not foo.partial, so foo.img and foo.iso don't share one
+    let mut partial_name = dest.file_name().unwrap_or_default().to_os_string();
+    partial_name.push(".partial");
+    let partial = dest.with_file_name(partial_name);
+
+    // Artifacts can take a while; only the connection gets the usual timeout
+    let response = client.get(format!("{}/ipxe/{}", primary_url, entry.path))
+        .timeout(std::time::Duration::from_secs(6 * 60 * 60))
+        .send().await?;
+    if !response.status().is_success() {
+        bail!("Download returned HTTP {}", response.status());
+    }
+
+    let mut file = tokio::fs::File::create(&partial).await?;
+    let mut hasher = Sha256::new();
+    let mut written = 0u64;
+    let mut stream = response.bytes_stream();
+    let result: Result<()> = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+        Ok(())
+    }.await;
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(e);
+    }
+
+    let digest = format!("{:x}", hasher.finalize());
+    if written != entry.size || digest != entry.sha256 {
+        let _ = tokio::fs::remove_file(&partial).await;
+        bail!("Checksum mismatch: expected {} ({} bytes), got {} ({} bytes)", entry.sha256, entry.size, digest, written);
+    }
+    tokio::fs::rename(&partial, &dest).await?;
+    Ok(written)
+}
+
+/// Pull the primary's manifest and download what's new or changed. Always returns the status,
+/// which is saved locally and reported to the primary.
+pub async fn pull(settings: &ReplicationSettings) -> SiteStatus {
+    let site = settings.site.clone().unwrap_or_default();
+    let previous = crate::db::get_replication_site(&site).await.ok().flatten().map(|(status, _)| status);
+    let mut status = SiteStatus {
+        site: site.clone(),
+        interval_minutes: settings.interval_minutes,
+        last_run_at: Some(Utc::now()),
+        last_success_at: previous.as_ref().and_then(|p| p.last_success_at),
+        manifest_generated_at: previous.as_ref().and_then(|p| p.manifest_generated_at),
+        ..Default::default()
+    };
+
+    let result: Result<()> = async {
+        let key = settings.key.as_deref().ok_or_else(|| anyhow!("No replication key is configured"))?;
+        let primary_url = settings.primary_url.as_deref().ok_or_else(|| anyhow!("No primary URL is configured"))?;
+        let client = reqwest::Client::builder().connect_timeout(REQUEST_TIMEOUT).build()?;
+
+        let response = client.get(format!("{}/api{}", primary_url, MANIFEST_PATH)).timeout(REQUEST_TIMEOUT).send().await
+            .context("Failed to reach the primary")?;
+        if !response.status().is_success() {
+            bail!("The primary answered HTTP {} for its manifest (is it set up as the primary?)", response.status());
+        }
+        let header = response.headers().get(SIGNATURE_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+        let body = response.bytes().await?;
+        let manifest = verify_manifest(key, header.as_deref(), &body, status.manifest_generated_at, Utc::now())?;
+
+        status.artifacts = manifest.artifacts.len();
+        let base = crate::api::artifact_base_dir();
+        for entry in &manifest.artifacts {
+            if crate::artifact_upload::validate_path(&entry.path).is_err() {
+                status.failures.push(Failure { path: entry.path.clone(), error: "Unsafe artifact path".to_string() });
+                continue;
+            }
+            let local = local_copy(&base.join(&entry.path)).await;
+            if !needs_fetch(entry, local.as_ref().map(|(size, hash)| (*size, hash.as_str()))) {
+                status.in_sync += 1;
+                continue;
+            }
+            match fetch(&client, primary_url, entry).await {
+                Ok(bytes) => {
+                    info!("Replicated artifact {} ({} bytes) from the primary", entry.path, bytes);
+                    status.fetched += 1;
+                    status.bytes_fetched += bytes;
+                    status.in_sync += 1;
+                }
+                Err(e) => {
+                    warn!("Failed to replicate artifact {}: {}", entry.path, e);
+                    status.failures.push(Failure { path: entry.path.clone(), error: e.to_string() });
+                }
+            }
+        }
+        status.manifest_generated_at = Some(manifest.generated_at);
+        Ok(())
+    }.await;
+
+    match result {
+        Ok(()) if status.failures.is_empty() => status.last_success_at = status.last_run_at,
+        Ok(()) => {}
+        Err(e) => {
+            warn!("Artifact replication pull failed: {:#}", e);
+            status.last_error = Some(format!("{:#}", e));
+        }
+    }
+    status
+}
+
+// Tell the primary how this site is doing
+async fn report(settings: &ReplicationSettings, status: &SiteStatus) -> Result<()> {
+    let (Some(key), Some(primary_url)) = (settings.key.as_deref(), settings.primary_url.as_deref()) else {
+        return Ok(());
+    };
+    let path = format!("/replication/sites/{}/status", status.site);
+    let body = serde_json::to_vec(status)?;
+    let signature = signing::sign(key, Utc::now().timestamp(), "POST", &path, &body);
+    let response = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?
+        .post(format!("{}/api{}", primary_url, path))
+        .header(SIGNATURE_HEADER, signature)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send().await?;
+    if !response.status().is_success() {
+        bail!("The primary answered HTTP {}", response.status());
+    }
+    Ok(())
+}
+
+/// Pull now, save the outcome and report it to the primary.
+pub async fn sync_now() -> Result<SiteStatus> {
+    let settings = settings().await?;
+    if settings.role != Role::Secondary {
+        bail!("This site isn't a replication secondary");
+    }
+    let status = pull(&settings).await;
+    crate::db::save_replication_site(&status).await?;
+    if let Err(e) = report(&settings, &status).await {
+        warn!("Failed to report replication status to the primary: {}", e);
+    }
+    Ok(status)
+}
+
+/// Accept a secondary's signed status report (primary only).
+pub async fn receive_report(site: &str, header: Option<&str>, body: &[u8]) -> Result<SiteStatus> {
+    let settings = settings().await?;
+    let key = match (settings.role, settings.key.as_deref()) {
+        (Role::Primary, Some(key)) => key,
+        _ => bail!("This site isn't a replication primary"),
+    };
+    let path = format!("/replication/sites/{}/status", site);
+    let header = header.ok_or_else(|| anyhow!("Status reports must be signed"))?;
+    signing::verify(key, header, "POST", &path, body, Utc::now().timestamp())
+        .map_err(|e| anyhow!("Status report rejected: {}", e))?;
+    let status: SiteStatus = serde_json::from_slice(body)?;
+    if status.site != site || !valid_site_name(site) {
+        bail!("Status report is for site '{}', not '{}'", status.site, site);
+    }
+    crate::db::save_replication_site(&status).await?;
+    Ok(status)
+}
+
+/// Every site's last known status, this one included when it's a secondary.
+pub async fn sites() -> Result<Vec<SiteReport>> {
+    let now = Utc::now();
+    Ok(crate::db::list_replication_sites().await?
+        .into_iter()
+        .map(|(status, reported_at)| SiteReport::new(status, reported_at, now))
+        .collect())
+}
+
+/// On secondaries, pull from the primary every interval (leader only).
+pub async fn start_replication_task(mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(CHECK_INTERVAL_SECS));
+        let mut last_pull: Option<DateTime<Utc>> = None;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
+                    let settings = match settings().await {
+                        Ok(settings) => settings,
+                        Err(e) => {
+                            error!("Failed to load replication settings: {}", e);
+                            continue;
+                        }
+                    };
+                    if settings.role != Role::Secondary {
+                        continue;
+                    }
+                    let due = last_pull.is_none_or(|at| Utc::now() - at >= Duration::minutes(settings.interval_minutes as i64));
+                    if due {
+                        last_pull = Some(Utc::now());
+                        if let Err(e) = sync_now().await {
+                            error!("Artifact replication failed: {}", e);
+                        }
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping artifact replication task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, size: u64, sha256: &str) -> ManifestEntry {
+        ManifestEntry { path: path.to_string(), size, sha256: sha256.to_string() }
+    }
+
+    #[test]
+    fn test_verify_manifest() {
+        let now = Utc::now();
+        let manifest = Manifest { site: Some("syd".to_string()), generated_at: now, artifacts: vec![entry("hookos/vmlinuz", 10, "ab")] };
+        let body = serde_json::to_vec(&manifest).unwrap();
+        let header = sign_manifest("key", &body, now.timestamp());
+
+        let verified = verify_manifest("key", Some(&header), &body, None, now).unwrap();
+        assert_eq!(verified.artifacts, manifest.artifacts);
+
+        assert!(verify_manifest("other-key", Some(&header), &body, None, now).is_err());
+        assert!(verify_manifest("key", None, &body, None, now).is_err());
+        let mut tampered = body.clone();
+        tampered[10] ^= 1;
+        assert!(verify_manifest("key", Some(&header), &tampered, None, now).is_err());
+        // Older than what's already been applied
+        assert!(verify_manifest("key", Some(&header), &body, Some(now + Duration::seconds(1)), now).is_err());
+    }
+
+    #[test]
+    fn test_needs_fetch() {
+        let e = entry("custom-images/lab/1.0/image.raw.gz", 100, "abc");
+        assert!(needs_fetch(&e, None));
+        assert!(needs_fetch(&e, Some((99, "abc"))));
+        assert!(needs_fetch(&e, Some((100, "abd"))));
+        assert!(!needs_fetch(&e, Some((100, "abc"))));
+    }
+
+    #[test]
+    fn test_normalize_settings() {
+        let secondary = ReplicationSettings {
+            role: Role::Secondary,
+            site: Some(" per ".to_string()),
+            primary_url: Some("https://dragonfly.syd.example.com/".to_string()),
+            key: Some("key".to_string()),
+            ..Default::default()
+        };
+        let normalized = secondary.clone().normalize().unwrap();
+        assert_eq!(normalized.site.as_deref(), Some("per"));
+        assert_eq!(normalized.primary_url.as_deref(), Some("https://dragonfly.syd.example.com"));
+
+        assert!(ReplicationSettings { key: None, ..secondary.clone() }.normalize().is_err());
+        assert!(ReplicationSettings { primary_url: None, ..secondary.clone() }.normalize().is_err());
+        assert!(ReplicationSettings { site: Some("per th".to_string()), ..secondary.clone() }.normalize().is_err());
+        assert!(ReplicationSettings { interval_minutes: 0, ..secondary }.normalize().is_err());
+
+        let primary = ReplicationSettings { role: Role::Primary, key: Some("key".to_string()), include: vec!["custom-images/".to_string()], ..Default::default() };
+        let primary = primary.normalize().unwrap();
+        assert!(primary.publishes("custom-images/lab/1.0/image.raw.gz"));
+        assert!(!primary.publishes("hookos/vmlinuz-x86_64"));
+    }
+}
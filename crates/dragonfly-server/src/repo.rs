@@ -0,0 +1,355 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{Machine, MachineStatus, RegisterRequest};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::auth::Settings;
+use crate::db::TemplateTiming;
+use crate::tinkerbell::WorkflowInfo;
+
+/// Machine records.
+#[async_trait]
+pub trait MachineRepo: Send + Sync {
+    async fn get(&self, id: &Uuid) -> Result<Option<Machine>>;
+    async fn get_by_mac(&self, mac_address: &str) -> Result<Option<Machine>>;
+    async fn list(&self) -> Result<Vec<Machine>>;
+    /// Insert a machine, or refresh the one with the same MAC address
+    async fn register(&self, req: &RegisterRequest) -> Result<Uuid>;
+    async fn update(&self, machine: &Machine) -> Result<bool>;
+    async fn update_status(&self, id: &Uuid, status: MachineStatus) -> Result<bool>;
+    async fn assign_os(&self, id: &Uuid, os_choice: &str) -> Result<bool>;
+    /// False if nothing was deleted, including when the deletion is held for approval
+    async fn delete(&self, id: &Uuid) -> Result<bool>;
+}
+
+/// Application settings.
+#[async_trait]
+pub trait SettingsRepo: Send + Sync {
+    async fn get(&self) -> Result<Settings>;
+    async fn save(&self, settings: &Settings) -> Result<()>;
+}
+
+/// Workflow history: completed workflows and per-action timings.
+#[async_trait]
+pub trait EventRepo: Send + Sync {
+    async fn store_completed_workflow(&self, machine_id: &Uuid, info: &WorkflowInfo) -> Result<()>;
+    async fn completed_workflow(&self, machine_id: &Uuid) -> Result<Option<(WorkflowInfo, DateTime<Utc>)>>;
+    async fn save_template_timing(&self, template_name: &str, action_name: &str, durations: &[u64]) -> Result<bool>;
+    async fn template_timings(&self) -> Result<Vec<TemplateTiming>>;
+}
+
+/// The repositories handlers use, carried on AppState.
+#[derive(Clone)]
+pub struct Repositories {
+    pub machines: Arc<dyn MachineRepo>,
+    pub settings: Arc<dyn SettingsRepo>,
+    pub events: Arc<dyn EventRepo>,
+}
+
+impl Repositories {
+    /// Backed by the global SQLite pool
+    pub fn sqlite() -> Self {
+        Self {
+            machines: Arc::new(SqliteRepo),
+            settings: Arc::new(SqliteRepo),
+            events: Arc::new(SqliteRepo),
+        }
+    }
+
+    /// Backed by process memory, for tests
+    pub fn in_memory() -> Self {
+        let repo = Arc::new(MemoryRepo::default());
+        Self {
+            machines: repo.clone(),
+            settings: repo.clone(),
+            events: repo,
+        }
+    }
+}
+
+/// Thin adapter over the free functions in `db`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SqliteRepo;
+
+#[async_trait]
+impl MachineRepo for SqliteRepo {
+    async fn get(&self, id: &Uuid) -> Result<Option<Machine>> {
+        crate::db::get_machine_by_id(id).await
+    }
+
+    async fn get_by_mac(&self, mac_address: &str) -> Result<Option<Machine>> {
+        crate::db::get_machine_by_mac(mac_address).await
+    }
+
+    async fn list(&self) -> Result<Vec<Machine>> {
+        crate::db::get_all_machines().await
+    }
+
+    async fn register(&self, req: &RegisterRequest) -> Result<Uuid> {
+        crate::db::register_machine(req).await
+    }
+
+    async fn update(&self, machine: &Machine) -> Result<bool> {
+        crate::db::update_machine(machine).await
+    }
+
+    async fn update_status(&self, id: &Uuid, status: MachineStatus) -> Result<bool> {
+        crate::db::update_status(id, status).await
+    }
+
+    async fn assign_os(&self, id: &Uuid, os_choice: &str) -> Result<bool> {
+        crate::db::assign_os(id, os_choice).await
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool> {
+        crate::action_approvals::delete_machine(id, format!("Delete machine {}", id), "dragonfly-server").await
+    }
+}
+
+#[async_trait]
+impl SettingsRepo for SqliteRepo {
+    async fn get(&self) -> Result<Settings> {
+        crate::db::get_app_settings().await
+    }
+
+    async fn save(&self, settings: &Settings) -> Result<()> {
+        crate::db::save_app_settings(settings).await
+    }
+}
+
+#[async_trait]
+impl EventRepo for SqliteRepo {
+    async fn store_completed_workflow(&self, machine_id: &Uuid, info: &WorkflowInfo) -> Result<()> {
+        crate::db::store_completed_workflow(machine_id, info).await
+    }
+
+    async fn completed_workflow(&self, machine_id: &Uuid) -> Result<Option<(WorkflowInfo, DateTime<Utc>)>> {
+        crate::db::get_completed_workflow(machine_id).await
+    }
+
+    async fn save_template_timing(&self, template_name: &str, action_name: &str, durations: &[u64]) -> Result<bool> {
+        crate::db::save_template_timing(template_name, action_name, durations).await
+    }
+
+    async fn template_timings(&self) -> Result<Vec<TemplateTiming>> {
+        crate::db::load_template_timings().await
+    }
+}
+
+// Same cap as the template_timings table keeps
+const MAX_TIMING_HISTORY: usize = 50;
+
+#[derive(Default)]
+struct MemoryState {
+    machines: HashMap<Uuid, Machine>,
+    settings: Option<Settings>,
+    completed_workflows: HashMap<Uuid, (WorkflowInfo, DateTime<Utc>)>,
+    timings: HashMap<(String, String), Vec<u64>>,
+}
+
+/// In-memory repositories with the same observable behaviour as the SQLite ones.
+#[derive(Default)]
+pub struct MemoryRepo {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryRepo {
+    fn with_state<T>(&self, f: impl FnOnce(&mut MemoryState) -> T) -> Result<T> {
+        let mut state = self.state.lock().map_err(|_| anyhow::anyhow!("In-memory repository lock poisoned"))?;
+        Ok(f(&mut state))
+    }
+}
+
+#[async_trait]
+impl MachineRepo for MemoryRepo {
+    async fn get(&self, id: &Uuid) -> Result<Option<Machine>> {
+        self.with_state(|s| s.machines.get(id).cloned())
+    }
+
+    async fn get_by_mac(&self, mac_address: &str) -> Result<Option<Machine>> {
+        self.with_state(|s| s.machines.values().find(|m| m.mac_address == mac_address).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<Machine>> {
+        self.with_state(|s| {
+            let mut machines: Vec<Machine> = s.machines.values().cloned().collect();
+            machines.sort_by_key(|m| m.created_at);
+            machines
+        })
+    }
+
+    async fn register(&self, req: &RegisterRequest) -> Result<Uuid> {
+        let now = Utc::now();
+        // Mirrors db::register_machine: deterministic ID, status from Proxmox hints, OS reset
+        let status = if req.proxmox_vmid.is_some() || req.proxmox_node.is_some() {
+            MachineStatus::ExistingOS
+        } else {
+            MachineStatus::AwaitingAssignment
+        };
+        self.with_state(|s| {
+            let existing = s.machines.values().find(|m| m.mac_address == req.mac_address).map(|m| (m.id, m.created_at));
+            let (id, created_at) = existing.unwrap_or_else(|| (Uuid::new_v5(&Uuid::NAMESPACE_DNS, req.mac_address.as_bytes()), now));
+            s.machines.insert(id, Machine {
+                id,
+                mac_address: req.mac_address.clone(),
+                ip_address: req.ip_address.clone(),
+                hostname: req.hostname.clone(),
+                os_choice: None,
+                os_installed: None,
+                status,
+                disks: req.disks.clone(),
+                nameservers: req.nameservers.clone(),
+                created_at,
+                updated_at: now,
+                memorable_name: Some(dragonfly_common::mac_to_words::mac_to_words_safe(&req.mac_address)),
+                bmc_credentials: None,
+                installation_progress: 0,
+                installation_step: None,
+                last_deployment_duration: None,
+                cpu_model: req.cpu_model.clone(),
+                cpu_cores: req.cpu_cores,
+                total_ram_bytes: req.total_ram_bytes,
+                proxmox_vmid: req.proxmox_vmid,
+                proxmox_node: req.proxmox_node.clone(),
+                proxmox_cluster: req.proxmox_cluster.clone(),
+                is_proxmox_host: req.proxmox_node.is_some() && req.proxmox_vmid.is_none(),
+                tinkerbell_synced: false,
+            });
+            id
+        })
+    }
+
+    async fn update(&self, machine: &Machine) -> Result<bool> {
+        self.with_state(|s| match s.machines.get_mut(&machine.id) {
+            Some(existing) => {
+                *existing = Machine { updated_at: Utc::now(), ..machine.clone() };
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn update_status(&self, id: &Uuid, status: MachineStatus) -> Result<bool> {
+        self.with_state(|s| match s.machines.get_mut(id) {
+            Some(machine) => {
+                machine.status = status;
+                machine.updated_at = Utc::now();
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn assign_os(&self, id: &Uuid, os_choice: &str) -> Result<bool> {
+        self.with_state(|s| match s.machines.get_mut(id) {
+            Some(machine) => {
+                machine.os_choice = Some(os_choice.to_string());
+                machine.updated_at = Utc::now();
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool> {
+        self.with_state(|s| s.machines.remove(id).is_some())
+    }
+}
+
+#[async_trait]
+impl SettingsRepo for MemoryRepo {
+    async fn get(&self) -> Result<Settings> {
+        self.with_state(|s| s.settings.clone().unwrap_or_default())
+    }
+
+    async fn save(&self, settings: &Settings) -> Result<()> {
+        self.with_state(|s| s.settings = Some(settings.clone()))
+    }
+}
+
+#[async_trait]
+impl EventRepo for MemoryRepo {
+    async fn store_completed_workflow(&self, machine_id: &Uuid, info: &WorkflowInfo) -> Result<()> {
+        self.with_state(|s| {
+            s.completed_workflows.insert(*machine_id, (info.clone(), Utc::now()));
+        })
+    }
+
+    async fn completed_workflow(&self, machine_id: &Uuid) -> Result<Option<(WorkflowInfo, DateTime<Utc>)>> {
+        self.with_state(|s| s.completed_workflows.get(machine_id).cloned())
+    }
+
+    async fn save_template_timing(&self, template_name: &str, action_name: &str, durations: &[u64]) -> Result<bool> {
+        let start = durations.len().saturating_sub(MAX_TIMING_HISTORY);
+        self.with_state(|s| {
+            s.timings.insert((template_name.to_string(), action_name.to_string()), durations[start..].to_vec());
+            true
+        })
+    }
+
+    async fn template_timings(&self) -> Result<Vec<TemplateTiming>> {
+        self.with_state(|s| {
+            s.timings.iter()
+                .map(|((template_name, action_name), durations)| TemplateTiming {
+                    template_name: template_name.clone(),
+                    action_name: action_name.clone(),
+                    durations: durations.clone(),
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(mac: &str, ip: &str) -> RegisterRequest {
+        RegisterRequest {
+            mac_address: mac.to_string(),
+            ip_address: ip.to_string(),
+            hostname: None,
+            disks: Vec::new(),
+            nameservers: Vec::new(),
+            cpu_model: None,
+            cpu_cores: None,
+            total_ram_bytes: None,
+            proxmox_vmid: None,
+            proxmox_node: None,
+            proxmox_cluster: None,
+            hardware_fingerprint: None,
+            agent_environment: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_is_keyed_by_mac() {
+        let repos = Repositories::in_memory();
+        let id = repos.machines.register(&request("aa:bb:cc:dd:ee:ff", "10.0.0.5")).await.unwrap();
+        assert!(repos.machines.assign_os(&id, "debian-12").await.unwrap());
+
+        // Re-registering refreshes the record and, like the SQLite repo, clears the OS choice
+        let again = repos.machines.register(&request("aa:bb:cc:dd:ee:ff", "10.0.0.6")).await.unwrap();
+        assert_eq!(id, again);
+        let machine = repos.machines.get(&id).await.unwrap().unwrap();
+        assert_eq!(machine.ip_address, "10.0.0.6");
+        assert_eq!(machine.os_choice, None);
+        assert_eq!(repos.machines.list().await.unwrap().len(), 1);
+
+        assert!(repos.machines.delete(&id).await.unwrap());
+        assert!(!repos.machines.update_status(&id, MachineStatus::Ready).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_timing_history_is_capped() {
+        let repos = Repositories::in_memory();
+        let durations: Vec<u64> = (0..80).collect();
+        repos.events.save_template_timing("debian-12", "stream-image", &durations).await.unwrap();
+        let timings = repos.events.template_timings().await.unwrap();
+        assert_eq!(timings[0].durations.len(), MAX_TIMING_HISTORY);
+        assert_eq!(timings[0].durations[0], 30);
+    }
+}
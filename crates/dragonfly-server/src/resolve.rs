@@ -0,0 +1,175 @@
+use dragonfly_common::models::{Machine, MachineMatch};
+use uuid::Uuid;
+
+// Upper bound on fuzzy candidates returned for one query
+const MAX_CANDIDATES: usize = 10;
+
+// Largest edit distance still considered a typo of a name
+const MAX_EDIT_DISTANCE: usize = 2;
+
+const SCORE_EXACT: u32 = 100;
+const SCORE_PREFIX: u32 = 75;
+const SCORE_SUBSTRING: u32 = 50;
+const SCORE_TYPO: u32 = 25;
+
+// MACs may be typed with dashes or in upper case
+fn normalize_mac(value: &str) -> String {
+    value.trim().to_lowercase().replace('-', ":")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b_chars.len() + 1];
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (previous[j + 1] + 1).min(current[j] + 1).min(previous[j] + cost);
+        }
+        previous = current;
+    }
+    previous[b_chars.len()]
+}
+
+// Score a single field against the query (both already lowercased)
+fn score_field(query: &str, value: &str, allow_typos: bool) -> u32 {
+    if value.is_empty() {
+        0
+    } else if value == query {
+        SCORE_EXACT
+    } else if value.starts_with(query) {
+        SCORE_PREFIX
+    } else if value.contains(query) {
+        SCORE_SUBSTRING
+    } else if allow_typos {
+        let distance = edit_distance(query, value);
+        if distance <= MAX_EDIT_DISTANCE {
+            SCORE_TYPO - distance as u32
+        } else {
+            0
+        }
+    } else {
+        0
+    }
+}
+
+fn to_match(machine: &Machine, matched_on: &str, exact: bool) -> MachineMatch {
+    MachineMatch {
+        id: machine.id,
+        hostname: machine.hostname.clone(),
+        memorable_name: machine.memorable_name.clone(),
+        mac_address: machine.mac_address.clone(),
+        ip_address: machine.ip_address.clone(),
+        status: machine.status.to_string(),
+        matched_on: matched_on.to_string(),
+        exact,
+    }
+}
+
+/// Resolve a hostname, memorable name, MAC address or UUID (or a fragment/typo of one)
+/// to candidate machines. If anything matches exactly, only exact matches are returned.
+pub fn resolve_machines(query: &str, machines: &[Machine]) -> Vec<MachineMatch> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    if let Ok(id) = Uuid::parse_str(&query) {
+        if let Some(machine) = machines.iter().find(|m| m.id == id) {
+            return vec![to_match(machine, "id", true)];
+        }
+    }
+
+    let mac_query = normalize_mac(&query);
+    let mut scored: Vec<(u32, &str, &Machine)> = Vec::new();
+    for machine in machines {
+        let fields = [
+            ("hostname", machine.hostname.as_deref().unwrap_or("").to_lowercase(), &query, true),
+            ("memorable_name", machine.memorable_name.as_deref().unwrap_or("").to_lowercase(), &query, true),
+            ("mac_address", normalize_mac(&machine.mac_address), &mac_query, false),
+            ("id", machine.id.to_string(), &query, false),
+        ];
+
+        let best = fields.iter()
+            .map(|(name, value, q, typos)| (score_field(q, value, *typos), *name))
+            .max_by_key(|(score, _)| *score);
+
+        if let Some((score, field)) = best {
+            if score > 0 {
+                scored.push((score, field, machine));
+            }
+        }
+    }
+
+    if scored.iter().any(|(score, _, _)| *score == SCORE_EXACT) {
+        scored.retain(|(score, _, _)| *score == SCORE_EXACT);
+    }
+
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.2.hostname.cmp(&b.2.hostname)));
+    scored.into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(score, field, machine)| to_match(machine, field, score == SCORE_EXACT))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(hostname: &str, memorable: &str, mac: &str) -> Machine {
+        Machine {
+            id: Uuid::new_v5(&Uuid::NAMESPACE_DNS, mac.as_bytes()),
+            mac_address: mac.to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            hostname: Some(hostname.to_string()),
+            memorable_name: Some(memorable.to_string()),
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    fn fleet() -> Vec<Machine> {
+        vec![
+            machine("node-01", "apple-banana-cherry-delta", "00:11:22:33:44:01"),
+            machine("node-02", "echo-foxtrot-golf-hotel", "00:11:22:33:44:02"),
+            machine("storage-01", "india-juliet-kilo-lima", "00:11:22:33:44:03"),
+        ]
+    }
+
+    #[test]
+    fn test_exact_hostname_wins() {
+        let matches = resolve_machines("NODE-01", &fleet());
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].exact);
+        assert_eq!(matches[0].matched_on, "hostname");
+    }
+
+    #[test]
+    fn test_mac_with_dashes() {
+        let matches = resolve_machines("00-11-22-33-44-03", &fleet());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].hostname.as_deref(), Some("storage-01"));
+    }
+
+    #[test]
+    fn test_prefix_is_ambiguous() {
+        let matches = resolve_machines("node", &fleet());
+        assert_eq!(matches.len(), 2);
+        assert!(matches.iter().all(|m| !m.exact));
+    }
+
+    #[test]
+    fn test_typo_in_memorable_name() {
+        let matches = resolve_machines("echo-foxtrot-golf-hotle", &fleet());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].hostname.as_deref(), Some("node-02"));
+    }
+
+    #[test]
+    fn test_uuid() {
+        let machines = fleet();
+        let id = machines[2].id.to_string();
+        let matches = resolve_machines(&id, &machines);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].matched_on, "id");
+    }
+}
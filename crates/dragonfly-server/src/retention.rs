@@ -0,0 +1,164 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tracing::{error, info};
+
+// How often the retention job runs
+const RETENTION_INTERVAL_SECS: u64 = 6 * 60 * 60;
+
+/// How long to keep accumulated data, in days. Zero means keep forever.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionSettings {
+    /// Template timing rows that haven't been updated in this many days
+    pub timing_days: u32,
+    /// In-memory event history used for diagnostics
+    pub event_days: u32,
+    /// Completed workflow records, diagnostics bundles and machine reports
+    pub job_history_days: u32,
+    /// BMC sensor samples
+    #[serde(default = "default_sensor_days")]
+    pub sensor_days: u32,
+}
+
+fn default_sensor_days() -> u32 {
+    14
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            timing_days: 90,
+            event_days: 7,
+            job_history_days: 30,
+            sensor_days: default_sensor_days(),
+        }
+    }
+}
+
+/// Rows removed by one run of the retention job.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PurgeReport {
+    pub ran_at: Option<DateTime<Utc>>,
+    pub timings: u64,
+    pub events: u64,
+    pub completed_workflows: u64,
+    pub diagnostic_bundles: u64,
+    #[serde(default)]
+    pub sensor_samples: u64,
+    #[serde(default)]
+    pub machine_reports: u64,
+}
+
+impl PurgeReport {
+    pub fn total(&self) -> u64 {
+        self.timings + self.events + self.completed_workflows + self.diagnostic_bundles + self.sensor_samples + self.machine_reports
+    }
+}
+
+// Result of the most recent retention run, surfaced through /api/settings
+static LAST_REPORT: Lazy<RwLock<Option<PurgeReport>>> = Lazy::new(|| RwLock::new(None));
+
+/// The report from the last retention run, if one has happened since startup.
+pub fn last_report() -> Option<PurgeReport> {
+    LAST_REPORT.read().ok().and_then(|r| r.clone())
+}
+
+// Cutoff timestamp for a retention window, or None when retention is disabled
+fn cutoff(days: u32) -> Option<DateTime<Utc>> {
+    if days == 0 {
+        None
+    } else {
+        Some(Utc::now() - Duration::days(days as i64))
+    }
+}
+
+/// Purge everything older than the configured windows and record the report.
+pub async fn run_cleanup(settings: &RetentionSettings) -> Result<PurgeReport> {
+    let mut report = PurgeReport {
+        ran_at: Some(Utc::now()),
+        ..Default::default()
+    };
+
+    if let Some(before) = cutoff(settings.timing_days) {
+        report.timings = crate::db::purge_template_timings_before(&before).await?;
+    }
+
+    if let Some(before) = cutoff(settings.event_days) {
+        if let Ok(guard) = crate::EVENT_MANAGER_REF.read() {
+            if let Some(event_manager) = guard.as_ref() {
+                report.events = event_manager.prune_before(&before) as u64;
+            }
+        }
+    }
+
+    if let Some(before) = cutoff(settings.job_history_days) {
+        report.completed_workflows = crate::db::purge_completed_workflows_before(&before).await?;
+        report.diagnostic_bundles = crate::db::purge_diagnostics_bundles_before(&before).await?;
+        report.machine_reports = crate::machine_reports::purge_before(&before).await?;
+    }
+
+    if let Some(before) = cutoff(settings.sensor_days) {
+        report.sensor_samples = crate::db::purge_sensor_samples_before(&before).await?;
+    }
+
+    info!(
+        "Retention cleanup purged {} rows (timings={}, events={}, completed_workflows={}, diagnostic_bundles={}, sensor_samples={}, machine_reports={})",
+        report.total(), report.timings, report.events, report.completed_workflows, report.diagnostic_bundles, report.sensor_samples, report.machine_reports
+    );
+
+    if let Ok(mut last) = LAST_REPORT.write() {
+        *last = Some(report.clone());
+    }
+
+    Ok(report)
+}
+
+/// Periodically enforce the retention settings stored in the database.
+pub async fn start_retention_task(mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        let interval = std::time::Duration::from_secs(RETENTION_INTERVAL_SECS);
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
+                    let settings = match crate::db::get_app_settings().await {
+                        Ok(s) => s.retention,
+                        Err(e) => {
+                            error!("Failed to load retention settings: {}", e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = run_cleanup(&settings).await {
+                        error!("Error during retention cleanup: {}", e);
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping retention task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_days_disables_retention() {
+        assert!(cutoff(0).is_none());
+    }
+
+    #[test]
+    fn test_cutoff_is_in_the_past() {
+        let before = cutoff(7).unwrap();
+        let age = Utc::now() - before;
+        assert_eq!(age.num_days(), 7);
+    }
+}
@@ -0,0 +1,126 @@
+// Signed boot chain for machines that enforce UEFI Secure Boot.
+//
+// Firmware with Secure Boot on refuses iPXE, so these machines boot a Microsoft-signed
+// shim, which loads a distribution-signed GRUB, which fetches its config from Dragonfly.
+// The signed binaries are not redistributable from here; operators drop them (e.g. from
+// Ubuntu's shim-signed and grub-efi-amd64-signed packages) into
+// <artifact dir>/secure-boot/ and point DHCP at /ipxe/secure-boot/shimx64.efi for
+// those machines. The kernels GRUB loads must also pass shim's verification, either
+// signed by the distribution or by a key enrolled with MOK.
+
+use url::Url;
+
+use crate::api::TinkerbellBootParams;
+
+/// Artifact subdirectory holding the signed binaries
+pub const ARTIFACT_SUBDIR: &str = "secure-boot";
+pub const SHIM_FILE: &str = "shimx64.efi";
+pub const GRUB_FILE: &str = "grubx64.efi";
+
+/// Whether the signed shim and GRUB are present to be served.
+pub fn artifacts_available() -> bool {
+    let dir = crate::api::artifact_base_dir().join(ARTIFACT_SUBDIR);
+    [SHIM_FILE, GRUB_FILE].iter().all(|file| dir.join(file).is_file())
+}
+
+/// What a machine's GRUB config boots, mirroring the iPXE flow.
+pub enum GrubTarget {
+    /// Unknown machine: the Dragonfly agent, which registers it
+    Agent,
+    /// Known machine: HookOS, which runs its Tinkerbell workflow
+    HookOs(TinkerbellBootParams),
+}
+
+// GRUB addresses HTTP files as (http,host[:port])/path
+fn grub_device(base_url: &str) -> Option<String> {
+    let url = Url::parse(base_url).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("(http,{}:{})", host, port),
+        None => format!("(http,{})", host),
+    })
+}
+
+/// The config signed network GRUB loads from its default prefix (/grub/grub.cfg);
+/// it hands over to the per-MAC config.
+pub fn bootstrap_config(base_url: &str) -> Option<String> {
+    let device = grub_device(base_url)?;
+    Some(format!(
+        "set timeout=0\n\
+         echo \"Fetching Dragonfly boot config for ${{net_default_mac}}...\"\n\
+         configfile {}/grub/${{net_default_mac}}/grub.cfg\n",
+        device
+    ))
+}
+
+/// Per-machine GRUB config, equivalent to the dragonfly-agent.ipxe / hookos.ipxe scripts.
+pub fn render_config(base_url: &str, mac: &str, target: &GrubTarget) -> Option<String> {
+    let device = grub_device(base_url)?;
+    let (title, linux, initrd) = match target {
+        GrubTarget::Agent => (
+            "Dragonfly Agent",
+            format!(
+                "{dev}/ipxe/dragonfly-agent/vmlinuz ip=dhcp alpine_repo=http://dl-cdn.alpinelinux.org/alpine/v3.21/main \
+                 modules=loop,squashfs,sd-mod,usb-storage modloop={base}/ipxe/dragonfly-agent/modloop \
+                 apkovl={base}/ipxe/dragonfly-agent/localhost.apkovl.tar.gz rw",
+                dev = device, base = base_url
+            ),
+            format!("{}/ipxe/dragonfly-agent/initramfs-lts", device),
+        ),
+        GrubTarget::HookOs(params) => (
+            "HookOS",
+            format!(
+                "{dev}/ipxe/hookos/vmlinuz-x86_64 syslog_host={syslog} grpc_authority={grpc} tinkerbell_tls={tls} \
+                 worker_id={mac} hw_addr={mac} console=tty1 console=ttyS0,115200 console=ttyS1,115200 \
+                 tink_worker_image=quay.io/tinkerbell/tink-worker:v0.12.1 intel_iommu=on iommu=pt",
+                dev = device, syslog = params.syslog_host, grpc = params.grpc_authority, tls = params.tls, mac = mac
+            ),
+            format!("{}/ipxe/hookos/initramfs-x86_64", device),
+        ),
+    };
+
+    Some(format!(
+        "set timeout=0\n\
+         set default=0\n\
+         menuentry \"{title}\" {{\n\
+         \x20   echo \"Loading {title} via Dragonfly (Secure Boot)...\"\n\
+         \x20   linux {linux}\n\
+         \x20   initrd {initrd}\n\
+         }}\n",
+        title = title, linux = linux, initrd = initrd
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grub_device() {
+        assert_eq!(grub_device("http://10.0.0.1:3000").as_deref(), Some("(http,10.0.0.1:3000)"));
+        assert_eq!(grub_device("http://dragonfly.lan").as_deref(), Some("(http,dragonfly.lan)"));
+        assert_eq!(grub_device("not a url"), None);
+    }
+
+    #[test]
+    fn test_hookos_config() {
+        let params = TinkerbellBootParams {
+            grpc_authority: "10.0.0.1:42113".to_string(),
+            syslog_host: "10.0.0.1".to_string(),
+            tls: false,
+        };
+        let config = render_config("http://10.0.0.1:3000", "aa:bb:cc:dd:ee:ff", &GrubTarget::HookOs(params)).unwrap();
+        assert!(config.contains("linux (http,10.0.0.1:3000)/ipxe/hookos/vmlinuz-x86_64 "));
+        assert!(config.contains("grpc_authority=10.0.0.1:42113"));
+        assert!(config.contains("worker_id=aa:bb:cc:dd:ee:ff"));
+        assert!(config.contains("initrd (http,10.0.0.1:3000)/ipxe/hookos/initramfs-x86_64"));
+    }
+
+    #[test]
+    fn test_agent_config_and_bootstrap() {
+        let config = render_config("http://10.0.0.1:3000", "aa:bb:cc:dd:ee:ff", &GrubTarget::Agent).unwrap();
+        assert!(config.contains("apkovl=http://10.0.0.1:3000/ipxe/dragonfly-agent/localhost.apkovl.tar.gz"));
+        let bootstrap = bootstrap_config("http://10.0.0.1:3000").unwrap();
+        assert!(bootstrap.contains("configfile (http,10.0.0.1:3000)/grub/${net_default_mac}/grub.cfg"));
+    }
+}
@@ -0,0 +1,342 @@
+// Sensor telemetry from machine BMCs over Redfish: power draw, temperatures (inlet among
+// them) and fan speeds.
+//
+// Every DRAGONFLY_SENSOR_INTERVAL_SECS (default 60) the collector reads the Power and
+// Thermal resources of each chassis on machines with Redfish BMC credentials, stores the
+// readings as samples (purged by the retention job) and keeps the latest ones in memory for
+// /metrics. BMCs usually have self-signed certificates; set
+// DRAGONFLY_REDFISH_SKIP_TLS_VERIFY=true to accept them.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{BmcCredentials, BmcType, Machine};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+const INTERVAL_ENV_VAR: &str = "DRAGONFLY_SENSOR_INTERVAL_SECS";
+const SKIP_TLS_VERIFY_ENV_VAR: &str = "DRAGONFLY_REDFISH_SKIP_TLS_VERIFY";
+
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+// Floor on the interval so a typo can't hammer every BMC
+const MIN_INTERVAL_SECS: u64 = 10;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// BMCs polled at once
+const CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorKind {
+    /// Watts
+    Power,
+    /// Degrees Celsius
+    Temperature,
+    /// RPM or percent, per the reading's unit
+    Fan,
+}
+
+impl SensorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SensorKind::Power => "power",
+            SensorKind::Temperature => "temperature",
+            SensorKind::Fan => "fan",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "power" => Some(SensorKind::Power),
+            "temperature" => Some(SensorKind::Temperature),
+            "fan" => Some(SensorKind::Fan),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub kind: SensorKind,
+    /// The BMC's name for the sensor, e.g. "System Board Inlet Temp"
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    /// Set on temperature sensors measuring intake air
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inlet: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SensorSample {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub reading: SensorReading,
+}
+
+/// Readings from a chassis Power resource.
+pub fn parse_power(body: &serde_json::Value) -> Vec<SensorReading> {
+    let Some(controls) = body["PowerControl"].as_array() else {
+        return Vec::new();
+    };
+    controls.iter()
+        .filter_map(|control| Some(SensorReading {
+            kind: SensorKind::Power,
+            name: control["Name"].as_str().unwrap_or("Power").to_string(),
+            value: control["PowerConsumedWatts"].as_f64()?,
+            unit: "W".to_string(),
+            inlet: false,
+        }))
+        .collect()
+}
+
+/// Readings from a chassis Thermal resource.
+pub fn parse_thermal(body: &serde_json::Value) -> Vec<SensorReading> {
+    let mut readings = Vec::new();
+    for sensor in body["Temperatures"].as_array().into_iter().flatten() {
+        let Some(value) = sensor["ReadingCelsius"].as_f64() else {
+            continue;
+        };
+        let name = sensor["Name"].as_str().unwrap_or("Temperature").to_string();
+        let inlet = sensor["PhysicalContext"].as_str() == Some("Intake") || name.to_ascii_lowercase().contains("inlet");
+        readings.push(SensorReading { kind: SensorKind::Temperature, name, value, unit: "Cel".to_string(), inlet });
+    }
+    for fan in body["Fans"].as_array().into_iter().flatten() {
+        let Some(value) = fan["Reading"].as_f64() else {
+            continue;
+        };
+        // Older schemas name fans with FanName
+        let name = fan["Name"].as_str().or(fan["FanName"].as_str()).unwrap_or("Fan").to_string();
+        let unit = match fan["ReadingUnits"].as_str() {
+            Some("Percent") => "%",
+            _ => "RPM",
+        };
+        readings.push(SensorReading { kind: SensorKind::Fan, name, value, unit: unit.to_string(), inlet: false });
+    }
+    readings
+}
+
+pub(crate) fn base_url(address: &str) -> String {
+    let address = address.trim().trim_end_matches('/');
+    if address.starts_with("http://") || address.starts_with("https://") {
+        address.to_string()
+    } else {
+        format!("https://{}", address)
+    }
+}
+
+pub(crate) async fn get(client: &reqwest::Client, credentials: &BmcCredentials, url: &str) -> Result<serde_json::Value> {
+    let response = client.get(url)
+        .basic_auth(&credentials.username, credentials.password.as_deref())
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("{} returned {}", url, response.status());
+    }
+    response.json().await.with_context(|| format!("{} returned invalid JSON", url))
+}
+
+/// Read every chassis's power and thermal sensors from a Redfish BMC.
+pub async fn read_sensors(client: &reqwest::Client, credentials: &BmcCredentials) -> Result<Vec<SensorReading>> {
+    let base = base_url(&credentials.address);
+    let chassis = get(client, credentials, &format!("{}/redfish/v1/Chassis", base)).await?;
+    let mut readings = Vec::new();
+    for member in chassis["Members"].as_array().into_iter().flatten() {
+        let Some(path) = member["@odata.id"].as_str() else {
+            continue;
+        };
+        // Not every chassis (e.g. a backplane) has both resources
+        match get(client, credentials, &format!("{}{}/Power", base, path)).await {
+            Ok(body) => readings.extend(parse_power(&body)),
+            Err(e) => debug!("No power readings from {}: {:#}", path, e),
+        }
+        match get(client, credentials, &format!("{}{}/Thermal", base, path)).await {
+            Ok(body) => readings.extend(parse_thermal(&body)),
+            Err(e) => debug!("No thermal readings from {}: {:#}", path, e),
+        }
+    }
+    Ok(readings)
+}
+
+// Latest readings per machine, for /metrics
+static LATEST: Lazy<RwLock<HashMap<Uuid, Vec<SensorReading>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The most recent readings for a machine, if it has been polled since startup.
+pub fn latest(machine_id: &Uuid) -> Option<Vec<SensorReading>> {
+    LATEST.read().ok()?.get(machine_id).cloned()
+}
+
+pub(crate) fn redfish_credentials(machine: &Machine) -> Option<&BmcCredentials> {
+    machine.bmc_credentials.as_ref()
+        .filter(|c| c.bmc_type == BmcType::Redfish && c.password.is_some())
+}
+
+async fn collect(client: &reqwest::Client) -> Result<()> {
+    let machines = crate::db::get_all_machines().await?;
+    let polled: Vec<(Uuid, BmcCredentials)> = machines.iter()
+        .filter_map(|m| redfish_credentials(m).map(|c| (m.id, c.clone())))
+        .collect();
+    let now = Utc::now();
+
+    futures::stream::iter(polled.iter())
+        .for_each_concurrent(CONCURRENCY, |(machine_id, credentials)| async move {
+            match read_sensors(client, credentials).await {
+                Ok(readings) => {
+                    if let Err(e) = crate::db::insert_sensor_samples(machine_id, &readings, now).await {
+                        error!("Failed to store sensor samples for machine {}: {}", machine_id, e);
+                    }
+                    if let Ok(mut latest) = LATEST.write() {
+                        latest.insert(*machine_id, readings);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to read Redfish sensors for machine {}: {:#}", machine_id, e);
+                    // Stale readings would look current on /metrics
+                    if let Ok(mut latest) = LATEST.write() {
+                        latest.remove(machine_id);
+                    }
+                }
+            }
+        })
+        .await;
+
+    // Forget machines that were deleted or lost their credentials
+    if let Ok(mut latest) = LATEST.write() {
+        latest.retain(|id, _| polled.iter().any(|(polled_id, _)| polled_id == id));
+    }
+    Ok(())
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Prometheus gauges for the latest readings.
+pub fn render_metrics() -> String {
+    let Ok(latest) = LATEST.read() else {
+        return String::new();
+    };
+    let mut power = String::new();
+    let mut temperature = String::new();
+    let mut fan = String::new();
+    for (machine_id, readings) in latest.iter() {
+        for reading in readings {
+            let name = escape_label(&reading.name);
+            match reading.kind {
+                SensorKind::Power => power.push_str(&format!(
+                    "dragonfly_machine_power_watts{{machine_id=\"{}\",sensor=\"{}\"}} {}\n", machine_id, name, reading.value)),
+                SensorKind::Temperature => temperature.push_str(&format!(
+                    "dragonfly_machine_temperature_celsius{{machine_id=\"{}\",sensor=\"{}\",inlet=\"{}\"}} {}\n", machine_id, name, reading.inlet, reading.value)),
+                SensorKind::Fan => fan.push_str(&format!(
+                    "dragonfly_machine_fan_speed{{machine_id=\"{}\",sensor=\"{}\",unit=\"{}\"}} {}\n", machine_id, name, escape_label(&reading.unit), reading.value)),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("# HELP dragonfly_machine_power_watts Power draw reported by the machine's BMC.\n");
+    out.push_str("# TYPE dragonfly_machine_power_watts gauge\n");
+    out.push_str(&power);
+    out.push_str("# HELP dragonfly_machine_temperature_celsius Temperature sensors reported by the machine's BMC.\n");
+    out.push_str("# TYPE dragonfly_machine_temperature_celsius gauge\n");
+    out.push_str(&temperature);
+    out.push_str("# HELP dragonfly_machine_fan_speed Fan speeds reported by the machine's BMC, in RPM or percent.\n");
+    out.push_str("# TYPE dragonfly_machine_fan_speed gauge\n");
+    out.push_str(&fan);
+    out
+}
+
+fn interval() -> Duration {
+    let secs = crate::config::var(INTERVAL_ENV_VAR)
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS)
+        .max(MIN_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+/// An HTTP client for talking to BMCs, honouring DRAGONFLY_REDFISH_SKIP_TLS_VERIFY.
+pub(crate) fn redfish_client() -> reqwest::Result<reqwest::Client> {
+    let skip_tls_verify = crate::config::var(SKIP_TLS_VERIFY_ENV_VAR).is_some_and(|v| v == "true" || v == "1");
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .danger_accept_invalid_certs(skip_tls_verify)
+        .build()
+}
+
+/// Poll Redfish sensors on machines with BMC credentials.
+pub async fn start_sensor_task(mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let client = match redfish_client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create Redfish HTTP client: {}", e);
+            return;
+        }
+    };
+    let interval = interval();
+    info!("Collecting Redfish sensor telemetry every {:?}", interval);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
+                    if let Err(e) = collect(&client).await {
+                        error!("Sensor collection failed: {:#}", e);
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping sensor task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_power_and_thermal() {
+        let power = json!({ "PowerControl": [{ "Name": "System Power Control", "PowerConsumedWatts": 212 }, { "Name": "Idle" }] });
+        assert_eq!(parse_power(&power), vec![SensorReading {
+            kind: SensorKind::Power,
+            name: "System Power Control".to_string(),
+            value: 212.0,
+            unit: "W".to_string(),
+            inlet: false,
+        }]);
+
+        let thermal = json!({
+            "Temperatures": [
+                { "Name": "System Board Inlet Temp", "ReadingCelsius": 22.5, "PhysicalContext": "Intake" },
+                { "Name": "CPU1 Temp", "ReadingCelsius": 48, "PhysicalContext": "CPU" },
+                { "Name": "Absent", "ReadingCelsius": null }
+            ],
+            "Fans": [
+                { "FanName": "Fan1A", "Reading": 5400, "ReadingUnits": "RPM" },
+                { "Name": "Fan2", "Reading": 35, "ReadingUnits": "Percent" }
+            ]
+        });
+        let readings = parse_thermal(&thermal);
+        assert_eq!(readings.len(), 4);
+        assert!(readings[0].inlet && !readings[1].inlet);
+        assert_eq!((readings[2].name.as_str(), readings[2].unit.as_str()), ("Fan1A", "RPM"));
+        assert_eq!((readings[3].value, readings[3].unit.as_str()), (35.0, "%"));
+    }
+
+    #[test]
+    fn test_base_url_and_labels() {
+        assert_eq!(base_url("10.0.0.5"), "https://10.0.0.5");
+        assert_eq!(base_url("http://bmc.lab/"), "http://bmc.lab");
+        assert_eq!(escape_label("CPU \"1\""), "CPU \\\"1\\\"");
+    }
+}
@@ -0,0 +1,267 @@
+// First-run setup wizard.
+//
+// A new server asks for the things it can't guess before anyone uses it: the admin password,
+// how machines reach it (a base URL, or a network interface to derive one from), the default
+// OS, where iPXE artifacts and images are kept, and optionally a notification channel. All of
+// it is saved in one transaction together with `setup_completed`, so a wizard abandoned or
+// failing half-way never leaves the server marked as set up with only some of its answers.
+//
+// The base URL and artifact directory can also come from dragonfly.toml, the environment or
+// flags; those win, and the wizard's values only fill in what they leave unset.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use once_cell::sync::Lazy;
+use std::path::Path;
+use std::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::notifications::{NotificationChannel, NotificationSettings};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// How machines reach this server and where it keeps its artifacts, as saved by the wizard.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSettings {
+    /// Address machines use to reach this server, e.g. "http://10.0.0.5:3000"
+    pub base_url: Option<String>,
+    /// Interface the base URL was derived from, when it wasn't entered directly
+    pub network_interface: Option<String>,
+    /// Where iPXE artifacts and OS images are cached
+    pub artifact_dir: Option<String>,
+}
+
+/// A host interface with an IPv4 address, offered by the wizard.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostInterface {
+    pub name: String,
+    pub address: Ipv4Addr,
+}
+
+/// The wizard's answers as submitted.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetupRequest {
+    pub admin_password: String,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub network_interface: Option<String>,
+    #[serde(default)]
+    pub default_os: Option<String>,
+    #[serde(default)]
+    pub artifact_dir: Option<String>,
+    #[serde(default)]
+    pub notification_channel: Option<NotificationChannel>,
+}
+
+/// Validated answers, ready to be saved.
+#[derive(Debug, Clone)]
+pub struct Setup {
+    pub admin_password: String,
+    pub server: ServerSettings,
+    pub default_os: Option<String>,
+    /// Notification settings with the wizard's channel added (or replacing one of the same name)
+    pub notifications: Option<NotificationSettings>,
+}
+
+// Trimmed, with blanks treated as unset
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+impl SetupRequest {
+    /// Check the answers against the host's interfaces and the existing notification settings.
+    pub fn validate(self, interfaces: &[HostInterface], port: u16, notifications: &NotificationSettings) -> Result<Setup> {
+        if self.admin_password.chars().count() < MIN_PASSWORD_LENGTH {
+            bail!("The admin password must be at least {} characters", MIN_PASSWORD_LENGTH);
+        }
+
+        let network_interface = non_empty(self.network_interface);
+        let interface = match &network_interface {
+            Some(name) => match interfaces.iter().find(|i| &i.name == name) {
+                Some(interface) => Some(interface),
+                None => bail!("This host has no interface '{}' with an IPv4 address", name),
+            },
+            None => None,
+        };
+        let base_url = match (non_empty(self.base_url), interface) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Some(interface)) => format!("http://{}:{}", interface.address, port),
+            (None, None) => bail!("Enter the base URL machines should use, or pick a network interface to derive it from"),
+        };
+        match url::Url::parse(&base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host_str().is_some() => {}
+            _ => bail!("'{}' is not an http(s) URL", base_url),
+        }
+
+        let default_os = non_empty(self.default_os);
+        if let Some(os) = &default_os {
+            if let Err(e) = os.parse::<dragonfly_common::OsIdentifier>() {
+                bail!("{}", e);
+            }
+        }
+
+        let artifact_dir = non_empty(self.artifact_dir);
+        if let Some(dir) = &artifact_dir {
+            if !Path::new(dir).is_absolute() {
+                bail!("The artifact directory must be an absolute path");
+            }
+        }
+
+        let notifications = match self.notification_channel {
+            Some(mut channel) => {
+                channel.name = channel.name.trim().to_string();
+                let mut settings = notifications.clone();
+                settings.channels.retain(|c| !c.name.eq_ignore_ascii_case(&channel.name));
+                settings.channels.push(channel);
+                settings.validate()?;
+                Some(settings)
+            }
+            None => None,
+        };
+
+        Ok(Setup {
+            admin_password: self.admin_password,
+            server: ServerSettings { base_url: Some(base_url), network_interface, artifact_dir },
+            default_os,
+            notifications,
+        })
+    }
+}
+
+// Interfaces in `ip -j -4 addr show` output, leaving out loopback
+fn parse_interfaces(json: &str) -> Vec<HostInterface> {
+    let links: Vec<serde_json::Value> = serde_json::from_str(json).unwrap_or_default();
+    let mut interfaces = Vec::new();
+    for link in &links {
+        let Some(name) = link["ifname"].as_str() else { continue };
+        let loopback = link["flags"].as_array()
+            .is_some_and(|flags| flags.iter().any(|f| f.as_str() == Some("LOOPBACK")));
+        if loopback {
+            continue;
+        }
+        let addresses = link["addr_info"].as_array().into_iter().flatten();
+        if let Some(address) = addresses.filter_map(|a| a["local"].as_str()?.parse().ok()).next() {
+            interfaces.push(HostInterface { name: name.to_string(), address });
+        }
+    }
+    interfaces
+}
+
+/// This host's interfaces with an IPv4 address.
+pub async fn host_interfaces() -> Vec<HostInterface> {
+    match tokio::process::Command::new("ip").args(["-j", "-4", "addr", "show"]).output().await {
+        Ok(output) if output.status.success() => parse_interfaces(&String::from_utf8_lossy(&output.stdout)),
+        Ok(output) => {
+            warn!("Listing network interfaces failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            Vec::new()
+        }
+        Err(e) => {
+            warn!("Failed to run ip to list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Make sure the artifact directory exists and can be written before it's saved.
+pub async fn prepare_artifact_dir(dir: &str) -> Result<()> {
+    tokio::fs::create_dir_all(dir).await
+        .with_context(|| format!("Failed to create the artifact directory {}", dir))?;
+    let probe = Path::new(dir).join(".dragonfly-write-test");
+    tokio::fs::write(&probe, b"").await
+        .with_context(|| format!("The artifact directory {} is not writable", dir))?;
+    let _ = tokio::fs::remove_file(&probe).await;
+    Ok(())
+}
+
+// The server settings last loaded or saved by the wizard
+static SAVED: Lazy<RwLock<ServerSettings>> = Lazy::new(|| RwLock::new(ServerSettings::default()));
+
+/// The wizard's server settings, for whatever the configuration leaves unset.
+pub fn saved() -> ServerSettings {
+    SAVED.read().map(|s| s.clone()).unwrap_or_default()
+}
+
+/// Put saved server settings into effect for whatever the configuration leaves unset.
+pub fn apply(server: &ServerSettings) {
+    let config = crate::config::get();
+    if config.server.base_url.is_none() {
+        if let Some(url) = &server.base_url {
+            info!("Using base URL {} from setup", url);
+        }
+    }
+    if config.storage.artifact_dir.is_none() {
+        if let Some(dir) = &server.artifact_dir {
+            info!("Using artifact directory {} from setup", dir);
+        }
+    }
+    if let Ok(mut saved) = SAVED.write() {
+        *saved = server.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> SetupRequest {
+        SetupRequest {
+            admin_password: "correct horse".to_string(),
+            base_url: None,
+            network_interface: Some("eth0".to_string()),
+            default_os: Some("ubuntu-2404".to_string()),
+            artifact_dir: Some(" ".to_string()),
+            notification_channel: None,
+        }
+    }
+
+    fn interfaces() -> Vec<HostInterface> {
+        vec![HostInterface { name: "eth0".to_string(), address: Ipv4Addr::new(10, 0, 0, 5) }]
+    }
+
+    #[test]
+    fn test_validate_setup() {
+        let setup = request().validate(&interfaces(), 3000, &NotificationSettings::default()).unwrap();
+        assert_eq!(setup.server.base_url.as_deref(), Some("http://10.0.0.5:3000"));
+        assert_eq!(setup.server.artifact_dir, None);
+        assert!(setup.notifications.is_none());
+
+        let explicit = SetupRequest { base_url: Some("https://df.example.com/".to_string()), ..request() };
+        let setup = explicit.validate(&interfaces(), 3000, &NotificationSettings::default()).unwrap();
+        assert_eq!(setup.server.base_url.as_deref(), Some("https://df.example.com"));
+
+        let short = SetupRequest { admin_password: "short".to_string(), ..request() };
+        assert!(short.validate(&interfaces(), 3000, &NotificationSettings::default()).is_err());
+        let unknown = SetupRequest { network_interface: Some("eth9".to_string()), ..request() };
+        assert!(unknown.validate(&interfaces(), 3000, &NotificationSettings::default()).is_err());
+        let neither = SetupRequest { network_interface: None, ..request() };
+        assert!(neither.validate(&interfaces(), 3000, &NotificationSettings::default()).is_err());
+        let relative = SetupRequest { artifact_dir: Some("artifacts".to_string()), ..request() };
+        assert!(relative.validate(&interfaces(), 3000, &NotificationSettings::default()).is_err());
+        let os = SetupRequest { default_os: Some("windows".to_string()), ..request() };
+        assert!(os.validate(&interfaces(), 3000, &NotificationSettings::default()).is_err());
+    }
+
+    #[test]
+    fn test_apply_keeps_settings_in_process() {
+        let server = ServerSettings {
+            base_url: Some("http://10.0.0.5:3000".to_string()),
+            network_interface: Some("eth0".to_string()),
+            artifact_dir: Some("/srv/dragonfly".to_string()),
+        };
+        apply(&server);
+        assert_eq!(saved(), server);
+    }
+
+    #[test]
+    fn test_parse_interfaces() {
+        let json = r#"[
+            {"ifindex":1,"ifname":"lo","flags":["LOOPBACK","UP"],"addr_info":[{"family":"inet","local":"127.0.0.1","prefixlen":8}]},
+            {"ifindex":2,"ifname":"eth0","flags":["BROADCAST","UP"],"addr_info":[{"family":"inet","local":"10.0.0.5","prefixlen":24}]},
+            {"ifindex":3,"ifname":"eth1","flags":["BROADCAST"],"addr_info":[]}
+        ]"#;
+        assert_eq!(parse_interfaces(json), interfaces());
+        assert!(parse_interfaces("not json").is_empty());
+    }
+}
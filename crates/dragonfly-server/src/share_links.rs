@@ -0,0 +1,157 @@
+// Expiring, signed links to a single machine's status page, for vendors or remote hands
+// who shouldn't need an account.
+//
+// Unlike status-link tokens these aren't stored: the link carries the machine, issue and
+// expiry times, signed with a key derived from the server secret. Revoking a machine's
+// links records a cutoff; anything issued before it stops working.
+
+use anyhow::Result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const DEFAULT_TTL_HOURS: u32 = 24;
+pub const MAX_TTL_HOURS: u32 = 30 * 24;
+
+const KEY_PURPOSE: &str = "dragonfly-share-links-v1";
+// machine id (16) + issued at ms (8) + expires at ms (8)
+const CLAIMS_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShareClaims {
+    pub machine_id: Uuid,
+    pub issued_at_ms: i64,
+    pub expires_at_ms: i64,
+}
+
+impl ShareClaims {
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(self.expires_at_ms).single().unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ShareLinkError {
+    #[error("This share link is malformed")]
+    Malformed,
+    #[error("This share link is invalid")]
+    BadSignature,
+    #[error("This share link is for a different machine")]
+    WrongMachine,
+    #[error("This share link has expired")]
+    Expired,
+    #[error("This share link has been revoked")]
+    Revoked,
+}
+
+fn mac(key: &[u8], data: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac
+}
+
+/// Encode and sign claims as `<claims>.<signature>`, both base64url.
+pub fn sign(key: &[u8], claims: &ShareClaims) -> String {
+    let mut data = Vec::with_capacity(CLAIMS_LEN);
+    data.extend_from_slice(claims.machine_id.as_bytes());
+    data.extend_from_slice(&claims.issued_at_ms.to_be_bytes());
+    data.extend_from_slice(&claims.expires_at_ms.to_be_bytes());
+    let signature = mac(key, &data).finalize().into_bytes();
+    format!("{}.{}", URL_SAFE_NO_PAD.encode(&data), URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// Check a link's signature and expiry. Revocation is checked separately, as it needs the database.
+pub fn decode(key: &[u8], token: &str, now: DateTime<Utc>) -> Result<ShareClaims, ShareLinkError> {
+    let (data, signature) = token.split_once('.').ok_or(ShareLinkError::Malformed)?;
+    let data = URL_SAFE_NO_PAD.decode(data).map_err(|_| ShareLinkError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| ShareLinkError::Malformed)?;
+    if data.len() != CLAIMS_LEN {
+        return Err(ShareLinkError::Malformed);
+    }
+    mac(key, &data).verify_slice(&signature).map_err(|_| ShareLinkError::BadSignature)?;
+
+    let claims = ShareClaims {
+        machine_id: Uuid::from_slice(&data[..16]).map_err(|_| ShareLinkError::Malformed)?,
+        issued_at_ms: i64::from_be_bytes(data[16..24].try_into().map_err(|_| ShareLinkError::Malformed)?),
+        expires_at_ms: i64::from_be_bytes(data[24..32].try_into().map_err(|_| ShareLinkError::Malformed)?),
+    };
+    if now.timestamp_millis() >= claims.expires_at_ms {
+        return Err(ShareLinkError::Expired);
+    }
+    Ok(claims)
+}
+
+fn signing_key() -> [u8; 32] {
+    crate::encryption::derive_key(KEY_PURPOSE)
+}
+
+/// Create a link token for a machine, valid for `ttl_hours` (capped at MAX_TTL_HOURS).
+pub fn issue(machine_id: &Uuid, ttl_hours: u32) -> (String, DateTime<Utc>) {
+    let now = Utc::now();
+    let expires_at = now + Duration::hours(ttl_hours.clamp(1, MAX_TTL_HOURS) as i64);
+    let claims = ShareClaims {
+        machine_id: *machine_id,
+        issued_at_ms: now.timestamp_millis(),
+        expires_at_ms: expires_at.timestamp_millis(),
+    };
+    (sign(&signing_key(), &claims), expires_at)
+}
+
+/// Verify a link for a machine's status page. The outer error is a database failure.
+pub async fn verify(token: &str, machine_id: &Uuid) -> Result<Result<ShareClaims, ShareLinkError>> {
+    let claims = match decode(&signing_key(), token, Utc::now()) {
+        Ok(claims) => claims,
+        Err(e) => return Ok(Err(e)),
+    };
+    if claims.machine_id != *machine_id {
+        return Ok(Err(ShareLinkError::WrongMachine));
+    }
+    if let Some(cutoff) = crate::db::get_share_links_revoked_before(machine_id).await? {
+        if claims.issued_at_ms <= cutoff.timestamp_millis() {
+            return Ok(Err(ShareLinkError::Revoked));
+        }
+    }
+    Ok(Ok(claims))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(expires_in: Duration) -> ShareClaims {
+        let now = Utc::now();
+        ShareClaims {
+            machine_id: Uuid::new_v4(),
+            issued_at_ms: now.timestamp_millis(),
+            expires_at_ms: (now + expires_in).timestamp_millis(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let claims = claims(Duration::hours(1));
+        let token = sign(b"key", &claims);
+        assert_eq!(decode(b"key", &token, Utc::now()), Ok(claims));
+        assert!(!token.contains(['+', '/', '=']));
+    }
+
+    #[test]
+    fn test_rejections() {
+        let claims = claims(Duration::hours(1));
+        let token = sign(b"key", &claims);
+        assert_eq!(decode(b"other-key", &token, Utc::now()), Err(ShareLinkError::BadSignature));
+        assert_eq!(decode(b"key", &token, Utc::now() + Duration::hours(2)), Err(ShareLinkError::Expired));
+        assert_eq!(decode(b"key", "not-a-token", Utc::now()), Err(ShareLinkError::Malformed));
+
+        // Extending the expiry invalidates the signature
+        let (data, signature) = token.split_once('.').unwrap();
+        let mut bytes = URL_SAFE_NO_PAD.decode(data).unwrap();
+        bytes[31] ^= 1;
+        let tampered = format!("{}.{}", URL_SAFE_NO_PAD.encode(bytes), signature);
+        assert_eq!(decode(b"key", &tampered, Utc::now()), Err(ShareLinkError::BadSignature));
+    }
+}
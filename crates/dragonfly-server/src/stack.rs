@@ -0,0 +1,311 @@
+// Tinkerbell stack conformance: the versions of smee (formerly boots), hegel, tink-server
+// and HookOS actually deployed, checked against the combinations Dragonfly supports.
+//
+// Service versions come from the container images of the deployments in the tink namespace;
+// the HookOS version from the marker written next to its artifacts when they're downloaded.
+// An unsupported combination is flagged in the web UI and by the bare `dragonfly` command,
+// and an upgrade job moves the stack to the recommended versions.
+
+use anyhow::{Context, Result};
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment};
+use k8s_openapi::api::core::v1::PodSpec;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::components::Component;
+use crate::event_manager::EventManager;
+
+const NAMESPACE: &str = "tink";
+pub const HOOKOS_VERSION_FILE: &str = "/var/lib/dragonfly/ipxe-artifacts/hookos/version";
+/// HookOS release downloaded on setup and by the upgrade job
+pub const RECOMMENDED_HOOK_RELEASE: &str = "v0.10.0";
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StackComponent {
+    Smee,
+    Hegel,
+    TinkServer,
+    Hook,
+}
+
+impl StackComponent {
+    pub const ALL: [StackComponent; 4] = [StackComponent::Smee, StackComponent::Hegel, StackComponent::TinkServer, StackComponent::Hook];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StackComponent::Smee => "smee",
+            StackComponent::Hegel => "hegel",
+            StackComponent::TinkServer => "tink-server",
+            StackComponent::Hook => "hook",
+        }
+    }
+
+    // Image names each service has been published under
+    fn from_image_name(name: &str) -> Option<Self> {
+        match name {
+            "smee" | "boots" => Some(StackComponent::Smee),
+            "hegel" => Some(StackComponent::Hegel),
+            "tink" | "tink-server" => Some(StackComponent::TinkServer),
+            _ => None,
+        }
+    }
+}
+
+/// A combination of component release series (major.minor) known to work together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SupportedStack {
+    /// Tinkerbell stack chart release the combination ships with
+    pub name: &'static str,
+    pub smee: &'static str,
+    pub hegel: &'static str,
+    pub tink_server: &'static str,
+    pub hook: &'static str,
+}
+
+impl SupportedStack {
+    fn series(&self, component: StackComponent) -> &'static str {
+        match component {
+            StackComponent::Smee => self.smee,
+            StackComponent::Hegel => self.hegel,
+            StackComponent::TinkServer => self.tink_server,
+            StackComponent::Hook => self.hook,
+        }
+    }
+}
+
+/// Newest first; the first entry is what the upgrade job installs.
+pub const SUPPORT_MATRIX: &[SupportedStack] = &[
+    SupportedStack { name: "stack 0.5", smee: "0.15", hegel: "0.14", tink_server: "0.12", hook: "0.10" },
+    SupportedStack { name: "stack 0.5 (early)", smee: "0.14", hegel: "0.14", tink_server: "0.11", hook: "0.10" },
+    SupportedStack { name: "stack 0.4", smee: "0.11", hegel: "0.12", tink_server: "0.10", hook: "0.9" },
+];
+
+/// A deployed component and its version, when it could be determined.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeployedVersion {
+    pub component: StackComponent,
+    pub version: Option<String>,
+}
+
+/// Result of checking the deployed versions against the support matrix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackReport {
+    pub versions: Vec<DeployedVersion>,
+    pub supported: bool,
+    /// The matrix entry the deployment matches
+    pub matched: Option<&'static str>,
+    /// Components off the recommended series, e.g. "hegel v0.10.0 (recommended 0.14.x)"
+    pub problems: Vec<String>,
+    pub checked_at: chrono::DateTime<chrono::Utc>,
+}
+
+// "v0.15.2", "0.15.2-rc1" or "sha-abc123" -> Some("0.15"), Some("0.15"), None
+fn release_series(version: &str) -> Option<String> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let mut parts = version.split(['.', '-', '+']);
+    let major = parts.next().filter(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))?;
+    let minor = parts.next().filter(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))?;
+    Some(format!("{}.{}", major, minor))
+}
+
+// "quay.io/tinkerbell/smee:v0.15.0" -> ("smee", Some("v0.15.0")); digests carry no version
+fn parse_image(image: &str) -> (&str, Option<&str>) {
+    let image = image.split('@').next().unwrap_or(image);
+    let name_start = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match image[name_start..].split_once(':') {
+        Some((name, tag)) => (name, Some(tag)),
+        None => (&image[name_start..], None),
+    }
+}
+
+/// Check deployed versions against the support matrix. Components whose version couldn't be
+/// determined don't count against a combination.
+pub fn check(versions: Vec<DeployedVersion>) -> StackReport {
+    let series: Vec<(StackComponent, Option<String>)> = versions.iter()
+        .map(|v| (v.component, v.version.as_deref().and_then(release_series)))
+        .collect();
+    let fits = |entry: &SupportedStack| series.iter().all(|(component, s)| match s {
+        Some(s) => s == entry.series(*component),
+        None => true,
+    });
+    let matched = SUPPORT_MATRIX.iter().find(|entry| fits(entry)).map(|entry| entry.name);
+
+    let recommended = &SUPPORT_MATRIX[0];
+    let problems = if matched.is_some() {
+        Vec::new()
+    } else {
+        versions.iter()
+            .zip(&series)
+            .filter_map(|(deployed, (component, s))| {
+                let s = s.as_ref()?;
+                (s != recommended.series(*component)).then(|| format!(
+                    "{} {} (recommended {}.x)",
+                    component.as_str(), deployed.version.as_deref().unwrap_or_default(), recommended.series(*component)
+                ))
+            })
+            .collect()
+    };
+
+    StackReport {
+        versions,
+        supported: matched.is_some(),
+        matched,
+        problems,
+        checked_at: chrono::Utc::now(),
+    }
+}
+
+fn record_images(spec: Option<&PodSpec>, versions: &mut [DeployedVersion]) {
+    let containers = spec.map(|s| s.containers.as_slice()).unwrap_or_default();
+    for image in containers.iter().filter_map(|c| c.image.as_deref()) {
+        let (name, tag) = parse_image(image);
+        if let Some(component) = StackComponent::from_image_name(name) {
+            if let Some(existing) = versions.iter_mut().find(|v| v.component == component) {
+                if existing.version.is_none() {
+                    existing.version = tag.map(str::to_string);
+                }
+            }
+        }
+    }
+}
+
+/// Note which HookOS release the artifacts in `hookos_dir` came from.
+pub fn record_hook_version(hookos_dir: &Path, version: &str) -> std::io::Result<()> {
+    std::fs::write(hookos_dir.join("version"), format!("{}\n", version))
+}
+
+/// The HookOS release currently served to booting machines, if recorded.
+pub async fn served_hook_version() -> Option<String> {
+    tokio::fs::read_to_string(HOOKOS_VERSION_FILE).await.ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Versions deployed in the cluster and on disk.
+pub async fn deployed_versions() -> Result<Vec<DeployedVersion>> {
+    let mut versions: Vec<DeployedVersion> = StackComponent::ALL.into_iter()
+        .map(|component| DeployedVersion { component, version: None })
+        .collect();
+
+    let client = Client::try_default().await.context("Failed to create Kubernetes client")?;
+    let deployments: Api<Deployment> = Api::namespaced(client.clone(), NAMESPACE);
+    for deployment in deployments.list(&ListParams::default()).await.context("Failed to list deployments")?.items {
+        record_images(deployment.spec.as_ref().and_then(|s| s.template.spec.as_ref()), &mut versions);
+    }
+    // Smee runs as a DaemonSet in host-network setups
+    let daemon_sets: Api<DaemonSet> = Api::namespaced(client, NAMESPACE);
+    for daemon_set in daemon_sets.list(&ListParams::default()).await.context("Failed to list daemonsets")?.items {
+        record_images(daemon_set.spec.as_ref().and_then(|s| s.template.spec.as_ref()), &mut versions);
+    }
+
+    let hook = served_hook_version().await;
+    if let Some(entry) = versions.iter_mut().find(|v| v.component == StackComponent::Hook) {
+        entry.version = hook;
+    }
+    Ok(versions)
+}
+
+// The report from the last check, for page templates
+static LAST_REPORT: Lazy<RwLock<Option<StackReport>>> = Lazy::new(|| RwLock::new(None));
+
+pub fn last_report() -> Option<StackReport> {
+    LAST_REPORT.read().ok().and_then(|r| r.clone())
+}
+
+/// Check the deployed stack now and remember the result.
+pub async fn check_deployed() -> Result<StackReport> {
+    let report = check(deployed_versions().await?);
+    if let Ok(mut last) = LAST_REPORT.write() {
+        *last = Some(report.clone());
+    }
+    Ok(report)
+}
+
+/// Upgrade the stack to the recommended versions: the Tinkerbell stack chart from
+/// dragonfly-charts, then HookOS if it's behind.
+pub async fn upgrade() -> Result<StackReport> {
+    info!("Upgrading Tinkerbell stack to {}", SUPPORT_MATRIX[0].name);
+    crate::components::upgrade_release(Component::TinkStack).await?;
+
+    if served_hook_version().await.as_deref() != Some(RECOMMENDED_HOOK_RELEASE) {
+        info!("Downloading HookOS {}", RECOMMENDED_HOOK_RELEASE);
+        crate::api::download_hookos_artifacts(RECOMMENDED_HOOK_RELEASE).await?;
+    }
+    check_deployed().await
+}
+
+/// Periodically re-check the deployed stack, warning when it drifts off the support matrix.
+pub async fn start_stack_check_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        let mut was_supported = true;
+        loop {
+            if crate::leader::is_leader() {
+                match check_deployed().await {
+                    Ok(report) => {
+                        if !report.supported {
+                            warn!("Unsupported Tinkerbell stack versions: {}", report.problems.join(", "));
+                            if was_supported {
+                                let _ = event_manager.send("stack_unsupported".to_string());
+                            }
+                        }
+                        was_supported = report.supported;
+                    }
+                    Err(e) => error!("Failed to check Tinkerbell stack versions: {:#}", e),
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping stack check task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deployed(smee: Option<&str>, hegel: Option<&str>, tink: Option<&str>, hook: Option<&str>) -> Vec<DeployedVersion> {
+        StackComponent::ALL.into_iter()
+            .zip([smee, hegel, tink, hook])
+            .map(|(component, version)| DeployedVersion { component, version: version.map(str::to_string) })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_image() {
+        assert_eq!(parse_image("quay.io/tinkerbell/smee:v0.15.0"), ("smee", Some("v0.15.0")));
+        assert_eq!(parse_image("localhost:5000/tinkerbell/hegel:v0.14.2@sha256:abc"), ("hegel", Some("v0.14.2")));
+        assert_eq!(parse_image("tink"), ("tink", None));
+        assert_eq!(release_series("v0.15.2"), Some("0.15".to_string()));
+        assert_eq!(release_series("0.12.0-rc1"), Some("0.12".to_string()));
+        assert_eq!(release_series("latest"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        let current = check(deployed(Some("v0.15.0"), Some("v0.14.2"), Some("v0.12.1"), Some("v0.10.0")));
+        assert!(current.supported);
+        assert_eq!(current.matched, Some("stack 0.5"));
+
+        let older = check(deployed(Some("v0.11.1"), Some("v0.12.0"), Some("v0.10.0"), None));
+        assert_eq!(older.matched, Some("stack 0.4"));
+
+        let mixed = check(deployed(Some("v0.15.0"), Some("v0.10.0"), Some("v0.12.0"), Some("v0.10.0")));
+        assert!(!mixed.supported);
+        assert_eq!(mixed.problems, vec!["hegel v0.10.0 (recommended 0.14.x)".to_string()]);
+
+        assert!(check(deployed(None, None, None, None)).supported);
+    }
+}
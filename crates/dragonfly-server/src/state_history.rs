@@ -0,0 +1,253 @@
+// Machine status history, for provisioning SLA reporting.
+//
+// Every status change is appended to its own table when it's written, together with the status
+// it replaced. A registration is recorded without a previous status, which is what marks when a
+// machine was discovered. /api/machines/{id}/history shows how long a machine spent in each
+// status, and /api/reports/sla aggregates a window: time from discovery to Ready, and time spent
+// in error states.
+//
+// Like the install log, the history is left alone by retention and outlives the machines it
+// describes, so SLAs stay measurable after machines are deleted or replaced.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use dragonfly_common::models::MachineStatus;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::usage::state_key;
+
+pub const DEFAULT_DAYS: u32 = 30;
+pub const MAX_DAYS: u32 = 3660;
+
+/// One recorded status change.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusChange {
+    pub machine_id: Uuid,
+    /// None when the machine was registered with this status
+    pub from: Option<MachineStatus>,
+    pub to: MachineStatus,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// A stretch of time a machine spent in one status.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub status: MachineStatus,
+    pub state: &'static str,
+    pub entered_at: DateTime<Utc>,
+    /// None while the machine is still in this status
+    pub left_at: Option<DateTime<Utc>>,
+    pub duration_secs: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineHistory {
+    pub machine_id: Uuid,
+    /// When the machine first registered, if that happened since history has been kept
+    pub discovered_at: Option<DateTime<Utc>>,
+    /// When the machine first reached Ready after it was discovered
+    pub first_ready_at: Option<DateTime<Utc>>,
+    /// Oldest first
+    pub entries: Vec<HistoryEntry>,
+    /// Seconds spent in each state, over the whole history
+    pub time_in_state: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DiscoveryToReady {
+    /// Machines discovered in the window
+    pub discovered: usize,
+    /// ...of which have reached Ready
+    pub reached_ready: usize,
+    pub mean_secs: Option<i64>,
+    pub median_secs: Option<i64>,
+    pub max_secs: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ErrorTime {
+    /// Machines that were in an error state at some point in the window
+    pub machines: usize,
+    pub total_secs: i64,
+    /// Mean over the machines that had errors
+    pub mean_secs_per_machine: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlaReport {
+    pub generated_at: DateTime<Utc>,
+    pub since: DateTime<Utc>,
+    pub discovery_to_ready: DiscoveryToReady,
+    pub error_time: ErrorTime,
+    /// Machine-seconds spent in each state within the window
+    pub time_in_state: BTreeMap<String, i64>,
+}
+
+/// Turn one machine's changes (oldest first) into the stretches between them.
+pub fn timeline(changes: &[StatusChange], now: DateTime<Utc>) -> Vec<HistoryEntry> {
+    changes.iter().enumerate()
+        .map(|(i, change)| {
+            let left_at = changes.get(i + 1).map(|next| next.changed_at);
+            HistoryEntry {
+                status: change.to.clone(),
+                state: state_key(&change.to),
+                entered_at: change.changed_at,
+                left_at,
+                duration_secs: (left_at.unwrap_or(now) - change.changed_at).num_seconds().max(0),
+            }
+        })
+        .collect()
+}
+
+// Seconds per state, counting only the part of each entry from `since` on
+fn time_in_state(entries: &[HistoryEntry], since: DateTime<Utc>, now: DateTime<Utc>, totals: &mut BTreeMap<String, i64>) {
+    for entry in entries {
+        let start = entry.entered_at.max(since);
+        let end = entry.left_at.unwrap_or(now);
+        if end > start {
+            *totals.entry(entry.state.to_string()).or_insert(0) += (end - start).num_seconds();
+        }
+    }
+}
+
+// When a machine was discovered and first Ready after that
+fn discovery(changes: &[StatusChange]) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let Some(start) = changes.iter().rposition(|c| c.from.is_none()) else {
+        return (None, None);
+    };
+    let discovered_at = changes[start].changed_at;
+    let ready_at = changes[start..].iter()
+        .find(|c| c.to == MachineStatus::Ready)
+        .map(|c| c.changed_at);
+    (Some(discovered_at), ready_at)
+}
+
+pub fn machine_history(machine_id: Uuid, changes: &[StatusChange], now: DateTime<Utc>) -> MachineHistory {
+    let entries = timeline(changes, now);
+    let mut totals = BTreeMap::new();
+    time_in_state(&entries, DateTime::<Utc>::MIN_UTC, now, &mut totals);
+    let (discovered_at, first_ready_at) = discovery(changes);
+    MachineHistory { machine_id, discovered_at, first_ready_at, entries, time_in_state: totals }
+}
+
+/// Aggregate changes for many machines; each machine's changes must be oldest first, and may
+/// start with the last change before `since` so the status it was in at the start is known.
+pub fn sla_report(changes: &[StatusChange], since: DateTime<Utc>, now: DateTime<Utc>) -> SlaReport {
+    let mut by_machine: BTreeMap<Uuid, Vec<StatusChange>> = BTreeMap::new();
+    for change in changes {
+        by_machine.entry(change.machine_id).or_default().push(change.clone());
+    }
+
+    let mut totals = BTreeMap::new();
+    let mut to_ready = Vec::new();
+    let mut discovered = 0;
+    let mut error_time = ErrorTime::default();
+    for machine_changes in by_machine.values() {
+        let entries = timeline(machine_changes, now);
+        let mut machine_totals = BTreeMap::new();
+        time_in_state(&entries, since, now, &mut machine_totals);
+        if let Some(secs) = machine_totals.get("error").filter(|secs| **secs > 0) {
+            error_time.machines += 1;
+            error_time.total_secs += secs;
+        }
+        for (state, secs) in machine_totals {
+            *totals.entry(state).or_insert(0) += secs;
+        }
+
+        if let (Some(discovered_at), ready_at) = discovery(machine_changes) {
+            if discovered_at >= since {
+                discovered += 1;
+                if let Some(ready_at) = ready_at {
+                    to_ready.push((ready_at - discovered_at).num_seconds().max(0));
+                }
+            }
+        }
+    }
+    if error_time.machines > 0 {
+        error_time.mean_secs_per_machine = Some(error_time.total_secs / error_time.machines as i64);
+    }
+
+    to_ready.sort_unstable();
+    let discovery_to_ready = DiscoveryToReady {
+        discovered,
+        reached_ready: to_ready.len(),
+        mean_secs: (!to_ready.is_empty()).then(|| to_ready.iter().sum::<i64>() / to_ready.len() as i64),
+        median_secs: to_ready.get(to_ready.len() / 2).copied(),
+        max_secs: to_ready.last().copied(),
+    };
+
+    SlaReport { generated_at: now, since, discovery_to_ready, error_time, time_in_state: totals }
+}
+
+pub async fn history(machine_id: &Uuid) -> Result<MachineHistory> {
+    let changes = crate::db::list_status_changes(machine_id).await?;
+    Ok(machine_history(*machine_id, &changes, Utc::now()))
+}
+
+pub async fn report(days: u32) -> Result<SlaReport> {
+    let now = Utc::now();
+    let since = now - Duration::days(days.clamp(1, MAX_DAYS) as i64);
+    let changes = crate::db::status_changes_since(since).await?;
+    Ok(sla_report(&changes, since, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn change(machine: u128, from: Option<MachineStatus>, to: MachineStatus, minute: i64) -> StatusChange {
+        let start = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        StatusChange { machine_id: Uuid::from_u128(machine), from, to, changed_at: start + Duration::minutes(minute) }
+    }
+
+    #[test]
+    fn test_machine_history() {
+        let changes = vec![
+            change(1, None, MachineStatus::AwaitingAssignment, 0),
+            change(1, Some(MachineStatus::AwaitingAssignment), MachineStatus::InstallingOS, 10),
+            change(1, Some(MachineStatus::InstallingOS), MachineStatus::Error("disk".to_string()), 20),
+            change(1, Some(MachineStatus::Error("disk".to_string())), MachineStatus::Ready, 50),
+        ];
+        let now = changes[3].changed_at + Duration::minutes(5);
+        let history = machine_history(Uuid::from_u128(1), &changes, now);
+
+        assert_eq!(history.entries.len(), 4);
+        assert_eq!(history.entries[0].duration_secs, 600);
+        assert_eq!(history.entries[3].left_at, None);
+        assert_eq!(history.entries[3].duration_secs, 300);
+        assert_eq!(history.time_in_state["error"], 1800);
+        assert_eq!(history.first_ready_at.unwrap() - history.discovered_at.unwrap(), Duration::minutes(50));
+    }
+
+    #[test]
+    fn test_sla_report() {
+        let changes = vec![
+            // Discovered in the window and Ready after 30 minutes
+            change(1, None, MachineStatus::AwaitingAssignment, 100),
+            change(1, Some(MachineStatus::AwaitingAssignment), MachineStatus::Ready, 130),
+            // Discovered in the window, not Ready yet
+            change(2, None, MachineStatus::AwaitingAssignment, 110),
+            // In error since before the window, fixed 20 minutes into it
+            change(3, Some(MachineStatus::Ready), MachineStatus::Error("boot".to_string()), 0),
+            change(3, Some(MachineStatus::Error("boot".to_string())), MachineStatus::Ready, 80),
+        ];
+        let start = changes[3].changed_at;
+        let since = start + Duration::minutes(60);
+        let now = start + Duration::minutes(140);
+        let report = sla_report(&changes, since, now);
+
+        assert_eq!(report.discovery_to_ready, DiscoveryToReady {
+            discovered: 2,
+            reached_ready: 1,
+            mean_secs: Some(1800),
+            median_secs: Some(1800),
+            max_secs: Some(1800),
+        });
+        assert_eq!(report.error_time, ErrorTime { machines: 1, total_secs: 1200, mean_secs_per_machine: Some(1200) });
+        assert_eq!(report.time_in_state["awaiting_assignment"], 1800 + 1800);
+        assert_eq!(report.time_in_state["ready"], 600 + 3600);
+    }
+}
@@ -2,7 +2,9 @@ use color_eyre::eyre::{Result, eyre};
 use color_eyre::eyre::WrapErr;
 use kube::{Client, Api, Error as KubeError};
 use k8s_openapi::api::apps::v1::StatefulSet;
-use k8s_openapi::api::core::v1::{Service};
+use k8s_openapi::api::core::v1::{Node, Service};
+use kube::api::ListParams;
+use serde::Serialize;
 use tracing::{debug, warn, info};
 
 const DRAGONFLY_NAMESPACE: &str = "tink";
@@ -169,4 +171,57 @@ pub async fn get_webui_address() -> Result<Option<String>> {
             Err(e).wrap_err_with(|| format!("Failed to get Service '{}' in namespace '{}'", service_name, WEBUI_NAMESPACE))
         }
     }
-} 
\ No newline at end of file
+} 
+/// Health of one node in the k3s cluster.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    pub name: String,
+    /// e.g. "control-plane", "etcd", "master"; empty for agents
+    pub roles: Vec<String>,
+    pub ready: bool,
+    pub kubelet_version: Option<String>,
+    pub internal_ip: Option<String>,
+}
+
+impl NodeStatus {
+    /// Whether this node runs the control plane (k3s server) rather than just workloads.
+    pub fn is_server(&self) -> bool {
+        self.roles.iter().any(|r| r == "control-plane" || r == "master")
+    }
+}
+
+fn node_status(node: &Node) -> NodeStatus {
+    let roles = node.metadata.labels.iter()
+        .flatten()
+        .filter_map(|(key, _)| key.strip_prefix("node-role.kubernetes.io/"))
+        .map(str::to_string)
+        .collect();
+    let status = node.status.as_ref();
+    let ready = status
+        .and_then(|s| s.conditions.as_ref())
+        .and_then(|conditions| conditions.iter().find(|c| c.type_ == "Ready"))
+        .map_or(false, |c| c.status == "True");
+    let internal_ip = status
+        .and_then(|s| s.addresses.as_ref())
+        .and_then(|addresses| addresses.iter().find(|a| a.type_ == "InternalIP"))
+        .map(|a| a.address.clone());
+
+    NodeStatus {
+        name: node.metadata.name.clone().unwrap_or_default(),
+        roles,
+        ready,
+        kubelet_version: status.and_then(|s| s.node_info.as_ref()).map(|i| i.kubelet_version.clone()),
+        internal_ip,
+    }
+}
+
+/// Lists every node in the cluster with its role and readiness.
+pub async fn get_cluster_nodes() -> Result<Vec<NodeStatus>> {
+    let client = Client::try_default().await.wrap_err("Failed to create Kubernetes client")?;
+    let nodes: Api<Node> = Api::all(client);
+    let list = nodes.list(&ListParams::default()).await.wrap_err("Failed to list cluster nodes")?;
+
+    let mut statuses: Vec<NodeStatus> = list.items.iter().map(node_status).collect();
+    statuses.sort_by(|a, b| b.is_server().cmp(&a.is_server()).then_with(|| a.name.cmp(&b.name)));
+    Ok(statuses)
+}
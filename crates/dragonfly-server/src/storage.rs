@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::event_manager::EventManager;
+
+// Default free-space floor for the artifact and database volumes (5 GiB)
+const DEFAULT_MIN_FREE_MB: u64 = 5 * 1024;
+const MIN_FREE_ENV_VAR: &str = "DRAGONFLY_MIN_FREE_SPACE_MB";
+
+// How often the monitor re-checks free space
+const MONITOR_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Free-space snapshot for one monitored volume.
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageStatus {
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+    pub threshold_bytes: u64,
+    pub low: bool,
+}
+
+/// Minimum free space required before new downloads/installs are allowed.
+pub fn min_free_bytes() -> u64 {
+    crate::config::var(MIN_FREE_ENV_VAR)
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MIN_FREE_MB)
+        * 1024 * 1024
+}
+
+// Walk up to the nearest existing ancestor so we can stat directories that
+// haven't been created yet (e.g. the artifact cache on a fresh install)
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.exists())
+}
+
+fn check_path(name: &'static str, path: &Path, threshold_bytes: u64) -> Result<StorageStatus> {
+    let target = existing_ancestor(path)
+        .ok_or_else(|| anyhow!("No existing ancestor for {}", path.display()))?;
+    let stat = nix::sys::statvfs::statvfs(target)
+        .map_err(|e| anyhow!("statvfs failed for {}: {}", target.display(), e))?;
+
+    let fragment = stat.fragment_size() as u64;
+    let free_bytes = stat.blocks_available() as u64 * fragment;
+    let total_bytes = stat.blocks() as u64 * fragment;
+
+    Ok(StorageStatus {
+        name,
+        path: path.to_path_buf(),
+        free_bytes,
+        total_bytes,
+        threshold_bytes,
+        low: free_bytes < threshold_bytes,
+    })
+}
+
+/// Check the artifact directory and the database volume.
+pub fn check_storage() -> Vec<StorageStatus> {
+    let threshold = min_free_bytes();
+    // A bare file name (the default "sqlite.db") lives in the working directory
+    let db_dir = Path::new(&crate::config::get().storage.db_path).parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    let volumes = [
+        ("artifacts", crate::api::artifact_base_dir()),
+        ("database", db_dir),
+    ];
+
+    volumes.iter()
+        .filter_map(|(name, path)| match check_path(name, path, threshold) {
+            Ok(status) => Some(status),
+            Err(e) => {
+                warn!("Failed to check free space for {}: {}", name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the first volume that is below the free-space threshold, if any.
+pub fn low_storage() -> Option<StorageStatus> {
+    check_storage().into_iter().find(|s| s.low)
+}
+
+/// Human-readable explanation for refusing work because of low storage.
+pub fn low_storage_message(status: &StorageStatus) -> String {
+    format!(
+        "Insufficient storage on {} volume ({}): {} MB free, {} MB required",
+        status.name,
+        status.path.display(),
+        status.free_bytes / (1024 * 1024),
+        status.threshold_bytes / (1024 * 1024)
+    )
+}
+
+/// Periodically check free space and raise an alert when a volume crosses the threshold.
+pub async fn start_storage_monitor_task(
+    event_manager: Arc<EventManager>,
+    mut shutdown_rx: tokio::sync::watch::Receiver<()>,
+) {
+    tokio::spawn(async move {
+        let mut was_low = false;
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(MONITOR_INTERVAL) => {
+                    let low = low_storage();
+                    match (&low, was_low) {
+                        (Some(status), false) => {
+                            warn!("{}", low_storage_message(status));
+                            let _ = event_manager.send(format!("storage_low:{}:{}", status.name, status.free_bytes));
+                        },
+                        (None, true) => {
+                            info!("Free space is back above the threshold on all volumes");
+                            let _ = event_manager.send("storage_ok".to_string());
+                        },
+                        _ => {}
+                    }
+                    was_low = low.is_some();
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping storage monitor task.");
+                    break;
+                }
+            }
+        }
+    });
+}
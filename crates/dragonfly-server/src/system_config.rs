@@ -0,0 +1,147 @@
+// Timezone, NTP servers and locale for installed systems.
+//
+// Set globally (in /api/settings) and per tag, so per fleet group; each field is taken from
+// the first of the machine's tags (by name) that sets it, else from the global settings.
+// The result reaches install templates through the workflow's hardware map, where the
+// bundled templates write it into the installed OS's cloud-init config.
+
+use anyhow::{bail, Result};
+use dragonfly_common::models::Machine;
+use serde::{Deserialize, Serialize};
+
+// More than this is a misconfiguration rather than redundancy
+const MAX_NTP_SERVERS: usize = 8;
+
+/// Unset fields leave whatever the image ships with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemConfig {
+    /// IANA zone name, e.g. "Australia/Brisbane" or "UTC"
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub ntp_servers: Vec<String>,
+    /// e.g. "en_AU.UTF-8"
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+impl SystemConfig {
+    pub fn is_empty(&self) -> bool {
+        self.timezone.is_none() && self.ntp_servers.is_empty() && self.locale.is_none()
+    }
+
+    /// Trim fields, dropping empty ones, and check they're safe to put in a cloud-init file.
+    pub fn normalize(mut self) -> Result<Self> {
+        let trim = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        self.timezone = trim(self.timezone);
+        self.locale = trim(self.locale);
+        self.ntp_servers = self.ntp_servers.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+
+        if let Some(timezone) = &self.timezone {
+            if timezone.len() > 64 || timezone.starts_with('/') || timezone.split('/').any(|part| part == "..")
+                || !timezone.chars().all(|c| c.is_ascii_alphanumeric() || "/_+-".contains(c))
+            {
+                bail!("Invalid timezone '{}'", timezone);
+            }
+        }
+        if let Some(locale) = &self.locale {
+            if locale.len() > 64 || !locale.chars().all(|c| c.is_ascii_alphanumeric() || "_.@-".contains(c)) {
+                bail!("Invalid locale '{}'", locale);
+            }
+        }
+        if self.ntp_servers.len() > MAX_NTP_SERVERS {
+            bail!("At most {} NTP servers can be set", MAX_NTP_SERVERS);
+        }
+        for server in &self.ntp_servers {
+            if server.len() > 253 || !server.chars().all(|c| c.is_ascii_alphanumeric() || ".-:".contains(c)) {
+                bail!("Invalid NTP server '{}'", server);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Values for the workflow hardware map; empty strings for unset fields, which templates test for.
+    pub fn hardware_map(&self) -> [(&'static str, String); 3] {
+        [
+            ("timezone", self.timezone.clone().unwrap_or_default()),
+            // Dropped into a YAML flow sequence by the templates
+            ("ntp_servers", self.ntp_servers.join(", ")),
+            ("locale", self.locale.clone().unwrap_or_default()),
+        ]
+    }
+}
+
+/// Each field from the first of the machine's tags (by name) that sets it, else the global setting.
+pub fn resolve(global: &SystemConfig, tag_configs: &[(String, SystemConfig)], machine_tags: &[String]) -> SystemConfig {
+    let mut matching: Vec<&(String, SystemConfig)> = tag_configs.iter()
+        .filter(|(tag, _)| machine_tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+        .collect();
+    matching.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let timezone = matching.iter().find_map(|(_, c)| c.timezone.clone()).or_else(|| global.timezone.clone());
+    let locale = matching.iter().find_map(|(_, c)| c.locale.clone()).or_else(|| global.locale.clone());
+    let ntp_servers = matching.iter()
+        .map(|(_, c)| &c.ntp_servers)
+        .find(|servers| !servers.is_empty())
+        .unwrap_or(&global.ntp_servers)
+        .clone();
+    SystemConfig { timezone, ntp_servers, locale }
+}
+
+/// A machine's effective timezone, NTP and locale settings.
+pub async fn for_machine(machine: &Machine) -> Result<SystemConfig> {
+    let global = crate::db::get_app_settings().await?.system;
+    let tag_configs = crate::db::list_tag_system_configs().await?;
+    if tag_configs.is_empty() {
+        return Ok(global);
+    }
+    let tags = crate::db::get_machine_tags(&machine.id).await?;
+    Ok(resolve(&global, &tag_configs, &tags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(timezone: Option<&str>, ntp: &[&str], locale: Option<&str>) -> SystemConfig {
+        SystemConfig {
+            timezone: timezone.map(str::to_string),
+            ntp_servers: ntp.iter().map(|s| s.to_string()).collect(),
+            locale: locale.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalized = config(Some(" Australia/Brisbane "), &["pool.ntp.org", " "], Some("")).normalize().unwrap();
+        assert_eq!(normalized, config(Some("Australia/Brisbane"), &["pool.ntp.org"], None));
+        assert!(config(Some("../../etc/shadow"), &[], None).normalize().is_err());
+        assert!(config(None, &["ntp1\n  runcmd: [reboot]"], None).normalize().is_err());
+        assert!(config(None, &[], Some("en_AU.UTF-8")).normalize().is_ok());
+        assert!(config(None, &[], None).normalize().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tags_override_global_per_field() {
+        let global = config(Some("UTC"), &["ntp.example.com"], Some("en_US.UTF-8"));
+        let tags = vec![
+            ("render".to_string(), config(Some("Europe/Berlin"), &[], None)),
+            ("lab".to_string(), config(Some("Asia/Tokyo"), &["10.0.0.1"], None)),
+        ];
+
+        // "lab" sorts first, so its timezone wins; locale still comes from global
+        let resolved = resolve(&global, &tags, &["Render".to_string(), "lab".to_string()]);
+        assert_eq!(resolved, config(Some("Asia/Tokyo"), &["10.0.0.1"], Some("en_US.UTF-8")));
+        let resolved = resolve(&global, &tags, &["render".to_string()]);
+        assert_eq!(resolved, config(Some("Europe/Berlin"), &["ntp.example.com"], Some("en_US.UTF-8")));
+        assert_eq!(resolve(&global, &tags, &[]), global);
+    }
+
+    #[test]
+    fn test_hardware_map() {
+        let map = config(Some("UTC"), &["a.example.com", "10.0.0.1"], None).hardware_map();
+        assert_eq!(map[0], ("timezone", "UTC".to_string()));
+        assert_eq!(map[1], ("ntp_servers", "a.example.com, 10.0.0.1".to_string()));
+        assert_eq!(map[2], ("locale", String::new()));
+    }
+}
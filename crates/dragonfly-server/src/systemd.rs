@@ -0,0 +1,244 @@
+// systemd integration for bare-metal deployments.
+//
+// Run as a Type=notify service, the server tells systemd it's ready once it's listening (on the
+// socket systemd passed in, when socket activated, see lib.rs), pings the watchdog at half of
+// WatchdogSec, and says when it's stopping. SIGHUP (`systemctl reload dragonfly`) reloads without
+// dropping connections: settings are re-read from the database and DRAGONFLY_LOG_FILE is
+// reopened, so logrotate can move it away. dragonfly.toml is only read at startup; a reload
+// warns when it has been edited since. Outside systemd (no NOTIFY_SOCKET) notifications are
+// no-ops.
+//
+// `dragonfly install --systemd` writes the units below.
+
+use anyhow::{Context, Result};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{debug, error, info, warn};
+
+use crate::AppState;
+
+pub const UNIT_DIR: &str = "/etc/systemd/system";
+
+/// Send a notification such as "READY=1" to systemd. Returns false when not running under
+/// systemd or the message couldn't be sent.
+pub fn notify(state: &str) -> bool {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        let path = path.to_string_lossy();
+        match path.strip_prefix('@') {
+            // A socket in the abstract namespace
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+                socket.send_to_addr(state.as_bytes(), &addr)
+            }
+            _ => socket.send_to(state.as_bytes(), path.as_ref()),
+        }
+    });
+    match sent {
+        Ok(_) => true,
+        Err(e) => {
+            debug!("Failed to notify systemd ({}): {}", state.lines().next().unwrap_or_default(), e);
+            false
+        }
+    }
+}
+
+/// How often to ping the watchdog: half of WatchdogSec, if systemd set one for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_interval_from(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn watchdog_interval_from(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    // Meant for another process (a child inherited the environment)
+    if pid.is_some_and(|pid| pid.trim().parse::<u32>().ok() != Some(own_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.trim().parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Ping the systemd watchdog until shutdown. The pings come from the async runtime, so a
+/// wedged runtime stops them and systemd restarts the server.
+pub async fn start_watchdog_task(mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let Some(period) = watchdog_interval() else {
+        return;
+    };
+    info!("systemd watchdog enabled, pinging every {}ms", period.as_millis());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    notify("WATCHDOG=1");
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping systemd watchdog task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+// CLOCK_MONOTONIC in microseconds, which systemd wants alongside RELOADING=1
+fn monotonic_usec() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: clock_gettime only writes to the timespec it's given
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000
+}
+
+/// Re-read what can change without a restart.
+pub async fn reload(state: &AppState) {
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()));
+    info!("Reloading configuration");
+
+    match crate::logs::reopen_log_file() {
+        Ok(true) => info!("Reopened log file"),
+        Ok(false) => {}
+        Err(e) => error!("Failed to reopen log file: {}", e),
+    }
+
+    let mut status = "Reloaded".to_string();
+    match state.repos.settings.get().await {
+        Ok(settings) => *state.settings.lock().await = settings,
+        Err(e) => {
+            error!("Failed to reload settings: {}", e);
+            status = format!("Reload failed: {}", e);
+        }
+    }
+
+    match crate::config::file_changed() {
+        Ok(Some(path)) => warn!("{} has changed since startup; restart Dragonfly to apply it", path.display()),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to check the configuration file: {:#}", e),
+    }
+
+    notify(&format!("READY=1\nSTATUS={}", status));
+}
+
+/// Reload on SIGHUP until shutdown.
+pub async fn start_reload_handler(state: AppState, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    info!("Received SIGHUP");
+                    reload(&state).await;
+                }
+                _ = shutdown_rx.changed() => {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// The service unit. `exec_start` is the full command line.
+pub fn service_unit(name: &str, description: &str, exec_start: &str) -> String {
+    format!(
+        r#"[Unit]
+Description={description}
+Documentation=https://github.com/Zorlin/dragonfly
+After=network-online.target
+Wants=network-online.target
+Requires={name}.socket
+
+[Service]
+Type=notify
+NotifyAccess=main
+Environment="DRAGONFLY_SERVICE=1"
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+RestartSec=1
+# The server pings the watchdog at half this interval
+WatchdogSec=30
+# In-flight artifact streams get DRAGONFLY_SHUTDOWN_GRACE_SECS to finish
+TimeoutStopSec=60
+
+# Hardening options
+ProtectSystem=full
+ProtectHome=read-only
+PrivateTmp=true
+NoNewPrivileges=true
+
+[Install]
+WantedBy=multi-user.target
+"#
+    )
+}
+
+/// The socket unit, listening on the server's port so it can be restarted without refusing
+/// connections.
+pub fn socket_unit(description: &str, port: u16) -> String {
+    format!(
+        r#"[Unit]
+Description={description} Socket
+
+[Socket]
+ListenStream={port}
+# Hand the listening socket to a single server process
+Accept=no
+
+[Install]
+WantedBy=sockets.target
+"#
+    )
+}
+
+/// Write both units into `dir`, returning their paths.
+pub fn write_units(dir: &Path, name: &str, description: &str, exec_start: &str, port: u16) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let units = [
+        (dir.join(format!("{}.socket", name)), socket_unit(description, port)),
+        (dir.join(format!("{}.service", name)), service_unit(name, description, exec_start)),
+    ];
+    let mut written = Vec::new();
+    for (path, content) in units {
+        std::fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(watchdog_interval_from(Some("30000000"), None, 42), Some(Duration::from_secs(15)));
+        assert_eq!(watchdog_interval_from(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(15)));
+        assert_eq!(watchdog_interval_from(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(watchdog_interval_from(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval_from(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_units() {
+        let service = service_unit("dragonfly", "Dragonfly", "/usr/local/bin/dragonfly server");
+        assert!(service.contains("Type=notify\n"));
+        assert!(service.contains("Requires=dragonfly.socket\n"));
+        assert!(service.contains("ExecStart=/usr/local/bin/dragonfly server\n"));
+        assert!(service.contains("ExecReload=/bin/kill -HUP $MAINPID\n"));
+        assert!(socket_unit("Dragonfly", 8080).contains("ListenStream=8080\n"));
+    }
+}
@@ -0,0 +1,502 @@
+// Linting for custom Tinkerbell templates, run by POST /api/templates/validate before a
+// template is installed and assigned to machines.
+//
+// A template is accepted either as a Template resource (the form in os-templates/) or as the
+// bare workflow definition that goes in its spec.data. Go template actions are masked out so
+// the workflow itself can be parsed as YAML; the variables they reference are checked against
+// what Dragonfly puts in each workflow's hardwareMap, and action images are looked up in their
+// registries.
+
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+// Stands in for template actions so the workflow parses; unlikely to appear in a real template
+const PLACEHOLDER: &str = "__dragonfly_template__";
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Workflow hardwareMap entries Dragonfly sets, plus what Tinkerbell itself provides.
+pub const PROVIDED_VARIABLES: &[&str] = &[
+    "Hardware",
+    "device_1",
+    "vlan_id",
+    "kernel_args",
+    "timezone",
+    "ntp_servers",
+    "locale",
+    "disk_layout",
+    "multi_disk_mode",
+    "multi_disk_targets",
+    "motd_b64",
+];
+
+// Placeholders Dragonfly substitutes itself before installing a template
+const SUBSTITUTED: [&str; 2] = ["{{ base_url }}", "{{ base_url_bare }}"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The template can't be installed or won't run
+    Error,
+    /// The template may work, but something looks off or couldn't be checked
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Stable identifier, e.g. "unknown_variable"
+    pub code: &'static str,
+    pub message: String,
+    /// e.g. "tasks[0].actions[2]"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl Finding {
+    fn error(code: &'static str, message: String, location: Option<String>) -> Self {
+        Finding { severity: Severity::Error, code, message, location }
+    }
+
+    fn warning(code: &'static str, message: String, location: Option<String>) -> Self {
+        Finding { severity: Severity::Warning, code, message, location }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    /// True when there are no errors; warnings don't block a template
+    pub valid: bool,
+    pub name: Option<String>,
+    pub images: Vec<String>,
+    pub variables: Vec<String>,
+    pub findings: Vec<Finding>,
+}
+
+/// A parsed container image reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageRef {
+    pub registry: String,
+    pub repository: String,
+    /// Tag or digest
+    pub reference: String,
+}
+
+impl ImageRef {
+    /// Parse a reference the way Docker does: a first component with a dot or port (or
+    /// "localhost") is the registry, otherwise it's Docker Hub.
+    pub fn parse(image: &str) -> Option<Self> {
+        if image.is_empty() || image.chars().any(|c| c.is_whitespace() || c.is_ascii_uppercase()) {
+            return None;
+        }
+        let (name, reference) = match image.split_once('@') {
+            Some((name, digest)) => (name, digest.to_string()),
+            None => {
+                let last_slash = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+                match image[last_slash..].rfind(':') {
+                    Some(colon) => (&image[..last_slash + colon], image[last_slash + colon + 1..].to_string()),
+                    None => (image, "latest".to_string()),
+                }
+            }
+        };
+        if reference.is_empty() {
+            return None;
+        }
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+                (first.to_string(), rest.to_string())
+            }
+            Some(_) => ("docker.io".to_string(), name.to_string()),
+            None => ("docker.io".to_string(), format!("library/{}", name)),
+        };
+        if repository.is_empty() || repository.split('/').any(str::is_empty) {
+            return None;
+        }
+        Some(ImageRef { registry, repository, reference })
+    }
+
+    fn registry_host(&self) -> &str {
+        if self.registry == "docker.io" { "registry-1.docker.io" } else { &self.registry }
+    }
+}
+
+/// How looking up an image in its registry went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Found,
+    NotFound,
+    /// The registry wants credentials we don't have
+    Unauthorized,
+    Unreachable(String),
+}
+
+// Pull the quoted parameters out of a `WWW-Authenticate: Bearer realm="...",service="..."` header
+fn bearer_challenge(header: &str) -> Option<Vec<(String, String)>> {
+    let params = header.strip_prefix("Bearer ")?;
+    Some(params.split(',')
+        .filter_map(|param| param.trim().split_once('='))
+        .map(|(key, value)| (key.to_string(), value.trim_matches('"').to_string()))
+        .collect())
+}
+
+// Fetch an anonymous pull token for a registry that answered with a Bearer challenge
+async fn anonymous_token(client: &reqwest::Client, challenge: &str) -> Option<String> {
+    let params = bearer_challenge(challenge)?;
+    let realm = params.iter().find(|(k, _)| k == "realm").map(|(_, v)| v.clone())?;
+    let query: Vec<&(String, String)> = params.iter().filter(|(k, _)| k != "realm").collect();
+    let response = client.get(&realm).query(&query).send().await.ok()?;
+    let body: serde_json::Value = response.json().await.ok()?;
+    body.get("token").or_else(|| body.get("access_token"))?.as_str().map(str::to_string)
+}
+
+/// Check an image's manifest exists, following the registry's anonymous token flow.
+pub async fn resolve_image(client: &reqwest::Client, image: &ImageRef) -> Resolution {
+    const ACCEPT: &str = "application/vnd.oci.image.index.v1+json, application/vnd.docker.distribution.manifest.list.v2+json, \
+        application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+    let url = format!("https://{}/v2/{}/manifests/{}", image.registry_host(), image.repository, image.reference);
+    let head = |token: Option<&str>| {
+        let mut request = client.head(&url).header(reqwest::header::ACCEPT, ACCEPT).timeout(REGISTRY_TIMEOUT);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send()
+    };
+
+    let mut response = match head(None).await {
+        Ok(response) => response,
+        Err(e) => return Resolution::Unreachable(e.to_string()),
+    };
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        let challenge = response.headers().get(reqwest::header::WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        let token = match challenge {
+            Some(challenge) => anonymous_token(client, &challenge).await,
+            None => None,
+        };
+        let Some(token) = token else {
+            return Resolution::Unauthorized;
+        };
+        response = match head(Some(&token)).await {
+            Ok(response) => response,
+            Err(e) => return Resolution::Unreachable(e.to_string()),
+        };
+    }
+
+    match response.status() {
+        status if status.is_success() => Resolution::Found,
+        reqwest::StatusCode::NOT_FOUND => Resolution::NotFound,
+        // Docker Hub answers 401 rather than 404 for repositories that don't exist
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Resolution::Unauthorized,
+        status => Resolution::Unreachable(format!("registry answered {}", status)),
+    }
+}
+
+/// Variables a template's actions reference, by their first field: `{{ index .Hardware.Disks 0 }}`
+/// references "Hardware".
+pub fn template_variables(data: &str) -> BTreeSet<String> {
+    let mut variables = BTreeSet::new();
+    let mut rest = data;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else { break };
+        let action = &rest[start + 2..start + end];
+        let mut chars = action.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            // A dot that starts a field chain, not one in the middle of `$x.y` or a number
+            let preceded_by_word = action[..i].chars().next_back().is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '$' || p == '.');
+            if c != '.' || preceded_by_word {
+                continue;
+            }
+            let field: String = action[i + 1..].chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+            if field.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_') {
+                variables.insert(field);
+            }
+            while chars.peek().is_some_and(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '.') {
+                chars.next();
+            }
+        }
+        rest = &rest[start + end + 2..];
+    }
+    variables
+}
+
+// Replace template actions so the workflow parses as YAML: lines holding nothing but control
+// actions ({{ if }}, {{ end }}, ...) are blanked and inline ones become a placeholder.
+fn mask_actions(data: &str) -> Result<String, String> {
+    let mut masked = String::with_capacity(data.len());
+    for (number, line) in data.lines().enumerate() {
+        let mut out = String::new();
+        let mut rest = line;
+        let mut only_actions = true;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                return Err(format!("Unclosed template action on line {}", number + 1));
+            };
+            only_actions &= rest[..start].trim().is_empty();
+            out.push_str(&rest[..start]);
+            out.push_str(PLACEHOLDER);
+            rest = &rest[start + end + 2..];
+        }
+        if rest.contains("}}") {
+            return Err(format!("Unopened template action on line {}", number + 1));
+        }
+        only_actions &= rest.trim().is_empty();
+        out.push_str(rest);
+        if only_actions && out.contains(PLACEHOLDER) {
+            out.clear();
+        }
+        masked.push_str(&out);
+        masked.push('\n');
+    }
+    Ok(masked)
+}
+
+// The workflow definition and template name, from either a Template resource or bare workflow
+fn workflow_data(yaml: &str, findings: &mut Vec<Finding>) -> Option<(Option<String>, String)> {
+    let document: Option<Value> = serde_yaml::from_str(yaml).ok();
+    let is_resource = document.as_ref().is_some_and(|d| d.get("kind").is_some());
+    if !is_resource {
+        return Some((None, yaml.to_string()));
+    }
+    let document = document?;
+
+    if document.get("kind").and_then(Value::as_str) != Some("Template") {
+        findings.push(Finding::error("wrong_kind", "Resource kind must be Template".to_string(), Some("kind".to_string())));
+    }
+    if document.get("apiVersion").and_then(Value::as_str) != Some("tinkerbell.org/v1alpha1") {
+        findings.push(Finding::warning("api_version", "apiVersion should be tinkerbell.org/v1alpha1".to_string(), Some("apiVersion".to_string())));
+    }
+    let name = document.get("metadata").and_then(|m| m.get("name")).and_then(Value::as_str).map(str::to_string);
+    if name.is_none() {
+        findings.push(Finding::error("missing_field", "metadata.name is required".to_string(), Some("metadata.name".to_string())));
+    }
+    match document.get("spec").and_then(|s| s.get("data")).and_then(Value::as_str) {
+        Some(data) => Some((name, data.to_string())),
+        None => {
+            findings.push(Finding::error("missing_field", "spec.data must hold the workflow definition".to_string(), Some("spec.data".to_string())));
+            None
+        }
+    }
+}
+
+fn require_str<'a>(value: &'a Value, field: &str, location: &str, findings: &mut Vec<Finding>) -> Option<&'a str> {
+    match value.get(field) {
+        Some(Value::String(s)) if !s.trim().is_empty() => Some(s),
+        Some(Value::String(_)) | None => {
+            findings.push(Finding::error("missing_field", format!("{} is required", field), Some(format!("{}.{}", location, field))));
+            None
+        }
+        Some(_) => {
+            findings.push(Finding::error("wrong_type", format!("{} must be a string", field), Some(format!("{}.{}", location, field))));
+            None
+        }
+    }
+}
+
+// Structural checks on the masked workflow; returns action images with their locations
+fn check_workflow(workflow: &Value, findings: &mut Vec<Finding>) -> Vec<(String, String)> {
+    let mut images = Vec::new();
+    if !workflow.is_mapping() {
+        findings.push(Finding::error("invalid_workflow", "The workflow definition must be a mapping".to_string(), None));
+        return images;
+    }
+    require_str(workflow, "name", "workflow", findings);
+    if workflow.get("global_timeout").and_then(Value::as_u64).is_none() {
+        findings.push(Finding::warning("missing_timeout", "global_timeout isn't set".to_string(), Some("global_timeout".to_string())));
+    }
+
+    let tasks = match workflow.get("tasks").and_then(Value::as_sequence) {
+        Some(tasks) if !tasks.is_empty() => tasks,
+        _ => {
+            findings.push(Finding::error("missing_field", "tasks must list at least one task".to_string(), Some("tasks".to_string())));
+            return images;
+        }
+    };
+    for (t, task) in tasks.iter().enumerate() {
+        let location = format!("tasks[{}]", t);
+        require_str(task, "name", &location, findings);
+        if let Some(worker) = require_str(task, "worker", &location, findings) {
+            if !worker.contains(PLACEHOLDER) {
+                findings.push(Finding::warning(
+                    "static_worker",
+                    "worker is a fixed value; Dragonfly's workflows expect {{.device_1}}".to_string(),
+                    Some(format!("{}.worker", location)),
+                ));
+            }
+        }
+        let actions = match task.get("actions").and_then(Value::as_sequence) {
+            Some(actions) if !actions.is_empty() => actions,
+            _ => {
+                findings.push(Finding::error("missing_field", "actions must list at least one action".to_string(), Some(format!("{}.actions", location))));
+                continue;
+            }
+        };
+        let mut names = BTreeSet::new();
+        for (a, action) in actions.iter().enumerate() {
+            let location = format!("{}.actions[{}]", location, a);
+            if let Some(name) = require_str(action, "name", &location, findings) {
+                if !names.insert(name) {
+                    findings.push(Finding::warning("duplicate_action", format!("Action name '{}' is used more than once in this task", name), Some(location.clone())));
+                }
+            }
+            if let Some(image) = require_str(action, "image", &location, findings) {
+                images.push((image.to_string(), format!("{}.image", location)));
+            }
+            if action.get("timeout").and_then(Value::as_u64).is_none() {
+                findings.push(Finding::warning("missing_timeout", "timeout isn't set".to_string(), Some(format!("{}.timeout", location))));
+            }
+        }
+    }
+    images
+}
+
+/// Lint a template. Images are looked up in their registries with `client` when given.
+pub async fn validate(yaml: &str, client: Option<&reqwest::Client>) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let mut findings = Vec::new();
+
+    if let Some((name, data)) = workflow_data(yaml, &mut findings) {
+        report.name = name;
+        let mut data = data;
+        for placeholder in SUBSTITUTED {
+            data = data.replace(placeholder, "dragonfly.invalid");
+        }
+
+        let variables = template_variables(&data);
+        for variable in &variables {
+            if !PROVIDED_VARIABLES.contains(&variable.as_str()) {
+                findings.push(Finding::error(
+                    "unknown_variable",
+                    format!("Template uses .{}, which Dragonfly doesn't provide", variable),
+                    None,
+                ));
+            }
+        }
+        report.variables = variables.into_iter().collect();
+
+        let workflow = mask_actions(&data)
+            .map_err(|e| Finding::error("template_syntax", e, None))
+            .and_then(|masked| serde_yaml::from_str::<Value>(&masked)
+                .map_err(|e| Finding::error("invalid_yaml", format!("Workflow isn't valid YAML: {}", e), None)));
+        match workflow {
+            Ok(workflow) => {
+                let images = check_workflow(&workflow, &mut findings);
+                let mut checked = BTreeSet::new();
+                for (image, location) in images {
+                    if image.contains(PLACEHOLDER) {
+                        findings.push(Finding::warning("templated_image", format!("Image '{}' is templated and can't be checked", image.replace(PLACEHOLDER, "{{ ... }}")), Some(location)));
+                        continue;
+                    }
+                    let Some(reference) = ImageRef::parse(&image) else {
+                        findings.push(Finding::error("invalid_image", format!("'{}' isn't a valid image reference", image), Some(location)));
+                        continue;
+                    };
+                    if !checked.insert(image.clone()) {
+                        continue;
+                    }
+                    report.images.push(image.clone());
+                    let Some(client) = client else { continue };
+                    match resolve_image(client, &reference).await {
+                        Resolution::Found => {}
+                        Resolution::NotFound => findings.push(Finding::error("image_not_found", format!("Image '{}' doesn't exist in {}", image, reference.registry), Some(location))),
+                        Resolution::Unauthorized => findings.push(Finding::warning("image_unverified", format!("Image '{}' couldn't be checked: {} requires credentials (or the repository doesn't exist)", image, reference.registry), Some(location))),
+                        Resolution::Unreachable(e) => findings.push(Finding::warning("image_unverified", format!("Image '{}' couldn't be checked: {}", image, e), Some(location))),
+                    }
+                }
+            }
+            Err(finding) => findings.push(finding),
+        }
+    }
+
+    report.valid = !findings.iter().any(|f| f.severity == Severity::Error);
+    report.findings = findings;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_ref_parse() {
+        assert_eq!(ImageRef::parse("alpine:3.20"), Some(ImageRef { registry: "docker.io".into(), repository: "library/alpine".into(), reference: "3.20".into() }));
+        assert_eq!(ImageRef::parse("curlimages/curl"), Some(ImageRef { registry: "docker.io".into(), repository: "curlimages/curl".into(), reference: "latest".into() }));
+        assert_eq!(
+            ImageRef::parse("quay.io/tinkerbell/actions/qemuimg2disk:latest"),
+            Some(ImageRef { registry: "quay.io".into(), repository: "tinkerbell/actions/qemuimg2disk".into(), reference: "latest".into() })
+        );
+        assert_eq!(
+            ImageRef::parse("localhost:5000/writefile@sha256:abcd"),
+            Some(ImageRef { registry: "localhost:5000".into(), repository: "writefile".into(), reference: "sha256:abcd".into() })
+        );
+        assert_eq!(ImageRef::parse("Alpine"), None);
+        assert_eq!(ImageRef::parse("alpine:"), None);
+    }
+
+    #[test]
+    fn test_template_variables() {
+        let variables = template_variables(r#"worker: "{{.device_1}}" disk: {{ index .Hardware.Disks 0 }} {{ if ne .multi_disk_mode "parallel" }} {{ $d := .vlan_id }}{{ $d.x }} 1.5"#);
+        assert_eq!(variables.into_iter().collect::<Vec<_>>(), vec!["Hardware", "device_1", "multi_disk_mode", "vlan_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_bundled_templates_are_valid() {
+        for name in ["ubuntu-2204", "ubuntu-2404"] {
+            let path = format!("{}/../../os-templates/{}.yml", env!("CARGO_MANIFEST_DIR"), name);
+            let yaml = std::fs::read_to_string(&path).unwrap();
+            let report = validate(&yaml, None).await;
+            assert!(report.valid, "{}: {:?}", name, report.findings);
+            assert_eq!(report.name.as_deref(), Some(name));
+            assert!(report.images.iter().any(|i| i == "quay.io/tinkerbell/actions/writefile:latest"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_findings() {
+        let yaml = r#"
+name: broken
+tasks:
+  - name: install
+    worker: "52:54:00:12:34:56"
+    actions:
+      - name: write
+        image: "{{ .image }}"
+        timeout: 60
+      - name: write
+        image: Not An Image
+        timeout: 60
+      - name: unclosed
+        timeout: 60
+"#;
+        let report = validate(yaml, None).await;
+        assert!(!report.valid);
+        let codes: Vec<&str> = report.findings.iter().map(|f| f.code).collect();
+        for code in ["unknown_variable", "missing_timeout", "static_worker", "duplicate_action", "templated_image", "invalid_image", "missing_field"] {
+            assert!(codes.contains(&code), "missing {} in {:?}", code, codes);
+        }
+
+        let report = validate("name: x\ntasks: {{ if .device_1 }\n", None).await;
+        assert_eq!(report.findings.last().map(|f| f.code), Some("template_syntax"));
+    }
+
+    #[test]
+    fn test_provided_variables_match_workflow() {
+        let machine = dragonfly_common::testing::machine();
+        let workflow = crate::tinkerbell::build_workflow_json(
+            &machine,
+            "ubuntu-2204",
+            Some(10),
+            "",
+            &Default::default(),
+            &Default::default(),
+            &Default::default(),
+        );
+        let mut keys: Vec<&str> = workflow["spec"]["hardwareMap"].as_object().unwrap().keys().map(String::as_str).collect();
+        keys.push("Hardware");
+        keys.sort();
+        let mut provided = PROVIDED_VARIABLES.to_vec();
+        provided.sort();
+        assert_eq!(keys, provided);
+    }
+}
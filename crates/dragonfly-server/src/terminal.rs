@@ -0,0 +1,274 @@
+// Browser terminals onto machines running the agent in daemon mode.
+//
+// The agent keeps a websocket open to /api/agent/channel/{id}; an admin's browser opens
+// /api/machines/{id}/terminal and the server relays between the two, one PTY per browser
+// session. Every session is audited: who opened it, when, how it ended, how much was typed and
+// when it was last typed in. What was typed isn't kept; it would include any passwords entered.
+
+use axum::extract::ws::{Message, WebSocket};
+use chrono::{DateTime, Utc};
+use dragonfly_common::channel::{decode_terminal_data, encode_terminal_data, ChannelMessage};
+use futures::{SinkExt, StreamExt};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+struct AgentConnection {
+    connection_id: Uuid,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+enum SessionEvent {
+    Output(Vec<u8>),
+    Exit { code: Option<i32>, error: Option<String> },
+}
+
+struct Session {
+    machine_id: Uuid,
+    connection_id: Uuid,
+    tx: mpsc::UnboundedSender<SessionEvent>,
+}
+
+// Open agent channels, by machine
+static AGENTS: Lazy<Mutex<HashMap<Uuid, AgentConnection>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Open browser sessions, by session ID
+static SESSIONS: Lazy<Mutex<HashMap<Uuid, Session>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn agents() -> MutexGuard<'static, HashMap<Uuid, AgentConnection>> {
+    AGENTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn sessions() -> MutexGuard<'static, HashMap<Uuid, Session>> {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether the machine's agent currently has its channel open.
+pub fn agent_connected(machine_id: &Uuid) -> bool {
+    agents().contains_key(machine_id)
+}
+
+/// How many agents have their channel open.
+pub fn connected_agents() -> usize {
+    agents().len()
+}
+
+fn send_to_agent(machine_id: &Uuid, message: Message) -> bool {
+    agents().get(machine_id).is_some_and(|agent| agent.tx.send(message).is_ok())
+}
+
+fn send_control(machine_id: &Uuid, message: &ChannelMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => send_to_agent(machine_id, Message::Text(json.into())),
+        Err(_) => false,
+    }
+}
+
+/// Ask the machine's agent to detect its OS and hardware again; false if it isn't connected.
+pub fn request_redetect(machine_id: &Uuid) -> bool {
+    send_control(machine_id, &ChannelMessage::Redetect)
+}
+
+/// Ask the machine's agent to shut the machine down; false if it isn't connected.
+pub fn request_shutdown(machine_id: &Uuid) -> bool {
+    send_control(machine_id, &ChannelMessage::Shutdown)
+}
+
+// Hand an event from a machine's agent to the browser session it belongs to
+fn deliver(machine_id: &Uuid, session_id: &Uuid, event: SessionEvent) {
+    if let Some(session) = sessions().get(session_id) {
+        // An agent may only write to sessions on its own machine
+        if session.machine_id == *machine_id {
+            let _ = session.tx.send(event);
+        }
+    }
+}
+
+/// Relay for one agent's channel, until the agent disconnects.
+pub async fn run_agent_channel(socket: WebSocket, machine_id: Uuid) {
+    let connection_id = Uuid::new_v4();
+    let (tx, mut outgoing) = mpsc::unbounded_channel();
+    // Only signed agents get this far (see api::agent_channel_ws), so a reconnecting
+    // agent replaces its old channel, which then winds down on its own
+    agents().insert(machine_id, AgentConnection { connection_id, tx });
+    info!("Agent channel opened for machine {}", machine_id);
+
+    let (mut sink, mut stream) = socket.split();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    while let Some(Ok(message)) = stream.next().await {
+        match message {
+            Message::Binary(frame) => match decode_terminal_data(&frame) {
+                Some((session_id, data)) => deliver(&machine_id, &session_id, SessionEvent::Output(data.to_vec())),
+                None => warn!("Ignoring short terminal frame from machine {}", machine_id),
+            },
+            Message::Text(text) => match serde_json::from_str::<ChannelMessage>(text.as_str()) {
+                Ok(ChannelMessage::TerminalExit { session_id, code, error }) => {
+                    deliver(&machine_id, &session_id, SessionEvent::Exit { code, error })
+                }
+                Ok(other) => warn!("Unexpected message on agent channel for machine {}: {:?}", machine_id, other),
+                Err(e) => warn!("Malformed message on agent channel for machine {}: {}", machine_id, e),
+            },
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    writer.abort();
+
+    {
+        let mut agents = agents();
+        if agents.get(&machine_id).is_some_and(|agent| agent.connection_id == connection_id) {
+            agents.remove(&machine_id);
+        }
+    }
+    // Shells started over this channel went with it
+    for session in sessions().values().filter(|s| s.connection_id == connection_id) {
+        let _ = session.tx.send(SessionEvent::Exit { code: None, error: Some("Agent disconnected".to_string()) });
+    }
+    info!("Agent channel closed for machine {}", machine_id);
+}
+
+/// What a terminal session did, for the audit log: sizes and timing, never contents.
+#[derive(Debug, Default)]
+pub struct SessionAudit {
+    pub exit_code: Option<i32>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub last_input_at: Option<DateTime<Utc>>,
+}
+
+impl SessionAudit {
+    pub fn record_input(&mut self, len: usize, at: DateTime<Utc>) {
+        self.bytes_in += len as u64;
+        self.last_input_at = Some(at);
+    }
+
+    pub fn record_output(&mut self, len: usize) {
+        self.bytes_out += len as u64;
+    }
+}
+
+/// A terminal session as listed by GET /api/machines/{id}/terminal-sessions.
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalSessionRecord {
+    pub id: String,
+    pub username: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    pub exit_code: Option<i32>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub last_input_at: Option<String>,
+}
+
+// Control messages from the browser; keystrokes arrive as binary frames
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BrowserMessage {
+    Resize { cols: u16, rows: u16 },
+}
+
+fn exit_message(code: Option<i32>, error: Option<&str>) -> Message {
+    Message::Text(json!({ "type": "exit", "code": code, "error": error }).to_string().into())
+}
+
+/// Relay between an admin's browser and a shell on the machine, until either side closes.
+pub async fn run_browser_session(mut socket: WebSocket, machine_id: Uuid, username: String, cols: u16, rows: u16) {
+    // Look up before the else branch awaits, so the lock isn't held across it
+    let connection_id = agents().get(&machine_id).map(|agent| agent.connection_id);
+    let Some(connection_id) = connection_id else {
+        let _ = socket.send(exit_message(None, Some("The agent on this machine is not connected"))).await;
+        return;
+    };
+
+    // No audit record, no session
+    let session_id = Uuid::new_v4();
+    if let Err(e) = crate::db::start_terminal_session(&session_id, &machine_id, &username).await {
+        error!("Failed to record terminal session on machine {}: {}", machine_id, e);
+        let _ = socket.send(exit_message(None, Some("Failed to record terminal session"))).await;
+        return;
+    }
+
+    let (tx, mut events) = mpsc::unbounded_channel();
+    sessions().insert(session_id, Session { machine_id, connection_id, tx });
+    info!("{} opened terminal session {} on machine {}", username, session_id, machine_id);
+
+    let mut audit = SessionAudit::default();
+    if !send_control(&machine_id, &ChannelMessage::TerminalOpen { session_id, cols, rows }) {
+        let _ = socket.send(exit_message(None, Some("Agent disconnected"))).await;
+    } else {
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(SessionEvent::Output(data)) => {
+                        audit.record_output(data.len());
+                        if socket.send(Message::Binary(data.into())).await.is_err() {
+                            send_control(&machine_id, &ChannelMessage::TerminalClose { session_id });
+                            break;
+                        }
+                    }
+                    Some(SessionEvent::Exit { code, error }) => {
+                        audit.exit_code = code;
+                        let _ = socket.send(exit_message(code, error.as_deref())).await;
+                        break;
+                    }
+                    None => break,
+                },
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Binary(data))) => {
+                        audit.record_input(data.len(), Utc::now());
+                        if !send_to_agent(&machine_id, Message::Binary(encode_terminal_data(&session_id, &data).into())) {
+                            let _ = socket.send(exit_message(None, Some("Agent disconnected"))).await;
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(BrowserMessage::Resize { cols, rows }) = serde_json::from_str(text.as_str()) {
+                            send_control(&machine_id, &ChannelMessage::TerminalResize { session_id, cols, rows });
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        send_control(&machine_id, &ChannelMessage::TerminalClose { session_id });
+                        break;
+                    }
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+
+    sessions().remove(&session_id);
+    if let Err(e) = crate::db::finish_terminal_session(&session_id, &audit).await {
+        error!("Failed to finish audit record for terminal session {}: {}", session_id, e);
+    }
+    info!("Terminal session {} on machine {} ended ({} bytes typed, {} bytes of output)",
+          session_id, machine_id, audit.bytes_in, audit.bytes_out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_counts_input_without_keeping_it() {
+        let mut audit = SessionAudit::default();
+        let first = Utc::now();
+        audit.record_input(b"ls -la\r".len(), first);
+        audit.record_input(b"\x1b[A\x03".len(), first + chrono::Duration::seconds(5));
+        audit.record_output(42);
+        assert_eq!(audit.bytes_in, 11);
+        assert_eq!(audit.bytes_out, 42);
+        assert_eq!(audit.last_input_at, Some(first + chrono::Duration::seconds(5)));
+    }
+}
@@ -1,14 +1,17 @@
 use anyhow::{anyhow, Result};
 use kube::{
-    api::{Api, PostParams, PatchParams, Patch},
+    api::Api,
     Client, Error as KubeError, core::DynamicObject,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::OnceCell;
 use tracing::{error, info, warn};
 use dragonfly_common::models::Machine;
+use dragonfly_common::OsIdentifier;
 use std::str::FromStr;
 
+use crate::tinkerbell_client::{upsert, ResourceKind, TinkerbellClient};
+
 // Define a static Kubernetes client
 static KUBE_CLIENT: OnceCell<Client> = OnceCell::const_new();
 
@@ -123,6 +126,8 @@ struct HardwareSpec {
     disks: Option<Vec<DiskSpec>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     interfaces: Option<Vec<InterfaceSpec>>,
+    #[serde(rename = "userData", skip_serializing_if = "Option::is_none")]
+    user_data: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -153,6 +158,8 @@ struct DHCPSpec {
     name_servers: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     uefi: Option<bool>,
+    // Always sent so that clearing a machine's VLAN removes it from the merge patch
+    vlan_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -175,18 +182,14 @@ struct NetbootSpec {
 }
 
 // Register a machine with Tinkerbell
-pub async fn register_machine(machine: &Machine) -> Result<()> {
-    // Get the Kubernetes client
-    let client = match get_client().await {
-        Ok(c) => c,
-        Err(e) => {
-            warn!("Skipping Tinkerbell registration: {}", e);
-            return Ok(());
-        }
-    };
+// Fails when Kubernetes is unreachable, so the caller can queue the write for later
+pub async fn register_machine(client: &dyn TinkerbellClient, machine: &Machine) -> Result<()> {
+    if let Err(e) = client.connect().await {
+        return Err(anyhow!("Kubernetes API unreachable, hardware not registered: {}", e));
+    }
     
     // Create a unique name for the hardware resource based on MAC address
-    let resource_name = format!("machine-{}", machine.mac_address.replace(":", "-"));
+    let resource_name = hardware_resource_name(&machine.mac_address);
     
     // --- Determine Hostname (Final Complete Rewrite) ---
     // Start with the fallback/default (MAC-based name)
@@ -228,7 +231,7 @@ pub async fn register_machine(machine: &Machine) -> Result<()> {
 
 // Internal function to handle the actual machine registration with Tinkerbell
 async fn register_machine_internal(
-    client: &'static Client,
+    client: &dyn TinkerbellClient,
     machine: &Machine,
     resource_name: &str,
     resolved_hostname: &str,
@@ -236,6 +239,41 @@ async fn register_machine_internal(
     let memorable_name = machine.memorable_name.clone().unwrap_or_else(|| resource_name.to_string());
 
     info!("Registering machine {} with Tinkerbell", resource_name);
+
+    // Cloud-init user data, if the machine (or a default profile of its tags) has any configured
+    let user_data = match crate::provisioning_profiles::effective_config(&machine.id).await {
+        Ok(config) => config.cloud_init,
+        Err(e) => {
+            warn!("Failed to load provisioning config for machine {}, registering without user data: {}", machine.id, e);
+            None
+        }
+    };
+
+    let vlan_id = machine_vlan_id(machine).await;
+
+    // Secure Boot machines get shim from DHCP directly; Tinkerbell mustn't hand them iPXE
+    let secure_boot = match crate::db::is_secure_boot_enabled(&machine.id).await {
+        Ok(enabled) => enabled,
+        Err(e) => {
+            warn!("Failed to check Secure Boot for machine {}, allowing PXE: {}", machine.id, e);
+            false
+        }
+    };
+
+    // Multi-homed machines only PXE from their preferred NICs
+    let boot_interfaces = match crate::db::get_boot_interfaces(&machine.id).await {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            warn!("Failed to load boot interfaces for machine {}, allowing PXE on its registered NIC: {}", machine.id, e);
+            None
+        }
+    };
+    let registered_nic_pxe = boot_interfaces.as_ref().is_none_or(|b| b.allows_pxe(&machine.mac_address));
+    let extra_nics: Vec<String> = boot_interfaces.iter()
+        .flat_map(|b| b.macs())
+        .filter(|mac| !mac.eq_ignore_ascii_case(&machine.mac_address))
+        .map(str::to_string)
+        .collect();
     
     // Create the Hardware resource, focusing only on the specific fields we need to set
     // to reduce conflicts with other field managers
@@ -257,7 +295,7 @@ async fn register_machine_internal(
             disks: Some(machine.disks.iter().map(|disk| DiskSpec {
                 device: disk.device.clone(),
             }).collect()),
-            interfaces: Some(vec![InterfaceSpec {
+            interfaces: Some(std::iter::once(InterfaceSpec {
                 dhcp: Some(DHCPSpec {
                     arch: Some("x86_64".to_string()),
                     hostname: Some(resolved_hostname.to_string()),
@@ -270,229 +308,113 @@ async fn register_machine_internal(
                     mac: machine.mac_address.clone(),
                     name_servers: Some(machine.nameservers.clone()),
                     uefi: Some(true),
+                    vlan_id: vlan_id.map(|v| v.to_string()),
                 }),
                 netboot: Some(NetbootSpec {
-                    allow_pxe: Some(true),
+                    allow_pxe: Some(!secure_boot && registered_nic_pxe),
                     allow_workflow: Some(true),
                 }),
-            }]),
+            }).chain(extra_nics.into_iter().map(|mac| InterfaceSpec {
+                // Other preferred NICs lease dynamically; the machine's address belongs to its registered NIC
+                dhcp: Some(DHCPSpec {
+                    arch: Some("x86_64".to_string()),
+                    hostname: Some(resolved_hostname.to_string()),
+                    ip: None,
+                    lease_time: Some(86400),
+                    mac,
+                    name_servers: Some(machine.nameservers.clone()),
+                    uefi: Some(true),
+                    vlan_id: vlan_id.map(|v| v.to_string()),
+                }),
+                netboot: Some(NetbootSpec {
+                    allow_pxe: Some(!secure_boot),
+                    allow_workflow: Some(true),
+                }),
+            })).collect()),
+            user_data,
         },
     };
     
     // Convert the Hardware resource to JSON
     let hardware_json = serde_json::to_value(&hardware)?;
     
-    // Create the ApiResource for the Hardware CRD
-    let api_resource = kube::core::ApiResource {
-        group: "tinkerbell.org".to_string(),
-        version: "v1alpha1".to_string(),
-        kind: "Hardware".to_string(),
-        api_version: "tinkerbell.org/v1alpha1".to_string(),
-        plural: "hardware".to_string(),
-    };
-    
-    info!("Using Kubernetes API Resource: group={}, version={}, kind={}, plural={}", 
-          api_resource.group, api_resource.version, api_resource.kind, api_resource.plural);
-    
-    // Create a dynamic API to interact with the Hardware custom resource
-    let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &api_resource);
-    
-    // Create a DynamicObject from our hardware_json
-    let mut dynamic_obj = DynamicObject {
-        metadata: kube::core::ObjectMeta {
-            name: Some(resource_name.to_string()),
-            namespace: Some("tink".to_string()),
-            ..Default::default()
-        },
-        types: Some(kube::core::TypeMeta {
-            api_version: "tinkerbell.org/v1alpha1".to_string(),
-            kind: "Hardware".to_string(),
-        }),
-        data: hardware_json,
-    };
-    
-    // Check if the hardware resource already exists
-    match api.get(&resource_name).await {
-        Ok(_existing) => {
-            info!("Found existing Hardware resource in Tinkerbell: {}", resource_name);
-            
-            // Use JSON merge patch instead of server-side apply
-            let patch_params = PatchParams::default();
-            
-            info!("Applying update via JSON merge patch");
-            
-            // Use JSON merge patch to update the resource
-            match api.patch(&resource_name, &patch_params, &Patch::Merge(dynamic_obj)).await {
-                Ok(patched) => {
-                    info!(
-                        "Updated Hardware resource in Tinkerbell: {} (resourceVersion: {:?})",
-                        resource_name,
-                        patched.metadata.resource_version
-                    );
-                    Ok(())
-                },
-                Err(e) => {
-                    error!("Failed to update Hardware resource in Tinkerbell: {}", e);
-                    Err(anyhow!("Failed to update Hardware resource: {}", e))
-                }
-            }
-        },
-        Err(KubeError::Api(ae)) if ae.code == 404 => {
-            info!("No existing Hardware resource found, creating new one: {}", resource_name);
-            
-            // For creation, ensure we have a clean metadata without resourceVersion
-            dynamic_obj.metadata = kube::core::ObjectMeta {
-                name: Some(resource_name.to_string()),
-                namespace: Some("tink".to_string()),
-                ..Default::default()
-            };
-            
-            // Create a new hardware resource
-            match api.create(&PostParams::default(), &dynamic_obj).await {
-                Ok(created) => {
-                    info!(
-                        "Created new Hardware resource in Tinkerbell: {} (initial resourceVersion: {:?})",
-                        resource_name,
-                        created.metadata.resource_version
-                    );
-                    Ok(())
-                },
-                Err(e) => {
-                    error!("Failed to create Hardware resource in Tinkerbell: {}", e);
-                    Err(anyhow!("Failed to create Hardware resource: {}", e))
-                }
-            }
+    // Patch the existing resource (JSON merge patch) or create a new one
+    match upsert(client, ResourceKind::Hardware, resource_name, hardware_json).await {
+        Ok(()) => {
+            info!("Registered Hardware resource in Tinkerbell: {}", resource_name);
+            Ok(())
         },
         Err(e) => {
-            error!("Error checking Hardware resource in Tinkerbell: {}", e);
-            Err(anyhow!("Error checking Hardware resource: {}", e))
+            error!("Failed to register Hardware resource in Tinkerbell: {}", e);
+            Err(e)
         }
     }
 }
 
-// Add this function to delete hardware resources
-pub async fn delete_hardware(mac_address: &str) -> Result<()> {
-    // Get the Kubernetes client
-    let client = match get_client().await {
-        Ok(c) => c,
-        Err(e) => {
-            warn!("Skipping Tinkerbell deletion: {}", e);
-            return Err(anyhow!("Kubernetes client not initialized: {}", e));
-        }
-    };
+// Name of the Hardware resource for a MAC address
+pub fn hardware_resource_name(mac_address: &str) -> String {
+    format!("machine-{}", mac_address.replace(":", "-"))
+}
+
+// Whether the Hardware resource for a machine exists; None when Kubernetes isn't reachable
+pub async fn hardware_exists(client: &dyn TinkerbellClient, mac_address: &str) -> Result<Option<bool>> {
+    if client.connect().await.is_err() {
+        return Ok(None);
+    }
+    let resource_name = hardware_resource_name(mac_address);
+    Ok(Some(client.get(ResourceKind::Hardware, &resource_name).await?.is_some()))
+}
+
+// Delete a machine's hardware resource and any workflow for it
+pub async fn delete_hardware(client: &dyn TinkerbellClient, mac_address: &str) -> Result<()> {
+    if let Err(e) = client.connect().await {
+        warn!("Skipping Tinkerbell deletion: {}", e);
+        return Err(anyhow!("Kubernetes client not initialized: {}", e));
+    }
     
-    let resource_name = mac_address.to_lowercase();
+    let resource_name = hardware_resource_name(mac_address);
     info!("Deleting hardware resource from Tinkerbell: {}", resource_name);
-    
-    // Create the ApiResource for the Hardware CRD
-    let api_resource = kube::core::ApiResource {
-        group: "tinkerbell.org".to_string(),
-        version: "v1alpha1".to_string(),
-        kind: "Hardware".to_string(),
-        api_version: "tinkerbell.org/v1alpha1".to_string(),
-        plural: "hardware".to_string(),
-    };
-    
-    // Create a dynamic API to interact with the Hardware custom resource
-    let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &api_resource);
-    
-    // Delete the hardware resource
-    let hardware_result = api.delete(&resource_name, &kube::api::DeleteParams::default()).await;
+    let hardware_deleted = client.delete(ResourceKind::Hardware, &resource_name).await
+        .inspect_err(|e| error!("Failed to delete hardware resource from Tinkerbell: {}", e))?;
 
     // Also delete any associated workflow
     let workflow_name = format!("os-install-{}", mac_address.replace(":", "-"));
     info!("Deleting workflow resource from Tinkerbell: {}", workflow_name);
+    let workflow_deleted = client.delete(ResourceKind::Workflow, &workflow_name).await
+        .inspect_err(|e| error!("Failed to delete workflow resource from Tinkerbell: {}", e))?;
 
-    // Create the ApiResource for the Workflow CRD
-    let workflow_api_resource = kube::core::ApiResource {
-        group: "tinkerbell.org".to_string(),
-        version: "v1alpha1".to_string(),
-        kind: "Workflow".to_string(),
-        api_version: "tinkerbell.org/v1alpha1".to_string(),
-        plural: "workflows".to_string(),
-    };
-
-    // Create a dynamic API to interact with the Workflow custom resource
-    let workflow_api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &workflow_api_resource);
-
-    // Delete the workflow resource
-    let workflow_result = workflow_api.delete(&workflow_name, &kube::api::DeleteParams::default()).await;
-
-    // Handle results
-    match (hardware_result, workflow_result) {
-        (Ok(_), Ok(_)) => {
-            info!("Successfully deleted hardware and workflow resources");
-            Ok(())
-        },
-        (Ok(_), Err(KubeError::Api(ae))) if ae.code == 404 => {
-            info!("Successfully deleted hardware resource, workflow was not found");
-            Ok(())
-        },
-        (Err(KubeError::Api(ae)), Ok(_)) if ae.code == 404 => {
-            info!("Hardware resource not found, but successfully deleted workflow");
-            Ok(())
-        },
-        (Err(KubeError::Api(ae1)), Err(KubeError::Api(ae2))) if ae1.code == 404 && ae2.code == 404 => {
-            info!("Neither hardware nor workflow resources were found (already deleted)");
-            Ok(())
-        },
-        (Err(e), _) => {
-            error!("Failed to delete hardware resource from Tinkerbell: {}", e);
-            Err(anyhow!("Failed to delete hardware resource: {}", e))
-        },
-        (_, Err(e)) => {
-            error!("Failed to delete workflow resource from Tinkerbell: {}", e);
-            Err(anyhow!("Failed to delete workflow resource: {}", e))
-        }
+    match (hardware_deleted, workflow_deleted) {
+        (true, true) => info!("Successfully deleted hardware and workflow resources"),
+        (true, false) => info!("Successfully deleted hardware resource, workflow was not found"),
+        (false, true) => info!("Hardware resource not found, but successfully deleted workflow"),
+        (false, false) => info!("Neither hardware nor workflow resources were found (already deleted)"),
     }
+    Ok(())
 }
 
 // Create a Workflow for OS installation
-pub async fn create_workflow(machine: &Machine, _os_choice: &str) -> Result<()> {
-    // Get the Kubernetes client
-    let client = match get_client().await {
-        Ok(c) => c,
-        Err(e) => {
-            warn!("Skipping Tinkerbell workflow creation: {}", e);
-            return Ok(());
-        }
-    };
+pub async fn create_workflow(client: &dyn TinkerbellClient, machine: &Machine, _os_choice: &str) -> Result<()> {
+    if let Err(e) = client.connect().await {
+        return Err(anyhow!("Kubernetes API unreachable, workflow not created: {}", e));
+    }
     
     // Use MAC address without colons as part of the workflow name
-    let resource_name = format!("os-install-{}", machine.mac_address.replace(":", "-"));
-    
-    // Hardware reference name (matches what we create in register_machine)
-    let hardware_ref = format!("machine-{}", machine.mac_address.replace(":", "-"));
+    let resource_name = workflow_resource_name(machine);
     
     info!("Creating workflow {} for machine {}", resource_name, machine.id);
     
     // Map OS choice to template reference
-    let template_ref = match machine.os_choice.as_ref() {
-        Some(os) if os == "ubuntu-2204" => "ubuntu-2204",
-        Some(os) if os == "ubuntu-2404" => "ubuntu-2404",
-        Some(os) if os == "debian-12" => "debian-12",
-        Some(os) if os == "proxmox" => "proxmox",
-        Some(os) if os == "talos" => "talos",
-        Some(os) => os,
-        None => "ubuntu-2204", // Default if no OS choice is specified
-    };
-    
-    // First check if the Template exists
-    let template_api_resource = kube::core::ApiResource {
-        group: "tinkerbell.org".to_string(),
-        version: "v1alpha1".to_string(),
-        kind: "Template".to_string(),
-        api_version: "tinkerbell.org/v1alpha1".to_string(),
-        plural: "templates".to_string(),
-    };
+    let template_ref = template_ref_for(machine.os_choice.as_deref());
     
-    let template_api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &template_api_resource);
+    // Never install from a cached custom image that no longer matches its checksum
+    crate::custom_images::verify_before_install(template_ref).await?;
     
-    match template_api.get(template_ref).await {
-        Ok(_) => {
+    // First check if the Template exists
+    match client.get(ResourceKind::Template, template_ref).await {
+        Ok(Some(_)) => {
             info!("Template '{}' found in Tinkerbell, proceeding with workflow creation", template_ref);
         },
-        Err(KubeError::Api(ae)) if ae.code == 404 => {
+        Ok(None) => {
             error!("Template '{}' not found in Tinkerbell! Workflow creation will fail. Please create this template first.", template_ref);
             return Err(anyhow!("Template '{}' not found in Tinkerbell namespace. Workflow creation aborted.", template_ref));
         },
@@ -501,24 +423,351 @@ pub async fn create_workflow(machine: &Machine, _os_choice: &str) -> Result<()>
         }
     }
     
-    // Create the Workflow resource
-    let workflow_json = serde_json::json!({
+    // Create the Workflow resource, or patch it if one already exists
+    let workflow_json = build_workflow_json(
+        machine,
+        template_ref,
+        machine_vlan_id(machine).await,
+        &machine_kernel_args(machine).await,
+        &machine_system_config(machine).await,
+        &machine_disk_layout(machine).await,
+        &install_branding().await,
+    );
+    match upsert(client, ResourceKind::Workflow, &resource_name, workflow_json).await {
+        Ok(()) => {
+            info!("Submitted Workflow resource to Tinkerbell: {}", resource_name);
+            // Pins the image version so cache cleanup keeps it while the machine runs it
+            if let Err(e) = crate::custom_images::record_install(&machine.id, template_ref).await {
+                warn!("Failed to record the image machine {} is installing: {}", machine.id, e);
+            }
+            crate::install_telemetry::reset(&machine.id).await;
+            crate::disk_layout::reset(&machine.id).await;
+            crate::install_verification::reset(&machine.id).await;
+            Ok(())
+        },
+        Err(e) => {
+            error!("Failed to submit Workflow resource to Tinkerbell: {}", e);
+            Err(e)
+        }
+    }
+}
+
+// Workflow resource name for a machine (MAC address with dashes)
+fn workflow_resource_name(machine: &Machine) -> String {
+    format!("os-install-{}", machine.mac_address.replace(":", "-"))
+}
+
+// Map an OS choice to the Tinkerbell template it installs with
+pub(crate) fn template_ref_for(os_choice: Option<&str>) -> &str {
+    match os_choice {
+        Some(os) => os,
+        None => OsIdentifier::default().as_str(), // Default if no OS choice is specified
+    }
+}
+
+// The VLAN a machine provisions on; lookup failures fall back to untagged
+async fn machine_vlan_id(machine: &Machine) -> Option<u16> {
+    match crate::vlan::for_machine(machine).await {
+        Ok(vlan) => vlan.map(|v| v.vlan_id),
+        Err(e) => {
+            warn!("Failed to look up VLAN for machine {}, assuming untagged: {}", machine.id, e);
+            None
+        }
+    }
+}
+
+// Timezone, NTP and locale for the installed OS; lookup failures leave the image defaults
+async fn machine_system_config(machine: &Machine) -> crate::system_config::SystemConfig {
+    match crate::system_config::for_machine(machine).await {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Failed to look up system settings for machine {}, using image defaults: {}", machine.id, e);
+            crate::system_config::SystemConfig::default()
+        }
+    }
+}
+
+// Organization branding for the installed OS's MOTD; lookup failures leave the image's MOTD alone
+async fn install_branding() -> crate::branding::Branding {
+    match crate::db::get_app_settings().await {
+        Ok(settings) => settings.branding,
+        Err(e) => {
+            warn!("Failed to load branding settings, leaving the image MOTD: {}", e);
+            crate::branding::Branding::default()
+        }
+    }
+}
+
+// Multi-disk imaging options from the machine's partitioning profile; a missing or invalid
+// profile images the boot disk only
+async fn machine_disk_layout(machine: &Machine) -> crate::disk_layout::DiskLayout {
+    let profile = match crate::provisioning_profiles::effective_config(&machine.id).await {
+        Ok(config) => config.partitioning,
+        Err(e) => {
+            warn!("Failed to load provisioning config for machine {}, imaging the boot disk only: {}", machine.id, e);
+            None
+        }
+    };
+    crate::disk_layout::DiskLayout::parse(profile.as_deref()).unwrap_or_else(|e| {
+        warn!("Ignoring partitioning profile of machine {}: {}", machine.id, e);
+        crate::disk_layout::DiskLayout::default()
+    })
+}
+
+// Extra kernel arguments for the installed OS; empty when there are none
+async fn machine_kernel_args(machine: &Machine) -> String {
+    match crate::provisioning_profiles::effective_config(&machine.id).await {
+        Ok(config) => config.kernel_args.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to load provisioning config for machine {}, installing without extra kernel arguments: {}", machine.id, e);
+            String::new()
+        }
+    }
+}
+
+// Build the Workflow manifest that create_workflow submits to Kubernetes
+pub(crate) fn build_workflow_json(
+    machine: &Machine,
+    template_ref: &str,
+    vlan_id: Option<u16>,
+    kernel_args: &str,
+    system: &crate::system_config::SystemConfig,
+    layout: &crate::disk_layout::DiskLayout,
+    branding: &crate::branding::Branding,
+) -> serde_json::Value {
+    // Hardware reference name (matches what we create in register_machine)
+    let hardware_ref = hardware_resource_name(&machine.mac_address);
+
+    let mut workflow = serde_json::json!({
         "apiVersion": "tinkerbell.org/v1alpha1",
         "kind": "Workflow",
         "metadata": {
-            "name": resource_name,
+            "name": workflow_resource_name(machine),
             "namespace": "tink"
         },
         "spec": {
             "templateRef": template_ref,
             "hardwareRef": hardware_ref,
             "hardwareMap": {
-                "device_1": machine.mac_address
+                "device_1": machine.mac_address,
+                // Templates test this to add a VLAN interface to the installed OS; empty when untagged
+                "vlan_id": vlan_id.map(|v| v.to_string()).unwrap_or_default(),
+                // Templates append these to the installed OS's kernel command line
+                "kernel_args": kernel_args
             }
         }
     });
-    
-    // Create the ApiResource for the Workflow CRD
+    // Templates write these into the installed system's config; empty when unset
+    for (key, value) in system.hardware_map() {
+        workflow["spec"]["hardwareMap"][key] = serde_json::Value::String(value);
+    }
+    // Templates image every listed disk when the mode is set; empty for a single disk
+    for (key, value) in layout.hardware_map(&machine.disks) {
+        workflow["spec"]["hardwareMap"][key] = serde_json::Value::String(value);
+    }
+    // Templates write the MOTD when set; base64 so it survives being dropped into YAML
+    for (key, value) in branding.hardware_map(machine) {
+        workflow["spec"]["hardwareMap"][key] = serde_json::Value::String(value);
+    }
+    workflow
+}
+
+// Minimum size of the target disk for an OS install (qemuimg2disk streams to disk 0)
+const MIN_INSTALL_DISK_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+// Assumed artifact download throughput used for dry-run estimates (100 Mbit/s)
+const ASSUMED_DOWNLOAD_BYTES_PER_SEC: u64 = 12_500_000;
+
+// Result of a single dry-run check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewCheck {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+}
+
+// Everything an OS assignment would do, computed without touching anything
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowPreview {
+    pub machine_id: uuid::Uuid,
+    pub os_choice: String,
+    pub template_ref: String,
+    pub ok: bool,
+    pub checks: Vec<PreviewCheck>,
+    pub image_path: Option<String>,
+    pub image_cached: bool,
+    pub estimated_download_secs: Option<u64>,
+    pub workflow: serde_json::Value,
+}
+
+// Load a template's YAML from the local os-templates directories, if present
+async fn read_local_template(template_name: &str) -> Option<String> {
+    for dir in ["/var/lib/dragonfly/os-templates", "os-templates"] {
+        let path = std::path::Path::new(dir).join(format!("{}.yml", template_name));
+        if let Ok(content) = tokio::fs::read_to_string(&path).await {
+            return Some(content);
+        }
+    }
+    None
+}
+
+// Extract the artifact path (relative to /ipxe/) of the image a template streams to disk
+fn template_image_path(template_yaml: &str) -> Option<String> {
+    template_yaml.lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("IMG_URL:"))
+        .and_then(|line| line.split("/ipxe/").nth(1))
+        .map(|rest| rest.trim_end_matches('"').to_string())
+}
+
+// The image is written to the first disk, and to any identical disks when the profile asks
+// for multi-disk imaging
+fn disk_check(disks: &[dragonfly_common::models::DiskInfo], layout: &crate::disk_layout::DiskLayout) -> PreviewCheck {
+    let targets = layout.target_disks(disks);
+    match disks.first() {
+        None => PreviewCheck {
+            name: "disks".to_string(),
+            passed: false,
+            message: "Machine has no disks reported".to_string(),
+        },
+        Some(disk) if disk.size_bytes < MIN_INSTALL_DISK_BYTES => PreviewCheck {
+            name: "disks".to_string(),
+            passed: false,
+            message: format!("Disk {} is {} bytes, at least {} bytes required",
+                disk.device, disk.size_bytes, MIN_INSTALL_DISK_BYTES),
+        },
+        Some(disk) if targets.len() > 1 => PreviewCheck {
+            name: "disks".to_string(),
+            passed: true,
+            message: format!("Image will be written to {} disks of {} bytes ({}, {})",
+                targets.len(), disk.size_bytes, layout.mode.as_str(), targets.join(", ")),
+        },
+        Some(disk) => PreviewCheck {
+            name: "disks".to_string(),
+            passed: true,
+            message: format!("Image will be written to {} ({} bytes)", disk.device, disk.size_bytes),
+        },
+    }
+}
+
+// Validate an OS assignment and render its workflow without creating anything
+pub async fn preview_workflow(machine: &Machine, os_choice: &str) -> Result<WorkflowPreview> {
+    let template_ref = template_ref_for(Some(os_choice)).to_string();
+    let mut checks = Vec::new();
+
+    // 1. Template must exist in Tinkerbell (or at least locally, so it can be installed)
+    let local_template = read_local_template(&template_ref).await;
+    let template_check = match get_client().await {
+        Ok(client) => {
+            let template_api_resource = kube::core::ApiResource {
+                group: "tinkerbell.org".to_string(),
+                version: "v1alpha1".to_string(),
+                kind: "Template".to_string(),
+                api_version: "tinkerbell.org/v1alpha1".to_string(),
+                plural: "templates".to_string(),
+            };
+            let template_api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &template_api_resource);
+            match template_api.get(&template_ref).await {
+                Ok(_) => PreviewCheck {
+                    name: "template".to_string(),
+                    passed: true,
+                    message: format!("Template '{}' exists in Tinkerbell", template_ref),
+                },
+                Err(KubeError::Api(ae)) if ae.code == 404 => PreviewCheck {
+                    name: "template".to_string(),
+                    passed: false,
+                    message: format!("Template '{}' not found in Tinkerbell namespace", template_ref),
+                },
+                Err(e) => PreviewCheck {
+                    name: "template".to_string(),
+                    passed: false,
+                    message: format!("Error checking for template '{}': {}", template_ref, e),
+                },
+            }
+        },
+        Err(e) => PreviewCheck {
+            name: "template".to_string(),
+            passed: local_template.is_some(),
+            message: if local_template.is_some() {
+                format!("Kubernetes unavailable ({}), but template '{}' is available locally", e, template_ref)
+            } else {
+                format!("Kubernetes unavailable ({}) and no local template '{}'", e, template_ref)
+            },
+        },
+    };
+    checks.push(template_check);
+
+    // 2. Disks must satisfy the partitioning profile
+    let layout = machine_disk_layout(machine).await;
+    checks.push(disk_check(&machine.disks, &layout));
+
+    // 3. Image should be cached; otherwise estimate how long the download will take
+    let image_path = local_template.as_deref().and_then(template_image_path);
+    let mut image_cached = false;
+    let mut estimated_download_secs = None;
+    let image_check = match &image_path {
+        Some(path) => {
+            image_cached = crate::api::artifact_base_dir().join(path).exists();
+            if image_cached {
+                PreviewCheck {
+                    name: "image".to_string(),
+                    passed: true,
+                    message: format!("Image {} is cached", path),
+                }
+            } else if let Some(url) = crate::api::remote_artifact_url(path) {
+                let size = reqwest::Client::new().head(url).send().await.ok()
+                    .and_then(|resp| resp.content_length());
+                estimated_download_secs = size.map(|bytes| bytes / ASSUMED_DOWNLOAD_BYTES_PER_SEC);
+                PreviewCheck {
+                    name: "image".to_string(),
+                    passed: true,
+                    message: match estimated_download_secs {
+                        Some(secs) => format!("Image {} not cached, download estimated at ~{}s", path, secs),
+                        None => format!("Image {} not cached, download size unknown", path),
+                    },
+                }
+            } else {
+                PreviewCheck {
+                    name: "image".to_string(),
+                    passed: false,
+                    message: format!("Image {} is not cached and has no known download source", path),
+                }
+            }
+        },
+        None => PreviewCheck {
+            name: "image".to_string(),
+            passed: true,
+            message: "Could not determine image from template, skipping cache check".to_string(),
+        },
+    };
+    checks.push(image_check);
+
+    let ok = checks.iter().all(|c| c.passed);
+    Ok(WorkflowPreview {
+        machine_id: machine.id,
+        os_choice: os_choice.to_string(),
+        workflow: build_workflow_json(
+            machine,
+            &template_ref,
+            machine_vlan_id(machine).await,
+            &machine_kernel_args(machine).await,
+            &machine_system_config(machine).await,
+            &layout,
+            &install_branding().await,
+        ),
+        template_ref,
+        ok,
+        checks,
+        image_path,
+        image_cached,
+        estimated_download_secs,
+    })
+}
+
+// Fetch the raw status block of a machine's Workflow resource, if the workflow exists
+pub async fn get_workflow_status_raw(machine: &Machine) -> Result<Option<serde_json::Value>> {
+    let client = get_client().await?;
+
     let api_resource = kube::core::ApiResource {
         group: "tinkerbell.org".to_string(),
         version: "v1alpha1".to_string(),
@@ -526,73 +775,44 @@ pub async fn create_workflow(machine: &Machine, _os_choice: &str) -> Result<()>
         api_version: "tinkerbell.org/v1alpha1".to_string(),
         plural: "workflows".to_string(),
     };
-    
-    info!("Using Kubernetes API Resource for Workflow: group={}, version={}, kind={}, plural={}", 
-          api_resource.group, api_resource.version, api_resource.kind, api_resource.plural);
-    
-    // Create a dynamic API to interact with the Workflow custom resource
     let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), "tink", &api_resource);
-    
-    // Create a DynamicObject from our workflow_json
-    let dynamic_obj = DynamicObject {
-        metadata: kube::core::ObjectMeta {
-            name: Some(resource_name.clone()),
-            namespace: Some("tink".to_string()),
+
+    match api.get(&workflow_resource_name(machine)).await {
+        Ok(workflow) => Ok(workflow.data.get("status").cloned()),
+        Err(KubeError::Api(ae)) if ae.code == 404 => Ok(None),
+        Err(e) => Err(anyhow!("Failed to get Workflow resource: {}", e)),
+    }
+}
+
+// Collect the last `tail_lines` log lines of every Tinkerbell pod in the tink namespace
+pub async fn get_tink_pod_logs(tail_lines: i64) -> Result<HashMap<String, String>> {
+    use k8s_openapi::api::core::v1::Pod;
+    use kube::api::{ListParams, LogParams};
+
+    let client = get_client().await?;
+    let pods: Api<Pod> = Api::namespaced(client.clone(), "tink");
+
+    let pod_list = pods.list(&ListParams::default()).await
+        .map_err(|e| anyhow!("Failed to list pods in tink namespace: {}", e))?;
+
+    let mut logs = HashMap::new();
+    for pod in pod_list.items {
+        let name = match pod.metadata.name {
+            Some(name) if name.starts_with("tink") => name,
+            _ => continue,
+        };
+        let params = LogParams {
+            tail_lines: Some(tail_lines),
             ..Default::default()
-        },
-        types: Some(kube::core::TypeMeta {
-            api_version: "tinkerbell.org/v1alpha1".to_string(),
-            kind: "Workflow".to_string(),
-        }),
-        data: workflow_json,
-    };
-    
-    // Check if the workflow resource already exists
-    match api.get(&resource_name).await {
-        Ok(_existing) => {
-            info!("Found existing Workflow resource in Tinkerbell: {}", resource_name);
-            
-            // Use JSON merge patch to update the resource
-            let patch_params = PatchParams::default();
-            match api.patch(&resource_name, &patch_params, &Patch::Merge(&dynamic_obj)).await {
-                Ok(patched) => {
-                    info!(
-                        "Updated Workflow resource in Tinkerbell: {} (resourceVersion: {:?})",
-                        resource_name,
-                        patched.metadata.resource_version
-                    );
-                    Ok(())
-                },
-                Err(e) => {
-                    error!("Failed to update Workflow resource in Tinkerbell: {}", e);
-                    Err(anyhow!("Failed to update Workflow resource: {}", e))
-                }
-            }
-        },
-        Err(KubeError::Api(ae)) if ae.code == 404 => {
-            info!("No existing Workflow resource found, creating new one: {}", resource_name);
-            
-            // Create a new workflow resource
-            match api.create(&PostParams::default(), &dynamic_obj).await {
-                Ok(created) => {
-                    info!(
-                        "Created new Workflow resource in Tinkerbell: {} (initial resourceVersion: {:?})",
-                        resource_name,
-                        created.metadata.resource_version
-                    );
-                    Ok(())
-                },
-                Err(e) => {
-                    error!("Failed to create Workflow resource in Tinkerbell: {}", e);
-                    Err(anyhow!("Failed to create Workflow resource: {}", e))
-                }
-            }
-        },
-        Err(e) => {
-            error!("Error checking Workflow resource in Tinkerbell: {}", e);
-            Err(anyhow!("Error checking Workflow resource: {}", e))
-        }
+        };
+        let output = match pods.logs(&name, &params).await {
+            Ok(output) => output,
+            Err(e) => format!("<failed to fetch logs: {}>", e),
+        };
+        logs.insert(name, output);
     }
+
+    Ok(logs)
 }
 
 // Define structs for the workflow status information
@@ -689,10 +909,47 @@ pub async fn load_historical_timings() -> Result<()> {
     Ok(())
 }
 
-// Store timing information after a successful workflow
-fn store_timing_info(template_name: &str, tasks: &[TaskInfo]) {
-    const MAX_TIMING_HISTORY: usize = 50; // Keep only the last 50 runs of timing data
+// Keep only the last 50 runs of timing data
+const MAX_TIMING_HISTORY: usize = 50;
+
+// Add one run's duration to an action's history and save it
+fn push_timing(template_timings: &mut HashMap<String, Vec<u64>>, template_name: &str, action_name: &str, seconds: u64) {
+    let durations = template_timings
+        .entry(action_name.to_string())
+        .or_insert_with(Vec::new);
+        
+    durations.push(seconds);
+    
+    // Trim the list to keep only the most recent MAX_TIMING_HISTORY entries
+    if durations.len() > MAX_TIMING_HISTORY {
+        // Remove the oldest entries (those at the start of the vector)
+        *durations = durations.iter().skip(durations.len() - MAX_TIMING_HISTORY).cloned().collect();
+    }
     
+    // Save to database asynchronously
+    tokio::spawn(save_timing_to_db(
+        template_name.to_string(),
+        action_name.to_string(),
+        durations.clone()
+    ));
+}
+
+// Store an action duration measured by the installer itself (see install_telemetry)
+pub fn record_measured_timing(template_name: &str, action_name: &str, seconds: u64) {
+    info!("Saving measured timing data: {}:{} = {}s", template_name, action_name, seconds);
+    if let Ok(mut timings) = HISTORICAL_TIMINGS.write() {
+        let template_timings = timings
+            .entry(template_name.to_string())
+            .or_insert_with(HashMap::new);
+        push_timing(template_timings, template_name, action_name, seconds);
+    } else {
+        error!("Failed to acquire write lock for storing timing data");
+    }
+}
+
+// Store timing information after a successful workflow. Actions in `measured` already
+// got an installer-reported duration for this install, which is more accurate.
+fn store_timing_info(template_name: &str, tasks: &[TaskInfo], measured: &[String]) {
     info!("Attempting to store timing data for {} tasks in template '{}'", tasks.len(), template_name);
     
     if let Ok(mut timings) = HISTORICAL_TIMINGS.write() {
@@ -708,28 +965,15 @@ fn store_timing_info(template_name: &str, tasks: &[TaskInfo]) {
                 warn!("Task '{}' has zero reported_duration, skipping", task.name);
                 continue;
             }
+            if measured.contains(&task.name) {
+                info!("Task '{}' was timed by the installer, skipping observed duration", task.name);
+                continue;
+            }
             
             info!("Saving timing data: {}:{} = {}s", template_name, task.name, task.reported_duration);
             
-            let durations = template_timings
-                .entry(task.name.clone())
-                .or_insert_with(Vec::new);
-                
             // Only store reported_duration (actual time taken)
-            durations.push(task.reported_duration);
-            
-            // Trim the list to keep only the most recent MAX_TIMING_HISTORY entries
-            if durations.len() > MAX_TIMING_HISTORY {
-                // Remove the oldest entries (those at the start of the vector)
-                *durations = durations.iter().skip(durations.len() - MAX_TIMING_HISTORY).cloned().collect();
-            }
-            
-            // Save to database asynchronously
-            tokio::spawn(save_timing_to_db(
-                template_name.to_string(),
-                task.name.clone(),
-                durations.clone()
-            ));
+            push_timing(template_timings, template_name, &task.name, task.reported_duration);
         }
     } else {
         error!("Failed to acquire write lock for storing timing data");
@@ -850,7 +1094,7 @@ pub async fn get_workflow_info(machine: &Machine) -> Result<Option<WorkflowInfo>
                     // Store timing data for completed tasks
                     if !tasks.is_empty() {
                         info!("Storing timing data for {} completed tasks from kexec-detected workflow", tasks.len());
-                        store_timing_info(template_ref, &tasks);
+                        store_timing_info(template_ref, &tasks, &crate::install_telemetry::measured_actions(&machine.id).await);
                     }
                     
                     // Create a special WorkflowInfo to indicate this was handled by the hack
@@ -1094,7 +1338,7 @@ pub async fn get_workflow_info(machine: &Machine) -> Result<Option<WorkflowInfo>
                 
                 // If the workflow completed successfully, store the timing information with template reference
                 if state == "STATE_SUCCESS" && tasks.iter().all(|t| t.status == "STATE_SUCCESS") {
-                    store_timing_info(template_ref, &tasks);
+                    store_timing_info(template_ref, &tasks, &crate::install_telemetry::measured_actions(&machine.id).await);
                     
                     // Send a machine_updated event
                     if let Some(event_manager) = get_event_manager() {
@@ -1105,7 +1349,20 @@ pub async fn get_workflow_info(machine: &Machine) -> Result<Option<WorkflowInfo>
                 
                 // If the workflow failed, update the machine status to Error
                 if state == "STATE_FAILED" {
-                    if let Err(e) = update_machine_status_on_failure(machine).await {
+                    // Collect a diagnostics bundle once, on the transition out of InstallingOS
+                    let bundle_id = if machine.status == dragonfly_common::models::MachineStatus::InstallingOS {
+                        match crate::diagnostics::collect_and_store(machine).await {
+                            Ok(id) => Some(id),
+                            Err(e) => {
+                                warn!("Failed to collect diagnostics bundle for machine {}: {}", machine.id, e);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    if let Err(e) = update_machine_status_on_failure(machine, bundle_id).await {
                         warn!("Failed to update machine status after workflow failure: {}", e);
                     }
                     
@@ -1181,16 +1438,22 @@ fn get_event_manager() -> Option<&'static crate::event_manager::EventManager> {
     None // For now, we'll rely on callers to send events properly
 }
 
-// Update machine status when workflow fails
-async fn update_machine_status_on_failure(machine: &Machine) -> Result<()> {
+// Update machine status when workflow fails, linking the diagnostics bundle if one was collected
+async fn update_machine_status_on_failure(machine: &Machine, bundle_id: Option<uuid::Uuid>) -> Result<()> {
     use dragonfly_common::models::MachineStatus;
     
     info!("Workflow failed for machine {}, updating status to Error", machine.id);
     
+    // A failed verification stops the workflow itself, so say what it found
+    let reason = crate::install_verification::failure(&machine.id).await
+        .unwrap_or_else(|| "OS installation failed".to_string());
+    let message = crate::diagnostics::with_bundle_link(reason, &machine.id, bundle_id);
+    
     let mut updated_machine = machine.clone();
-    updated_machine.status = MachineStatus::Error("OS installation failed".to_string());
+    updated_machine.status = MachineStatus::Error(message);
     
     crate::db::update_machine(&updated_machine).await?;
+    crate::usage::record_install(machine, false).await;
     Ok(())
 }
 
@@ -1200,12 +1463,28 @@ async fn update_machine_status_on_success(machine: &Machine) -> Result<()> {
     use dragonfly_common::models::Machine;
     use anyhow::anyhow;
     
+    // Templates without a verification step never fail it; those with one only reach here
+    // if it passed, unless its report and exit status disagree
+    if let Some(message) = crate::install_verification::failure(&machine.id).await {
+        warn!("Workflow completed for machine {} but {}", machine.id, message);
+        crate::db::update_machine(&Machine {
+            status: MachineStatus::Error(message),
+            ..machine.clone()
+        }).await?;
+        crate::usage::record_install(machine, false).await;
+        return Ok(());
+    }
+    
     info!("Workflow completed successfully for machine {}, updating status to Ready", machine.id);
     
     // First update just the status for reliability
     match crate::db::update_status(&machine.id, MachineStatus::Ready).await {
         Ok(true) => {
             info!("Successfully updated status to Ready for machine {}", machine.id);
+            if let Err(e) = crate::db::mark_machine_image_installed(&machine.id, chrono::Utc::now()).await {
+                warn!("Failed to record completed install for machine {}: {}", machine.id, e);
+            }
+            crate::usage::record_install(machine, true).await;
             
             // Calculate deployment duration
             if machine.status == MachineStatus::InstallingOS {
@@ -1349,6 +1628,9 @@ pub async fn start_timing_cleanup_task(mut shutdown_rx: tokio::sync::watch::Rece
         loop {
             tokio::select! {
                 _ = tokio::time::sleep(cleanup_interval) => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
                     info!("Running timing cleanup task");
                     if let Err(e) = cleanup_historical_timings().await {
                         error!("Error during timing cleanup: {}", e);
@@ -1473,6 +1755,9 @@ pub async fn start_workflow_polling_task(
             // Wait for the poll interval or shutdown signal
             tokio::select! {
                 _ = tokio::time::sleep(poll_interval) => { 
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
                     // Get all machines with InstallingOS status
                     let machines = match crate::db::get_machines_by_status(MachineStatus::InstallingOS).await {
                         Ok(machines) => machines,
@@ -1548,6 +1833,270 @@ pub async fn start_workflow_polling_task(
     });
 }
 
+// --- Stuck-workflow watchdog ---
+//
+// A workflow whose actions haven't changed state for DRAGONFLY_WORKFLOW_STALL_MINUTES
+// (default 30, 0 disables the watchdog) is considered stuck. Each time that happens the
+// watchdog collects a diagnostics bundle and takes the next step of
+// DRAGONFLY_WORKFLOW_STALL_POLICY (default "retry,power_cycle,fail"): recreate the
+// workflow, recreate it and PXE-reboot the machine through its BMC, or mark the install
+// failed. Steps a machine doesn't support are skipped; once the policy is used up the
+// install is marked failed.
+
+const DEFAULT_STALL_MINUTES: i64 = 30;
+const WATCHDOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+// One step of the stall remediation policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StallRemediation {
+    Retry,
+    PowerCycle,
+    Fail,
+}
+
+impl StallRemediation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StallRemediation::Retry => "retry",
+            StallRemediation::PowerCycle => "power_cycle",
+            StallRemediation::Fail => "fail",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().replace('-', "_").as_str() {
+            "retry" => Some(StallRemediation::Retry),
+            "power_cycle" => Some(StallRemediation::PowerCycle),
+            "fail" => Some(StallRemediation::Fail),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    pub stall_after: chrono::Duration,
+    pub policy: Vec<StallRemediation>,
+}
+
+impl WatchdogConfig {
+    // None when the watchdog is disabled
+    pub fn parse(minutes: Option<&str>, policy: Option<&str>) -> Result<Option<Self>> {
+        let minutes = match minutes.map(str::trim).filter(|m| !m.is_empty()) {
+            Some(m) => m.parse::<i64>().map_err(|_| anyhow!("stall minutes must be a whole number, got '{}'", m))?,
+            None => DEFAULT_STALL_MINUTES,
+        };
+        if minutes <= 0 {
+            return Ok(None);
+        }
+        let policy = match policy.map(str::trim).filter(|p| !p.is_empty()) {
+            Some(p) => p.split(',')
+                .map(|step| StallRemediation::parse(step).ok_or_else(|| anyhow!("unknown remediation step '{}'", step.trim())))
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![StallRemediation::Retry, StallRemediation::PowerCycle, StallRemediation::Fail],
+        };
+        Ok(Some(Self { stall_after: chrono::Duration::minutes(minutes), policy }))
+    }
+
+    fn from_env() -> Option<Self> {
+        let minutes = crate::config::var("DRAGONFLY_WORKFLOW_STALL_MINUTES");
+        let policy = crate::config::var("DRAGONFLY_WORKFLOW_STALL_POLICY");
+        Self::parse(minutes.as_deref(), policy.as_deref()).unwrap_or_else(|e| {
+            error!("Invalid workflow watchdog settings, using defaults: {}", e);
+            Self::parse(None, None).ok().flatten()
+        })
+    }
+
+    // The step for a machine's `attempt`th stall (counting from 0) and the attempt after it
+    pub fn next_step(&self, attempt: usize, can_power_cycle: bool) -> (StallRemediation, usize) {
+        self.policy.iter()
+            .enumerate()
+            .skip(attempt)
+            .find(|(_, step)| can_power_cycle || **step != StallRemediation::PowerCycle)
+            .map(|(i, step)| (*step, i + 1))
+            .unwrap_or((StallRemediation::Fail, self.policy.len()))
+    }
+}
+
+// What a workflow has done so far; any change counts as progress
+fn progress_signature(status: &serde_json::Value) -> String {
+    let mut signature = status.get("state").and_then(|s| s.as_str()).unwrap_or("UNKNOWN").to_string();
+    let tasks = status.get("tasks").and_then(|t| t.as_array()).map(Vec::as_slice).unwrap_or_default();
+    for action in tasks.iter().filter_map(|t| t.get("actions").and_then(|a| a.as_array())).flatten() {
+        signature.push_str(&format!("|{}={}",
+            action.get("name").and_then(|n| n.as_str()).unwrap_or_default(),
+            action.get("status").and_then(|s| s.as_str()).unwrap_or_default()));
+    }
+    signature
+}
+
+// The action a workflow is stuck in, for logs and the machine's error message
+fn stalled_action(status: &serde_json::Value) -> String {
+    let tasks = status.get("tasks").and_then(|t| t.as_array()).map(Vec::as_slice).unwrap_or_default();
+    tasks.iter()
+        .filter_map(|t| t.get("actions").and_then(|a| a.as_array()))
+        .flatten()
+        .find(|a| a.get("status").and_then(|s| s.as_str()) == Some("STATE_RUNNING"))
+        .and_then(|a| a.get("name").and_then(|n| n.as_str()))
+        .unwrap_or("waiting to start")
+        .to_string()
+}
+
+struct WatchedWorkflow {
+    signature: String,
+    since: chrono::DateTime<chrono::Utc>,
+    attempt: usize,
+}
+
+// Delete the machine's workflow and submit it again from the start
+async fn restart_workflow(client: &dyn TinkerbellClient, machine: &Machine) -> Result<()> {
+    client.delete(ResourceKind::Workflow, &workflow_resource_name(machine)).await?;
+    create_workflow(client, machine, template_ref_for(machine.os_choice.as_deref())).await
+}
+
+async fn remediate_stalled_workflow(
+    state: &crate::AppState,
+    machine: &Machine,
+    step: StallRemediation,
+    action: &str,
+    stalled_minutes: i64,
+) -> Result<()> {
+    let bundle_id = match crate::diagnostics::collect_and_store(machine).await {
+        Ok(id) => Some(id),
+        Err(e) => {
+            warn!("Failed to collect diagnostics bundle for stalled machine {}: {}", machine.id, e);
+            None
+        }
+    };
+    warn!("Workflow for machine {} made no progress in '{}' for {} minutes, remediating: {}",
+        machine.id, action, stalled_minutes, step.as_str());
+
+    let result = match step {
+        StallRemediation::Retry => restart_workflow(&*state.tinkerbell, machine).await,
+        StallRemediation::PowerCycle => match restart_workflow(&*state.tinkerbell, machine).await {
+            Ok(()) => crate::handlers::machines::bmc_power_action_handler(
+                axum::extract::State(state.clone()),
+                axum::extract::Path(machine.id),
+                axum::Json(crate::handlers::machines::BmcPowerActionRequest { action: "reboot-pxe".to_string() }),
+            ).await.map(|_| ()).map_err(|response| anyhow!("BMC power action failed ({})", response.status())),
+            Err(e) => Err(e),
+        },
+        StallRemediation::Fail => {
+            let mut message = format!("Install stalled in '{}' for {} minutes", action, stalled_minutes);
+            if let Some(id) = bundle_id {
+                message.push_str(&format!(" (diagnostics: {})", crate::diagnostics::bundle_url(&machine.id, &id)));
+            }
+            crate::db::update_machine(&Machine {
+                status: dragonfly_common::models::MachineStatus::Error(message),
+                ..machine.clone()
+            }).await
+        }
+    };
+
+    let _ = state.event_manager.send(format!("workflow_stalled:{}:{}", machine.id, step.as_str()));
+    let _ = state.event_manager.send(format!("machine_updated:{}", machine.id));
+    result
+}
+
+async fn check_stalled_workflows(
+    state: &crate::AppState,
+    config: &WatchdogConfig,
+    watched: &mut HashMap<uuid::Uuid, WatchedWorkflow>,
+) {
+    use dragonfly_common::models::MachineStatus;
+
+    let machines = match crate::db::get_machines_by_status(MachineStatus::InstallingOS).await {
+        Ok(machines) => machines,
+        Err(e) => {
+            error!("Failed to get installing machines for the workflow watchdog: {}", e);
+            return;
+        }
+    };
+    watched.retain(|id, _| machines.iter().any(|m| m.id == *id));
+
+    let now = chrono::Utc::now();
+    for machine in &machines {
+        let status = match get_workflow_status_raw(machine).await {
+            Ok(Some(status)) => status,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Workflow watchdog could not read the workflow of machine {}: {}", machine.id, e);
+                continue;
+            }
+        };
+        // Finished workflows are the polling task's business
+        let workflow_state = status.get("state").and_then(|s| s.as_str()).unwrap_or_default();
+        if workflow_state != "STATE_RUNNING" && workflow_state != "STATE_PENDING" {
+            continue;
+        }
+
+        let signature = progress_signature(&status);
+        let entry = watched.entry(machine.id).or_insert_with(|| WatchedWorkflow {
+            signature: signature.clone(),
+            since: now,
+            attempt: 0,
+        });
+        if entry.signature != signature {
+            entry.signature = signature;
+            entry.since = now;
+            continue;
+        }
+        if now - entry.since < config.stall_after {
+            continue;
+        }
+
+        // Someone is working on the machine by hand; leave it be
+        if matches!(crate::maintenance::status(&machine.id).await, Ok(s) if s.maintenance) {
+            continue;
+        }
+        // Restarting the workflow would only feed the loop
+        if crate::boot_loop::is_flagged(&machine.id).await {
+            continue;
+        }
+
+        let can_power_cycle = machine.proxmox_vmid.is_some() && machine.proxmox_node.is_some();
+        let (step, next_attempt) = config.next_step(entry.attempt, can_power_cycle);
+        let stalled_minutes = (now - entry.since).num_minutes();
+        entry.attempt = next_attempt;
+        entry.since = now;
+        if let Err(e) = remediate_stalled_workflow(state, machine, step, &stalled_action(&status), stalled_minutes).await {
+            error!("Remediation '{}' failed for machine {}: {}", step.as_str(), machine.id, e);
+        }
+    }
+}
+
+// Watch installing machines for workflows that stopped making progress
+pub async fn start_workflow_watchdog_task(
+    state: std::sync::Arc<crate::AppState>,
+    mut shutdown_rx: tokio::sync::watch::Receiver<()>,
+) {
+    let Some(config) = WatchdogConfig::from_env() else {
+        info!("Workflow watchdog disabled");
+        return;
+    };
+    info!("Starting workflow watchdog: stall after {} minutes, policy {:?}",
+        config.stall_after.num_minutes(), config.policy.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+
+    tokio::spawn(async move {
+        let mut watched = HashMap::new();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(WATCHDOG_INTERVAL) => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
+                    check_stalled_workflows(&state, &config, &mut watched).await;
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping workflow watchdog.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
 // Get workflow information from Kubernetes for a specific machine ID
 pub async fn get_workflow_info_by_id(id: &uuid::Uuid) -> Result<Option<WorkflowInfo>> {
     // First, find the machine by ID
@@ -1565,4 +2114,48 @@ pub async fn get_workflow_info_by_id(id: &uuid::Uuid) -> Result<Option<WorkflowI
             Err(anyhow!("Error fetching machine: {}", e))
         }
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dragonfly_common::models::DiskInfo;
+
+    fn disk(device: &str, size_bytes: u64) -> DiskInfo {
+        DiskInfo { device: device.to_string(), size_bytes, model: None, calculated_size: None }
+    }
+
+    #[test]
+    fn test_template_ref_defaults_when_no_os_is_chosen() {
+        assert_eq!(template_ref_for(Some("debian-12")), "debian-12");
+        assert_eq!(template_ref_for(None), OsIdentifier::default().as_str());
+    }
+
+    #[test]
+    fn test_template_image_path() {
+        let template = "tasks:\n  - actions:\n      - name: stream image\n        environment:\n          IMG_URL: \"http://10.0.0.1:3000/ipxe/ubuntu/jammy.raw\"\n";
+        assert_eq!(template_image_path(template).as_deref(), Some("ubuntu/jammy.raw"));
+        assert_eq!(template_image_path("IMG_URL: \"https://example.com/jammy.raw\""), None);
+        assert_eq!(template_image_path("tasks: []"), None);
+    }
+
+    #[test]
+    fn test_disk_check() {
+        let layout = crate::disk_layout::DiskLayout::default();
+        assert!(!disk_check(&[], &layout).passed);
+
+        let small = disk_check(&[disk("/dev/sda", 4 << 30)], &layout);
+        assert!(!small.passed);
+        assert!(small.message.contains("/dev/sda"));
+
+        let disks = [disk("/dev/sda", 500 << 30), disk("/dev/sdb", 500 << 30)];
+        let ok = disk_check(&disks, &layout);
+        assert!(ok.passed);
+        assert_eq!(ok.message, format!("Image will be written to /dev/sda ({} bytes)", 500u64 << 30));
+
+        // A multi-disk profile lists every disk the image goes to
+        let replicated = disk_check(&disks, &crate::disk_layout::DiskLayout::parse(Some("multi=replicate")).unwrap());
+        assert!(replicated.passed);
+        assert!(replicated.message.contains("2 disks") && replicated.message.contains("/dev/sda, /dev/sdb"));
+    }
+}
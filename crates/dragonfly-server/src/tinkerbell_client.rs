@@ -0,0 +1,350 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use kube::{
+    api::{Api, DeleteParams, Patch, PatchParams, PostParams},
+    core::{ApiResource, DynamicObject, ObjectMeta, TypeMeta},
+    Error as KubeError,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// Namespace all Tinkerbell resources live in
+const NAMESPACE: &str = "tink";
+
+const API_VERSION: &str = "tinkerbell.org/v1alpha1";
+
+/// The Tinkerbell custom resources Dragonfly manages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceKind {
+    Hardware,
+    Workflow,
+    Template,
+}
+
+impl ResourceKind {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ResourceKind::Hardware => "Hardware",
+            ResourceKind::Workflow => "Workflow",
+            ResourceKind::Template => "Template",
+        }
+    }
+
+    fn plural(&self) -> &'static str {
+        match self {
+            ResourceKind::Hardware => "hardware",
+            ResourceKind::Workflow => "workflows",
+            ResourceKind::Template => "templates",
+        }
+    }
+
+    fn api_resource(&self) -> ApiResource {
+        ApiResource {
+            group: "tinkerbell.org".to_string(),
+            version: "v1alpha1".to_string(),
+            kind: self.kind().to_string(),
+            api_version: API_VERSION.to_string(),
+            plural: self.plural().to_string(),
+        }
+    }
+}
+
+/// Access to Tinkerbell resources. Bodies are full manifests (apiVersion, kind,
+/// metadata, spec); implementations take the name from the `name` argument.
+#[async_trait]
+pub trait TinkerbellClient: Send + Sync {
+    /// Fails when the cluster can't be reached, so callers can skip Tinkerbell entirely
+    async fn connect(&self) -> Result<()>;
+    async fn get(&self, kind: ResourceKind, name: &str) -> Result<Option<Value>>;
+    async fn create(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()>;
+    /// JSON merge patch of an existing resource
+    async fn patch(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()>;
+    /// Returns false if the resource didn't exist
+    async fn delete(&self, kind: ResourceKind, name: &str) -> Result<bool>;
+}
+
+/// Create a resource, or merge-patch it if it already exists.
+pub async fn upsert(client: &dyn TinkerbellClient, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+    match client.get(kind, name).await? {
+        Some(_) => client.patch(kind, name, body).await,
+        None => client.create(kind, name, body).await,
+    }
+}
+
+/// The real client, backed by the shared kube-rs connection.
+#[derive(Debug, Default, Clone)]
+pub struct KubeTinkerbellClient;
+
+impl KubeTinkerbellClient {
+    async fn api(&self, kind: ResourceKind) -> Result<Api<DynamicObject>> {
+        let client = crate::tinkerbell::get_client().await?;
+        Ok(Api::namespaced_with(client.clone(), NAMESPACE, &kind.api_resource()))
+    }
+
+    fn object(kind: ResourceKind, name: &str, mut body: Value) -> DynamicObject {
+        // Type and object metadata are set explicitly; keep them out of the flattened data
+        if let Some(fields) = body.as_object_mut() {
+            fields.remove("apiVersion");
+            fields.remove("kind");
+            fields.remove("metadata");
+        }
+        DynamicObject {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(NAMESPACE.to_string()),
+                ..Default::default()
+            },
+            types: Some(TypeMeta {
+                api_version: API_VERSION.to_string(),
+                kind: kind.kind().to_string(),
+            }),
+            data: body,
+        }
+    }
+}
+
+#[async_trait]
+impl TinkerbellClient for KubeTinkerbellClient {
+    async fn connect(&self) -> Result<()> {
+        crate::tinkerbell::get_client().await.map(|_| ())
+    }
+
+    async fn get(&self, kind: ResourceKind, name: &str) -> Result<Option<Value>> {
+        let object = self.api(kind).await?.get_opt(name).await
+            .map_err(|e| anyhow!("Failed to look up {} resource {}: {}", kind.kind(), name, e))?;
+        object.map(|o| serde_json::to_value(o).map_err(Into::into)).transpose()
+    }
+
+    async fn create(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+        self.api(kind).await?
+            .create(&PostParams::default(), &Self::object(kind, name, body))
+            .await
+            .map_err(|e| anyhow!("Failed to create {} resource {}: {}", kind.kind(), name, e))?;
+        Ok(())
+    }
+
+    async fn patch(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+        self.api(kind).await?
+            .patch(name, &PatchParams::default(), &Patch::Merge(Self::object(kind, name, body)))
+            .await
+            .map_err(|e| anyhow!("Failed to update {} resource {}: {}", kind.kind(), name, e))?;
+        Ok(())
+    }
+
+    async fn delete(&self, kind: ResourceKind, name: &str) -> Result<bool> {
+        match self.api(kind).await?.delete(name, &DeleteParams::default()).await {
+            Ok(_) => Ok(true),
+            Err(KubeError::Api(ae)) if ae.code == 404 => Ok(false),
+            Err(e) => Err(anyhow!("Failed to delete {} resource {}: {}", kind.kind(), name, e)),
+        }
+    }
+}
+
+/// An in-memory stand-in for the cluster, for tests that exercise Tinkerbell logic.
+#[derive(Debug, Default)]
+pub struct FakeTinkerbellClient {
+    resources: Mutex<BTreeMap<(ResourceKind, String), Value>>,
+    unavailable: AtomicBool,
+    fail_writes: AtomicBool,
+}
+
+impl FakeTinkerbellClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cluster that is never reachable
+    pub fn unavailable() -> Self {
+        let fake = Self::default();
+        fake.unavailable.store(true, Ordering::Relaxed);
+        fake
+    }
+
+    pub fn with_template(self, name: &str) -> Self {
+        self.insert(ResourceKind::Template, name, serde_json::json!({ "spec": {} }));
+        self
+    }
+
+    pub fn insert(&self, kind: ResourceKind, name: &str, body: Value) {
+        if let Ok(mut resources) = self.resources.lock() {
+            resources.insert((kind, name.to_string()), body);
+        }
+    }
+
+    /// Make creates and patches fail, as if the API server rejected them
+    pub fn set_fail_writes(&self, fail: bool) {
+        self.fail_writes.store(fail, Ordering::Relaxed);
+    }
+
+    pub fn resource(&self, kind: ResourceKind, name: &str) -> Option<Value> {
+        self.resources.lock().ok()?.get(&(kind, name.to_string())).cloned()
+    }
+
+    pub fn names(&self, kind: ResourceKind) -> Vec<String> {
+        match self.resources.lock() {
+            Ok(resources) => resources.keys().filter(|(k, _)| *k == kind).map(|(_, name)| name.clone()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn check_write(&self, kind: ResourceKind, name: &str) -> Result<()> {
+        self.check_connected()?;
+        if self.fail_writes.load(Ordering::Relaxed) {
+            return Err(anyhow!("Write of {} resource {} rejected", kind.kind(), name));
+        }
+        Ok(())
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.unavailable.load(Ordering::Relaxed) {
+            return Err(anyhow!("Kubernetes API server unreachable"));
+        }
+        Ok(())
+    }
+}
+
+// Recursive JSON merge patch (RFC 7386)
+fn merge(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+#[async_trait]
+impl TinkerbellClient for FakeTinkerbellClient {
+    async fn connect(&self) -> Result<()> {
+        self.check_connected()
+    }
+
+    async fn get(&self, kind: ResourceKind, name: &str) -> Result<Option<Value>> {
+        self.check_connected()?;
+        Ok(self.resource(kind, name))
+    }
+
+    async fn create(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+        self.check_write(kind, name)?;
+        let mut resources = self.resources.lock().map_err(|_| anyhow!("Fake client lock poisoned"))?;
+        let key = (kind, name.to_string());
+        if resources.contains_key(&key) {
+            return Err(anyhow!("{} resource {} already exists", kind.kind(), name));
+        }
+        resources.insert(key, body);
+        Ok(())
+    }
+
+    async fn patch(&self, kind: ResourceKind, name: &str, body: Value) -> Result<()> {
+        self.check_write(kind, name)?;
+        let mut resources = self.resources.lock().map_err(|_| anyhow!("Fake client lock poisoned"))?;
+        let existing = resources.get_mut(&(kind, name.to_string()))
+            .ok_or_else(|| anyhow!("{} resource {} not found", kind.kind(), name))?;
+        merge(existing, body);
+        Ok(())
+    }
+
+    async fn delete(&self, kind: ResourceKind, name: &str) -> Result<bool> {
+        self.check_write(kind, name)?;
+        let mut resources = self.resources.lock().map_err(|_| anyhow!("Fake client lock poisoned"))?;
+        Ok(resources.remove(&(kind, name.to_string())).is_some())
+    }
+}
+
+static CLIENT: OnceLock<Arc<dyn TinkerbellClient>> = OnceLock::new();
+
+/// Set the client used by code that has no AppState to hand. Only the first call takes effect.
+pub fn install(client: Arc<dyn TinkerbellClient>) {
+    let _ = CLIENT.set(client);
+}
+
+/// The installed client, or the kube-rs one if none was installed.
+pub fn client() -> Arc<dyn TinkerbellClient> {
+    CLIENT.get_or_init(|| crate::chaos::wrap_tinkerbell(Arc::new(KubeTinkerbellClient))).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tinkerbell::{create_workflow, delete_hardware, hardware_resource_name, register_machine};
+    use dragonfly_common::models::{Machine, MachineStatus};
+
+    fn machine(os_choice: Option<&str>) -> Machine {
+        Machine {
+            mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+            // Not an IP, so registration skips the reverse DNS lookup
+            ip_address: "unknown".to_string(),
+            hostname: Some("node-1".to_string()),
+            os_choice: os_choice.map(str::to_string),
+            status: MachineStatus::AwaitingAssignment,
+            ..dragonfly_common::testing::machine()
+        }
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = serde_json::json!({ "spec": { "a": 1, "b": 2 } });
+        merge(&mut target, serde_json::json!({ "spec": { "b": null, "c": 3 } }));
+        assert_eq!(target, serde_json::json!({ "spec": { "a": 1, "c": 3 } }));
+    }
+
+    #[tokio::test]
+    async fn test_register_creates_then_updates_hardware() {
+        let fake = FakeTinkerbellClient::new();
+        let mut machine = machine(None);
+        register_machine(&fake, &machine).await.unwrap();
+
+        let name = hardware_resource_name(&machine.mac_address);
+        let hardware = fake.resource(ResourceKind::Hardware, &name).unwrap();
+        assert_eq!(hardware["spec"]["metadata"]["instance"]["hostname"], "node-1");
+
+        machine.hostname = Some("node-2".to_string());
+        register_machine(&fake, &machine).await.unwrap();
+        let hardware = fake.resource(ResourceKind::Hardware, &name).unwrap();
+        assert_eq!(hardware["spec"]["metadata"]["instance"]["hostname"], "node-2");
+    }
+
+    #[tokio::test]
+    async fn test_register_surfaces_unreachable_cluster_and_write_errors() {
+        let machine = machine(None);
+        assert!(register_machine(&FakeTinkerbellClient::unavailable(), &machine).await.is_err());
+
+        let fake = FakeTinkerbellClient::new();
+        fake.set_fail_writes(true);
+        assert!(register_machine(&fake, &machine).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_workflow_requires_template() {
+        let machine = machine(Some("debian-12"));
+        assert!(create_workflow(&FakeTinkerbellClient::new(), &machine, "debian-12").await.is_err());
+
+        let fake = FakeTinkerbellClient::new().with_template("debian-12");
+        create_workflow(&fake, &machine, "debian-12").await.unwrap();
+        let workflow = fake.resource(ResourceKind::Workflow, "os-install-aa-bb-cc-dd-ee-ff").unwrap();
+        assert_eq!(workflow["spec"]["templateRef"], "debian-12");
+        assert_eq!(workflow["spec"]["hardwareRef"], hardware_resource_name(&machine.mac_address));
+    }
+
+    #[tokio::test]
+    async fn test_delete_removes_hardware_and_workflow() {
+        let machine = machine(Some("debian-12"));
+        let fake = FakeTinkerbellClient::new().with_template("debian-12");
+        register_machine(&fake, &machine).await.unwrap();
+        create_workflow(&fake, &machine, "debian-12").await.unwrap();
+
+        delete_hardware(&fake, &machine.mac_address).await.unwrap();
+        assert!(fake.names(ResourceKind::Hardware).is_empty());
+        assert!(fake.names(ResourceKind::Workflow).is_empty());
+        // Deleting again is not an error
+        delete_hardware(&fake, &machine.mac_address).await.unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+// Outbox for Tinkerbell operations that can't reach Kubernetes.
+//
+// Hardware records already have a retry queue of their own (a machine stays unsynced until its
+// record is written, see tinkerbell_sync). Workflow creation and hardware deletion don't live on
+// a machine row, so when the kube API is down they're written here instead of being dropped with
+// a warning, and replayed in order by the sync retry task once the API answers again, after any
+// pending hardware records. Operations that fail for other reasons keep their error and back off
+// like hardware syncs; /api/status shows how much is waiting.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+use crate::tinkerbell_client::TinkerbellClient;
+
+/// A Tinkerbell write waiting for the cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    CreateWorkflow { os_choice: String },
+    DeleteHardware { mac_address: String },
+}
+
+impl Operation {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Operation::CreateWorkflow { .. } => "create_workflow",
+            Operation::DeleteHardware { .. } => "delete_hardware",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingOperation {
+    pub id: i64,
+    pub machine_id: Uuid,
+    #[serde(flatten)]
+    pub operation: Operation,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+impl PendingOperation {
+    /// Whether the backoff since the last failed attempt has elapsed.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        match self.last_attempt_at {
+            Some(at) => now >= at + chrono::Duration::seconds(crate::tinkerbell_sync::retry_delay_secs(self.attempts)),
+            None => true,
+        }
+    }
+}
+
+/// Whether an operation went through or is waiting in the outbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    Queued,
+}
+
+/// What's waiting to be written to Tinkerbell.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Backlog {
+    pub kubernetes_reachable: bool,
+    /// Machines whose hardware record still needs writing
+    pub hardware_pending: usize,
+    /// Queued workflow creations and hardware deletions, by operation
+    pub operations_pending: BTreeMap<String, usize>,
+    /// When the oldest queued operation was queued
+    pub oldest_queued_at: Option<DateTime<Utc>>,
+}
+
+pub fn backlog(reachable: bool, hardware_pending: usize, operations: &[PendingOperation]) -> Backlog {
+    let mut operations_pending = BTreeMap::new();
+    for op in operations {
+        *operations_pending.entry(op.operation.kind().to_string()).or_insert(0) += 1;
+    }
+    Backlog {
+        kubernetes_reachable: reachable,
+        hardware_pending,
+        operations_pending,
+        oldest_queued_at: operations.iter().map(|op| op.created_at).min(),
+    }
+}
+
+async fn enqueue(machine_id: &Uuid, operation: Operation, reason: &anyhow::Error) -> Result<Outcome> {
+    crate::db::enqueue_tinkerbell_op(machine_id, &operation).await?;
+    warn!("Kubernetes is unreachable, queued {} for machine {}: {}", operation.kind(), machine_id, reason);
+    Ok(Outcome::Queued)
+}
+
+/// Create the machine's install workflow, or queue it if Kubernetes can't be reached.
+pub async fn create_workflow(client: &dyn TinkerbellClient, machine: &Machine, os_choice: &str) -> Result<Outcome> {
+    if let Err(e) = client.connect().await {
+        return enqueue(&machine.id, Operation::CreateWorkflow { os_choice: os_choice.to_string() }, &e).await;
+    }
+    crate::tinkerbell::create_workflow(client, machine, os_choice).await?;
+    Ok(Outcome::Done)
+}
+
+/// Delete a MAC's hardware record and workflow, or queue the deletion if Kubernetes can't be reached.
+pub async fn delete_hardware(client: &dyn TinkerbellClient, machine_id: &Uuid, mac_address: &str) -> Result<Outcome> {
+    if let Err(e) = client.connect().await {
+        return enqueue(machine_id, Operation::DeleteHardware { mac_address: mac_address.to_string() }, &e).await;
+    }
+    crate::tinkerbell::delete_hardware(client, mac_address).await?;
+    Ok(Outcome::Done)
+}
+
+// Run one queued operation; Ok(false) when it has to wait for something else first
+async fn run(client: &dyn TinkerbellClient, op: &PendingOperation) -> Result<bool> {
+    match &op.operation {
+        Operation::CreateWorkflow { os_choice } => {
+            let machine = match crate::db::get_machine_by_id(&op.machine_id).await? {
+                // Only still wanted if nothing has changed the machine's plans since
+                Some(machine) if machine.status == MachineStatus::InstallingOS => machine,
+                _ => {
+                    info!("Dropping queued workflow for machine {}, which is no longer installing", op.machine_id);
+                    return Ok(true);
+                }
+            };
+            // The workflow refers to the hardware record, which is replayed separately
+            if !machine.tinkerbell_synced {
+                return Ok(false);
+            }
+            crate::tinkerbell::create_workflow(client, &machine, os_choice).await?;
+        }
+        Operation::DeleteHardware { mac_address } => {
+            // Re-registered since, so the record belongs to a live machine again
+            if crate::db::get_machine_by_mac(&mac_address.replace('-', ":")).await?.is_some() {
+                info!("Dropping queued deletion of hardware for {}, which has registered again", mac_address);
+                return Ok(true);
+            }
+            crate::tinkerbell::delete_hardware(client, mac_address).await?;
+        }
+    }
+    Ok(true)
+}
+
+/// Replay queued operations, oldest first. Only called once Kubernetes is reachable.
+pub async fn replay(client: &dyn TinkerbellClient, event_manager: &EventManager) {
+    let pending = match crate::db::list_tinkerbell_ops().await {
+        Ok(pending) => pending,
+        Err(e) => {
+            warn!("Failed to list queued Tinkerbell operations: {}", e);
+            return;
+        }
+    };
+    let now = Utc::now();
+    for op in pending.into_iter().filter(|op| op.is_due(now)) {
+        match run(client, &op).await {
+            Ok(true) => {
+                if let Err(e) = crate::db::delete_tinkerbell_op(op.id).await {
+                    warn!("Failed to remove replayed Tinkerbell operation {}: {}", op.id, e);
+                }
+                info!("Replayed queued {} for machine {}", op.operation.kind(), op.machine_id);
+                let _ = event_manager.send(format!("machine_updated:{}", op.machine_id));
+            }
+            Ok(false) => {}
+            Err(e) => {
+                warn!("Queued {} for machine {} failed (attempt {}): {}", op.operation.kind(), op.machine_id, op.attempts + 1, e);
+                if let Err(db_err) = crate::db::record_tinkerbell_op_failure(op.id, &e.to_string()).await {
+                    warn!("Failed to record Tinkerbell operation failure: {}", db_err);
+                }
+            }
+        }
+    }
+}
+
+/// The current backlog, checking whether Kubernetes answers.
+pub async fn status() -> Result<Backlog> {
+    let reachable = crate::tinkerbell_client::client().connect().await.is_ok();
+    let hardware_pending = crate::db::list_pending_tinkerbell_syncs().await?.len();
+    let operations = crate::db::list_tinkerbell_ops().await?;
+    Ok(backlog(reachable, hardware_pending, &operations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(id: i64, operation: Operation, minutes_ago: i64) -> PendingOperation {
+        PendingOperation {
+            id,
+            machine_id: Uuid::new_v4(),
+            operation,
+            attempts: 0,
+            last_error: None,
+            created_at: Utc::now() - chrono::Duration::minutes(minutes_ago),
+            last_attempt_at: None,
+        }
+    }
+
+    #[test]
+    fn test_backlog() {
+        let operations = vec![
+            op(1, Operation::DeleteHardware { mac_address: "aa:bb:cc:dd:ee:ff".to_string() }, 30),
+            op(2, Operation::CreateWorkflow { os_choice: "debian-12".to_string() }, 10),
+            op(3, Operation::CreateWorkflow { os_choice: "ubuntu-2404".to_string() }, 5),
+        ];
+        let backlog = backlog(false, 2, &operations);
+        assert!(!backlog.kubernetes_reachable);
+        assert_eq!(backlog.hardware_pending, 2);
+        assert_eq!(backlog.operations_pending["create_workflow"], 2);
+        assert_eq!(backlog.operations_pending["delete_hardware"], 1);
+        assert_eq!(backlog.oldest_queued_at, Some(operations[0].created_at));
+    }
+
+    #[test]
+    fn test_operation_serialization() {
+        let operation = Operation::CreateWorkflow { os_choice: "debian-12".to_string() };
+        let json = serde_json::to_string(&operation).unwrap();
+        assert_eq!(json, r#"{"op":"create_workflow","os_choice":"debian-12"}"#);
+        assert_eq!(serde_json::from_str::<Operation>(&json).unwrap(), operation);
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use dragonfly_common::models::Machine;
+
+use crate::event_manager::EventManager;
+use crate::tinkerbell_client::TinkerbellClient;
+
+const POLICY_ENV_VAR: &str = "DRAGONFLY_TINKERBELL_SYNC_POLICY";
+
+// How often the retry queue is checked
+const RETRY_INTERVAL_SECS: u64 = 30;
+
+// Backoff doubles per failed attempt up to this ceiling
+const MAX_RETRY_DELAY_SECS: i64 = 1800;
+
+/// What to do when a machine is saved but its Tinkerbell hardware record can't be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Keep the machine and retry in the background
+    Retry,
+    /// Delete a newly registered machine so the agent registers again later.
+    /// Re-registrations of known machines are always retried instead, since the
+    /// previous row has already been overwritten.
+    Rollback,
+}
+
+impl FailurePolicy {
+    pub fn from_env() -> Self {
+        match crate::config::var(POLICY_ENV_VAR).as_deref() {
+            Some(v) if v.eq_ignore_ascii_case("rollback") => FailurePolicy::Rollback,
+            _ => FailurePolicy::Retry,
+        }
+    }
+}
+
+/// A machine whose Tinkerbell hardware record still needs writing.
+#[derive(Debug, Clone)]
+pub struct PendingSync {
+    pub machine_id: Uuid,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+impl PendingSync {
+    /// Whether the backoff since the last failed attempt has elapsed.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        match self.last_attempt_at {
+            Some(at) => now >= at + chrono::Duration::seconds(retry_delay_secs(self.attempts)),
+            None => true,
+        }
+    }
+}
+
+/// Seconds to wait after `attempts` failures before trying again.
+pub fn retry_delay_secs(attempts: u32) -> i64 {
+    if attempts == 0 {
+        return 0;
+    }
+    (RETRY_INTERVAL_SECS as i64)
+        .saturating_mul(1i64 << (attempts - 1).min(16))
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+// Write the machine's hardware record to Tinkerbell and record the outcome
+async fn sync_machine(machine_id: &Uuid) -> Result<()> {
+    let machine = crate::db::get_machine_by_id(machine_id).await?
+        .ok_or_else(|| anyhow!("Machine {} not found", machine_id))?;
+    match crate::tinkerbell::register_machine(&*crate::tinkerbell_client::client(), &machine).await {
+        Ok(()) => crate::db::mark_tinkerbell_synced(machine_id).await,
+        Err(e) => {
+            if let Err(db_err) = crate::db::mark_tinkerbell_sync_failed(machine_id, &e.to_string()).await {
+                error!("Failed to record Tinkerbell sync failure for machine {}: {}", machine_id, db_err);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Push a changed machine's hardware record to Tinkerbell. If it can't be written (say the
+/// cluster is unreachable), the machine is queued for the retry task instead of the change being lost.
+pub async fn sync_or_queue(client: &dyn TinkerbellClient, machine: &Machine) {
+    let result = match crate::tinkerbell::register_machine(client, machine).await {
+        Ok(()) => crate::db::mark_tinkerbell_synced(&machine.id).await,
+        Err(e) => {
+            warn!("Failed to update machine {} in Tinkerbell, queued for retry: {}", machine.id, e);
+            crate::db::mark_tinkerbell_sync_failed(&machine.id, &e.to_string()).await
+        }
+    };
+    if let Err(e) = result {
+        error!("Failed to record Tinkerbell sync state for machine {}: {}", machine.id, e);
+    }
+}
+
+/// Finish a registration by syncing the machine to Tinkerbell. On failure the machine is
+/// either queued for retry (Ok) or, for new machines under the rollback policy, deleted (Err).
+pub async fn sync_after_registration(machine_id: &Uuid, is_new: bool, event_manager: &EventManager) -> Result<()> {
+    let e = match sync_machine(machine_id).await {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+
+    if is_new && FailurePolicy::from_env() == FailurePolicy::Rollback {
+        warn!("Tinkerbell registration failed for new machine {}, rolling back: {}", machine_id, e);
+        // With the two-person rule on, the rollback waits for approval and the sync is retried meanwhile
+        let summary = format!("Delete machine {}, whose Tinkerbell registration failed", machine_id);
+        if crate::action_approvals::delete_machine(machine_id, summary, "tinkerbell-sync").await? {
+            bail!("Tinkerbell registration failed, registration rolled back: {}", e);
+        }
+        info!("Rolling back machine {} is awaiting approval", machine_id);
+    }
+
+    warn!("Tinkerbell registration failed for machine {}, queued for retry: {}", machine_id, e);
+    // Machines in maintenance are expected to misbehave, so the failure isn't raised
+    if !crate::maintenance::is_active(machine_id).await {
+        let _ = event_manager.send(format!("tinkerbell_sync_failed:{}", machine_id));
+    }
+    Ok(())
+}
+
+/// Retry queued Tinkerbell syncs with exponential backoff, then replay the outbox. Nothing is
+/// attempted while Kubernetes is unreachable, so an outage doesn't use up the backoff.
+pub async fn start_sync_retry_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(RETRY_INTERVAL_SECS));
+        let mut reachable = true;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if !crate::leader::is_leader() {
+                        continue;
+                    }
+                    let client = crate::tinkerbell_client::client();
+                    match client.connect().await {
+                        Ok(()) if !reachable => {
+                            info!("Kubernetes is reachable again, replaying queued Tinkerbell changes");
+                            reachable = true;
+                        }
+                        Ok(()) => {}
+                        Err(e) => {
+                            if reachable {
+                                warn!("Kubernetes is unreachable, queueing Tinkerbell changes until it's back: {}", e);
+                                reachable = false;
+                            }
+                            continue;
+                        }
+                    }
+                    let pending = match crate::db::list_pending_tinkerbell_syncs().await {
+                        Ok(pending) => pending,
+                        Err(e) => {
+                            error!("Failed to list pending Tinkerbell syncs: {}", e);
+                            continue;
+                        }
+                    };
+                    let now = Utc::now();
+                    for sync in pending.into_iter().filter(|s| s.is_due(now)) {
+                        match sync_machine(&sync.machine_id).await {
+                            Ok(()) => {
+                                info!("Tinkerbell sync succeeded for machine {} after {} failed attempts", sync.machine_id, sync.attempts);
+                                let _ = event_manager.send(format!("machine_updated:{}", sync.machine_id));
+                            }
+                            Err(e) => warn!("Tinkerbell sync retry {} failed for machine {}: {}", sync.attempts + 1, sync.machine_id, e),
+                        }
+                    }
+                    // Workflows need their hardware records, so these go after the syncs
+                    crate::tinkerbell_outbox::replay(&*client, &event_manager).await;
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping Tinkerbell sync retry task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_backs_off_to_ceiling() {
+        assert_eq!(retry_delay_secs(0), 0);
+        assert_eq!(retry_delay_secs(1), 30);
+        assert_eq!(retry_delay_secs(2), 60);
+        assert_eq!(retry_delay_secs(4), 240);
+        assert_eq!(retry_delay_secs(10), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay_secs(u32::MAX), MAX_RETRY_DELAY_SECS);
+    }
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc::now();
+        let sync = PendingSync {
+            machine_id: Uuid::new_v4(),
+            attempts: 2,
+            last_error: Some("connection refused".to_string()),
+            last_attempt_at: Some(now - chrono::Duration::seconds(30)),
+        };
+        assert!(!sync.is_due(now));
+        assert!(sync.is_due(now + chrono::Duration::seconds(30)));
+        // Never attempted, e.g. the server stopped between the DB write and the sync
+        assert!(PendingSync { attempts: 0, last_attempt_at: None, ..sync }.is_due(now));
+    }
+}
@@ -75,6 +75,22 @@ pub struct MachineListTemplate {
     pub is_admin: bool,
     pub workflow_infos: HashMap<uuid::Uuid, crate::tinkerbell::WorkflowInfo>,
     pub current_path: String,
+    /// Latest patch report per machine
+    pub patch_statuses: HashMap<uuid::Uuid, crate::patches::PatchStatus>,
+    /// The active ?patches= filter, empty for none
+    pub patch_filter: String,
+    /// Agent, HookOS and boot method each machine was last provisioned with
+    pub boot_environments: HashMap<uuid::Uuid, crate::boot_environment::BootEnvironment>,
+    /// The active ?environment= filter, empty for none
+    pub environment_filter: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MachineListQuery {
+    // Patch state to filter on: pending, security, reboot, current or unknown
+    pub patches: Option<String>,
+    // Boot environment to filter on: outdated, outdated_agent, outdated_hookos or unknown
+    pub environment: Option<String>,
 }
 
 // No Serialize derive needed for Askama
@@ -90,6 +106,7 @@ pub struct MachineDetailsTemplate {
     pub workflow_info: Option<WorkflowInfo>, // Original workflow info for convenience
     pub current_path: String,
     pub ip_address_type: String, // New field for IP address type
+    pub custom_os_choices: Vec<crate::custom_images::CatalogEntry>,
 }
 
 #[derive(Serialize)]
@@ -157,8 +174,23 @@ pub fn render_minijinja<T: Serialize>(
     template_name: &str, 
     context: T
 ) -> Response {
+    match render_to_string(app_state, template_name, context) {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("MiniJinja render/load error for {}: {}", template_name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response()
+        }
+    }
+}
+
+// Render a template to HTML without wrapping it in a response
+pub fn render_to_string<T: Serialize>(
+    app_state: &crate::AppState,
+    template_name: &str,
+    context: T
+) -> Result<String, MiniJinjaError> {
     // Get the environment based on the mode (static or reloading)
-    let render_result = match &app_state.template_env {
+    match &app_state.template_env {
         crate::TemplateEnv::Static(env) => {
             env.get_template(template_name)
                .and_then(|tmpl| tmpl.render(context))
@@ -179,18 +211,55 @@ pub fn render_minijinja<T: Serialize>(
                 }
             }
         }
-    };
-
-    // Handle the final rendering result
-    match render_result {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("MiniJinja render/load error for {}: {}", template_name, e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response()
-        }
     }
 }
 
+// Template names the preview endpoint will load: .html files under the template directory
+pub fn is_previewable_template(name: &str) -> bool {
+    name.ends_with(".html")
+        && !name.starts_with('/')
+        && name.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/'))
+}
+
+// Context for rendering a template outside its page (GET /api/admin/templates/render): the
+// variables the pages share, filled in from one machine. Anything a template needs beyond
+// these renders as undefined, like on a page that doesn't set it.
+pub fn template_preview_context(machine: &Machine, machines: &[Machine], theme: &str) -> serde_json::Value {
+    let ip_address_type = if machine.ip_address.is_empty() || machine.ip_address == "0.0.0.0" {
+        "DHCP"
+    } else {
+        "Static/IPAM"
+    };
+    serde_json::json!({
+        "preview": true,
+        "theme": theme,
+        "is_authenticated": true,
+        "is_admin": true,
+        "is_demo_mode": false,
+        "current_path": format!("/machines/{}", machine.id),
+        "machine": machine,
+        "machine_json": serde_json::to_string(machine).unwrap_or_else(|_| "{}".to_string()),
+        "machines": machines,
+        "total_machines": machines.len(),
+        "status_counts": count_machines_by_status(machines),
+        "workflow_info": null,
+        "workflow_info_json": "null",
+        "workflow_infos": {},
+        "created_at_formatted": format_datetime(&machine.created_at),
+        "updated_at_formatted": format_datetime(&machine.updated_at),
+        "ip_address_type": ip_address_type,
+        "custom_os_choices": [],
+        "title": "Template Preview",
+        "message": "This page was rendered with preview data.",
+        "error_details": "",
+        "back_url": "/machines",
+        "back_text": "Back to Machines",
+        "show_retry": false,
+        "retry_url": "",
+    })
+}
+
 // Create router with state
 pub fn ui_router() -> Router<crate::AppState> {
     Router::new()
@@ -199,11 +268,16 @@ pub fn ui_router() -> Router<crate::AppState> {
         .route("/machines/{id}", get(machine_details))
         .route("/compute", get(compute_page))
         .route("/tags", get(tags_page))
+        .route("/status/{name}", get(public_status_page))
+        .route("/approve/{id}", get(action_approval_page).post(action_approval_decision))
+        .route("/wallboard", get(wallboard_page))
+        .route("/wallboard/tiles", get(wallboard_tiles))
+        .route("/logs", get(logs_page))
         .route("/theme/toggle", get(toggle_theme))
         .route("/settings", get(settings_page))
         .route("/settings", post(update_settings))
         .route("/welcome", get(welcome_page))
-        .route("/setup", get(welcome_page)) // Alias for welcome
+        .route("/setup", get(setup_page))
         .route("/setup/simple", get(setup_simple))
         .route("/setup/flight", get(setup_flight))
         .route("/setup/swarm", get(setup_swarm))
@@ -218,7 +292,9 @@ fn count_machines_by_status(machines: &[Machine]) -> HashMap<String, usize> {
     counts.insert("Awaiting OS Assignment".to_string(), 0);
     counts.insert("Installing OS".to_string(), 0);
     counts.insert("Ready".to_string(), 0);
+    counts.insert("Booted Live".to_string(), 0);
     counts.insert("Offline".to_string(), 0);
+    counts.insert("Powered Off".to_string(), 0);
     counts.insert("Error".to_string(), 0);
     
     // Count actual statuses
@@ -228,7 +304,9 @@ fn count_machines_by_status(machines: &[Machine]) -> HashMap<String, usize> {
             MachineStatus::AwaitingAssignment => "Awaiting OS Assignment",
             MachineStatus::InstallingOS => "Installing OS",
             MachineStatus::Ready => "Ready",
+            MachineStatus::BootedLive => "Booted Live",
             MachineStatus::Offline => "Offline",
+            MachineStatus::PoweredOff => "Powered Off",
             MachineStatus::Error(_) => "Error",
         };
         
@@ -244,7 +322,7 @@ fn format_datetime(dt: &DateTime<Utc>) -> String {
 }
 
 // Function to generate demo machines
-fn generate_demo_machines() -> Vec<Machine> {
+pub fn generate_demo_machines() -> Vec<Machine> {
     let mut machines = Vec::new();
     let base_time = Utc.with_ymd_and_hms(2023, 4, 15, 12, 0, 0).unwrap();
     let base_mac = [0x52, 0x54, 0x00, 0xAB, 0xCD, 0x00];
@@ -396,6 +474,7 @@ fn create_demo_machine(
         proxmox_node: None,
         proxmox_cluster: None, // Add the new field, initialize to None for demo
         is_proxmox_host: false, // Add the new field, default to false for demo data
+        tinkerbell_synced: true,
     }
 }
 
@@ -409,7 +488,7 @@ pub async fn index(
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
     let require_login = app_state.settings.lock().await.require_login;
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
 
     // --- Scenario B Logic --- 
     if app_state.is_demo_mode {
@@ -418,6 +497,11 @@ pub async fn index(
         // The rest of this function will now handle rendering the demo dashboard
         // Ensure is_demo_mode is passed to the template
     } else if app_state.is_installed {
+        // Case B.0: Installed, first-run setup not finished -> Show Setup Wizard
+        if !app_state.is_installation_server && !app_state.settings.lock().await.setup_completed {
+            info!("Installed, setup not completed, redirecting to /setup");
+            return Redirect::to(&crate::base_path::url("/setup")).into_response();
+        }
         // Case B.1 & B.2: Installed
         let current_mode = mode::get_current_mode().await.unwrap_or(None);
         if current_mode.is_none() {
@@ -425,7 +509,7 @@ pub async fn index(
             // BUT ONLY if not in installation server mode
             if !app_state.is_installation_server {
                 info!("Installed, no mode selected, redirecting to /welcome");
-                return Redirect::to("/welcome").into_response();
+                return Redirect::to(&crate::base_path::url("/welcome")).into_response();
             } else {
                 // We're in installation server mode, so we want to show the installation UI
                 info!("Rendering installation UI");
@@ -467,7 +551,7 @@ pub async fn index(
         let current_mode = mode::get_current_mode().await.unwrap_or(None);
         if current_mode.is_some() { // Only redirect if mode is selected
              info!("Login required, redirecting to /login");
-             return Redirect::to("/login").into_response();
+             return Redirect::to(&crate::base_path::url("/login")).into_response();
         }
     }
 
@@ -544,6 +628,7 @@ pub async fn index(
 
 pub async fn machine_list(
     State(app_state): State<crate::AppState>,
+    Query(query): Query<MachineListQuery>,
     headers: HeaderMap,
     auth_session: AuthSession,
     uri: OriginalUri,
@@ -551,7 +636,9 @@ pub async fn machine_list(
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
     let is_admin = is_authenticated;
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
+    let patch_filter = query.patches.as_deref().and_then(crate::patches::PatchFilter::parse);
+    let environment_filter = query.environment.as_deref().and_then(crate::boot_environment::EnvironmentFilter::parse);
 
     let require_login = app_state.settings.lock().await.require_login;
 
@@ -562,8 +649,8 @@ pub async fn machine_list(
         if current_mode.is_none() {
             info!("/machines accessed before mode selection, redirecting to /welcome");
             // Need to return a response that HTMX can use to redirect
-            let mut response = Redirect::to("/welcome").into_response();
-            response.headers_mut().insert("HX-Redirect", "/welcome".parse().unwrap());
+            let mut response = Redirect::to(&crate::base_path::url("/welcome")).into_response();
+            response.headers_mut().insert("HX-Redirect", crate::base_path::url("/welcome").parse().unwrap());
             return response;
         }
     }
@@ -573,8 +660,8 @@ pub async fn machine_list(
     if require_login && !is_authenticated {
         info!("Login required for /machines, redirecting to /login");
         // HTMX redirect
-        let mut response = Redirect::to("/login").into_response();
-        response.headers_mut().insert("HX-Redirect", "/login".parse().unwrap());
+        let mut response = Redirect::to(&crate::base_path::url("/login")).into_response();
+        response.headers_mut().insert("HX-Redirect", crate::base_path::url("/login").parse().unwrap());
         return response;
     }
 
@@ -595,12 +682,31 @@ pub async fn machine_list(
             is_admin,
             workflow_infos,
             current_path,
+            patch_statuses: HashMap::new(),
+            patch_filter: String::new(),
+            boot_environments: HashMap::new(),
+            environment_filter: String::new(),
         };
         return render_minijinja(&app_state, "machine_list.html", context);
     } else { // Normal mode
         // Normal mode - fetch machines from database
         match db::get_all_machines().await {
-            Ok(machines) => {
+            Ok(mut machines) => {
+                let patch_statuses = db::list_patch_statuses().await.unwrap_or_else(|e| {
+                    error!("Failed to load patch reports for machine list: {}", e);
+                    HashMap::new()
+                });
+                if let Some(filter) = patch_filter {
+                    machines.retain(|m| filter.matches(patch_statuses.get(&m.id)));
+                }
+                let boot_environments = db::list_boot_environments().await.unwrap_or_else(|e| {
+                    error!("Failed to load boot environments for machine list: {}", e);
+                    HashMap::new()
+                });
+                if let Some(filter) = environment_filter {
+                    machines.retain(|m| filter.matches(boot_environments.get(&m.id)));
+                }
+
                 let mut workflow_infos = HashMap::new();
                 for machine in &machines {
                     if machine.status == MachineStatus::InstallingOS {
@@ -625,6 +731,10 @@ pub async fn machine_list(
                     is_admin,
                     workflow_infos,
                     current_path,
+                    patch_statuses,
+                    patch_filter: patch_filter.map(|f| f.as_str().to_string()).unwrap_or_default(),
+                    boot_environments,
+                    environment_filter: environment_filter.map(|f| f.as_str().to_string()).unwrap_or_default(),
                 };
                 // Pass AppState to render_minijinja
                 render_minijinja(&app_state, "machine_list.html", context)
@@ -639,6 +749,10 @@ pub async fn machine_list(
                     is_admin,
                     workflow_infos: HashMap::new(),
                     current_path,
+                    patch_statuses: HashMap::new(),
+                    patch_filter: String::new(),
+                    boot_environments: HashMap::new(),
+                    environment_filter: String::new(),
                 };
                 // Pass AppState to render_minijinja
                 render_minijinja(&app_state, "machine_list.html", context)
@@ -657,7 +771,7 @@ pub async fn machine_details(
     // Get theme preference from cookie
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
     
     // Check if login is required site-wide
     let require_login = app_state.settings.lock().await.require_login;
@@ -665,11 +779,11 @@ pub async fn machine_details(
     // If require_login is enabled and user is not authenticated,
     // redirect to login page
     if require_login && !is_authenticated {
-        return Redirect::to("/login").into_response();
+        return Redirect::to(&crate::base_path::url("/login")).into_response();
     }
     
     // Check if we are in demo mode
-    let is_demo_mode = std::env::var("DRAGONFLY_DEMO_MODE").is_ok();
+    let is_demo_mode = app_state.config.server.demo_mode;
     
     // Parse UUID from string
     match uuid::Uuid::parse_str(&id) {
@@ -743,6 +857,7 @@ pub async fn machine_details(
                         workflow_info, // Pass original option too
                         current_path,
                         ip_address_type, // Pass the determined type
+                        custom_os_choices: Vec::new(),
                     };
                     // Use render_minijinja
                     return render_minijinja(&app_state, "machine_details.html", context);
@@ -818,6 +933,12 @@ pub async fn machine_details(
                         "Static/IPAM".to_string()
                     };
 
+                    let custom_os_choices = crate::custom_images::custom_catalog().await
+                        .unwrap_or_else(|e| {
+                            error!("Failed to load custom images for machine {}: {}", machine.id, e);
+                            Vec::new()
+                        });
+
                     // Create the Askama template context
                     let context = MachineDetailsTemplate {
                         machine_json, // Pass JSON string
@@ -830,6 +951,7 @@ pub async fn machine_details(
                         workflow_info, // Pass original option too
                         current_path,
                         ip_address_type, // Pass the determined type
+                        custom_os_choices,
                     };
                     // Use render_minijinja
                     return render_minijinja(&app_state, "machine_details.html", context);
@@ -925,7 +1047,7 @@ pub async fn settings_page(
     
     // Check if user is authenticated
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
     
     // Get current settings
     let settings_lock = app_state.settings.lock().await;
@@ -936,7 +1058,7 @@ pub async fn settings_page(
     // If require_login is enabled and user is not authenticated,
     // redirect to login page
     if require_login && !is_authenticated {
-        return Redirect::to("/login").into_response();
+        return Redirect::to(&crate::base_path::url("/login")).into_response();
     }
     
     let show_admin_settings = is_authenticated;
@@ -1022,7 +1144,7 @@ pub async fn update_settings(
 ) -> Response {
     let is_authenticated = auth_session.user.is_some();
     let theme = form.theme.clone();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
 
     // Only require admin authentication for admin settings
     // If trying to change admin settings but not authenticated, redirect to login
@@ -1040,7 +1162,7 @@ pub async fn update_settings(
         form.proxmox_username.is_some() ||
         form.proxmox_password.is_some() ||
         form.proxmox_port.is_some()) && !is_authenticated {
-        return Redirect::to("/login").into_response();
+        return Redirect::to(&crate::base_path::url("/login")).into_response();
     }
 
     // Only update admin settings if user is authenticated
@@ -1077,6 +1199,10 @@ pub async fn update_settings(
             proxmox_password: current_settings.proxmox_password.clone(),
             proxmox_port: current_settings.proxmox_port,
             proxmox_skip_tls_verify: current_settings.proxmox_skip_tls_verify,
+            retention: current_settings.retention.clone(),
+            system: current_settings.system.clone(),
+            branding: current_settings.branding.clone(),
+            server: current_settings.server.clone(),
         };
 
         info!("Saving settings: require_login={}, default_os={:?}, setup_completed={:?}", 
@@ -1207,7 +1333,7 @@ pub async fn update_settings(
                             }
                             // Force logout after password change
                             let _ = auth_session.logout().await;
-                            return Redirect::to("/login?message=password_updated").into_response();
+                            return Redirect::to(&crate::base_path::url("/login?message=password_updated")).into_response();
                         }
                     }
                     Err(e) => {
@@ -1323,7 +1449,7 @@ pub async fn update_settings(
     // Set cookie header and redirect back to settings page
     (
         [(header::SET_COOKIE, cookie.to_string())],
-        Redirect::to("/settings")
+        Redirect::to(&crate::base_path::url("/settings"))
     ).into_response()
 }
 
@@ -1337,7 +1463,12 @@ pub async fn welcome_page(
     // Get theme preference from cookie
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
+    
+    // The mode is picked once the setup wizard is done
+    if app_state.is_installed && !app_state.is_demo_mode && !app_state.settings.lock().await.setup_completed {
+        return Redirect::to(&crate::base_path::url("/setup")).into_response();
+    }
     
     // Replace Askama render with placeholder
     let context = WelcomeTemplate {
@@ -1350,6 +1481,26 @@ pub async fn welcome_page(
     render_minijinja(&app_state, "welcome.html", context)
 }
 
+// First-run setup wizard; it saves through /api/setup and then moves on to mode selection
+pub async fn setup_page(
+    State(app_state): State<crate::AppState>,
+    headers: HeaderMap,
+    auth_session: AuthSession,
+    uri: OriginalUri,
+) -> Response {
+    if app_state.settings.lock().await.setup_completed {
+        return Redirect::to(&crate::base_path::url("/")).into_response();
+    }
+    
+    let context = WelcomeTemplate {
+        theme: get_theme_from_cookie(&headers),
+        is_authenticated: auth_session.user.is_some(),
+        hide_footer: true,
+        current_path: crate::base_path::strip(uri.path()).to_string(),
+    };
+    render_minijinja(&app_state, "setup.html", context)
+}
+
 // Handlers for the different setup modes
 pub async fn setup_simple(
     State(app_state): State<crate::AppState>,
@@ -1360,7 +1511,7 @@ pub async fn setup_simple(
     // Get theme preference from cookie
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
     
     // Save mode to database immediately
     if let Err(e) = mode::save_mode(mode::DeploymentMode::Simple, false).await {
@@ -1412,7 +1563,7 @@ pub async fn setup_simple(
     
     // Immediately redirect to home page
     info!("Saved Simple mode and initiated background configuration, redirecting to home");
-    Redirect::to("/").into_response()
+    Redirect::to(&crate::base_path::url("/")).into_response()
 }
 
 pub async fn setup_flight(
@@ -1424,7 +1575,7 @@ pub async fn setup_flight(
     // Get theme preference from cookie
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
     
     // Save mode to database immediately
     if let Err(e) = mode::save_mode(mode::DeploymentMode::Flight, false).await {
@@ -1476,7 +1627,7 @@ pub async fn setup_flight(
     
     // Immediately redirect to home page
     info!("Saved Flight mode and initiated background configuration, redirecting to home");
-    Redirect::to("/").into_response()
+    Redirect::to(&crate::base_path::url("/")).into_response()
 }
 
 pub async fn setup_swarm(
@@ -1488,7 +1639,7 @@ pub async fn setup_swarm(
     // Get theme preference from cookie
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
     
     // Save mode to database immediately
     if let Err(e) = mode::save_mode(mode::DeploymentMode::Swarm, false).await {
@@ -1540,7 +1691,7 @@ pub async fn setup_swarm(
     
     // Immediately redirect to home page
     info!("Saved Swarm mode and initiated background configuration, redirecting to home");
-    Redirect::to("/").into_response()
+    Redirect::to(&crate::base_path::url("/")).into_response()
 }
 
 // Environment setup for MiniJinja
@@ -1604,6 +1755,13 @@ pub fn setup_minijinja_environment(env: &mut minijinja::Environment) -> Result<(
     // Set up more configuration as needed
     env.add_global("now", minijinja::Value::from(chrono::Utc::now().to_rfc3339()));
     
+    // URL prefix when served behind a reverse proxy; templates put it in front of every local URL
+    env.add_global("base_path", crate::base_path::get());
+    
+    // Organization branding for the header; a function so changes show without a restart
+    env.add_function("branding", || minijinja::Value::from_serialize(crate::branding::current()));
+    env.add_function("stack_report", || minijinja::Value::from_serialize(crate::stack::last_report()));
+    
     // Add custom filter for robust JSON serialization
     env.add_filter("to_json", |value: minijinja::Value| -> Result<String, minijinja::Error> {
         match serde_json::to_string(&value) {
@@ -1678,15 +1836,15 @@ pub async fn compute_page(
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
     let is_admin = is_authenticated;
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
 
     let require_login = app_state.settings.lock().await.require_login;
 
     // Login check
     if require_login && !is_authenticated {
         info!("Login required for /compute, redirecting to /login");
-        let mut response = Redirect::to("/login").into_response();
-        response.headers_mut().insert("HX-Redirect", "/login".parse().unwrap());
+        let mut response = Redirect::to(&crate::base_path::url("/login")).into_response();
+        response.headers_mut().insert("HX-Redirect", crate::base_path::url("/login").parse().unwrap());
         return response;
     }
 
@@ -1777,13 +1935,13 @@ pub async fn tags_page(
     // Get theme preference from cookie
     let theme = get_theme_from_cookie(&headers);
     let is_authenticated = auth_session.user.is_some();
-    let current_path = uri.path().to_string();
+    let current_path = crate::base_path::strip(uri.path()).to_string();
     
     // Check if user is authenticated if login is required
     let require_login = app_state.settings.lock().await.require_login;
     if require_login && !is_authenticated && app_state.is_installed {
         if let Some(_) = mode::get_current_mode().await.unwrap_or(None) {
-            return Redirect::to("/login").into_response();
+            return Redirect::to(&crate::base_path::url("/login")).into_response();
         }
     }
     
@@ -1808,3 +1966,547 @@ pub async fn tags_page(
     // Render the tags page template
     render_minijinja(&app_state, "tags.html", context)
 }
+
+#[derive(serde::Deserialize)]
+pub struct LogsPageQuery {
+    pub level: Option<String>,
+}
+
+// In-memory server logs, for when stdout/journald isn't reachable
+pub async fn logs_page(
+    State(app_state): State<crate::AppState>,
+    Query(query): Query<LogsPageQuery>,
+    headers: HeaderMap,
+    auth_session: AuthSession,
+    uri: OriginalUri,
+) -> Response {
+    if auth_session.user.is_none() {
+        return Redirect::to(&crate::base_path::url("/login")).into_response();
+    }
+
+    let level = query.level
+        .as_deref()
+        .and_then(crate::logs::parse_level)
+        .unwrap_or(tracing::Level::INFO);
+
+    let context = serde_json::json!({
+        "theme": get_theme_from_cookie(&headers),
+        "is_authenticated": true,
+        "current_path": crate::base_path::strip(uri.path()).to_string(),
+        "level": level.to_string().to_lowercase(),
+        "levels": ["error", "warn", "info", "debug", "trace"],
+        "records": crate::logs::recent(&level, None, None),
+    });
+
+    render_minijinja(&app_state, "logs.html", context)
+}
+
+// Anyone with the link can view status pages when this is set; otherwise a token or admin session is needed
+const PUBLIC_STATUS_ENV_VAR: &str = "DRAGONFLY_PUBLIC_STATUS_PAGES";
+
+// How often the status page reloads itself while something is still happening
+const STATUS_PAGE_REFRESH_SECS: u32 = 10;
+
+#[derive(serde::Deserialize)]
+pub struct StatusPageQuery {
+    pub token: Option<String>,
+    // Expiring signed link from /api/machines/{id}/share-links
+    pub share: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PublicStatusTemplate {
+    pub theme: String,
+    pub display_name: String,
+    pub memorable_name: String,
+    // One of: waiting, queued, installing, done, failed
+    pub phase: String,
+    pub headline: String,
+    pub detail: Option<String>,
+    pub progress: u8,
+    pub queue_position: Option<usize>,
+    pub queue_length: usize,
+    pub active_installs: usize,
+    pub estimated_completion: Option<String>,
+    pub refresh_seconds: Option<u32>,
+    pub updated_at: String,
+    // Set when the page was opened through an expiring share link
+    pub share_expires_at: Option<String>,
+}
+
+fn public_status_pages_enabled() -> bool {
+    crate::config::var(PUBLIC_STATUS_ENV_VAR)
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+// Friendly, non-technical summary of where a machine is in the install pipeline.
+// Machines with an OS assigned but not yet booted are queued in the order they were assigned.
+async fn build_public_status(machine: &Machine, all_machines: &[Machine], theme: String) -> PublicStatusTemplate {
+    let mut queued: Vec<&Machine> = all_machines.iter()
+        .filter(|m| m.status == MachineStatus::AwaitingAssignment && m.os_choice.is_some())
+        .collect();
+    queued.sort_by_key(|m| m.updated_at);
+    let queue_position = queued.iter().position(|m| m.id == machine.id).map(|p| p + 1);
+    let active_installs = all_machines.iter()
+        .filter(|m| m.status == MachineStatus::InstallingOS)
+        .count();
+
+    let os_name = machine.os_choice.as_deref().map(format_os_name);
+    let mut progress = machine.installation_progress;
+    let mut estimated_completion = None;
+    let mut detail = machine.installation_step.clone();
+
+    let (phase, headline) = match &machine.status {
+        MachineStatus::InstallingOS => {
+            if let Ok(Some(info)) = crate::tinkerbell::get_workflow_info(machine).await {
+                progress = info.progress;
+                estimated_completion = info.estimated_completion;
+                detail = info.current_action.or(detail);
+            }
+            ("installing", format!("Installing {}", os_name.as_deref().unwrap_or("operating system")))
+        },
+        MachineStatus::AwaitingAssignment if queue_position.is_some() => {
+            detail = Some("Waiting for the machine to network boot".to_string());
+            ("queued", format!("Queued for {}", os_name.as_deref().unwrap_or("installation")))
+        },
+        MachineStatus::Ready | MachineStatus::ExistingOS => {
+            progress = 100;
+            detail = machine.os_installed.as_deref().map(format_os_name);
+            ("done", "Installation complete".to_string())
+        },
+        MachineStatus::Error(_) => {
+            // The error text is for admins; don't show internals on a public page
+            detail = Some("An administrator has been notified".to_string());
+            ("failed", "Installation needs attention".to_string())
+        },
+        MachineStatus::BootedLive => {
+            progress = 100;
+            ("done", "Running live image".to_string())
+        },
+        MachineStatus::Offline => ("waiting", "Machine is offline".to_string()),
+        MachineStatus::PoweredOff => ("waiting", "Machine is powered off to save energy".to_string()),
+        MachineStatus::AwaitingAssignment => ("waiting", "Waiting for an operating system to be assigned".to_string()),
+    };
+
+    let refresh_seconds = match phase {
+        "done" | "failed" => None,
+        _ => Some(STATUS_PAGE_REFRESH_SECS),
+    };
+
+    PublicStatusTemplate {
+        theme,
+        display_name: machine.hostname.clone()
+            .or_else(|| machine.memorable_name.clone())
+            .unwrap_or_else(|| machine.mac_address.clone()),
+        memorable_name: machine.memorable_name.clone().unwrap_or_default(),
+        phase: phase.to_string(),
+        headline,
+        detail,
+        progress,
+        queue_position,
+        queue_length: queued.len(),
+        active_installs,
+        estimated_completion,
+        refresh_seconds,
+        updated_at: machine.updated_at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        share_expires_at: None,
+    }
+}
+
+// Per-machine install progress for the person standing at the rack
+pub async fn public_status_page(
+    State(app_state): State<crate::AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    Query(query): Query<StatusPageQuery>,
+    headers: HeaderMap,
+    auth_session: AuthSession,
+) -> Response {
+    let theme = get_theme_from_cookie(&headers);
+
+    let machine = match db::get_machine_by_memorable_name(&name).await {
+        Ok(Some(machine)) => machine,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No machine with that name").into_response(),
+        Err(e) => {
+            error!("Failed to look up machine {} for status page: {}", name, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load machine status").into_response();
+        }
+    };
+
+    // A share link works even when status pages aren't public, until it expires or is revoked
+    let mut share_expires_at = None;
+    if let (None, Some(share)) = (&auth_session.user, query.share.as_deref()) {
+        match crate::share_links::verify(share, &machine.id).await {
+            Ok(Ok(claims)) => share_expires_at = Some(claims.expires_at()),
+            Ok(Err(e)) => return (StatusCode::FORBIDDEN, e.to_string()).into_response(),
+            Err(e) => {
+                error!("Failed to verify share link for machine {}: {}", machine.id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify share link").into_response();
+            }
+        }
+    }
+
+    // Admins always get in; everyone else needs the page to be public or a token for this machine
+    if auth_session.user.is_none() && share_expires_at.is_none() && !public_status_pages_enabled() {
+        let token = query.token.as_deref().unwrap_or("");
+        match crate::access_tokens::verify(token, crate::access_tokens::TokenScope::MachineStatus, Some(&machine.id)).await {
+            Ok(true) => {},
+            Ok(false) => return (StatusCode::FORBIDDEN, "This status link is invalid or has been revoked").into_response(),
+            Err(e) => {
+                error!("Failed to verify status token for machine {}: {}", machine.id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify status link").into_response();
+            }
+        }
+    }
+
+    let all_machines = db::get_all_machines().await.unwrap_or_else(|e| {
+        warn!("Failed to load machines for install queue: {}", e);
+        vec![machine.clone()]
+    });
+
+    let mut context = build_public_status(&machine, &all_machines, theme).await;
+    context.share_expires_at = share_expires_at.map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string());
+    render_minijinja(&app_state, "public_status.html", context)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ActionApprovalQuery {
+    pub approver: Option<String>,
+    pub sig: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ActionApprovalForm {
+    pub approver: Option<String>,
+    pub sig: Option<String>,
+    pub decision: String,
+}
+
+#[derive(Serialize)]
+pub struct ActionApprovalTemplate {
+    pub theme: String,
+    pub summary: String,
+    pub requested_by: String,
+    pub requested_at: String,
+    pub expires_at: String,
+    pub state: String,
+    pub decided_by: Option<String>,
+    // The signed-in admin deciding
+    pub decider: String,
+    // Carried through the form when the page was opened from an emailed link
+    pub approver: Option<String>,
+    pub signature: Option<String>,
+    pub message: Option<String>,
+    pub error: bool,
+}
+
+// Who is deciding on an approval: always the signed-in admin, even from an emailed link, so the
+// requester can't approve their own request by following a link sent to an address they read
+async fn action_approval_decider(
+    id: &Uuid,
+    approver: Option<&str>,
+    sig: Option<&str>,
+    auth_session: &AuthSession,
+) -> Result<(String, crate::action_approvals::DecidedVia), Response> {
+    use crate::action_approvals::DecidedVia;
+    let Some(user) = &auth_session.user else {
+        return Err(Redirect::to(&crate::base_path::url("/login")).into_response());
+    };
+    match (approver, sig) {
+        (Some(approver), Some(sig)) => match crate::action_approvals::link_approver(id, approver, sig).await {
+            Ok(Ok(_)) => Ok((user.username.clone(), DecidedVia::Email)),
+            Ok(Err(e)) => Err((StatusCode::FORBIDDEN, e.to_string()).into_response()),
+            Err(e) => {
+                error!("Failed to verify approval link for {}: {}", id, e);
+                Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify approval link").into_response())
+            }
+        },
+        _ => Ok((user.username.clone(), DecidedVia::Ui)),
+    }
+}
+
+fn action_approval_template(
+    approval: &crate::action_approvals::ActionApproval,
+    theme: String,
+    decider: String,
+    link: (Option<String>, Option<String>),
+) -> ActionApprovalTemplate {
+    let state = serde_json::to_value(approval.state_at(Utc::now())).ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+    ActionApprovalTemplate {
+        theme,
+        summary: approval.summary.clone(),
+        requested_by: approval.requested_by.clone(),
+        requested_at: approval.requested_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        expires_at: approval.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        state,
+        decided_by: approval.decided_by.clone(),
+        decider,
+        approver: link.0,
+        signature: link.1,
+        message: None,
+        error: false,
+    }
+}
+
+// Confirmation page for a destructive action waiting on a second person (linked from approval mail)
+pub async fn action_approval_page(
+    State(app_state): State<crate::AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Query(query): Query<ActionApprovalQuery>,
+    headers: HeaderMap,
+    auth_session: AuthSession,
+) -> Response {
+    let (decider, _) = match action_approval_decider(&id, query.approver.as_deref(), query.sig.as_deref(), &auth_session).await {
+        Ok(decider) => decider,
+        Err(response) => return response,
+    };
+    let approval = match db::get_action_approval(&id).await {
+        Ok(Some(approval)) => approval,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No such approval request").into_response(),
+        Err(e) => {
+            error!("Failed to load action approval {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load approval request").into_response();
+        }
+    };
+
+    let context = action_approval_template(&approval, get_theme_from_cookie(&headers), decider, (query.approver, query.sig));
+    render_minijinja(&app_state, "action_approval.html", context)
+}
+
+pub async fn action_approval_decision(
+    State(app_state): State<crate::AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    headers: HeaderMap,
+    auth_session: AuthSession,
+    Form(form): Form<ActionApprovalForm>,
+) -> Response {
+    let (decider, via) = match action_approval_decider(&id, form.approver.as_deref(), form.sig.as_deref(), &auth_session).await {
+        Ok(decider) => decider,
+        Err(response) => return response,
+    };
+    let approve = match form.decision.as_str() {
+        "approve" => true,
+        "reject" => false,
+        _ => return (StatusCode::BAD_REQUEST, "Decision must be approve or reject").into_response(),
+    };
+
+    let (message, error) = match crate::api::decide_action_approval(&app_state, &id, approve, &decider, via).await {
+        Ok(approval) if !approve => (format!("Rejected. \"{}\" will not run.", approval.summary), false),
+        Ok(approval) => match approval.outcome.as_ref().and_then(|o| o["failed"].as_u64()).filter(|failed| *failed > 0) {
+            Some(failed) => (format!("Approved, but {} machine(s) failed; see the approval record for details.", failed), true),
+            None => ("Approved. The action has been carried out.".to_string(), false),
+        },
+        Err(response) => (crate::api::response_message(response).await, true),
+    };
+
+    let approval = match db::get_action_approval(&id).await {
+        Ok(Some(approval)) => approval,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No such approval request").into_response(),
+        Err(e) => {
+            error!("Failed to load action approval {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load approval request").into_response();
+        }
+    };
+    let mut context = action_approval_template(&approval, get_theme_from_cookie(&headers), decider, (form.approver, form.sig));
+    context.message = Some(message);
+    context.error = error;
+    render_minijinja(&app_state, "action_approval.html", context)
+}
+
+// Number of recent failures listed on the wallboard
+const WALLBOARD_RECENT_FAILURES: usize = 8;
+
+// Tile order on the wallboard, matching the keys from count_machines_by_status
+const WALLBOARD_STATES: [&str; 7] = [
+    "Installing OS",
+    "Awaiting OS Assignment",
+    "Ready",
+    "Existing OS",
+    "Offline",
+    "Powered Off",
+    "Error",
+];
+
+#[derive(serde::Deserialize)]
+pub struct WallboardQuery {
+    pub token: Option<String>,
+    // Comma-separated tags, e.g. a fleet group, to scope the board to one team's machines
+    #[serde(alias = "tag")]
+    pub group: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WallboardStateTile {
+    pub label: String,
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+pub struct WallboardInstall {
+    pub id: Uuid,
+    pub name: String,
+    pub os: Option<String>,
+    pub progress: u8,
+    pub current_action: Option<String>,
+    pub estimated_completion: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WallboardFailure {
+    pub name: String,
+    pub message: String,
+    pub failed_at: String,
+}
+
+#[derive(Serialize)]
+pub struct WallboardTemplate {
+    pub total_machines: usize,
+    pub states: Vec<WallboardStateTile>,
+    pub active_installs: Vec<WallboardInstall>,
+    pub recent_failures: Vec<WallboardFailure>,
+    pub generated_at: String,
+    // Passed back to /wallboard/tiles and /api/events when refreshing
+    pub token: Option<String>,
+    pub group: Option<String>,
+}
+
+fn machine_display_name(machine: &Machine) -> String {
+    machine.hostname.clone()
+        .or_else(|| machine.memorable_name.clone())
+        .unwrap_or_else(|| machine.mac_address.clone())
+}
+
+// Admin sessions always get in; displays use a wallboard token
+async fn wallboard_authorized(auth_session: &AuthSession, token: Option<&str>) -> Result<bool, anyhow::Error> {
+    if auth_session.user.is_some() {
+        return Ok(true);
+    }
+    match token {
+        Some(token) => crate::access_tokens::verify(token, crate::access_tokens::TokenScope::Wallboard, None).await,
+        None => Ok(false),
+    }
+}
+
+// Machines carrying any of the tags, each once
+async fn machines_with_tags(tags: &[String]) -> Result<Vec<Machine>, anyhow::Error> {
+    let mut machines: Vec<Machine> = Vec::new();
+    for tag in tags {
+        for machine in db::get_machines_by_tag(tag).await? {
+            if !machines.iter().any(|m| m.id == machine.id) {
+                machines.push(machine);
+            }
+        }
+    }
+    Ok(machines)
+}
+
+async fn build_wallboard(app_state: &crate::AppState, query: WallboardQuery) -> WallboardTemplate {
+    let tags = query.group.as_deref().map(crate::event_scope::parse_tags).unwrap_or_default();
+    let group = (!tags.is_empty()).then(|| tags.join(","));
+    // Demo machines aren't tagged, so a scoped board in demo mode shows them all
+    let machines = if app_state.is_demo_mode {
+        Ok(generate_demo_machines())
+    } else if tags.is_empty() {
+        db::get_all_machines().await
+    } else {
+        machines_with_tags(&tags).await
+    };
+    let machines = machines.unwrap_or_else(|e| {
+        error!("Failed to fetch machines for wallboard: {}", e);
+        vec![]
+    });
+
+    let counts = count_machines_by_status(&machines);
+    let states = WALLBOARD_STATES.iter()
+        .map(|label| WallboardStateTile {
+            label: label.to_string(),
+            count: counts.get(*label).copied().unwrap_or(0),
+        })
+        .collect();
+
+    let mut active_installs = Vec::new();
+    for machine in machines.iter().filter(|m| m.status == MachineStatus::InstallingOS) {
+        let workflow_info = if app_state.is_demo_mode {
+            None
+        } else {
+            crate::tinkerbell::get_workflow_info(machine).await.ok().flatten()
+        };
+        active_installs.push(WallboardInstall {
+            id: machine.id,
+            name: machine_display_name(machine),
+            os: machine.os_choice.as_deref().map(format_os_name),
+            progress: workflow_info.as_ref().map_or(machine.installation_progress, |w| w.progress),
+            current_action: workflow_info.as_ref()
+                .and_then(|w| w.current_action.clone())
+                .or_else(|| machine.installation_step.clone()),
+            estimated_completion: workflow_info.and_then(|w| w.estimated_completion),
+        });
+    }
+    active_installs.sort_by(|a, b| b.progress.cmp(&a.progress));
+
+    let mut failed: Vec<&Machine> = machines.iter()
+        .filter(|m| matches!(m.status, MachineStatus::Error(_)))
+        .collect();
+    failed.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    let recent_failures = failed.into_iter()
+        .take(WALLBOARD_RECENT_FAILURES)
+        .map(|m| WallboardFailure {
+            name: machine_display_name(m),
+            message: match &m.status {
+                MachineStatus::Error(msg) => msg.clone(),
+                _ => String::new(),
+            },
+            failed_at: format_datetime(&m.updated_at),
+        })
+        .collect();
+
+    WallboardTemplate {
+        total_machines: machines.len(),
+        states,
+        active_installs,
+        recent_failures,
+        generated_at: format_datetime(&Utc::now()),
+        token: query.token,
+        group,
+    }
+}
+
+// Read-only fleet overview for a NOC display; ?group=<tag> scopes it to one group's machines
+pub async fn wallboard_page(
+    State(app_state): State<crate::AppState>,
+    Query(query): Query<WallboardQuery>,
+    auth_session: AuthSession,
+) -> Response {
+    match wallboard_authorized(&auth_session, query.token.as_deref()).await {
+        Ok(true) => {},
+        Ok(false) => return Redirect::to(&crate::base_path::url("/login")).into_response(),
+        Err(e) => {
+            error!("Failed to verify wallboard token: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify wallboard token").into_response();
+        }
+    }
+
+    let context = build_wallboard(&app_state, query).await;
+    render_minijinja(&app_state, "wallboard.html", context)
+}
+
+// Tile fragment the wallboard re-fetches when an SSE event arrives
+pub async fn wallboard_tiles(
+    State(app_state): State<crate::AppState>,
+    Query(query): Query<WallboardQuery>,
+    auth_session: AuthSession,
+) -> Response {
+    match wallboard_authorized(&auth_session, query.token.as_deref()).await {
+        Ok(true) => {},
+        Ok(false) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            error!("Failed to verify wallboard token: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let context = build_wallboard(&app_state, query).await;
+    render_minijinja(&app_state, "partials/wallboard_tiles.html", context)
+}
@@ -0,0 +1,213 @@
+// Usage reporting for chargeback and capacity planning: machine counts by state, installs
+// per month, artifact bytes served per month and connected agents, as JSON or CSV from
+// /api/reports/usage.
+//
+// Finished installs are logged to their own table, which retention leaves alone. Artifact
+// bytes are counted in memory by the bandwidth meter and added to the current month's
+// total once a minute and at shutdown.
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Utc};
+use dragonfly_common::models::{Machine, MachineStatus};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{info, warn};
+
+const FLUSH_INTERVAL_SECS: u64 = 60;
+
+pub const DEFAULT_MONTHS: u32 = 12;
+pub const MAX_MONTHS: u32 = 120;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MachineCounts {
+    pub total: usize,
+    pub by_state: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MonthlyUsage {
+    /// "2026-09"
+    pub month: String,
+    pub installs_succeeded: u64,
+    pub installs_failed: u64,
+    pub artifact_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub generated_at: DateTime<Utc>,
+    pub machines: MachineCounts,
+    /// Agents with their channel open right now
+    pub active_agents: usize,
+    /// Oldest month first, including months with no activity
+    pub months: Vec<MonthlyUsage>,
+}
+
+pub fn state_key(status: &MachineStatus) -> &'static str {
+    match status {
+        MachineStatus::ExistingOS => "existing_os",
+        MachineStatus::AwaitingAssignment => "awaiting_assignment",
+        MachineStatus::InstallingOS => "installing_os",
+        MachineStatus::Ready => "ready",
+        MachineStatus::BootedLive => "booted_live",
+        MachineStatus::Offline => "offline",
+        MachineStatus::PoweredOff => "powered_off",
+        MachineStatus::Error(_) => "error",
+    }
+}
+
+pub fn count_machines(machines: &[Machine]) -> MachineCounts {
+    let mut by_state = BTreeMap::new();
+    for machine in machines {
+        *by_state.entry(state_key(&machine.status).to_string()).or_insert(0) += 1;
+    }
+    MachineCounts { total: machines.len(), by_state }
+}
+
+pub fn month_key(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m").to_string()
+}
+
+/// The last `count` months up to and including the one `now` falls in, oldest first.
+pub fn months_ending(now: DateTime<Utc>, count: u32) -> Vec<String> {
+    let current = now.year() * 12 + now.month0() as i32;
+    (0..count as i32).rev()
+        .map(|back| current - back)
+        .map(|m| format!("{:04}-{:02}", m.div_euclid(12), m.rem_euclid(12) + 1))
+        .collect()
+}
+
+/// One row per month, with zeroes where nothing happened.
+pub fn monthly_usage(months: &[String], installs: &[(String, i64, i64)], bytes: &[(String, i64)]) -> Vec<MonthlyUsage> {
+    months.iter()
+        .map(|month| {
+            let (succeeded, failed) = installs.iter()
+                .find(|(m, _, _)| m == month)
+                .map_or((0, 0), |(_, s, f)| (*s, *f));
+            let artifact_bytes = bytes.iter().find(|(m, _)| m == month).map_or(0, |(_, b)| *b);
+            MonthlyUsage {
+                month: month.clone(),
+                installs_succeeded: succeeded.max(0) as u64,
+                installs_failed: failed.max(0) as u64,
+                artifact_bytes: artifact_bytes.max(0) as u64,
+            }
+        })
+        .collect()
+}
+
+pub async fn report(months: u32) -> Result<UsageReport> {
+    // Count what's been served since the last flush too
+    flush_artifact_bytes().await;
+
+    let now = Utc::now();
+    let month_keys = months_ending(now, months.clamp(1, MAX_MONTHS));
+    let since = month_keys.first().cloned().unwrap_or_else(|| month_key(now));
+    let machines = crate::db::get_all_machines().await?;
+    let installs = crate::db::installs_by_month(&since).await?;
+    let bytes = crate::db::artifact_bytes_by_month(&since).await?;
+
+    Ok(UsageReport {
+        generated_at: now,
+        machines: count_machines(&machines),
+        active_agents: crate::terminal::connected_agents(),
+        months: monthly_usage(&month_keys, &installs, &bytes),
+    })
+}
+
+impl UsageReport {
+    /// Long-format CSV (metric, period, value), which pivots easily in a spreadsheet.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("metric,period,value\n");
+        out.push_str(&format!("machines_total,current,{}\n", self.machines.total));
+        for (state, count) in &self.machines.by_state {
+            out.push_str(&format!("machines_{},current,{}\n", state, count));
+        }
+        out.push_str(&format!("active_agents,current,{}\n", self.active_agents));
+        for month in &self.months {
+            out.push_str(&format!("installs_succeeded,{},{}\n", month.month, month.installs_succeeded));
+            out.push_str(&format!("installs_failed,{},{}\n", month.month, month.installs_failed));
+            out.push_str(&format!("artifact_bytes,{},{}\n", month.month, month.artifact_bytes));
+        }
+        out
+    }
+}
+
+/// Log a finished install for the monthly counts.
+pub async fn record_install(machine: &Machine, succeeded: bool) {
+    if let Err(e) = crate::db::record_install(&machine.id, machine.os_choice.as_deref(), succeeded, Utc::now()).await {
+        warn!("Failed to record install for usage reporting on machine {}: {}", machine.id, e);
+    }
+}
+
+// Bandwidth meter total already added to the monthly counts
+static FLUSHED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+async fn flush_artifact_bytes() {
+    let total = crate::bandwidth::status().bytes_total;
+    let previous = FLUSHED_BYTES.swap(total, Ordering::SeqCst);
+    let delta = total.saturating_sub(previous);
+    if delta == 0 {
+        return;
+    }
+    if let Err(e) = crate::db::add_artifact_bytes_served(&month_key(Utc::now()), delta).await {
+        warn!("Failed to record {} artifact bytes served: {}", delta, e);
+        // Try again on the next flush
+        FLUSHED_BYTES.fetch_sub(delta, Ordering::SeqCst);
+    }
+}
+
+pub async fn start_usage_task(mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        let interval = std::time::Duration::from_secs(FLUSH_INTERVAL_SECS);
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {
+                    flush_artifact_bytes().await;
+                }
+                _ = shutdown_rx.changed() => {
+                    flush_artifact_bytes().await;
+                    info!("Shutdown signal received, stopping usage task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_months_ending() {
+        let now = Utc.with_ymd_and_hms(2026, 2, 14, 12, 0, 0).unwrap();
+        assert_eq!(months_ending(now, 4), vec!["2025-11", "2025-12", "2026-01", "2026-02"]);
+        assert_eq!(months_ending(now, 1), vec!["2026-02"]);
+        assert_eq!(month_key(now), "2026-02");
+    }
+
+    #[test]
+    fn test_monthly_usage_and_csv() {
+        let months = vec!["2026-01".to_string(), "2026-02".to_string()];
+        let installs = vec![("2026-02".to_string(), 5, 1)];
+        let bytes = vec![("2026-01".to_string(), 1024)];
+        let usage = monthly_usage(&months, &installs, &bytes);
+        assert_eq!(usage[0], MonthlyUsage { month: "2026-01".to_string(), installs_succeeded: 0, installs_failed: 0, artifact_bytes: 1024 });
+        assert_eq!(usage[1].installs_succeeded, 5);
+        assert_eq!(usage[1].installs_failed, 1);
+
+        let report = UsageReport {
+            generated_at: Utc::now(),
+            machines: MachineCounts { total: 3, by_state: [("ready".to_string(), 2), ("error".to_string(), 1)].into_iter().collect() },
+            active_agents: 2,
+            months: usage,
+        };
+        let csv = report.to_csv();
+        assert!(csv.starts_with("metric,period,value\nmachines_total,current,3\nmachines_error,current,1\nmachines_ready,current,2\nactive_agents,current,2\n"));
+        assert!(csv.contains("artifact_bytes,2026-01,1024\n"));
+        assert!(csv.ends_with("artifact_bytes,2026-02,0\n"));
+    }
+}
@@ -0,0 +1,113 @@
+// VLAN-tagged provisioning, for machines whose switch port is a trunk.
+//
+// A machine still reaches /{mac} over the port's native VLAN; from there iPXE moves onto
+// the machine's provisioning VLAN, HookOS is told the same ID, and the installed OS gets
+// a matching netplan VLAN interface. A VLAN can be set on a machine directly or on a tag
+// (fleet groups tag their members), with the machine's own setting taking precedence.
+
+use anyhow::{bail, Result};
+use dragonfly_common::models::Machine;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VlanSetting {
+    pub vlan_id: u16,
+    /// Tag the VLAN was inherited from; None when set on the machine itself
+    pub source_tag: Option<String>,
+}
+
+/// VLAN as submitted by an admin; null clears it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VlanInput {
+    pub vlan_id: Option<u16>,
+}
+
+pub fn validate(vlan_id: u16) -> Result<()> {
+    // 0 means untagged and 4095 is reserved
+    if !(1..=4094).contains(&vlan_id) {
+        bail!("VLAN ID {} is out of range (1-4094)", vlan_id);
+    }
+    Ok(())
+}
+
+/// The VLAN a machine provisions on: its own, else the first of its tags (by name) that has one.
+pub fn resolve(machine_vlan: Option<u16>, tag_vlans: &[(String, u16)], machine_tags: &[String]) -> Option<VlanSetting> {
+    if let Some(vlan_id) = machine_vlan {
+        return Some(VlanSetting { vlan_id, source_tag: None });
+    }
+    let mut matching: Vec<&(String, u16)> = tag_vlans.iter()
+        .filter(|(tag, _)| machine_tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+        .collect();
+    matching.sort();
+    matching.first().map(|(tag, vlan_id)| VlanSetting { vlan_id: *vlan_id, source_tag: Some(tag.clone()) })
+}
+
+/// A machine's effective VLAN, if any.
+pub async fn for_machine(machine: &Machine) -> Result<Option<VlanSetting>> {
+    let machine_vlan = crate::db::get_machine_vlan(&machine.id).await?;
+    if machine_vlan.is_some() {
+        return Ok(resolve(machine_vlan, &[], &[]));
+    }
+    let tag_vlans = crate::db::list_tag_vlans().await?;
+    if tag_vlans.is_empty() {
+        return Ok(None);
+    }
+    let tags = crate::db::get_machine_tags(&machine.id).await?;
+    Ok(resolve(None, &tag_vlans, &tags))
+}
+
+/// iPXE lines that move the boot onto a VLAN; goes right after `#!ipxe`. Everything the
+/// script chains to afterwards, and the kernel command lines, inherit ${dragonfly_vlan}.
+pub fn ipxe_preamble(vlan_id: u16) -> String {
+    format!(
+        "set dragonfly_vlan {vlan}\n\
+         set trunk ${{netX/ifname}}\n\
+         vcreate --tag {vlan} ${{trunk}} || goto vlan_failed\n\
+         dhcp ${{trunk}}-{vlan} || goto vlan_failed\n\
+         goto vlan_ready\n\
+         :vlan_failed\n\
+         echo Could not bring up VLAN {vlan} on ${{trunk}}\n\
+         sleep 10\n\
+         reboot\n\
+         :vlan_ready\n",
+        vlan = vlan_id
+    )
+}
+
+/// Prefix an iPXE script with the VLAN preamble.
+pub fn wrap_script(script: &str, vlan_id: u16) -> String {
+    let body = script.strip_prefix("#!ipxe\n").unwrap_or(script);
+    format!("#!ipxe\n{}{}", ipxe_preamble(vlan_id), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_range() {
+        assert!(validate(1).is_ok());
+        assert!(validate(4094).is_ok());
+        assert!(validate(0).is_err());
+        assert!(validate(4095).is_err());
+    }
+
+    #[test]
+    fn test_machine_overrides_tags() {
+        let tag_vlans = vec![("storage".to_string(), 20), ("ceph".to_string(), 30)];
+        let tags = vec!["Storage".to_string(), "ceph".to_string()];
+        assert_eq!(resolve(Some(10), &tag_vlans, &tags), Some(VlanSetting { vlan_id: 10, source_tag: None }));
+        // Tags are taken in name order, so the choice doesn't depend on insertion order
+        assert_eq!(resolve(None, &tag_vlans, &tags), Some(VlanSetting { vlan_id: 30, source_tag: Some("ceph".to_string()) }));
+        assert_eq!(resolve(None, &tag_vlans, &["gpu".to_string()]), None);
+    }
+
+    #[test]
+    fn test_wrap_script() {
+        let script = wrap_script("#!ipxe\nchain http://10.0.0.1:3000/ipxe/hookos.ipxe", 42);
+        assert!(script.starts_with("#!ipxe\nset dragonfly_vlan 42\n"));
+        assert!(script.contains("vcreate --tag 42 ${trunk}"));
+        assert!(script.contains("dhcp ${trunk}-42"));
+        assert!(script.ends_with(":vlan_ready\nchain http://10.0.0.1:3000/ipxe/hookos.ipxe"));
+    }
+}
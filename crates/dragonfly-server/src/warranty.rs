@@ -0,0 +1,444 @@
+// Warranty enrichment: looks up each machine's warranty with its vendor, using the serial
+// number and vendor from the hardware fingerprint the agent reports, and keeps the end date
+// so expiring warranties can be reported and alerted on.
+//
+// Opt-in: nothing is looked up unless a vendor is configured.
+//   Dell:   DRAGONFLY_WARRANTY_DELL_CLIENT_ID and DRAGONFLY_WARRANTY_DELL_CLIENT_SECRET
+//           (TechDirect API credentials)
+//   Lenovo: DRAGONFLY_WARRANTY_LENOVO_TOKEN (support API client ID)
+//   HPE:    DRAGONFLY_WARRANTY_HPE_URL, with an optional DRAGONFLY_WARRANTY_HPE_TOKEN.
+//           HPE has no public warranty API, so this points at a lookup service of your
+//           own: `{serial}` in the URL is replaced, and it answers with
+//           {"end_date": "YYYY-MM-DD", "service_level": "..."}.
+// Other vendors plug in by implementing `WarrantyProvider`.
+//
+// Machines are checked once a week. A machine whose warranty ends within
+// DRAGONFLY_WARRANTY_ALERT_DAYS (default 60) raises one `warranty_expiring` event per end
+// date, which notification channels pick up.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::event_manager::EventManager;
+
+const DELL_CLIENT_ID_ENV_VAR: &str = "DRAGONFLY_WARRANTY_DELL_CLIENT_ID";
+const DELL_CLIENT_SECRET_ENV_VAR: &str = "DRAGONFLY_WARRANTY_DELL_CLIENT_SECRET";
+const LENOVO_TOKEN_ENV_VAR: &str = "DRAGONFLY_WARRANTY_LENOVO_TOKEN";
+const HPE_URL_ENV_VAR: &str = "DRAGONFLY_WARRANTY_HPE_URL";
+const HPE_TOKEN_ENV_VAR: &str = "DRAGONFLY_WARRANTY_HPE_TOKEN";
+const ALERT_DAYS_ENV_VAR: &str = "DRAGONFLY_WARRANTY_ALERT_DAYS";
+
+const DELL_TOKEN_URL: &str = "https://apigtwb2c.us.dell.com/auth/oauth/v2/token";
+const DELL_ENTITLEMENTS_URL: &str = "https://apigtwb2c.us.dell.com/PROD/sbil/eapi/v5/asset-entitlements";
+const LENOVO_WARRANTY_URL: &str = "https://supportapi.lenovo.com/v2.5/warranty";
+
+pub const DEFAULT_ALERT_DAYS: i64 = 60;
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+const REFRESH_AFTER_DAYS: i64 = 7;
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Vendor {
+    Dell,
+    Lenovo,
+    Hpe,
+}
+
+impl Vendor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Vendor::Dell => "dell",
+            Vendor::Lenovo => "lenovo",
+            Vendor::Hpe => "hpe",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "dell" => Some(Vendor::Dell),
+            "lenovo" => Some(Vendor::Lenovo),
+            "hpe" => Some(Vendor::Hpe),
+            _ => None,
+        }
+    }
+
+    /// The vendor from a DMI sys_vendor string ("Dell Inc.", "LENOVO", "HPE", ...).
+    pub fn from_sys_vendor(sys_vendor: &str) -> Option<Self> {
+        let vendor = sys_vendor.trim().to_ascii_lowercase();
+        if vendor.starts_with("dell") {
+            Some(Vendor::Dell)
+        } else if vendor.starts_with("lenovo") {
+            Some(Vendor::Lenovo)
+        } else if vendor == "hpe" || vendor == "hp" || vendor.starts_with("hewlett") {
+            Some(Vendor::Hpe)
+        } else {
+            None
+        }
+    }
+}
+
+/// What a vendor says about one serial number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarrantyLookup {
+    pub end_date: NaiveDate,
+    pub service_level: Option<String>,
+}
+
+/// A machine's warranty as last looked up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warranty {
+    pub vendor: Vendor,
+    pub serial: String,
+    /// None when the vendor didn't recognise the serial
+    pub end_date: Option<NaiveDate>,
+    pub service_level: Option<String>,
+    pub checked_at: DateTime<Utc>,
+    // The end date a warranty_expiring alert was last raised for
+    #[serde(skip)]
+    pub alerted_for: Option<NaiveDate>,
+}
+
+/// Something that can look warranties up for one vendor.
+#[async_trait]
+pub trait WarrantyProvider: Send + Sync {
+    fn vendor(&self) -> Vendor;
+    /// None when the vendor doesn't know the serial.
+    async fn lookup(&self, client: &reqwest::Client, serial: &str) -> Result<Option<WarrantyLookup>>;
+}
+
+// Vendor APIs send timestamps or dates; the date part is all that matters
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+}
+
+// The entitlement that runs longest is the one that counts
+fn latest<'a>(entries: impl Iterator<Item = (Option<&'a str>, Option<&'a str>)>) -> Option<WarrantyLookup> {
+    entries
+        .filter_map(|(end, level)| Some(WarrantyLookup { end_date: parse_date(end?)?, service_level: level.map(str::to_string) }))
+        .max_by_key(|lookup| lookup.end_date)
+}
+
+pub fn parse_dell_response(body: &serde_json::Value) -> Option<WarrantyLookup> {
+    let asset = body.as_array()?.first()?;
+    if asset["invalid"].as_bool() == Some(true) {
+        return None;
+    }
+    latest(asset["entitlements"].as_array()?.iter()
+        .map(|e| (e["endDate"].as_str(), e["serviceLevelDescription"].as_str())))
+}
+
+pub fn parse_lenovo_response(body: &serde_json::Value) -> Option<WarrantyLookup> {
+    latest(body["Warranty"].as_array()?.iter()
+        .map(|w| (w["End"].as_str(), w["Name"].as_str())))
+}
+
+pub fn parse_generic_response(body: &serde_json::Value) -> Option<WarrantyLookup> {
+    latest(std::iter::once((body["end_date"].as_str(), body["service_level"].as_str())))
+}
+
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Option<serde_json::Value>> {
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        bail!("warranty API returned {}", response.status());
+    }
+    Ok(Some(response.json().await.context("warranty API returned invalid JSON")?))
+}
+
+pub struct DellProvider {
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(Deserialize)]
+struct DellToken {
+    access_token: String,
+}
+
+#[async_trait]
+impl WarrantyProvider for DellProvider {
+    fn vendor(&self) -> Vendor {
+        Vendor::Dell
+    }
+
+    async fn lookup(&self, client: &reqwest::Client, serial: &str) -> Result<Option<WarrantyLookup>> {
+        let response = client.post(DELL_TOKEN_URL)
+            .form(&[("grant_type", "client_credentials"), ("client_id", self.client_id.as_str()), ("client_secret", self.client_secret.as_str())])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("Dell token request returned {}", response.status());
+        }
+        let token: DellToken = response.json().await.context("Dell token response was invalid")?;
+        let body = get_json(client.get(DELL_ENTITLEMENTS_URL)
+            .query(&[("servicetags", serial)])
+            .bearer_auth(&token.access_token)).await?;
+        Ok(body.as_ref().and_then(parse_dell_response))
+    }
+}
+
+pub struct LenovoProvider {
+    token: String,
+}
+
+#[async_trait]
+impl WarrantyProvider for LenovoProvider {
+    fn vendor(&self) -> Vendor {
+        Vendor::Lenovo
+    }
+
+    async fn lookup(&self, client: &reqwest::Client, serial: &str) -> Result<Option<WarrantyLookup>> {
+        let body = get_json(client.get(LENOVO_WARRANTY_URL)
+            .query(&[("Serial", serial)])
+            .header("ClientID", &self.token)).await?;
+        Ok(body.as_ref().and_then(parse_lenovo_response))
+    }
+}
+
+/// A lookup service that answers {"end_date": ..., "service_level": ...} for `{serial}`.
+pub struct HttpProvider {
+    vendor: Vendor,
+    url_template: String,
+    token: Option<String>,
+}
+
+#[async_trait]
+impl WarrantyProvider for HttpProvider {
+    fn vendor(&self) -> Vendor {
+        self.vendor
+    }
+
+    async fn lookup(&self, client: &reqwest::Client, serial: &str) -> Result<Option<WarrantyLookup>> {
+        let url = self.url_template.replace("{serial}", &url::form_urlencoded::byte_serialize(serial.as_bytes()).collect::<String>());
+        let mut request = client.get(url);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let body = get_json(request).await?;
+        Ok(body.as_ref().and_then(parse_generic_response))
+    }
+}
+
+/// The vendors with credentials configured.
+pub fn providers_from_env() -> Vec<Arc<dyn WarrantyProvider>> {
+    let var = |name: &str| crate::config::var(name).filter(|v| !v.trim().is_empty());
+    let mut providers: Vec<Arc<dyn WarrantyProvider>> = Vec::new();
+    if let (Some(client_id), Some(client_secret)) = (var(DELL_CLIENT_ID_ENV_VAR), var(DELL_CLIENT_SECRET_ENV_VAR)) {
+        providers.push(Arc::new(DellProvider { client_id, client_secret }));
+    }
+    if let Some(token) = var(LENOVO_TOKEN_ENV_VAR) {
+        providers.push(Arc::new(LenovoProvider { token }));
+    }
+    if let Some(url_template) = var(HPE_URL_ENV_VAR) {
+        providers.push(Arc::new(HttpProvider { vendor: Vendor::Hpe, url_template, token: var(HPE_TOKEN_ENV_VAR) }));
+    }
+    providers
+}
+
+pub fn alert_days() -> i64 {
+    crate::config::var(ALERT_DAYS_ENV_VAR)
+        .and_then(|v| v.parse().ok())
+        .filter(|days: &i64| *days >= 0)
+        .unwrap_or(DEFAULT_ALERT_DAYS)
+}
+
+/// Days until the warranty ends (negative once it has), if it ends within `within_days`.
+pub fn expiring_in(warranty: &Warranty, today: NaiveDate, within_days: i64) -> Option<i64> {
+    let days = (warranty.end_date? - today).num_days();
+    (days <= within_days).then_some(days)
+}
+
+/// One row of the expiring-warranty report.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpiringWarranty {
+    pub machine_id: Uuid,
+    pub name: String,
+    pub days_remaining: i64,
+    #[serde(flatten)]
+    pub warranty: Warranty,
+}
+
+/// Machines whose warranty ends within `within_days` (or already has), soonest first.
+pub async fn expiring(within_days: i64) -> Result<Vec<ExpiringWarranty>> {
+    let today = Utc::now().date_naive();
+    let mut rows = Vec::new();
+    for (machine_id, warranty) in crate::db::list_warranties().await? {
+        let Some(days_remaining) = expiring_in(&warranty, today, within_days) else {
+            continue;
+        };
+        let name = match crate::db::get_machine_by_id(&machine_id).await? {
+            Some(machine) => machine.hostname.or(machine.memorable_name).unwrap_or(machine.mac_address),
+            None => continue,
+        };
+        rows.push(ExpiringWarranty { machine_id, name, days_remaining, warranty });
+    }
+    rows.sort_by_key(|row| row.days_remaining);
+    Ok(rows)
+}
+
+// Look up machines whose warranty hasn't been checked lately
+async fn refresh(client: &reqwest::Client, providers: &[Arc<dyn WarrantyProvider>]) -> Result<()> {
+    let now = Utc::now();
+    for machine in crate::db::get_all_machines().await? {
+        let Some(fingerprint) = crate::db::get_hardware_fingerprint(&machine.id).await? else {
+            continue;
+        };
+        let (Some(serial), Some(vendor)) = (
+            fingerprint.system_serial,
+            fingerprint.system_vendor.as_deref().and_then(Vendor::from_sys_vendor),
+        ) else {
+            continue;
+        };
+        let Some(provider) = providers.iter().find(|p| p.vendor() == vendor) else {
+            continue;
+        };
+        let previous = crate::db::get_warranty(&machine.id).await?;
+        if let Some(previous) = &previous {
+            if previous.serial == serial && now - previous.checked_at < Duration::days(REFRESH_AFTER_DAYS) {
+                continue;
+            }
+        }
+
+        match provider.lookup(client, &serial).await {
+            Ok(lookup) => {
+                let warranty = Warranty {
+                    vendor,
+                    end_date: lookup.as_ref().map(|l| l.end_date),
+                    service_level: lookup.and_then(|l| l.service_level),
+                    serial,
+                    checked_at: now,
+                    alerted_for: previous.and_then(|p| p.alerted_for),
+                };
+                match warranty.end_date {
+                    Some(end) => debug!("Warranty for machine {} ends {}", machine.id, end),
+                    None => debug!("{:?} has no warranty on record for machine {}", vendor, machine.id),
+                }
+                crate::db::save_warranty(&machine.id, &warranty).await?;
+            }
+            // Try again on the next pass
+            Err(e) => warn!("Failed to look up warranty for machine {}: {:#}", machine.id, e),
+        }
+    }
+    Ok(())
+}
+
+// Raise one alert per machine and end date once the end is near
+async fn alert(event_manager: &EventManager) -> Result<()> {
+    let today = Utc::now().date_naive();
+    let within_days = alert_days();
+    for (machine_id, warranty) in crate::db::list_warranties().await? {
+        let Some(days) = expiring_in(&warranty, today, within_days) else {
+            continue;
+        };
+        if warranty.alerted_for == warranty.end_date {
+            continue;
+        }
+        let _ = event_manager.send(format!("warranty_expiring:{}:{}", machine_id, days));
+        if let Some(end_date) = warranty.end_date {
+            crate::db::mark_warranty_alerted(&machine_id, end_date).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Keep warranty end dates current and alert on ones about to run out (when a vendor is configured).
+pub async fn start_warranty_task(event_manager: Arc<EventManager>, mut shutdown_rx: tokio::sync::watch::Receiver<()>) {
+    let providers = providers_from_env();
+    if providers.is_empty() {
+        debug!("Warranty enrichment not configured (no vendor credentials set)");
+        return;
+    }
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create warranty HTTP client: {}", e);
+            return;
+        }
+    };
+    info!("Looking up warranties with {:?}", providers.iter().map(|p| p.vendor()).collect::<Vec<_>>());
+
+    tokio::spawn(async move {
+        loop {
+            if crate::leader::is_leader() {
+                if let Err(e) = refresh(&client, &providers).await {
+                    error!("Warranty refresh failed: {:#}", e);
+                }
+                if let Err(e) = alert(&event_manager).await {
+                    error!("Failed to check for expiring warranties: {:#}", e);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = shutdown_rx.changed() => {
+                    info!("Shutdown signal received, stopping warranty task.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_vendor_from_sys_vendor() {
+        assert_eq!(Vendor::from_sys_vendor("Dell Inc."), Some(Vendor::Dell));
+        assert_eq!(Vendor::from_sys_vendor("LENOVO"), Some(Vendor::Lenovo));
+        assert_eq!(Vendor::from_sys_vendor("HPE"), Some(Vendor::Hpe));
+        assert_eq!(Vendor::from_sys_vendor("Hewlett Packard Enterprise"), Some(Vendor::Hpe));
+        assert_eq!(Vendor::from_sys_vendor("Supermicro"), None);
+    }
+
+    #[test]
+    fn test_parse_vendor_responses() {
+        let dell = json!([{
+            "serviceTag": "ABC1234",
+            "invalid": false,
+            "entitlements": [
+                { "serviceLevelDescription": "Basic", "endDate": "2024-03-01T05:59:59.999Z" },
+                { "serviceLevelDescription": "ProSupport", "endDate": "2026-03-01T05:59:59.999Z" }
+            ]
+        }]);
+        assert_eq!(parse_dell_response(&dell), Some(WarrantyLookup {
+            end_date: NaiveDate::from_ymd_opt(2026, 3, 1).unwrap(),
+            service_level: Some("ProSupport".to_string()),
+        }));
+        assert_eq!(parse_dell_response(&json!([{ "serviceTag": "NOPE", "invalid": true, "entitlements": [] }])), None);
+
+        let lenovo = json!({ "Serial": "PF0ABCDE", "Warranty": [{ "Name": "Base Warranty", "Start": "2021-01-01T00:00:00Z", "End": "2024-01-01T00:00:00Z" }] });
+        assert_eq!(parse_lenovo_response(&lenovo).map(|l| l.end_date), NaiveDate::from_ymd_opt(2024, 1, 1));
+
+        assert_eq!(parse_generic_response(&json!({ "end_date": "2027-06-30" })).map(|l| l.end_date), NaiveDate::from_ymd_opt(2027, 6, 30));
+        assert_eq!(parse_generic_response(&json!({ "end_date": null })), None);
+    }
+
+    #[test]
+    fn test_expiring_in() {
+        let today = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        let mut warranty = Warranty {
+            vendor: Vendor::Dell,
+            serial: "ABC1234".to_string(),
+            end_date: NaiveDate::from_ymd_opt(2026, 2, 1),
+            service_level: None,
+            checked_at: Utc::now(),
+            alerted_for: None,
+        };
+        assert_eq!(expiring_in(&warranty, today, 60), Some(31));
+        assert_eq!(expiring_in(&warranty, today, 30), None);
+        warranty.end_date = NaiveDate::from_ymd_opt(2025, 12, 1);
+        assert_eq!(expiring_in(&warranty, today, 0), Some(-31));
+        warranty.end_date = None;
+        assert_eq!(expiring_in(&warranty, today, 60), None);
+    }
+}
@@ -127,7 +127,7 @@ class GamepadController {
         });
         
         // Check if we are on the tags page
-        if (window.location.pathname === '/tags') {
+        if (window.location.pathname.endsWith('/tags')) {
             this.isTagsPage = true;
             // Wait for Alpine component to initialize
             setTimeout(() => {
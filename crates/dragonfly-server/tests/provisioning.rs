@@ -0,0 +1,154 @@
+// End-to-end provisioning path: an iPXE client boots an unknown MAC, pulls artifacts,
+// the agent registers it, and the machine shows up over SSE, in the database and in Tinkerbell.
+// The server runs in-process against a temporary directory and a fake Tinkerbell.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use dragonfly_common::models::MachineStatus;
+use dragonfly_server::tinkerbell_client::{FakeTinkerbellClient, ResourceKind};
+use futures::StreamExt;
+use reqwest::{header, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+const MAC: &str = "de:ad:be:ef:00:01";
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+async fn wait_until_up(client: &reqwest::Client, base: &str) {
+    for _ in 0..100 {
+        if let Ok(response) = client.get(format!("{}/api/heartbeat", base)).send().await {
+            if response.status().is_success() {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Server at {} never became ready", base);
+}
+
+// Forward the `event:` names from the SSE stream so the test can wait for specific ones
+fn watch_events(client: reqwest::Client, base: String) -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let response = client.get(format!("{}/api/events", base)).send().await.unwrap();
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        while let Some(Ok(chunk)) = stream.next().await {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+            while let Some(end) = buffer.find('\n') {
+                let line: String = buffer.drain(..=end).collect();
+                if let Some(name) = line.trim_end().strip_prefix("event:") {
+                    let _ = tx.send(name.trim().to_string());
+                }
+            }
+        }
+    });
+    rx
+}
+
+async fn expect_event(events: &mut mpsc::UnboundedReceiver<String>, name: &str) {
+    let wait = async {
+        while let Some(event) = events.recv().await {
+            if event == name {
+                return;
+            }
+        }
+        panic!("Event stream closed before {}", name);
+    };
+    tokio::time::timeout(Duration::from_secs(10), wait).await
+        .unwrap_or_else(|_| panic!("Timed out waiting for {} event", name));
+}
+
+#[tokio::test]
+async fn test_pxe_boot_and_registration() {
+    let workdir = tempfile::tempdir().unwrap();
+    let artifacts = workdir.path().join("artifacts");
+    std::fs::create_dir_all(artifacts.join("test")).unwrap();
+    let blob: Vec<u8> = (0..=255u8).cycle().take(64 * 1024).collect();
+    std::fs::write(artifacts.join("test/blob.bin"), &blob).unwrap();
+
+    let port = free_port();
+    let base = format!("http://127.0.0.1:{}", port);
+    // Relative paths the server writes (initial password etc.) land in the temp dir too
+    std::env::set_current_dir(workdir.path()).unwrap();
+    std::env::set_var("DRAGONFLY_DB_PATH", workdir.path().join("test.db"));
+    std::env::set_var("DRAGONFLY_PORT", port.to_string());
+    std::env::set_var("DRAGONFLY_BASE_URL", &base);
+    std::env::set_var("DRAGONFLY_IPXE_ARTIFACT_DIR", &artifacts);
+    std::env::set_var("KUBECONFIG", workdir.path().join("no-such-kubeconfig"));
+
+    let tinkerbell = Arc::new(FakeTinkerbellClient::new());
+    dragonfly_server::tinkerbell_client::install(tinkerbell.clone());
+    tokio::spawn(async {
+        if let Err(e) = dragonfly_server::run().await {
+            panic!("Server exited: {}", e);
+        }
+    });
+
+    let client = reqwest::Client::new();
+    wait_until_up(&client, &base).await;
+    let mut events = watch_events(client.clone(), base.clone());
+
+    // Unknown MAC boots into the agent
+    let script = client.get(format!("{}/{}", base, MAC)).send().await.unwrap().text().await.unwrap();
+    assert!(script.starts_with("#!ipxe"), "not an iPXE script: {}", script);
+    assert!(script.contains("dragonfly-agent.ipxe"), "unknown MAC should chain to the agent: {}", script);
+
+    // Artifacts: full, single range and multiple ranges
+    let url = format!("{}/ipxe/test/blob.bin", base);
+    let full = client.get(&url).send().await.unwrap();
+    assert_eq!(full.status(), StatusCode::OK);
+    assert_eq!(full.bytes().await.unwrap().as_ref(), blob.as_slice());
+
+    let partial = client.get(&url).header(header::RANGE, "bytes=100-199").send().await.unwrap();
+    assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(partial.headers()[header::CONTENT_RANGE], format!("bytes 100-199/{}", blob.len()).as_str());
+    assert_eq!(partial.bytes().await.unwrap().as_ref(), &blob[100..200]);
+
+    let multi = client.get(&url).header(header::RANGE, "bytes=0-9,1000-1009").send().await.unwrap();
+    assert_eq!(multi.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = multi.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("multipart/byteranges"), "unexpected content type {}", content_type);
+    let body = multi.bytes().await.unwrap();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains(&format!("bytes 0-9/{}", blob.len())));
+    assert!(body.contains(&format!("bytes 1000-1009/{}", blob.len())));
+
+    // The agent registers the machine
+    let registration = json!({
+        "mac_address": MAC,
+        "ip_address": "10.0.0.50",
+        "hostname": "e2e-node",
+        "disks": [{ "device": "/dev/sda", "size_bytes": 500_000_000_000u64, "model": null, "calculated_size": null }],
+        "nameservers": ["10.0.0.1"],
+        "cpu_model": "Test CPU",
+        "cpu_cores": 8,
+        "total_ram_bytes": 17_179_869_184u64,
+        "proxmox_vmid": null,
+        "proxmox_node": null,
+        "proxmox_cluster": null,
+    });
+    let response = client.post(format!("{}/api/machines", base)).json(&registration).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let registered: Value = response.json().await.unwrap();
+    assert!(registered["machine_id"].is_string());
+
+    expect_event(&mut events, "machine_discovered").await;
+
+    let repos = dragonfly_server::repo::Repositories::sqlite();
+    let machine = repos.machines.get_by_mac(MAC).await.unwrap().expect("machine saved");
+    assert_eq!(machine.id.to_string(), registered["machine_id"].as_str().unwrap());
+    assert_eq!(machine.status, MachineStatus::AwaitingAssignment);
+    assert_eq!(machine.hostname.as_deref(), Some("e2e-node"));
+
+    let hardware = tinkerbell.resource(ResourceKind::Hardware, &dragonfly_server::tinkerbell::hardware_resource_name(MAC));
+    assert!(hardware.is_some(), "hardware not registered with Tinkerbell: {:?}", tinkerbell.names(ResourceKind::Hardware));
+
+    // Known MAC now boots into HookOS
+    let script = client.get(format!("{}/{}", base, MAC)).send().await.unwrap().text().await.unwrap();
+    assert!(script.contains("hookos.ipxe"), "known MAC should chain to HookOS: {}", script);
+}
@@ -0,0 +1,108 @@
+use clap::Parser;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use super::client::{admin_client, server_url};
+
+#[derive(Parser, Debug)]
+pub struct ApplyArgs {
+    /// Fleet document (YAML) describing boot rules, images, groups and machines
+    #[arg(short = 'f', long = "file")]
+    pub file: PathBuf,
+
+    /// Dragonfly server URL (default: $DRAGONFLY_SERVER_URL or http://localhost:3000)
+    #[arg(long)]
+    pub server: Option<String>,
+
+    /// Show the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Apply without asking for confirmation
+    #[arg(short = 'y', long)]
+    pub yes: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlannedChange {
+    resource: String,
+    name: String,
+    action: String,
+    details: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Plan {
+    changes: Vec<PlannedChange>,
+}
+
+async fn post_document(client: &reqwest::Client, url: String, document: &str) -> Result<Plan> {
+    let response = client.post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/yaml")
+        .body(document.to_string())
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to reach {}", url))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        let message = body.get("detail").or_else(|| body.get("message")).and_then(|m| m.as_str()).unwrap_or("no details");
+        return Err(eyre!("Server returned {}: {}", status, message));
+    }
+    response.json().await.wrap_err("Failed to parse plan response")
+}
+
+fn print_plan(plan: &Plan) {
+    for change in &plan.changes {
+        let symbol = match change.action.as_str() {
+            "create" => "+",
+            "delete" => "-",
+            _ => "~",
+        };
+        println!("{} {} {}", symbol, change.resource, change.name);
+        for detail in &change.details {
+            println!("      {}", detail);
+        }
+    }
+}
+
+fn confirm(count: usize) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(eyre!("Refusing to apply {} changes without confirmation; pass --yes", count));
+    }
+    eprint!("Apply {} changes? [y/N]: ", count);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(matches!(line.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+pub async fn run_apply(args: ApplyArgs) -> Result<()> {
+    let document = std::fs::read_to_string(&args.file)
+        .wrap_err_with(|| format!("Failed to read {}", args.file.display()))?;
+    let server = server_url(args.server.as_deref());
+    let client = admin_client(&server).await?;
+
+    let plan = post_document(&client, format!("{}/api/fleet/plan", server), &document).await?;
+    if plan.changes.is_empty() {
+        println!("No changes. The fleet matches {}.", args.file.display());
+        return Ok(());
+    }
+    print_plan(&plan);
+    if args.dry_run {
+        println!("\n{} changes planned (dry run, nothing applied).", plan.changes.len());
+        return Ok(());
+    }
+    if !args.yes && !confirm(plan.changes.len())? {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    // The server re-plans against the current state, so report what it actually applied
+    let applied = post_document(&client, format!("{}/api/fleet/apply", server), &document).await?;
+    println!("Applied {} changes.", applied.changes.len());
+    Ok(())
+}
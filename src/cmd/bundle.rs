@@ -0,0 +1,382 @@
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info};
+
+use super::install::{is_command_present, run_command, run_shell_command};
+
+// Bumped whenever the bundle layout changes incompatibly
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+
+const DEFAULT_K3S_VERSION: &str = "v1.30.4+k3s1";
+const DEFAULT_HELM_VERSION: &str = "v3.15.4";
+// Must match the version the server fetches when it has internet access
+const DEFAULT_HOOKOS_VERSION: &str = "v0.10.0";
+
+const CHARTS_REPO: &str = "https://github.com/Zorlin/dragonfly-charts.git";
+const HOOKOS_TARBALLS: [&str; 4] = [
+    "hook_x86_64.tar.gz",
+    "hook_aarch64.tar.gz",
+    "hook_latest-lts-x86_64.tar.gz",
+    "hook_latest-lts-aarch64.tar.gz",
+];
+
+// Where k3s imports image tarballs from on startup
+const K3S_IMAGES_DIR: &str = "/var/lib/rancher/k3s/agent/images";
+const HOOKOS_INSTALL_DIR: &str = "/var/lib/dragonfly/ipxe-artifacts/hookos";
+
+#[derive(Parser, Debug)]
+pub struct BundleArgs {
+    #[command(subcommand)]
+    pub command: BundleCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommand {
+    /// Download everything `dragonfly install --offline` needs into a single archive
+    Create {
+        /// Where to write the bundle (.tar.gz)
+        #[arg(long, short)]
+        output: PathBuf,
+
+        /// Target architecture (amd64 or arm64)
+        #[arg(long, default_value = "amd64")]
+        arch: String,
+
+        /// k3s release to bundle
+        #[arg(long, default_value = DEFAULT_K3S_VERSION)]
+        k3s_version: String,
+
+        /// Helm release to bundle
+        #[arg(long, default_value = DEFAULT_HELM_VERSION)]
+        helm_version: String,
+
+        /// HookOS release to bundle
+        #[arg(long, default_value = DEFAULT_HOOKOS_VERSION)]
+        hookos_version: String,
+    },
+}
+
+/// Describes what a bundle contains; stored as manifest.json at the bundle root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format_version: u32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub arch: String,
+    pub k3s_version: String,
+    pub helm_version: String,
+    pub hookos_version: String,
+    /// Container images saved under images/
+    pub images: Vec<String>,
+}
+
+/// An unpacked offline bundle ready to install from.
+pub struct OfflineBundle {
+    root: PathBuf,
+    pub manifest: BundleManifest,
+}
+
+impl OfflineBundle {
+    /// Open a bundle archive (or an already-extracted bundle directory) and check it's usable on this host.
+    pub async fn open(path: &Path) -> Result<Self> {
+        let root = if path.is_dir() {
+            path.to_path_buf()
+        } else if path.is_file() {
+            let root = std::env::temp_dir().join("dragonfly-bundle");
+            if root.exists() {
+                tokio::fs::remove_dir_all(&root).await
+                    .wrap_err_with(|| format!("Failed to clean up previous bundle directory: {:?}", root))?;
+            }
+            tokio::fs::create_dir_all(&root).await?;
+            info!("Extracting offline bundle {:?}...", path);
+            run_shell_command(
+                &format!("tar -xzf '{}' -C '{}'", path.display(), root.display()),
+                "extract offline bundle",
+            )?;
+            root
+        } else {
+            bail!("Offline bundle not found at {:?}", path);
+        };
+
+        let manifest_path = root.join(MANIFEST_FILE);
+        let manifest: BundleManifest = serde_json::from_slice(
+            &tokio::fs::read(&manifest_path).await
+                .wrap_err_with(|| format!("{:?} is not a Dragonfly bundle (no {})", path, MANIFEST_FILE))?,
+        ).wrap_err("Failed to parse bundle manifest")?;
+
+        if manifest.format_version != BUNDLE_FORMAT_VERSION {
+            bail!("Bundle format version {} is not supported (expected {}); recreate it with this version of dragonfly",
+                manifest.format_version, BUNDLE_FORMAT_VERSION);
+        }
+        if manifest.arch != host_arch() {
+            bail!("Bundle was built for {} but this host is {}", manifest.arch, host_arch());
+        }
+
+        let bundle = Self { root, manifest };
+        for required in [bundle.k3s_binary(), bundle.k3s_install_script(), bundle.helm_binary(), bundle.charts_dir()] {
+            if !required.exists() {
+                bail!("Bundle is incomplete: missing {:?}", required);
+            }
+        }
+        info!("Using offline bundle (k3s {}, {} images)", bundle.manifest.k3s_version, bundle.manifest.images.len());
+        Ok(bundle)
+    }
+
+    fn k3s_binary(&self) -> PathBuf {
+        self.root.join("k3s").join("k3s")
+    }
+
+    fn k3s_install_script(&self) -> PathBuf {
+        self.root.join("k3s").join("install.sh")
+    }
+
+    fn images_dir(&self) -> PathBuf {
+        self.root.join("images")
+    }
+
+    fn helm_binary(&self) -> PathBuf {
+        self.root.join("helm").join("helm")
+    }
+
+    /// The dragonfly-charts checkout, with chart dependencies already vendored.
+    pub fn charts_dir(&self) -> PathBuf {
+        self.root.join("charts")
+    }
+
+    fn hookos_dir(&self) -> PathBuf {
+        self.root.join("hookos")
+    }
+
+    /// Install k3s from the bundle. Image tarballs go where k3s imports them at startup,
+    /// so nothing is pulled from a registry.
+    pub fn install_k3s(&self, install_env: &str) -> Result<()> {
+        info!("Installing k3s {} from offline bundle", self.manifest.k3s_version);
+        run_shell_command(
+            &format!("sudo mkdir -p {dir} && sudo cp {images}/* {dir}/", dir = K3S_IMAGES_DIR, images = self.images_dir().display()),
+            "stage bundled container images",
+        )?;
+        run_command("sudo", &["install", "-m", "0755", &self.k3s_binary().to_string_lossy(), "/usr/local/bin/k3s"], "install k3s binary")?;
+        run_shell_command(
+            &format!("INSTALL_K3S_SKIP_DOWNLOAD=true {} sh '{}'", install_env, self.k3s_install_script().display()),
+            "k3s offline installation script",
+        )?;
+        Ok(())
+    }
+
+    pub fn install_helm(&self) -> Result<()> {
+        info!("Installing Helm {} from offline bundle", self.manifest.helm_version);
+        run_command("sudo", &["install", "-m", "0755", &self.helm_binary().to_string_lossy(), "/usr/local/bin/helm"], "install helm binary")?;
+        Ok(())
+    }
+
+    /// Put HookOS where the server looks for it, so it doesn't try to download it.
+    pub fn install_hookos(&self) -> Result<()> {
+        if !self.hookos_dir().exists() {
+            bail!("Bundle is incomplete: missing {:?}", self.hookos_dir());
+        }
+        info!("Installing HookOS {} artifacts from offline bundle", self.manifest.hookos_version);
+        run_shell_command(
+            &format!("sudo mkdir -p {dir} && sudo cp -r {src}/. {dir}/", dir = HOOKOS_INSTALL_DIR, src = self.hookos_dir().display()),
+            "install HookOS artifacts",
+        )?;
+        Ok(())
+    }
+}
+
+// Architecture name as used by k3s and Helm release assets
+fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "aarch64" => "arm64",
+        _ => "amd64",
+    }
+}
+
+// Pull `image:` references out of rendered Kubernetes manifests
+fn extract_images(rendered: &str) -> Vec<String> {
+    let images: BTreeSet<String> = rendered.lines()
+        .filter_map(|line| line.trim().trim_start_matches("- ").strip_prefix("image:"))
+        .map(|value| value.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+        .filter(|image| !image.is_empty())
+        .collect();
+    images.into_iter().collect()
+}
+
+// File name for a saved image; registry paths and tags become dashes
+fn image_file_name(image: &str) -> String {
+    let name: String = image.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    format!("{}.tar", name)
+}
+
+async fn download(client: &reqwest::Client, url: &str, dest: &Path) -> Result<()> {
+    debug!("Downloading {} to {:?}", url, dest);
+    let mut response = client.get(url).send().await
+        .wrap_err_with(|| format!("Failed to download {}", url))?;
+    if !response.status().is_success() {
+        bail!("Failed to download {}: server returned {}", url, response.status());
+    }
+
+    let mut file = tokio::fs::File::create(dest).await
+        .wrap_err_with(|| format!("Failed to create {:?}", dest))?;
+    while let Some(chunk) = response.chunk().await.wrap_err_with(|| format!("Download of {} interrupted", url))? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+async fn create_bundle(
+    output: &Path,
+    arch: &str,
+    k3s_version: &str,
+    helm_version: &str,
+    hookos_version: &str,
+) -> Result<()> {
+    if arch != "amd64" && arch != "arm64" {
+        bail!("Unsupported architecture '{}': expected amd64 or arm64", arch);
+    }
+    for tool in ["git", "docker", "tar"] {
+        if !is_command_present(tool) {
+            bail!("'{}' is required to create a bundle", tool);
+        }
+    }
+
+    let staging = std::env::temp_dir().join("dragonfly-bundle-build");
+    if staging.exists() {
+        tokio::fs::remove_dir_all(&staging).await?;
+    }
+    for dir in ["k3s", "images", "helm", "hookos"] {
+        tokio::fs::create_dir_all(staging.join(dir)).await?;
+    }
+    let client = reqwest::Client::new();
+
+    // --- k3s ---
+    println!("📦 Fetching k3s {}...", k3s_version);
+    let release = format!("https://github.com/k3s-io/k3s/releases/download/{}", k3s_version.replace('+', "%2B"));
+    let binary_name = if arch == "amd64" { "k3s".to_string() } else { format!("k3s-{}", arch) };
+    download(&client, &format!("{}/{}", release, binary_name), &staging.join("k3s").join("k3s")).await?;
+    download(&client, &format!("{}/k3s-airgap-images-{}.tar.zst", release, arch),
+        &staging.join("images").join(format!("k3s-airgap-images-{}.tar.zst", arch))).await?;
+    download(&client, "https://get.k3s.io", &staging.join("k3s").join("install.sh")).await?;
+
+    // --- Helm ---
+    println!("📦 Fetching Helm {}...", helm_version);
+    let helm_tarball = staging.join("helm").join("helm.tar.gz");
+    download(&client, &format!("https://get.helm.sh/helm-{}-linux-{}.tar.gz", helm_version, arch), &helm_tarball).await?;
+    run_shell_command(
+        &format!("tar -xzf '{}' -C '{}' --strip-components=1 linux-{}/helm", helm_tarball.display(), staging.join("helm").display(), arch),
+        "extract helm binary",
+    )?;
+    tokio::fs::remove_file(&helm_tarball).await?;
+
+    // Chart dependencies are built with whichever Helm runs here; prefer the bundled one when it matches
+    let helm = if arch == host_arch() {
+        staging.join("helm").join("helm").to_string_lossy().to_string()
+    } else if is_command_present("helm") {
+        "helm".to_string()
+    } else {
+        bail!("Building a {} bundle on a {} host needs a local 'helm' binary", arch, host_arch());
+    };
+
+    // --- Helm charts ---
+    println!("📦 Fetching Dragonfly Helm charts...");
+    let charts_dir = staging.join("charts");
+    run_shell_command(&format!("git clone --depth 1 {} '{}'", CHARTS_REPO, charts_dir.display()), "clone Dragonfly Helm charts")?;
+    let stack_chart = charts_dir.join("tinkerbell").join("stack");
+    run_shell_command(&format!("cd '{}' && '{}' dependency build", stack_chart.display(), helm), "build Helm chart dependencies")?;
+
+    // --- Container images ---
+    // Render both charts with placeholder values and save every image they reference
+    let mut rendered = String::new();
+    for (release, chart) in [("tink-stack", stack_chart.clone()), ("dragonfly", charts_dir.join("dragonfly"))] {
+        let output = run_command(&helm, &[
+            "template", release, &chart.to_string_lossy(),
+            "--set", "global.publicIP=127.0.0.1",
+        ], "render Helm chart")?;
+        rendered.push_str(&String::from_utf8_lossy(&output.stdout));
+    }
+    let images = extract_images(&rendered);
+    println!("📦 Saving {} container images...", images.len());
+    for image in &images {
+        let platform = format!("linux/{}", arch);
+        run_command("docker", &["pull", "--platform", &platform, image], &format!("pull {}", image))?;
+        let dest = staging.join("images").join(image_file_name(image));
+        run_command("docker", &["save", "-o", &dest.to_string_lossy(), image], &format!("save {}", image))?;
+    }
+
+    // --- HookOS ---
+    println!("📦 Fetching HookOS {}...", hookos_version);
+    let hookos_dir = staging.join("hookos");
+    let hookos_release = format!("https://github.com/tinkerbell/hook/releases/download/{}", hookos_version);
+    download(&client, &format!("{}/checksum.txt", hookos_release), &hookos_dir.join("checksum.txt")).await?;
+    for tarball in HOOKOS_TARBALLS {
+        let path = hookos_dir.join(tarball);
+        download(&client, &format!("{}/{}", hookos_release, tarball), &path).await?;
+        run_shell_command(&format!("tar -xzf '{}' -C '{}'", path.display(), hookos_dir.display()), "extract HookOS artifacts")?;
+        tokio::fs::remove_file(&path).await?;
+    }
+
+    // --- Manifest and archive ---
+    let manifest = BundleManifest {
+        format_version: BUNDLE_FORMAT_VERSION,
+        created_at: chrono::Utc::now(),
+        arch: arch.to_string(),
+        k3s_version: k3s_version.to_string(),
+        helm_version: helm_version.to_string(),
+        hookos_version: hookos_version.to_string(),
+        images,
+    };
+    tokio::fs::write(staging.join(MANIFEST_FILE), serde_json::to_vec_pretty(&manifest)?).await?;
+
+    println!("📦 Writing bundle to {}...", output.display());
+    run_shell_command(
+        &format!("tar -czf '{}' -C '{}' .", output.display(), staging.display()),
+        "write bundle archive",
+    )?;
+    tokio::fs::remove_dir_all(&staging).await?;
+
+    println!("✅ Bundle created: {}", output.display());
+    Ok(())
+}
+
+pub async fn run_bundle(args: BundleArgs) -> Result<()> {
+    match args.command {
+        BundleCommand::Create { output, arch, k3s_version, helm_version, hookos_version } => {
+            create_bundle(&output, &arch, &k3s_version, &helm_version, &hookos_version)
+                .await
+                .map_err(|e| eyre!("Failed to create bundle: {:#}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_images() {
+        let rendered = r#"
+      containers:
+        - name: smee
+          image: "quay.io/tinkerbell/smee:v0.15.0"
+        - image: quay.io/tinkerbell/tink:v0.11.0
+      initContainers:
+        - name: init
+          image: 'quay.io/tinkerbell/smee:v0.15.0'
+"#;
+        assert_eq!(extract_images(rendered), vec![
+            "quay.io/tinkerbell/smee:v0.15.0".to_string(),
+            "quay.io/tinkerbell/tink:v0.11.0".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_image_file_name() {
+        assert_eq!(image_file_name("quay.io/tinkerbell/smee:v0.15.0"), "quay.io-tinkerbell-smee-v0.15.0.tar");
+    }
+}
@@ -0,0 +1,52 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode};
+
+const DEFAULT_SERVER_URL: &str = "http://localhost:3000";
+const SERVER_URL_ENV_VAR: &str = "DRAGONFLY_SERVER_URL";
+const USERNAME_ENV_VAR: &str = "DRAGONFLY_USERNAME";
+const PASSWORD_ENV_VAR: &str = "DRAGONFLY_PASSWORD";
+
+/// Server URL from the flag, then the environment, then the local default.
+pub fn server_url(flag: Option<&str>) -> String {
+    flag.map(str::to_string)
+        .or_else(|| std::env::var(SERVER_URL_ENV_VAR).ok())
+        .unwrap_or_else(|| DEFAULT_SERVER_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Log in as an admin and return a client carrying the session cookie.
+/// Credentials come from $DRAGONFLY_USERNAME/$DRAGONFLY_PASSWORD, prompting for the password if unset.
+pub async fn admin_client(server: &str) -> Result<Client> {
+    let client = Client::builder()
+        .cookie_store(true)
+        // The login handler answers with a redirect; we inspect it rather than follow it
+        .redirect(Policy::none())
+        .build()
+        .wrap_err("Failed to build HTTP client")?;
+
+    let username = std::env::var(USERNAME_ENV_VAR).unwrap_or_else(|_| "admin".to_string());
+    let password = match std::env::var(PASSWORD_ENV_VAR) {
+        Ok(password) => password,
+        Err(_) => rpassword::prompt_password(format!("Password for {}@{}: ", username, server))
+            .wrap_err("Failed to read password")?,
+    };
+
+    let response = client.post(format!("{}/login", server))
+        .form(&[("username", username.as_str()), ("password", password.as_str())])
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to reach Dragonfly server at {}", server))?;
+
+    let location = response.headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|l| l.to_str().ok())
+        .unwrap_or("");
+    match response.status() {
+        status if status.is_redirection() && !location.contains("error") => Ok(client),
+        status if status.is_redirection() => Err(eyre!("Login failed: invalid username or password")),
+        StatusCode::OK => Ok(client), // Demo mode accepts any credentials
+        status => Err(eyre!("Login failed: server returned {}", status)),
+    }
+}
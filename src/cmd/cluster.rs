@@ -0,0 +1,220 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::{bail, Result, WrapErr};
+use dragonfly_server::status::{get_cluster_nodes, NodeStatus};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, info};
+
+use super::bundle::OfflineBundle;
+use super::install::{check_service_running, configure_kubectl, get_host_ip_and_mask, install_k3s, run_command, run_shell_command};
+
+// k3s keeps the cluster token here on every server node; it works for both servers and agents
+const K3S_TOKEN_PATH: &str = "/var/lib/rancher/k3s/server/token";
+// Only present when the cluster was started with --cluster-init (embedded etcd)
+const K3S_ETCD_DIR: &str = "/var/lib/rancher/k3s/server/db/etcd";
+const K3S_API_PORT: u16 = 6443;
+
+const NODE_READY_TIMEOUT: Duration = Duration::from_secs(300);
+const NODE_READY_POLL: Duration = Duration::from_secs(5);
+
+/// What a node does once it's part of the cluster.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeRole {
+    /// Runs the control plane; three or more make it highly available
+    Server,
+    /// Only runs workloads
+    Agent,
+}
+
+impl std::fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeRole::Server => write!(f, "server"),
+            NodeRole::Agent => write!(f, "agent"),
+        }
+    }
+}
+
+/// An existing cluster for this node to join.
+#[derive(Debug, Clone)]
+pub struct JoinTarget {
+    pub url: String,
+    pub token: String,
+    pub role: NodeRole,
+}
+
+/// How k3s should be set up on this node.
+#[derive(Debug, Clone, Default)]
+pub struct K3sSetup {
+    /// Start embedded etcd on the first server so more servers can join later
+    pub ha: bool,
+    pub join: Option<JoinTarget>,
+}
+
+impl K3sSetup {
+    pub fn is_agent(&self) -> bool {
+        matches!(self.join, Some(JoinTarget { role: NodeRole::Agent, .. }))
+    }
+
+    /// The systemd unit the k3s installer creates for this role.
+    pub fn service_name(&self) -> &'static str {
+        if self.is_agent() { "k3s-agent" } else { "k3s" }
+    }
+
+    /// Environment for the k3s install script.
+    pub fn install_env(&self) -> String {
+        let exec = if self.is_agent() {
+            "agent"
+        } else if self.join.is_some() {
+            "server --disable traefik"
+        } else if self.ha {
+            "--disable traefik --cluster-init"
+        } else {
+            "--disable traefik"
+        };
+
+        match &self.join {
+            Some(join) => format!("K3S_URL='{}' K3S_TOKEN='{}' INSTALL_K3S_EXEC='{}'", join.url, join.token, exec),
+            None => format!("INSTALL_K3S_EXEC='{}'", exec),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ClusterArgs {
+    #[command(subcommand)]
+    pub command: ClusterCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClusterCommand {
+    /// Print the command to run on a new node to join this cluster (run on a server node)
+    JoinCommand {
+        /// Role the new node should take
+        #[arg(long, value_enum, default_value_t = NodeRole::Server)]
+        role: NodeRole,
+
+        /// Network interface whose address other nodes should use to reach this one
+        #[arg(long)]
+        interface: Option<String>,
+    },
+    /// Show every node in the cluster and whether it's ready
+    Status,
+}
+
+fn print_nodes(nodes: &[NodeStatus]) {
+    println!("{:<24} {:<22} {:<8} {:<16} {}", "NODE", "ROLES", "READY", "ADDRESS", "VERSION");
+    for node in nodes {
+        let roles = if node.roles.is_empty() { "agent".to_string() } else { node.roles.join(",") };
+        println!(
+            "{:<24} {:<22} {:<8} {:<16} {}",
+            node.name,
+            roles,
+            if node.ready { "yes" } else { "no" },
+            node.internal_ip.as_deref().unwrap_or("-"),
+            node.kubelet_version.as_deref().unwrap_or("-")
+        );
+    }
+    let servers = nodes.iter().filter(|n| n.is_server()).count();
+    let ready_servers = nodes.iter().filter(|n| n.is_server() && n.ready).count();
+    if servers >= 3 {
+        println!("Control plane: {}/{} servers ready (HA)", ready_servers, servers);
+    } else {
+        println!("Control plane: {}/{} servers ready (not HA; 3 or more servers needed)", ready_servers, servers);
+    }
+}
+
+fn node_name() -> Result<String> {
+    let output = run_command("hostname", &[], "get hostname")?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_lowercase())
+}
+
+// Wait until this node shows up Ready, then report on the whole cluster
+async fn wait_for_node(name: &str) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + NODE_READY_TIMEOUT;
+    loop {
+        match get_cluster_nodes().await {
+            Ok(nodes) if nodes.iter().any(|n| n.name == name && n.ready) => {
+                print_nodes(&nodes);
+                return Ok(());
+            }
+            Ok(_) => debug!("Node {} not ready yet", name),
+            Err(e) => debug!("Cluster not reachable yet: {:#}", e),
+        }
+        if tokio::time::Instant::now() >= deadline {
+            bail!("Timed out waiting for node {} to become ready", name);
+        }
+        tokio::time::sleep(NODE_READY_POLL).await;
+    }
+}
+
+/// Join this host to an existing cluster. Tinkerbell and Dragonfly already run
+/// cluster-wide, so this only sets up k3s.
+pub async fn join_cluster(join: JoinTarget, bundle: Option<&OfflineBundle>) -> Result<()> {
+    println!("🐉 Joining the Dragonfly cluster at {} as a {} node...", join.url, join.role);
+    let role = join.role;
+    let setup = K3sSetup { ha: false, join: Some(join) };
+
+    install_k3s(bundle, &setup).await.wrap_err("Failed to set up k3s")?;
+    if !check_service_running(setup.service_name()).await {
+        bail!("k3s was installed but the {} service isn't running", setup.service_name());
+    }
+
+    match role {
+        NodeRole::Server => {
+            let kubeconfig_path = configure_kubectl().await.wrap_err("Failed to configure kubectl")?;
+            std::env::set_var("KUBECONFIG", &kubeconfig_path);
+            let name = node_name()?;
+            info!("Waiting for node {} to become ready", name);
+            wait_for_node(&name).await?;
+        }
+        NodeRole::Agent => {
+            // Agents have no API access of their own
+            println!("Run `dragonfly cluster status` on a server node to check on this node.");
+        }
+    }
+
+    println!("✅ Joined the cluster.");
+    Ok(())
+}
+
+async fn print_join_command(role: NodeRole, interface: Option<&str>) -> Result<()> {
+    if !Path::new(K3S_TOKEN_PATH).exists() && !check_service_running("k3s").await {
+        bail!("This host isn't a k3s server node; run this on a node installed with `dragonfly install`");
+    }
+
+    let output = run_command("sudo", &["cat", K3S_TOKEN_PATH], "read k3s cluster token")?;
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let (host_ip, _, _) = get_host_ip_and_mask(interface)?;
+
+    if role == NodeRole::Server && run_shell_command(&format!("sudo test -d {}", K3S_ETCD_DIR), "check for embedded etcd").is_err() {
+        println!("⚠️  This cluster was installed without --ha, so additional server nodes can't join.");
+        println!("   Reinstall the first node with `dragonfly install --ha`, or join this node as an agent.");
+        bail!("Cluster is not HA-capable");
+    }
+
+    println!("Run this on the new node:");
+    println!();
+    println!("  dragonfly install --join https://{}:{} --token {} --role {}", host_ip, K3S_API_PORT, token, role);
+    println!();
+    println!("Keep the token secret; it grants full access to the cluster.");
+    Ok(())
+}
+
+async fn show_status() -> Result<()> {
+    // The installer leaves a kubeconfig in the directory it ran from
+    if std::env::var_os("KUBECONFIG").is_none() && Path::new("k3s.yaml").exists() {
+        std::env::set_var("KUBECONFIG", "k3s.yaml");
+    }
+    let nodes = get_cluster_nodes().await
+        .wrap_err("Failed to query the cluster; is KUBECONFIG set on this server node?")?;
+    print_nodes(&nodes);
+    Ok(())
+}
+
+pub async fn run_cluster(args: ClusterArgs) -> Result<()> {
+    match args.command {
+        ClusterCommand::JoinCommand { role, interface } => print_join_command(role, interface.as_deref()).await,
+        ClusterCommand::Status => show_status().await,
+    }
+}
@@ -0,0 +1,54 @@
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{bail, eyre, Result};
+use dragonfly_server::config::{self, Overrides};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ConfigArgs {
+    /// Configuration file (default: $DRAGONFLY_CONFIG or /etc/dragonfly/dragonfly.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the configuration the server would run with, after the environment is applied
+    Show {
+        /// Print secret-looking values instead of masking them
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Check the configuration file and environment for mistakes
+    Validate,
+}
+
+pub async fn run_config(args: ConfigArgs) -> Result<()> {
+    let overrides = Overrides { config_file: args.config, ..Default::default() };
+    let loaded = config::load(&overrides).map_err(|e| eyre!("{:#}", e))?;
+    let source = match &loaded.file {
+        Some(path) => path.display().to_string(),
+        None => "no file; defaults and environment only".to_string(),
+    };
+
+    match args.command {
+        ConfigCommand::Show { reveal } => {
+            let toml = loaded.config.to_toml(!reveal).map_err(|e| eyre!("{:#}", e))?;
+            println!("# Source: {}", source);
+            print!("{}", toml);
+        }
+        ConfigCommand::Validate => {
+            let problems = loaded.config.problems();
+            if !problems.is_empty() {
+                for problem in &problems {
+                    eprintln!("✗ {}", problem);
+                }
+                bail!("{} problem(s) in configuration ({})", problems.len(), source);
+            }
+            println!("✓ Configuration is valid ({})", source);
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,6 @@
 use clap::Args;
 use color_eyre::eyre::{bail, Result, WrapErr}; // Add bail!
 use std::net::Ipv4Addr; // Use specific types
-use std::io::Write; // Import Write trait for stdout().flush()
 use tracing::{debug, error, info, warn}; // Use tracing macros
 use std::path::PathBuf;
 use std::process::{Command, Output}; // For running commands
@@ -17,6 +16,10 @@ use std::collections::VecDeque;
  // Import signal for Ctrl+C
 use tokio::sync::watch; // Import watch
 
+use super::bundle::OfflineBundle;
+use super::cluster::{join_cluster, JoinTarget, K3sSetup, NodeRole};
+use super::preflight::{run_checks, PreflightArgs};
+
 // Import state and globals from server crate
 use dragonfly_server::{
     InstallationState, 
@@ -38,13 +41,58 @@ pub struct InstallArgs {
     #[arg(long, default_value_t = 20)]
     pub max_ip_search: u8,
 
+    /// Resume an interrupted installation from the stage it stopped at.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Continue even if preflight checks fail.
+    #[arg(long)]
+    pub ignore_preflight: bool,
+
+    /// Set up the control plane with embedded etcd so more server nodes can join for high availability.
+    #[arg(long, conflicts_with = "join")]
+    pub ha: bool,
+
+    /// Join an existing cluster at this k3s server URL (e.g. https://10.0.0.5:6443) instead of creating one.
+    #[arg(long)]
+    pub join: Option<String>,
+
+    /// Cluster token for --join, as printed by `dragonfly cluster join-command` (default: $DRAGONFLY_JOIN_TOKEN).
+    #[arg(long, requires = "join")]
+    pub token: Option<String>,
+
+    /// Role this node takes when joining.
+    #[arg(long, value_enum, default_value_t = NodeRole::Server, requires = "join")]
+    pub role: NodeRole,
+
+    /// Install without internet access, using a bundle made by `dragonfly bundle create`.
+    #[arg(long, requires = "bundle")]
+    pub offline: bool,
+
+    /// Path to the offline bundle (archive or extracted directory).
+    #[arg(long, requires = "offline")]
+    pub bundle: Option<PathBuf>,
+
+    /// Print plain progress lines instead of the interactive UI (for CI logs).
+    #[arg(long)]
+    pub plain: bool,
+
+    /// Run the server directly under systemd (socket activated, no k3s or Tinkerbell stack),
+    /// for bare-metal deployments that bring their own Tinkerbell.
+    #[arg(long, conflicts_with_all = ["ha", "join", "offline", "resume"])]
+    pub systemd: bool,
+
+    /// Write the --systemd units here instead of /etc/systemd/system, without enabling them.
+    #[arg(long, requires = "systemd")]
+    pub unit_dir: Option<PathBuf>,
+
     // Add other install-specific args here
 }
 
 // Helper function to update the global installation state and send SSE event
 async fn update_install_state(new_state: InstallationState) {
     info!("[update_install_state] Called with state: {:?}", new_state);
-    eprintln!("[DEBUG] update_install_state called for state: {:?}", new_state);
+    super::install_ui::stage(&new_state);
     // --- Update State --- 
     let state_arc_mutex: Option<Arc<tokio::sync::Mutex<InstallationState>>> = {
         INSTALL_STATE_REF.read().unwrap().as_ref().cloned()
@@ -57,6 +105,12 @@ async fn update_install_state(new_state: InstallationState) {
     } else {
          info!("[update_install_state] Install state ref NOT found (UI state won't update globally).");
     }
+
+    // --- Persist State ---
+    // Kept on disk so `--resume` can pick up after a crash
+    if let Err(e) = dragonfly_server::install_state::record_stage(&new_state).await {
+        warn!("[update_install_state] Failed to persist install state: {:#}", e);
+    }
     
     // --- Send Event --- 
     // Attempt to get EventManager
@@ -67,6 +121,7 @@ async fn update_install_state(new_state: InstallationState) {
     if let Some(event_manager) = event_manager_arc {
         // Event manager is available, send the current state update
         let payload = serde_json::json!({
+            "stage": new_state,
             "message": new_state.get_message(),
             "animation": new_state.get_animation_class(),
         });
@@ -95,6 +150,46 @@ pub async fn sudo_prompt() -> Result<()> {
 
 // The main function for the install command
 pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>) -> Result<()> {
+    if args.systemd {
+        return install_systemd(args.unit_dir.as_deref());
+    }
+
+    // --- Preflight Checks ---
+    // Runs before the installer's own server claims port 3000. A resumed install has
+    // already changed the host (k3s holds some of the ports), so only check fresh ones.
+    if !args.resume {
+        let report = tokio::task::spawn_blocking(|| run_checks(&PreflightArgs::default())).await?;
+        report.print();
+        if report.has_failures() {
+            if args.ignore_preflight {
+                println!("⚠️  Continuing despite failed preflight checks (--ignore-preflight)");
+            } else {
+                bail!("Preflight checks failed; fix the problems above or pass --ignore-preflight to install anyway");
+            }
+        }
+    }
+
+    // --- Offline Bundle ---
+    let bundle: Option<Arc<OfflineBundle>> = match args.bundle.as_deref() {
+        Some(path) if args.offline => {
+            if cfg!(target_os = "macos") {
+                bail!("Offline installs are only supported on Linux");
+            }
+            Some(Arc::new(OfflineBundle::open(path).await.wrap_err("Failed to open offline bundle")?))
+        }
+        _ => None,
+    };
+
+    // --- Joining An Existing Cluster ---
+    // Additional nodes only need k3s; the installer UI and chart deployment belong to the first node
+    if let Some(url) = args.join.clone() {
+        let token = args.token.clone()
+            .or_else(|| std::env::var("DRAGONFLY_JOIN_TOKEN").ok())
+            .ok_or_else(|| color_eyre::eyre::eyre!("--join needs a cluster token (--token or $DRAGONFLY_JOIN_TOKEN)"))?;
+        return join_cluster(JoinTarget { url, token, role: args.role }, bundle.as_deref()).await;
+    }
+    let k3s_setup = K3sSetup { ha: args.ha, join: None };
+
     // Start the webserver immediately
     let server_handle = tokio::spawn(async move {
         // Server task inherits environment.
@@ -140,10 +235,48 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
     }
     // --- End Wait --- 
 
+    // --- Resume Handling ---
+    let previous = dragonfly_server::install_state::load().await.unwrap_or_else(|e| {
+        warn!("Ignoring unreadable install state: {:#}", e);
+        None
+    });
+    let resume_from = match previous.as_ref().and_then(|p| p.resume_stage()) {
+        Some(stage) if args.resume => {
+            println!("🔁 Resuming the previous installation from: {}", stage.get_message());
+            Some(stage)
+        }
+        Some(stage) => {
+            println!("⚠️  A previous installation stopped during {:?}. Starting over; use --resume to continue from there instead.", stage);
+            None
+        }
+        None => {
+            if args.resume {
+                println!("ℹ️  No interrupted installation found; starting a fresh install.");
+            }
+            None
+        }
+    };
+    if resume_from.is_none() {
+        if let Err(e) = dragonfly_server::install_state::clear().await {
+            warn!("Failed to clear previous install state: {:#}", e);
+        }
+    }
+    // Tinkerbell may already be bound to the IP picked last time, so don't pick a new one
+    let saved_bootstrap_ip: Option<Ipv4Addr> = resume_from.as_ref()
+        .and(previous.as_ref())
+        .and_then(|p| p.bootstrap_ip.as_deref())
+        .and_then(|ip| ip.parse().ok());
+    let resume_index = resume_from.as_ref().map_or(0, |stage| stage.stage_index());
+    // Stages before the one we're resuming from finished last time
+    let completed = move |stage: InstallationState| stage.stage_index() < resume_index;
+
     // --- Start Background Installation Task --- 
     // Clone the receiver *before* spawning the task that moves it
     let mut shutdown_rx_clone = shutdown_rx.clone(); 
 
+    // Ask for sudo before the UI takes over the terminal
+    let ui_enabled = super::install_ui::use_tui(args.plain);
+
     println!("🐉 Welcome to Dragonfly.");
     println!("🚀 Open http://localhost:3000 to get started. We're ready for you to look around!");
 
@@ -155,6 +288,7 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
         // Handle the Result from sudo_prompt
         let _ = sudo_prompt().await;
     }
+    let ui = super::install_ui::start(ui_enabled);
 
     // Set initial state (WaitingSudo) - use the helper
     // This should now reliably update the global state and send the event because we waited
@@ -175,13 +309,35 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
                     .wrap_err("Failed to determine host IP (required for install)")?;
                 
                 // --- 2. Find Available Floating IP --- 
-                let bootstrap_ip = find_available_ip(host_ip, network, args.start_offset, args.max_ip_search)
-                    .await
-                    .wrap_err("Failed to find an available IP address for the bootstrap node")?;
+                let bootstrap_ip = match saved_bootstrap_ip {
+                    Some(ip) => {
+                        info!("Reusing bootstrap IP {} from the previous run", ip);
+                        ip
+                    }
+                    None => {
+                        let ip = find_available_ip(host_ip, network, args.start_offset, args.max_ip_search)
+                            .await
+                            .wrap_err("Failed to find an available IP address for the bootstrap node")?;
+                        if let Err(e) = dragonfly_server::install_state::record_bootstrap_ip(&ip.to_string()).await {
+                            warn!("Failed to persist bootstrap IP: {:#}", e);
+                        }
+                        ip
+                    }
+                };
+                super::install_ui::network(super::install_ui::NetworkSummary {
+                    interface: args.interface.clone(),
+                    host_ip,
+                    network,
+                    bootstrap_ip,
+                });
                 
                 // --- 3. Install k3s --- 
-                update_install_state(InstallationState::InstallingK3s).await;
-                install_k3s().await.wrap_err("Failed to set up k3s")?;
+                if completed(InstallationState::InstallingK3s) {
+                    info!("Skipping k3s installation (completed in a previous run)");
+                } else {
+                    update_install_state(InstallationState::InstallingK3s).await;
+                    install_k3s(bundle.as_deref(), &k3s_setup).await.wrap_err("Failed to set up k3s")?;
+                }
 
                 // --- 4. Configure kubectl --- 
                 let kubeconfig_path = configure_kubectl().await.wrap_err("Failed to configure kubectl")?;
@@ -192,15 +348,23 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
                 wait_for_node_ready(&kubeconfig_path).await.wrap_err("Timed out waiting for Kubernetes node")?;
 
                 // --- 6. Install Helm --- 
-                install_helm().await.wrap_err("Failed to set up Helm")?;
+                install_helm(bundle.as_deref()).await.wrap_err("Failed to set up Helm")?;
 
                 // --- 7. Install Tinkerbell Stack --- 
-                update_install_state(InstallationState::DeployingTinkerbell).await;
-                install_tinkerbell_stack(bootstrap_ip, network, &kubeconfig_path).await.wrap_err("Failed to install Tinkerbell stack")?;
+                if completed(InstallationState::DeployingTinkerbell) {
+                    info!("Skipping Tinkerbell deployment (completed in a previous run)");
+                } else {
+                    update_install_state(InstallationState::DeployingTinkerbell).await;
+                    install_tinkerbell_stack(bootstrap_ip, network, &kubeconfig_path, bundle.as_deref()).await.wrap_err("Failed to install Tinkerbell stack")?;
+                }
 
                 // --- 8. Install Dragonfly Helm Chart (if applicable) --- 
                 update_install_state(InstallationState::DeployingDragonfly).await;
-                install_dragonfly_chart(bootstrap_ip, &kubeconfig_path).await.wrap_err("Failed to install Dragonfly chart")?;
+                if let Some(bundle) = bundle.as_deref() {
+                    // The server would otherwise try to download these on first start
+                    bundle.install_hookos().wrap_err("Failed to install HookOS artifacts")?;
+                }
+                install_dragonfly_chart(bootstrap_ip, &kubeconfig_path, bundle.as_deref()).await.wrap_err("Failed to install Dragonfly chart")?;
 
                 // --- 9. Mark as Ready --- 
                 update_install_state(InstallationState::Ready).await;
@@ -212,6 +376,7 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
                 tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
                 
                 // --- 11. Send browser redirect event ---
+                super::install_ui::finished(format!("http://{}:3000", bootstrap_ip));
                 send_redirect_event(bootstrap_ip).await;
                 
                 // --- 12. Automatically shut down the installer after 2 more seconds ---
@@ -237,10 +402,12 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
             info!("Background installation task finished successfully.");
             // Send shutdown signal to main thread to exit gracefully
             let _ = shutdown_rx_clone.changed().await;
+            ui.stop().await;
         }
         Ok(Err(e)) => {
             error!("Background installation task failed: {:#}", e);
             update_install_state(InstallationState::Failed(e.to_string())).await;
+            ui.stop().await;
             eprintln!("Installation failed in background: {}", e);
              // Propagate error to main
              return Err(e);
@@ -248,6 +415,7 @@ pub async fn run_install(args: InstallArgs, mut shutdown_rx: watch::Receiver<()>
         Err(e) => { // JoinError (panic or cancellation)
             error!("Installation task panicked or was cancelled: {:#}", e);
             update_install_state(InstallationState::Failed("Installer task panicked or was cancelled".to_string())).await;
+            ui.stop().await;
             eprintln!("Installation task failed: {}", e);
              // Return a new error
             return Err(color_eyre::eyre::eyre!("Installation task did not complete: {}", e));
@@ -298,7 +466,7 @@ async fn send_redirect_event(bootstrap_ip: Ipv4Addr) {
 // --- Helper function implementations (from previous response) ---
 
 // Placeholder for run_shell_command - Implement robustly
-fn run_shell_command(script: &str, description: &str) -> Result<()> {
+pub(crate) fn run_shell_command(script: &str, description: &str) -> Result<()> {
     debug!("Running shell command: {}", description);
     let output = Command::new("sh")
         .arg("-c")
@@ -317,8 +485,38 @@ fn run_shell_command(script: &str, description: &str) -> Result<()> {
     Ok(())
 }
 
+// Bare-metal install: write socket and service units that run this binary's server, then
+// enable them (unless they were written somewhere else for packaging)
+fn install_systemd(unit_dir: Option<&std::path::Path>) -> Result<()> {
+    if !cfg!(target_os = "linux") {
+        bail!("--systemd installs are only supported on Linux");
+    }
+    let exe = std::env::current_exe().wrap_err("Failed to find the dragonfly executable")?;
+    let exec_start = format!("{} server", exe.display());
+    let port = dragonfly_server::config::get().server.port;
+    let dir = unit_dir.map(PathBuf::from).unwrap_or_else(|| PathBuf::from(dragonfly_server::systemd::UNIT_DIR));
+
+    let written = dragonfly_server::systemd::write_units(&dir, "dragonfly", "Dragonfly", &exec_start, port)
+        .map_err(|e| color_eyre::eyre::eyre!("{:#}", e))?;
+    for path in &written {
+        println!("Wrote {}", path.display());
+    }
+    if unit_dir.is_some() {
+        println!("Enable them with: systemctl enable --now dragonfly.socket dragonfly.service");
+        return Ok(());
+    }
+
+    run_command("systemctl", &["daemon-reload"], "Reload systemd")?;
+    run_command("systemctl", &["enable", "--now", "dragonfly.socket"], "Enable dragonfly.socket")?;
+    run_command("systemctl", &["enable", "--now", "dragonfly.service"], "Enable dragonfly.service")?;
+    println!("✅ Dragonfly is running under systemd on port {}", port);
+    println!("   Status: systemctl status dragonfly");
+    println!("   Reload settings and reopen logs: systemctl reload dragonfly");
+    Ok(())
+}
+
 // Placeholder for run_command - Implement robustly
-fn run_command(cmd: &str, args: &[&str], description: &str) -> Result<Output> {
+pub(crate) fn run_command(cmd: &str, args: &[&str], description: &str) -> Result<Output> {
     debug!("Running command: {} {}", cmd, args.join(" "));
      let output = Command::new(cmd)
         .args(args)
@@ -338,12 +536,12 @@ fn run_command(cmd: &str, args: &[&str], description: &str) -> Result<Output> {
 
 
 // Placeholder for is_command_present - Implement robustly
-fn is_command_present(cmd: &str) -> bool {
+pub(crate) fn is_command_present(cmd: &str) -> bool {
     Command::new(cmd).arg("--version").output().is_ok() // Simple check
 }
 
 // Helper function to find the primary network interface and its IP/netmask
-fn get_host_ip_and_mask(interface_name: Option<&str>) -> Result<(Ipv4Addr, Ipv4Addr, Ipv4Network)> {
+pub(crate) fn get_host_ip_and_mask(interface_name: Option<&str>) -> Result<(Ipv4Addr, Ipv4Addr, Ipv4Network)> {
     use network_interface::{NetworkInterface, NetworkInterfaceConfig};
 
     // Get all network interfaces
@@ -513,7 +711,7 @@ async fn check_ip_availability(ip: Ipv4Addr) -> Result<bool> {
     Ok(!output.status.success())
 }
 
-async fn install_k3s() -> Result<()> {
+pub(crate) async fn install_k3s(bundle: Option<&OfflineBundle>, setup: &K3sSetup) -> Result<()> {
     // Add macOS Docker check
     #[cfg(target_os = "macos")]
     {
@@ -540,8 +738,8 @@ async fn install_k3s() -> Result<()> {
 
     debug!("Checking if k3s is already installed");
     
-    // Check for existing k3s config and service
-    let config_exists = check_file_exists("/etc/rancher/k3s/k3s.yaml").await;
+    // Check for existing k3s config and service (agents never get a kubeconfig)
+    let config_exists = setup.is_agent() || check_file_exists("/etc/rancher/k3s/k3s.yaml").await;
     
     // Conditionally define service_exists based on OS
     #[cfg(not(target_os = "macos"))]
@@ -550,7 +748,7 @@ async fn install_k3s() -> Result<()> {
     let service_exists = false; // k3s doesn't run as a direct service on macOS
 
     #[cfg(not(target_os = "macos"))]
-    let is_running = check_service_running("k3s").await;
+    let is_running = check_service_running(setup.service_name()).await;
     #[cfg(target_os = "macos")]
     let is_running = false; // Assume not running as a service on macOS initially
     
@@ -563,7 +761,7 @@ async fn install_k3s() -> Result<()> {
     // Handle partially installed k3s on Linux
     if cfg!(not(target_os = "macos")) && service_exists && !is_running {
         info!("K3s service exists but isn't running, starting service (Linux)...");
-        restart_k3s_service().await?;
+        restart_k3s_service(setup.service_name()).await?;
         return Ok(());
     }
     
@@ -601,9 +799,13 @@ async fn install_k3s() -> Result<()> {
         // k3d handles starting the k3s server within Docker
     } else {
         // Linux k3s installation
-        info!("Installing k3s (single-node Linux)");
-        let script = r#"curl -sfL https://get.k3s.io | INSTALL_K3S_EXEC='--disable traefik' sh -"#;
-        run_shell_command(script, "k3s installation script")?;
+        info!("Installing k3s (Linux)");
+        if let Some(bundle) = bundle {
+            bundle.install_k3s(&setup.install_env())?;
+        } else {
+            let script = format!("curl -sfL https://get.k3s.io | {} sh -", setup.install_env());
+            run_shell_command(&script, "k3s installation script")?;
+        }
 
         // Verify installation
         if !is_command_present("k3s") {
@@ -614,16 +816,16 @@ async fn install_k3s() -> Result<()> {
         tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
         
         // Make sure the service is up
-        if !check_service_running("k3s").await {
+        if !check_service_running(setup.service_name()).await {
             info!("Starting k3s service...");
-            restart_k3s_service().await?;
+            restart_k3s_service(setup.service_name()).await?;
         }
     }
     
     info!("K3s setup completed successfully");
     Ok(())}
 
-async fn check_service_running(service_name: &str) -> bool {
+pub(crate) async fn check_service_running(service_name: &str) -> bool {
     let output = Command::new("systemctl")
         .args(["is-active", service_name])
         .output();
@@ -637,14 +839,14 @@ async fn check_service_running(service_name: &str) -> bool {
     }
 }
 
-async fn restart_k3s_service() -> Result<()> {
-    debug!("Restarting k3s service");
-    let restart_cmd = "sudo systemctl restart k3s";
-    run_shell_command(restart_cmd, "restart k3s service")?;
+async fn restart_k3s_service(service_name: &str) -> Result<()> {
+    debug!("Restarting {} service", service_name);
+    let restart_cmd = format!("sudo systemctl restart {}", service_name);
+    run_shell_command(&restart_cmd, "restart k3s service")?;
     
     // Check if service started successfully
     tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-    if check_service_running("k3s").await {
+    if check_service_running(service_name).await {
         debug!("K3s service started successfully");
         Ok(())
     } else {
@@ -652,7 +854,7 @@ async fn restart_k3s_service() -> Result<()> {
     }
 }
 
-async fn configure_kubectl() -> Result<PathBuf> {
+pub(crate) async fn configure_kubectl() -> Result<PathBuf> {
     debug!("Configuring kubectl access");
     let source_path = PathBuf::from("/etc/rancher/k3s/k3s.yaml");
     let dest_path = std::env::current_dir()?.join("k3s.yaml");
@@ -723,9 +925,7 @@ async fn wait_for_node_ready(kubeconfig_path: &PathBuf) -> Result<()> {
     let max_wait = std::time::Duration::from_secs(300); // 5 minutes timeout
     let check_interval = std::time::Duration::from_secs(5);
     let start_time = std::time::Instant::now();
-    
-    // Print a dot every few seconds to show progress
-    let mut dots_printed = 0;
+
     let mut node_ready = false;
     let mut coredns_ready = false;
 
@@ -750,6 +950,7 @@ async fn wait_for_node_ready(kubeconfig_path: &PathBuf) -> Result<()> {
                     stdout.contains(" Ready") && 
                     !stdout.contains("NotReady") {
                         debug!("Kubernetes node has become ready");
+                        super::install_ui::progress("Kubernetes node is ready");
                         node_ready = true;
                     } else {
                         // Node exists but is not ready yet
@@ -789,6 +990,7 @@ async fn wait_for_node_ready(kubeconfig_path: &PathBuf) -> Result<()> {
                     let status_str = String::from_utf8_lossy(&status.stdout).trim().trim_matches('\'').to_string();
                     if status_str.contains("True") {
                         debug!("CoreDNS is ready");
+                        super::install_ui::progress("CoreDNS is ready");
                         coredns_ready = true;
                     } else {
                         debug!("Waiting for CoreDNS to become ready: {}", status_str);
@@ -799,17 +1001,8 @@ async fn wait_for_node_ready(kubeconfig_path: &PathBuf) -> Result<()> {
             }
         }
 
-        // Print status
-        print!(".");
-        std::io::stdout().flush().wrap_err("Failed to flush stdout")?;
-        dots_printed += 1;
-
         // Check if both conditions are met
         if node_ready && coredns_ready {
-            // Add a newline after dots if we printed any
-            if dots_printed > 0 {
-                println!();
-            }
             info!("Kubernetes node and CoreDNS are ready");
             return Ok(());
         }
@@ -818,7 +1011,7 @@ async fn wait_for_node_ready(kubeconfig_path: &PathBuf) -> Result<()> {
     }
 }
 
-async fn install_helm() -> Result<()> {
+async fn install_helm(bundle: Option<&OfflineBundle>) -> Result<()> {
     debug!("Checking if Helm is already installed");
     if is_command_present("helm") {
         // Additionally check the helm version works
@@ -835,8 +1028,12 @@ async fn install_helm() -> Result<()> {
     }
     
     info!("Installing Helm");
-    let script = r#"curl -sSL https://raw.githubusercontent.com/helm/helm/main/scripts/get-helm-3 | bash"#;
-    run_shell_command(script, "Helm installation script")?;
+    if let Some(bundle) = bundle {
+        bundle.install_helm()?;
+    } else {
+        let script = r#"curl -sSL https://raw.githubusercontent.com/helm/helm/main/scripts/get-helm-3 | bash"#;
+        run_shell_command(script, "Helm installation script")?;
+    }
 
     // Verify installation
     if !is_command_present("helm") {
@@ -847,8 +1044,13 @@ async fn install_helm() -> Result<()> {
     Ok(())
 }
 
-async fn install_dragonfly_chart(bootstrap_ip: Ipv4Addr, kubeconfig_path: &PathBuf) -> Result<()> {
-    // --- Clone the GitHub repository for the Helm chart ---
+// Get the dragonfly-charts repository, from the offline bundle if there is one
+async fn fetch_charts(bundle: Option<&OfflineBundle>) -> Result<PathBuf> {
+    if let Some(bundle) = bundle {
+        info!("Using Dragonfly Helm charts from offline bundle");
+        return Ok(bundle.charts_dir());
+    }
+
     info!("Fetching Dragonfly Helm charts from GitHub...");
     
     // Create a temporary directory for the repo
@@ -866,6 +1068,12 @@ async fn install_dragonfly_chart(bootstrap_ip: Ipv4Addr, kubeconfig_path: &PathB
         repo_dir.display()
     );
     run_shell_command(&clone_cmd, "clone Dragonfly Helm charts").wrap_err("Failed to clone Helm charts repository")?;
+    Ok(repo_dir)
+}
+
+async fn install_dragonfly_chart(bootstrap_ip: Ipv4Addr, kubeconfig_path: &PathBuf, bundle: Option<&OfflineBundle>) -> Result<()> {
+    // --- Fetch the Helm charts ---
+    let repo_dir = fetch_charts(bundle).await?;
     
     // Path to the chart
     let chart_path = repo_dir.join("dragonfly");
@@ -904,7 +1112,7 @@ async fn install_dragonfly_chart(bootstrap_ip: Ipv4Addr, kubeconfig_path: &PathB
     Ok(())
 }
 
-async fn install_tinkerbell_stack(bootstrap_ip: Ipv4Addr, network: Ipv4Network, kubeconfig_path: &PathBuf) -> Result<()> {
+async fn install_tinkerbell_stack(bootstrap_ip: Ipv4Addr, network: Ipv4Network, kubeconfig_path: &PathBuf, bundle: Option<&OfflineBundle>) -> Result<()> {
     // Check if the Tinkerbell stack is already installed
     let release_exists = {
         let release_check = Command::new("helm")
@@ -1006,24 +1214,8 @@ stack:
         .wrap_err_with(|| format!("Failed to write Helm values to {:?}", values_path))?;
     debug!("Generated Helm values file: {:?}", values_path);
 
-    // --- Clone the GitHub repository for the Helm chart ---
-    info!("Fetching Dragonfly Helm charts from GitHub...");
-    
-    // Create a temporary directory for the repo
-    let repo_dir = std::env::temp_dir().join("dragonfly-charts");
-    
-    // Remove the directory if it already exists
-    if repo_dir.exists() {
-        fs::remove_dir_all(&repo_dir).await
-            .wrap_err_with(|| format!("Failed to clean up previous charts directory: {:?}", repo_dir))?;
-    }
-    
-    // Clone the repository
-    let clone_cmd = format!(
-        "git clone --depth 1 https://github.com/Zorlin/dragonfly-charts.git {}",
-        repo_dir.display()
-    );
-    run_shell_command(&clone_cmd, "clone Dragonfly Helm charts").wrap_err("Failed to clone Helm charts repository")?;
+    // --- Fetch the Helm charts ---
+    let repo_dir = fetch_charts(bundle).await?;
     
     // Path to the chart
     let chart_path = repo_dir.join("tinkerbell").join("stack");
@@ -1034,13 +1226,16 @@ stack:
     }
 
     // --- Build the Helm chart dependencies ---
-    info!("Building Helm chart dependencies...");
-    let dependency_build_cmd = format!(
-        "cd {} && helm dependency build",
-        chart_path.display()
-    );
-    run_shell_command(&dependency_build_cmd, "build Helm chart dependencies")
-        .wrap_err("Failed to build Helm chart dependencies")?;
+    // Bundled charts ship with their dependencies already vendored
+    if bundle.is_none() {
+        info!("Building Helm chart dependencies...");
+        let dependency_build_cmd = format!(
+            "cd {} && helm dependency build",
+            chart_path.display()
+        );
+        run_shell_command(&dependency_build_cmd, "build Helm chart dependencies")
+            .wrap_err("Failed to build Helm chart dependencies")?;
+    }
 
     // --- Run Helm Install/Upgrade with the local chart path ---
     let helm_args = [
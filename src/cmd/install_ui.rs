@@ -0,0 +1,627 @@
+// What `dragonfly install` shows while it runs.
+//
+// On an interactive terminal the installer takes over the screen: a checklist of install
+// phases, the network settings it detected, live pod status from k3s while Tinkerbell and
+// Dragonfly deploy, recent log lines and, once done, the URL to open along with a QR code
+// for it. With `--plain`, or when stdout isn't a terminal (CI logs), it prints one line per
+// event instead and logs go to stderr as usual.
+//
+// Install code reports through the free functions here (`stage`, `network`, `progress`,
+// `finished`); they do nothing until `start` is called, so shared helpers can call them
+// unconditionally.
+//
+// Phases come from the installer server's event stream, the same `install_status` events
+// that move the rocket in the web UI, so the terminal and the browser always agree. Until the
+// stream is connected (or while it reconnects) `stage` reports them directly instead.
+
+use dragonfly_server::InstallationState;
+use ipnetwork::Ipv4Network;
+use once_cell::sync::OnceCell;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, Wrap};
+use ratatui::Frame;
+use std::collections::{HashMap, VecDeque};
+use std::io::IsTerminal;
+use std::net::Ipv4Addr;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+// Where the installer's own UI is served while it runs
+const INSTALLER_URL: &str = "http://localhost:3000";
+// Wait between attempts to connect to the installer's event stream
+const EVENT_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// How often pods are listed while the cluster comes up
+const POD_POLL_INTERVAL: Duration = Duration::from_secs(3);
+// Redraw at least this often, for the spinner and elapsed time
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_LOG_LINES: usize = 200;
+const MAX_PROGRESS_LINES: usize = 50;
+// Namespaces whose pods make up the Dragonfly stack
+const WATCHED_NAMESPACES: [&str; 3] = ["kube-system", "tink", "dragonfly"];
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+// The phases shown in the checklist, in install order
+const PHASES: [(InstallationState, &str); 7] = [
+    (InstallationState::WaitingSudo, "Get sudo access"),
+    (InstallationState::DetectingNetwork, "Detect network"),
+    (InstallationState::InstallingK3s, "Install k3s"),
+    (InstallationState::WaitingK3s, "Wait for Kubernetes"),
+    (InstallationState::DeployingTinkerbell, "Deploy Tinkerbell"),
+    (InstallationState::DeployingDragonfly, "Deploy Dragonfly"),
+    (InstallationState::Ready, "Ready"),
+];
+
+/// Network settings the installer detected and chose.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkSummary {
+    pub interface: Option<String>,
+    pub host_ip: Ipv4Addr,
+    pub network: Ipv4Network,
+    pub bootstrap_ip: Ipv4Addr,
+}
+
+/// One line of `kubectl get pods -A`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodStatus {
+    pub namespace: String,
+    pub name: String,
+    /// Ready containers, e.g. "1/2"
+    pub ready: String,
+    pub status: String,
+}
+
+impl PodStatus {
+    pub fn healthy(&self) -> bool {
+        let mut counts = self.ready.split('/');
+        let all_ready = matches!((counts.next(), counts.next()), (Some(a), Some(b)) if a == b);
+        self.status == "Completed" || (self.status == "Running" && all_ready)
+    }
+}
+
+/// Parse `kubectl get pods -A --no-headers`, keeping the namespaces of the Dragonfly stack.
+pub fn parse_pods(output: &str) -> Vec<PodStatus> {
+    output.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pod = PodStatus {
+                namespace: fields.next()?.to_string(),
+                name: fields.next()?.to_string(),
+                ready: fields.next()?.to_string(),
+                status: fields.next()?.to_string(),
+            };
+            WATCHED_NAMESPACES.contains(&pod.namespace.as_str()).then_some(pod)
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum UiEvent {
+    Stage(InstallationState),
+    Network(NetworkSummary),
+    Progress(String),
+    Pods(Vec<PodStatus>),
+    Finished(String),
+}
+
+static EVENTS: OnceCell<mpsc::UnboundedSender<UiEvent>> = OnceCell::new();
+
+// Set while phases are arriving over the installer's event stream
+static FOLLOWING: AtomicBool = AtomicBool::new(false);
+
+// The phase last shown; a phase can be reported both directly and over the stream
+static SHOWN_STAGE: Mutex<Option<InstallationState>> = Mutex::new(None);
+
+fn send(event: UiEvent) {
+    if let Some(events) = EVENTS.get() {
+        let _ = events.send(event);
+    }
+}
+
+fn show_stage(state: InstallationState) {
+    let mut shown = SHOWN_STAGE.lock().unwrap_or_else(|e| e.into_inner());
+    if shown.as_ref() == Some(&state) {
+        return;
+    }
+    *shown = Some(state.clone());
+    send(UiEvent::Stage(state));
+}
+
+/// The installer entered a phase.
+pub fn stage(state: &InstallationState) {
+    // The UI stops right after the last phase, possibly before its event comes back
+    let last = matches!(state, InstallationState::Ready | InstallationState::Failed(_));
+    if last || !FOLLOWING.load(Ordering::Acquire) {
+        show_stage(state.clone());
+    }
+}
+
+pub fn network(summary: NetworkSummary) {
+    send(UiEvent::Network(summary));
+}
+
+/// A step within the current phase, e.g. "Kubernetes node is ready".
+pub fn progress(message: impl Into<String>) {
+    send(UiEvent::Progress(message.into()));
+}
+
+/// The install finished; `url` is where Dragonfly now runs.
+pub fn finished(url: impl Into<String>) {
+    send(UiEvent::Finished(url.into()));
+}
+
+/// Whether to draw the full-screen UI rather than plain lines.
+pub fn use_tui(plain: bool) -> bool {
+    !plain
+        && std::io::stdout().is_terminal()
+        && std::io::stdin().is_terminal()
+        && std::env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseState {
+    Done,
+    Active,
+    Pending,
+    Failed,
+}
+
+/// Everything the installer has reported so far.
+#[derive(Debug)]
+pub struct InstallView {
+    pub stage: InstallationState,
+    pub network: Option<NetworkSummary>,
+    pub progress: VecDeque<String>,
+    pub pods: Vec<PodStatus>,
+    pub logs: VecDeque<String>,
+    pub url: Option<String>,
+    // Stage that was active when the install failed
+    failed_at: Option<InstallationState>,
+    started: Instant,
+}
+
+impl InstallView {
+    pub fn new() -> Self {
+        Self {
+            stage: InstallationState::WaitingSudo,
+            network: None,
+            progress: VecDeque::new(),
+            pods: Vec::new(),
+            logs: VecDeque::new(),
+            url: None,
+            failed_at: None,
+            started: Instant::now(),
+        }
+    }
+
+    fn apply(&mut self, event: UiEvent) {
+        match event {
+            UiEvent::Stage(stage) => {
+                if matches!(stage, InstallationState::Failed(_)) {
+                    self.failed_at = Some(self.stage.clone());
+                }
+                self.stage = stage;
+            }
+            UiEvent::Network(summary) => self.network = Some(summary),
+            UiEvent::Progress(message) => push_line(&mut self.progress, message, MAX_PROGRESS_LINES),
+            UiEvent::Pods(pods) => self.pods = pods,
+            UiEvent::Finished(url) => self.url = Some(url),
+        }
+    }
+
+    /// Each phase with how far the install got through it.
+    pub fn checklist(&self) -> Vec<(&'static str, PhaseState)> {
+        let (current, failed) = match &self.failed_at {
+            Some(stage) => (stage.stage_index(), true),
+            None => (self.stage.stage_index(), false),
+        };
+        PHASES.iter()
+            .map(|(phase, label)| {
+                let index = phase.stage_index();
+                let state = if index < current || (index == current && *phase == InstallationState::Ready) {
+                    PhaseState::Done
+                } else if index == current {
+                    if failed { PhaseState::Failed } else { PhaseState::Active }
+                } else {
+                    PhaseState::Pending
+                };
+                (*label, state)
+            })
+            .collect()
+    }
+}
+
+fn push_line(lines: &mut VecDeque<String>, line: String, capacity: usize) {
+    if lines.len() >= capacity {
+        lines.pop_front();
+    }
+    lines.push_back(line);
+}
+
+/// Running UI; `stop` it before printing anything else.
+pub struct InstallUi {
+    done: Arc<AtomicBool>,
+    renderer: JoinHandle<()>,
+    pod_watcher: JoinHandle<()>,
+    event_follower: JoinHandle<()>,
+}
+
+/// Start reporting install progress, full-screen or as plain lines.
+pub fn start(tui: bool) -> InstallUi {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let _ = EVENTS.set(tx);
+    let done = Arc::new(AtomicBool::new(false));
+    let renderer = if tui {
+        let done = done.clone();
+        tokio::task::spawn_blocking(move || run_tui(rx, done))
+    } else {
+        tokio::spawn(run_plain(rx, done.clone()))
+    };
+    InstallUi {
+        done,
+        renderer,
+        pod_watcher: tokio::spawn(watch_pods()),
+        event_follower: tokio::spawn(follow_events()),
+    }
+}
+
+impl InstallUi {
+    /// Print or draw whatever is still queued, then restore the terminal.
+    pub async fn stop(self) {
+        self.pod_watcher.abort();
+        self.event_follower.abort();
+        FOLLOWING.store(false, Ordering::Release);
+        self.done.store(true, Ordering::Relaxed);
+        let mut renderer = self.renderer;
+        if tokio::time::timeout(Duration::from_secs(1), &mut renderer).await.is_err() {
+            renderer.abort();
+        }
+    }
+}
+
+// The parts of the server's event envelope we need; install_status events carry their
+// JSON payload as a string in `id`
+#[derive(Deserialize)]
+struct EventEnvelope {
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InstallStatus {
+    stage: Option<InstallationState>,
+}
+
+/// The phase an install_status event reports, if it is one.
+pub fn event_stage(event: &str, data: &str) -> Option<InstallationState> {
+    if event != "install_status" {
+        return None;
+    }
+    let envelope: EventEnvelope = serde_json::from_str(data).ok()?;
+    serde_json::from_str::<InstallStatus>(&envelope.id?).ok()?.stage
+}
+
+// Show phases as the installer server broadcasts them, reconnecting until the UI stops
+async fn follow_events() {
+    let client = reqwest::Client::new();
+    loop {
+        if let Err(e) = follow_events_once(&client).await {
+            debug!("Installer event stream unavailable: {}", e);
+        }
+        FOLLOWING.store(false, Ordering::Release);
+        tokio::time::sleep(EVENT_RECONNECT_INTERVAL).await;
+    }
+}
+
+async fn follow_events_once(client: &reqwest::Client) -> Result<(), reqwest::Error> {
+    let mut response = client.get(format!("{}/api/events", INSTALLER_URL)).send().await?.error_for_status()?;
+    FOLLOWING.store(true, Ordering::Release);
+
+    // Subscribed now, so catch up on the phase reached before the stream opened
+    let current: serde_json::Value = client.get(format!("{}/api/machines/install-status", INSTALLER_URL))
+        .send().await?
+        .json().await?;
+    if let Some(stage) = current.get("status").and_then(|s| serde_json::from_value(s.clone()).ok()) {
+        show_stage(stage);
+    }
+
+    let mut buffer = String::new();
+    while let Some(chunk) = response.chunk().await? {
+        buffer.push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n"));
+        while let Some(end) = buffer.find("\n\n") {
+            let block: String = buffer.drain(..end + 2).collect();
+            if let Some(stage) = super::logs::parse_sse_block(&block).and_then(|(event, data)| event_stage(&event, &data)) {
+                show_stage(stage);
+            }
+        }
+    }
+    Ok(())
+}
+
+// List the stack's pods once KUBECONFIG is set, i.e. once k3s is up
+async fn watch_pods() {
+    let mut last = Vec::new();
+    loop {
+        tokio::time::sleep(POD_POLL_INTERVAL).await;
+        let Ok(kubeconfig) = std::env::var("KUBECONFIG") else {
+            continue;
+        };
+        let output = tokio::process::Command::new("kubectl")
+            .args(["get", "pods", "-A", "--no-headers"])
+            .env("KUBECONFIG", kubeconfig)
+            .output()
+            .await;
+        if let Ok(output) = output {
+            if output.status.success() {
+                let pods = parse_pods(&String::from_utf8_lossy(&output.stdout));
+                if pods != last {
+                    last = pods.clone();
+                    send(UiEvent::Pods(pods));
+                }
+            }
+        }
+    }
+}
+
+// Pods whose status changed since the last listing, as plain lines
+fn pod_changes(previous: &HashMap<String, String>, pods: &[PodStatus]) -> Vec<String> {
+    pods.iter()
+        .filter_map(|pod| {
+            let key = format!("{}/{}", pod.namespace, pod.name);
+            let summary = format!("{} ({})", pod.status, pod.ready);
+            (previous.get(&key) != Some(&summary)).then(|| format!("   pod {}: {}", key, summary))
+        })
+        .collect()
+}
+
+fn print_plain(event: UiEvent, pods: &mut HashMap<String, String>) {
+    match event {
+        UiEvent::Stage(InstallationState::Failed(message)) => println!("❌ Installation failed: {}", message),
+        UiEvent::Stage(stage) => println!("[{}/{}] {}", stage.stage_index(), InstallationState::Ready.stage_index(), stage.get_message()),
+        UiEvent::Network(summary) => println!(
+            "🌐 Host {} on {}{}; bootstrap IP {}",
+            summary.host_ip,
+            summary.network,
+            summary.interface.map(|i| format!(" ({})", i)).unwrap_or_default(),
+            summary.bootstrap_ip,
+        ),
+        UiEvent::Progress(message) => println!("   {}", message),
+        UiEvent::Pods(current) => {
+            for line in pod_changes(pods, &current) {
+                println!("{}", line);
+            }
+            *pods = current.iter()
+                .map(|pod| (format!("{}/{}", pod.namespace, pod.name), format!("{} ({})", pod.status, pod.ready)))
+                .collect();
+        }
+        UiEvent::Finished(url) => println!("✅ Dragonfly is ready at {}", url),
+    }
+}
+
+async fn run_plain(mut events: mpsc::UnboundedReceiver<UiEvent>, done: Arc<AtomicBool>) {
+    let mut pods: HashMap<String, String> = HashMap::new();
+    while !done.load(Ordering::Relaxed) {
+        match tokio::time::timeout(FRAME_INTERVAL, events.recv()).await {
+            Ok(Some(event)) => print_plain(event, &mut pods),
+            Ok(None) => return,
+            Err(_) => {}
+        }
+    }
+    while let Ok(event) = events.try_recv() {
+        print_plain(event, &mut pods);
+    }
+}
+
+// Quitting from the TUI: raw mode swallows Ctrl+C, so hand the installer the signal it expects
+fn request_shutdown() {
+    // SAFETY: raise only delivers a signal to this process; tokio's ctrl_c handler receives it
+    unsafe {
+        libc::raise(libc::SIGINT);
+    }
+}
+
+fn run_tui(mut events: mpsc::UnboundedReceiver<UiEvent>, done: Arc<AtomicBool>) {
+    let mut logs = dragonfly_server::logs::subscribe();
+    let mut view = InstallView::new();
+    let mut terminal = ratatui::init();
+    let mut frame_count: usize = 0;
+
+    loop {
+        let stopping = done.load(Ordering::Relaxed);
+        while let Ok(event) = events.try_recv() {
+            view.apply(event);
+        }
+        while let Ok(record) = logs.try_recv() {
+            let line = format!("{} {:<5} {}", record.timestamp.format("%H:%M:%S"), record.level, record.message);
+            push_line(&mut view.logs, line, MAX_LOG_LINES);
+        }
+        frame_count = frame_count.wrapping_add(1);
+        if terminal.draw(|frame| draw(frame, &view, frame_count)).is_err() || stopping {
+            break;
+        }
+        if event::poll(FRAME_INTERVAL).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (ctrl_c || key.code == KeyCode::Char('q')) {
+                    request_shutdown();
+                }
+            }
+        }
+    }
+    ratatui::restore();
+}
+
+fn draw(frame: &mut Frame, view: &InstallView, frame_count: usize) {
+    let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)])
+        .areas(frame.area());
+    let [left, right] = Layout::horizontal([Constraint::Length(36), Constraint::Min(0)]).areas(body);
+    let [phases, network] = Layout::vertical([Constraint::Length(PHASES.len() as u16 + 2), Constraint::Min(0)]).areas(left);
+
+    let elapsed = view.started.elapsed().as_secs();
+    frame.render_widget(
+        Line::from(vec![
+            Span::styled(" 🐉 Dragonfly installer ", Style::new().bold().fg(Color::Magenta)),
+            Span::raw(format!(" {:02}:{:02} ", elapsed / 60, elapsed % 60)),
+            Span::styled(format!(" Installer UI: {}", INSTALLER_URL), Style::new().fg(Color::DarkGray)),
+        ]),
+        header,
+    );
+
+    let spinner = SPINNER[frame_count % SPINNER.len()];
+    let checklist: Vec<Line> = view.checklist().into_iter()
+        .map(|(label, state)| match state {
+            PhaseState::Done => Line::from(format!(" ✔ {}", label)).fg(Color::Green),
+            PhaseState::Active => Line::from(format!(" {} {}", spinner, label)).fg(Color::Yellow).bold(),
+            PhaseState::Pending => Line::from(format!(" · {}", label)).fg(Color::DarkGray),
+            PhaseState::Failed => Line::from(format!(" ✘ {}", label)).fg(Color::Red).bold(),
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(checklist).block(Block::bordered().title(" Phases ")), phases);
+
+    let network_lines = match &view.network {
+        Some(summary) => vec![
+            Line::from(format!(" Interface:    {}", summary.interface.as_deref().unwrap_or("auto"))),
+            Line::from(format!(" Host IP:      {}", summary.host_ip)),
+            Line::from(format!(" Network:      {}", summary.network)),
+            Line::from(format!(" Bootstrap IP: {}", summary.bootstrap_ip)),
+        ],
+        None => vec![Line::from(" Detecting...").fg(Color::DarkGray)],
+    };
+    frame.render_widget(Paragraph::new(network_lines).block(Block::bordered().title(" Network ")), network);
+
+    let [main, log] = Layout::vertical([Constraint::Percentage(55), Constraint::Min(0)]).areas(right);
+    match (&view.stage, &view.url) {
+        (InstallationState::Failed(message), _) => draw_failure(frame, main, message),
+        (_, Some(url)) => draw_ready(frame, main, url),
+        _ => draw_deployment(frame, main, view),
+    }
+
+    let height = log.height.saturating_sub(2) as usize;
+    let skip = view.logs.len().saturating_sub(height);
+    let log_lines: Vec<Line> = view.logs.iter().skip(skip).map(|l| Line::from(l.as_str())).collect();
+    frame.render_widget(Paragraph::new(log_lines).block(Block::bordered().title(" Log ")), log);
+
+    let hint = if view.url.is_some() || matches!(view.stage, InstallationState::Failed(_)) {
+        " q / Ctrl+C: exit"
+    } else {
+        " q / Ctrl+C: cancel the install"
+    };
+    frame.render_widget(Line::from(hint).fg(Color::DarkGray), footer);
+}
+
+fn draw_deployment(frame: &mut Frame, area: Rect, view: &InstallView) {
+    let mut lines: Vec<Line> = vec![Line::from(view.stage.get_message()).add_modifier(Modifier::BOLD)];
+    let steps = view.progress.len().min(3);
+    lines.extend(view.progress.iter().skip(view.progress.len() - steps).map(|p| Line::from(format!("  {}", p))));
+    let [summary, table] = Layout::vertical([Constraint::Length(lines.len() as u16 + 2), Constraint::Min(0)]).areas(area);
+    frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: true }).block(Block::bordered().title(" Deployment ")), summary);
+
+    let rows = view.pods.iter().map(|pod| {
+        let colour = if pod.healthy() { Color::Green } else { Color::Yellow };
+        Row::new(vec![
+            Cell::from(pod.namespace.as_str()),
+            Cell::from(pod.name.as_str()),
+            Cell::from(pod.ready.as_str()),
+            Cell::from(pod.status.as_str()).fg(colour),
+        ])
+    });
+    let healthy = view.pods.iter().filter(|pod| pod.healthy()).count();
+    let widths = [Constraint::Length(12), Constraint::Min(20), Constraint::Length(6), Constraint::Length(18)];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(Row::new(vec!["NAMESPACE", "POD", "READY", "STATUS"]).bold())
+            .block(Block::bordered().title(format!(" Pods ({}/{} ready) ", healthy, view.pods.len()))),
+        table,
+    );
+}
+
+fn draw_ready(frame: &mut Frame, area: Rect, url: &str) {
+    let mut lines = vec![
+        Line::from("Dragonfly is installed and running.").fg(Color::Green).bold(),
+        Line::from(""),
+        Line::from(vec![Span::raw("Open "), Span::styled(url.to_string(), Style::new().bold().underlined())]),
+        Line::from(""),
+    ];
+    // Dense1x2 packs two QR rows into each line; dark text on a light terminal inverts, so
+    // colours are swapped to scan on the usual dark background
+    if let Ok(code) = qrcode::QrCode::new(url.as_bytes()) {
+        let qr = code.render::<qrcode::render::unicode::Dense1x2>()
+            .dark_color(qrcode::render::unicode::Dense1x2::Light)
+            .light_color(qrcode::render::unicode::Dense1x2::Dark)
+            .quiet_zone(true)
+            .build();
+        lines.extend(qr.lines().map(|l| Line::from(l.to_string())));
+    }
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Ready ")), area);
+}
+
+fn draw_failure(frame: &mut Frame, area: Rect, message: &str) {
+    let lines = vec![
+        Line::from("Installation failed").fg(Color::Red).bold(),
+        Line::from(""),
+        Line::from(message.to_string()),
+        Line::from(""),
+        Line::from("Fix the problem and run `dragonfly install --resume` to continue.").fg(Color::DarkGray),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(Block::bordered().title(" Failed ")),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pods() {
+        let output = "kube-system   coredns-6799fbcd5-x7k2p   1/1   Running   0   2m\n\
+                      tink          smee-5c9d7b8f6-abcde      0/1   ContainerCreating   0   10s\n\
+                      default       nginx-1                   1/1   Running   0   1h\n";
+        let pods = parse_pods(output);
+        assert_eq!(pods.len(), 2);
+        assert!(pods[0].healthy());
+        assert_eq!(pods[1].status, "ContainerCreating");
+        assert!(!pods[1].healthy());
+
+        let changes = pod_changes(&HashMap::from([("kube-system/coredns-6799fbcd5-x7k2p".to_string(), "Running (1/1)".to_string())]), &pods);
+        assert_eq!(changes, vec!["   pod tink/smee-5c9d7b8f6-abcde: ContainerCreating (0/1)".to_string()]);
+    }
+
+    #[test]
+    fn test_event_stage() {
+        let data = r#"{"type":"install_status","id":"{\"stage\":\"WaitingK3s\",\"message\":\"...\",\"animation\":\"\"}","timestamp":"2026-10-18T10:00:00Z"}"#;
+        assert_eq!(event_stage("install_status", data), Some(InstallationState::WaitingK3s));
+
+        let failed = r#"{"type":"install_status","id":"{\"stage\":{\"Failed\":\"helm failed\"}}"}"#;
+        assert_eq!(event_stage("install_status", failed), Some(InstallationState::Failed("helm failed".to_string())));
+
+        // Events from before stages were included, and other events
+        assert_eq!(event_stage("install_status", r#"{"type":"install_status","id":"{\"message\":\"...\"}"}"#), None);
+        assert_eq!(event_stage("machine_updated", r#"{"type":"machine_updated","id":"abc"}"#), None);
+    }
+
+    #[test]
+    fn test_checklist() {
+        let mut view = InstallView::new();
+        view.apply(UiEvent::Stage(InstallationState::WaitingK3s));
+        let states: Vec<PhaseState> = view.checklist().into_iter().map(|(_, s)| s).collect();
+        assert_eq!(states[2], PhaseState::Done);
+        assert_eq!(states[3], PhaseState::Active);
+        assert_eq!(states[4], PhaseState::Pending);
+
+        view.apply(UiEvent::Stage(InstallationState::Failed("helm failed".to_string())));
+        assert_eq!(view.checklist()[3].1, PhaseState::Failed);
+        assert_eq!(view.checklist()[2].1, PhaseState::Done);
+
+        let mut view = InstallView::new();
+        view.apply(UiEvent::Stage(InstallationState::Ready));
+        assert!(view.checklist().iter().all(|(_, s)| *s == PhaseState::Done));
+    }
+}
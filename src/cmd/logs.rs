@@ -0,0 +1,134 @@
+use clap::Parser;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use tokio::sync::watch;
+use tracing::debug;
+
+use super::client::{admin_client, server_url};
+use super::machine::resolve_machine;
+
+#[derive(Parser, Debug)]
+pub struct LogsArgs {
+    /// Dragonfly server URL (default: $DRAGONFLY_SERVER_URL or http://localhost:3000)
+    #[arg(long)]
+    pub server: Option<String>,
+
+    /// Only show logs for this machine (hostname, memorable name, MAC or UUID) plus its workflow state
+    #[arg(long)]
+    pub machine: Option<String>,
+
+    /// Minimum level to show (error, warn, info, debug, trace)
+    #[arg(long, default_value = "info")]
+    pub level: String,
+
+    /// Keep streaming new log lines until interrupted
+    #[arg(short, long)]
+    pub follow: bool,
+}
+
+// Mirrors the server's log record
+#[derive(Deserialize, Debug)]
+struct LogRecord {
+    timestamp: String,
+    level: String,
+    target: String,
+    message: String,
+}
+
+// The subset of the server's workflow info we print
+#[derive(Deserialize, Debug)]
+struct WorkflowInfo {
+    state: String,
+    current_action: Option<String>,
+    progress: u8,
+}
+
+// Pull the event name and data out of one SSE message block
+pub(crate) fn parse_sse_block(block: &str) -> Option<(String, String)> {
+    let mut event = String::from("message");
+    let mut data: Vec<&str> = Vec::new();
+    for line in block.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            event = value.trim().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    // Keep-alive comments carry no data
+    if data.is_empty() {
+        None
+    } else {
+        Some((event, data.join("\n")))
+    }
+}
+
+// Print one event; returns true once the server says the backlog is done
+fn handle_event(event: &str, data: &str) -> bool {
+    match event {
+        "log" => match serde_json::from_str::<LogRecord>(data) {
+            Ok(record) => println!("{} {:>5} {}: {}", record.timestamp, record.level, record.target, record.message),
+            Err(e) => debug!("Skipping malformed log record: {}", e),
+        },
+        "workflow" => match serde_json::from_str::<WorkflowInfo>(data) {
+            Ok(info) => println!(
+                "[workflow] {} - {} ({}%)",
+                info.state,
+                info.current_action.as_deref().unwrap_or("no current action"),
+                info.progress
+            ),
+            Err(e) => debug!("Skipping malformed workflow update: {}", e),
+        },
+        "lagged" => eprintln!("(some log lines were dropped because the client fell behind)"),
+        "ready" => return true,
+        other => debug!("Ignoring unknown event '{}'", other),
+    }
+    false
+}
+
+pub async fn run_logs(args: LogsArgs, mut shutdown_rx: watch::Receiver<()>) -> Result<()> {
+    let server = server_url(args.server.as_deref());
+    let client = admin_client(&server).await?;
+
+    let mut query: Vec<(&str, String)> = vec![("level", args.level.clone())];
+    if let Some(reference) = args.machine.as_deref() {
+        let machine = resolve_machine(&client, &server, reference).await?;
+        query.push(("machine", machine.id.to_string()));
+    }
+
+    let mut response = client.get(format!("{}/api/admin/logs/stream", server))
+        .query(&query)
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to reach Dragonfly server at {}", server))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(eyre!("Server returned {} for log stream: {}", status, body));
+    }
+
+    let mut buffer = String::new();
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk.wrap_err("Log stream interrupted")?,
+            // Ctrl+C while following is the normal way out
+            _ = shutdown_rx.changed() => return Ok(()),
+        };
+        let Some(chunk) = chunk else { break };
+        buffer.push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n"));
+
+        while let Some(end) = buffer.find("\n\n") {
+            let block: String = buffer.drain(..end + 2).collect();
+            if let Some((event, data)) = parse_sse_block(&block) {
+                if handle_event(&event, &data) && !args.follow {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    if args.follow {
+        Err(eyre!("Server closed the log stream"))
+    } else {
+        Ok(())
+    }
+}
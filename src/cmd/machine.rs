@@ -0,0 +1,132 @@
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dragonfly_common::models::{Machine, MachineMatch, MachineResolveResponse};
+use std::io::{self, BufRead, IsTerminal, Write};
+use tracing::debug;
+
+use super::client::server_url;
+
+#[derive(Parser, Debug)]
+pub struct MachineArgs {
+    /// Dragonfly server URL (default: $DRAGONFLY_SERVER_URL or http://localhost:3000)
+    #[arg(long, global = true)]
+    pub server: Option<String>,
+
+    #[command(subcommand)]
+    pub command: MachineCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MachineCommand {
+    /// Show details for a machine
+    Show {
+        /// Hostname, memorable name, MAC address or UUID; partial names are matched fuzzily
+        machine: String,
+    },
+}
+
+fn describe(candidate: &MachineMatch) -> String {
+    format!(
+        "{} ({}, {}, {}) [{}]",
+        candidate.hostname.as_deref().unwrap_or("<no hostname>"),
+        candidate.memorable_name.as_deref().unwrap_or("-"),
+        candidate.mac_address,
+        candidate.ip_address,
+        candidate.status
+    )
+}
+
+// Ask the user to pick one of several candidates. Without a terminal there's nobody
+// to ask, so list the candidates and fail instead of guessing.
+fn choose_candidate(reference: &str, candidates: Vec<MachineMatch>) -> Result<MachineMatch> {
+    if !io::stdin().is_terminal() {
+        let listing: Vec<String> = candidates.iter().map(|c| format!("  {}", describe(c))).collect();
+        return Err(eyre!("'{}' matches {} machines:\n{}", reference, candidates.len(), listing.join("\n")));
+    }
+
+    eprintln!("'{}' matches {} machines:", reference, candidates.len());
+    for (i, candidate) in candidates.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, describe(candidate));
+    }
+
+    let stdin = io::stdin();
+    loop {
+        eprint!("Select a machine [1-{}]: ", candidates.len());
+        io::stderr().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Err(eyre!("No machine selected"));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= candidates.len() => {
+                return Ok(candidates.into_iter().nth(n - 1).expect("index checked above"));
+            }
+            _ => eprintln!("Please enter a number between 1 and {}", candidates.len()),
+        }
+    }
+}
+
+/// Resolve a user-supplied machine reference via the server, prompting on ambiguity.
+pub async fn resolve_machine(client: &reqwest::Client, server: &str, reference: &str) -> Result<MachineMatch> {
+    let response = client.get(format!("{}/api/machines/resolve", server))
+        .query(&[("q", reference)])
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to reach Dragonfly server at {}", server))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(eyre!("Server returned {} while resolving '{}': {}", status, reference, body));
+    }
+
+    let resolved: MachineResolveResponse = response.json().await
+        .wrap_err("Failed to parse machine resolution response")?;
+    debug!("Resolved '{}' to {} candidate(s)", reference, resolved.matches.len());
+
+    let mut matches = resolved.matches;
+    match matches.len() {
+        0 => Err(eyre!("No machine matches '{}'", reference)),
+        1 => Ok(matches.remove(0)),
+        _ => choose_candidate(reference, matches),
+    }
+}
+
+async fn show_machine(client: &reqwest::Client, server: &str, reference: &str) -> Result<()> {
+    let candidate = resolve_machine(client, server, reference).await?;
+
+    let response = client.get(format!("{}/api/machines/{}", server, candidate.id))
+        .send()
+        .await
+        .wrap_err("Failed to fetch machine")?;
+    if !response.status().is_success() {
+        return Err(eyre!("Server returned {} for machine {}", response.status(), candidate.id));
+    }
+
+    let body: serde_json::Value = response.json().await.wrap_err("Failed to parse machine response")?;
+    let machine: Machine = serde_json::from_value(body.get("machine").cloned().unwrap_or_default())
+        .wrap_err("Unexpected machine response format")?;
+
+    println!("ID:             {}", machine.id);
+    println!("Hostname:       {}", machine.hostname.as_deref().unwrap_or("-"));
+    println!("Memorable name: {}", machine.memorable_name.as_deref().unwrap_or("-"));
+    println!("MAC address:    {}", machine.mac_address);
+    println!("IP address:     {}", machine.ip_address);
+    println!("Status:         {}", machine.status);
+    println!("OS:             {}", machine.os_installed.as_deref().or(machine.os_choice.as_deref()).unwrap_or("-"));
+    if machine.status == dragonfly_common::models::MachineStatus::InstallingOS {
+        println!("Progress:       {}%", machine.installation_progress);
+    }
+
+    Ok(())
+}
+
+pub async fn run_machine(args: MachineArgs) -> Result<()> {
+    let server = server_url(args.server.as_deref());
+    let client = reqwest::Client::new();
+
+    match args.command {
+        MachineCommand::Show { machine } => show_machine(&client, &server, &machine).await,
+    }
+}
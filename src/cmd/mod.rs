@@ -1,5 +1,27 @@
 // Declare the install subcommand module
 pub mod install;
+// Terminal UI shown while installing
+pub mod install_ui;
+// Offline install bundles
+pub mod bundle;
+// Host checks run before installing
+pub mod preflight;
+// Multi-node cluster management
+pub mod cluster;
+// Machine inspection subcommands
+pub mod machine;
+// Server/workflow log tailing
+pub mod logs;
+// HTTP helpers shared by the client-side subcommands
+pub mod client;
+// Declarative fleet configuration
+pub mod apply;
+// Re-sending recorded agent payloads to a dev server
+pub mod replay;
+// dragonfly.toml inspection
+pub mod config;
+// Prometheus rules and Grafana dashboards for /metrics
+pub mod observability;
 
 // Declare other subcommand modules as you create them
 // pub mod server;
@@ -0,0 +1,372 @@
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use dragonfly_server::config::{self, Overrides};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+const RULES_FILE: &str = "dragonfly-alerts.yml";
+const SCRAPE_FILE: &str = "dragonfly-scrape.yml";
+const DASHBOARD_FILE: &str = "dragonfly-dashboard.json";
+
+// Inlet air above this is outside most vendors' supported range
+const INLET_TEMPERATURE_CELSIUS: u32 = 35;
+
+#[derive(Parser, Debug)]
+pub struct ObservabilityArgs {
+    /// Configuration file (default: $DRAGONFLY_CONFIG or /etc/dragonfly/dragonfly.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: ObservabilityCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ObservabilityCommand {
+    /// Write Prometheus alerting rules, a scrape config and a Grafana dashboard for /metrics
+    Export {
+        /// URL Prometheus scrapes and alerts link to (default: the configured base URL)
+        #[arg(long)]
+        base_url: Option<String>,
+
+        /// Prometheus job name for the Dragonfly target
+        #[arg(long, default_value = "dragonfly")]
+        job: String,
+
+        /// Extra target label as name=value, added to the scrape config and every query (repeatable)
+        #[arg(long = "label", value_name = "NAME=VALUE")]
+        labels: Vec<String>,
+
+        /// Directory to write the files to
+        #[arg(long, short, default_value = ".")]
+        output_dir: PathBuf,
+    },
+}
+
+/// What the generated files are parameterized by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub base_url: String,
+    pub job: String,
+    pub labels: Vec<(String, String)>,
+}
+
+impl Target {
+    fn new(base_url: &str, job: &str, labels: &[String]) -> Result<Self> {
+        let valid_name = |name: &str| {
+            name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        let mut parsed = Vec::new();
+        for label in labels {
+            let Some((name, value)) = label.split_once('=') else {
+                bail!("Label '{}' should look like name=value", label);
+            };
+            let name = name.trim();
+            if !valid_name(name) || name.starts_with("__") || name == "job" || name == "instance" {
+                bail!("'{}' can't be used as a Prometheus target label", name);
+            }
+            parsed.push((name.to_string(), value.trim().to_string()));
+        }
+        if job.trim().is_empty() {
+            bail!("The job name can't be empty");
+        }
+        Ok(Target {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            job: job.trim().to_string(),
+            labels: parsed,
+        })
+    }
+
+    // The label matchers every query starts from, without braces
+    fn matchers(&self) -> String {
+        std::iter::once(format!("job=\"{}\"", escape(&self.job)))
+            .chain(self.labels.iter().map(|(name, value)| format!("{}=\"{}\"", name, escape(value))))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    // A selector for `metric`, with extra matchers appended
+    fn selector(&self, metric: &str, extra: &str) -> String {
+        if extra.is_empty() {
+            format!("{}{{{}}}", metric, self.matchers())
+        } else {
+            format!("{}{{{},{}}}", metric, self.matchers(), extra)
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// A YAML double-quoted scalar
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", escape(value))
+}
+
+/// A Prometheus scrape config for the server's /metrics endpoint.
+pub fn scrape_config(target: &Target) -> Result<String> {
+    let url = reqwest::Url::parse(&target.base_url)
+        .wrap_err_with(|| format!("'{}' is not a URL", target.base_url))?;
+    let host = url.host_str().ok_or_else(|| eyre!("'{}' has no host", target.base_url))?;
+    let address = match url.port_or_known_default() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+
+    let mut out = String::new();
+    out.push_str("# Add to scrape_configs in prometheus.yml\n");
+    out.push_str(&format!("- job_name: {}\n", quoted(&target.job)));
+    out.push_str(&format!("  scheme: {}\n", url.scheme()));
+    // Served at the root even when the UI sits under a base path
+    out.push_str("  metrics_path: /metrics\n");
+    out.push_str("  static_configs:\n");
+    out.push_str(&format!("    - targets: [{}]\n", quoted(&address)));
+    if !target.labels.is_empty() {
+        out.push_str("      labels:\n");
+        for (name, value) in &target.labels {
+            out.push_str(&format!("        {}: {}\n", name, quoted(value)));
+        }
+    }
+    Ok(out)
+}
+
+struct Rule {
+    alert: &'static str,
+    expr: String,
+    for_: &'static str,
+    severity: &'static str,
+    summary: &'static str,
+    description: String,
+}
+
+/// Prometheus alerting rules for the metrics Dragonfly exports.
+pub fn alert_rules(target: &Target) -> String {
+    let t = target;
+    let rules = vec![
+        Rule {
+            alert: "DragonflyDown",
+            expr: format!("up{{{}}} == 0", t.matchers()),
+            for_: "5m",
+            severity: "critical",
+            summary: "Dragonfly is down",
+            description: format!("Prometheus can't scrape {}/metrics. Machines can't register or network boot.", t.base_url),
+        },
+        Rule {
+            alert: "DragonflyEventsDropped",
+            expr: format!("rate({}[10m]) > 0", t.selector("dragonfly_events_dropped_total", "reason=\"queue_full\"")),
+            for_: "10m",
+            severity: "warning",
+            summary: "Dragonfly is dropping events",
+            description: "Event subscribers (the UI, webhooks) can't keep up and are missing events.".to_string(),
+        },
+        Rule {
+            alert: "DragonflyEventSubscribersDisconnected",
+            expr: format!("increase({}[15m]) > 0", t.selector("dragonfly_event_subscribers_disconnected_total", "")),
+            for_: "0m",
+            severity: "info",
+            summary: "Dragonfly disconnected lagging event subscribers",
+            description: "A subscriber fell too far behind on machine lifecycle events and was cut off.".to_string(),
+        },
+        Rule {
+            alert: "DragonflyArtifactBandwidthSaturated",
+            expr: format!(
+                "{} / on(job, instance) ({} > 0) > 0.9",
+                t.selector("dragonfly_artifact_throughput_bytes", "client=\"\""),
+                t.selector("dragonfly_artifact_bandwidth_limit_bytes", "scope=\"global\""),
+            ),
+            for_: "15m",
+            severity: "warning",
+            summary: "Artifact downloads are held at the bandwidth limit",
+            description: "Image downloads have used over 90% of the global bandwidth limit for 15 minutes; installs will be slow.".to_string(),
+        },
+        Rule {
+            alert: "DragonflyMachineInletTemperatureHigh",
+            expr: format!("{} > {}", t.selector("dragonfly_machine_temperature_celsius", "inlet=\"true\""), INLET_TEMPERATURE_CELSIUS),
+            for_: "10m",
+            severity: "warning",
+            summary: "Machine inlet temperature is high",
+            description: format!(
+                "Inlet sensor {{{{ $labels.sensor }}}} reads {{{{ $value }}}}°C. {}/machines/{{{{ $labels.machine_id }}}}",
+                t.base_url
+            ),
+        },
+        Rule {
+            alert: "DragonflyMachineFanStopped",
+            expr: format!("{} == 0", t.selector("dragonfly_machine_fan_speed", "")),
+            for_: "5m",
+            severity: "warning",
+            summary: "Machine fan has stopped",
+            description: format!(
+                "Fan {{{{ $labels.sensor }}}} reads 0. {}/machines/{{{{ $labels.machine_id }}}}",
+                t.base_url
+            ),
+        },
+    ];
+
+    let mut out = String::new();
+    out.push_str("# Dragonfly alerting rules; add this file to rule_files in prometheus.yml\n");
+    out.push_str("groups:\n");
+    out.push_str("  - name: dragonfly\n");
+    out.push_str("    rules:\n");
+    for rule in rules {
+        out.push_str(&format!("      - alert: {}\n", rule.alert));
+        out.push_str(&format!("        expr: {}\n", quoted(&rule.expr)));
+        out.push_str(&format!("        for: {}\n", rule.for_));
+        out.push_str("        labels:\n");
+        out.push_str(&format!("          severity: {}\n", rule.severity));
+        out.push_str("        annotations:\n");
+        out.push_str(&format!("          summary: {}\n", quoted(rule.summary)));
+        out.push_str(&format!("          description: {}\n", quoted(&rule.description)));
+    }
+    out
+}
+
+// A time series panel at grid position (x, y), half the dashboard wide
+fn panel(id: u32, title: &str, x: u32, y: u32, unit: &str, targets: &[(String, &str)]) -> Value {
+    json!({
+        "id": id,
+        "type": "timeseries",
+        "title": title,
+        "datasource": { "type": "prometheus", "uid": "${datasource}" },
+        "gridPos": { "h": 8, "w": 12, "x": x, "y": y },
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "targets": targets.iter().enumerate().map(|(i, (expr, legend))| json!({
+            "refId": ((b'A' + i as u8) as char).to_string(),
+            "expr": expr,
+            "legendFormat": legend,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// A Grafana dashboard over the metrics Dragonfly exports.
+pub fn dashboard(target: &Target) -> Value {
+    let t = target;
+    let panels = vec![
+        panel(1, "Server up", 0, 0, "none", &[(format!("up{{{}}}", t.matchers()), "{{instance}}")]),
+        panel(2, "Event subscribers", 12, 0, "none", &[(t.selector("dragonfly_event_subscribers", ""), "subscribers")]),
+        panel(3, "Events", 0, 8, "ops", &[
+            (format!("rate({}[5m])", t.selector("dragonfly_events_published_total", "")), "published"),
+            (format!("rate({}[5m])", t.selector("dragonfly_events_delivered_total", "")), "delivered"),
+        ]),
+        panel(4, "Events dropped", 12, 8, "ops", &[
+            (format!("rate({}[5m])", t.selector("dragonfly_events_dropped_total", "")), "{{reason}}"),
+            (format!("rate({}[5m])", t.selector("dragonfly_event_subscribers_disconnected_total", "")), "subscribers disconnected"),
+        ]),
+        panel(5, "Artifact throughput", 0, 16, "Bps", &[
+            (t.selector("dragonfly_artifact_throughput_bytes", "client=\"\""), "total"),
+            (t.selector("dragonfly_artifact_bandwidth_limit_bytes", "scope=\"global\"") + " > 0", "limit"),
+        ]),
+        panel(6, "Artifact throughput by client", 12, 16, "Bps", &[
+            (t.selector("dragonfly_artifact_throughput_bytes", "client!=\"\""), "{{client}}"),
+        ]),
+        panel(7, "Power draw", 0, 24, "watt", &[
+            (format!("sum by (machine_id) ({})", t.selector("dragonfly_machine_power_watts", "")), "{{machine_id}}"),
+        ]),
+        panel(8, "Inlet temperature", 12, 24, "celsius", &[
+            (t.selector("dragonfly_machine_temperature_celsius", "inlet=\"true\""), "{{machine_id}} {{sensor}}"),
+        ]),
+        panel(9, "Fan speed (RPM)", 0, 32, "rpm", &[
+            (t.selector("dragonfly_machine_fan_speed", "unit=\"RPM\""), "{{machine_id}} {{sensor}}"),
+        ]),
+        panel(10, "Artifacts served", 12, 32, "bytes", &[
+            (format!("increase({}[1h])", t.selector("dragonfly_artifact_bytes_total", "")), "last hour"),
+        ]),
+    ];
+
+    json!({
+        "title": format!("Dragonfly ({})", t.job),
+        "uid": format!("dragonfly-{}", t.job.to_lowercase().replace(|c: char| !c.is_ascii_alphanumeric(), "-")),
+        "tags": ["dragonfly"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "time": { "from": "now-6h", "to": "now" },
+        "refresh": "30s",
+        "templating": {
+            "list": [{
+                "name": "datasource",
+                "label": "Prometheus",
+                "type": "datasource",
+                "query": "prometheus",
+            }],
+        },
+        "links": [{
+            "title": "Dragonfly",
+            "type": "link",
+            "url": t.base_url,
+            "targetBlank": true,
+        }],
+        "panels": panels,
+    })
+}
+
+pub async fn run_observability(args: ObservabilityArgs) -> Result<()> {
+    match args.command {
+        ObservabilityCommand::Export { base_url, job, labels, output_dir } => {
+            let base_url = match base_url {
+                Some(url) => url,
+                None => {
+                    let overrides = Overrides { config_file: args.config, ..Default::default() };
+                    let loaded = config::load(&overrides).map_err(|e| eyre!("{:#}", e))?;
+                    loaded.config.server.base_url
+                        .unwrap_or_else(|| format!("http://localhost:{}", loaded.config.server.port))
+                }
+            };
+            let target = Target::new(&base_url, &job, &labels)?;
+
+            std::fs::create_dir_all(&output_dir)
+                .wrap_err_with(|| format!("Failed to create {}", output_dir.display()))?;
+            let files = [
+                (RULES_FILE, alert_rules(&target)),
+                (SCRAPE_FILE, scrape_config(&target)?),
+                (DASHBOARD_FILE, serde_json::to_string_pretty(&dashboard(&target))? + "\n"),
+            ];
+            for (name, content) in files {
+                let path = output_dir.join(name);
+                std::fs::write(&path, content).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+                println!("✓ Wrote {}", path.display());
+            }
+            println!("Scraping {}/metrics as job \"{}\"", target.base_url, target.job);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> Target {
+        Target::new("https://dragonfly.example.com:8443/", "dragonfly-prod", &["site=syd\"1".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_target_labels() {
+        let t = target();
+        assert_eq!(t.base_url, "https://dragonfly.example.com:8443");
+        assert_eq!(t.selector("up", "a=\"b\""), r#"up{job="dragonfly-prod",site="syd\"1",a="b"}"#);
+        for bad in ["site", "job=x", "__name__=x", "1site=x"] {
+            assert!(Target::new("http://localhost:3000", "dragonfly", &[bad.to_string()]).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_generated_files_use_target() {
+        let t = target();
+        let scrape = scrape_config(&t).unwrap();
+        assert!(scrape.contains("scheme: https"));
+        assert!(scrape.contains(r#"targets: ["dragonfly.example.com:8443"]"#));
+        assert!(scrape.contains(r#"site: "syd\"1""#));
+
+        let rules = alert_rules(&t);
+        assert!(rules.contains(r#"expr: "up{job=\"dragonfly-prod\",site=\"syd\\\"1\"} == 0""#));
+
+        let dashboard = dashboard(&t);
+        assert_eq!(dashboard["links"][0]["url"], "https://dragonfly.example.com:8443");
+        let exprs: Vec<&str> = dashboard["panels"].as_array().unwrap().iter()
+            .flat_map(|p| p["targets"].as_array().unwrap().iter().map(|t| t["expr"].as_str().unwrap()))
+            .collect();
+        assert!(exprs.iter().all(|e| e.contains(r#"job="dragonfly-prod""#)));
+    }
+}
@@ -0,0 +1,351 @@
+use clap::Args;
+use color_eyre::eyre::{bail, Result};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, UdpSocket};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+// Minimums for a single-node install (k3s + Tinkerbell + Dragonfly)
+const MIN_CPU_CORES: usize = 2;
+const RECOMMENDED_CPU_CORES: usize = 4;
+const MIN_RAM_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+const RECOMMENDED_RAM_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+const MIN_DISK_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+const RECOMMENDED_DISK_BYTES: u64 = 20 * 1024 * 1024 * 1024;
+
+// Ports Dragonfly and Tinkerbell need on the host
+const TCP_PORTS: [(u16, &str); 3] = [(80, "HTTP (Hook/iPXE)"), (3000, "Dragonfly UI/API"), (42113, "Tinkerbell gRPC")];
+const UDP_PORTS: [(u16, &str); 2] = [(67, "DHCP"), (69, "TFTP")];
+
+// Modules k3s networking depends on
+const KERNEL_MODULES: [&str; 2] = ["overlay", "br_netfilter"];
+
+// How long to listen for DHCP offers
+const DHCP_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Args, Debug, Default)]
+pub struct PreflightArgs {
+    /// Skip probing the network for existing DHCP servers.
+    #[arg(long)]
+    pub skip_dhcp_probe: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status, detail: detail.into() }
+    }
+}
+
+/// Outcome of all preflight checks.
+#[derive(Debug, Default)]
+pub struct PreflightReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl PreflightReport {
+    pub fn has_failures(&self) -> bool {
+        self.results.iter().any(|r| r.status == CheckStatus::Fail)
+    }
+
+    pub fn print(&self) {
+        println!("🔍 Preflight checks:");
+        for result in &self.results {
+            let symbol = match result.status {
+                CheckStatus::Pass => "✅",
+                CheckStatus::Warn => "⚠️ ",
+                CheckStatus::Fail => "❌",
+            };
+            println!("  {} {:<28} {}", symbol, result.name, result.detail);
+        }
+        let count = |status| self.results.iter().filter(|r| r.status == status).count();
+        println!(
+            "  {} passed, {} warnings, {} failed",
+            count(CheckStatus::Pass),
+            count(CheckStatus::Warn),
+            count(CheckStatus::Fail)
+        );
+    }
+}
+
+// Classify a measured amount against minimum and recommended thresholds
+fn grade(value: u64, minimum: u64, recommended: u64) -> CheckStatus {
+    if value < minimum {
+        CheckStatus::Fail
+    } else if value < recommended {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Pass
+    }
+}
+
+fn gib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
+}
+
+fn check_cpu() -> CheckResult {
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let status = grade(cores as u64, MIN_CPU_CORES as u64, RECOMMENDED_CPU_CORES as u64);
+    CheckResult::new("CPU cores", status, format!("{} (minimum {}, recommended {})", cores, MIN_CPU_CORES, RECOMMENDED_CPU_CORES))
+}
+
+// MemTotal from /proc/meminfo, in bytes
+fn parse_mem_total(meminfo: &str) -> Option<u64> {
+    meminfo.lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+fn check_ram() -> CheckResult {
+    match std::fs::read_to_string("/proc/meminfo").ok().as_deref().and_then(parse_mem_total) {
+        Some(total) => CheckResult::new(
+            "Memory",
+            grade(total, MIN_RAM_BYTES, RECOMMENDED_RAM_BYTES),
+            format!("{:.1} GiB (minimum {:.0}, recommended {:.0})", gib(total), gib(MIN_RAM_BYTES), gib(RECOMMENDED_RAM_BYTES)),
+        ),
+        None => CheckResult::new("Memory", CheckStatus::Warn, "could not read /proc/meminfo"),
+    }
+}
+
+fn free_disk_bytes(path: &Path) -> Option<u64> {
+    let stat = nix::sys::statvfs::statvfs(path).ok()?;
+    Some(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+fn check_disk() -> CheckResult {
+    // k3s and Dragonfly both keep their data under /var/lib
+    let path = if Path::new("/var/lib").exists() { Path::new("/var/lib") } else { Path::new("/") };
+    match free_disk_bytes(path) {
+        Some(free) => CheckResult::new(
+            "Free disk space",
+            grade(free, MIN_DISK_BYTES, RECOMMENDED_DISK_BYTES),
+            format!("{:.1} GiB free on {} (minimum {:.0}, recommended {:.0})", gib(free), path.display(), gib(MIN_DISK_BYTES), gib(RECOMMENDED_DISK_BYTES)),
+        ),
+        None => CheckResult::new("Free disk space", CheckStatus::Warn, format!("could not stat {}", path.display())),
+    }
+}
+
+fn port_result(proto: &str, port: u16, purpose: &str, bind: std::io::Result<()>) -> CheckResult {
+    let name = format!("Port {}/{}", port, proto);
+    match bind {
+        Ok(()) => CheckResult::new(name, CheckStatus::Pass, format!("available for {}", purpose)),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            CheckResult::new(name, CheckStatus::Fail, format!("already in use; needed for {}", purpose))
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            CheckResult::new(name, CheckStatus::Warn, "not checked (privileged port, run as root to check)")
+        }
+        Err(e) => CheckResult::new(name, CheckStatus::Warn, format!("could not check: {}", e)),
+    }
+}
+
+fn check_ports() -> Vec<CheckResult> {
+    let any = |port| SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+    let mut results: Vec<CheckResult> = TCP_PORTS.iter()
+        .map(|(port, purpose)| port_result("tcp", *port, purpose, TcpListener::bind(any(*port)).map(|_| ())))
+        .collect();
+    results.extend(UDP_PORTS.iter()
+        .map(|(port, purpose)| port_result("udp", *port, purpose, UdpSocket::bind(any(*port)).map(|_| ()))));
+    results
+}
+
+// A minimal DHCPDISCOVER with the broadcast flag set
+fn build_dhcp_discover(xid: u32, mac: [u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0u8; 240];
+    packet[0] = 1; // BOOTREQUEST
+    packet[1] = 1; // Ethernet
+    packet[2] = 6; // Hardware address length
+    packet[4..8].copy_from_slice(&xid.to_be_bytes());
+    packet[10] = 0x80; // Broadcast flag, so offers reach us without an address
+    packet[28..34].copy_from_slice(&mac);
+    packet[236..240].copy_from_slice(&[99, 130, 83, 99]); // Magic cookie
+    packet.extend_from_slice(&[53, 1, 1]); // DHCP message type: DISCOVER
+    packet.push(255); // End
+    packet
+}
+
+// The server identifier of a DHCPOFFER answering our transaction, if that's what this is
+fn parse_dhcp_offer(packet: &[u8], xid: u32) -> Option<Ipv4Addr> {
+    if packet.len() < 240 || packet[0] != 2 || packet[4..8] != xid.to_be_bytes() || packet[236..240] != [99, 130, 83, 99] {
+        return None;
+    }
+
+    let mut is_offer = false;
+    let mut server_id = None;
+    let mut i = 240;
+    while i < packet.len() {
+        match packet[i] {
+            0 => { i += 1; continue; }
+            255 => break,
+            option => {
+                let len = *packet.get(i + 1)? as usize;
+                let value = packet.get(i + 2..i + 2 + len)?;
+                match (option, value) {
+                    (53, [2]) => is_offer = true,
+                    (54, [a, b, c, d]) => server_id = Some(Ipv4Addr::new(*a, *b, *c, *d)),
+                    _ => {}
+                }
+                i += 2 + len;
+            }
+        }
+    }
+
+    if !is_offer {
+        return None;
+    }
+    // Fall back to siaddr when the server identifier option is missing
+    server_id.or_else(|| Some(Ipv4Addr::new(packet[20], packet[21], packet[22], packet[23])))
+}
+
+fn probe_dhcp_servers() -> std::io::Result<Vec<Ipv4Addr>> {
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 68))?;
+    socket.set_broadcast(true)?;
+
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let xid = seed ^ std::process::id();
+    // Locally administered address so we never collide with real hardware
+    let mac = [0x02, 0x00, (xid >> 24) as u8, (xid >> 16) as u8, (xid >> 8) as u8, xid as u8];
+    socket.send_to(&build_dhcp_discover(xid, mac), SocketAddrV4::new(Ipv4Addr::BROADCAST, 67))?;
+
+    let deadline = Instant::now() + DHCP_PROBE_TIMEOUT;
+    let mut servers = Vec::new();
+    let mut buf = [0u8; 1500];
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                debug!("DHCP probe got {} bytes from {}", len, from);
+                if let Some(server) = parse_dhcp_offer(&buf[..len], xid) {
+                    if !servers.contains(&server) {
+                        servers.push(server);
+                    }
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(servers)
+}
+
+fn check_dhcp() -> CheckResult {
+    // Smee runs as a proxy, so an existing DHCP server is what hands out addresses
+    match probe_dhcp_servers() {
+        Ok(servers) if servers.is_empty() => CheckResult::new(
+            "DHCP servers",
+            CheckStatus::Warn,
+            "none answered; machines will need another way to get an address",
+        ),
+        Ok(servers) => {
+            let list: Vec<String> = servers.iter().map(|s| s.to_string()).collect();
+            CheckResult::new("DHCP servers", CheckStatus::Pass, format!("found {}; Dragonfly will proxy alongside", list.join(", ")))
+        }
+        Err(e) if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::AddrInUse) => {
+            CheckResult::new("DHCP servers", CheckStatus::Warn, format!("not checked ({}); run as root to probe", e))
+        }
+        Err(e) => CheckResult::new("DHCP servers", CheckStatus::Warn, format!("probe failed: {}", e)),
+    }
+}
+
+fn check_virtualization() -> CheckResult {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    let flags: Vec<&str> = cpuinfo.lines()
+        .filter(|line| line.starts_with("flags") || line.starts_with("Features"))
+        .flat_map(|line| line.split_whitespace())
+        .collect();
+    if flags.contains(&"vmx") || flags.contains(&"svm") {
+        CheckResult::new("Virtualization", CheckStatus::Pass, "hardware virtualization available")
+    } else {
+        CheckResult::new("Virtualization", CheckStatus::Warn, "no vmx/svm flag; fine unless you plan to run VMs here")
+    }
+}
+
+fn check_kernel_modules() -> Vec<CheckResult> {
+    KERNEL_MODULES.iter()
+        .map(|module| {
+            let name = format!("Kernel module {}", module);
+            // Built-in modules also appear under /sys/module
+            if Path::new("/sys/module").join(module).exists() {
+                CheckResult::new(name, CheckStatus::Pass, "loaded")
+            } else {
+                CheckResult::new(name, CheckStatus::Warn, "not loaded; k3s will try to load it")
+            }
+        })
+        .collect()
+}
+
+/// Run every check without changing anything on the host.
+pub fn run_checks(args: &PreflightArgs) -> PreflightReport {
+    let mut report = PreflightReport::default();
+    report.results.push(check_cpu());
+    report.results.push(check_ram());
+    report.results.push(check_disk());
+    report.results.extend(check_ports());
+    if !args.skip_dhcp_probe {
+        report.results.push(check_dhcp());
+    }
+    report.results.push(check_virtualization());
+    report.results.extend(check_kernel_modules());
+    report
+}
+
+pub async fn run_preflight(args: PreflightArgs) -> Result<()> {
+    let report = tokio::task::spawn_blocking(move || run_checks(&args)).await?;
+    report.print();
+    if report.has_failures() {
+        bail!("Preflight checks failed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mem_total() {
+        let meminfo = "MemTotal:        8048576 kB\nMemFree:         1024 kB\n";
+        assert_eq!(parse_mem_total(meminfo), Some(8048576 * 1024));
+        assert_eq!(parse_mem_total("MemFree: 1 kB"), None);
+    }
+
+    #[test]
+    fn test_grade() {
+        assert_eq!(grade(1, 2, 4), CheckStatus::Fail);
+        assert_eq!(grade(2, 2, 4), CheckStatus::Warn);
+        assert_eq!(grade(4, 2, 4), CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_dhcp_offer_round_trip() {
+        let xid = 0xdeadbeef;
+        // Turn our own DISCOVER into an OFFER from 192.168.1.1
+        let mut offer = build_dhcp_discover(xid, [2, 0, 0, 0, 0, 1]);
+        offer[0] = 2;
+        offer.truncate(240);
+        offer.extend_from_slice(&[53, 1, 2, 54, 4, 192, 168, 1, 1, 255]);
+
+        assert_eq!(parse_dhcp_offer(&offer, xid), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_dhcp_offer(&offer, xid + 1), None);
+        assert_eq!(parse_dhcp_offer(&build_dhcp_discover(xid, [0; 6]), xid), None);
+    }
+}
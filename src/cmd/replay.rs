@@ -0,0 +1,147 @@
+use clap::Parser;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dragonfly_server::recorder::{PayloadKind, RecordedPayload};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::client::{admin_client, server_url};
+
+#[derive(Parser, Debug)]
+pub struct ReplayArgs {
+    /// Recording to replay (agent-payloads.jsonl from $DRAGONFLY_RECORD_PAYLOADS_DIR)
+    pub file: PathBuf,
+
+    /// Dragonfly server URL (default: $DRAGONFLY_SERVER_URL or http://localhost:3000)
+    #[arg(long)]
+    pub server: Option<String>,
+
+    /// Only replay registrations, skipping later updates
+    #[arg(long)]
+    pub registrations_only: bool,
+
+    /// Pause between requests, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    pub delay_ms: u64,
+
+    /// Log in as admin first; machine updates from another address are otherwise rejected
+    #[arg(long)]
+    pub login: bool,
+}
+
+fn load(file: &Path) -> Result<Vec<RecordedPayload>> {
+    let content = std::fs::read_to_string(file)
+        .wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line)
+            .wrap_err_with(|| format!("{}:{}: not a recorded payload", file.display(), i + 1)))
+        .collect()
+}
+
+// Point a recorded call at the machine its registration created on this server
+fn remap(record: &RecordedPayload, ids: &HashMap<Uuid, Uuid>) -> (String, Value) {
+    let mut path = record.path.clone();
+    let mut body = record.body.clone();
+    if let Some((old, new)) = record.machine_id.and_then(|old| ids.get(&old).map(|new| (old, *new))) {
+        path = path.replace(&old.to_string(), &new.to_string());
+        if body.get("id").and_then(Value::as_str) == Some(old.to_string().as_str()) {
+            body["id"] = Value::String(new.to_string());
+        }
+    }
+    (path, body)
+}
+
+pub async fn run_replay(args: ReplayArgs) -> Result<()> {
+    let records = load(&args.file)?;
+    if records.is_empty() {
+        println!("{} contains no payloads.", args.file.display());
+        return Ok(());
+    }
+    let server = server_url(args.server.as_deref());
+    let client = if args.login {
+        admin_client(&server).await?
+    } else {
+        reqwest::Client::new()
+    };
+
+    let mut ids: HashMap<Uuid, Uuid> = HashMap::new();
+    let mut sent = 0;
+    let mut failed = 0;
+    for (i, record) in records.iter().enumerate() {
+        if args.registrations_only && record.kind != PayloadKind::Register {
+            continue;
+        }
+        if i > 0 && args.delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(args.delay_ms)).await;
+        }
+        if let Some(id) = record.machine_id.filter(|id| !ids.contains_key(id)) {
+            println!("  note: machine {} wasn't registered in this recording; sending to the original ID", id);
+        }
+
+        let (path, body) = remap(record, &ids);
+        let method = reqwest::Method::from_bytes(record.method.as_bytes())
+            .map_err(|_| eyre!("Invalid method '{}' in recording", record.method))?;
+        let response = client.request(method, format!("{}/api{}", server, path))
+            .json(&body)
+            .send()
+            .await
+            .wrap_err_with(|| format!("Failed to reach {}", server))?;
+        sent += 1;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+
+        println!("[{}] {} {} -> {} (recorded {})", i + 1, record.method, path, status.as_u16(), record.status);
+        if !status.is_success() {
+            failed += 1;
+            println!("      {}", text);
+        }
+        if record.kind == PayloadKind::Register && status.is_success() {
+            let new_id = serde_json::from_str::<Value>(&text).ok()
+                .and_then(|v| v.get("machine_id").and_then(Value::as_str).and_then(|id| Uuid::parse_str(id).ok()));
+            if let (Some(old), Some(new)) = (record.assigned_machine_id, new_id) {
+                ids.insert(old, new);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(eyre!("{} of {} replayed requests failed", failed, sent));
+    }
+    println!("Replayed {} payloads against {}.", sent, server);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    #[test]
+    fn test_remap_follows_registration() {
+        let old = Uuid::new_v4();
+        let new = Uuid::new_v4();
+        let record = RecordedPayload {
+            recorded_at: Utc::now(),
+            kind: PayloadKind::Update,
+            method: "PUT".to_string(),
+            path: format!("/machines/{}", old),
+            machine_id: Some(old),
+            assigned_machine_id: None,
+            status: 200,
+            body: json!({ "id": old, "hostname": "node-1" }),
+        };
+
+        let (path, body) = remap(&record, &HashMap::new());
+        assert_eq!(path, record.path);
+
+        let (path, body_mapped) = remap(&record, &HashMap::from([(old, new)]));
+        assert_eq!(path, format!("/machines/{}", new));
+        assert_eq!(body_mapped["id"], new.to_string());
+        assert_eq!(body["id"], old.to_string());
+    }
+}
@@ -16,6 +16,15 @@ use clap::CommandFactory; // Needed for print_help
 mod cmd;
 // Reference the actual install args from its module
 use cmd::install::InstallArgs;
+use cmd::machine::MachineArgs;
+use cmd::logs::LogsArgs;
+use cmd::bundle::BundleArgs;
+use cmd::preflight::PreflightArgs;
+use cmd::cluster::ClusterArgs;
+use cmd::apply::ApplyArgs;
+use cmd::replay::ReplayArgs;
+use cmd::config::ConfigArgs;
+use cmd::observability::ObservabilityArgs;
 
 // Import necessary file handling modules
 use std::io::stderr; // For foreground logging
@@ -93,19 +102,63 @@ enum Commands {
     Install(InstallArgs), // Use the actual InstallArgs from cmd::install
     /// Runs the setup wizard for Dragonfly.
     Setup(SetupArgs),
+    /// Checks whether this host is ready for Dragonfly, without changing anything.
+    Preflight(PreflightArgs),
+    /// Manages the nodes of a multi-node Dragonfly cluster.
+    Cluster(ClusterArgs),
+    /// Builds offline install bundles for air-gapped hosts.
+    Bundle(BundleArgs),
+    /// Inspects machines known to a running Dragonfly server.
+    Machine(MachineArgs),
+    /// Tails server logs and machine workflow progress.
+    Logs(LogsArgs),
+    /// Applies a declarative fleet configuration file to a running server.
+    Apply(ApplyArgs),
+    /// Re-sends recorded agent payloads to a server, to reproduce hardware-detection bugs.
+    Replay(ReplayArgs),
+    /// Shows or validates the server configuration (dragonfly.toml and environment).
+    Config(ConfigArgs),
+    /// Exports Prometheus alerting rules and a Grafana dashboard for the server's metrics.
+    Observability(ObservabilityArgs),
+    /// Generates shell completion scripts.
+    Completions(CompletionsArgs),
     // Add Agent command later if needed
     // Agent(AgentArgs),
 }
 
-// Placeholder arguments for Server (can be empty if no args needed yet)
+// Server arguments; these override dragonfly.toml and the environment
 // This could eventually move to `src/cmd/server.rs` if server logic is extracted
 #[derive(Parser, Debug)]
-struct ServerArgs {}
+struct ServerArgs {
+    /// Configuration file (default: $DRAGONFLY_CONFIG or /etc/dragonfly/dragonfly.toml)
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+
+    /// Port to listen on
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Address machines use to reach this server, e.g. http://10.0.0.5:3000
+    #[arg(long)]
+    base_url: Option<String>,
+
+    /// SQLite database file
+    #[arg(long)]
+    db_path: Option<String>,
+}
 
 // Setup command arguments (empty for now)
 #[derive(Parser, Debug)]
 struct SetupArgs {}
 
+// Completions command arguments
+#[derive(Parser, Debug)]
+struct CompletionsArgs {
+    /// Shell to generate completions for
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize dhat heap profiler if feature is enabled
@@ -130,6 +183,11 @@ async fn main() -> Result<()> {
             );
             EnvFilter::new(directives)
         }
+        Some(Commands::Machine(_)) | Some(Commands::Logs(_)) | Some(Commands::Apply(_)) | Some(Commands::Replay(_)) | Some(Commands::Config(_)) | Some(Commands::Observability(_)) | Some(Commands::Bundle(_)) | Some(Commands::Preflight(_)) | Some(Commands::Cluster(_)) | Some(Commands::Completions(_)) => {
+            // Client commands: keep the terminal clean unless something goes wrong
+            let log_level = if cli.verbose { "debug" } else { "warn" };
+            EnvFilter::new(format!("dragonfly={level},dragonfly_server=off,reqwest=warn", level = log_level))
+        }
         _ => {
             // Server/Setup/Default mode: Respect RUST_LOG, fallback to verbose/info for this crate
             let default_level = if cli.verbose { "debug" } else { "info" };
@@ -143,9 +201,26 @@ async fn main() -> Result<()> {
         }
     };
 
+    // The installer's terminal UI owns the screen, so it shows captured logs itself
+    // instead of having them written over it.
+    let install_tui = matches!(&cli.command, Some(Commands::Install(args)) if cmd::install_ui::use_tui(args.plain || args.systemd));
+
+    // The server can also log to DRAGONFLY_LOG_FILE, which is reopened on SIGHUP for logrotate
+    let log_file = match &cli.command {
+        Some(Commands::Server(_)) => dragonfly_server::logs::open_log_file().unwrap_or_else(|e| {
+            eprintln!("Failed to open log file: {}", e);
+            None
+        }),
+        _ => None,
+    };
+
     // Initialize the global logger ONCE
-    // TODO: Add file logging here maybe, depending on mode?
-    registry().with(filter).with(fmt::layer().with_writer(stderr)).init();
+    registry()
+        .with(filter)
+        .with((!install_tui).then(|| fmt::layer().with_writer(stderr)))
+        .with(log_file.map(|writer| fmt::layer().with_ansi(false).with_writer(writer)))
+        .with(dragonfly_server::logs::capture_layer()) // Feeds `dragonfly logs` / the admin log stream
+        .init();
 
     info!("Global logger initialized."); // Should appear based on filter settings
     // --- End Centralized Logging Initialization ---
@@ -178,11 +253,83 @@ async fn main() -> Result<()> {
                  // let _ = shutdown_tx.send(()); // Optional: Signal server to stop
             }
         }
+        Some(Commands::Preflight(args)) => {
+            if let Err(e) = cmd::preflight::run_preflight(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Cluster(args)) => {
+            if let Err(e) = cmd::cluster::run_cluster(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Bundle(args)) => {
+            if let Err(e) = cmd::bundle::run_bundle(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Machine(args)) => {
+            if let Err(e) = cmd::machine::run_machine(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Logs(args)) => {
+            if let Err(e) = cmd::logs::run_logs(args, shutdown_rx).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Apply(args)) => {
+            if let Err(e) = cmd::apply::run_apply(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Replay(args)) => {
+            if let Err(e) = cmd::replay::run_replay(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Config(args)) => {
+            if let Err(e) = cmd::config::run_config(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Observability(args)) => {
+            if let Err(e) = cmd::observability::run_observability(args).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Completions(args)) => {
+            clap_complete::generate(args.shell, &mut Cli::command(), "dragonfly", &mut std::io::stdout());
+        }
         // Separate Server command logic
-        Some(Commands::Server(_args)) => {
+        Some(Commands::Server(args)) => {
             info!("Checking Dragonfly installation status for server mode...");
             // Use the comprehensive installation check from the server crate
             let is_installed = dragonfly_server::is_dragonfly_installed().await;
+            if !is_installed {
+                // Set before the configuration loads so it picks demo mode up
+                std::env::set_var("DRAGONFLY_DEMO_MODE", "true");
+            }
+
+            let overrides = dragonfly_server::config::Overrides {
+                config_file: args.config,
+                port: args.port,
+                base_url: args.base_url,
+                db_path: args.db_path,
+            };
+            if let Err(e) = dragonfly_server::config::init(&overrides) {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
             
             // Register a panic handler to ensure clean exit
             let original_hook = std::panic::take_hook();
@@ -218,8 +365,6 @@ async fn main() -> Result<()> {
                 }
             } else {
                 info!("Dragonfly is not installed. Starting Demo Experience...");
-                // Set environment variable to trigger demo mode within the server run function
-                std::env::set_var("DRAGONFLY_DEMO_MODE", "true");
                 println!("🚀 Starting Dragonfly in Demo Mode (no hardware touched).");
                 println!("   Run 'dragonfly install' to set up the full system.");
                 println!("   Press Ctrl+C to stop the server.");
@@ -362,6 +507,23 @@ async fn print_default_invocation_output(output: DefaultInvocationOutput) -> Res
                                      println!("  ❓ Web UI status check was skipped.");
                                 }
                             }
+
+                            // Flag Tinkerbell component versions outside the support matrix
+                            match dragonfly_server::stack::check_deployed().await {
+                                Ok(report) if report.supported => {
+                                    println!("  ✅ Tinkerbell stack: supported ({})", report.matched.unwrap_or_default());
+                                }
+                                Ok(report) => {
+                                    println!("  🟡 Tinkerbell stack: unsupported version combination");
+                                    for problem in &report.problems {
+                                        println!("      - {}", problem);
+                                    }
+                                    println!("      Upgrade it from the web UI banner or with POST /api/admin/stack/upgrade");
+                                }
+                                Err(e) => {
+                                    println!("  ❓ Could not check Tinkerbell stack versions: {}", e);
+                                }
+                            }
                         }
                         StatefulSetStatus::NotReady => {
                              println!("    🟡 StatefulSet 'dragonfly': Not Ready (may be starting up or have issues)");